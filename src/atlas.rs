@@ -0,0 +1,112 @@
+// Many small diffuse textures packed into one, so the entities using them share a bind group.
+// Each instance picks its image through the uv offset and scale of its InstanceAttributes, a
+// whole set of differently textured copies of a mesh is still one draw call. Texture
+// coordinates outside 0..1 don't repeat any more, they run into the neighbouring images.
+use crate::texture::Texture;
+use crate::world_space::InstanceAttributes;
+use anyhow::*;
+use image::RgbaImage;
+use wgpu::{Device, Queue};
+
+// Edge pixels repeated around every image, keeps linear filtering and the first mips from
+// bleeding the neighbours in
+const PADDING: u32 = 2;
+
+// Where the images go, in pixels. Kept apart from the texture so packing needs no GPU.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasLayout {
+    // x, y, width, height of each image without its padding, in input order
+    pub rects: Vec<[u32; 4]>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasLayout {
+    // Shelves of images sorted by height, about as wide as the atlas is tall
+    pub fn pack(sizes: &[(u32, u32)]) -> Self {
+        let padded = |(width, height): (u32, u32)| (width + 2 * PADDING, height + 2 * PADDING);
+        let area: u32 = sizes
+            .iter()
+            .map(|&size| padded(size))
+            .map(|(width, height)| width * height)
+            .sum();
+        let widest = sizes.iter().map(|&size| padded(size).0).max().unwrap_or(1);
+        let width = ((area as f32).sqrt().ceil() as u32)
+            .max(widest)
+            .next_power_of_two();
+
+        let mut order: Vec<_> = (0..sizes.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+        let mut rects = vec![[0; 4]; sizes.len()];
+        let (mut x, mut y, mut shelf) = (0, 0, 0);
+        for i in order {
+            let (padded_width, padded_height) = padded(sizes[i]);
+            if x + padded_width > width {
+                x = 0;
+                y += shelf;
+                shelf = 0;
+            }
+            rects[i] = [x + PADDING, y + PADDING, sizes[i].0, sizes[i].1];
+            x += padded_width;
+            shelf = shelf.max(padded_height);
+        }
+        Self {
+            rects,
+            width,
+            height: (y + shelf).max(1),
+        }
+    }
+
+    // Shows image `index` where the whole texture was shown before, the diffuse lookup flips v
+    pub fn attributes(&self, index: usize) -> InstanceAttributes {
+        let [x, y, width, height] = self.rects[index];
+        let scale = [
+            width as f32 / self.width as f32,
+            height as f32 / self.height as f32,
+        ];
+        InstanceAttributes {
+            uv_offset: [
+                x as f32 / self.width as f32,
+                1. - y as f32 / self.height as f32 - scale[1],
+            ],
+            uv_scale: scale,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct TextureAtlas {
+    pub texture: Texture,
+    pub layout: AtlasLayout,
+}
+
+impl TextureAtlas {
+    // Pass the texture to Entity::with_texture and `layout.attributes(i)` to the instances
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        images: &[RgbaImage],
+        label: Option<&str>,
+        mip_level_count: u32,
+    ) -> Result<Self> {
+        let sizes: Vec<_> = images.iter().map(RgbaImage::dimensions).collect();
+        ensure!(
+            sizes.iter().all(|&(width, height)| width > 0 && height > 0),
+            "Empty image in the atlas"
+        );
+        let layout = AtlasLayout::pack(&sizes);
+        let mut pixels = RgbaImage::new(layout.width, layout.height);
+        for (image, &[x, y, width, height]) in images.iter().zip(&layout.rects) {
+            for dy in 0..height + 2 * PADDING {
+                for dx in 0..width + 2 * PADDING {
+                    let source_x = dx.saturating_sub(PADDING).min(width - 1);
+                    let source_y = dy.saturating_sub(PADDING).min(height - 1);
+                    let pixel = *image.get_pixel(source_x, source_y);
+                    pixels.put_pixel(x + dx - PADDING, y + dy - PADDING, pixel);
+                }
+            }
+        }
+        let texture = Texture::from_rgba(device, queue, &pixels, label, mip_level_count)?;
+        Ok(Self { texture, layout })
+    }
+}
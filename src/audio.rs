@@ -0,0 +1,217 @@
+// Background music and positional sounds with rodio, enabled by the `audio` feature. On the web
+// rodio plays through WebAudio. Emitters are attached to entities: every frame State::update
+// moves them to the world transform of their entity, sets their gain from the distance to the
+// camera and pans them by where they are in view.
+use crate::ecs::{EntityId, World};
+use crate::events::AssetLoaded;
+use crate::State;
+use anyhow::Context;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source, SpatialSink};
+use std::io::Cursor;
+
+// Ears of the listener. Emitters are placed on the unit sphere around it, the distance only
+// goes into the gain.
+const EAR_OFFSET: f32 = 0.2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SoundSettings {
+    pub volume: f32,
+    // Starts over at the end, otherwise the emitter is dropped once it's done
+    pub looped: bool,
+    // Full volume closer than this
+    pub min_distance: f32,
+    // Silent further than this
+    pub max_distance: f32,
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.,
+            looped: true,
+            min_distance: 10.,
+            max_distance: 400.,
+        }
+    }
+}
+
+// Inverse distance falloff past min_distance, faded out to 0 at max_distance so sounds don't
+// cut off
+pub fn distance_gain(distance: f32, settings: &SoundSettings) -> f32 {
+    let min = settings.min_distance.max(f32::EPSILON);
+    let max = settings.max_distance;
+    if distance >= max {
+        return 0.;
+    }
+    if distance <= min {
+        return 1.;
+    }
+    min / distance * (max - distance) / (max - min)
+}
+
+// Where `position` is as seen through `view`, on the unit sphere: x right, y up and -z ahead.
+// At the listener counts as ahead.
+pub fn listener_direction(view: &Matrix4<f32>, position: Point3<f32>) -> [f32; 3] {
+    let local = view.transform_point(position).to_vec();
+    if local.magnitude2() > 0. {
+        local.normalize().into()
+    } else {
+        [0., 0., -1.]
+    }
+}
+
+struct Emitter {
+    entity: EntityId,
+    sink: SpatialSink,
+    settings: SoundSettings,
+}
+
+pub struct Audio {
+    // Every sound stops when it's dropped
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    // Of the music and every emitter
+    volume: f32,
+    // With its own volume
+    music: Option<(Sink, f32)>,
+    emitters: Vec<Emitter>,
+}
+
+impl Audio {
+    // Fails without an output device
+    pub fn new(volume: f32) -> anyhow::Result<Self> {
+        let (stream, handle) = OutputStream::try_default()?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+            volume,
+            music: None,
+            emitters: vec![],
+        })
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+        if let Some((sink, music_volume)) = &self.music {
+            sink.set_volume(music_volume * volume);
+        }
+    }
+
+    // Replaces the music, played in a loop. WAV or Ogg Vorbis.
+    pub fn play_music(&mut self, data: Vec<u8>, volume: f32) -> anyhow::Result<()> {
+        let source = Decoder::new(Cursor::new(data))?;
+        let sink = Sink::try_new(&self.handle)?;
+        sink.set_volume(volume * self.volume);
+        sink.append(source.repeat_infinite());
+        self.music = Some((sink, volume));
+        Ok(())
+    }
+
+    pub fn stop_music(&mut self) {
+        self.music = None;
+    }
+
+    // Plays at `entity` until it's despawned, silent until the next update places it
+    pub fn add_emitter(
+        &mut self,
+        entity: EntityId,
+        data: Vec<u8>,
+        settings: SoundSettings,
+    ) -> anyhow::Result<()> {
+        let source = Decoder::new(Cursor::new(data))?;
+        let sink = SpatialSink::try_new(
+            &self.handle,
+            [0., 0., -1.],
+            [-EAR_OFFSET, 0., 0.],
+            [EAR_OFFSET, 0., 0.],
+        )?;
+        sink.set_volume(0.);
+        if settings.looped {
+            sink.append(source.repeat_infinite());
+        } else {
+            sink.append(source);
+        }
+        self.emitters.push(Emitter {
+            entity,
+            sink,
+            settings,
+        });
+        Ok(())
+    }
+
+    // Stops the sounds of `entity`, false if it had none
+    pub fn remove_emitters(&mut self, entity: EntityId) -> bool {
+        let count = self.emitters.len();
+        self.emitters.retain(|emitter| emitter.entity != entity);
+        self.emitters.len() != count
+    }
+
+    pub fn emitter_count(&self) -> usize {
+        self.emitters.len()
+    }
+
+    // For the camera at `eye` looking through `view`. Emitters of despawned entities and sounds
+    // that ended are dropped.
+    fn update(&mut self, world: &World, eye: Point3<f32>, view: &Matrix4<f32>) {
+        self.emitters
+            .retain(|emitter| world.is_alive(emitter.entity) && !emitter.sink.empty());
+        for emitter in &self.emitters {
+            let position = match world.world_transform(emitter.entity) {
+                Some(transform) => Point3::from_vec(transform.position),
+                None => continue,
+            };
+            let gain = distance_gain((position - eye).magnitude(), &emitter.settings);
+            let sink = &emitter.sink;
+            sink.set_volume(self.volume * emitter.settings.volume * gain);
+            sink.set_emitter_position(listener_direction(view, position));
+        }
+    }
+}
+
+impl State {
+    // None without an output device
+    pub fn audio_mut(&mut self) -> Option<&mut Audio> {
+        self.audio.as_mut()
+    }
+
+    // A sound file of the assets played at `entity`, see Audio::add_emitter
+    pub async fn add_sound(
+        &mut self,
+        entity: EntityId,
+        file: &str,
+        settings: SoundSettings,
+    ) -> anyhow::Result<()> {
+        let data = self.assets.load_binary(file).await?;
+        let audio = self.audio.as_mut().context("No audio output")?;
+        audio.add_emitter(entity, data, settings)?;
+        let name = file.to_string();
+        self.publish(AssetLoaded { name, group: None });
+        Ok(())
+    }
+
+    // The music of the config, logged if it can't be played
+    pub(crate) async fn start_music(&mut self, file: &str) {
+        if file.is_empty() {
+            return;
+        }
+        let played = match self.assets.load_binary(file).await {
+            Ok(data) => match &mut self.audio {
+                Some(audio) => audio.play_music(data, 1.),
+                None => return,
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = played {
+            log::error!("Music {} not played: {:?}", file, e);
+        }
+    }
+
+    // After the world systems, so emitters are where their entities are drawn
+    pub(crate) fn update_audio(&mut self) {
+        if let Some(audio) = &mut self.audio {
+            let view = &self.camera.view;
+            audio.update(&self.world, view.position, &view.calc_matrix());
+        }
+    }
+}
@@ -0,0 +1,428 @@
+// Fits the linearly transformed cosines of the rectangle lights to the GGX lobe and writes the
+// tables embedded by ltc.rs. The fit follows Heitz et al., "Real-Time Polygonal-Light Shading
+// with Linearly Transformed Cosines": for every roughness and view angle a clamped cosine is
+// stretched by a matrix until it matches the BRDF times the cosine of the light.
+//
+//     cargo run --release --bin fit_ltc -- src/ltc.bin
+//
+// The file holds two SIZE x SIZE tables of four f16 each, little endian and row by row: the
+// inverse matrix, then the magnitude and Fresnel weight of the lobe. Columns are roughness
+// from 0 to 1, rows sqrt(1 - cos) of the view angle from 0 to 1.
+use std::f32::consts::PI;
+use std::io::Write;
+
+const SIZE: usize = 64;
+const MIN_ALPHA: f32 = 0.00001;
+// Per dimension, for the error and the averages
+const SAMPLES: usize = 32;
+
+type Vec3 = [f32; 3];
+// Row major
+type Mat3 = [[f32; 3]; 3];
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn scale(a: Vec3, s: f32) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn length(a: Vec3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: Vec3) -> Vec3 {
+    scale(a, 1. / length(a))
+}
+
+fn mul(m: &Mat3, v: Vec3) -> Vec3 {
+    [dot(m[0], v), dot(m[1], v), dot(m[2], v)]
+}
+
+fn mat_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut m = [[0.; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn determinant(m: &Mat3) -> f32 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+fn inverse(m: &Mat3) -> Mat3 {
+    let det = determinant(m);
+    let mut inv = [[0.; 3]; 3];
+    for (i, row) in inv.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            // Cofactor of the transposed entry
+            let (r0, r1) = ((j + 1) % 3, (j + 2) % 3);
+            let (c0, c1) = ((i + 1) % 3, (i + 2) % 3);
+            *value = (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / det;
+        }
+    }
+    inv
+}
+
+// GGX with height-correlated Smith shadowing, times the cosine of the light, without Fresnel
+struct Ggx;
+
+impl Ggx {
+    fn lambda(alpha: f32, cos_theta: f32) -> f32 {
+        if cos_theta >= 1. {
+            return 0.;
+        }
+        let a = 1. / alpha / cos_theta.acos().tan();
+        0.5 * (-1. + (1. + 1. / a / a).sqrt())
+    }
+
+    // The value and the pdf of sample()
+    fn eval(v: Vec3, l: Vec3, alpha: f32) -> (f32, f32) {
+        if v[2] <= 0. {
+            return (0., 0.);
+        }
+        let lambda_v = Self::lambda(alpha, v[2]);
+        let g2 = if l[2] <= 0. {
+            0.
+        } else {
+            1. / (1. + lambda_v + Self::lambda(alpha, l[2]))
+        };
+        let h = normalize(add(v, l));
+        let slope_x = h[0] / h[2];
+        let slope_y = h[1] / h[2];
+        let d = 1. / (1. + (slope_x * slope_x + slope_y * slope_y) / alpha / alpha);
+        let d = d * d / (PI * alpha * alpha * h[2].powi(4));
+        let pdf = (d * h[2] / 4. / dot(v, h)).abs();
+        (d * g2 / 4. / v[2], pdf)
+    }
+
+    // Reflects the view around a sampled microfacet normal
+    fn sample(v: Vec3, alpha: f32, u1: f32, u2: f32) -> Vec3 {
+        let phi = 2. * PI * u1;
+        let r = alpha * (u2 / (1. - u2)).sqrt();
+        let n = normalize([r * phi.cos(), r * phi.sin(), 1.]);
+        add(scale(v, -1.), scale(n, 2. * dot(n, v)))
+    }
+}
+
+#[derive(Clone)]
+struct Ltc {
+    magnitude: f32,
+    m11: f32,
+    m22: f32,
+    m13: f32,
+    // Frame around the average direction of the lobe
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+    m: Mat3,
+    inv_m: Mat3,
+    det_m: f32,
+}
+
+impl Ltc {
+    fn new() -> Self {
+        let mut ltc = Self {
+            magnitude: 1.,
+            m11: 1.,
+            m22: 1.,
+            m13: 0.,
+            x: [1., 0., 0.],
+            y: [0., 1., 0.],
+            z: [0., 0., 1.],
+            m: [[0.; 3]; 3],
+            inv_m: [[0.; 3]; 3],
+            det_m: 0.,
+        };
+        ltc.update();
+        ltc
+    }
+
+    fn update(&mut self) {
+        let frame = [
+            [self.x[0], self.y[0], self.z[0]],
+            [self.x[1], self.y[1], self.z[1]],
+            [self.x[2], self.y[2], self.z[2]],
+        ];
+        let stretch = [[self.m11, 0., self.m13], [0., self.m22, 0.], [0., 0., 1.]];
+        self.m = mat_mul(&frame, &stretch);
+        self.inv_m = inverse(&self.m);
+        self.det_m = determinant(&self.m).abs();
+    }
+
+    fn eval(&self, l: Vec3) -> f32 {
+        let original = normalize(mul(&self.inv_m, l));
+        let transformed = mul(&self.m, original);
+        let l = length(transformed);
+        let jacobian = self.det_m / (l * l * l);
+        let d = original[2].max(0.) / PI;
+        self.magnitude * d / jacobian
+    }
+
+    fn sample(&self, u1: f32, u2: f32) -> Vec3 {
+        let theta = u1.sqrt().acos();
+        let phi = 2. * PI * u2;
+        let l = [
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        ];
+        normalize(mul(&self.m, l))
+    }
+}
+
+fn uniform_samples() -> impl Iterator<Item = (f32, f32)> {
+    (0..SAMPLES * SAMPLES).map(|k| {
+        let (i, j) = (k % SAMPLES, k / SAMPLES);
+        (
+            (i as f32 + 0.5) / SAMPLES as f32,
+            (j as f32 + 0.5) / SAMPLES as f32,
+        )
+    })
+}
+
+// Magnitude, Fresnel weight and average direction of the lobe
+fn average_terms(v: Vec3, alpha: f32) -> (f32, f32, Vec3) {
+    let (mut norm, mut fresnel, mut direction) = (0., 0., [0.; 3]);
+    for (u1, u2) in uniform_samples() {
+        let l = Ggx::sample(v, alpha, u1, u2);
+        let (value, pdf) = Ggx::eval(v, l, alpha);
+        if pdf > 0. {
+            let weight = value / pdf;
+            let h = normalize(add(v, l));
+            norm += weight;
+            fresnel += weight * (1. - dot(v, h).max(0.)).powi(5);
+            direction = add(direction, scale(l, weight));
+        }
+    }
+    let count = (SAMPLES * SAMPLES) as f32;
+    direction[1] = 0.;
+    (norm / count, fresnel / count, normalize(direction))
+}
+
+// Cubed difference of the two lobes, sampled from both
+fn error(ltc: &Ltc, v: Vec3, alpha: f32) -> f32 {
+    let mut error = 0.;
+    let mut compare = |l: Vec3| {
+        let (brdf, brdf_pdf) = Ggx::eval(v, l, alpha);
+        let value = ltc.eval(l);
+        let ltc_pdf = value / ltc.magnitude;
+        let difference = ((brdf - value).abs() as f64).powi(3);
+        error += difference / (ltc_pdf + brdf_pdf) as f64;
+    };
+    for (u1, u2) in uniform_samples() {
+        compare(ltc.sample(u1, u2));
+        compare(Ggx::sample(v, alpha, u1, u2));
+    }
+    (error / (SAMPLES * SAMPLES) as f64) as f32
+}
+
+fn set_parameters(ltc: &mut Ltc, [m11, m22, m13]: [f32; 3], isotropic: bool) {
+    let m11 = m11.max(1e-7);
+    let m22 = m22.max(1e-7);
+    if isotropic {
+        ltc.m11 = m11;
+        ltc.m22 = m11;
+        ltc.m13 = 0.;
+    } else {
+        ltc.m11 = m11;
+        ltc.m22 = m22;
+        ltc.m13 = m13;
+    }
+    ltc.update();
+}
+
+fn nelder_mead(
+    start: [f32; 3],
+    delta: f32,
+    tolerance: f32,
+    max_iterations: usize,
+    mut objective: impl FnMut([f32; 3]) -> f32,
+) -> [f32; 3] {
+    const REFLECT: f32 = 1.;
+    const EXPAND: f32 = 2.;
+    const CONTRACT: f32 = 0.5;
+    const SHRINK: f32 = 0.5;
+    let mut points = [start; 4];
+    for (i, point) in points.iter_mut().enumerate().skip(1) {
+        point[i - 1] += delta;
+    }
+    let mut values = points.map(&mut objective);
+    let towards =
+        |from: [f32; 3], to: [f32; 3], t: f32| [0, 1, 2].map(|i| from[i] + t * (to[i] - from[i]));
+    let mut lo = 0;
+    for _ in 0..max_iterations {
+        lo = 0;
+        let (mut hi, mut next_hi) = (0, 0);
+        for i in 1..4 {
+            if values[i] < values[lo] {
+                lo = i;
+            }
+            if values[i] > values[hi] {
+                next_hi = hi;
+                hi = i;
+            } else if values[i] > values[next_hi] {
+                next_hi = i;
+            }
+        }
+        let (a, b) = (values[lo].abs(), values[hi].abs());
+        if 2. * (a - b).abs() < (a + b) * tolerance {
+            break;
+        }
+        let mut centroid = [0.; 3];
+        for (i, point) in points.iter().enumerate() {
+            if i != hi {
+                centroid = add(centroid, *point);
+            }
+        }
+        let centroid = scale(centroid, 1. / 3.);
+        let worst = points[hi];
+
+        let reflected = towards(centroid, worst, -REFLECT);
+        let reflected_value = objective(reflected);
+        if reflected_value < values[lo] {
+            let expanded = towards(centroid, worst, -EXPAND);
+            let expanded_value = objective(expanded);
+            if expanded_value < reflected_value {
+                points[hi] = expanded;
+                values[hi] = expanded_value;
+                continue;
+            }
+        }
+        if reflected_value < values[next_hi] {
+            points[hi] = reflected;
+            values[hi] = reflected_value;
+            continue;
+        }
+        let contracted = towards(centroid, worst, CONTRACT);
+        let contracted_value = objective(contracted);
+        if contracted_value < values[hi] {
+            points[hi] = contracted;
+            values[hi] = contracted_value;
+            continue;
+        }
+        for k in 0..4 {
+            if k != lo {
+                points[k] = towards(points[lo], points[k], SHRINK);
+                values[k] = objective(points[k]);
+            }
+        }
+    }
+    points[lo]
+}
+
+// Matrices and magnitude, Fresnel pairs, indexed by roughness + SIZE * view angle
+fn fit() -> (Vec<Mat3>, Vec<[f32; 2]>) {
+    let mut matrices = vec![[[0.; 3]; 3]; SIZE * SIZE];
+    let mut terms = vec![[0.; 2]; SIZE * SIZE];
+    let mut ltc = Ltc::new();
+    // Rough lobes first, every fit starts from its neighbour
+    for a in (0..SIZE).rev() {
+        for t in 0..SIZE {
+            let x = t as f32 / (SIZE - 1) as f32;
+            let theta = (1. - x * x).acos().min(1.57);
+            let v = [theta.sin(), 0., theta.cos()];
+            let roughness = a as f32 / (SIZE - 1) as f32;
+            let alpha = (roughness * roughness).max(MIN_ALPHA);
+            let (magnitude, fresnel, direction) = average_terms(v, alpha);
+            ltc.magnitude = magnitude;
+
+            // Looking straight down the lobe is symmetric around the normal
+            let isotropic = t == 0;
+            if isotropic {
+                ltc.x = [1., 0., 0.];
+                ltc.y = [0., 1., 0.];
+                ltc.z = [0., 0., 1.];
+                if a == SIZE - 1 {
+                    ltc.m11 = 1.;
+                    ltc.m22 = 1.;
+                } else {
+                    let previous = matrices[a + 1];
+                    ltc.m11 = previous[0][0];
+                    ltc.m22 = previous[1][1];
+                }
+                ltc.m13 = 0.;
+            } else {
+                ltc.x = [direction[2], 0., -direction[0]];
+                ltc.y = [0., 1., 0.];
+                ltc.z = direction;
+            }
+            ltc.update();
+
+            let start = [ltc.m11, ltc.m22, ltc.m13];
+            let mut candidate = ltc.clone();
+            let best = nelder_mead(start, 0.05, 1e-5, 100, |parameters| {
+                set_parameters(&mut candidate, parameters, isotropic);
+                error(&candidate, v, alpha)
+            });
+            set_parameters(&mut ltc, best, isotropic);
+
+            let mut m = ltc.m;
+            m[0][1] = 0.;
+            m[1][0] = 0.;
+            m[1][2] = 0.;
+            m[2][1] = 0.;
+            matrices[a + t * SIZE] = m;
+            terms[a + t * SIZE] = [magnitude, fresnel];
+        }
+        eprint!("\rroughness {}/{}", SIZE - a, SIZE);
+    }
+    eprintln!();
+    (matrices, terms)
+}
+
+// Round to nearest. The smoothest lobes need the subnormals, their matrices scale by alpha.
+fn f16_bits(value: f32) -> u16 {
+    let value = value.clamp(-65504., 65504.);
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent < -10 {
+        return sign;
+    }
+    if exponent <= 0 {
+        let shift = (14 - exponent) as u32;
+        let mantissa = mantissa | 0x80_0000;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let round = (mantissa >> 12) & 1;
+    sign | (half + round) as u16
+}
+
+fn main() -> std::io::Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "src/ltc.bin".into());
+    let (matrices, terms) = fit();
+    let mut bytes = Vec::with_capacity(2 * SIZE * SIZE * 4 * 2);
+    let mut push = |values: [f32; 4]| {
+        for value in values {
+            bytes.extend_from_slice(&f16_bits(value).to_le_bytes());
+        }
+    };
+    for m in &matrices {
+        // Normalized by the middle element, which the shaders take as 1
+        let inv = inverse(m);
+        let inv = inv.map(|row| row.map(|value| value / inv[1][1]));
+        push([inv[0][0], inv[2][0], inv[0][2], inv[2][2]]);
+    }
+    for &[magnitude, fresnel] in &terms {
+        push([magnitude, fresnel, 0., 0.]);
+    }
+    std::fs::File::create(&path)?.write_all(&bytes)?;
+    eprintln!("Wrote {}", path);
+    Ok(())
+}
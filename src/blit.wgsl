@@ -32,3 +32,9 @@ var r_sampler: sampler;
 fn fs_main(vertex: VertexOutput) -> @location(0) vec4<f32> {
     return textureSample(r_color, r_sampler, vertex.tex_coords);
 }
+
+// Flipped left to right, for cube faces rendered with a right-handed camera
+@fragment
+fn fs_mirror(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(r_color, r_sampler, vec2<f32>(1.0 - vertex.tex_coords.x, vertex.tex_coords.y));
+}
@@ -0,0 +1,127 @@
+use cgmath::{ElementWise, EuclideanSpace, InnerSpace, Point3, Quaternion, Rotation, Vector3};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl Iterator<Item = Point3<f32>>) -> Option<Self> {
+        points.fold(None, |aabb: Option<Self>, p| {
+            Some(match aabb {
+                None => Self { min: p, max: p },
+                Some(aabb) => aabb.extended(p),
+            })
+        })
+    }
+
+    pub fn extended(&self, p: Point3<f32>) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(p.x),
+                self.min.y.min(p.y),
+                self.min.z.min(p.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(p.x),
+                self.max.y.max(p.y),
+                self.max.z.max(p.z),
+            ),
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        self.extended(other.min).extended(other.max)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn around(center: Point3<f32>, half_extent: Vector3<f32>) -> Self {
+        Self {
+            min: center - half_extent,
+            max: center + half_extent,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let [min, max] = [self.min, self.max];
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn from_points(points: impl Iterator<Item = [f32; 3]> + Clone) -> Self {
+        let center = match Aabb::from_points(points.clone().map(Point3::from)) {
+            Some(aabb) => aabb.center(),
+            None => {
+                return Self {
+                    center: Point3::origin(),
+                    radius: 0.,
+                }
+            }
+        };
+        let radius = points
+            .map(|p| (Point3::from(p) - center).magnitude())
+            .fold(0., f32::max);
+        Self { center, radius }
+    }
+
+    // The box around the sphere
+    pub fn aabb(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::around(self.center, Vector3::new(r, r, r))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let d = other.center - self.center;
+        let dist = d.magnitude();
+        if dist + other.radius <= self.radius {
+            return *self;
+        }
+        if dist + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (dist + self.radius + other.radius) / 2.;
+        let center = self.center + d * ((radius - self.radius) / dist);
+        Self { center, radius }
+    }
+
+    // Scaled per axis before the rotation, the radius grows with the largest axis
+    pub fn transformed(
+        &self,
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Self {
+        let center = scale.mul_element_wise(self.center.to_vec());
+        let max_scale = scale.x.abs().max(scale.y.abs()).max(scale.z.abs());
+        Self {
+            center: Point3::from_vec(position + rotation.rotate_vector(center)),
+            radius: self.radius * max_scale,
+        }
+    }
+}
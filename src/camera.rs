@@ -1,5 +1,11 @@
+use crate::raycast::Ray;
+use crate::stats;
 use crate::uniform_desc;
-use cgmath::{perspective, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3, Zero};
+use cgmath::{
+    ortho, perspective, Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix,
+    Vector3, Vector4, Zero,
+};
+use serde::Deserialize;
 use std::f32::consts::FRAC_PI_2;
 use std::time::Duration;
 use wgpu::util::DeviceExt;
@@ -15,6 +21,19 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 );
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+// Walk mode, in world units and seconds
+const GRAVITY: f32 = 60.0;
+const JUMP_SPEED: f32 = 25.0;
+const MAX_FALL_SPEED: f32 = 120.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraMode {
+    // Free movement, Space lifts the camera and it sinks back to the ground plane
+    Fly,
+    // Falls and stands on the meshes below, Space jumps while on the ground
+    Walk,
+}
 
 pub struct Camera {
     pub(crate) view: CameraView,
@@ -23,12 +42,13 @@ pub struct Camera {
     camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
+    relative: bool,
 }
 
 impl Camera {
     pub fn new(view: CameraView, projection: Projection, device: &wgpu::Device) -> Camera {
         let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&view, &projection);
+        camera_uniform.update_view_proj(&view, &projection, false);
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Buffer"),
             contents: bytemuck::cast_slice(&[camera_uniform]),
@@ -52,13 +72,56 @@ impl Camera {
             camera_buffer,
             camera_bind_group,
             camera_bind_group_layout,
+            relative: false,
         }
     }
 
+    // Shaders subtract the camera position from world positions before the rotation-only
+    // view projection, instead of multiplying by a matrix holding a large translation
+    pub fn set_relative(&mut self, relative: bool) {
+        self.relative = relative;
+    }
+
+    pub fn is_relative(&self) -> bool {
+        self.relative
+    }
+
+    // Project a world position to pixel coordinates (origin top left) of this camera's viewport
+    pub fn world_to_screen(&self, point: Point3<f32>) -> ScreenPoint {
+        let clip = self.projection.calc_matrix() * self.view.calc_matrix() * point.to_homogeneous();
+        let (width, height) = self.projection.size();
+        if clip.w <= f32::EPSILON {
+            // Dividing by |w| keeps the point on the side it really is on, so edge markers
+            // still point towards targets behind the camera
+            let w = clip.w.abs().max(f32::EPSILON);
+            let ndc = Vector3::new(clip.x / w, clip.y / w, 0.);
+            return ScreenPoint::from_ndc(ndc, width, height, Visibility::BehindCamera);
+        }
+        let ndc = clip.truncate() / clip.w;
+        let visibility = if ndc.x.abs() <= 1. && ndc.y.abs() <= 1. && (0. ..=1.).contains(&ndc.z) {
+            Visibility::OnScreen
+        } else {
+            Visibility::OffScreen
+        };
+        ScreenPoint::from_ndc(ndc, width, height, visibility)
+    }
+
+    // Ray through a pixel, the inverse of world_to_screen
+    pub fn screen_ray(&self, position: [f32; 2]) -> Option<Ray> {
+        let (width, height) = self.projection.size();
+        let x = position[0] / width as f32 * 2. - 1.;
+        let y = 1. - position[1] / height as f32 * 2.;
+        let inverse = (self.projection.calc_matrix() * self.view.calc_matrix()).invert()?;
+        let unproject = |z: f32| Point3::from_homogeneous(inverse * Vector4::new(x, y, z, 1.));
+        let near = unproject(0.);
+        Some(Ray::new(near, unproject(1.) - near))
+    }
+
     pub fn update_camera(&mut self, queue: &wgpu::Queue) {
         self.camera_uniform
-            .update_view_proj(&self.view, &self.projection);
-        queue.write_buffer(
+            .update_view_proj(&self.view, &self.projection, self.relative);
+        stats::write_buffer(
+            queue,
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
@@ -66,6 +129,71 @@ impl Camera {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Visibility {
+    OnScreen,
+    // In front of the camera but outside the viewport or depth range
+    OffScreen,
+    BehindCamera,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ScreenPoint {
+    pub position: [f32; 2],
+    // Normalized device depth in [0, 1] when on screen
+    pub depth: f32,
+    pub visibility: Visibility,
+    viewport: [f32; 2],
+}
+
+impl ScreenPoint {
+    fn from_ndc(ndc: Vector3<f32>, width: u32, height: u32, visibility: Visibility) -> Self {
+        let viewport = [width as f32, height as f32];
+        Self {
+            position: [
+                (ndc.x * 0.5 + 0.5) * viewport[0],
+                (0.5 - ndc.y * 0.5) * viewport[1],
+            ],
+            depth: ndc.z,
+            visibility,
+            viewport,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visibility == Visibility::OnScreen
+    }
+
+    // Waypoint style placement: points off screen or behind the camera slide along the
+    // viewport border, `margin` pixels inside it
+    pub fn clamped_to_edges(&self, margin: f32) -> [f32; 2] {
+        let [width, height] = self.viewport;
+        let center = [width / 2., height / 2.];
+        let half = [(center[0] - margin).max(0.), (center[1] - margin).max(0.)];
+        let dir = [self.position[0] - center[0], self.position[1] - center[1]];
+        let inside = dir[0].abs() <= half[0] && dir[1].abs() <= half[1];
+        if self.is_visible() && inside {
+            return self.position;
+        }
+        if dir[0] == 0. && dir[1] == 0. {
+            // Directly behind, park at the bottom edge
+            return [center[0], center[1] + half[1]];
+        }
+        let scale_x = if dir[0] != 0. {
+            half[0] / dir[0].abs()
+        } else {
+            f32::INFINITY
+        };
+        let scale_y = if dir[1] != 0. {
+            half[1] / dir[1].abs()
+        } else {
+            f32::INFINITY
+        };
+        let scale = scale_x.min(scale_y);
+        [center[0] + dir[0] * scale, center[1] + dir[1] * scale]
+    }
+}
+
 // We need this for Rust to store our data correctly for the shaders
 #[repr(C)]
 // This is so we can store this in a buffer
@@ -74,9 +202,13 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     pub view_position: [f32; 4],
-    view_proj: [[f32; 4]; 4],
+    pub(crate) view_proj: [[f32; 4]; 4],
     proj_inv: [[f32; 4]; 4],
     view: [[f32; 4]; 4],
+    // Projects positions relative to render_origin, see world_to_clip in geo.wgsl
+    pub(crate) relative_view_proj: [[f32; 4]; 4],
+    // The camera position when rendering camera relative, zero otherwise
+    pub(crate) render_origin: [f32; 4],
 }
 
 impl CameraUniform {
@@ -86,25 +218,38 @@ impl CameraUniform {
             view_proj: cgmath::Matrix4::identity().into(),
             proj_inv: cgmath::Matrix4::identity().into(),
             view: cgmath::Matrix4::identity().into(),
+            relative_view_proj: cgmath::Matrix4::identity().into(),
+            render_origin: [0.0; 4],
         }
     }
 
-    fn update_view_proj(&mut self, camera: &CameraView, projection: &Projection) {
+    fn update_view_proj(&mut self, camera: &CameraView, projection: &Projection, relative: bool) {
         self.view_position = camera.position.to_homogeneous().into();
         let proj = projection.calc_matrix();
         let view = camera.calc_matrix();
         self.view_proj = (proj * view).into();
         self.view = view.into();
         self.proj_inv = proj.invert().expect("Should be invertible").into();
+        if relative {
+            let rotation = Matrix4::look_to_rh(Point3::origin(), camera.get_dir(), camera.up());
+            self.relative_view_proj = (proj * rotation).into();
+            self.render_origin = self.view_position;
+        } else {
+            self.relative_view_proj = self.view_proj;
+            self.render_origin = [0.0; 4];
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CameraView {
     pub position: Point3<f32>,
     pub velocity: Vector3<f32>,
     yaw: Rad<f32>,
     pitch: Rad<f32>,
+    // Looks straight down (-1) or up (1) with the yaw direction at the top of the image, for
+    // maps and cube faces
+    vertical: Option<f32>,
 }
 
 impl CameraView {
@@ -118,19 +263,79 @@ impl CameraView {
             velocity: Vector3::zero(),
             yaw: yaw.into(),
             pitch: pitch.into(),
+            vertical: None,
+        }
+    }
+    pub fn top_down<V: Into<Point3<f32>>, Y: Into<Rad<f32>>>(position: V, yaw: Y) -> Self {
+        Self {
+            vertical: Some(-1.),
+            ..Self::new(position, yaw, Rad(-FRAC_PI_2))
+        }
+    }
+    pub fn bottom_up<V: Into<Point3<f32>>, Y: Into<Rad<f32>>>(position: V, yaw: Y) -> Self {
+        Self {
+            vertical: Some(1.),
+            ..Self::new(position, yaw, Rad(FRAC_PI_2))
+        }
+    }
+    // Face `face` of a cube map around `position` in posx, negx, posy, negy, posz, negz order.
+    // Cube faces are left-handed, the image has to be mirrored horizontally to match.
+    pub fn cube_face<V: Into<Point3<f32>>>(position: V, face: usize) -> Self {
+        let position = position.into();
+        match face {
+            0 => Self::new(position, Deg(0.), Deg(0.)),
+            1 => Self::new(position, Deg(180.), Deg(0.)),
+            2 => Self::bottom_up(position, Deg(-90.)),
+            3 => Self::top_down(position, Deg(90.)),
+            4 => Self::new(position, Deg(90.), Deg(0.)),
+            _ => Self::new(position, Deg(-90.), Deg(0.)),
         }
     }
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+    // Pitch is kept short of straight up or down like the controller does
+    pub fn set_yaw_pitch<Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(&mut self, yaw: Y, pitch: P) {
+        self.yaw = yaw.into();
+        self.pitch = Rad(pitch.into().0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+    }
     pub fn get_dir(&self) -> Vector3<f32> {
+        if let Some(sign) = self.vertical {
+            return Vector3::unit_y() * sign;
+        }
         Vector3::new(self.yaw.0.cos(), self.pitch.0.sin(), self.yaw.0.sin()).normalize()
     }
+    pub fn up(&self) -> Vector3<f32> {
+        if self.vertical.is_some() {
+            Vector3::new(self.yaw.0.cos(), 0.0, self.yaw.0.sin())
+        } else {
+            Vector3::unit_y()
+        }
+    }
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        Matrix4::look_to_rh(self.position, self.get_dir(), Vector3::unit_y())
+        Matrix4::look_to_rh(self.position, self.get_dir(), self.up())
     }
 }
 
+// Distance from its center at which a sphere of `radius` just fits the narrower of the vertical
+// and horizontal field of view
+pub fn fit_distance(radius: f32, fovy: Rad<f32>, aspect: f32) -> f32 {
+    let half_y = fovy.0 / 2.;
+    let half_x = (half_y.tan() * aspect).atan();
+    radius / half_y.min(half_x).sin()
+}
+
+#[derive(Debug, Clone)]
 pub struct Projection {
+    width: u32,
+    height: u32,
     aspect: f32,
     fovy: Rad<f32>,
+    // Half the height of the view volume of an orthographic projection
+    ortho_height: Option<f32>,
     znear: f32,
     zfar: f32,
 }
@@ -138,19 +343,76 @@ pub struct Projection {
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
         Self {
+            width,
+            height,
             aspect: width as f32 / height as f32,
             fovy: fovy.into(),
+            ortho_height: None,
             znear,
             zfar,
         }
     }
 
+    // Sees `half_height` world units above and below the view direction at any distance
+    pub fn orthographic(width: u32, height: u32, half_height: f32, znear: f32, zfar: f32) -> Self {
+        Self {
+            ortho_height: Some(half_height),
+            ..Self::new(width, height, Rad(0.0), znear, zfar)
+        }
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
         self.aspect = width as f32 / height as f32;
     }
 
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // The same view volume drawn into an attachment of another size, the aspect stays
+    pub fn resized(&self, width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            ..self.clone()
+        }
+    }
+
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
+    pub fn set_depth_range(&mut self, znear: f32, zfar: f32) {
+        self.znear = znear;
+        self.zfar = zfar;
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        let projection = match self.ortho_height {
+            Some(half_height) => {
+                let half_width = half_height * self.aspect;
+                ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+            None => perspective(self.fovy, self.aspect, self.znear, self.zfar),
+        };
+        OPENGL_TO_WGPU_MATRIX * projection
     }
 }
 
@@ -168,6 +430,9 @@ pub struct CameraController {
     speed: f32,
     speed_up: f32,
     sensitivity: f32,
+    mode: CameraMode,
+    // Set by the walk collision each frame
+    pub(crate) grounded: bool,
 }
 
 impl CameraController {
@@ -185,9 +450,36 @@ impl CameraController {
             speed,
             speed_up: 1.0,
             sensitivity,
+            mode: CameraMode::Fly,
+            grounded: false,
         }
     }
 
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    // World units a second before the speed up, scenes of other sizes need another
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn sensitivity(&self) -> f32 {
+        self.sensitivity
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &mut CameraView) {
+        if mode != self.mode {
+            camera.velocity = Vector3::zero();
+            self.grounded = false;
+        }
+        self.mode = mode;
+    }
+
     pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
             1.0
@@ -234,6 +526,19 @@ impl CameraController {
         self.rotate_vertical = sensitivity * mouse_dy as f32;
     }
 
+    // From the virtual joystick of TouchInput, each axis in [-1, 1]
+    pub fn process_joystick(&mut self, right: f32, forward: f32) {
+        self.amount_right = right.max(0.0);
+        self.amount_left = (-right).max(0.0);
+        self.amount_forward = forward.max(0.0);
+        self.amount_backward = (-forward).max(0.0);
+    }
+
+    // Pixels the fingers spread apart, moves along the view like scrolling
+    pub fn process_pinch(&mut self, spread: f32) {
+        self.scroll = spread;
+    }
+
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
         self.scroll = -match delta {
             // I'm assuming a line is about 100 pixels
@@ -242,8 +547,29 @@ impl CameraController {
         };
     }
 
+    // Pending input or the camera still falling back to the ground
+    pub fn is_moving(&self, camera: &CameraView) -> bool {
+        let amounts = [
+            self.amount_left,
+            self.amount_right,
+            self.amount_forward,
+            self.amount_backward,
+            self.amount_up,
+            self.amount_down,
+            self.rotate_horizontal,
+            self.rotate_vertical,
+            self.scroll,
+        ];
+        let settling = match self.mode {
+            CameraMode::Fly => camera.position.y > 0.0,
+            CameraMode::Walk => !self.grounded,
+        };
+        amounts.iter().any(|amount| *amount != 0.0) || settling
+    }
+
     pub fn update_camera(&mut self, camera: &mut CameraView, dt: Duration) {
-        let dt = dt.as_secs_f32() * 4.0;
+        let seconds = dt.as_secs_f32();
+        let dt = seconds * 4.0;
 
         // Move forward/backward and left/right
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
@@ -257,6 +583,30 @@ impl CameraController {
         camera.position +=
             self.speed_up * right * (self.amount_right - self.amount_left) * self.speed * dt;
 
+        if self.mode == CameraMode::Walk {
+            self.fall(camera, seconds);
+        } else {
+            self.fly(camera, dt);
+        }
+        self.rotate(camera, dt);
+    }
+
+    // Jumps off the ground and falls until the walk collision lands the camera
+    fn fall(&mut self, camera: &mut CameraView, seconds: f32) {
+        if self.amount_up > 0.0 && self.grounded {
+            camera.velocity.y = JUMP_SPEED;
+            self.grounded = false;
+        }
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.scroll = 0.0;
+        camera.velocity.y = (camera.velocity.y - GRAVITY * seconds).max(-MAX_FALL_SPEED);
+        camera.position.y += camera.velocity.y * seconds;
+    }
+
+    fn fly(&mut self, camera: &mut CameraView, dt: f32) {
+        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
+
         // Move in/out (aka. "zoom")
         // Note: this isn't an actual zoom. The camera's position
         // changes when zooming. I've added this to make it easier
@@ -280,7 +630,9 @@ impl CameraController {
                 camera.velocity.y = -0.5 * camera.velocity.y;
             }
         }
+    }
 
+    fn rotate(&mut self, camera: &mut CameraView, dt: f32) {
         // Rotate
         camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
         camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
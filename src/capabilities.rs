@@ -0,0 +1,101 @@
+use crate::config::Backend;
+use wgpu::{Adapter, DownlevelFlags};
+
+// What the adapter can do, detected once at startup. Subsystems consult this rather than
+// assuming every web build is WebGL2 and every native one is not: native GL drivers share the
+// WebGL limits, and browser WebGPU has none of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RendererCapabilities {
+    pub backend: wgpu::Backend,
+    pub adapter_name: String,
+    // Largest side of a 2D texture the device allows
+    pub max_texture_size: u32,
+    // Features the device is created with, a subset of the adapter's
+    pub features: wgpu::Features,
+    // Samples per pixel the scene passes can use, 1 first
+    pub sample_counts: Vec<u32>,
+    // Compute shaders and storage buffers, see compute.rs
+    pub compute: bool,
+    // Storage buffers in vertex shaders, for the morph targets and skins, see deform.rs
+    pub vertex_storage: bool,
+    // More than one reflection probe, see probes.rs
+    pub cube_arrays: bool,
+    pub anisotropic_filtering: bool,
+    // Everything WebGPU guarantees, false on WebGL2 and most native GL drivers
+    pub webgpu_compliant: bool,
+}
+
+impl RendererCapabilities {
+    pub(crate) fn new(adapter: &Adapter, features: wgpu::Features) -> Self {
+        let info = adapter.get_info();
+        let downlevel = adapter.get_downlevel_properties();
+        let limits = adapter.limits();
+        let webgpu_compliant = downlevel.is_webgpu_compliant();
+        let mut capabilities = Self {
+            backend: info.backend,
+            adapter_name: info.name,
+            max_texture_size: 0,
+            features,
+            // Resolving the depth reads a multisampled depth texture, which downlevel
+            // adapters can't bind
+            sample_counts: if webgpu_compliant {
+                vec![1, 4]
+            } else {
+                vec![1]
+            },
+            compute: downlevel.flags.contains(DownlevelFlags::COMPUTE_SHADERS)
+                && limits.max_storage_buffers_per_shader_stage > 0,
+            vertex_storage: downlevel.flags.contains(DownlevelFlags::VERTEX_STORAGE)
+                && limits.max_storage_buffers_per_shader_stage >= 3,
+            cube_arrays: downlevel
+                .flags
+                .contains(DownlevelFlags::CUBE_ARRAY_TEXTURES),
+            anisotropic_filtering: downlevel
+                .flags
+                .contains(DownlevelFlags::ANISOTROPIC_FILTERING),
+            webgpu_compliant,
+        };
+        capabilities.max_texture_size = capabilities.limits(adapter).max_texture_dimension_2d;
+        capabilities
+    }
+
+    // `requested` if the adapter supports it, else the most it does below that
+    pub fn sample_count(&self, requested: u32) -> u32 {
+        self.sample_counts
+            .iter()
+            .copied()
+            .filter(|count| *count <= requested)
+            .max()
+            .unwrap_or(1)
+    }
+
+    // Requested limits of the device, the WebGL2 ones on downlevel adapters
+    pub(crate) fn limits(&self, adapter: &Adapter) -> wgpu::Limits {
+        if !self.webgpu_compliant {
+            return wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits());
+        }
+        let bindless = crate::model::bindless_features();
+        if self.features.contains(bindless) {
+            let adapter_limits = adapter.limits();
+            wgpu::Limits {
+                max_push_constant_size: adapter_limits.max_push_constant_size.min(128),
+                max_sampled_textures_per_shader_stage: adapter_limits
+                    .max_sampled_textures_per_shader_stage,
+                ..Default::default()
+            }
+        } else {
+            wgpu::Limits::default()
+        }
+    }
+}
+
+// Backends to request an adapter from, in order. In the browser WebGPU is tried before WebGL2
+// unless the setting names one of them, each try gets its own instance and surface.
+pub fn backend_order(backend: Backend, web: bool) -> Vec<wgpu::Backends> {
+    match backend {
+        Backend::All | Backend::Primary if web => {
+            vec![wgpu::Backends::BROWSER_WEBGPU, wgpu::Backends::GL]
+        }
+        backend => vec![backend.into()],
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{stats, texture};
+use serde::Deserialize;
+use std::borrow::Cow;
+use wgpu::util::DeviceExt;
+use wgpu::{
+    BindGroup, Buffer, CommandEncoder, Device, Queue, RenderPipeline, SurfaceConfiguration,
+    TextureView,
+};
+
+type Matrix = [[f32; 3]; 3];
+
+const IDENTITY: Matrix = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorFilter {
+    None,
+    // Missing long wavelength cones, reds look dark and mix with greens
+    Protanopia,
+    // Missing medium wavelength cones, reds and greens mix
+    Deuteranopia,
+    // Missing short wavelength cones, blues and greens mix
+    Tritanopia,
+}
+
+impl ColorFilter {
+    // Machado et al. 2009 at full severity
+    fn simulation(self) -> Matrix {
+        match self {
+            ColorFilter::None => IDENTITY,
+            ColorFilter::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            ColorFilter::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            ColorFilter::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+
+    // Where daltonization moves the color difference the viewer can't see
+    fn shift(self) -> Matrix {
+        match self {
+            ColorFilter::Tritanopia => [[1., 0., 0.7], [0., 1., 0.7], [0., 0., 0.]],
+            _ => [[0., 0., 0.], [0.7, 1., 0.], [0.7, 0., 1.]],
+        }
+    }
+
+    // Shows the scene as the viewer with the deficiency sees it, or with `correct` shifts the
+    // colors they confuse towards ones they can tell apart
+    pub fn matrix(self, correct: bool) -> Matrix {
+        let simulation = self.simulation();
+        if !correct || self == ColorFilter::None {
+            return simulation;
+        }
+        // color + shift * (color - simulated)
+        let lost = add(IDENTITY, scale(simulation, -1.));
+        add(IDENTITY, multiply(self.shift(), lost))
+    }
+}
+
+fn add(a: Matrix, b: Matrix) -> Matrix {
+    let mut m = a;
+    for (row, b_row) in m.iter_mut().zip(b) {
+        for (x, y) in row.iter_mut().zip(b_row) {
+            *x += y;
+        }
+    }
+    m
+}
+
+fn scale(a: Matrix, factor: f32) -> Matrix {
+    a.map(|row| row.map(|x| x * factor))
+}
+
+fn multiply(a: Matrix, b: Matrix) -> Matrix {
+    let mut m = [[0.; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ColorMatrixUniform {
+    rows: [[f32; 4]; 3],
+}
+
+impl ColorMatrixUniform {
+    fn new(matrix: Matrix) -> Self {
+        Self {
+            rows: matrix.map(|[x, y, z]| [x, y, z, 0.]),
+        }
+    }
+}
+
+// The scene is rendered into `texture` instead of the surface while a filter is selected,
+// then drawn onto the surface through the filter matrix
+pub struct ColorFilterPass {
+    pipeline: RenderPipeline,
+    buffer: Buffer,
+    bind_group: BindGroup,
+    pub texture: texture::Texture,
+}
+
+impl ColorFilterPass {
+    pub fn new(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        filter: ColorFilter,
+        correct: bool,
+    ) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Color Filter Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("color_filter.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Color Filter Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[config.format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Color Filter Buffer"),
+            contents: bytemuck::cast_slice(&[ColorMatrixUniform::new(filter.matrix(correct))]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let texture = create_scene_texture(device, config);
+        let bind_group = create_bind_group(device, &pipeline, &texture, &buffer);
+        Self {
+            pipeline,
+            buffer,
+            bind_group,
+            texture,
+        }
+    }
+
+    pub fn set_filter(&self, queue: &Queue, filter: ColorFilter, correct: bool) {
+        let uniform = ColorMatrixUniform::new(filter.matrix(correct));
+        stats::write_buffer(queue, &self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn resize(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        self.texture = create_scene_texture(device, config);
+        self.bind_group = create_bind_group(device, &self.pipeline, &self.texture, &self.buffer);
+    }
+
+    // Call after everything was drawn into `texture`
+    pub fn apply(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Color Filter Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_scene_texture(device: &Device, config: &SurfaceConfiguration) -> texture::Texture {
+    texture::Texture::create_render_target(
+        device,
+        config.width,
+        config.height,
+        config.format,
+        "color_filter_scene",
+    )
+}
+
+fn create_bind_group(
+    device: &Device,
+    pipeline: &RenderPipeline,
+    texture: &texture::Texture,
+    buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("Color Filter Bind Group"),
+    })
+}
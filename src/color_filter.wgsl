@@ -0,0 +1,41 @@
+// Recolors the scene with a 3x3 matrix in linear RGB, see color_filter.rs
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    var v_out: VertexOutput;
+    v_out.position = vec4<f32>(x, y, 0.0, 1.0);
+    v_out.tex_coords = vec2<f32>((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+    return v_out;
+}
+
+struct ColorMatrix {
+    // Rows, w unused
+    r: vec4<f32>,
+    g: vec4<f32>,
+    b: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+@group(0) @binding(2)
+var<uniform> color_matrix: ColorMatrix;
+
+@fragment
+fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_scene, s_scene, f_in.tex_coords);
+    let rgb = vec3<f32>(
+        dot(color_matrix.r.xyz, color.rgb),
+        dot(color_matrix.g.xyz, color.rgb),
+        dot(color_matrix.b.xyz, color.rgb)
+    );
+    return vec4<f32>(clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
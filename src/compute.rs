@@ -0,0 +1,88 @@
+use std::borrow::Cow;
+use wgpu::{BindGroupLayout, BindGroupLayoutEntry, ComputePipeline, Device};
+
+// Compute passes and their WebGL fallback. WebGL2 and other GL backends have neither compute
+// shaders nor storage buffers, so every user of this module keeps a second path for them:
+// a CPU version of the same step uploading its results (particles.rs), or going without the
+// feature when it is only an optimization (occlusion.rs). Which one runs is decided once at
+// startup from `RendererCapabilities::compute`.
+
+// Workgroups to dispatch so that `items` invocations are run, `workgroup_size` per group
+pub fn workgroup_count(items: u32, workgroup_size: u32) -> u32 {
+    (items + workgroup_size - 1) / workgroup_size.max(1)
+}
+
+pub(crate) fn uniform_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn storage_entry(binding: u32, read_only: bool) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// Read with textureLoad, float textures that can't be filtered included
+pub(crate) fn texture_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn bind_group_layout(
+    device: &Device,
+    label: &str,
+    entries: &[BindGroupLayoutEntry],
+) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries,
+        label: Some(label),
+    })
+}
+
+// The `cs_main` entry point of `source` with the given bind groups
+pub(crate) fn create_pipeline(
+    device: &Device,
+    label: &str,
+    source: &'static str,
+    bind_group_layouts: &[&BindGroupLayout],
+) -> ComputePipeline {
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        module: &shader,
+        entry_point: "cs_main",
+    })
+}
@@ -0,0 +1,324 @@
+use crate::camera::CameraMode;
+use crate::color_filter::ColorFilter;
+use crate::cursor::GrabMode;
+use crate::light::{MAX_SHADOW_RESOLUTION, MIN_SHADOW_RESOLUTION};
+use crate::shadow::ShadowFilter;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "renderer.toml";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    All,
+    Primary,
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+    Webgpu,
+}
+
+impl From<Backend> for wgpu::Backends {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::All => wgpu::Backends::all(),
+            Backend::Primary => wgpu::Backends::PRIMARY,
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Dx11 => wgpu::Backends::DX11,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Webgpu => wgpu::Backends::BROWSER_WEBGPU,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerPreference {
+    Default,
+    Low,
+    High,
+}
+
+impl From<PowerPreference> for wgpu::PowerPreference {
+    fn from(power: PowerPreference) -> Self {
+        match power {
+            PowerPreference::Default => wgpu::PowerPreference::default(),
+            PowerPreference::Low => wgpu::PowerPreference::LowPower,
+            PowerPreference::High => wgpu::PowerPreference::HighPerformance,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedrawMode {
+    // Renders every frame like a game loop
+    Continuous,
+    // Waits for events and only renders while something changes, for embedded viewers.
+    // The built-in scene animations are paused.
+    Reactive,
+}
+
+// Startup settings of the renderer, read from renderer.toml and overridden by
+// `--key value` command line arguments with the same names
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendererConfig {
+    pub backend: Backend,
+    pub power_preference: PowerPreference,
+    // Samples per pixel of the scene passes, 1 or 4
+    pub msaa: u32,
+    // Post-process anti-aliasing, for builds without msaa
+    pub fxaa: bool,
+    pub vsync: bool,
+    pub redraw_mode: RedrawMode,
+    // Frame rate cap, 0 is unlimited
+    pub max_fps: u32,
+    // Frame rate while the window is unfocused or the page hidden, 0 disables throttling
+    pub background_fps: u32,
+    // Window sizes are applied once they stop changing for this long, 0 on the next frame
+    pub resize_delay_ms: u32,
+    pub shadow_resolution: u32,
+    // Kernel the shadow maps are sampled with, also State::set_shadow_filter
+    pub shadow_filter: ShadowFilter,
+    // Light shafts of the shadow casting lights, scattering per world unit, 0 disables them
+    pub volumetric_density: f32,
+    // Glare and ghosts of the lights in view, fading as geometry covers them
+    pub lens_flares: bool,
+    // A glass ball refracting the scene, which is drawn once more behind it at half resolution
+    pub glass: bool,
+    // Bakes the ambient light of the floor around the origin with the shadows of the scene. Off
+    // by default, the bake casts about 2 million rays on the CPU before the first frame.
+    pub lightmaps: bool,
+    // Max anisotropy of mipmapped textures, 1 disables it
+    pub anisotropy: u8,
+    // All textures of a model in one binding array where the adapter supports it, see model.rs
+    pub bindless: bool,
+    // Vertical field of view of the main camera in degrees
+    pub fov: f32,
+    pub movement_speed: f32,
+    pub mouse_sensitivity: f32,
+    // How the cursor is held while the left button has captured it for mouse look
+    pub cursor_grab: GrabMode,
+    // Keeps the camera from moving through the meshes of the scene
+    pub camera_collision: bool,
+    // Walking always collides, regardless of camera_collision
+    pub camera_mode: CameraMode,
+    // Top-down map of the surroundings in the top right corner
+    pub minimap: bool,
+    // Offset geometry by the camera position before projecting, for precision far from the origin
+    pub camera_relative: bool,
+    // Models further from the camera are drawn as billboards baked at startup, 0 never
+    pub imposter_distance: f32,
+    // Cubes scattered over the floor in one instanced draw for benchmarking, 0 adds none
+    pub stress_test_cubes: u32,
+    // Skips the stress test cubes hidden behind the last frame's depth, not on the web
+    pub occlusion_culling: bool,
+    // Grass blades swaying around the origin in one instanced draw, 0 grows none
+    pub grass_blades: u32,
+    // Sparks of the fountain, simulated on the GPU where compute shaders are supported
+    pub particles: u32,
+    // Simulates a color vision deficiency on the final image
+    pub color_filter: ColorFilter,
+    // Compensates for the filter's deficiency instead of simulating it
+    pub color_correction: bool,
+    pub high_contrast: bool,
+    // Directory or URL textures and models are read from, empty for the obj directory of the build
+    pub asset_root: String,
+    // Searched before the root, for replacing single files
+    pub asset_overrides: Vec<String>,
+    // Played in a loop from the assets with the audio feature, empty for silence
+    pub music: String,
+    // Of the music and every sound
+    pub volume: f32,
+    // Directory of Rhai files run and reloaded with the scripting feature, empty for none
+    pub scripts: String,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::All,
+            power_preference: PowerPreference::Default,
+            msaa: 4,
+            fxaa: cfg!(target_arch = "wasm32"),
+            vsync: true,
+            redraw_mode: RedrawMode::Continuous,
+            max_fps: 0,
+            background_fps: 5,
+            resize_delay_ms: 100,
+            shadow_resolution: 2048,
+            shadow_filter: ShadowFilter::Pcf3,
+            volumetric_density: 0.0,
+            lens_flares: true,
+            glass: true,
+            lightmaps: false,
+            anisotropy: 16,
+            bindless: true,
+            fov: 45.0,
+            movement_speed: 4.0,
+            mouse_sensitivity: 0.2,
+            cursor_grab: GrabMode::Locked,
+            camera_collision: true,
+            camera_mode: CameraMode::Fly,
+            minimap: false,
+            camera_relative: false,
+            imposter_distance: 500.0,
+            stress_test_cubes: 0,
+            occlusion_culling: false,
+            grass_blades: 0,
+            particles: 0,
+            color_filter: ColorFilter::None,
+            color_correction: false,
+            high_contrast: false,
+            asset_root: String::new(),
+            asset_overrides: vec![],
+            music: String::new(),
+            volume: 1.0,
+            scripts: "res/scripts".to_string(),
+        }
+    }
+}
+
+impl RendererConfig {
+    // Falls back to the defaults with a warning if the file or an argument is invalid
+    pub fn load() -> Self {
+        // No file system or arguments on the web
+        if cfg!(target_arch = "wasm32") {
+            return Self::default();
+        }
+        let config = Self::from_file(CONFIG_FILE)
+            .and_then(|config| config.with_args(std::env::args().skip(1)));
+        match config {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Invalid renderer config, using defaults: {:?}", e);
+                Self::default()
+            }
+        }
+    }
+
+    // A missing file is not an error
+    pub fn from_file(path: &str) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(source) => Self::from_toml(&source).with_context(|| format!("Reading {}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self> {
+        Ok(toml::from_str::<Self>(source)?.validated())
+    }
+
+    // Accepts `--key value` and `--key=value`
+    pub fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = match arg.strip_prefix("--") {
+                Some(arg) => arg.to_string(),
+                None => bail!("Unexpected argument {}", arg),
+            };
+            let (key, value) = match arg.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing value for --{}", arg))?;
+                    (arg, value)
+                }
+            };
+            self.set(&key.replace('-', "_"), &value)
+                .with_context(|| format!("Argument --{} {}", key, value))?;
+        }
+        Ok(self.validated())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        // Enums are parsed with serde so arguments accept the same names as the file
+        let variant = || toml::Value::String(value.to_lowercase());
+        match key {
+            "backend" => self.backend = variant().try_into()?,
+            "power_preference" => self.power_preference = variant().try_into()?,
+            "redraw_mode" => self.redraw_mode = variant().try_into()?,
+            "camera_mode" => self.camera_mode = variant().try_into()?,
+            "color_filter" => self.color_filter = variant().try_into()?,
+            "shadow_filter" => self.shadow_filter = variant().try_into()?,
+            "cursor_grab" => self.cursor_grab = variant().try_into()?,
+            "msaa" => self.msaa = value.parse()?,
+            "fxaa" => self.fxaa = value.parse()?,
+            "vsync" => self.vsync = value.parse()?,
+            "max_fps" => self.max_fps = value.parse()?,
+            "background_fps" => self.background_fps = value.parse()?,
+            "resize_delay_ms" => self.resize_delay_ms = value.parse()?,
+            "shadow_resolution" => self.shadow_resolution = value.parse()?,
+            "volumetric_density" => self.volumetric_density = value.parse()?,
+            "lens_flares" => self.lens_flares = value.parse()?,
+            "glass" => self.glass = value.parse()?,
+            "lightmaps" => self.lightmaps = value.parse()?,
+            "anisotropy" => self.anisotropy = value.parse()?,
+            "bindless" => self.bindless = value.parse()?,
+            "fov" => self.fov = value.parse()?,
+            "movement_speed" => self.movement_speed = value.parse()?,
+            "mouse_sensitivity" => self.mouse_sensitivity = value.parse()?,
+            "camera_collision" => self.camera_collision = value.parse()?,
+            "minimap" => self.minimap = value.parse()?,
+            "camera_relative" => self.camera_relative = value.parse()?,
+            "imposter_distance" => self.imposter_distance = value.parse()?,
+            "stress_test_cubes" => self.stress_test_cubes = value.parse()?,
+            "occlusion_culling" => self.occlusion_culling = value.parse()?,
+            "grass_blades" => self.grass_blades = value.parse()?,
+            "particles" => self.particles = value.parse()?,
+            "color_correction" => self.color_correction = value.parse()?,
+            "high_contrast" => self.high_contrast = value.parse()?,
+            "asset_root" => self.asset_root = value.to_string(),
+            // Comma separated on the command line
+            "asset_overrides" => {
+                self.asset_overrides = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|dir| !dir.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "music" => self.music = value.to_string(),
+            "volume" => self.volume = value.parse()?,
+            "scripts" => self.scripts = value.to_string(),
+            _ => bail!("Unknown setting {}", key),
+        }
+        Ok(())
+    }
+
+    fn validated(mut self) -> Self {
+        // Lowered further at startup if the adapter can't do 4
+        if self.msaa != 1 && self.msaa != 4 {
+            log::warn!("Unsupported msaa {}, using 4", self.msaa);
+            self.msaa = 4;
+        }
+        self.shadow_resolution = self
+            .shadow_resolution
+            .clamp(MIN_SHADOW_RESOLUTION, MAX_SHADOW_RESOLUTION);
+        self.anisotropy = self.anisotropy.clamp(1, 16);
+        // Also turns NaN into no shafts
+        self.volumetric_density = self.volumetric_density.max(0.);
+        self.imposter_distance = self.imposter_distance.max(0.);
+        self
+    }
+
+    pub fn max_fps(&self) -> Option<u32> {
+        Some(self.max_fps).filter(|fps| *fps > 0)
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        if self.vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            wgpu::PresentMode::Immediate
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use serde::Deserialize;
+use winit::dpi::PhysicalPosition;
+use winit::window::Window;
+#[cfg(target_arch = "wasm32")]
+use {
+    std::{cell::Cell, rc::Rc},
+    wasm_bindgen::{prelude::*, JsCast},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GrabMode {
+    // The cursor stays visible and inside the window
+    Confined,
+    // The cursor is hidden and held in the center, only its motion is used
+    Locked,
+}
+
+// Captures the cursor for mouse look while the left button is clicked, and lets it go on
+// Escape or when the window loses focus. Platforms differ in what set_cursor_grab does: macOS
+// locks, Windows and X11 confine, Wayland may refuse, and the web has neither but the Pointer
+// Lock API. Where the grab fails a locked cursor is put back in the center after every move.
+pub struct CursorCapture {
+    pub mode: GrabMode,
+    captured: bool,
+    recenter: bool,
+    // Set by the page's pointerlockchange events, browsers release the lock on Escape
+    // themselves and it has to be requested again from a click
+    #[cfg(target_arch = "wasm32")]
+    pointer_locked: Rc<Cell<bool>>,
+}
+
+impl CursorCapture {
+    pub fn new(mode: GrabMode) -> Self {
+        Self {
+            mode,
+            captured: false,
+            recenter: false,
+            #[cfg(target_arch = "wasm32")]
+            pointer_locked: watch_pointer_lock(),
+        }
+    }
+
+    // Whether mouse motion moves the camera
+    pub fn is_captured(&self) -> bool {
+        self.captured && self.pointer_locked()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn pointer_locked(&self) -> bool {
+        self.pointer_locked.get()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn pointer_locked(&self) -> bool {
+        true
+    }
+
+    pub fn capture(&mut self, window: &Window) {
+        if self.is_captured() {
+            return;
+        }
+        self.captured = true;
+        #[cfg(target_arch = "wasm32")]
+        {
+            use winit::platform::web::WindowExtWebSys;
+            // Both modes, pages can't confine the cursor
+            window.canvas().request_pointer_lock();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let grabbed = window.set_cursor_grab(true);
+            if let Err(e) = &grabbed {
+                log::warn!("Cursor grab failed: {}", e);
+            }
+            self.recenter = self.mode == GrabMode::Locked && grabbed.is_err();
+            window.set_cursor_visible(self.mode == GrabMode::Confined);
+        }
+    }
+
+    pub fn release(&mut self, window: &Window) {
+        if !self.captured {
+            return;
+        }
+        self.captured = false;
+        self.recenter = false;
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = window;
+            if let Some(document) = web_sys::window().and_then(|win| win.document()) {
+                document.exit_pointer_lock();
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            window.set_cursor_grab(false).ok();
+            window.set_cursor_visible(true);
+        }
+    }
+
+    // After each mouse motion while captured
+    pub fn moved(&self, window: &Window) {
+        if self.recenter {
+            let size = window.inner_size();
+            let center = PhysicalPosition::new(size.width / 2, size.height / 2);
+            window.set_cursor_position(center).ok();
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn watch_pointer_lock() -> Rc<Cell<bool>> {
+    let locked = Rc::new(Cell::new(false));
+    let document = match web_sys::window().and_then(|win| win.document()) {
+        Some(document) => document,
+        None => return locked,
+    };
+    let on_change = {
+        let locked = Rc::clone(&locked);
+        Closure::wrap(Box::new(move |_e: web_sys::Event| {
+            let document = web_sys::window().and_then(|win| win.document());
+            let element = document.and_then(|doc| doc.pointer_lock_element());
+            locked.set(element.is_some());
+        }) as Box<dyn FnMut(_)>)
+    };
+    document
+        .add_event_listener_with_callback("pointerlockchange", on_change.as_ref().unchecked_ref())
+        .ok();
+    on_change.forget();
+    // Requests too soon after the browser released the lock are refused
+    let on_error = Closure::wrap(Box::new(move |_e: web_sys::Event| {
+        log::warn!("Pointer lock refused, click again");
+    }) as Box<dyn FnMut(_)>);
+    document
+        .add_event_listener_with_callback("pointerlockerror", on_error.as_ref().unchecked_ref())
+        .ok();
+    on_error.forget();
+    locked
+}
@@ -0,0 +1,143 @@
+use crate::probes;
+use anyhow::{anyhow, bail, Result};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::{Binding, Module, ShaderStage, TypeInner};
+
+const GEO_SHADER: &str = include_str!("geo.wgsl");
+const FRAGMENT_MARKER: &str = "// FRAGMENT_ENTRY";
+
+// (group, binding) pairs provided by the engine's pipeline layout for entities
+const ENGINE_BINDINGS: [(u32, u32); 21] = [
+    // camera
+    (0, 0),
+    // lights, scene uniform, reflection probes, the area light tables and the sky
+    (1, 0),
+    (1, 1),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (1, 5),
+    (1, 6),
+    (1, 7),
+    (1, 8),
+    // diffuse texture, sampler, tint, emissive texture and lightmap
+    (2, 0),
+    (2, 1),
+    (2, 2),
+    (2, 3),
+    (2, 4),
+    // shadow maps, comparison sampler, filter settings, variance shadow moments and gobos
+    (3, 0),
+    (3, 1),
+    (3, 2),
+    (3, 3),
+    (3, 4),
+    (3, 5),
+];
+
+// Vertex and instance buffer locations, see geo_gen::Vertex::desc and world_space::desc
+const ENGINE_VERTEX_LOCATIONS: [u32; 13] = [0, 1, 2, 3, 5, 6, 7, 8, 9, 10, 11, 12, 13];
+
+pub enum CustomShader {
+    // Replaces `fs_main` of geo.wgsl, every struct, binding and helper declared there is in scope.
+    // Must declare `@fragment fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32>`.
+    Fragment(String),
+    // A complete module with `vs_main` and `fs_main` entry points
+    Full(String),
+}
+
+pub const HOLOGRAM: &str = r#"
+@fragment
+fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    let view_dir = normalize(camera.view_pos.xyz - f_in.world_position);
+    let rim = 1.0 - abs(dot(normalize(f_in.world_normal), view_dir));
+    let scan = 0.5 + 0.5 * sin(f_in.world_position.y * 4.0 - scene.time * 6.0);
+    let color = vec3<f32>(0.2, 0.8, 1.0) * (pow(rim, 2.0) + 0.3 * scan);
+    return vec4<f32>(color, 1.0);
+}
+"#;
+
+impl CustomShader {
+    // Build the final WGSL source and check it against the engine layouts before wgpu sees it,
+    // so a typo in a user shader is reported instead of panicking inside pipeline creation.
+    pub(crate) fn compose(&self) -> Result<String> {
+        let source = match self {
+            CustomShader::Fragment(fragment) => format!("{}\n{}", geo_prelude()?, fragment),
+            CustomShader::Full(source) => probes::shader_source(source),
+        };
+        let module = naga::front::wgsl::parse_str(&source)
+            .map_err(|e| anyhow!("{}", e.emit_to_string(&source)))?;
+        Validator::new(ValidationFlags::all(), Capabilities::empty())
+            .validate(&module)
+            .map_err(|e| anyhow!("Invalid custom shader: {:?}", e))?;
+        validate_interface(&module)?;
+        Ok(source)
+    }
+}
+
+// Structs, bindings and lighting helpers of geo.wgsl without its fragment entry point
+pub(crate) fn geo_prelude() -> Result<String> {
+    GEO_SHADER
+        .split(FRAGMENT_MARKER)
+        .next()
+        .map(probes::shader_source)
+        .ok_or_else(|| anyhow!("geo.wgsl has no fragment marker"))
+}
+
+fn validate_interface(module: &Module) -> Result<()> {
+    for (stage, name) in [
+        (ShaderStage::Vertex, "vs_main"),
+        (ShaderStage::Fragment, "fs_main"),
+    ] {
+        if !module
+            .entry_points
+            .iter()
+            .any(|ep| ep.stage == stage && ep.name == name)
+        {
+            bail!(
+                "Custom shader is missing the {:?} entry point {}",
+                stage,
+                name
+            );
+        }
+    }
+
+    for (_, var) in module.global_variables.iter() {
+        if let Some(binding) = &var.binding {
+            if !ENGINE_BINDINGS.contains(&(binding.group, binding.binding)) {
+                bail!(
+                    "Custom shader binding {:?} (group {}, binding {}) is not provided by the engine",
+                    var.name,
+                    binding.group,
+                    binding.binding
+                );
+            }
+        }
+    }
+
+    let vs_main = module
+        .entry_points
+        .iter()
+        .find(|ep| ep.stage == ShaderStage::Vertex && ep.name == "vs_main")
+        .unwrap();
+    for argument in &vs_main.function.arguments {
+        let bindings: Vec<&Binding> = match (&argument.binding, &module.types[argument.ty].inner) {
+            (Some(binding), _) => vec![binding],
+            (None, TypeInner::Struct { members, .. }) => {
+                members.iter().filter_map(|m| m.binding.as_ref()).collect()
+            }
+            _ => vec![],
+        };
+        for binding in bindings {
+            if let Binding::Location { location, .. } = binding {
+                if !ENGINE_VERTEX_LOCATIONS.contains(location) {
+                    bail!(
+                        "Custom shader reads vertex location {} which no buffer provides",
+                        location
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::camera::Camera;
+use crate::stats::DrawStats;
+use crate::world_space::InstanceTransform;
+use crate::{multi_sample, stats, texture, RenderGroup, State, PRIMITIVE};
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use std::time::Duration;
+use wgpu::{Buffer, Device, Queue, RenderPass, RenderPipeline, SurfaceConfiguration};
+
+const CAMERA_COLOR: [f32; 4] = [1., 1., 1., 1.];
+const LIGHT_COLOR: [f32; 4] = [1., 0.85, 0.1, 1.];
+const BOUNDS_COLOR: [f32; 4] = [0.2, 1., 0.4, 1.];
+const AXES_SIZE: f32 = 10.;
+// Segments of each of the three circles outlining a sphere
+const SPHERE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl LineVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// Line segments in world space, two vertices each
+#[derive(Debug, Default)]
+pub struct LineList {
+    pub vertices: Vec<LineVertex>,
+}
+
+impl LineList {
+    pub fn line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4]) {
+        for point in [from, to] {
+            self.vertices.push(LineVertex {
+                position: point.into(),
+                color,
+            });
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        self.box_edges(aabb.corners(), color);
+    }
+
+    // The volume a view projection sees, e.g. of a camera or a shadow casting light.
+    // Nothing is added for a matrix that can't be inverted.
+    pub fn frustum(&mut self, view_proj: Matrix4<f32>, color: [f32; 4]) {
+        if let Some(corners) = frustum_corners(view_proj) {
+            self.box_edges(corners, color);
+        }
+    }
+
+    // A circle around each axis
+    pub fn sphere(&mut self, sphere: &BoundingSphere, color: [f32; 4]) {
+        let point = |axis: usize, angle: f32| {
+            let (sin, cos) = (angle.sin() * sphere.radius, angle.cos() * sphere.radius);
+            sphere.center
+                + match axis {
+                    0 => Vector3::new(0., cos, sin),
+                    1 => Vector3::new(cos, 0., sin),
+                    _ => Vector3::new(cos, sin, 0.),
+                }
+        };
+        let step = std::f32::consts::TAU / SPHERE_SEGMENTS as f32;
+        for axis in 0..3 {
+            for i in 0..SPHERE_SEGMENTS {
+                let from = point(axis, i as f32 * step);
+                self.line(from, point(axis, (i + 1) as f32 * step), color);
+            }
+        }
+    }
+
+    // Red, green and blue along the rotated x, y and z axes, ignoring the scale
+    pub fn axes(&mut self, transform: &InstanceTransform, size: f32) {
+        let origin = Point3::from_vec(transform.position);
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        for (i, axis) in axes.into_iter().enumerate() {
+            let mut color = [0., 0., 0., 1.];
+            color[i] = 1.;
+            self.line(origin, origin + transform.rotation * axis * size, color);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    // Corners indexed by bits like Aabb::corners: bit 0 is x, bit 1 is y and bit 2 is z
+    fn box_edges(&mut self, corners: [Point3<f32>; 8], color: [f32; 4]) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+}
+
+// Corners of the clip volume in world space, in the order of Aabb::corners with the near
+// plane (depth 0) first along z
+pub fn frustum_corners(view_proj: Matrix4<f32>) -> Option<[Point3<f32>; 8]> {
+    let inverse = view_proj.invert()?;
+    Some([0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+        let ndc = Vector4::new(
+            if i & 1 == 0 { -1. } else { 1. },
+            if i & 2 == 0 { -1. } else { 1. },
+            if i & 4 == 0 { 0. } else { 1. },
+            1.,
+        );
+        Point3::from_homogeneous(inverse * ndc)
+    }))
+}
+
+// Drawn over everything else, without depth testing. Lines are uploaded and cleared in update,
+// add them again every frame.
+pub struct DebugLinesRenderGroup {
+    pub lines: LineList,
+    // Lines the vertex buffer holds, more are dropped until `fit` grows it
+    capacity: usize,
+    vertex_buffer: Buffer,
+    vertex_count: u32,
+    render_pipeline: RenderPipeline,
+}
+
+impl DebugLinesRenderGroup {
+    pub fn new(
+        device: &Device,
+        camera: &Camera,
+        config: &SurfaceConfiguration,
+        capacity: usize,
+    ) -> Self {
+        let vertex_buffer = create_vertex_buffer(device, capacity);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Lines Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug_lines.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Lines Pipeline Layout"),
+            bind_group_layouts: &[&camera.camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Lines Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..PRIMITIVE
+            },
+            depth_stencil: texture::Texture::create_depth_state().map(|state| {
+                wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    ..state
+                }
+            }),
+            multisample: multi_sample(),
+            multiview: None,
+        });
+        Self {
+            lines: LineList::default(),
+            capacity,
+            vertex_buffer,
+            vertex_count: 0,
+            render_pipeline,
+        }
+    }
+
+    // Grows the vertex buffer to the next power of two above the lines drawn this frame
+    pub fn fit(&mut self, device: &Device) {
+        if self.lines.len() > self.capacity {
+            self.capacity = self.lines.len().next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.capacity);
+        }
+    }
+}
+
+fn create_vertex_buffer(device: &Device, lines: usize) -> Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Lines Vertex Buffer"),
+        size: (std::mem::size_of::<LineVertex>() * lines * 2) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+impl RenderGroup for DebugLinesRenderGroup {
+    fn update(&mut self, _dt: Duration, queue: &Queue) {
+        let vertices = &self.lines.vertices;
+        let vertices = &vertices[..vertices.len().min(self.capacity * 2)];
+        if !vertices.is_empty() {
+            stats::write_buffer(
+                queue,
+                &self.vertex_buffer,
+                0,
+                bytemuck::cast_slice(vertices),
+            );
+        }
+        self.vertex_count = vertices.len() as u32;
+        self.lines.clear();
+    }
+
+    fn draw_stats(&self, shadow_pass: bool) -> DrawStats {
+        if shadow_pass || self.vertex_count == 0 {
+            return DrawStats::default();
+        }
+        DrawStats::new(1, 1)
+    }
+
+    fn render<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, shadow_pass: bool) {
+        if shadow_pass || self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+// What State draws into the debug lines group every frame, nothing by default
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DebugView {
+    // Frozen where the camera was when this was turned on, so it can be looked at from outside
+    pub camera_frustum: bool,
+    // The shadow frustum of every light
+    pub light_frusta: bool,
+    // Bounding sphere of every render group
+    pub bounds: bool,
+    // World axes at the origin and local axes of the picked instance
+    pub axes: bool,
+}
+
+impl DebugView {
+    pub fn all() -> Self {
+        Self {
+            camera_frustum: true,
+            light_frusta: true,
+            bounds: true,
+            axes: true,
+        }
+    }
+}
+
+impl State {
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        if view.camera_frustum && !self.debug_view.camera_frustum {
+            self.frozen_frustum = Some(self.camera.camera_uniform.view_proj.into());
+        }
+        self.debug_view = view;
+        self.request_redraw();
+    }
+
+    // For lines of your own, drawn in the next frame and then cleared. Call the draw functions
+    // again every frame to keep something on screen.
+    pub fn debug_lines_mut(&mut self) -> Option<&mut LineList> {
+        let group = self
+            .render_groups
+            .typed_mut::<DebugLinesRenderGroup>(self.debug_lines)?;
+        Some(&mut group.lines)
+    }
+
+    pub fn draw_line(&mut self, from: Point3<f32>, to: Point3<f32>, color: [f32; 4]) {
+        if let Some(lines) = self.debug_lines_mut() {
+            lines.line(from, to, color);
+        }
+    }
+
+    pub fn draw_aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        if let Some(lines) = self.debug_lines_mut() {
+            lines.aabb(aabb, color);
+        }
+    }
+
+    pub fn draw_sphere(&mut self, sphere: &BoundingSphere, color: [f32; 4]) {
+        if let Some(lines) = self.debug_lines_mut() {
+            lines.sphere(sphere, color);
+        }
+    }
+
+    pub fn draw_axes(&mut self, transform: &InstanceTransform, size: f32) {
+        if let Some(lines) = self.debug_lines_mut() {
+            lines.axes(transform, size);
+        }
+    }
+
+    pub(crate) fn draw_debug_view(&mut self) {
+        let view = self.debug_view;
+        let mut lines = LineList::default();
+        if let (true, Some(frustum)) = (view.camera_frustum, self.frozen_frustum) {
+            lines.frustum(frustum, CAMERA_COLOR);
+        }
+        if view.light_frusta {
+            for light in &self.light_render_group.light_uniforms {
+                lines.frustum(light.view_proj.into(), LIGHT_COLOR);
+            }
+        }
+        if view.bounds {
+            for (_, group) in self.render_groups.iter() {
+                if let Some(sphere) = group.bounding_sphere() {
+                    lines.sphere(&sphere, BOUNDS_COLOR);
+                }
+            }
+        }
+        if view.axes {
+            lines.axes(&InstanceTransform::default(), AXES_SIZE);
+            let picked = self
+                .picked
+                .and_then(|(group, instance)| self.render_groups.get(group)?.transform(instance));
+            if let Some(transform) = picked {
+                lines.axes(&transform, AXES_SIZE / 2.);
+            }
+        }
+        let device = &self.device;
+        let group = self
+            .render_groups
+            .typed_mut::<DebugLinesRenderGroup>(self.debug_lines);
+        if let Some(group) = group {
+            group.lines.vertices.append(&mut lines.vertices);
+            group.fit(device);
+        }
+    }
+}
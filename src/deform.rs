@@ -0,0 +1,241 @@
+// Morph targets and skins moved in the vertex shaders. deform of deform.wgsl adds up the
+// targets of a mesh with their current weights, then blends up to 4 joint matrices by the joint
+// indices and weights of the vertex. vs_main calls it like vs_bake and vs_depth of shadow.wgsl,
+// so the shadow maps and the depth pre-pass see the same shape as the main pass. The targets,
+// weights and joint matrices are storage buffers indexed by the vertex, the joint indices and
+// weights a third vertex buffer. Vertex shaders without storage buffers, on WebGL and most GL
+// drivers, draw the vertices morph.rs and skinning.rs move on the CPU instead.
+use crate::morph::MeshMorph;
+use crate::skinning::{matrix_data, Influence, MeshSkin, ModelSkeleton};
+use crate::stats;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, Buffer, Device};
+use wgpu::{Queue, RenderPass};
+
+// Whether vertex shaders can read storage buffers, set at startup from RendererCapabilities
+pub(crate) static VERTEX_DEFORM: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn vertex_deform() -> bool {
+    VERTEX_DEFORM.load(Ordering::Relaxed)
+}
+
+// Group 1 of the depth-only passes, which only bind the light before it
+pub(crate) const DEPTH_GROUP: u32 = 1;
+
+// The vertices as they are, for the pipelines of meshes that don't move
+const RIGID: &str = "struct Deformed {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+};
+
+fn deform(model: VertexInput) -> Deformed {
+    return Deformed(model.position, model.normal);
+}";
+
+// Fills in deform and the inputs it reads of shader.wgsl or shadow.wgsl, from the buffers
+// bound at `group`. None draws the rest pose without them.
+pub(crate) fn shader_source(source: &str, group: Option<u32>) -> String {
+    match group {
+        Some(group) => source
+            .replace(
+                "// DEFORM_INPUT",
+                "@location(14) joints: vec4<u32>,\n    \
+                 @location(15) weights: vec4<f32>,\n    \
+                 @builtin(vertex_index) index: u32,",
+            )
+            .replace(
+                "// DEFORM_FUNCTION",
+                &include_str!("deform.wgsl").replace("DEFORM_GROUP", &group.to_string()),
+            ),
+        None => source.replace("// DEFORM_FUNCTION", RIGID),
+    }
+}
+
+fn storage_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::VERTEX,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// The counts, morph deltas, morph weights and joint matrices, after the material bindings so
+// the main pass can share its group with them
+pub(crate) fn layout_entries() -> [BindGroupLayoutEntry; 4] {
+    [
+        BindGroupLayoutEntry {
+            binding: 8,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        storage_entry(9),
+        storage_entry(10),
+        storage_entry(11),
+    ]
+}
+
+// Of DEPTH_GROUP, created by every pass that draws deformed meshes. Layouts with the same
+// entries are interchangeable.
+pub(crate) fn bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &layout_entries(),
+        label: Some("Deform Bind Group Layout"),
+    })
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DeformCounts {
+    // Vertices, morph targets and joints, w unused
+    counts: [u32; 4],
+}
+
+// The buffers deform reads for one mesh. Storage buffers can't be empty, a mesh without morph
+// targets binds a zero delta and weight, one without a skin zero joint weights.
+pub(crate) struct MeshDeform {
+    // The material of the mesh followed by the deform bindings, group 2 of the main pass
+    bind_group: BindGroup,
+    depth_bind_group: BindGroup,
+    influence_buffer: Buffer,
+    // Written whenever the morph weights change
+    weight_buffer: Buffer,
+}
+
+impl MeshDeform {
+    // `layouts` are the material and deform layout of the main pass and bind_group_layout,
+    // `material` the entries of the material of the mesh. The morph targets and joint influences
+    // move to the GPU, the CPU copies are dropped.
+    pub fn new(
+        device: &Device,
+        label: &str,
+        layouts: [&BindGroupLayout; 2],
+        material: Vec<BindGroupEntry>,
+        vertex_count: usize,
+        morph: Option<&mut MeshMorph>,
+        skin: Option<(&mut MeshSkin, &ModelSkeleton)>,
+    ) -> Self {
+        let (mut deltas, mut weights) = match morph {
+            Some(morph) => (morph.take_deltas(), morph.weights().to_vec()),
+            None => (vec![], vec![]),
+        };
+        let target_count = weights.len() as u32;
+        let (influences, joints) = match skin {
+            Some((skin, skeleton)) => {
+                let joints = skeleton.joint_buffer(skin.skin());
+                (skin.take_influences(), joints)
+            }
+            None => (vec![Influence::default(); vertex_count], None),
+        };
+        let joint_count = joints.map_or(0, |(_, count)| count);
+        if deltas.is_empty() {
+            deltas.push([0.; 4]);
+            weights.push(0.);
+        }
+        let storage = |name, contents: &[u8], usage| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} {} Buffer", label, name)),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE | usage,
+            })
+        };
+        let counts_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Deform Uniform Buffer", label)),
+            contents: bytemuck::cast_slice(&[DeformCounts {
+                counts: [vertex_count as u32, target_count, joint_count, 0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let delta_buffer = storage(
+            "Morph Target",
+            bytemuck::cast_slice(&deltas),
+            wgpu::BufferUsages::empty(),
+        );
+        let weight_buffer = storage(
+            "Morph Weight",
+            bytemuck::cast_slice(&weights),
+            wgpu::BufferUsages::COPY_DST,
+        );
+        let identity_buffer;
+        let joint_buffer = match joints {
+            Some((buffer, _)) => buffer,
+            None => {
+                identity_buffer = storage(
+                    "Joint Matrix",
+                    bytemuck::cast_slice(&matrix_data(&[])),
+                    wgpu::BufferUsages::empty(),
+                );
+                &identity_buffer
+            }
+        };
+        let influence_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Joint Influence Buffer", label)),
+            contents: bytemuck::cast_slice(&influences),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let buffers = [&counts_buffer, &delta_buffer, &weight_buffer, joint_buffer];
+        let deform_entries = buffers
+            .iter()
+            .zip(8..)
+            .map(|(buffer, binding)| BindGroupEntry {
+                binding,
+                resource: buffer.as_entire_binding(),
+            });
+        let entries: Vec<_> = material.into_iter().chain(deform_entries.clone()).collect();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: layouts[0],
+            entries: &entries,
+            label: Some(&format!("{} Deform Bind Group", label)),
+        });
+        let depth_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: layouts[1],
+            entries: &deform_entries.collect::<Vec<_>>(),
+            label: Some(&format!("{} Deform Depth Bind Group", label)),
+        });
+        Self {
+            bind_group,
+            depth_bind_group,
+            influence_buffer,
+            weight_buffer,
+        }
+    }
+
+    pub fn set_weights(&self, queue: &Queue, weights: &[f32]) {
+        if !weights.is_empty() {
+            stats::write_buffer(queue, &self.weight_buffer, 0, bytemuck::cast_slice(weights));
+        }
+    }
+
+    // Binds the material and the buffers of the mesh for the main pass
+    pub fn bind<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(2, self.influence_buffer.slice(..));
+    }
+
+    // With the deformed pipeline of a depth-only pass and the instances bound
+    pub fn render_depth<'a, 'b: 'a>(
+        &'b self,
+        render_pass: &mut RenderPass<'a>,
+        vertex_buffer: &'b Buffer,
+        index_buffer: &'b Buffer,
+        num_elements: u32,
+        instances: Range<u32>,
+    ) {
+        render_pass.set_bind_group(DEPTH_GROUP, &self.depth_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(2, self.influence_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..num_elements, 0, instances);
+    }
+}
@@ -0,0 +1,58 @@
+// Moves a vertex by the morph targets of its mesh, then by the joints of its skin. Mirrors
+// morph::blend_morph_targets followed by skinning::skin_vertices, meshes with only one of them
+// bind no targets or no joints.
+struct Deformed {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+};
+
+struct DeformCounts {
+    // Vertices, morph targets and joints, w unused
+    counts: vec4<u32>,
+};
+
+@group(DEFORM_GROUP) @binding(8)
+var<uniform> deform_counts: DeformCounts;
+// Position then normal offset of every vertex of the first target, then of the next one
+@group(DEFORM_GROUP) @binding(9)
+var<storage, read> morph_deltas: array<vec4<f32>>;
+@group(DEFORM_GROUP) @binding(10)
+var<storage, read> morph_weights: array<f32>;
+@group(DEFORM_GROUP) @binding(11)
+var<storage, read> joint_matrices: array<mat4x4<f32>>;
+
+fn deform(model: VertexInput) -> Deformed {
+    var position = model.position;
+    var normal = model.normal;
+    let count = deform_counts.counts.x;
+    for (var i = 0u; i < deform_counts.counts.y; i = i + 1u) {
+        let weight = morph_weights[i];
+        let delta = (i * count + model.index) * 2u;
+        position = position + morph_deltas[delta].xyz * weight;
+        normal = normal + morph_deltas[delta + 1u].xyz * weight;
+    }
+    if dot(normal, normal) > 0.0 {
+        normal = normalize(normal);
+    }
+    var skinned = vec4<f32>(0.0);
+    var skinned_normal = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = 0u; i < 4u; i = i + 1u) {
+        let joint = model.joints[i];
+        let weight = model.weights[i];
+        if weight != 0.0 && joint < deform_counts.counts.z {
+            let joint_matrix = joint_matrices[joint];
+            skinned = skinned + joint_matrix * vec4<f32>(position, 1.0) * weight;
+            skinned_normal = skinned_normal + joint_matrix * vec4<f32>(normal, 0.0) * weight;
+            total = total + weight;
+        }
+    }
+    if total == 0.0 {
+        return Deformed(position, normal);
+    }
+    var out_normal = skinned_normal.xyz;
+    if dot(out_normal, out_normal) > 0.0 {
+        out_normal = normalize(out_normal);
+    }
+    return Deformed(skinned.xyz, out_normal);
+}
@@ -0,0 +1,169 @@
+use crate::light::{LightRenderGroup, LightUniform};
+use crate::scene::Scene;
+use crate::stats::DrawStats;
+use crate::{deform, multi_sample, primitive, shadow, stats, texture, Camera, RenderGroup};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, Buffer, CommandEncoder, Device, Queue, RenderPipeline, TextureView};
+
+// Lays down depth with the shadow vertex shader so the main pass only shades visible fragments.
+// vs_bake reads `light.view_proj`, so the camera matrix is fed through a light shaped uniform.
+pub struct DepthPrePass {
+    pipeline: RenderPipeline,
+    double_sided_pipeline: RenderPipeline,
+    // Single then double-sided, for the meshes moved in the vertex shader. None where they are
+    // moved on the CPU.
+    deformed_pipelines: Option<[RenderPipeline; 2]>,
+    buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl DepthPrePass {
+    pub fn new(device: &Device, light_render_group: &LightRenderGroup, scene: &Scene) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth pre-pass VB"),
+            contents: bytemuck::cast_slice(&[LightUniform::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let [probe_texture, probe_sampler, probe_uniform, sky] = scene.probes.bind_group_entries();
+        let [ltc_matrices, ltc_magnitudes, ltc_sampler] =
+            light_render_group.ltc.bind_group_entries();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &light_render_group.light_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: scene.buffer.as_entire_binding(),
+                },
+                probe_texture,
+                probe_sampler,
+                probe_uniform,
+                ltc_matrices,
+                ltc_magnitudes,
+                ltc_sampler,
+                sky,
+            ],
+            label: Some("Depth pre-pass bind group"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth pre-pass"),
+            bind_group_layouts: &[&light_render_group.light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_shader = |deformed| {
+            device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Depth pre-pass Shader"),
+                source: wgpu::ShaderSource::Wgsl(shadow::shader_source(deformed).into()),
+            })
+        };
+        let create_pipeline =
+            |layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, deformed, double_sided| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Depth pre-pass"),
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: shader,
+                        entry_point: "vs_depth",
+                        buffers: &shadow::vertex_buffers(deformed),
+                    },
+                    fragment: None,
+                    // Must rasterize exactly like the main pass or depth_compare Equal will reject
+                    // fragments, culling included
+                    primitive: primitive(double_sided),
+                    depth_stencil: texture::Texture::create_depth_state(),
+                    multisample: multi_sample(),
+                    multiview: None,
+                })
+            };
+        let deformed_pipelines = deform::vertex_deform().then(|| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Deformed depth pre-pass"),
+                bind_group_layouts: &[
+                    &light_render_group.light_bind_group_layout,
+                    &deform::bind_group_layout(device),
+                ],
+                push_constant_ranges: &[],
+            });
+            let shader = create_shader(true);
+            [false, true].map(|double_sided| create_pipeline(&layout, &shader, true, double_sided))
+        });
+        let shader = create_shader(false);
+        Self {
+            pipeline: create_pipeline(&pipeline_layout, &shader, false, false),
+            double_sided_pipeline: create_pipeline(&pipeline_layout, &shader, false, true),
+            deformed_pipelines,
+            buffer,
+            bind_group,
+        }
+    }
+
+    pub fn update(&self, queue: &Queue, camera: &Camera) {
+        let [x, y, z, _] = camera.camera_uniform.render_origin;
+        let uniform = LightUniform {
+            position: [x, y, z],
+            view_proj: camera.camera_uniform.relative_view_proj,
+            ..Default::default()
+        };
+        stats::write_buffer(queue, &self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Split screen cameras share the depth texture, only the first one clears it. `culled`
+    // draws what the occlusion culling kept, as the main pass will.
+    pub fn render_pass(
+        &self,
+        encoder: &mut CommandEncoder,
+        depth_view: &TextureView,
+        clear: bool,
+        viewport: Option<[f32; 4]>,
+        culled: bool,
+        groups: &[&dyn RenderGroup],
+    ) -> DrawStats {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Pre-Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: if clear {
+                        wgpu::LoadOp::Clear(1.0)
+                    } else {
+                        wgpu::LoadOp::Load
+                    },
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        if let Some([x, y, width, height]) = viewport {
+            pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        }
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        // The shadow path of every group only binds geometry, which is all a depth pass needs
+        // Cut out groups would lay down depth where their fragments are discarded
+        let mut stats = DrawStats::default();
+        groups.iter().filter(|x| !x.cutout()).for_each(|x| {
+            pass.set_pipeline(if x.double_sided() {
+                &self.double_sided_pipeline
+            } else {
+                &self.pipeline
+            });
+            if culled {
+                x.render_culled(&mut pass, true);
+            } else {
+                x.render(&mut pass, true);
+            }
+            stats += x.draw_stats(true);
+        });
+        if let Some(pipelines) = &self.deformed_pipelines {
+            let deformed = groups.iter().filter(|x| !x.cutout() && x.deformed());
+            deformed.for_each(|x| {
+                pass.set_pipeline(&pipelines[x.double_sided() as usize]);
+                x.render_deformed(&mut pass, false);
+            });
+        }
+        stats
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{sample_count, texture};
+use std::borrow::Cow;
+use wgpu::{BindGroup, CommandEncoder, Device, RenderPipeline, SurfaceConfiguration};
+
+// Copies the scene depth into a single sampled texture post effects can read on every backend.
+// Multisampled depth keeps the nearest sample of each pixel.
+pub struct DepthResolve {
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    pub texture: texture::Texture,
+}
+
+impl DepthResolve {
+    pub fn new(device: &Device, config: &SurfaceConfiguration, depth: &texture::Texture) -> Self {
+        let depth_texture = if sample_count() == 1 {
+            "texture_depth_2d"
+        } else {
+            "texture_depth_multisampled_2d"
+        };
+        let source = include_str!("depth_resolve.wgsl")
+            .replace("DEPTH_TEXTURE", depth_texture)
+            .replace("SAMPLE_COUNT", &sample_count().to_string());
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Resolve Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Resolve Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            }],
+            label: Some("Depth Resolve Bind Group"),
+        });
+        Self {
+            pipeline,
+            bind_group,
+            texture: create_resolved_texture(device, config),
+        }
+    }
+
+    // Call after the main pass wrote the depth texture
+    pub fn resolve(&self, encoder: &mut CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_resolved_texture(device: &Device, config: &SurfaceConfiguration) -> texture::Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("resolved_depth"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: texture::Texture::DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // Depth is read unfiltered, post effects use textureLoad or a non-filtering sampler
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("resolved_depth"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+    texture::Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
@@ -0,0 +1,22 @@
+// DEPTH_TEXTURE and SAMPLE_COUNT are substituted when the pipeline is built
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+@group(0)
+@binding(0)
+var t_depth: DEPTH_TEXTURE;
+
+// Keeps the nearest sample so edges belong to the foreground
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    let coords = vec2<i32>(position.xy);
+    var depth = 1.0;
+    for (var i = 0; i < SAMPLE_COUNT; i = i + 1) {
+        depth = min(depth, textureLoad(t_depth, coords, i));
+    }
+    return depth;
+}
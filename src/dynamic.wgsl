@@ -0,0 +1,32 @@
+// Appended to the geo.wgsl prelude, see dynamic_uniform.rs
+
+struct ObjectUniform {
+    model: mat4x4<f32>,
+    normal: mat3x3<f32>,
+    // Multiplied with the diffuse texture
+    color: vec4<f32>,
+};
+
+// Bound once per group, a dynamic offset selects the object of each draw
+@group(2) @binding(4)
+var<uniform> object: ObjectUniform;
+
+@vertex
+fn vs_dynamic(model: VertexInput) -> VertexOutput {
+    var v_out: VertexOutput;
+    v_out.tex_coords = model.tex_coords;
+    v_out.instance_color = vec4<f32>(1.0);
+    v_out.world_normal = normalize(object.normal * model.normal);
+    var world_position: vec4<f32> = object.model * vec4<f32>(model.position, 1.0);
+    v_out.world_position = world_position.xyz;
+    v_out.clip_position = world_to_clip(world_position.xyz);
+    v_out.probe = nearest_probe(object.model[3].xyz);
+    return v_out;
+}
+
+@fragment
+fn fs_dynamic(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    let v_tex = vec2<f32>(f_in.tex_coords.x, 1.0 - f_in.tex_coords.y);
+    let obj_color = textureSample(t_diffuse, s_diffuse, v_tex) * object.color;
+    return vec4<f32>(apply_tint(shade(f_in, obj_color.rgb)) + emission(v_tex), obj_color.a);
+}
@@ -0,0 +1,381 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::camera::Camera;
+use crate::custom_shader::geo_prelude;
+use crate::geo_gen::{GeoObj, Vertex};
+use crate::light::LightRenderGroup;
+use crate::raycast::{closer, Ray, RayHit};
+use crate::shadow::ShadowPass;
+use crate::spatial::Contact;
+use crate::stats::DrawStats;
+use crate::tint::Tint;
+use crate::world_space::{InstanceTransform, Instances};
+use crate::{multi_sample, stats, texture, RenderGroup, PRIMITIVE};
+use std::marker::PhantomData;
+use std::mem;
+use std::num::NonZeroU64;
+use std::sync::Arc;
+use std::time::Duration;
+use wgpu::{BindGroup, Buffer, Device, Queue, RenderPass, RenderPipeline, SurfaceConfiguration};
+
+// One uniform buffer holding `capacity` values of T, each at an offset the device accepts
+// for has_dynamic_offset bindings
+pub struct DynamicUniform<T> {
+    pub(crate) buffer: Buffer,
+    stride: u32,
+    data: Vec<u8>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> DynamicUniform<T> {
+    pub fn new(device: &Device, capacity: usize) -> Self {
+        let size = mem::size_of::<T>() as u32;
+        let align = device.limits().min_uniform_buffer_offset_alignment;
+        let stride = match size % align {
+            0 => size,
+            rest => size + align - rest,
+        };
+        let data = vec![0; stride as usize * capacity.max(1)];
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Dynamic Uniform Buffer"),
+            size: data.len() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            stride,
+            data,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len() / self.stride as usize
+    }
+
+    pub fn offset(&self, index: usize) -> u32 {
+        index as u32 * self.stride
+    }
+
+    pub fn set(&mut self, index: usize, value: T) {
+        let start = self.offset(index) as usize;
+        self.data[start..start + mem::size_of::<T>()].copy_from_slice(bytemuck::bytes_of(&value));
+    }
+
+    pub fn write(&self, queue: &Queue) {
+        stats::write_buffer(queue, &self.buffer, 0, &self.data);
+    }
+
+    pub fn layout_entry(
+        binding: u32,
+        visibility: wgpu::ShaderStages,
+    ) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: NonZeroU64::new(mem::size_of::<T>() as u64),
+            },
+            count: None,
+        }
+    }
+
+    // Each draw sees a single T, the dynamic offset picks which one
+    pub fn binding(&self) -> wgpu::BindingResource {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: NonZeroU64::new(mem::size_of::<T>() as u64),
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ObjectUniform {
+    model: [[f32; 4]; 4],
+    // mat3x3 columns are padded to vec4 in uniform buffers
+    normal: [[f32; 4]; 3],
+    color: [f32; 4],
+}
+
+impl ObjectUniform {
+    fn new(transform: &InstanceTransform, color: [f32; 4]) -> Self {
+        let normal: [[f32; 3]; 3] = transform.normal_matrix().into();
+        Self {
+            model: transform.model_matrix().into(),
+            normal: normal.map(|[x, y, z]| [x, y, z, 0.]),
+            color,
+        }
+    }
+}
+
+pub struct DynamicObject {
+    // Shared so many objects can draw the same mesh
+    pub obj: Arc<GeoObj>,
+    pub transform: InstanceTransform,
+    pub color: [f32; 4],
+}
+
+// Draws many small objects with one bind group, selecting the per-object data with a
+// dynamic offset instead of creating a buffer and bind group per object.
+pub struct DynamicRenderGroup {
+    pub objects: Vec<DynamicObject>,
+    pub tint: Tint,
+    uniforms: DynamicUniform<ObjectUniform>,
+    bind_group: BindGroup,
+    // The shadow and depth pre-passes only know the instanced vertex layout,
+    // so the transforms are mirrored into an instance buffer and drawn one instance at a time
+    shadow_instances: Instances,
+    render_pipeline: RenderPipeline,
+    // Of despawned entities, objects past the end are shown
+    hidden: Vec<bool>,
+}
+
+impl DynamicRenderGroup {
+    pub fn new(
+        device: &Device,
+        camera: &Camera,
+        diffuse_texture: &texture::Texture,
+        objects: Vec<DynamicObject>,
+        config: &SurfaceConfiguration,
+        light_render_group: &LightRenderGroup,
+        shadow_pass: &ShadowPass,
+    ) -> Self {
+        let tint = Tint::new(device);
+        let uniforms = DynamicUniform::new(device, objects.len());
+        let emissive_texture = texture::Texture::black(device, "No emission");
+        let mut entries = texture::Texture::desc().entries.to_vec();
+        entries.push(DynamicUniform::<ObjectUniform>::layout_entry(
+            4,
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("dynamic_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: tint.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&emissive_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniforms.binding(),
+                },
+            ],
+            label: Some("dynamic_bind_group"),
+        });
+        let shadow_instances = Instances::new(
+            objects.iter().map(|object| object.transform).collect(),
+            device,
+        );
+
+        let source = format!(
+            "{}\n{}",
+            geo_prelude().unwrap(),
+            include_str!("dynamic.wgsl")
+        );
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Dynamic Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Dynamic Pipeline Layout"),
+                bind_group_layouts: &[
+                    &camera.camera_bind_group_layout,
+                    &light_render_group.light_bind_group_layout,
+                    &bind_group_layout,
+                    &shadow_pass.shadow_map_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Dynamic Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_dynamic",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_dynamic",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: PRIMITIVE,
+            depth_stencil: texture::Texture::create_opaque_depth_state(),
+            multisample: multi_sample(),
+            multiview: None,
+        });
+        Self {
+            objects,
+            tint,
+            uniforms,
+            bind_group,
+            shadow_instances,
+            render_pipeline,
+            hidden: vec![],
+        }
+    }
+}
+
+impl DynamicRenderGroup {
+    fn is_hidden(&self, instance: usize) -> bool {
+        self.hidden.get(instance).copied().unwrap_or(false)
+    }
+
+    // Objects that are drawn, with their index
+    fn shown(&self) -> impl Iterator<Item = (usize, &DynamicObject)> {
+        let objects = self.objects.iter().enumerate();
+        objects.filter(move |(i, _)| !self.is_hidden(*i))
+    }
+}
+
+impl RenderGroup for DynamicRenderGroup {
+    fn update(&mut self, dt: Duration, queue: &Queue) {
+        self.tint.update(dt, queue);
+        // Objects can't be added after creation, the buffers are sized for the initial set
+        let count = self.uniforms.capacity().min(self.objects.len());
+        for (i, object) in self.objects.iter().take(count).enumerate() {
+            self.uniforms
+                .set(i, ObjectUniform::new(&object.transform, object.color));
+            self.shadow_instances.instance_transforms[i] = object.transform;
+        }
+        self.uniforms.write(queue);
+        self.shadow_instances.update_buffer(queue);
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.shown()
+            .map(|(_, object)| {
+                let transform = &object.transform;
+                object.obj.bounds.transformed(
+                    transform.position,
+                    transform.rotation,
+                    transform.scale,
+                )
+            })
+            .reduce(|acc, next| acc.union(&next))
+    }
+
+    fn instance_bounding_sphere(&self, instance: usize) -> Option<BoundingSphere> {
+        if self.is_hidden(instance) {
+            return None;
+        }
+        let object = self.objects.get(instance)?;
+        let t = &object.transform;
+        let bounds = &object.obj.bounds;
+        Some(bounds.transformed(t.position, t.rotation, t.scale))
+    }
+
+    // `instance` of the hit is the index into `objects`
+    fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        self.shown()
+            .map(|(i, object)| {
+                let hit = object.obj.raycast(ray, &object.transform)?;
+                Some(RayHit { instance: i, ..hit })
+            })
+            .fold(None, closer)
+    }
+
+    fn contacts(&self, sphere: &BoundingSphere) -> Vec<Contact> {
+        self.shown()
+            .filter_map(|(instance, object)| {
+                let (point, distance) =
+                    object
+                        .obj
+                        .closest_point(sphere.center, sphere.radius, &object.transform)?;
+                Some(Contact {
+                    instance,
+                    point,
+                    distance,
+                })
+            })
+            .collect()
+    }
+
+    fn overlapping(&self, aabb: &Aabb) -> Vec<usize> {
+        self.shown()
+            .filter(|(_, object)| object.obj.overlaps_aabb(aabb, &object.transform))
+            .map(|(instance, _)| instance)
+            .collect()
+    }
+
+    fn transform(&self, instance: usize) -> Option<InstanceTransform> {
+        self.objects.get(instance).map(|object| object.transform)
+    }
+
+    // Written to the GPU with the next update
+    fn set_transform(&mut self, instance: usize, transform: InstanceTransform, _queue: &Queue) {
+        if let Some(object) = self.objects.get_mut(instance) {
+            object.transform = transform;
+        }
+    }
+
+    fn set_hidden(&mut self, instance: usize, hidden: bool, _queue: &Queue) {
+        if instance >= self.objects.len() {
+            return;
+        }
+        if self.hidden.len() <= instance {
+            self.hidden.resize(instance + 1, false);
+        }
+        self.hidden[instance] = hidden;
+    }
+
+    fn tint_mut(&mut self) -> Option<&mut Tint> {
+        Some(&mut self.tint)
+    }
+
+    fn is_animating(&self) -> bool {
+        self.tint.is_animating()
+    }
+
+    fn draw_stats(&self, _shadow_pass: bool) -> DrawStats {
+        let count = self.uniforms.capacity().min(self.objects.len()) as u32;
+        DrawStats::new(count, count)
+    }
+
+    fn render<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, shadow_pass: bool) {
+        let count = self.uniforms.capacity().min(self.objects.len());
+        if shadow_pass {
+            render_pass.set_vertex_buffer(0, self.shadow_instances.instance_buffer.slice(..));
+            for (i, object) in self.shown().take_while(|(i, _)| *i < count) {
+                let obj = &object.obj;
+                render_pass.set_vertex_buffer(1, obj.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(obj.index_buffer.slice(..), obj.index_format);
+                render_pass.draw_indexed(obj.get_index_range(), 0, i as u32..i as u32 + 1);
+            }
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        for (i, object) in self.shown().take_while(|(i, _)| *i < count) {
+            let obj = &object.obj;
+            render_pass.set_bind_group(2, &self.bind_group, &[self.uniforms.offset(i)]);
+            render_pass.set_vertex_buffer(0, obj.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(obj.index_buffer.slice(..), obj.index_format);
+            render_pass.draw_indexed(obj.get_index_range(), 0, 0..1);
+        }
+    }
+}
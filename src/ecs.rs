@@ -0,0 +1,474 @@
+use crate::history::Edit;
+use crate::render_groups::{GroupId, RenderGroups};
+use crate::world_space::InstanceTransform;
+use crate::State;
+use cgmath::{EuclideanSpace, Vector3};
+use std::collections::{BTreeMap, BTreeSet};
+
+// A scene object. The generation tells a despawned entity apart from a new one in its slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+// Drawn as one instance of a render group
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MeshHandle {
+    pub group: GroupId,
+    pub instance: usize,
+}
+
+// The tint of a render group, shared by every instance of it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MaterialHandle {
+    pub group: GroupId,
+}
+
+// One of the light uniforms of the LightRenderGroup
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LightHandle {
+    pub index: usize,
+}
+
+// The entity follows the camera, its transform is read only
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CameraHandle;
+
+// One component type, in entity order
+pub struct Components<T> {
+    items: BTreeMap<EntityId, T>,
+}
+
+impl<T> Default for Components<T> {
+    fn default() -> Self {
+        Self {
+            items: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Components<T> {
+    pub fn get(&self, entity: EntityId) -> Option<&T> {
+        self.items.get(&entity)
+    }
+
+    pub fn get_mut(&mut self, entity: EntityId) -> Option<&mut T> {
+        self.items.get_mut(&entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.items.iter().map(|(entity, item)| (*entity, item))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn insert(&mut self, entity: EntityId, item: T) {
+        self.items.insert(entity, item);
+    }
+
+    fn remove(&mut self, entity: EntityId) -> Option<T> {
+        self.items.remove(&entity)
+    }
+}
+
+// Scene objects as entities with components. Render groups still own the GPU resources,
+// components point into them and the systems in State::run_world_systems keep both in sync.
+// Culling and drawing stay with the render groups, there are no systems for them here.
+// Entities with a parent have their transform relative to it.
+#[derive(Default)]
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    transforms: Components<InstanceTransform>,
+    meshes: Components<MeshHandle>,
+    materials: Components<MaterialHandle>,
+    lights: Components<LightHandle>,
+    cameras: Components<CameraHandle>,
+    parents: Components<EntityId>,
+    // Transforms set since the last upload
+    changed: BTreeSet<EntityId>,
+    // Since State last published them as EntitySpawned
+    spawned: Vec<EntityId>,
+    // Meshes of despawned entities, hidden on the next update
+    despawned: Vec<MeshHandle>,
+}
+
+impl World {
+    pub fn spawn(&mut self) -> EntityId {
+        let entity = match self.free.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                EntityId {
+                    index,
+                    generation: self.generations[index as usize],
+                }
+            }
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                EntityId {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+        self.spawned.push(entity);
+        entity
+    }
+
+    // Drops every component, false if the entity was already gone. Its mesh instance is
+    // hidden on the next update as an edit that undo brings back with a new entity.
+    pub fn despawn(&mut self, entity: EntityId) -> bool {
+        let mesh = self.meshes.get(entity).copied();
+        if !self.remove(entity) {
+            return false;
+        }
+        self.despawned.extend(mesh);
+        true
+    }
+
+    // Despawns without hiding the mesh, for history and groups that are gone anyway
+    pub(crate) fn remove(&mut self, entity: EntityId) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        // Children stay where they are in the world
+        for child in self.children(entity) {
+            self.set_parent(child, None);
+        }
+        let index = entity.index as usize;
+        self.alive[index] = false;
+        self.generations[index] += 1;
+        self.free.push(entity.index);
+        self.transforms.remove(entity);
+        self.meshes.remove(entity);
+        self.materials.remove(entity);
+        self.lights.remove(entity);
+        self.cameras.remove(entity);
+        self.parents.remove(entity);
+        self.changed.remove(&entity);
+        true
+    }
+
+    pub fn is_alive(&self, entity: EntityId) -> bool {
+        let index = entity.index as usize;
+        index < self.alive.len()
+            && self.alive[index]
+            && self.generations[index] == entity.generation
+    }
+
+    pub fn len(&self) -> usize {
+        self.alive.iter().filter(|alive| **alive).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Relative to the parent, the world transform for entities without one
+    pub fn transform(&self, entity: EntityId) -> Option<InstanceTransform> {
+        self.transforms.get(entity).copied()
+    }
+
+    // Uploaded to the mesh or light of the entity and its descendants on the next update
+    pub fn set_transform(&mut self, entity: EntityId, transform: InstanceTransform) {
+        if self.is_alive(entity) {
+            self.transforms.insert(entity, transform);
+            self.changed.insert(entity);
+        }
+    }
+
+    // Every ancestor's transform applied to the local one
+    pub fn world_transform(&self, entity: EntityId) -> Option<InstanceTransform> {
+        let mut transform = self.transform(entity)?;
+        let mut parent = self.parent(entity);
+        while let Some(ancestor) = parent {
+            if let Some(ancestor_transform) = self.transform(ancestor) {
+                transform = ancestor_transform.compose(&transform);
+            }
+            parent = self.parent(ancestor);
+        }
+        Some(transform)
+    }
+
+    pub fn set_world_transform(&mut self, entity: EntityId, transform: InstanceTransform) {
+        let local = match self
+            .parent(entity)
+            .and_then(|parent| self.world_transform(parent))
+        {
+            Some(parent) => parent.relative(&transform),
+            None => transform,
+        };
+        self.set_transform(entity, local);
+    }
+
+    pub fn parent(&self, entity: EntityId) -> Option<EntityId> {
+        self.parents.get(entity).copied()
+    }
+
+    // The child keeps its world placement. False if either entity is gone or the child is
+    // an ancestor of the parent.
+    pub fn set_parent(&mut self, child: EntityId, parent: Option<EntityId>) -> bool {
+        if !self.is_alive(child) {
+            return false;
+        }
+        if let Some(parent) = parent {
+            if !self.is_alive(parent)
+                || self.ancestors(parent).any(|a| a == child)
+                || parent == child
+            {
+                return false;
+            }
+        }
+        let world = self.world_transform(child);
+        match parent {
+            Some(parent) => self.parents.insert(child, parent),
+            None => {
+                self.parents.remove(child);
+            }
+        }
+        if let Some(world) = world {
+            self.set_world_transform(child, world);
+        }
+        true
+    }
+
+    pub fn children(&self, entity: EntityId) -> Vec<EntityId> {
+        self.parents
+            .iter()
+            .filter(|(_, parent)| **parent == entity)
+            .map(|(child, _)| child)
+            .collect()
+    }
+
+    fn has_children(&self, entity: EntityId) -> bool {
+        self.parents.iter().any(|(_, parent)| *parent == entity)
+    }
+
+    fn ancestors(&self, entity: EntityId) -> impl Iterator<Item = EntityId> + '_ {
+        std::iter::successors(self.parent(entity), |entity| self.parent(*entity))
+    }
+
+    // Children, their children and so on
+    fn descendants(&self, entity: EntityId) -> Vec<EntityId> {
+        let mut descendants = self.children(entity);
+        let mut i = 0;
+        while i < descendants.len() {
+            let children = self.children(descendants[i]);
+            descendants.extend(children);
+            i += 1;
+        }
+        descendants
+    }
+
+    pub fn set_mesh(&mut self, entity: EntityId, mesh: MeshHandle) {
+        if self.is_alive(entity) {
+            self.meshes.insert(entity, mesh);
+        }
+    }
+
+    pub fn set_material(&mut self, entity: EntityId, material: MaterialHandle) {
+        if self.is_alive(entity) {
+            self.materials.insert(entity, material);
+        }
+    }
+
+    pub fn set_light(&mut self, entity: EntityId, light: LightHandle) {
+        if self.is_alive(entity) {
+            self.lights.insert(entity, light);
+        }
+    }
+
+    pub fn set_camera(&mut self, entity: EntityId) {
+        if self.is_alive(entity) {
+            self.cameras.insert(entity, CameraHandle);
+        }
+    }
+
+    pub fn meshes(&self) -> &Components<MeshHandle> {
+        &self.meshes
+    }
+
+    pub fn materials(&self) -> &Components<MaterialHandle> {
+        &self.materials
+    }
+
+    pub fn lights(&self) -> &Components<LightHandle> {
+        &self.lights
+    }
+
+    pub fn cameras(&self) -> &Components<CameraHandle> {
+        &self.cameras
+    }
+
+    // The entity drawn as this instance
+    pub fn mesh_entity(&self, group: GroupId, instance: usize) -> Option<EntityId> {
+        self.meshes
+            .iter()
+            .find(|(_, mesh)| mesh.group == group && mesh.instance == instance)
+            .map(|(entity, _)| entity)
+    }
+
+    // One entity per editable instance of the group, with the group's tint as material
+    pub(crate) fn spawn_group(&mut self, render_groups: &RenderGroups, group: GroupId) {
+        let index = render_groups.index_of(group);
+        if let Some(render_group) = index.and_then(|index| render_groups.get(index)) {
+            let transforms = (0..).map_while(|i| render_group.transform(i).map(|t| (i, t)));
+            for (instance, transform) in transforms {
+                self.spawn_mesh(MeshHandle { group, instance }, transform);
+            }
+        }
+    }
+
+    pub(crate) fn take_spawned(&mut self) -> Vec<EntityId> {
+        std::mem::take(&mut self.spawned)
+    }
+
+    // An entity for an instance that was shown again
+    pub(crate) fn spawn_mesh(&mut self, mesh: MeshHandle, transform: InstanceTransform) {
+        let entity = self.spawn();
+        self.transforms.insert(entity, transform);
+        self.set_mesh(entity, mesh);
+        self.set_material(entity, MaterialHandle { group: mesh.group });
+    }
+
+    // Keeps a transform without uploading it, for changes that already reached the GPU
+    fn mirror_transform(&mut self, entity: EntityId, transform: InstanceTransform) {
+        self.transforms.insert(entity, transform);
+    }
+
+    fn mirror_position(&mut self, entity: EntityId, position: Vector3<f32>) {
+        let mut transform = self.transform(entity).unwrap_or_default();
+        transform.position = position;
+        self.mirror_transform(entity, transform);
+    }
+}
+
+impl State {
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    // Recolors the material of the entity as an undoable edit
+    pub fn set_entity_color(&mut self, entity: EntityId, color: [f32; 3]) {
+        let group = self
+            .world
+            .materials
+            .get(entity)
+            .and_then(|material| self.render_groups.index_of(material.group));
+        if let Some(group) = group {
+            self.set_material_color(group, color);
+        }
+    }
+
+    // Called by history when an edit moves an instance, so the world does not go stale
+    pub(crate) fn mirror_instance_transform(
+        &mut self,
+        group: usize,
+        instance: usize,
+        transform: InstanceTransform,
+    ) {
+        let entity = self
+            .render_groups
+            .id(group)
+            .and_then(|group| self.world.mesh_entity(group, instance));
+        if let Some(entity) = entity {
+            // Children follow on the next update
+            self.world.set_world_transform(entity, transform);
+        }
+    }
+
+    // Meshes of despawned entities are hidden first. Animated root lights and the camera are
+    // mirrored into the world next. Then the world transform of everything that moved, and of
+    // its descendants, is uploaded to meshes and lights. Meshes of removed groups are skipped
+    // until the group returns.
+    pub(crate) fn run_world_systems(&mut self) {
+        for mesh in std::mem::take(&mut self.world.despawned) {
+            if let Some(group) = self.render_groups.index_of(mesh.group) {
+                self.edit(Edit::Despawn {
+                    group,
+                    instance: mesh.instance,
+                });
+            }
+        }
+        let mut moved = std::mem::take(&mut self.world.changed);
+        let lights: Vec<_> = self.world.lights.iter().map(|(e, l)| (e, *l)).collect();
+        for &(entity, light) in &lights {
+            if moved.contains(&entity) || self.world.parent(entity).is_some() {
+                continue;
+            }
+            if let Some(uniform) = self.light_render_group.light_uniforms.get(light.index) {
+                self.world.mirror_position(entity, uniform.position.into());
+                if self.world.has_children(entity) {
+                    moved.insert(entity);
+                }
+            }
+        }
+        let cameras: Vec<_> = self.world.cameras.iter().map(|(e, _)| e).collect();
+        for &entity in &cameras {
+            let position = self.camera.view.position.to_vec();
+            self.world.mirror_position(entity, position);
+            if self.world.has_children(entity) {
+                moved.insert(entity);
+            }
+        }
+
+        let mut dirty = BTreeSet::new();
+        for entity in moved {
+            dirty.insert(entity);
+            dirty.extend(self.world.descendants(entity));
+        }
+        // update_light moved them again this frame
+        dirty.extend(
+            lights
+                .iter()
+                .map(|(entity, _)| *entity)
+                .filter(|entity| self.world.parent(*entity).is_some()),
+        );
+        for entity in dirty {
+            let transform = match self.world.world_transform(entity) {
+                Some(transform) => transform,
+                None => continue,
+            };
+            if let Some(mesh) = self.world.meshes.get(entity) {
+                let index = self.render_groups.index_of(mesh.group);
+                if let Some(group) = index.and_then(|index| self.render_groups.get_mut(index)) {
+                    group.set_transform(mesh.instance, transform, &self.queue);
+                }
+            }
+            if let Some(light) = self.world.lights.get(entity) {
+                if let Some(uniform) = self.light_render_group.light_uniforms.get_mut(light.index) {
+                    uniform.position = transform.position.into();
+                }
+            }
+        }
+    }
+
+    // Entities for the built-in scene, called once from State::new
+    pub(crate) fn spawn_world(&mut self) {
+        let groups: Vec<_> = self.render_groups.iter().map(|(id, _)| id).collect();
+        for group in groups {
+            self.world.spawn_group(&self.render_groups, group);
+        }
+        for index in 0..self.light_render_group.light_uniforms.len() {
+            let entity = self.world.spawn();
+            self.world.set_light(entity, LightHandle { index });
+        }
+        let camera = self.world.spawn();
+        self.world.set_camera(camera);
+        // Fills in the light and camera transforms
+        self.run_world_systems();
+    }
+}
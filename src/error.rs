@@ -0,0 +1,51 @@
+use crate::config::Backend;
+use std::fmt;
+
+// Why the renderer could not start. `run` shows it in place of the scene instead of panicking.
+#[derive(Debug)]
+pub enum InitError {
+    // No GPU, or none matching the backend and power preference settings
+    NoAdapter(Backend),
+    // The adapter refused the features and limits asked for
+    Device(wgpu::RequestDeviceError),
+    // The surface supports none of the color formats the renderer can present
+    NoSurfaceFormat,
+    // A texture or model of the built-in scene is missing or can't be decoded
+    Asset { name: String, source: anyhow::Error },
+    Window(winit::error::OsError),
+}
+
+impl InitError {
+    // For map_err on the result of loading `name`
+    pub(crate) fn asset(name: &str) -> impl FnOnce(anyhow::Error) -> Self + '_ {
+        move |source| InitError::Asset {
+            name: name.to_string(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InitError::NoAdapter(backend) => {
+                write!(f, "No graphics adapter found for the {:?} backend", backend)
+            }
+            InitError::Device(e) => write!(f, "The graphics device could not be created: {}", e),
+            InitError::NoSurfaceFormat => f.write_str("The window has no usable color format"),
+            InitError::Asset { name, source } => write!(f, "Could not load {}: {:#}", name, source),
+            InitError::Window(e) => write!(f, "The window could not be created: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InitError::Device(e) => Some(e),
+            InitError::Asset { source, .. } => Some(source.as_ref()),
+            InitError::Window(e) => Some(e),
+            InitError::NoAdapter(_) | InitError::NoSurfaceFormat => None,
+        }
+    }
+}
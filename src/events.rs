@@ -0,0 +1,170 @@
+// Engine happenings as plain event types that handlers subscribe to by type. Handlers get the
+// context the bus lives in, for State that's the whole renderer, and run in the order they were
+// subscribed as soon as an event is published. Events published by a handler wait until the
+// current one reached every handler.
+use crate::ecs::EntityId;
+use crate::render_groups::GroupId;
+use crate::State;
+use std::any::{Any, TypeId};
+
+// After the surface took the new size. The attachments are recreated by the first handler.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WindowResized {
+    pub width: u32,
+    pub height: u32,
+}
+
+// A file of the assets or one opened by the user made it into the scene
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetLoaded {
+    pub name: String,
+    // None for sounds and scripts
+    pub group: Option<GroupId>,
+}
+
+// Published once per update for the entities spawned since the last one that are still alive
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntitySpawned {
+    pub entity: EntityId,
+}
+
+// By State::set_light and undoing or redoing it, not by animators or scripts
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LightChanged {
+    pub index: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Subscription(u64);
+
+type Handler<C, E> = Box<dyn FnMut(&mut C, &E)>;
+
+pub struct EventBus<C> {
+    // A Handler<C, E> per entry, E is the TypeId
+    handlers: Vec<(Subscription, TypeId, Box<dyn Any>)>,
+    next_id: u64,
+    dispatching: bool,
+    // Of the handlers taken out while dispatching, and those of them unsubscribed meanwhile
+    running: Vec<Subscription>,
+    removed: Vec<Subscription>,
+    deferred: Vec<Box<dyn FnOnce(&mut C)>>,
+}
+
+impl<C> Default for EventBus<C> {
+    fn default() -> Self {
+        Self {
+            handlers: vec![],
+            next_id: 0,
+            dispatching: false,
+            running: vec![],
+            removed: vec![],
+            deferred: vec![],
+        }
+    }
+}
+
+impl<C: 'static> EventBus<C> {
+    pub fn subscribe<E: 'static>(
+        &mut self,
+        handler: impl FnMut(&mut C, &E) + 'static,
+    ) -> Subscription {
+        let subscription = Subscription(self.next_id);
+        self.next_id += 1;
+        let handler: Handler<C, E> = Box::new(handler);
+        let entry = (
+            subscription,
+            TypeId::of::<E>(),
+            Box::new(handler) as Box<dyn Any>,
+        );
+        self.handlers.push(entry);
+        subscription
+    }
+
+    // False if it was unsubscribed already
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        let count = self.handlers.len();
+        self.handlers.retain(|(id, ..)| *id != subscription);
+        if self.handlers.len() != count {
+            return true;
+        }
+        if self.running.contains(&subscription) && !self.removed.contains(&subscription) {
+            self.removed.push(subscription);
+            return true;
+        }
+        false
+    }
+
+    pub fn subscriber_count<E: 'static>(&self) -> usize {
+        let event = TypeId::of::<E>();
+        self.handlers
+            .iter()
+            .filter(|(_, ty, _)| *ty == event)
+            .count()
+    }
+
+    // Runs the handlers of E on the context the bus is part of, `bus` finds it there. Handlers
+    // may subscribe, unsubscribe and publish through the context meanwhile.
+    pub fn publish<E: 'static>(context: &mut C, bus: fn(&mut C) -> &mut Self, event: E) {
+        if bus(context).dispatching {
+            bus(context).deferred.push(Box::new(move |context: &mut C| {
+                Self::publish(context, bus, event)
+            }));
+            return;
+        }
+        let mut handlers = std::mem::take(&mut bus(context).handlers);
+        bus(context).running = handlers.iter().map(|(id, ..)| *id).collect();
+        bus(context).dispatching = true;
+        let kind = TypeId::of::<E>();
+        for (_, ty, handler) in &mut handlers {
+            if *ty != kind {
+                continue;
+            }
+            if let Some(handler) = handler.downcast_mut::<Handler<C, E>>() {
+                handler(context, &event);
+            }
+        }
+        let bus_now = bus(context);
+        bus_now.running.clear();
+        let removed = std::mem::take(&mut bus_now.removed);
+        handlers.retain(|(id, ..)| !removed.contains(id));
+        // Subscribed by the handlers
+        handlers.append(&mut bus_now.handlers);
+        bus_now.handlers = handlers;
+        bus_now.dispatching = false;
+        for publish in std::mem::take(&mut bus_now.deferred) {
+            publish(context);
+        }
+    }
+}
+
+impl State {
+    // See events.rs
+    pub fn subscribe<E: 'static>(
+        &mut self,
+        handler: impl FnMut(&mut State, &E) + 'static,
+    ) -> Subscription {
+        self.events.subscribe(handler)
+    }
+
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        self.events.unsubscribe(subscription)
+    }
+
+    // For events of the user as well as the engine's own
+    pub fn publish<E: 'static>(&mut self, event: E) {
+        EventBus::publish(self, events_of, event);
+    }
+
+    // Of the entities spawned since the last update
+    pub(crate) fn publish_spawned(&mut self) {
+        for entity in self.world.take_spawned() {
+            if self.world.is_alive(entity) {
+                self.publish(EntitySpawned { entity });
+            }
+        }
+    }
+}
+
+fn events_of(state: &mut State) -> &mut EventBus<State> {
+    &mut state.events
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// The passes of one frame and the attachments they read and write, for visualization only.
+// Every write creates a new version of the resource so the exported graph stays acyclic.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrameGraph {
+    passes: Vec<(String, Vec<String>, Vec<String>)>,
+    versions: HashMap<String, usize>,
+    resources: Vec<String>,
+}
+
+impl FrameGraph {
+    pub fn pass(&mut self, name: &str, reads: &[&str], writes: &[&str]) {
+        let reads = reads.iter().map(|r| self.current(r)).collect();
+        let writes = writes
+            .iter()
+            .map(|w| {
+                let version = self.versions.entry(w.to_string()).or_insert(0);
+                *version += 1;
+                let id = format!("{}#{}", w, version);
+                self.resources.push(id.clone());
+                id
+            })
+            .collect();
+        self.passes.push((name.to_string(), reads, writes));
+    }
+
+    pub fn len(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    fn current(&mut self, resource: &str) -> String {
+        let version = *self.versions.entry(resource.to_string()).or_insert(0);
+        let id = format!("{}#{}", resource, version);
+        if !self.resources.contains(&id) {
+            self.resources.push(id.clone());
+        }
+        id
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph frame {\n    rankdir=LR;\n");
+        for id in &self.resources {
+            let (name, version) = id.rsplit_once('#').unwrap();
+            writeln!(
+                dot,
+                "    \"{}\" [shape=ellipse, label=\"{} v{}\"];",
+                id, name, version
+            )
+            .unwrap();
+        }
+        for (i, (name, reads, writes)) in self.passes.iter().enumerate() {
+            writeln!(
+                dot,
+                "    pass{} [shape=box, style=filled, fillcolor=lightblue, label=\"{}\"];",
+                i, name
+            )
+            .unwrap();
+            for read in reads {
+                writeln!(dot, "    \"{}\" -> pass{};", read, i).unwrap();
+            }
+            for write in writes {
+                writeln!(dot, "    pass{} -> \"{}\";", i, write).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Passes and resources in columns, each one column right of the rightmost of its inputs.
+    // Resources nothing wrote this frame start in column 0.
+    pub fn layout(&self) -> GraphLayout {
+        let mut layout = GraphLayout::default();
+        let mut nodes: HashMap<&str, usize> = HashMap::new();
+        for (name, reads, writes) in &self.passes {
+            let reads: Vec<_> = reads
+                .iter()
+                .map(|read| match nodes.get(read.as_str()) {
+                    Some(node) => *node,
+                    None => {
+                        let node = layout.add(resource_label(read), false, 0);
+                        nodes.insert(read, node);
+                        node
+                    }
+                })
+                .collect();
+            let column = reads
+                .iter()
+                .map(|read| layout.nodes[*read].column + 1)
+                .max()
+                .unwrap_or(0);
+            let pass = layout.add(name.clone(), true, column);
+            layout.edges.extend(reads.iter().map(|read| (*read, pass)));
+            for write in writes {
+                let node = layout.add(resource_label(write), false, column + 1);
+                nodes.insert(write, node);
+                layout.edges.push((pass, node));
+            }
+        }
+        layout
+    }
+}
+
+fn resource_label(id: &str) -> String {
+    let (name, version) = id.rsplit_once('#').unwrap();
+    format!("{} v{}", name, version)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphNode {
+    pub label: String,
+    // A pass, else a version of a resource
+    pub pass: bool,
+    pub column: usize,
+    // From the top, in the order the nodes of the column were added
+    pub row: usize,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GraphLayout {
+    pub nodes: Vec<GraphNode>,
+    // Indices into `nodes`, from the resource read to the pass or the pass to the resource written
+    pub edges: Vec<(usize, usize)>,
+    // Nodes of each column
+    pub rows: Vec<usize>,
+}
+
+impl GraphLayout {
+    fn add(&mut self, label: String, pass: bool, column: usize) -> usize {
+        if self.rows.len() <= column {
+            self.rows.resize(column + 1, 0);
+        }
+        self.nodes.push(GraphNode {
+            label,
+            pass,
+            column,
+            row: self.rows[column],
+        });
+        self.rows[column] += 1;
+        self.nodes.len() - 1
+    }
+}
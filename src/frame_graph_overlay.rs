@@ -0,0 +1,317 @@
+use crate::frame_graph::{FrameGraph, GraphLayout};
+use crate::stats;
+use std::borrow::Cow;
+use wgpu::{Buffer, CommandEncoder, Device, Queue, RenderPipeline, TextureFormat, TextureView};
+
+const MARGIN: f32 = 16.;
+const GAP: f32 = 8.;
+const MAX_NODE_HEIGHT: f32 = 24.;
+const PADDING: f32 = 3.;
+const EDGE_WIDTH: f32 = 1.;
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.08, 0.8];
+// The fill of the passes in the DOT export
+const PASS_COLOR: [f32; 4] = [0.68, 0.85, 0.9, 1.];
+const RESOURCE_COLOR: [f32; 4] = [0.35, 0.35, 0.4, 1.];
+const EDGE_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 0.5];
+const PASS_TEXT: [f32; 4] = [0., 0., 0., 1.];
+const RESOURCE_TEXT: [f32; 4] = [1., 1., 1., 1.];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+impl OverlayVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<OverlayVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// Draws the frame graph of the last frame over the presented image, with the passes and
+// resources as labeled boxes. The triangles are only rebuilt when the graph or size changes.
+pub struct FrameGraphOverlay {
+    pipeline: RenderPipeline,
+    // Grown to the next power of two in bytes when the triangles don't fit
+    vertex_buffer: Option<(Buffer, wgpu::BufferAddress)>,
+    vertex_count: u32,
+    // What the vertex buffer shows
+    shown: Option<(FrameGraph, u32, u32)>,
+    pub(crate) visible: bool,
+}
+
+impl FrameGraphOverlay {
+    pub fn new(device: &Device, surface_format: TextureFormat) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Frame Graph Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                "frame_graph_overlay.wgsl"
+            ))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Frame Graph Overlay Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[OverlayVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        Self {
+            pipeline,
+            vertex_buffer: None,
+            vertex_count: 0,
+            shown: None,
+            visible: false,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        graph: &FrameGraph,
+        width: u32,
+        height: u32,
+    ) {
+        if let Some((shown, w, h)) = &self.shown {
+            if shown == graph && (*w, *h) == (width, height) {
+                return;
+            }
+        }
+        let vertices = overlay_vertices(&graph.layout(), width as f32, height as f32);
+        let size = std::mem::size_of_val(vertices.as_slice()) as wgpu::BufferAddress;
+        let fits = matches!(self.vertex_buffer, Some((_, capacity)) if capacity >= size);
+        if !fits {
+            let capacity = size.next_power_of_two();
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Frame Graph Overlay Vertex Buffer"),
+                size: capacity,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            self.vertex_buffer = Some((buffer, capacity));
+        }
+        if let Some((buffer, _)) = &self.vertex_buffer {
+            stats::write_buffer(queue, buffer, 0, bytemuck::cast_slice(&vertices));
+        }
+        self.vertex_count = vertices.len() as u32;
+        self.shown = Some((graph.clone(), width, height));
+    }
+
+    pub fn render(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let buffer = match &self.vertex_buffer {
+            Some((buffer, _)) if self.visible && self.vertex_count > 0 => buffer,
+            _ => return,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Frame Graph Overlay Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+// Triangles in clip space, built in pixels from the top left
+struct Triangles {
+    vertices: Vec<OverlayVertex>,
+    size: [f32; 2],
+}
+
+impl Triangles {
+    fn quad(&mut self, corners: [[f32; 2]; 4], color: [f32; 4]) {
+        let [width, height] = self.size;
+        let clip = |[x, y]: [f32; 2]| [x / width * 2. - 1., 1. - y / height * 2.];
+        for i in [0, 1, 2, 0, 2, 3] {
+            self.vertices.push(OverlayVertex {
+                position: clip(corners[i]),
+                color,
+            });
+        }
+    }
+
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        let (right, bottom) = (x + width, y + height);
+        self.quad([[x, y], [right, y], [right, bottom], [x, bottom]], color);
+    }
+
+    fn line(&mut self, from: [f32; 2], to: [f32; 2], color: [f32; 4]) {
+        let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+        let length = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+        let (nx, ny) = (
+            -dy / length * EDGE_WIDTH / 2.,
+            dx / length * EDGE_WIDTH / 2.,
+        );
+        self.quad(
+            [
+                [from[0] + nx, from[1] + ny],
+                [to[0] + nx, to[1] + ny],
+                [to[0] - nx, to[1] - ny],
+                [from[0] - nx, from[1] - ny],
+            ],
+            color,
+        );
+    }
+
+    // As many characters as fit into `width`, one pixel per font pixel scaled by `scale`
+    fn text(&mut self, text: &str, x: f32, y: f32, width: f32, scale: f32, color: [f32; 4]) {
+        let advance = (GLYPH_WIDTH + 1) as f32 * scale;
+        let fitting = ((width + scale) / advance).max(0.) as usize;
+        for (i, c) in text.chars().take(fitting).enumerate() {
+            let left = x + i as f32 * advance;
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                let lit = |column: usize| bits & (0x10 >> column) != 0;
+                // Runs of set pixels in a row become one rectangle
+                let mut column = 0;
+                while column < GLYPH_WIDTH {
+                    if !lit(column) {
+                        column += 1;
+                        continue;
+                    }
+                    let start = column;
+                    while column < GLYPH_WIDTH && lit(column) {
+                        column += 1;
+                    }
+                    self.rect(
+                        left + start as f32 * scale,
+                        y + row as f32 * scale,
+                        (column - start) as f32 * scale,
+                        scale,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn overlay_vertices(layout: &GraphLayout, width: f32, height: f32) -> Vec<OverlayVertex> {
+    let mut triangles = Triangles {
+        vertices: vec![],
+        size: [width, height],
+    };
+    let columns = layout.rows.len();
+    let rows = layout.rows.iter().copied().max().unwrap_or(0);
+    if columns == 0 || width <= 2. * MARGIN || height <= 2. * MARGIN {
+        return triangles.vertices;
+    }
+    let (panel_width, panel_height) = (width - 2. * MARGIN, height - 2. * MARGIN);
+    triangles.rect(MARGIN, MARGIN, panel_width, panel_height, BACKGROUND);
+    let cell_width = panel_width / columns as f32;
+    let cell_height = (panel_height / rows as f32).min(MAX_NODE_HEIGHT + GAP);
+    let node_width = (cell_width - GAP).max(1.);
+    let node_height = (cell_height - GAP).max(1.);
+    let origin = |column: usize, row: usize| {
+        [
+            MARGIN + GAP / 2. + column as f32 * cell_width,
+            MARGIN + GAP / 2. + row as f32 * cell_height,
+        ]
+    };
+    for (from, to) in &layout.edges {
+        let (from, to) = (&layout.nodes[*from], &layout.nodes[*to]);
+        let [x0, y0] = origin(from.column, from.row);
+        let [x1, y1] = origin(to.column, to.row);
+        let start = [x0 + node_width, y0 + node_height / 2.];
+        triangles.line(start, [x1, y1 + node_height / 2.], EDGE_COLOR);
+    }
+    // Two screen pixels per font pixel where the boxes are tall enough
+    let scale = ((node_height - 2. * PADDING) / GLYPH_HEIGHT as f32)
+        .floor()
+        .clamp(1., 2.);
+    for node in &layout.nodes {
+        let [x, y] = origin(node.column, node.row);
+        let (fill, text) = if node.pass {
+            (PASS_COLOR, PASS_TEXT)
+        } else {
+            (RESOURCE_COLOR, RESOURCE_TEXT)
+        };
+        triangles.rect(x, y, node_width, node_height, fill);
+        let text_y = y + (node_height - GLYPH_HEIGHT as f32 * scale) / 2.;
+        let text_width = node_width - 2. * PADDING;
+        triangles.text(&node.label, x + PADDING, text_y, text_width, scale, text);
+    }
+    triangles.vertices
+}
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+// 5x7 pixel font of the characters in pass and resource names, rows from the top with the
+// leftmost pixel in bit 4. Lowercase is drawn as uppercase, unknown characters stay blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1e],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x0a, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        '[' => [0x0e, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0e],
+        ']' => [0x0e, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0e],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
@@ -0,0 +1,84 @@
+use instant::{Duration, Instant};
+
+// Frame caps cycled through at runtime, None renders as fast as the present mode allows
+const PRESETS: [Option<u32>; 4] = [None, Some(30), Some(60), Some(144)];
+// Present modes cycled through at runtime, in this order
+const PRESENT_MODES: [wgpu::PresentMode; 3] = [
+    wgpu::PresentMode::Fifo,
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+];
+
+// The next of PRESENT_MODES the surface supports, `current` when it supports no other
+pub fn next_present_mode(
+    current: wgpu::PresentMode,
+    supported: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    let start = PRESENT_MODES.iter().position(|mode| *mode == current);
+    (1..=PRESENT_MODES.len())
+        .map(|i| PRESENT_MODES[(start.unwrap_or(0) + i) % PRESENT_MODES.len()])
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(current)
+}
+
+// Paces redraws on the CPU, independent of the present mode
+pub struct FrameLimiter {
+    pub max_fps: Option<u32>,
+    // Replaces max_fps while the window is unfocused or the page hidden
+    pub background_fps: u32,
+    focused: bool,
+    hidden: bool,
+    last_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(max_fps: Option<u32>, background_fps: u32) -> Self {
+        Self {
+            max_fps,
+            background_fps,
+            focused: true,
+            hidden: false,
+            last_frame: Instant::now(),
+        }
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        if focused != self.focused {
+            self.focused = focused;
+            log::warn!("Background throttling: {}", self.is_throttled());
+        }
+    }
+
+    // Set from the page's visibilitychange events on the web
+    pub fn set_hidden(&mut self, hidden: bool) {
+        if hidden != self.hidden {
+            self.hidden = hidden;
+            log::warn!("Background throttling: {}", self.is_throttled());
+        }
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        !self.focused || self.hidden
+    }
+
+    // Earliest time the next frame may start, None if unlimited
+    pub fn next_frame(&self) -> Option<Instant> {
+        let fps = if self.is_throttled() {
+            Some(self.background_fps)
+        } else {
+            self.max_fps
+        };
+        let fps = fps.filter(|fps| *fps > 0)?;
+        Some(self.last_frame + Duration::from_secs_f64(1.0 / fps as f64))
+    }
+
+    pub fn frame_started(&mut self, now: Instant) {
+        self.last_frame = now;
+    }
+
+    pub fn cycle(&mut self) {
+        let current = PRESETS.iter().position(|p| *p == self.max_fps);
+        self.max_fps = PRESETS[current.map_or(0, |i| (i + 1) % PRESETS.len())];
+        log::warn!("Frame limit: {:?}", self.max_fps);
+    }
+}
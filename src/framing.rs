@@ -0,0 +1,75 @@
+// Moves the camera back along its view until the bounds of the scene or one entity fit, so
+// new models can be found without tuning their transforms first
+use crate::bounds::Aabb;
+use crate::camera::fit_distance;
+use crate::ecs::EntityId;
+use crate::State;
+use cgmath::{InnerSpace, Point3, Rad, Vector3, Zero};
+
+// Where a camera looking along `dir` sees all of `bounds`, the sphere around the box just fits
+pub fn framing_position(
+    bounds: &Aabb,
+    dir: Vector3<f32>,
+    fovy: Rad<f32>,
+    aspect: f32,
+) -> Point3<f32> {
+    let radius = (bounds.max - bounds.min).magnitude() / 2.;
+    bounds.center() - dir.normalize() * fit_distance(radius, fovy, aspect)
+}
+
+impl State {
+    // Everything with bounds, the debug lines only show what is there. False for an empty scene.
+    pub fn frame_scene(&mut self) -> bool {
+        let bounds = self
+            .render_groups
+            .iter()
+            .filter(|(id, _)| *id != self.debug_lines)
+            .filter_map(|(_, group)| group.bounding_sphere())
+            .map(|sphere| sphere.aabb())
+            .reduce(|a, b| a.union(&b));
+        match bounds {
+            Some(bounds) => {
+                self.frame_bounds(&bounds);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // False if the entity is gone, has no mesh or its group has no bounds
+    pub fn frame_entity(&mut self, entity: EntityId) -> bool {
+        let mesh = match self.world.meshes().get(entity) {
+            Some(mesh) => *mesh,
+            None => return false,
+        };
+        let bounds = self
+            .render_groups
+            .index_of(mesh.group)
+            .and_then(|index| self.render_groups.get(index))
+            .and_then(|group| group.instance_bounding_sphere(mesh.instance));
+        match bounds {
+            Some(bounds) => {
+                self.frame_bounds(&bounds.aabb());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn frame_bounds(&mut self, bounds: &Aabb) {
+        let projection = &self.camera.projection;
+        let dir = self.camera.view.get_dir();
+        let position = framing_position(bounds, dir, projection.fovy(), projection.aspect());
+        // The far side has to stay in front of the far plane
+        let radius = (bounds.max - bounds.min).magnitude() / 2.;
+        let reach = (bounds.center() - position).magnitude() + radius;
+        let (znear, zfar) = projection.depth_range();
+        if reach > zfar {
+            self.camera.projection.set_depth_range(znear, reach);
+        }
+        let mut view = self.camera.view.clone();
+        view.position = position;
+        view.velocity = Vector3::zero();
+        self.set_camera_view(view);
+    }
+}
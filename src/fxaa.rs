@@ -0,0 +1,101 @@
+use crate::texture;
+use std::borrow::Cow;
+use wgpu::{BindGroup, CommandEncoder, Device, RenderPipeline, SurfaceConfiguration, TextureView};
+
+// Anti-aliasing of the final image for when the scene isn't multisampled, as on the web.
+// The scene is rendered into `texture`, then drawn onto the next target of the chain with
+// the edges blended along their direction.
+pub struct FxaaPass {
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    pub texture: texture::Texture,
+}
+
+impl FxaaPass {
+    pub fn new(device: &Device, config: &SurfaceConfiguration) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("FXAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("fxaa.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("FXAA Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[config.format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let texture = create_scene_texture(device, config);
+        let bind_group = create_bind_group(device, &pipeline, &texture);
+        Self {
+            pipeline,
+            bind_group,
+            texture,
+        }
+    }
+
+    pub fn resize(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        self.texture = create_scene_texture(device, config);
+        self.bind_group = create_bind_group(device, &self.pipeline, &self.texture);
+    }
+
+    // Call after everything was drawn into `texture`
+    pub fn apply(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("FXAA Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_scene_texture(device: &Device, config: &SurfaceConfiguration) -> texture::Texture {
+    texture::Texture::create_render_target(
+        device,
+        config.width,
+        config.height,
+        config.format,
+        "fxaa_scene",
+    )
+}
+
+fn create_bind_group(
+    device: &Device,
+    pipeline: &RenderPipeline,
+    texture: &texture::Texture,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("FXAA Bind Group"),
+    })
+}
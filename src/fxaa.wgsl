@@ -0,0 +1,132 @@
+// Fast approximate anti-aliasing (Lottes 2009) of the scene, see fxaa.rs
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    var v_out: VertexOutput;
+    v_out.position = vec4<f32>(x, y, 0.0, 1.0);
+    v_out.tex_coords = vec2<f32>((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+    return v_out;
+}
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+// Step lengths in pixels of the search for the ends of an edge
+var<private> QUALITY: array<f32, 12> = array<f32, 12>(1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+// The scene is sampled in linear RGB, the square root is close to the gamma the thresholds
+// were tuned for
+fn luma(uv: vec2<f32>) -> f32 {
+    let color = textureSampleLevel(t_scene, s_scene, uv, 0.0).rgb;
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+@fragment
+fn fs_main(v_in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = v_in.tex_coords;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_scene));
+    let center = luma(uv);
+    let down = luma(uv + vec2<f32>(0.0, texel.y));
+    let up = luma(uv - vec2<f32>(0.0, texel.y));
+    let left = luma(uv - vec2<f32>(texel.x, 0.0));
+    let right = luma(uv + vec2<f32>(texel.x, 0.0));
+    let luma_min = min(center, min(min(down, up), min(left, right)));
+    let luma_max = max(center, max(max(down, up), max(left, right)));
+    let range = luma_max - luma_min;
+    // Edges with less contrast are left alone
+    if range < max(0.0312, luma_max * 0.125) {
+        return textureSampleLevel(t_scene, s_scene, uv, 0.0);
+    }
+
+    let down_left = luma(uv + vec2<f32>(-texel.x, texel.y));
+    let up_right = luma(uv + vec2<f32>(texel.x, -texel.y));
+    let up_left = luma(uv - texel);
+    let down_right = luma(uv + texel);
+    let down_up = down + up;
+    let left_right = left + right;
+    let left_corners = down_left + up_left;
+    let down_corners = down_left + down_right;
+    let right_corners = down_right + up_right;
+    let up_corners = up_right + up_left;
+
+    // Which way the edge runs
+    let edge_horizontal = abs(-2.0 * left + left_corners) + abs(-2.0 * center + down_up) * 2.0 + abs(-2.0 * right + right_corners);
+    let edge_vertical = abs(-2.0 * up + up_corners) + abs(-2.0 * center + left_right) * 2.0 + abs(-2.0 * down + down_corners);
+    let horizontal = edge_horizontal >= edge_vertical;
+
+    // Which side of the pixel the edge is on
+    let luma1 = select(left, down, horizontal);
+    let luma2 = select(right, up, horizontal);
+    let gradient1 = luma1 - center;
+    let gradient2 = luma2 - center;
+    let steepest1 = abs(gradient1) >= abs(gradient2);
+    let gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+    let local_average = 0.5 * (select(luma2, luma1, steepest1) + center);
+    // Towards the steeper neighbor, down is +y in texture coordinates
+    var step_length = select(texel.x, texel.y, horizontal);
+    if steepest1 != horizontal {
+        step_length = -step_length;
+    }
+
+    // Halfway onto the edge, then along it both ways until the luma changes
+    var edge_uv = uv;
+    if horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), horizontal);
+    var uv1 = edge_uv - offset;
+    var uv2 = edge_uv + offset;
+    var end1 = luma(uv1) - local_average;
+    var end2 = luma(uv2) - local_average;
+    var reached1 = abs(end1) >= gradient_scaled;
+    var reached2 = abs(end2) >= gradient_scaled;
+    for (var i = 0; i < 12; i++) {
+        if reached1 && reached2 {
+            break;
+        }
+        if !reached1 {
+            uv1 -= offset * QUALITY[i];
+            end1 = luma(uv1) - local_average;
+            reached1 = abs(end1) >= gradient_scaled;
+        }
+        if !reached2 {
+            uv2 += offset * QUALITY[i];
+            end2 = luma(uv2) - local_average;
+            reached2 = abs(end2) >= gradient_scaled;
+        }
+    }
+
+    // Pixels near the end the edge closes at are moved the most
+    let distance1 = select(uv.y - uv1.y, uv.x - uv1.x, horizontal);
+    let distance2 = select(uv2.y - uv.y, uv2.x - uv.x, horizontal);
+    let closer1 = distance1 < distance2;
+    let pixel_offset = -min(distance1, distance2) / (distance1 + distance2) + 0.5;
+    let center_smaller = center < local_average;
+    let good_variation = (select(end2, end1, closer1) < 0.0) != center_smaller;
+    var final_offset = select(0.0, pixel_offset, good_variation);
+
+    // Thin lines and single pixels, from the contrast of the whole neighborhood
+    let average = (2.0 * (down_up + left_right) + left_corners + right_corners) / 12.0;
+    let subpixel1 = clamp(abs(average - center) / range, 0.0, 1.0);
+    let subpixel2 = (-2.0 * subpixel1 + 3.0) * subpixel1 * subpixel1;
+    final_offset = max(final_offset, subpixel2 * subpixel2 * 0.75);
+
+    var final_uv = uv;
+    if horizontal {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    return textureSampleLevel(t_scene, s_scene, final_uv, 0.0);
+}
@@ -1,27 +1,54 @@
-use crate::{texture, Camera, ShadowPass};
-use crate::{world_space, LightRenderGroup, RenderGroup, MULTI_SAMPLE, PRIMITIVE};
-use std::cell::RefCell;
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::custom_shader::CustomShader;
+use crate::lightmap::Lightmap;
+use crate::normals::{recompute_normals, NormalMode};
+use crate::occlusion::{CulledInstances, OcclusionCulling};
+use crate::raycast::{closer, Ray, RayHit};
+use crate::spatial::{
+    closest_point_on_triangle, local_bounds, triangle_overlaps_aabb, Contact, MeshBvh,
+};
+use crate::stats::DrawStats;
+use crate::tint::Tint;
+use crate::world_space::InstanceTransform;
+use crate::{multi_sample, primitive, world_space, LightRenderGroup, RenderGroup};
+use crate::{probes, texture, Camera, ShadowPass};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+use std::collections::HashMap;
 use std::ops::Range;
-use std::rc::Rc;
+use std::time::Duration;
 use wgpu::util::DeviceExt;
-use wgpu::{Device, IndexFormat, Queue, RenderPipeline, SurfaceConfiguration};
+use wgpu::{
+    Device, IndexFormat, PipelineLayout, Queue, RenderPass, RenderPipeline, ShaderModule,
+    SurfaceConfiguration,
+};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
-    pub(crate) position: [f32; 3],
-    pub(crate) tex_coords: [f32; 2],
-    pub(crate) normal: [f32; 3],
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    // Lightmap and detail texture coordinates, a copy of tex_coords unless unwrapped separately
+    pub tex_coords2: [f32; 2],
 }
 
 impl Vertex {
-    fn new(position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> Self {
+    pub fn new(position: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> Self {
         Vertex {
             position,
             tex_coords,
             normal,
+            tex_coords2: tex_coords,
         }
     }
+
+    pub fn with_tex_coords2(self, tex_coords2: [f32; 2]) -> Self {
+        Vertex {
+            tex_coords2,
+            ..self
+        }
+    }
+
     pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -43,6 +70,11 @@ impl Vertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }
@@ -53,41 +85,157 @@ pub struct GeoObj {
     pub(crate) index_data: Vec<u32>,
     pub(crate) vertex_buffer: wgpu::Buffer,
     pub(crate) index_buffer: wgpu::Buffer,
+    pub(crate) index_format: IndexFormat,
+    pub(crate) bounds: BoundingSphere,
+    bvh: MeshBvh,
 }
 
 impl GeoObj {
-    pub const INDEX_FORMAT: IndexFormat = IndexFormat::Uint32;
+    // Indices are uploaded as u16 while every vertex fits, which halves the buffer,
+    // and as u32 for larger meshes. The CPU copy for picking stays u32.
     pub fn new(vertex_data: Vec<Vertex>, index_data: Vec<u32>, device: &Device) -> Self {
+        let vertex_count = vertex_data.len();
+        if let Some(i) = index_data.iter().find(|&&i| i as usize >= vertex_count) {
+            log::error!("Index {} is past the {} vertices", i, vertex_count);
+        }
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(&vertex_data),
             usage: wgpu::BufferUsages::VERTEX,
         });
+        let index_format = Self::index_format_for(vertex_count);
+        let short_indices: Vec<u16>;
+        let contents = match index_format {
+            IndexFormat::Uint16 => {
+                short_indices = index_data.iter().map(|&i| i as u16).collect();
+                bytemuck::cast_slice(&short_indices)
+            }
+            IndexFormat::Uint32 => bytemuck::cast_slice(&index_data),
+        };
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&index_data),
+            contents,
             usage: wgpu::BufferUsages::INDEX,
         });
+        let bounds = BoundingSphere::from_points(vertex_data.iter().map(|v| v.position));
+        let bvh = MeshBvh::new(&vertex_data, &index_data);
         Self {
             vertex_data,
             index_data,
             vertex_buffer,
             index_buffer,
+            index_format,
+            bounds,
+            bvh,
+        }
+    }
+
+    // 0xFFFF is left out, it restarts strips
+    pub fn index_format_for(vertex_count: usize) -> IndexFormat {
+        if vertex_count < u16::MAX as usize {
+            IndexFormat::Uint16
+        } else {
+            IndexFormat::Uint32
         }
     }
+
+    pub fn index_format(&self) -> IndexFormat {
+        self.index_format
+    }
+
+    // A copy of the CPU side data
+    pub fn mesh_data(&self) -> MeshData {
+        MeshData::new(self.vertex_data.clone(), self.index_data.clone())
+    }
+
+    // Uploads a copy with recomputed normals
+    pub fn with_normals(&self, mode: NormalMode, device: &Device) -> GeoObj {
+        let mut data = self.mesh_data();
+        recompute_normals(&mut data, mode);
+        data.build(device)
+    }
+
     pub(crate) fn get_index_range(&self) -> Range<u32> {
         0..self.index_data.len() as u32
     }
+
+    // World space hit against the mesh drawn with `transform`, the bounds reject most misses early
+    pub(crate) fn raycast(&self, ray: &Ray, transform: &InstanceTransform) -> Option<RayHit> {
+        let local = ray.to_local(transform.position, transform.rotation, transform.scale);
+        local.intersect_sphere(&self.bounds)?;
+        let hit = self
+            .bvh
+            .raycast(&local, &self.vertex_data, &self.index_data)?;
+        let position = Point3::from_vec(transform.transform_point(hit.position.to_vec()));
+        Some(RayHit {
+            distance: (position - ray.origin).magnitude(),
+            position,
+            normal: (transform.normal_matrix() * hit.normal).normalize(),
+            ..hit
+        })
+    }
+
+    // Closest point of the mesh drawn with `transform` within `max_distance` of a world point,
+    // with its distance
+    pub(crate) fn closest_point(
+        &self,
+        point: Point3<f32>,
+        max_distance: f32,
+        transform: &InstanceTransform,
+    ) -> Option<(Point3<f32>, f32)> {
+        let bounds =
+            self.bounds
+                .transformed(transform.position, transform.rotation, transform.scale);
+        if (bounds.center - point).magnitude() > bounds.radius + max_distance {
+            return None;
+        }
+        let reach = Aabb::around(point, Vector3::new(1., 1., 1.) * max_distance);
+        let mut closest = None;
+        self.bvh
+            .query(&local_bounds(&reach, transform), |triangle| {
+                let candidate =
+                    closest_point_on_triangle(point, self.world_triangle(triangle, transform));
+                let distance = (candidate - point).magnitude();
+                if distance <= max_distance && !matches!(closest, Some((_, d)) if d <= distance) {
+                    closest = Some((candidate, distance));
+                }
+            });
+        closest
+    }
+
+    pub(crate) fn overlaps_aabb(&self, aabb: &Aabb, transform: &InstanceTransform) -> bool {
+        let mut overlaps = false;
+        self.bvh.query(&local_bounds(aabb, transform), |triangle| {
+            overlaps =
+                overlaps || triangle_overlaps_aabb(self.world_triangle(triangle, transform), aabb);
+        });
+        overlaps
+    }
+
+    fn world_triangle(&self, triangle: usize, transform: &InstanceTransform) -> [Point3<f32>; 3] {
+        let face = &self.index_data[triangle * 3..triangle * 3 + 3];
+        [face[0], face[1], face[2]].map(|i| {
+            let position = Vector3::from(self.vertex_data[i as usize].position);
+            Point3::from_vec(transform.transform_point(position))
+        })
+    }
 }
 
 pub struct Entity {
     pub(crate) obj: GeoObj,
+    pub tint: Tint,
     pub texture_bind_group_layout: wgpu::BindGroupLayout,
     pub texture_bind_group: wgpu::BindGroup,
+    // Drawn without back-face culling, set before GeoRenderGroup::new. For planes seen from both
+    // sides, closed meshes only pay for faces that are never seen.
+    pub double_sided: bool,
+    // Baked ambient light, see lightmap.rs
+    pub(crate) lightmap: Option<Lightmap>,
 }
 
 impl Entity {
-    pub(crate) fn new(
+    // Panics if the bytes are not a png or jpeg
+    pub fn new(
         device: &Device,
         queue: &Queue,
         obj: GeoObj,
@@ -97,7 +245,59 @@ impl Entity {
         let diffuse_texture =
             texture::Texture::from_bytes(device, queue, diffuse_bytes, "Todo", mip_level_count)
                 .unwrap();
-        let texture_bind_group_layout = device.create_bind_group_layout(&texture::Texture::desc());
+        Self::with_texture(device, obj, &diffuse_texture)
+    }
+
+    pub fn with_texture(device: &Device, obj: GeoObj, diffuse_texture: &texture::Texture) -> Self {
+        Self::with_textures(device, obj, diffuse_texture, None, None)
+    }
+
+    // The emissive texture scales the emissive color of the tint, which is black until
+    // Tint::set_emissive
+    pub fn with_emissive(
+        device: &Device,
+        obj: GeoObj,
+        diffuse_texture: &texture::Texture,
+        emissive_texture: &texture::Texture,
+    ) -> Self {
+        Self::with_textures(device, obj, diffuse_texture, Some(emissive_texture), None)
+    }
+
+    // Lit by the scene's ambient light until State::bake_lightmaps fills the lightmap
+    pub fn with_lightmap(
+        device: &Device,
+        obj: GeoObj,
+        diffuse_texture: &texture::Texture,
+        lightmap: Lightmap,
+    ) -> Self {
+        Self::with_textures(device, obj, diffuse_texture, None, Some(lightmap))
+    }
+
+    fn with_textures(
+        device: &Device,
+        obj: GeoObj,
+        diffuse_texture: &texture::Texture,
+        emissive_texture: Option<&texture::Texture>,
+        lightmap: Option<Lightmap>,
+    ) -> Self {
+        let mut tint = Tint::new(device);
+        tint.set_emissive_texture(emissive_texture.is_some());
+        let black = texture::Texture::black(device, "No emission");
+        let emissive_texture = emissive_texture.unwrap_or(&black);
+        let lightmap_texture = lightmap
+            .as_ref()
+            .map_or(&black, |lightmap| &lightmap.texture);
+        let mut entries = texture::Texture::desc().entries.to_vec();
+        // The lightmap, read with the sampler of the diffuse texture
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            ..entries[3]
+        });
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &entries,
+                label: Some("geo_texture_bind_group_layout"),
+            });
         let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &texture_bind_group_layout,
             entries: &[
@@ -109,21 +309,47 @@ impl Entity {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: tint.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&emissive_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&lightmap_texture.view),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
         Self {
             obj,
+            tint,
             texture_bind_group_layout,
             texture_bind_group,
+            double_sided: false,
+            lightmap,
         }
     }
 }
 
 pub struct GeoRenderGroup {
     pub(crate) entity: Entity,
+    // Cheaper meshes for distant shadow casters, index i is lod i + 1
+    pub(crate) shadow_lods: Vec<GeoObj>,
+    // Coarser tessellations drawn in place of the entity mesh, index i is lod i + 1. They are
+    // built once, switching only binds other buffers.
+    lods: Vec<GeoObj>,
+    lod: usize,
+    // Camera distance per level of detail, 0 leaves the choice to set_lod
+    lod_distance: f32,
     instances: world_space::Instances,
+    // Only with occlusion culling enabled
+    culled: Option<CulledInstances>,
     pub(crate) render_pipeline: RenderPipeline,
+    render_pipeline_layout: PipelineLayout,
 }
 
 impl GeoRenderGroup {
@@ -135,10 +361,12 @@ impl GeoRenderGroup {
         config: &SurfaceConfiguration,
         light_render_group: &LightRenderGroup,
         shadow_pass: &ShadowPass,
-    ) -> Rc<RefCell<Self>> {
+    ) -> Self {
         let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
             label: Some("Geo Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("geo.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(
+                probes::shader_source(include_str!("geo.wgsl")).into(),
+            ),
         });
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -152,19 +380,108 @@ impl GeoRenderGroup {
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let render_pipeline = Self::create_pipeline(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            entity.double_sided,
+        );
+        Self {
+            entity,
+            shadow_lods: vec![],
+            lods: vec![],
+            lod: 0,
+            lod_distance: 0.,
+            instances,
+            culled: None,
+            render_pipeline,
+            render_pipeline_layout,
+        }
+    }
+
+    // Levels after the entity mesh, most detailed first. With a `distance` the lod goes one
+    // level coarser every `distance` between the camera and the closest instance.
+    pub fn set_lods(&mut self, lods: Vec<GeoObj>, distance: f32) {
+        self.lods = lods;
+        self.lod = self.lod.min(self.lods.len());
+        self.lod_distance = distance.max(0.);
+    }
+
+    // Draws the main camera's view of the instances indirectly, without the ones hidden behind
+    // the depth of the last frame
+    pub(crate) fn enable_occlusion_culling(
+        &mut self,
+        device: &Device,
+        occlusion: &OcclusionCulling,
+    ) {
+        self.instances.enable_storage(device);
+        self.culled = Some(CulledInstances::new(device, occlusion, &self.instances));
+    }
+
+    // Clamped to the coarsest level
+    pub fn set_lod(&mut self, lod: usize) {
+        self.lod = lod.min(self.lods.len());
+    }
+
+    // The mesh drawn and picked at the current lod
+    pub fn mesh(&self) -> &GeoObj {
+        match self.lod.checked_sub(1) {
+            Some(i) => &self.lods[i],
+            None => &self.entity.obj,
+        }
+    }
+
+    // Texels from lightmap::encode_texel, the entity is lit by them from the next update on
+    pub(crate) fn write_lightmap(&mut self, queue: &Queue, texels: &[[u8; 4]]) {
+        if let Some(lightmap) = &self.entity.lightmap {
+            lightmap.write(queue, texels);
+            self.entity.tint.set_lightmap_uv(lightmap.uv);
+        }
+    }
+
+    // Swap the shading of this entity for a user shader that reuses the engine layouts
+    pub fn set_custom_shader(
+        &mut self,
+        device: &Device,
+        config: &SurfaceConfiguration,
+        custom_shader: &CustomShader,
+    ) -> anyhow::Result<()> {
+        let source = custom_shader.compose()?;
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Custom Geo Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        self.render_pipeline = Self::create_pipeline(
+            device,
+            &self.render_pipeline_layout,
+            &shader,
+            config.format,
+            self.entity.double_sided,
+        );
+        Ok(())
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        shader: &ShaderModule,
+        format: wgpu::TextureFormat,
+        double_sided: bool,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Entity Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: "vs_main",
                 buffers: &[world_space::desc(), Vertex::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
+                module: shader,
                 entry_point: "fs_main",
                 targets: &[wgpu::ColorTargetState {
-                    format: config.format,
+                    format,
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent::REPLACE,
                         alpha: wgpu::BlendComponent::REPLACE,
@@ -172,22 +489,128 @@ impl GeoRenderGroup {
                     write_mask: wgpu::ColorWrites::ALL,
                 }],
             }),
-            primitive: PRIMITIVE,
-            depth_stencil: texture::Texture::create_depth_state(),
-            multisample: MULTI_SAMPLE,
+            primitive: primitive(double_sided),
+            depth_stencil: texture::Texture::create_opaque_depth_state(),
+            multisample: multi_sample(),
             // If the pipeline will be used with a multiview render pass, this
             // indicates how many array layers the attachments will have.
             multiview: None,
-        });
-        Rc::new(RefCell::new(Self {
-            entity,
-            instances,
-            render_pipeline,
-        }))
+        })
     }
 }
 
 impl RenderGroup for GeoRenderGroup {
+    fn update(&mut self, dt: Duration, queue: &Queue) {
+        self.entity.tint.update(dt, queue);
+    }
+
+    fn transform(&self, instance: usize) -> Option<InstanceTransform> {
+        self.instances.instance_transforms.get(instance).copied()
+    }
+
+    fn set_transform(&mut self, instance: usize, transform: InstanceTransform, queue: &Queue) {
+        if let Some(old) = self.instances.instance_transforms.get_mut(instance) {
+            *old = transform;
+            self.instances.update_buffer(queue);
+        }
+    }
+
+    fn set_hidden(&mut self, instance: usize, hidden: bool, queue: &Queue) {
+        self.instances.set_hidden(instance, hidden, queue);
+    }
+
+    fn tint_mut(&mut self) -> Option<&mut Tint> {
+        Some(&mut self.entity.tint)
+    }
+
+    fn select_lod(&mut self, eye: Point3<f32>) {
+        if self.lod_distance <= 0. {
+            return;
+        }
+        if let Some(closest) = self.instances.closest_distance(eye.to_vec()) {
+            self.set_lod((closest / self.lod_distance) as usize);
+        }
+    }
+
+    fn is_animating(&self) -> bool {
+        self.entity.tint.is_animating()
+    }
+
+    fn double_sided(&self) -> bool {
+        self.entity.double_sided
+    }
+
+    fn draw_stats(&self, _shadow_pass: bool) -> DrawStats {
+        DrawStats::new(1, self.instances.instance_transforms.len() as u32)
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.instances.bounding_sphere(&self.mesh().bounds)
+    }
+
+    fn instance_bounding_sphere(&self, instance: usize) -> Option<BoundingSphere> {
+        self.instances
+            .instance_bounding_sphere(instance, &self.mesh().bounds)
+    }
+
+    fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        self.instances
+            .instance_transforms
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.instances.is_hidden(*i))
+            .map(|(i, transform)| {
+                let hit = self.mesh().raycast(ray, transform)?;
+                Some(RayHit { instance: i, ..hit })
+            })
+            .fold(None, closer)
+    }
+
+    fn contacts(&self, sphere: &BoundingSphere) -> Vec<Contact> {
+        self.instances
+            .instance_transforms
+            .iter()
+            .enumerate()
+            .filter(|(instance, _)| !self.instances.is_hidden(*instance))
+            .filter_map(|(instance, transform)| {
+                let (point, distance) =
+                    self.mesh()
+                        .closest_point(sphere.center, sphere.radius, transform)?;
+                Some(Contact {
+                    instance,
+                    point,
+                    distance,
+                })
+            })
+            .collect()
+    }
+
+    fn overlapping(&self, aabb: &Aabb) -> Vec<usize> {
+        let transforms = self.instances.instance_transforms.iter();
+        transforms
+            .enumerate()
+            .filter(|(instance, _)| !self.instances.is_hidden(*instance))
+            .filter(|(_, transform)| self.mesh().overlaps_aabb(aabb, transform))
+            .map(|(instance, _)| instance)
+            .collect()
+    }
+
+    fn render_shadow<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, lod: usize) {
+        let obj = match lod.checked_sub(1) {
+            Some(i) => self.shadow_lods.get(i).or(self.shadow_lods.last()),
+            None => None,
+        }
+        .unwrap_or_else(|| self.mesh());
+        render_pass.set_vertex_buffer(0, self.instances.instance_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, obj.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(obj.index_buffer.slice(..), obj.index_format);
+        render_pass.draw_indexed(
+            obj.get_index_range(),
+            0,
+            self.instances.get_instance_range(),
+        );
+    }
+
     fn render<'a, 'b: 'a>(&'b self, render_pass: &mut wgpu::RenderPass<'a>, shadow_pass: bool) {
         if !shadow_pass {
             render_pass.set_pipeline(&self.render_pipeline);
@@ -195,16 +618,43 @@ impl RenderGroup for GeoRenderGroup {
         }
 
         render_pass.set_vertex_buffer(0, self.instances.instance_buffer.slice(..));
-        render_pass.set_vertex_buffer(1, self.entity.obj.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.entity.obj.index_buffer.slice(..), GeoObj::INDEX_FORMAT);
+        let obj = self.mesh();
+        render_pass.set_vertex_buffer(1, obj.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(obj.index_buffer.slice(..), obj.index_format);
         render_pass.draw_indexed(
-            self.entity.obj.get_index_range(),
+            obj.get_index_range(),
             0,
             self.instances.get_instance_range(),
         );
     }
+
+    fn cull<'a, 'b: 'a>(&'b self, queue: &Queue, pass: &mut wgpu::ComputePass<'a>) {
+        if let Some(culled) = &self.culled {
+            let obj = self.mesh();
+            culled.cull(queue, pass, &obj.bounds, obj.get_index_range());
+        }
+    }
+
+    fn render_culled<'a, 'b: 'a>(
+        &'b self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        shadow_pass: bool,
+    ) {
+        let culled = match &self.culled {
+            Some(culled) => culled,
+            None => return self.render(render_pass, shadow_pass),
+        };
+        if !shadow_pass {
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(2, &self.entity.texture_bind_group, &[]);
+        }
+        let obj = self.mesh();
+        render_pass.set_vertex_buffer(1, obj.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(obj.index_buffer.slice(..), obj.index_format);
+        culled.draw(render_pass);
+    }
 }
-pub fn create_square(height: f32, width: f32, device: &Device) -> GeoObj {
+pub fn square_mesh(height: f32, width: f32) -> MeshData {
     let half_width = width / 2.0;
     let half_height = height / 2.0;
     let vertex_data = vec![
@@ -214,45 +664,104 @@ pub fn create_square(height: f32, width: f32, device: &Device) -> GeoObj {
         Vertex::new([half_width, -half_height, 0.0], [1.0, 0.0], [0., 0., 1.]),
     ];
     let index_data = vec![0, 1, 2, 2, 3, 0];
-    GeoObj::new(vertex_data, index_data, device)
+    MeshData::new(vertex_data, index_data)
 }
 
-pub fn create_floor(height: f32, width: f32, device: &Device) -> GeoObj {
+pub fn create_square(height: f32, width: f32, device: &Device) -> GeoObj {
+    square_mesh(height, width).build(device)
+}
+
+pub fn floor_mesh(height: f32, width: f32) -> MeshData {
     let half_width = width / 2.0;
     let half_height = height / 2.0;
     let mul = 100.0;
+    // The texture repeats, the second set covers the floor once for its lightmap
     let vertex_data = vec![
-        Vertex::new([half_width, half_height, 0.0], [mul, mul], [0., 0., 1.]),
-        Vertex::new([-half_width, half_height, 0.0], [0.0, mul], [0., 0., 1.]),
-        Vertex::new([-half_width, -half_height, 0.0], [0.0, 0.0], [0., 0., 1.]),
-        Vertex::new([half_width, -half_height, 0.0], [mul, 0.0], [0., 0., 1.]),
+        Vertex::new([half_width, half_height, 0.0], [mul, mul], [0., 0., 1.])
+            .with_tex_coords2([1., 1.]),
+        Vertex::new([-half_width, half_height, 0.0], [0.0, mul], [0., 0., 1.])
+            .with_tex_coords2([0., 1.]),
+        Vertex::new([-half_width, -half_height, 0.0], [0.0, 0.0], [0., 0., 1.])
+            .with_tex_coords2([0., 0.]),
+        Vertex::new([half_width, -half_height, 0.0], [mul, 0.0], [0., 0., 1.])
+            .with_tex_coords2([1., 0.]),
     ];
     let index_data = vec![0, 1, 2, 2, 3, 0];
-    GeoObj::new(vertex_data, index_data, device)
+    MeshData::new(vertex_data, index_data)
+}
+
+pub fn create_floor(height: f32, width: f32, device: &Device) -> GeoObj {
+    floor_mesh(height, width).build(device)
+}
+
+// Normal, right and up axis of each face, right x up = normal so the quads wind outward
+pub(crate) const CUBE_FACES: [[[f32; 3]; 3]; 6] = [
+    [[0., 0., 1.], [1., 0., 0.], [0., 1., 0.]],
+    [[0., 0., -1.], [-1., 0., 0.], [0., 1., 0.]],
+    [[1., 0., 0.], [0., 0., -1.], [0., 1., 0.]],
+    [[-1., 0., 0.], [0., 0., 1.], [0., 1., 0.]],
+    [[0., 1., 0.], [1., 0., 0.], [0., 0., -1.]],
+    [[0., -1., 0.], [1., 0., 0.], [0., 0., 1.]],
+];
+
+// 24 vertices, every face has its own normal and the whole texture
+pub fn cube_mesh(size: f32) -> MeshData {
+    rounded_cube_mesh(size, 0., 1)
 }
 
 pub fn create_cube(size: f32, device: &Device) -> GeoObj {
+    cube_mesh(size).build(device)
+}
+
+// Edges and corners rounded with `radius`, each bevel split into `segments` quads per face
+pub fn rounded_cube_mesh(size: f32, radius: f32, segments: usize) -> MeshData {
     let half = size / 2.;
-    let vertex_data = vec![
-        Vertex::new([-half, -half, half], [0., 0.], [0., 0., 1.]),
-        Vertex::new([half, -half, half], [0., 0.], [0., 0., 1.]),
-        Vertex::new([half, half, half], [0., 0.], [0., 0., 1.]),
-        Vertex::new([-half, half, half], [0., 0.], [0., 0., 1.]),
-        Vertex::new([-half, -half, -half], [0., 0.], [0., 0., 1.]),
-        Vertex::new([half, -half, -half], [0., 0.], [0., 0., 1.]),
-        Vertex::new([half, half, -half], [0., 0.], [0., 0., 1.]),
-        Vertex::new([-half, half, -half], [0., 0.], [0., 0., 1.]),
-    ];
-    let index_data = vec![
-        //Front
-        0, 1, 2, 2, 3, 0, // Back
-        4, 7, 6, 6, 5, 4, // Top
-        3, 2, 6, 6, 7, 3, // Bottom
-        0, 4, 5, 5, 1, 0, // Left
-        0, 3, 7, 7, 4, 0, // Right
-        1, 5, 6, 6, 2, 1,
-    ];
-    GeoObj::new(vertex_data, index_data, device)
+    let radius = radius.clamp(0., half);
+    let inner = half - radius;
+    let segments = segments.max(1);
+    // Even angles over the 45 degrees of each bevel that belong to this face
+    let band: Vec<f32> = (0..=segments)
+        .map(|i| inner + radius * (PI / 4. * i as f32 / segments as f32).tan())
+        .collect();
+    let mut steps: Vec<f32> = band
+        .iter()
+        .rev()
+        .map(|x| -x)
+        .chain(band.iter().copied())
+        .collect();
+    steps.dedup();
+    let n = steps.len();
+    let mut mesh = MeshData::default();
+    for [normal, right, up] in CUBE_FACES {
+        let (normal, right, up) = (
+            Vector3::from(normal),
+            Vector3::from(right),
+            Vector3::from(up),
+        );
+        let first = mesh.vertex_data.len() as u32;
+        for &y in &steps {
+            for &x in &steps {
+                let point = normal * half + right * x + up * y;
+                let core = point.map(|c| c.clamp(-inner, inner));
+                let vertex_normal = if radius > 0. {
+                    (point - core).normalize()
+                } else {
+                    normal
+                };
+                mesh.vertex_data.push(Vertex::new(
+                    (core + vertex_normal * radius).into(),
+                    [(x + half) / size, (y + half) / size],
+                    vertex_normal.into(),
+                ));
+            }
+        }
+        mesh.grid(first, n - 1, n - 1);
+    }
+    mesh
+}
+
+pub fn create_rounded_cube(size: f32, radius: f32, segments: usize, device: &Device) -> GeoObj {
+    rounded_cube_mesh(size, radius, segments).build(device)
 }
 
 struct SphereGenerator {
@@ -297,11 +806,7 @@ impl SphereGenerator {
         let position = [x, y, z];
         let normal = [x / r, y / r, z / r];
         let tex_coords = [phi / (2. * PI), 1.0 - theta / PI];
-        Vertex {
-            position,
-            tex_coords,
-            normal,
-        }
+        Vertex::new(position, tex_coords, normal)
     }
 
     fn get_index(&mut self, v: usize, u: usize) -> u32 {
@@ -331,17 +836,394 @@ impl SphereGenerator {
         self
     }
 
-    fn create_obj(self, device: &Device) -> GeoObj {
-        let Self {
-            vertex_data,
-            index_data,
-            ..
-        } = self;
-        GeoObj::new(vertex_data, index_data, device)
+    fn into_mesh(self) -> MeshData {
+        MeshData::new(self.vertex_data, self.index_data)
     }
 }
-pub fn create_sphere(radius: f32, u: usize, v: usize, device: &Device) -> GeoObj {
+pub fn sphere_mesh(radius: f32, u: usize, v: usize) -> MeshData {
     SphereGenerator::new(radius, u, v)
         .build_sphere()
-        .create_obj(device)
+        .into_mesh()
+}
+
+pub fn create_sphere(radius: f32, u: usize, v: usize, device: &Device) -> GeoObj {
+    sphere_mesh(radius, u, v).build(device)
+}
+
+// A point of a profile revolved around the y axis: distance from the axis, height,
+// the outward normal in the same plane and the v texture coordinate
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: [f32; 2],
+    v: f32,
+}
+
+// Plain vertex and index data, what the generators produce before anything touches the GPU
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub vertex_data: Vec<Vertex>,
+    pub index_data: Vec<u32>,
+}
+
+impl MeshData {
+    // Two triangles per cell of a row major grid of (columns + 1) x (rows + 1) vertices from
+    // `first`. Counterclockwise where going along a row cross going up the rows points.
+    fn grid(&mut self, first: u32, columns: usize, rows: usize) {
+        let stride = columns as u32 + 1;
+        for row in 0..rows as u32 {
+            for col in 0..columns as u32 {
+                let a = first + row * stride + col;
+                let d = a + stride;
+                self.index_data.extend([a, a + 1, d + 1, d + 1, d, a]);
+            }
+        }
+    }
+
+    // The profile runs upward with the normal on its outside, u goes once around
+    fn revolve(&mut self, profile: &[ProfilePoint], segments: usize) {
+        let first = self.vertex_data.len() as u32;
+        for point in profile {
+            for col in 0..=segments {
+                let u = col as f32 / segments as f32;
+                let (sin, cos) = (u * 2. * PI).sin_cos();
+                let [normal_radius, normal_y] = point.normal;
+                self.vertex_data.push(Vertex::new(
+                    [point.radius * cos, point.y, -point.radius * sin],
+                    [u, point.v],
+                    [normal_radius * cos, normal_y, -normal_radius * sin],
+                ));
+            }
+        }
+        self.grid(first, segments, profile.len() - 1);
+    }
+
+    // Flat circle at height `y`, facing up or down
+    fn disk(&mut self, radius: f32, y: f32, up: bool, segments: usize) {
+        let center = self.vertex_data.len() as u32;
+        let normal = [0., if up { 1. } else { -1. }, 0.];
+        self.vertex_data
+            .push(Vertex::new([0., y, 0.], [0.5, 0.5], normal));
+        for col in 0..=segments {
+            let (sin, cos) = (col as f32 / segments as f32 * 2. * PI).sin_cos();
+            self.vertex_data.push(Vertex::new(
+                [radius * cos, y, -radius * sin],
+                [0.5 + 0.5 * cos, 0.5 + 0.5 * sin],
+                normal,
+            ));
+        }
+        for col in 0..segments as u32 {
+            let (a, b) = (center + 1 + col, center + 2 + col);
+            self.index_data
+                .extend(if up { [center, a, b] } else { [center, b, a] });
+        }
+    }
+
+    pub fn new(vertex_data: Vec<Vertex>, index_data: Vec<u32>) -> Self {
+        Self {
+            vertex_data,
+            index_data,
+        }
+    }
+
+    pub fn build(self, device: &Device) -> GeoObj {
+        GeoObj::new(self.vertex_data, self.index_data, device)
+    }
+}
+
+// Centered on the origin along y, capped at both ends
+pub fn cylinder_mesh(radius: f32, height: f32, segments: usize) -> MeshData {
+    let segments = segments.max(3);
+    let half = height / 2.;
+    let mut mesh = MeshData::default();
+    let side = [-half, half].map(|y| ProfilePoint {
+        radius,
+        y,
+        normal: [1., 0.],
+        v: (y + half) / height,
+    });
+    mesh.revolve(&side, segments);
+    mesh.disk(radius, half, true, segments);
+    mesh.disk(radius, -half, false, segments);
+    mesh
+}
+
+pub fn create_cylinder(radius: f32, height: f32, segments: usize, device: &Device) -> GeoObj {
+    cylinder_mesh(radius, height, segments).build(device)
+}
+
+// Base centered below the origin, apex at height / 2
+pub fn cone_mesh(radius: f32, height: f32, segments: usize) -> MeshData {
+    let segments = segments.max(3);
+    let half = height / 2.;
+    let slope = Vector3::new(height, radius, 0.).normalize();
+    let mut mesh = MeshData::default();
+    let side = [(radius, -half, 0.), (0., half, 1.)].map(|(radius, y, v)| ProfilePoint {
+        radius,
+        y,
+        normal: [slope.x, slope.y],
+        v,
+    });
+    mesh.revolve(&side, segments);
+    mesh.disk(radius, -half, false, segments);
+    mesh
+}
+
+pub fn create_cone(radius: f32, height: f32, segments: usize, device: &Device) -> GeoObj {
+    cone_mesh(radius, height, segments).build(device)
+}
+
+// A cylinder of `height` with hemispheres on both ends, `rings` per hemisphere
+pub fn capsule_mesh(radius: f32, height: f32, segments: usize, rings: usize) -> MeshData {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let half = height / 2.;
+    let total = height + 2. * radius;
+    let hemisphere = |offset: f32, from: f32| {
+        (0..=rings).map(move |i| {
+            let (sin, cos) = (from + PI / 2. * i as f32 / rings as f32).sin_cos();
+            let y = offset + radius * sin;
+            ProfilePoint {
+                radius: radius * cos,
+                y,
+                normal: [cos, sin],
+                v: (y + half + radius) / total,
+            }
+        })
+    };
+    let profile: Vec<_> = hemisphere(-half, -PI / 2.)
+        .chain(hemisphere(half, 0.))
+        .collect();
+    let mut mesh = MeshData::default();
+    mesh.revolve(&profile, segments);
+    mesh
+}
+
+pub fn create_capsule(
+    radius: f32,
+    height: f32,
+    segments: usize,
+    rings: usize,
+    device: &Device,
+) -> GeoObj {
+    capsule_mesh(radius, height, segments, rings).build(device)
+}
+
+// Ring around the y axis, `sides` around the tube
+pub fn torus_mesh(major_radius: f32, minor_radius: f32, segments: usize, sides: usize) -> MeshData {
+    let (segments, sides) = (segments.max(3), sides.max(3));
+    let profile: Vec<_> = (0..=sides)
+        .map(|i| {
+            let v = i as f32 / sides as f32;
+            let (sin, cos) = (v * 2. * PI).sin_cos();
+            ProfilePoint {
+                radius: major_radius + minor_radius * cos,
+                y: minor_radius * sin,
+                normal: [cos, sin],
+                v,
+            }
+        })
+        .collect();
+    let mut mesh = MeshData::default();
+    mesh.revolve(&profile, segments);
+    mesh
+}
+
+pub fn create_torus(
+    major_radius: f32,
+    minor_radius: f32,
+    segments: usize,
+    sides: usize,
+    device: &Device,
+) -> GeoObj {
+    torus_mesh(major_radius, minor_radius, segments, sides).build(device)
+}
+
+// In the xz plane facing up, split into `subdivisions` quads per side
+pub fn plane_mesh(width: f32, depth: f32, subdivisions: usize) -> MeshData {
+    let subdivisions = subdivisions.max(1);
+    let mut vertex_data = Vec::with_capacity((subdivisions + 1) * (subdivisions + 1));
+    for row in 0..=subdivisions {
+        let v = row as f32 / subdivisions as f32;
+        for col in 0..=subdivisions {
+            let u = col as f32 / subdivisions as f32;
+            vertex_data.push(Vertex::new(
+                [(u - 0.5) * width, 0., (0.5 - v) * depth],
+                [u, v],
+                [0., 1., 0.],
+            ));
+        }
+    }
+    let mut mesh = MeshData {
+        vertex_data,
+        index_data: Vec::with_capacity(6 * subdivisions * subdivisions),
+    };
+    mesh.grid(0, subdivisions, subdivisions);
+    mesh
+}
+
+pub fn create_plane(width: f32, depth: f32, subdivisions: usize, device: &Device) -> GeoObj {
+    plane_mesh(width, depth, subdivisions).build(device)
+}
+
+// Subdivided icosahedron, evenly spread triangles unlike the uv sphere.
+// Triangles across the texture seam get their own vertices with u past 1.
+pub fn icosphere_mesh(radius: f32, subdivisions: usize) -> MeshData {
+    let t = (1. + 5f32.sqrt()) / 2.;
+    let mut positions: Vec<Vector3<f32>> = [
+        [-1., t, 0.],
+        [1., t, 0.],
+        [-1., -t, 0.],
+        [1., -t, 0.],
+        [0., -1., t],
+        [0., 1., t],
+        [0., -1., -t],
+        [0., 1., -t],
+        [t, 0., -1.],
+        [t, 0., 1.],
+        [-t, 0., -1.],
+        [-t, 0., 1.],
+    ]
+    .iter()
+    .map(|&p| Vector3::from(p).normalize())
+    .collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let p = (positions[a as usize] + positions[b as usize]).normalize();
+                positions.push(p);
+                positions.len() as u32 - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+    // Same mapping as the uv sphere
+    let mut vertex_data: Vec<Vertex> = positions
+        .iter()
+        .map(|p| {
+            let u = (-p.z).atan2(p.x).rem_euclid(2. * PI) / (2. * PI);
+            let v = 1. - p.y.clamp(-1., 1.).acos() / PI;
+            Vertex::new((*p * radius).into(), [u, v], (*p).into())
+        })
+        .collect();
+    let mut wrapped = HashMap::new();
+    let mut index_data = Vec::with_capacity(triangles.len() * 3);
+    for triangle in triangles {
+        let us = triangle.map(|i| vertex_data[i as usize].tex_coords[0]);
+        let crosses_seam = us.iter().any(|u| us.iter().any(|other| u - other > 0.5));
+        for i in triangle {
+            let u = vertex_data[i as usize].tex_coords[0];
+            if crosses_seam && u < 0.5 {
+                let copy = *wrapped.entry(i).or_insert_with(|| {
+                    let mut vertex = vertex_data[i as usize];
+                    vertex.tex_coords[0] += 1.;
+                    vertex.tex_coords2[0] += 1.;
+                    vertex_data.push(vertex);
+                    vertex_data.len() as u32 - 1
+                });
+                index_data.push(copy);
+            } else {
+                index_data.push(i);
+            }
+        }
+    }
+    MeshData::new(vertex_data, index_data)
+}
+
+pub fn create_icosphere(radius: f32, subdivisions: usize, device: &Device) -> GeoObj {
+    icosphere_mesh(radius, subdivisions).build(device)
+}
+
+// Samples f over u and v in [0, 1] on an nu x nv grid of cells, with uv as texture coordinates.
+// Normals come from central differences of f, counterclockwise faces are where du x dv points.
+pub fn parametric_mesh(f: impl Fn(f32, f32) -> [f32; 3], nu: usize, nv: usize) -> MeshData {
+    let normal = |u, v| finite_difference_normal(&f, u, v);
+    parametric_with_normals_mesh(&f, normal, nu, nv)
+}
+
+pub fn create_parametric(
+    f: impl Fn(f32, f32) -> [f32; 3],
+    nu: usize,
+    nv: usize,
+    device: &Device,
+) -> GeoObj {
+    parametric_mesh(f, nu, nv).build(device)
+}
+
+// For surfaces with a known normal, which is used as is
+pub fn parametric_with_normals_mesh(
+    f: impl Fn(f32, f32) -> [f32; 3],
+    normal: impl Fn(f32, f32) -> [f32; 3],
+    nu: usize,
+    nv: usize,
+) -> MeshData {
+    let (nu, nv) = (nu.max(1), nv.max(1));
+    let mut mesh = MeshData::default();
+    for row in 0..=nv {
+        let v = row as f32 / nv as f32;
+        for col in 0..=nu {
+            let u = col as f32 / nu as f32;
+            mesh.vertex_data
+                .push(Vertex::new(f(u, v), [u, v], normal(u, v)));
+        }
+    }
+    mesh.grid(0, nu, nv);
+    mesh
+}
+
+pub fn create_parametric_with_normals(
+    f: impl Fn(f32, f32) -> [f32; 3],
+    normal: impl Fn(f32, f32) -> [f32; 3],
+    nu: usize,
+    nv: usize,
+    device: &Device,
+) -> GeoObj {
+    parametric_with_normals_mesh(f, normal, nu, nv).build(device)
+}
+
+// Steps stay inside [0, 1]. Where the surface degenerates, like at the pole of a sphere,
+// the normal is taken a little further inside the domain.
+fn finite_difference_normal(f: &impl Fn(f32, f32) -> [f32; 3], u: f32, v: f32) -> [f32; 3] {
+    let h = 1e-3;
+    let derivative = |(u, v): (f32, f32)| {
+        let (u0, u1) = ((u - h).max(0.), (u + h).min(1.));
+        let (v0, v1) = ((v - h).max(0.), (v + h).min(1.));
+        let du = Vector3::from(f(u1, v)) - Vector3::from(f(u0, v));
+        let dv = Vector3::from(f(u, v1)) - Vector3::from(f(u, v0));
+        du.cross(dv)
+    };
+    let inside = |x: f32| x + (0.5 - x) * 1e-2;
+    [(u, v), (inside(u), inside(v))]
+        .into_iter()
+        .map(derivative)
+        .find(|n| n.magnitude2() > f32::EPSILON * f32::EPSILON)
+        .map_or([0., 1., 0.], |n| n.normalize().into())
 }
@@ -0,0 +1,128 @@
+// Surfaces that bend the scene behind them. The scene without the glass is drawn into a render
+// target following the main camera, the glass samples it where the refracted view ray comes out
+// and mirrors the environment by the Fresnel term on top.
+use crate::camera::Camera;
+use crate::custom_shader::CustomShader;
+use crate::geo_gen::{GeoObj, GeoRenderGroup};
+use crate::light::LightRenderGroup;
+use crate::render_groups::GroupId;
+use crate::render_target::RenderTarget;
+use crate::scene::Scene;
+use crate::world_space::Instances;
+use crate::State;
+use wgpu::{Device, SurfaceConfiguration};
+
+// The scene behind the glass is drawn at the window size divided by this
+const SCENE_COLOR_DIVISOR: u32 = 2;
+
+// GLASS_IOR and GLASS_THICKNESS are filled in by Glass::shader. The background is tinted by the
+// tint and instance color, it is opaque: what is behind shows through the render target only.
+const GLASS_FRAGMENT: &str = r#"
+@fragment
+fn fs_main(vertex: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let ior = GLASS_IOR;
+    let thickness = GLASS_THICKNESS;
+    let normal = normalize(select(-vertex.world_normal, vertex.world_normal, front_facing));
+    let view_dir = normalize(vertex.world_position - camera.view_pos.xyz);
+    // Where the bent ray leaves the glass, in the scene color of the main camera
+    let bent = refract(view_dir, normal, 1.0 / ior);
+    let clip = world_to_clip(vertex.world_position + bent * thickness);
+    let ndc = clip.xy / max(clip.w, 0.0001);
+    let uv = clamp(vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5), vec2<f32>(0.0), vec2<f32>(1.0));
+    let behind = textureSampleLevel(t_diffuse, s_diffuse, uv, 0.0).rgb;
+    let f0 = pow((ior - 1.0) / (ior + 1.0), 2.0);
+    let cos_theta = clamp(dot(-view_dir, normal), 0.0, 1.0);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+    let mirrored = sample_environment(reflect(view_dir, normal), vertex.probe);
+    let transmitted = behind * tint.tint.rgb * vertex.instance_color.rgb;
+    return vec4<f32>(mix(transmitted, mirrored, fresnel), 1.0);
+}
+"#;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Glass {
+    // Index of refraction, 1.5 for window glass and 1.33 for water
+    pub ior: f32,
+    // World units the bent ray travels before it is projected, more bends the background further
+    pub thickness: f32,
+}
+
+impl Default for Glass {
+    fn default() -> Self {
+        Self {
+            ior: 1.5,
+            thickness: 2.0,
+        }
+    }
+}
+
+impl Glass {
+    // Fragment shader of a glass group, colored by the tint of the group
+    pub fn shader(&self) -> CustomShader {
+        CustomShader::Fragment(
+            GLASS_FRAGMENT
+                .replace("GLASS_IOR", &format!("{:?}", self.ior.max(1.)))
+                .replace("GLASS_THICKNESS", &format!("{:?}", self.thickness)),
+        )
+    }
+}
+
+// Schlick's approximation of the share of light reflected at `cos_theta` between the view and
+// the normal, as the glass shader computes it
+pub fn fresnel(cos_theta: f32, ior: f32) -> f32 {
+    let f0 = ((ior - 1.) / (ior + 1.)).powi(2);
+    f0 + (1. - f0) * (1. - cos_theta.clamp(0., 1.)).powi(5)
+}
+
+// Created with the first glass group, glass groups hide in it
+pub(crate) fn scene_color_target(
+    device: &Device,
+    config: &SurfaceConfiguration,
+    light_render_group: &LightRenderGroup,
+    scene: &Scene,
+    camera: &Camera,
+) -> RenderTarget {
+    let width = (config.width / SCENE_COLOR_DIVISOR).max(1);
+    let height = (config.height / SCENE_COLOR_DIVISOR).max(1);
+    let projection = camera.projection.resized(width, height);
+    let camera = Camera::new(camera.view.clone(), projection, device);
+    RenderTarget::with_camera(device, config, light_render_group, scene, camera)
+}
+
+impl State {
+    // Draws `obj` at every instance as glass and spawns an entity per instance, undoable like
+    // add_mesh_group. Split screen cameras see the background of the main camera through it.
+    pub fn add_glass_group(
+        &mut self,
+        obj: GeoObj,
+        instances: Instances,
+        glass: &Glass,
+    ) -> anyhow::Result<GroupId> {
+        let target = self.scene_color.get_or_insert_with(|| {
+            scene_color_target(
+                &self.device,
+                &self.config,
+                &self.light_render_group,
+                &self.scene,
+                &self.camera,
+            )
+        });
+        let entity = target.create_entity(&self.device, obj);
+        let mut group = GeoRenderGroup::new(
+            &self.device,
+            &self.camera,
+            entity,
+            instances,
+            &self.config,
+            &self.light_render_group,
+            &self.shadow_pass,
+        );
+        group.set_custom_shader(&self.device, &self.config, &glass.shader())?;
+        let id = self.add_render_group(Box::new(group));
+        self.world.spawn_group(&self.render_groups, id);
+        if let Some(target) = &mut self.scene_color {
+            target.hide(id);
+        }
+        Ok(id)
+    }
+}
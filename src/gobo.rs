@@ -0,0 +1,92 @@
+// Images projected by the spot lights, also called cookies. Each light has a layer of one
+// array, looked up through the view projection of its shadow map and multiplied into the light
+// inside the cone. Layers of lights without a gobo stay white.
+use image::imageops::FilterType;
+use image::RgbaImage;
+use std::num::NonZeroU32;
+use wgpu::{Device, Queue, Texture, TextureView};
+
+// Every image is resized to this, the clamped bilinear lookup hides the difference
+const GOBO_SIZE: u32 = 512;
+const GOBO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+pub(crate) struct Gobos {
+    texture: Texture,
+    pub(crate) view: TextureView,
+    light_count: u32,
+}
+
+impl Gobos {
+    pub(crate) fn new(device: &Device, queue: &Queue, light_count: usize) -> Self {
+        let light_count = light_count as u32;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Gobos"),
+            size: wgpu::Extent3d {
+                width: GOBO_SIZE,
+                height: GOBO_SIZE,
+                depth_or_array_layers: light_count,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: GOBO_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Gobos"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let gobos = Self {
+            texture,
+            view,
+            light_count,
+        };
+        for light in 0..light_count as usize {
+            gobos.set(queue, light, None);
+        }
+        gobos
+    }
+
+    pub(crate) fn light_count(&self) -> usize {
+        self.light_count as usize
+    }
+
+    // None clears the layer back to white
+    pub(crate) fn set(&self, queue: &Queue, light: usize, image: Option<&RgbaImage>) {
+        let image = match image {
+            Some(image) if image.dimensions() == (GOBO_SIZE, GOBO_SIZE) => image.clone(),
+            Some(image) => {
+                image::imageops::resize(image, GOBO_SIZE, GOBO_SIZE, FilterType::Triangle)
+            }
+            None => RgbaImage::from_pixel(GOBO_SIZE, GOBO_SIZE, image::Rgba([255; 4])),
+        };
+        self.write(queue, light, &image);
+    }
+
+    fn write(&self, queue: &Queue, light: usize, image: &RgbaImage) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: light as u32,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            image,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * GOBO_SIZE),
+                rows_per_image: NonZeroU32::new(GOBO_SIZE),
+            },
+            wgpu::Extent3d {
+                width: GOBO_SIZE,
+                height: GOBO_SIZE,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use wgpu::{Buffer, CommandEncoder, Device, Queue};
+
+// Timed scopes per frame, each one takes a begin and an end timestamp
+const MAX_SCOPES: u32 = 16;
+// Readbacks in flight, frames are not timed while all of them wait for the GPU
+const FRAMES_IN_FLIGHT: usize = 3;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>;
+
+struct Readback {
+    buffer: Buffer,
+    labels: Vec<String>,
+    map: Option<MapFuture>,
+}
+
+// Measures passes with timestamp queries written between them. Results arrive a few frames
+// late without stalling, without Features::TIMESTAMP_QUERY every call is a no-op.
+pub struct GpuTimer {
+    query_set: Option<wgpu::QuerySet>,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    readbacks: Vec<Readback>,
+    // Readback recording this frame, None if timing is skipped
+    current: Option<usize>,
+    open_scope: bool,
+    timings: Vec<(String, f32)>,
+}
+
+impl GpuTimer {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let supported = device.features().contains(wgpu::Features::TIMESTAMP_QUERY);
+        let query_set = supported.then(|| {
+            device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("GPU Timer Queries"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_SCOPES * 2,
+            })
+        });
+        let readbacks = if supported {
+            (0..FRAMES_IN_FLIGHT)
+                .map(|_| Readback {
+                    buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("GPU Timer Readback"),
+                        size: (MAX_SCOPES * 2) as u64 * wgpu::QUERY_SIZE as u64,
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                        mapped_at_creation: false,
+                    }),
+                    labels: vec![],
+                    map: None,
+                })
+                .collect()
+        } else {
+            log::warn!("Timestamp queries unsupported, GPU timings are disabled");
+            vec![]
+        };
+        Self {
+            query_set,
+            period: queue.get_timestamp_period(),
+            readbacks,
+            current: None,
+            open_scope: false,
+            timings: vec![],
+        }
+    }
+
+    // Milliseconds of each scope of the latest finished frame, in recording order
+    pub fn timings(&self) -> &[(String, f32)] {
+        &self.timings
+    }
+
+    // Picks a free readback buffer, call before the first scope of a frame
+    pub fn begin_frame(&mut self) {
+        self.current = self
+            .readbacks
+            .iter()
+            .position(|readback| readback.map.is_none());
+        if let Some(i) = self.current {
+            self.readbacks[i].labels.clear();
+        }
+    }
+
+    // Scopes can't nest, the end of one and the begin of the next may be the same point
+    pub fn begin(&mut self, encoder: &mut CommandEncoder, label: &str) {
+        let (query_set, readback) = match (&self.query_set, self.current) {
+            (Some(query_set), Some(i)) => (query_set, &mut self.readbacks[i]),
+            _ => return,
+        };
+        if self.open_scope || readback.labels.len() as u32 == MAX_SCOPES {
+            return;
+        }
+        encoder.write_timestamp(query_set, readback.labels.len() as u32 * 2);
+        readback.labels.push(label.to_string());
+        self.open_scope = true;
+    }
+
+    pub fn end(&mut self, encoder: &mut CommandEncoder) {
+        let (query_set, readback) = match (&self.query_set, self.current) {
+            (Some(query_set), Some(i)) => (query_set, &self.readbacks[i]),
+            _ => return,
+        };
+        if !self.open_scope {
+            return;
+        }
+        encoder.write_timestamp(query_set, readback.labels.len() as u32 * 2 - 1);
+        self.open_scope = false;
+    }
+
+    // Copies this frame's timestamps, call after the last scope
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        let (query_set, readback) = match (&self.query_set, self.current) {
+            (Some(query_set), Some(i)) => (query_set, &self.readbacks[i]),
+            _ => return,
+        };
+        let count = readback.labels.len() as u32 * 2;
+        if count > 0 {
+            encoder.resolve_query_set(query_set, 0..count, &readback.buffer, 0);
+        }
+    }
+
+    // Starts reading back this frame and collects any earlier frame the GPU has finished,
+    // call after submitting the encoder
+    pub fn end_frame(&mut self, device: &Device) {
+        if let Some(readback) = self.current.take().map(|i| &mut self.readbacks[i]) {
+            if !readback.labels.is_empty() {
+                let count = readback.labels.len() as u64 * 2;
+                let slice = readback.buffer.slice(..count * wgpu::QUERY_SIZE as u64);
+                readback.map = Some(Box::pin(slice.map_async(wgpu::MapMode::Read)));
+            }
+        }
+        device.poll(wgpu::Maintain::Poll);
+        for readback in &mut self.readbacks {
+            let done = match &mut readback.map {
+                Some(map) => poll_now(map.as_mut()),
+                None => continue,
+            };
+            match done {
+                Some(Ok(())) => {
+                    let count = readback.labels.len() as u64 * 2;
+                    let slice = readback.buffer.slice(..count * wgpu::QUERY_SIZE as u64);
+                    let timestamps =
+                        bytemuck::cast_slice::<u8, u64>(&slice.get_mapped_range()).to_vec();
+                    readback.buffer.unmap();
+                    self.timings = readback
+                        .labels
+                        .iter()
+                        .zip(timestamps.chunks_exact(2))
+                        .map(|(label, ticks)| {
+                            let ticks = ticks[1].saturating_sub(ticks[0]);
+                            (label.clone(), ticks as f32 * self.period / 1e6)
+                        })
+                        .collect();
+                    readback.map = None;
+                }
+                Some(Err(e)) => {
+                    log::error!("GPU timer readback failed: {:?}", e);
+                    readback.map = None;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+// One poll without an executor, the map callback completes the future once the device is polled
+fn poll_now<F: Future + ?Sized>(future: Pin<&mut F>) -> Option<F::Output> {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    match future.poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
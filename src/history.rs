@@ -0,0 +1,339 @@
+use crate::ecs::MeshHandle;
+use crate::events::LightChanged;
+use crate::light::LightUniform;
+use crate::scene::SceneUniform;
+use crate::world_space::InstanceTransform;
+use crate::{GroupId, RenderGroup, State};
+
+// Oldest edits are forgotten past this, matches the undo depth of PaintableTexture
+const MAX_HISTORY: usize = 64;
+
+// One reversible scene edit. Edits keep the values from before and after the change
+// so undo and redo restore them exactly instead of replaying deltas.
+pub enum Edit {
+    Transform {
+        group: usize,
+        instance: usize,
+        before: InstanceTransform,
+        after: InstanceTransform,
+    },
+    // Lights 0 and 1 are animated, their position and direction are left alone
+    Light {
+        index: usize,
+        before: LightUniform,
+        after: LightUniform,
+    },
+    Material {
+        group: usize,
+        before: [f32; 3],
+        after: [f32; 3],
+    },
+    // The animated time of the uniform is left alone
+    Ambient {
+        before: SceneUniform,
+        after: SceneUniform,
+    },
+    // The group lives here while it is not in the scene
+    AddGroup {
+        index: usize,
+        group: Option<(GroupId, Box<dyn RenderGroup>)>,
+    },
+    RemoveGroup {
+        index: usize,
+        group: Option<(GroupId, Box<dyn RenderGroup>)>,
+    },
+    // Hides the instance of a despawned entity, undo shows it again with a new entity
+    Despawn {
+        group: usize,
+        instance: usize,
+    },
+    // A brush stroke on the canvas, undone by the canvas' own patches
+    Paint {
+        uv: [f32; 2],
+        radius: f32,
+        color: [u8; 4],
+    },
+}
+
+impl Edit {
+    fn name(&self) -> &'static str {
+        match self {
+            Edit::Transform { .. } => "transform",
+            Edit::Light { .. } => "light",
+            Edit::Material { .. } => "material",
+            Edit::Ambient { .. } => "ambient",
+            Edit::AddGroup { .. } => "add entity",
+            Edit::RemoveGroup { .. } => "remove entity",
+            Edit::Despawn { .. } => "despawn",
+            Edit::Paint { .. } => "paint",
+        }
+    }
+
+    fn apply(&mut self, state: &mut State) {
+        match self {
+            Edit::Transform {
+                group,
+                instance,
+                after,
+                ..
+            } => set_transform(state, *group, *instance, *after),
+            Edit::Light { index, after, .. } => set_light(state, *index, *after),
+            Edit::Material { group, after, .. } => set_material(state, *group, *after),
+            Edit::Ambient { after, .. } => set_ambient(state, after),
+            Edit::AddGroup { index, group } => insert_group(state, *index, group),
+            Edit::RemoveGroup { index, group } => remove_group(state, *index, group),
+            Edit::Despawn { group, instance } => despawn(state, *group, *instance),
+            Edit::Paint { uv, radius, color } => {
+                state.canvas.paint(&state.queue, *uv, *radius, *color);
+            }
+        }
+    }
+
+    fn revert(&mut self, state: &mut State) {
+        match self {
+            Edit::Transform {
+                group,
+                instance,
+                before,
+                ..
+            } => set_transform(state, *group, *instance, *before),
+            Edit::Light { index, before, .. } => set_light(state, *index, *before),
+            Edit::Material { group, before, .. } => set_material(state, *group, *before),
+            Edit::Ambient { before, .. } => set_ambient(state, before),
+            Edit::AddGroup { index, group } => remove_group(state, *index, group),
+            Edit::RemoveGroup { index, group } => insert_group(state, *index, group),
+            Edit::Despawn { group, instance } => respawn(state, *group, *instance),
+            Edit::Paint { .. } => {
+                state.canvas.undo(&state.queue);
+            }
+        }
+    }
+}
+
+// Linear undo and redo stacks, recording a new edit drops everything that was undone
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    // Records an edit that has already been applied
+    pub fn record(&mut self, edit: Edit) {
+        if self.undo.len() == MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.undo.push(edit);
+        self.redo.clear();
+    }
+
+    // The edit to revert, hand it back with `undone` once reverted
+    pub fn undo(&mut self) -> Option<Edit> {
+        self.undo.pop()
+    }
+
+    pub fn undone(&mut self, edit: Edit) {
+        self.redo.push(edit);
+    }
+
+    // The edit to apply again, hand it back with `redone` once applied
+    pub fn redo(&mut self) -> Option<Edit> {
+        self.redo.pop()
+    }
+
+    pub fn redone(&mut self, edit: Edit) {
+        self.undo.push(edit);
+    }
+}
+
+// Editor operations on the scene, each one can be undone
+impl State {
+    pub fn edit(&mut self, mut edit: Edit) {
+        edit.apply(self);
+        self.history.record(edit);
+    }
+
+    pub fn set_instance_transform(
+        &mut self,
+        group: usize,
+        instance: usize,
+        transform: InstanceTransform,
+    ) {
+        let before = self
+            .render_groups
+            .get(group)
+            .and_then(|render_group| render_group.transform(instance));
+        if let Some(before) = before {
+            self.edit(Edit::Transform {
+                group,
+                instance,
+                before,
+                after: transform,
+            });
+        }
+    }
+
+    pub fn set_light(&mut self, index: usize, uniform: LightUniform) {
+        let before = self.light_render_group.light_uniforms.get(index).copied();
+        if let Some(before) = before {
+            self.edit(Edit::Light {
+                index,
+                before,
+                after: uniform,
+            });
+        }
+    }
+
+    pub fn set_material_color(&mut self, group: usize, color: [f32; 3]) {
+        let before = self
+            .render_groups
+            .get_mut(group)
+            .and_then(|render_group| render_group.tint_mut())
+            .map(|tint| tint.color());
+        if let Some(before) = before {
+            self.edit(Edit::Material {
+                group,
+                before,
+                after: color,
+            });
+        }
+    }
+
+    pub fn set_ambient(&mut self, uniform: SceneUniform) {
+        self.edit(Edit::Ambient {
+            before: self.scene.uniform,
+            after: uniform,
+        });
+    }
+
+    // Inserted before the light trail, transparent groups are drawn last
+    pub fn add_render_group(&mut self, group: Box<dyn RenderGroup>) -> GroupId {
+        let id = self.render_groups.new_id();
+        let index = self
+            .render_groups
+            .index_of(self.light_trail)
+            .unwrap_or_else(|| self.render_groups.len());
+        self.edit(Edit::AddGroup {
+            index,
+            group: Some((id, group)),
+        });
+        id
+    }
+
+    pub fn remove_render_group(&mut self, index: usize) {
+        if index < self.render_groups.len() {
+            self.edit(Edit::RemoveGroup { index, group: None });
+        }
+    }
+
+    // Strokes that miss every pixel are not recorded
+    pub fn paint(&mut self, uv: [f32; 2], radius: f32, color: [u8; 4]) {
+        if self.canvas.paint(&self.queue, uv, radius, color) {
+            self.history.record(Edit::Paint { uv, radius, color });
+        }
+    }
+
+    // False if there was nothing to undo
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo() {
+            Some(mut edit) => {
+                log::warn!("Undo {}", edit.name());
+                edit.revert(self);
+                self.history.undone(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo() {
+            Some(mut edit) => {
+                log::warn!("Redo {}", edit.name());
+                edit.apply(self);
+                self.history.redone(edit);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn set_transform(state: &mut State, group: usize, instance: usize, transform: InstanceTransform) {
+    if let Some(render_group) = state.render_groups.get_mut(group) {
+        render_group.set_transform(instance, transform, &state.queue);
+        state.mirror_instance_transform(group, instance, transform);
+    }
+}
+
+fn set_light(state: &mut State, index: usize, uniform: LightUniform) {
+    if let Some(light) = state.light_render_group.light_uniforms.get_mut(index) {
+        *light = LightUniform {
+            position: light.position,
+            direction: light.direction,
+            ..uniform
+        };
+        state.publish(LightChanged { index });
+    }
+}
+
+fn set_material(state: &mut State, group: usize, color: [f32; 3]) {
+    if let Some(group) = state.render_groups.get_mut(group) {
+        if let Some(tint) = group.tint_mut() {
+            tint.set_color(color);
+        }
+    }
+}
+
+fn set_ambient(state: &mut State, uniform: &SceneUniform) {
+    let time = state.scene.uniform.time;
+    state.scene.uniform = SceneUniform { time, ..*uniform };
+}
+
+// The entity is already gone when the world systems record the edit, not on redo
+fn despawn(state: &mut State, group: usize, instance: usize) {
+    if let Some(render_group) = state.render_groups.get_mut(group) {
+        render_group.set_hidden(instance, true, &state.queue);
+    }
+    let entity = state
+        .render_groups
+        .id(group)
+        .and_then(|group| state.world.mesh_entity(group, instance));
+    if let Some(entity) = entity {
+        state.world.remove(entity);
+    }
+}
+
+// Scripts and parents of the old entity are not restored
+fn respawn(state: &mut State, group: usize, instance: usize) {
+    let id = state.render_groups.id(group);
+    if let (Some(id), Some(render_group)) = (id, state.render_groups.get_mut(group)) {
+        render_group.set_hidden(instance, false, &state.queue);
+        if let Some(transform) = render_group.transform(instance) {
+            let mesh = MeshHandle {
+                group: id,
+                instance,
+            };
+            state.world.spawn_mesh(mesh, transform);
+        }
+    }
+}
+
+// Moves the group between the edit and the scene
+fn insert_group(
+    state: &mut State,
+    index: usize,
+    group: &mut Option<(GroupId, Box<dyn RenderGroup>)>,
+) {
+    if let Some(group) = group.take() {
+        state.render_groups.insert(index, group);
+    }
+}
+
+fn remove_group(
+    state: &mut State,
+    index: usize,
+    group: &mut Option<(GroupId, Box<dyn RenderGroup>)>,
+) {
+    *group = state.render_groups.remove(index);
+}
@@ -0,0 +1,55 @@
+// Hi-Z pyramid of occlusion.rs, each level keeps the farthest depth of the texels it covers
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+// Level 0 reduces the resolved depth
+@group(0)
+@binding(0)
+var t_depth: texture_depth_2d;
+
+// Every further level the one before it
+@group(0)
+@binding(1)
+var t_previous: texture_2d<f32>;
+
+// First texel and texel count on each axis of the 2x2 block under `coords`. An odd row or
+// column left over at the edge goes to the last texel, so nothing is skipped.
+fn footprint(coords: vec2<i32>, previous: vec2<i32>) -> vec4<i32> {
+    let current = max(previous / 2, vec2<i32>(1));
+    var count = vec2<i32>(2);
+    if coords.x == current.x - 1 && previous.x % 2 == 1 {
+        count.x = 3;
+    }
+    if coords.y == current.y - 1 && previous.y % 2 == 1 {
+        count.y = 3;
+    }
+    return vec4<i32>(coords * 2, min(count, previous));
+}
+
+@fragment
+fn fs_first(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    let span = footprint(vec2<i32>(position.xy), textureDimensions(t_depth));
+    var depth = 0.0;
+    for (var y = 0; y < span.w; y++) {
+        for (var x = 0; x < span.z; x++) {
+            depth = max(depth, textureLoad(t_depth, span.xy + vec2<i32>(x, y), 0));
+        }
+    }
+    return depth;
+}
+
+@fragment
+fn fs_reduce(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    let span = footprint(vec2<i32>(position.xy), textureDimensions(t_previous));
+    var depth = 0.0;
+    for (var y = 0; y < span.w; y++) {
+        for (var x = 0; x < span.z; x++) {
+            depth = max(depth, textureLoad(t_previous, span.xy + vec2<i32>(x, y), 0).r);
+        }
+    }
+    return depth;
+}
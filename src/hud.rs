@@ -0,0 +1,72 @@
+use crate::camera::{Camera, ScreenPoint};
+use crate::ecs::EntityId;
+use crate::State;
+use cgmath::{EuclideanSpace, Point3, Vector3};
+
+// Keeps a HUD element (label, health bar, waypoint marker) glued to a point in the world.
+// The engine has no UI of its own, the resolved pixel position is for whatever draws the HUD.
+pub struct HudAnchor {
+    // Applied in world space before projecting, e.g. to float a label above a head
+    pub world_offset: Vector3<f32>,
+    // Applied in pixels after projecting
+    pub screen_offset: [f32; 2],
+    // Clamp to the viewport border instead of hiding when off screen
+    pub edge_margin: Option<f32>,
+    pub screen_point: Option<ScreenPoint>,
+}
+
+impl HudAnchor {
+    pub fn new(world_offset: Vector3<f32>) -> Self {
+        Self {
+            world_offset,
+            screen_offset: [0.; 2],
+            edge_margin: None,
+            screen_point: None,
+        }
+    }
+
+    pub fn with_edge_margin(mut self, margin: f32) -> Self {
+        self.edge_margin = Some(margin);
+        self
+    }
+
+    // Returns where the element should be drawn this frame, None if it should be hidden
+    pub(crate) fn update(&mut self, camera: &Camera, target: Point3<f32>) -> Option<[f32; 2]> {
+        let screen_point = camera.world_to_screen(target + self.world_offset);
+        self.screen_point = Some(screen_point);
+        let position = match self.edge_margin {
+            Some(margin) => screen_point.clamped_to_edges(margin),
+            None if screen_point.is_visible() => screen_point.position,
+            None => return None,
+        };
+        Some([
+            position[0] + self.screen_offset[0],
+            position[1] + self.screen_offset[1],
+        ])
+    }
+}
+
+impl State {
+    // Pixel position of a world point in the main camera's viewport
+    pub fn world_to_screen(&self, point: Point3<f32>) -> ScreenPoint {
+        self.camera.world_to_screen(point)
+    }
+
+    pub fn place_hud_anchor(
+        &self,
+        anchor: &mut HudAnchor,
+        target: Point3<f32>,
+    ) -> Option<[f32; 2]> {
+        anchor.update(&self.camera, target)
+    }
+
+    // Follows the world position of the entity, None once it is gone
+    pub fn place_hud_anchor_on(
+        &self,
+        anchor: &mut HudAnchor,
+        entity: EntityId,
+    ) -> Option<[f32; 2]> {
+        let transform = self.world.world_transform(entity)?;
+        anchor.update(&self.camera, Point3::from_vec(transform.position()))
+    }
+}
@@ -0,0 +1,309 @@
+use crate::bounds::BoundingSphere;
+use crate::camera::{Camera, CameraView, Projection};
+use crate::depth_prepass::DepthPrePass;
+use crate::light::LightRenderGroup;
+use crate::scene::Scene;
+use crate::shadow::ShadowPass;
+use crate::world_space::InstanceTransform;
+use crate::{
+    create_multisampled_view, multi_sample, sample_count, texture, world_space, RenderGroup,
+    PRIMITIVE,
+};
+use cgmath::{EuclideanSpace, Rad, Vector3};
+use std::f32::consts::TAU;
+use std::iter;
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, Buffer, Device, Queue, RenderPass, RenderPipeline, SurfaceConfiguration};
+
+// Views baked around the model, evenly spaced about its up axis
+pub const IMPOSTER_VIEWS: u32 = 8;
+// Pixels on each side of one view
+const VIEW_RESOLUTION: u32 = 256;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ImposterUniform {
+    // Bounds of the model in its own space, radius in w
+    center_radius: [f32; 4],
+    // View count in x, yzw unused
+    views: [f32; 4],
+}
+
+// Yaw of baked view `view` about the model's up axis, the view looks at the model from
+// (sin, 0, cos) of it. Mirrors the frame lookup in imposter.wgsl.
+pub fn imposter_view_angle(view: u32) -> Rad<f32> {
+    Rad(view as f32 * TAU / IMPOSTER_VIEWS as f32)
+}
+
+// The two baked views around a camera seen from `angle` about the model's up axis and how far
+// it is from the first towards the second
+pub fn imposter_views(angle: Rad<f32>) -> (u32, u32, f32) {
+    let frame = (angle.0 / TAU).rem_euclid(1.) * IMPOSTER_VIEWS as f32;
+    let first = frame.floor() as u32 % IMPOSTER_VIEWS;
+    (first, (first + 1) % IMPOSTER_VIEWS, frame.fract())
+}
+
+// A model rendered at startup from IMPOSTER_VIEWS directions around it into one atlas, then
+// drawn as a camera facing billboard per instance that blends the two closest views.
+// Lighting and tint are frozen at bake time, shadows come from the model itself only.
+pub(crate) struct Imposter {
+    // The views side by side, kept alive for the bind group
+    _atlas: texture::Texture,
+    _uniform_buffer: Buffer,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl Imposter {
+    // Renders `group` around `transform`, where it draws a model with bounds `local` in its own
+    // space. Other instances of the group should be outside the bounds.
+    #[allow(clippy::too_many_arguments)]
+    pub fn bake(
+        device: &Device,
+        queue: &Queue,
+        config: &SurfaceConfiguration,
+        camera: &Camera,
+        light_render_group: &LightRenderGroup,
+        shadow_pass: &ShadowPass,
+        scene: &Scene,
+        group: &dyn RenderGroup,
+        local: BoundingSphere,
+        transform: &InstanceTransform,
+    ) -> Self {
+        let width = VIEW_RESOLUTION * IMPOSTER_VIEWS;
+        let atlas = texture::Texture::create_render_target(
+            device,
+            width,
+            VIEW_RESOLUTION,
+            config.format,
+            "imposter_atlas",
+        );
+        let scale = transform.scale();
+        let center = transform.transform_point(local.center.to_vec());
+        let radius = (local.radius * scale.x.abs().max(scale.y.abs()).max(scale.z.abs())).max(0.01);
+        // Far enough out that the near plane stays clear of the model
+        let distance = radius * 2.;
+        let cameras: Vec<_> = (0..IMPOSTER_VIEWS)
+            .map(|view| {
+                let angle = imposter_view_angle(view);
+                let from = transform.rotation() * Vector3::new(angle.0.sin(), 0., angle.0.cos());
+                let yaw = Rad((-from.z).atan2(-from.x));
+                let mut camera = Camera::new(
+                    CameraView::new(
+                        cgmath::Point3::from_vec(center + from * distance),
+                        yaw,
+                        Rad(0.),
+                    ),
+                    Projection::orthographic(
+                        VIEW_RESOLUTION,
+                        VIEW_RESOLUTION,
+                        radius,
+                        distance - radius,
+                        distance + radius,
+                    ),
+                    device,
+                );
+                camera.update_camera(queue);
+                camera
+            })
+            .collect();
+        let pre_passes: Vec<_> = cameras
+            .iter()
+            .map(|camera| {
+                let pre_pass = DepthPrePass::new(device, light_render_group, scene);
+                pre_pass.update(queue, camera);
+                pre_pass
+            })
+            .collect();
+        let viewport = |view: u32| {
+            [
+                (view * VIEW_RESOLUTION) as f32,
+                0.,
+                VIEW_RESOLUTION as f32,
+                VIEW_RESOLUTION as f32,
+            ]
+        };
+
+        // The shadow maps are still empty at startup, the model shadows itself
+        let lights: &dyn RenderGroup = light_render_group;
+        let (shadow_buffers, _) = shadow_pass.encode(device, &[lights, group], light_render_group);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Imposter Bake Encoder"),
+        });
+        let depth = texture::Texture::create_depth_texture_sized(
+            device,
+            width,
+            VIEW_RESOLUTION,
+            "imposter_depth",
+        );
+        for (view, pre_pass) in pre_passes.iter().enumerate() {
+            pre_pass.render_pass(
+                &mut encoder,
+                &depth.view,
+                view == 0,
+                Some(viewport(view as u32)),
+                false,
+                &[group],
+            );
+        }
+        let msaa_view = create_multisampled_view(device, width, VIEW_RESOLUTION, config.format);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Imposter Bake Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: if sample_count() == 1 {
+                        &atlas.view
+                    } else {
+                        &msaa_view
+                    },
+                    resolve_target: Some(&atlas.view).filter(|_| sample_count() != 1),
+                    ops: wgpu::Operations {
+                        // Transparent around the model, the billboards cut it out
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            // Bound here instead of drawing the light group, its markers could end up in view
+            render_pass.set_bind_group(1, &light_render_group.light_bind_group, &[]);
+            render_pass.set_bind_group(3, &shadow_pass.shadow_map_bind_group, &[]);
+            for (view, camera) in cameras.iter().enumerate() {
+                let [x, y, width, height] = viewport(view as u32);
+                render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+                render_pass.set_bind_group(0, &camera.camera_bind_group, &[]);
+                group.render(&mut render_pass, false);
+            }
+        }
+        queue.submit(
+            shadow_buffers
+                .into_iter()
+                .chain(iter::once(encoder.finish())),
+        );
+
+        let uniform = ImposterUniform {
+            center_radius: [local.center.x, local.center.y, local.center.z, local.radius],
+            views: [IMPOSTER_VIEWS as f32, 0., 0., 0.],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Imposter Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Imposter Bind Group Layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Imposter Bind Group"),
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Imposter Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("imposter.wgsl").into()),
+        });
+        // The lights stay bound at 1 for the groups drawn after
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Imposter Pipeline Layout"),
+            bind_group_layouts: &[
+                &camera.camera_bind_group_layout,
+                &light_render_group.light_bind_group_layout,
+                &layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Imposter Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[world_space::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..PRIMITIVE
+            },
+            // Skipped by the depth pre-pass, which would lay down the full mesh
+            depth_stencil: texture::Texture::create_depth_state(),
+            multisample: multi_sample(),
+            multiview: None,
+        });
+        Self {
+            _atlas: atlas,
+            _uniform_buffer: uniform_buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    // One billboard per instance in `instance_buffer`, laid out as world_space::desc
+    pub fn render<'a, 'b: 'a>(
+        &'b self,
+        render_pass: &mut RenderPass<'a>,
+        instance_buffer: &'b Buffer,
+        instance_count: u32,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        render_pass.draw(0..6, 0..instance_count);
+    }
+}
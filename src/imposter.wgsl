@@ -0,0 +1,109 @@
+// Billboards of a model baked from several sides, see imposter.rs
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    proj_inv: mat4x4<f32>,
+    view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+fn world_to_clip(world_position: vec3<f32>) -> vec4<f32> {
+    return camera.relative_view_proj * vec4<f32>(world_position - camera.render_origin.xyz, 1.0);
+}
+
+struct Imposter {
+    // Bounds of the model in its own space, radius in w
+    center_radius: vec4<f32>,
+    views: vec4<f32>,
+}
+
+@group(2) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(2) @binding(1)
+var s_atlas: sampler;
+@group(2) @binding(2)
+var<uniform> imposter: Imposter;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+var<private> CORNERS: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Within one view
+    @location(0) uv: vec2<f32>,
+    // The two closest views and the blend towards the second
+    @location(1) @interpolate(flat) views: vec2<u32>,
+    @location(2) @interpolate(flat) blend: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3
+    );
+    let center = (model_matrix * vec4<f32>(imposter.center_radius.xyz, 1.0)).xyz;
+    let scale = max(
+        length(instance.model_matrix_0.xyz),
+        max(length(instance.model_matrix_1.xyz), length(instance.model_matrix_2.xyz))
+    );
+    let radius = imposter.center_radius.w * scale;
+
+    // Turns about the up axis only, the views were baked level with the model
+    let to_camera = camera.view_pos.xyz - center;
+    var from = vec3<f32>(to_camera.x, 0.0, to_camera.z);
+    if length(from) < 0.0001 {
+        from = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    from = normalize(from);
+    let right = vec3<f32>(from.z, 0.0, -from.x);
+    let corner = CORNERS[vertex_index];
+    let world_position = center + (right * corner.x + vec3<f32>(0.0, corner.y, 0.0)) * radius;
+
+    // Side the camera is on in the model's own space, mirrors imposter::imposter_views
+    let local_x = normalize(instance.model_matrix_0.xyz);
+    let local_z = normalize(instance.model_matrix_2.xyz);
+    let angle = atan2(dot(from, local_x), dot(from, local_z));
+    let count = imposter.views.x;
+    let frame = fract(angle / 6.2831853) * count;
+    let first = u32(floor(frame)) % u32(count);
+
+    var v_out: VertexOutput;
+    v_out.clip_position = world_to_clip(world_position);
+    v_out.uv = vec2<f32>(corner.x * 0.5 + 0.5, 0.5 - corner.y * 0.5);
+    v_out.views = vec2<u32>(first, (first + 1u) % u32(count));
+    v_out.blend = fract(frame);
+    return v_out;
+}
+
+@fragment
+fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    let count = imposter.views.x;
+    let first = textureSample(t_atlas, s_atlas, vec2<f32>((f32(f_in.views.x) + f_in.uv.x) / count, f_in.uv.y));
+    let second = textureSample(t_atlas, s_atlas, vec2<f32>((f32(f_in.views.y) + f_in.uv.x) / count, f_in.uv.y));
+    let color = mix(first, second, f_in.blend);
+    // Cut out like alpha mapped materials, so the billboards write depth
+    if color.a < 0.5 {
+        discard;
+    }
+    return vec4<f32>(color.rgb / color.a, 1.0);
+}
@@ -0,0 +1,10 @@
+// The steps of GPU passes and the lightmap bake, public so tests/ can check them without a
+// device or a scene. Unlike the prelude, names here may change with any release.
+
+pub use crate::lightmap::{
+    bake_texel, dilate, direct_light, encode_texel, hemisphere_samples, lightmap_texels,
+    LightmapTexel, LIGHTMAP_RANGE,
+};
+pub use crate::morph::blend_morph_targets;
+pub use crate::occlusion::{hiz_level, hiz_levels};
+pub use crate::skinning::{global_transforms, skin_vertices};
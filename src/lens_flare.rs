@@ -0,0 +1,144 @@
+use crate::camera::Camera;
+use crate::light::LightRenderGroup;
+use crate::texture;
+use std::borrow::Cow;
+use wgpu::{
+    BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPipeline, SurfaceConfiguration,
+    TextureView,
+};
+
+// Glare and ghost sprites of each light, see SPRITES in lens_flare.wgsl
+const SPRITES_PER_LIGHT: u32 = 6;
+
+// Sprites of the lights in view, added onto the scene. The vertex shader tests a grid of taps
+// of the resolved depth around each light, so flares fade as geometry covers the light
+// without reading anything back. Spot lights only flare inside their cone.
+pub struct LensFlarePass {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+}
+
+impl LensFlarePass {
+    pub fn new(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        depth: &texture::Texture,
+        camera: &Camera,
+        light_render_group: &LightRenderGroup,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+            label: Some("Lens Flare Bind Group Layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Lens Flare Pipeline Layout"),
+            bind_group_layouts: &[
+                &camera.camera_bind_group_layout,
+                &light_render_group.light_bind_group_layout,
+                &layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Lens Flare Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("lens_flare.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Lens Flare Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let bind_group = create_bind_group(device, &layout, depth);
+        Self {
+            pipeline,
+            layout,
+            bind_group,
+        }
+    }
+
+    // `depth` is the recreated resolved depth
+    pub fn resize(&mut self, device: &Device, depth: &texture::Texture) {
+        self.bind_group = create_bind_group(device, &self.layout, depth);
+    }
+
+    // Call after the depth resolve, adds the flares onto `target`
+    pub fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        camera: &Camera,
+        light_render_group: &LightRenderGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Lens Flare Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &camera.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &light_render_group.light_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        let light_count = light_render_group.light_uniforms.len() as u32;
+        render_pass.draw(0..6, 0..light_count * SPRITES_PER_LIGHT);
+    }
+}
+
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    depth: &texture::Texture,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&depth.view),
+        }],
+        label: Some("Lens Flare Bind Group"),
+    })
+}
@@ -0,0 +1,165 @@
+// Glare and ghost sprites of the lights in view, see lens_flare.rs
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    proj_inv: mat4x4<f32>,
+    view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+fn world_to_clip(world_position: vec3<f32>) -> vec4<f32> {
+    return camera.relative_view_proj * vec4<f32>(world_position - camera.render_origin.xyz, 1.0);
+}
+
+// Mirrors Light in shader.wgsl
+struct Light {
+    position: vec3<f32>,
+    direction: vec3<f32>,
+    color: vec4<f32>,
+    diffuse_strength: f32,
+    ambient_strength: f32,
+    specular_strength: f32,
+    point_clq: vec4<f32>,
+    cutoff_inner_outer_eps: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    shadow_resolution: f32,
+    cast_shadows: f32,
+    gobo: f32,
+    area_size: vec2<f32>,
+}
+
+struct Lights {
+    lights: array<Light, 2>
+}
+
+@group(1) @binding(0)
+var<uniform> lights: Lights;
+
+@group(2) @binding(0)
+var t_depth: texture_depth_2d;
+
+// Six sprites per light, the first is the glare around the light, the rest are
+// ghosts on the line from the light through the screen center.
+// x: position along the line, 1 is the light, y: height in NDC, zw unused
+var<private> SPRITES: array<vec4<f32>, 6> = array<vec4<f32>, 6>(
+    vec4<f32>(1.0, 0.5, 0.0, 0.0),
+    vec4<f32>(0.45, 0.08, 0.0, 0.0),
+    vec4<f32>(-0.2, 0.05, 0.0, 0.0),
+    vec4<f32>(-0.45, 0.16, 0.0, 0.0),
+    vec4<f32>(-0.8, 0.1, 0.0, 0.0),
+    vec4<f32>(-1.3, 0.3, 0.0, 0.0)
+);
+
+var<private> TINTS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(0.3, 0.5, 1.0),
+    vec3<f32>(1.0, 0.6, 0.3),
+    vec3<f32>(0.4, 1.0, 0.5),
+    vec3<f32>(0.8, 0.4, 1.0),
+    vec3<f32>(0.3, 0.6, 1.0)
+);
+
+var<private> CORNERS: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) @interpolate(flat) sprite: u32,
+};
+
+fn cutoff(light: Light, dir: vec3<f32>) -> f32 {
+    if light.cutoff_inner_outer_eps[3] == 0.0 {
+        return 1.0;
+    }
+    let theta = dot(-light.direction, dir);
+    return clamp((theta - light.cutoff_inner_outer_eps[1]) / light.cutoff_inner_outer_eps[2], 0.0, 1.0);
+}
+
+// Fraction of a 5x5 grid of taps 3 pixels apart around the light the scene depth doesn't
+// cover, 0 off screen
+fn visibility(light: Light) -> f32 {
+    // Tested a unit towards the camera, out of the marker of the light
+    let towards_camera = normalize(camera.view_pos.xyz - light.position);
+    let clip = world_to_clip(light.position + towards_camera);
+    if clip.w <= 0.0 {
+        return 0.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    // Lights beyond the far plane still shine over the sky
+    let depth = min(ndc.z, 1.0);
+    let size = vec2<i32>(textureDimensions(t_depth));
+    let center = (ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5)) * vec2<f32>(size);
+    var visible = 0.0;
+    for (var x = -2; x <= 2; x++) {
+        for (var y = -2; y <= 2; y++) {
+            let texel = vec2<i32>(center + vec2<f32>(f32(x), f32(y)) * 3.0);
+            if all(texel >= vec2<i32>(0)) && all(texel < size) && depth <= textureLoad(t_depth, texel, 0) {
+                visible += 1.0;
+            }
+        }
+    }
+    return visible / 25.0;
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32
+) -> VertexOutput {
+    let sprite = instance_index % 6u;
+    let light = lights.lights[instance_index / 6u];
+    var v_out: VertexOutput;
+    v_out.sprite = sprite;
+    v_out.uv = CORNERS[vertex_index];
+    // Degenerate behind the camera, off or facing away lights
+    v_out.position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    v_out.color = vec3<f32>(0.0);
+    let clip = world_to_clip(light.position);
+    if clip.w <= 0.0 || light.color.a == 0.0 {
+        return v_out;
+    }
+    let strength = cutoff(light, normalize(camera.view_pos.xyz - light.position)) * visibility(light);
+    if strength <= 0.0 {
+        return v_out;
+    }
+    let light_ndc = clip.xy / clip.w;
+    let aspect = vec2<f32>(textureDimensions(t_depth));
+    let square = vec2<f32>(aspect.y / aspect.x, 1.0);
+    let params = SPRITES[sprite];
+    let center = light_ndc * params.x;
+    v_out.position = vec4<f32>(center + v_out.uv * params.y * square, 0.0, 1.0);
+    // Ghosts grow stronger toward the screen center, where the lens reflects the most
+    let ghost_fade = select(max(1.0 - length(light_ndc) * 0.7, 0.0), 1.0, sprite == 0u);
+    v_out.color = light.color.rgb * TINTS[sprite] * strength * ghost_fade;
+    return v_out;
+}
+
+@fragment
+fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    let r = length(f_in.uv);
+    var shape: f32;
+    if f_in.sprite == 0u {
+        // Soft core and a horizontal streak
+        let core = pow(max(1.0 - r, 0.0), 4.0);
+        let streak = max(1.0 - abs(f_in.uv.y) * 12.0, 0.0) * max(1.0 - abs(f_in.uv.x), 0.0) * 0.5;
+        shape = core + streak;
+    } else if f_in.sprite == 3u {
+        // A ring
+        shape = max(1.0 - abs(r - 0.8) * 8.0, 0.0) * 0.15;
+    } else {
+        shape = (1.0 - smoothstep(0.7, 1.0, r)) * 0.12;
+    }
+    return vec4<f32>(f_in.color * shape, 1.0);
+}
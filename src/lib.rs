@@ -1,22 +1,169 @@
 use cgmath::prelude::*;
-use cgmath::{Quaternion, Vector3};
-use std::cell::RefCell;
+use cgmath::{Matrix4, Point3, Quaternion, Vector3};
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::iter;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
+mod atlas;
+#[cfg(feature = "audio")]
+mod audio;
+mod bounds;
 mod camera;
 use camera::Camera;
+mod capabilities;
+mod color_filter;
+mod compute;
+
+mod config;
+pub use atlas::{AtlasLayout, TextureAtlas};
+#[cfg(feature = "audio")]
+pub use audio::{distance_gain, listener_direction, Audio, SoundSettings};
+pub use bounds::{Aabb, BoundingSphere};
+pub use camera::{fit_distance, CameraController, CameraMode};
+pub use capabilities::{backend_order, RendererCapabilities};
+pub use color_filter::ColorFilter;
+pub use compute::workgroup_count;
+pub use config::{RedrawMode, RendererConfig};
+pub use cursor::{CursorCapture, GrabMode};
+pub use debug_lines::{frustum_corners, DebugView, LineList, LineVertex};
+pub use ecs::{CameraHandle, Components, EntityId, LightHandle, MaterialHandle, MeshHandle, World};
+pub use error::InitError;
+pub use events::{AssetLoaded, EntitySpawned, EventBus, LightChanged, Subscription, WindowResized};
+pub use frame_graph::{FrameGraph, GraphLayout, GraphNode};
+pub use frame_limiter::next_present_mode;
+pub use framing::framing_position;
+pub use geo_gen::{MeshData, Vertex};
+pub use glass::{fresnel, Glass};
+pub use imposter::{imposter_view_angle, imposter_views, IMPOSTER_VIEWS};
+pub use light::{cal_cutoff, cutoff_intensity};
+pub use light_animation::{flicker_noise, path_position, rotate_hue, LightAnimator, LightFrame};
+pub use lightmap::{BakeSettings, BakedLight, Lightmap};
+pub use mipmap::MipmapGenerator;
+pub use morph::{MorphAnimation, MorphTarget, MorphTargets};
+pub use noise::{noise, NoiseKind, NoiseSettings, MAX_OCTAVES};
+pub use normals::{recompute_normals, NormalMode};
+pub use palette::Palette;
+pub use particles::{spawn_particle, step_particle, Emitter, Particle};
+#[cfg(feature = "physics")]
+pub use physics::{BodyShape, Physics};
+pub use present::{encode_srgb, scene_format};
+pub use probes::{ReflectionProbes, PROBE_MIPS, PROBE_SIZE};
+pub use raycast::{raycast_mesh, Ray, RayHit};
+pub use reflect::{FieldInfo, FieldKind, UniformFields};
+pub use render_groups::{GroupId, RenderGroups};
+pub use resize::ResizeDebouncer;
+pub use resources::{fetch_model, is_model_file, split_asset_path, Assets, ModelSource};
+#[cfg(feature = "scripting")]
+pub use rhai_script::RhaiScript;
+#[cfg(all(feature = "scripting", not(target_arch = "wasm32")))]
+pub use rhai_script::ScriptFolder;
+pub use scene_commands::{SceneCommand, SceneCommands};
+pub use script::{SceneApi, Script, Scripts, Spin};
+pub use shadow::ShadowFilter;
+pub use simplify::{lod_chain, simplify};
+pub use skinning::{
+    ChannelKeys, Influence, NodeChannel, Skeleton, SkeletonAnimation, SkeletonNode, Skin,
+    SkinBinding,
+};
+pub use spatial::{closest_point_on_triangle, triangle_overlaps_aabb, Contact, MeshBvh};
+pub use split_screen::{split_rects, SplitView};
+pub use startup::Startup;
+pub use texture::{surface_format, FALLBACK_SURFACE_FORMATS};
+pub use texture_decode::{decode_all, DecodedImage, TextureDecoder};
+pub use touch::{TouchGesture, TouchInput, TouchPoint};
+pub use unwrap::{box_unwrap, planar_unwrap};
+pub use vegetation::{blade_fade, scatter_blades, GrassBlade};
+pub use viewer::scale_light;
+pub use world_space::{
+    grid, jitter_rotations, jitter_scales, random_colors, scatter, InstanceAttributes,
+    InstanceTransform,
+};
+
+mod cursor;
+mod custom_shader;
+mod debug_lines;
+mod deform;
+mod depth_prepass;
+mod depth_resolve;
+mod dynamic_uniform;
+mod ecs;
+mod error;
+mod events;
+mod frame_graph;
+mod frame_graph_overlay;
+mod frame_limiter;
+mod framing;
+mod fxaa;
 
 mod geo_gen;
+mod glass;
+mod gobo;
+mod gpu_timer;
+mod history;
+mod hud;
 use geo_gen::Entity;
+mod imposter;
+pub mod internals;
 
+mod lens_flare;
 mod light;
+mod light_animation;
+mod lightmap;
+mod ltc;
+mod minimap;
+mod mipmap;
 mod model;
+mod morph;
+mod noise;
+mod normals;
+mod occlusion;
+mod outline;
+mod paint;
+mod palette;
+mod particles;
+#[cfg(feature = "physics")]
+mod physics;
+pub mod prelude;
+mod present;
+mod probe_capture;
+mod probes;
+mod profiling;
+mod raycast;
+mod reflect;
+mod render_groups;
+mod render_target;
+mod resize;
 mod resources;
+#[cfg(feature = "scripting")]
+mod rhai_script;
+mod scene;
+mod scene_commands;
+mod script;
 mod shadow;
+mod simplify;
+mod skinning;
 mod skybox;
+mod spatial;
+mod split_screen;
+mod startup;
+mod stats;
 mod texture;
+mod texture_decode;
+mod tint;
+mod touch;
+mod trail;
+mod unwrap;
+mod variance_shadow;
+mod vegetation;
+mod viewer;
+mod volumetric;
+#[cfg(target_arch = "wasm32")]
+mod web_api;
+mod window_mode;
 mod world_space;
 
 use model::ModelRenderGroup;
@@ -27,34 +174,91 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
-use crate::camera::{CameraController, CameraView, Projection};
-use crate::geo_gen::{create_sphere, GeoRenderGroup};
+use crate::camera::{CameraController, CameraMode, CameraView, Projection};
+use crate::color_filter::ColorFilterPass;
+use crate::custom_shader::CustomShader;
+use crate::debug_lines::DebugLinesRenderGroup;
+use crate::depth_prepass::DepthPrePass;
+use crate::depth_resolve::DepthResolve;
+use crate::dynamic_uniform::{DynamicObject, DynamicRenderGroup};
+use crate::frame_graph::FrameGraph;
+use crate::frame_graph_overlay::FrameGraphOverlay;
+use crate::frame_limiter::FrameLimiter;
+use crate::fxaa::FxaaPass;
+use crate::geo_gen::{create_sphere, create_torus, GeoObj, GeoRenderGroup};
+use crate::gpu_timer::GpuTimer;
+use crate::history::History;
+use crate::lens_flare::LensFlarePass;
 use crate::light::{LightRenderGroup, LightUniform};
+use crate::minimap::Minimap;
+use crate::occlusion::OcclusionCulling;
+use crate::particles::ParticleRenderGroup;
+use crate::outline::OutlinePass;
+use crate::paint::PaintableTexture;
+use crate::present::PresentPass;
+use crate::probe_capture::ProbeCapture;
+use crate::profiling::profile_scope;
+use crate::render_groups::{AsAny, ThreadSafe};
+use crate::render_target::RenderTarget;
+use crate::scene::Scene;
+use crate::scene_commands::SceneCommand;
 use crate::shadow::ShadowPass;
+use crate::stats::{DrawStats, FrameStats};
 use crate::texture::Texture;
-use crate::world_space::{InstanceTransform, Instances};
+use crate::tint::Tint;
+use crate::trail::TrailRenderGroup;
+use crate::vegetation::VegetationRenderGroup;
+use crate::volumetric::VolumetricPass;
+use crate::window_mode::WindowMode;
+use crate::world_space::Instances;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-#[cfg(target_arch = "wasm32")]
-const SAMPLE_COUNT: u32 = 1;
-#[cfg(not(target_arch = "wasm32"))]
-const SAMPLE_COUNT: u32 = 4;
+// Set from RendererConfig::msaa before any pipeline or attachment is created
+static SAMPLE_COUNT: AtomicU32 = AtomicU32::new(1);
+
+fn sample_count() -> u32 {
+    SAMPLE_COUNT.load(Ordering::Relaxed)
+}
 
 const TEXTURE_SAMPLE_COUNT: u32 = 1;
 
-const MULTI_SAMPLE: wgpu::MultisampleState = wgpu::MultisampleState {
-    count: SAMPLE_COUNT,
-    mask: !0,
-    alpha_to_coverage_enabled: false,
-};
+fn multi_sample() -> wgpu::MultisampleState {
+    wgpu::MultisampleState {
+        count: sample_count(),
+        mask: !0,
+        alpha_to_coverage_enabled: false,
+    }
+}
+
+// Render opaque depth first and shade with depth_compare Equal to avoid overdraw
+const DEPTH_PRE_PASS: bool = true;
 
 const FLOOR_HEIGHT: f32 = -10.0;
+const STRESS_TEST_SEED: u64 = 7;
+// Half the side of the square the grass grows on, around the origin
+const GRASS_HALF_EXTENT: f32 = 250.0;
+// Where the glass ball stands on the floor, next to the sword
+const GLASS_BALL: [f32; 3] = [15.0, FLOOR_HEIGHT, -10.0];
+const GLASS_BALL_RADIUS: f32 = 5.0;
+// Texels of the floor lightmap and the side of the square around the origin it covers
+const FLOOR_LIGHTMAP_SIZE: u32 = 256;
+const FLOOR_LIGHTMAP_EXTENT: f32 = 320.0;
+// Where the spark fountain stands on the floor
+const PARTICLE_EMITTER: [f32; 3] = [0.0, FLOOR_HEIGHT, -20.0];
+// Segments around the demo sphere at its coarsest and finest lod
+const SPHERE_DETAIL_MIN: usize = 3;
+const SPHERE_DETAIL_MAX: usize = 17;
+// Simplified copies of the demo model, each coarser level starts MODEL_LOD_DISTANCE further out
+const MODEL_LOD_LEVELS: usize = 3;
+const MODEL_LOD_DISTANCE: f32 = 150.;
+// Lines the debug lines buffer starts with, it grows when more are drawn
+const DEBUG_LINES_CAPACITY: usize = 4096;
 const PRIMITIVE: wgpu::PrimitiveState = wgpu::PrimitiveState {
     topology: wgpu::PrimitiveTopology::TriangleList,
     strip_index_format: None,
     front_face: wgpu::FrontFace::Ccw,
-    cull_mode: None,
+    cull_mode: Some(wgpu::Face::Back),
     // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
     polygon_mode: wgpu::PolygonMode::Fill,
     // Requires Features::DEPTH_CLIP_CONTROL
@@ -63,8 +267,106 @@ const PRIMITIVE: wgpu::PrimitiveState = wgpu::PrimitiveState {
     conservative: false,
 };
 
-pub trait RenderGroup {
+// Double-sided surfaces draw their back faces too, the shaders flip the normal for them
+fn primitive(double_sided: bool) -> wgpu::PrimitiveState {
+    wgpu::PrimitiveState {
+        cull_mode: PRIMITIVE.cull_mode.filter(|_| !double_sided),
+        ..PRIMITIVE
+    }
+}
+
+// Groups are owned by RenderGroups and only change through these methods
+pub trait RenderGroup: AsAny + ThreadSafe {
     fn render<'a, 'b: 'a>(&'b self, render_pass: &mut wgpu::RenderPass<'a>, shadow_pass: bool);
+    // Per-frame animation of GPU side parameters, called before rendering
+    fn update(&mut self, _dt: Duration, _queue: &wgpu::Queue) {}
+    // World space bounds of everything the group draws, None if it can't be culled
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        None
+    }
+    // World space bounds of one instance, `instance` as in RayHit
+    fn instance_bounding_sphere(&self, _instance: usize) -> Option<BoundingSphere> {
+        None
+    }
+    // Shadow casters far from a light may draw a cheaper mesh, lod 0 is full detail
+    fn render_shadow<'a, 'b: 'a>(&'b self, render_pass: &mut wgpu::RenderPass<'a>, _lod: usize) {
+        self.render(render_pass, true);
+    }
+    // Choose the detail level drawn from the camera position, called after update
+    fn select_lod(&mut self, _eye: Point3<f32>) {}
+    // Exact triangle hit, only groups that keep their meshes on the CPU can be picked
+    fn raycast(&self, _ray: &Ray) -> Option<RayHit> {
+        None
+    }
+    // Closest point of each instance inside the sphere, from the same CPU meshes as raycast
+    fn contacts(&self, _sphere: &BoundingSphere) -> Vec<Contact> {
+        vec![]
+    }
+    // Instances with a triangle inside the box
+    fn overlapping(&self, _aabb: &Aabb) -> Vec<usize> {
+        vec![]
+    }
+    // Editable placement of one instance, `instance` as in RayHit
+    fn transform(&self, _instance: usize) -> Option<InstanceTransform> {
+        None
+    }
+    fn set_transform(
+        &mut self,
+        _instance: usize,
+        _transform: InstanceTransform,
+        _queue: &wgpu::Queue,
+    ) {
+    }
+    // Hidden instances keep their transform but are not drawn, picked or collided with
+    fn set_hidden(&mut self, _instance: usize, _hidden: bool, _queue: &wgpu::Queue) {}
+    // The material color of groups drawn with a tint uniform
+    fn tint_mut(&mut self) -> Option<&mut Tint> {
+        None
+    }
+    // Whether update keeps changing the group without new input, reactive redraws continue
+    // while any group animates
+    fn is_animating(&self) -> bool {
+        false
+    }
+    // Whether any back faces are drawn, the depth pre-pass has to rasterize them as well
+    fn double_sided(&self) -> bool {
+        false
+    }
+    // Whether fragments are discarded by an alpha map. The depth pre-pass skips such groups,
+    // they have to draw with a depth state that tests and writes instead of Equal.
+    fn cutout(&self) -> bool {
+        false
+    }
+    // Whether meshes of the group move in the vertex shader, see deform.rs. The shadow path of
+    // render and render_shadow leaves them out, the depth-only passes bind their deformed
+    // pipeline and call render_deformed instead.
+    fn deformed(&self) -> bool {
+        false
+    }
+    // `shadow_map` is false in the depth pre-pass
+    fn render_deformed<'a, 'b: 'a>(
+        &'b self,
+        _render_pass: &mut wgpu::RenderPass<'a>,
+        _shadow_map: bool,
+    ) {
+    }
+    // Draw calls and instances one render call issues, for FrameStats
+    fn draw_stats(&self, _shadow_pass: bool) -> DrawStats {
+        DrawStats::default()
+    }
+    // Records GPU simulation steps into the compute pass of the frame, before any drawing.
+    // Only called where compute shaders are supported, see compute.rs.
+    fn dispatch<'a, 'b: 'a>(&'b self, _pass: &mut wgpu::ComputePass<'a>) {}
+    // Tests the instances against the Hi-Z pyramid, only groups with CulledInstances take part
+    fn cull<'a, 'b: 'a>(&'b self, _queue: &wgpu::Queue, _pass: &mut wgpu::ComputePass<'a>) {}
+    // Draws only what the last cull kept, the main camera's passes call it after a cull
+    fn render_culled<'a, 'b: 'a>(
+        &'b self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        shadow_pass: bool,
+    ) {
+        self.render(render_pass, shadow_pass);
+    }
 }
 
 static UNIFORM_BIND_GROUP_LAYOUT_ENTRY: [wgpu::BindGroupLayoutEntry; 1] =
@@ -79,6 +381,13 @@ static UNIFORM_BIND_GROUP_LAYOUT_ENTRY: [wgpu::BindGroupLayoutEntry; 1] =
         count: None,
     }];
 
+// The second uv set of the floor runs from 0 to 1 over its 2800 units, see floor_mesh
+fn floor_lightmap_uv() -> [f32; 4] {
+    let scale = 2800.0 / FLOOR_LIGHTMAP_EXTENT;
+    let offset = 0.5 - 0.5 * scale;
+    [offset, offset, scale, scale]
+}
+
 fn uniform_desc(label_str: &str) -> wgpu::BindGroupLayoutDescriptor {
     wgpu::BindGroupLayoutDescriptor {
         entries: &UNIFORM_BIND_GROUP_LAYOUT_ENTRY,
@@ -87,81 +396,208 @@ fn uniform_desc(label_str: &str) -> wgpu::BindGroupLayoutDescriptor {
 }
 
 pub struct State {
-    surface: wgpu::Surface,
+    instance: wgpu::Instance,
+    // None while the app is suspended, every other GPU resource stays alive
+    surface: Option<wgpu::Surface>,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    // Of the scene, `surface_format` may be its non-sRGB variant
     config: wgpu::SurfaceConfiguration,
+    surface_format: wgpu::TextureFormat,
+    // What the adapter can present to the surface, F9 only cycles through these
+    present_modes: Vec<wgpu::PresentMode>,
     size: winit::dpi::PhysicalSize<u32>,
     // NEW!
     tex_view: wgpu::TextureView,
-    frame_count: usize,
+    stats: FrameStats,
     camera: Camera,
     camera_controller: CameraController,
-    mouse_pressed: bool,
+    // Mouse look while captured
+    cursor: CursorCapture,
+    touch_input: TouchInput,
     depth_texture: Texture,
-    render_groups: Vec<Rc<RefCell<dyn RenderGroup>>>,
-    light_render_group: Rc<RefCell<LightRenderGroup>>,
-    render_group_sphere: Rc<RefCell<GeoRenderGroup>>,
+    depth_resolve: DepthResolve,
+    // Only with occlusion_culling, tests the stress test cubes against the last frame's depth
+    occlusion: Option<OcclusionCulling>,
+    // Without compute the groups simulate on the CPU and the compute pass is skipped
+    capabilities: RendererCapabilities,
+    render_groups: RenderGroups,
+    // Drawn before the render groups, every pipeline needs its layout
+    light_render_group: LightRenderGroup,
+    render_group_sphere: GroupId,
+    render_group_dynamic: GroupId,
+    light_trail: GroupId,
+    debug_lines: GroupId,
+    debug_view: DebugView,
+    // Camera frustum drawn by the debug view, captured when it was turned on
+    frozen_frustum: Option<Matrix4<f32>>,
+    frame_limiter: FrameLimiter,
+    resize_debouncer: ResizeDebouncer,
+    // Middle click paints into the poster
+    canvas: PaintableTexture,
+    canvas_group: GroupId,
+    history: History,
+    scene_commands: SceneCommands,
+    scene_command_receiver: std::sync::mpsc::Receiver<SceneCommand>,
+    // Colors of the lights SceneCommand::SetLightEnabled turned off
+    switched_off_lights: HashMap<usize, [f32; 4]>,
+    // Entities addressing the objects in the render groups
+    world: World,
+    // Run every update, see script.rs
+    scripts: script::Scripts,
+    events: events::EventBus<State>,
+    modifiers: ModifiersState,
+    // Group and instance of the last middle click hit, target of the edit keys
+    picked: Option<(usize, usize)>,
+    window_mode: WindowMode,
+    redraw_mode: RedrawMode,
+    camera_collision: bool,
+    // Size of the scene relative to the built-in one, see State::view_model
+    scene_scale: f32,
+    // Set by input and edits, cleared once a frame is rendered
+    redraw_requested: bool,
+    gpu_timer: GpuTimer,
+    render_targets: Vec<RenderTarget>,
+    // The scene without the glass groups from the main camera, only once there is glass
+    scene_color: Option<RenderTarget>,
+    // Renders the reflection probes waiting for the scene around them, one a frame
+    probe_capture: ProbeCapture,
+    minimap: Minimap,
+    // Cameras drawn next to the main one, see split_screen.rs
+    split_views: Vec<SplitView>,
+    scene: Scene,
+    depth_pre_pass: Option<DepthPrePass>,
     total_duration: Duration,
     shadow_pass: ShadowPass,
+    // Only while a filter is selected, the scene goes through its texture
+    color_filter: Option<ColorFilterPass>,
+    // Only while enabled, the scene goes through its texture before the color filter
+    fxaa: Option<FxaaPass>,
+    // Only on non-sRGB surfaces, the finished frame goes through its texture
+    present: Option<PresentPass>,
+    // The passes the last frame encoded, in order
+    frame_graph: FrameGraph,
+    frame_graph_overlay: FrameGraphOverlay,
+    // Only while the air has a density
+    volumetric: Option<VolumetricPass>,
+    lens_flare: Option<LensFlarePass>,
+    outline: OutlinePass,
+    palette: Palette,
+    assets: Assets,
+    #[cfg(feature = "physics")]
+    physics: physics::Physics,
+    // None without an output device
+    #[cfg(feature = "audio")]
+    audio: Option<audio::Audio>,
 }
 
 impl State {
-    async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window, renderer_config: &RendererConfig) -> Result<Self, InitError> {
+        profile_scope!("State::new");
         let size = window.inner_size();
-        // The instance is a handle to our GPU
-        // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
-        let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        // The instance is a handle to our GPU, one per backend tried
+        let mut found = None;
+        let web = cfg!(target_arch = "wasm32");
+        for backends in capabilities::backend_order(renderer_config.backend, web) {
+            let instance = wgpu::Instance::new(backends);
+            let surface = unsafe { instance.create_surface(window) };
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: renderer_config.power_preference.into(),
+                    compatible_surface: Some(&surface),
+                    force_fallback_adapter: false,
+                })
+                .await;
+            if let Some(adapter) = adapter {
+                found = Some((instance, surface, adapter));
+                break;
+            }
+            log::warn!("No adapter for {:?}", backends);
+        }
+        let (instance, surface, adapter) =
+            found.ok_or(InitError::NoAdapter(renderer_config.backend))?;
 
+        let bindless = if renderer_config.bindless {
+            model::bindless_features()
+        } else {
+            wgpu::Features::empty()
+        };
+        // Optional, GpuTimer is a no-op without timestamps and models fall back to a bind group
+        // per material
+        let features = adapter.features() & (wgpu::Features::TIMESTAMP_QUERY | bindless);
+        let capabilities = RendererCapabilities::new(&adapter, features);
+        log::info!("{:?}", capabilities);
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        {
-                            let mut limit = wgpu::Limits::downlevel_webgl2_defaults();
-                            limit.max_texture_dimension_2d = 4096;
-                            limit
-                        }
-                    } else {
-                        wgpu::Limits::default()
-                    },
+                    features,
+                    limits: capabilities.limits(&adapter),
                 },
                 None, // Trace path
             )
             .await
-            .unwrap();
+            .map_err(InitError::Device)?;
 
+        // Before any pipeline or attachment is created
+        let requested = renderer_config.msaa;
+        let msaa = capabilities.sample_count(requested);
+        if msaa != requested {
+            log::warn!("{}x msaa unsupported, using {}x", requested, msaa);
+        }
+        SAMPLE_COUNT.store(msaa, Ordering::Relaxed);
+        let anisotropy = if capabilities.anisotropic_filtering {
+            renderer_config.anisotropy
+        } else {
+            1
+        };
+        texture::ANISOTROPY.store(anisotropy, Ordering::Relaxed);
+        probes::CUBE_ARRAYS.store(capabilities.cube_arrays, Ordering::Relaxed);
+        deform::VERTEX_DEFORM.store(capabilities.vertex_storage, Ordering::Relaxed);
+        let compute_supported = capabilities.compute;
+        let surface_format =
+            texture::surface_format(surface.get_preferred_format(&adapter), |format| {
+                adapter
+                    .get_texture_format_features(format)
+                    .allowed_usages
+                    .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            })
+            .ok_or(InitError::NoSurfaceFormat)?;
+        let present_modes = surface.get_supported_modes(&adapter);
+        // Every pipeline targets the scene format, the surface gets it through PresentPass
+        // when the two differ
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_preferred_format(&adapter).unwrap(),
+            format: present::scene_format(surface_format),
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: renderer_config.present_mode(),
         };
-        surface.configure(&device, &config);
+        log::info!(
+            "Surface format {:?}, scene format {:?}",
+            surface_format,
+            config.format
+        );
+        surface.configure(&device, &surface_config(&config, surface_format));
 
-        let camera = Camera::new(
+        let mut camera = Camera::new(
             CameraView::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0)),
-            Projection::new(config.width, config.height, cgmath::Deg(45.0), 1., 800.0),
+            Projection::new(
+                config.width,
+                config.height,
+                cgmath::Deg(renderer_config.fov),
+                1.,
+                800.0,
+            ),
             &device,
         );
+        camera.set_relative(renderer_config.camera_relative);
 
-        let light_render_group = {
+        let mut scene = Scene::new(&device);
+        let mut light_render_group = {
             LightRenderGroup::new(
                 &device,
+                &queue,
                 vec![
                     (
                         LightUniform {
@@ -175,27 +611,49 @@ impl State {
                         LightUniform {
                             cutoff_inner_outer_eps: light::cal_cutoff(4.0, 30.0),
                             ambient_strength: 0.01,
+                            // The narrow cone needs fewer texels
+                            shadow_resolution: 1024.,
                             ..Default::default()
                         },
                         geo_gen::create_sphere(10., 20, 20, &device),
                     ),
                 ],
-                &camera,
                 &config,
+                &scene,
             )
         };
 
-        let shadow_pass = shadow::ShadowPass::new(&device, &light_render_group.borrow());
+        let shadow_pass = shadow::ShadowPass::new(
+            &device,
+            &queue,
+            &light_render_group,
+            renderer_config.shadow_resolution,
+            renderer_config.shadow_filter,
+        );
+        light_render_group.add_markers(&device, &camera, &config, &shadow_pass);
+        let assets = Assets::from_config(renderer_config).await;
+        let canvas = assets
+            .load_binary("asuka.png")
+            .await
+            .and_then(|bytes| PaintableTexture::from_bytes(&device, &queue, &bytes, "canvas"))
+            .map_err(InitError::asset("asuka.png"))?;
+        // Shared by the sphere, the dynamic objects and the stress test cubes
+        let test_texture = assets
+            .load_binary("texture_test.png")
+            .await
+            .and_then(|bytes| Texture::from_bytes(&device, &queue, &bytes, "texture_test", 1))
+            .map_err(InitError::asset("texture_test.png"))?;
         let render_group = {
             let height = 26.0;
             let half_height = height / 2.0;
             let obj = geo_gen::create_square(height, 40.0, &device);
-            let entity_cube = Entity::new(&device, &queue, obj, include_bytes!("asuka.png"), 1);
+            let entity_cube = Entity::with_texture(&device, obj, &canvas.texture);
             let instances = Instances::new(
                 vec![
                     InstanceTransform {
                         position: Vector3::new(0.0, half_height + FLOOR_HEIGHT, -40.0),
                         rotation: Quaternion::one(),
+                        ..Default::default()
                     },
                     // InstanceTransform {
                     //     position: Vector3::new(10.0, half_height + FLOOR_HEIGHT, 0.0),
@@ -210,13 +668,23 @@ impl State {
                 entity_cube,
                 instances,
                 &config,
-                &light_render_group.borrow(),
+                &light_render_group,
                 &shadow_pass,
             )
         };
         let render_group_floor = {
             let obj = geo_gen::create_floor(2800.0, 2800.0, &device);
-            let entity_cube = Entity::new(&device, &queue, obj, include_bytes!("albedo.png"), 11);
+            let texture = assets
+                .load_binary("albedo.png")
+                .await
+                .and_then(|bytes| Texture::from_bytes(&device, &queue, &bytes, "albedo", 11))
+                .map_err(InitError::asset("albedo.png"))?;
+            let entity_cube = if renderer_config.lightmaps {
+                let lightmap = Lightmap::new(&device, FLOOR_LIGHTMAP_SIZE, floor_lightmap_uv());
+                Entity::with_lightmap(&device, obj, &texture, lightmap)
+            } else {
+                Entity::with_texture(&device, obj, &texture)
+            };
             let instances = Instances::new(
                 vec![InstanceTransform {
                     position: Vector3::new(00.0, FLOOR_HEIGHT, 0.0),
@@ -224,6 +692,7 @@ impl State {
                         cgmath::Vector3::unit_x(),
                         cgmath::Deg(-90.0),
                     ),
+                    ..Default::default()
                 }],
                 &device,
             );
@@ -233,110 +702,613 @@ impl State {
                 entity_cube,
                 instances,
                 &config,
-                &light_render_group.borrow(),
+                &light_render_group,
                 &shadow_pass,
             )
         };
         let render_group_sphere = {
-            let obj = geo_gen::create_sphere(10.0, 3, 2, &device);
-            let entity_cube =
-                Entity::new(&device, &queue, obj, include_bytes!("texture_test.png"), 1);
+            let obj = create_sphere(10.0, SPHERE_DETAIL_MAX, SPHERE_DETAIL_MAX - 1, &device);
+            let entity_cube = Entity::with_texture(&device, obj, &test_texture);
             let instances = Instances::new(
                 vec![InstanceTransform {
                     position: Vector3::new(60.0, 5.0, -15.0),
                     rotation: Quaternion::one(),
+                    ..Default::default()
                 }],
                 &device,
             );
-            GeoRenderGroup::new(
+            let mut group = GeoRenderGroup::new(
                 &device,
                 &camera,
                 entity_cube,
                 instances,
                 &config,
-                &light_render_group.borrow(),
+                &light_render_group,
+                &shadow_pass,
+            );
+            group
+                .shadow_lods
+                .push(geo_gen::create_sphere(10.0, 6, 5, &device));
+            let lods = (SPHERE_DETAIL_MIN..SPHERE_DETAIL_MAX)
+                .rev()
+                .map(|count| create_sphere(10.0, count, count - 1, &device))
+                .collect();
+            group.set_lods(lods, 0.);
+            group
+        };
+        // A ring floating above the sphere, shaded by a custom fragment shader
+        let hologram = {
+            let obj = create_torus(6.0, 1.0, 48, 16, &device);
+            let entity = Entity::with_texture(&device, obj, &test_texture);
+            let instances = Instances::new(
+                vec![InstanceTransform {
+                    position: Vector3::new(60.0, 25.0, -15.0),
+                    ..Default::default()
+                }],
+                &device,
+            );
+            let mut group = GeoRenderGroup::new(
+                &device,
+                &camera,
+                entity,
+                instances,
+                &config,
+                &light_render_group,
+                &shadow_pass,
+            );
+            // Keeps the default shading if the hologram doesn't compose
+            if let Err(e) = group.set_custom_shader(
+                &device,
+                &config,
+                &CustomShader::Fragment(custom_shader::HOLOGRAM.into()),
+            ) {
+                log::error!("Hologram shader: {:?}", e);
+            }
+            group
+        };
+
+        let mut security_camera = RenderTarget::new(
+            &device,
+            &config,
+            &light_render_group,
+            &scene,
+            512,
+            512,
+            CameraView::new((0.0, 40.0, 60.0), cgmath::Deg(-90.0), cgmath::Deg(-30.0)),
+        );
+        let render_group_monitor = {
+            let obj = geo_gen::create_square(12.0, 12.0, &device);
+            let mut entity = security_camera.create_entity(&device, obj);
+            // Walking around the monitor shows the feed from behind too
+            entity.double_sided = true;
+            let instances = Instances::new(
+                vec![InstanceTransform {
+                    position: Vector3::new(40.0, 6.0 + FLOOR_HEIGHT, -30.0),
+                    rotation: Quaternion::from_axis_angle(
+                        cgmath::Vector3::unit_y(),
+                        cgmath::Deg(-30.0),
+                    ),
+                    ..Default::default()
+                }],
+                &device,
+            );
+            GeoRenderGroup::new(
+                &device,
+                &camera,
+                entity,
+                instances,
+                &config,
+                &light_render_group,
                 &shadow_pass,
             )
         };
 
+        // A ring of small cubes sharing one mesh, bind group and uniform buffer
+        let render_group_dynamic = {
+            let cube = Arc::new(geo_gen::create_cube(3.0, &device));
+            let objects = (0..24)
+                .map(|i| {
+                    let angle = cgmath::Deg(i as f32 * 15.0);
+                    DynamicObject {
+                        obj: cube.clone(),
+                        transform: InstanceTransform {
+                            position: Vector3::new(
+                                -20.0 + 16.0 * angle.cos(),
+                                2.0 + FLOOR_HEIGHT,
+                                30.0 + 16.0 * angle.sin(),
+                            ),
+                            rotation: Quaternion::from_axis_angle(Vector3::unit_y(), -angle),
+                            ..Default::default()
+                        },
+                        color: [
+                            0.5 + 0.5 * angle.cos(),
+                            0.5 + 0.5 * angle.sin(),
+                            1.0 - 0.5 * angle.cos(),
+                            1.0,
+                        ],
+                    }
+                })
+                .collect();
+            DynamicRenderGroup::new(
+                &device,
+                &camera,
+                &test_texture,
+                objects,
+                &config,
+                &light_render_group,
+                &shadow_pass,
+            )
+        };
+
+        // One instanced draw of many cubes, for benchmarking
+        let stress_test_group = (renderer_config.stress_test_cubes > 0).then(|| {
+            let count = renderer_config.stress_test_cubes as usize;
+            let obj = geo_gen::create_cube(2.0, &device);
+            let entity = Entity::with_texture(&device, obj, &test_texture);
+            let mut transforms = world_space::scatter(
+                count,
+                Vector3::zero(),
+                1000.0,
+                1.0 + FLOOR_HEIGHT,
+                STRESS_TEST_SEED,
+            );
+            world_space::jitter_rotations(
+                &mut transforms,
+                cgmath::Deg(30.0).into(),
+                STRESS_TEST_SEED,
+            );
+            world_space::jitter_scales(&mut transforms, 0.5, 1.5, STRESS_TEST_SEED);
+            let instances = Instances::with_attributes(
+                transforms,
+                world_space::random_colors(count, STRESS_TEST_SEED),
+                &device,
+            );
+            GeoRenderGroup::new(
+                &device,
+                &camera,
+                entity,
+                instances,
+                &config,
+                &light_render_group,
+                &shadow_pass,
+            )
+        });
+
+        // Tens of thousands of wind blown blades in one instanced draw
+        let vegetation_group = (renderer_config.grass_blades > 0).then(|| {
+            let blades = vegetation::scatter_blades(
+                renderer_config.grass_blades as usize,
+                Vector3::zero(),
+                GRASS_HALF_EXTENT,
+                FLOOR_HEIGHT,
+                STRESS_TEST_SEED,
+            );
+            VegetationRenderGroup::new(&device, &camera, &config, &light_render_group, &blades)
+        });
+
+        // Simulated in a compute shader, or on the CPU where there are none
+        let particle_group = (renderer_config.particles > 0).then(|| {
+            let emitter = Emitter {
+                position: PARTICLE_EMITTER,
+                ..Default::default()
+            };
+            ParticleRenderGroup::new(
+                &device,
+                &camera,
+                &config,
+                emitter,
+                renderer_config.particles,
+                compute_supported,
+            )
+        });
+
         let model_render_group = {
             log::warn!("Load model");
-            let obj_model = resources::load_model("girl.obj", &device, &queue, 40.0)
-                .await
-                .unwrap();
+            let obj_model = resources::load_model(
+                &assets,
+                "girl.obj",
+                &device,
+                &queue,
+                40.0,
+                MODEL_LOD_LEVELS,
+                None,
+            )
+            .await
+            .map_err(InitError::asset("girl.obj"))?;
             let instances = Instances::new(
                 vec![InstanceTransform {
                     position: Vector3::new(-60.0, -11.0, 0.0),
                     rotation: Quaternion::one(),
+                    ..Default::default()
                 }],
                 &device,
             );
-            ModelRenderGroup::new(
+            let mut group = ModelRenderGroup::new(
                 obj_model,
                 instances,
                 &device,
                 &camera,
                 &config,
-                &light_render_group.borrow(),
+                &light_render_group,
                 &shadow_pass,
-            )
+            );
+            group.set_lod_distance(MODEL_LOD_DISTANCE);
+            if renderer_config.imposter_distance > 0. {
+                group.bake_imposter(
+                    &device,
+                    &queue,
+                    &config,
+                    &camera,
+                    &light_render_group,
+                    &shadow_pass,
+                    &scene,
+                    renderer_config.imposter_distance,
+                );
+            }
+            group
         };
         let sword_model_render_group = {
             log::warn!("Load model");
-            let obj_model = resources::load_model("arto.obj", &device, &queue, 1.0)
-                .await
-                .unwrap();
+            // Pm of its material mirrors the sky and the probes
+            let obj_model =
+                resources::load_model(&assets, "arto.obj", &device, &queue, 1.0, 0, None)
+                    .await
+                    .map_err(InitError::asset("arto.obj"))?;
             let instances = Instances::new(
                 vec![InstanceTransform {
                     position: Vector3::new(-0.0, -10.0, 0.0),
                     rotation: Quaternion::one(),
+                    ..Default::default()
                 }],
                 &device,
             );
-            ModelRenderGroup::new(
+            let mut group = ModelRenderGroup::new(
                 obj_model,
                 instances,
                 &device,
                 &camera,
                 &config,
-                &light_render_group.borrow(),
+                &light_render_group,
                 &shadow_pass,
-            )
+            );
+            if renderer_config.imposter_distance > 0. {
+                group.bake_imposter(
+                    &device,
+                    &queue,
+                    &config,
+                    &camera,
+                    &light_render_group,
+                    &shadow_pass,
+                    &scene,
+                    renderer_config.imposter_distance,
+                );
+            }
+            group
         };
-        let skybox = skybox::create(&device, &config, &queue, &camera).await;
-        let render_groups: Vec<Rc<RefCell<dyn RenderGroup>>> = vec![
-            skybox,
-            light_render_group.clone(),
-            render_group,
-            render_group_floor,
-            model_render_group,
-            sword_model_render_group,
-            render_group_sphere.clone(),
-        ];
+        let palette = if renderer_config.high_contrast {
+            Palette::high_contrast()
+        } else {
+            Palette::standard()
+        };
+        let light_trail = TrailRenderGroup::new(
+            &device,
+            &camera,
+            &config,
+            128,
+            palette.trail,
+            2.0,
+            Duration::from_secs(2),
+        );
+        let mut skybox = skybox::create(&device, &config, &queue, &camera, &assets)
+            .await
+            .map_err(InitError::asset("skybox"))?;
+        scene.set_ambient_sh(skybox.sh);
+        let probe_faces = std::mem::take(&mut skybox.probe_faces);
+        scene.probes.write_sky(&queue, &probe_faces);
+        let mut probe_capture = ProbeCapture::new(&device, &config, &light_render_group, &scene);
+        // The sky stands in until the first frames capture the scene
+        for position in [Vector3::new(-60.0, 10.0, 0.0), Vector3::new(0.0, 0.0, 0.0)] {
+            if let Some(probe) = scene.probes.add(&queue, position) {
+                scene.probes.write_faces(&queue, probe, &probe_faces);
+                probe_capture.request(probe);
+            }
+        }
+        scene.probes.generate_mips(&device, &queue);
+        let mut render_groups = RenderGroups::default();
+        let mut minimap = Minimap::new(
+            &device,
+            &config,
+            &light_render_group,
+            &scene,
+            renderer_config.minimap,
+        );
+        minimap.hide(render_groups.push(skybox));
+        let canvas_group = render_groups.push(render_group);
         let depth_texture =
             texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        let depth_resolve = DepthResolve::new(&device, &config, &depth_texture);
+        let occlusion = (renderer_config.occlusion_culling && compute_supported)
+            .then(|| OcclusionCulling::new(&device, &config, &depth_resolve.texture));
+        let floor = render_groups.push(render_group_floor);
+        let girl = render_groups.push(model_render_group);
+        let sword = render_groups.push(sword_model_render_group);
+        let render_group_sphere = render_groups.push(render_group_sphere);
+        render_groups.push(hologram);
+        security_camera.hide(render_groups.push(render_group_monitor));
+        let render_group_dynamic = render_groups.push(render_group_dynamic);
+        let scene_color = renderer_config.glass.then(|| {
+            let mut target =
+                glass::scene_color_target(&device, &config, &light_render_group, &scene, &camera);
+            let obj = create_sphere(GLASS_BALL_RADIUS, 32, 31, &device);
+            let entity = target.create_entity(&device, obj);
+            let [x, y, z] = GLASS_BALL;
+            let instances = Instances::new(
+                vec![InstanceTransform {
+                    position: Vector3::new(x, y + GLASS_BALL_RADIUS, z),
+                    ..Default::default()
+                }],
+                &device,
+            );
+            let mut group = GeoRenderGroup::new(
+                &device,
+                &camera,
+                entity,
+                instances,
+                &config,
+                &light_render_group,
+                &shadow_pass,
+            );
+            if let Err(e) = group.set_custom_shader(&device, &config, &Glass::default().shader()) {
+                log::error!("Glass shader: {:?}", e);
+            }
+            target.hide(render_groups.push(group));
+            target
+        });
+        if let Some(mut group) = stress_test_group {
+            if let Some(occlusion) = &occlusion {
+                group.enable_occlusion_culling(&device, occlusion);
+            }
+            render_groups.push(group);
+        }
+        if let Some(group) = vegetation_group {
+            render_groups.push(group);
+        }
+        if let Some(group) = particle_group {
+            render_groups.push(group);
+        }
+        // Transparent, drawn after everything opaque
+        let light_trail = render_groups.push(light_trail);
+        // After everything else, drawn on top
+        let debug_lines = render_groups.push(DebugLinesRenderGroup::new(
+            &device,
+            &camera,
+            &config,
+            DEBUG_LINES_CAPACITY,
+        ));
+        let depth_pre_pass = if DEPTH_PRE_PASS {
+            Some(DepthPrePass::new(&device, &light_render_group, &scene))
+        } else {
+            None
+        };
 
         let tex_view = create_multisampled_framebuffer(&device, &config);
-        let camera_controller = camera::CameraController::new(4.0, 0.2);
+        let mut camera_controller = camera::CameraController::new(
+            renderer_config.movement_speed,
+            renderer_config.mouse_sensitivity,
+        );
+        camera_controller.set_mode(renderer_config.camera_mode, &mut camera.view);
 
-        Self {
-            surface,
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        let color_filter = (renderer_config.color_filter != ColorFilter::None).then(|| {
+            ColorFilterPass::new(
+                &device,
+                &config,
+                renderer_config.color_filter,
+                renderer_config.color_correction,
+            )
+        });
+        let fxaa = renderer_config
+            .fxaa
+            .then(|| FxaaPass::new(&device, &config));
+        let present = PresentPass::new(&device, &config, surface_format);
+        let volumetric = (renderer_config.volumetric_density > 0.).then(|| {
+            VolumetricPass::new(
+                &device,
+                &queue,
+                &config,
+                &depth_resolve.texture,
+                &camera,
+                &light_render_group,
+                &shadow_pass,
+                renderer_config.volumetric_density,
+            )
+        });
+        let lens_flare = renderer_config.lens_flares.then(|| {
+            LensFlarePass::new(
+                &device,
+                &config,
+                &depth_resolve.texture,
+                &camera,
+                &light_render_group,
+            )
+        });
+        let outline = OutlinePass::new(&device, &config, &camera);
+        let (scene_commands, scene_command_receiver) = scene_commands::channel();
+        let mut state = Self {
+            instance,
+            surface: Some(surface),
             device,
             queue,
             config,
+            surface_format,
+            present_modes,
             size,
             tex_view,
-            frame_count: 0,
+            stats: FrameStats::default(),
             camera,
             camera_controller,
-            mouse_pressed: false,
+            cursor: CursorCapture::new(renderer_config.cursor_grab),
+            touch_input: TouchInput::default(),
             depth_texture,
+            depth_resolve,
+            occlusion,
+            capabilities,
             render_groups,
             light_render_group,
             render_group_sphere,
+            render_group_dynamic,
+            light_trail,
+            debug_lines,
+            debug_view: DebugView::default(),
+            frozen_frustum: None,
+            frame_limiter: FrameLimiter::new(
+                renderer_config.max_fps(),
+                renderer_config.background_fps,
+            ),
+            resize_debouncer: ResizeDebouncer::new(Duration::from_millis(
+                renderer_config.resize_delay_ms.into(),
+            )),
+            canvas,
+            canvas_group,
+            history: History::default(),
+            scene_commands,
+            switched_off_lights: HashMap::new(),
+            scene_command_receiver,
+            modifiers: ModifiersState::empty(),
+            picked: None,
+            window_mode: WindowMode::Windowed,
+            redraw_mode: renderer_config.redraw_mode,
+            camera_collision: renderer_config.camera_collision,
+            scene_scale: 1.,
+            redraw_requested: true,
+            gpu_timer,
+            render_targets: vec![security_camera],
+            scene_color,
+            probe_capture,
+            minimap,
+            split_views: vec![],
+            scene,
+            depth_pre_pass,
             total_duration: Duration::from_secs(0),
             shadow_pass,
+            world: World::default(),
+            scripts: script::Scripts::default(),
+            events: events::EventBus::default(),
+            color_filter,
+            fxaa,
+            present,
+            frame_graph: FrameGraph::default(),
+            frame_graph_overlay: FrameGraphOverlay::new(&device, surface_format),
+            volumetric,
+            lens_flare,
+            outline,
+            palette,
+            assets,
+            #[cfg(feature = "physics")]
+            physics: physics::Physics::default(),
+            #[cfg(feature = "audio")]
+            audio: audio::Audio::new(renderer_config.volume)
+                .map_err(|e| log::error!("No audio output: {:?}", e))
+                .ok(),
+        };
+        state.subscribe(State::resize_attachments);
+        state.spawn_world();
+        #[cfg(feature = "physics")]
+        state.add_demo_bodies(floor);
+        #[cfg(not(feature = "physics"))]
+        let _ = floor;
+        // Moving the girl carries the sword along
+        if let (Some(girl), Some(sword)) = (
+            state.world.mesh_entity(girl, 0),
+            state.world.mesh_entity(sword, 0),
+        ) {
+            state.world.set_parent(sword, Some(girl));
+        }
+        if renderer_config.lightmaps {
+            state.bake_lightmaps(&BakeSettings::default());
+        }
+        #[cfg(feature = "audio")]
+        state.start_music(&renderer_config.music).await;
+        #[cfg(all(feature = "scripting", not(target_arch = "wasm32")))]
+        if !renderer_config.scripts.is_empty() {
+            state.add_script(rhai_script::ScriptFolder::new(&renderer_config.scripts));
+        }
+        Ok(state)
+    }
+
+    // Unsupported modes fall back to Fifo when the surface is configured
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        self.config.present_mode = present_mode;
+        self.configure_surface();
+        log::warn!("Present mode: {:?}", present_mode);
+    }
+
+    // F11 or Alt+Enter cycles windowed, borderless and exclusive fullscreen
+    pub fn set_window_mode(&mut self, window: &Window, mode: WindowMode) {
+        self.window_mode = mode.apply(window);
+    }
+
+    pub fn set_redraw_mode(&mut self, redraw_mode: RedrawMode) {
+        self.redraw_mode = redraw_mode;
+        self.redraw_requested = true;
+        log::warn!("Redraw mode: {:?}", redraw_mode);
+    }
+
+    pub fn request_redraw(&mut self) {
+        self.redraw_requested = true;
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        if self.surface.is_none() {
+            return false;
+        }
+        self.redraw_mode == RedrawMode::Continuous
+            || self.redraw_requested
+            || self.camera_controller.is_moving(&self.camera.view)
+            || self
+                .split_views
+                .iter()
+                .any(|view| view.controller.is_moving(&view.camera.view))
+            || self.scene.is_animating()
+            || self.scripts.is_animating()
+            || self
+                .render_groups
+                .iter()
+                .any(|(_, group)| group.is_animating())
+    }
+
+    fn cycle_present_mode(&mut self) {
+        let present_mode = next_present_mode(self.config.present_mode, &self.present_modes);
+        if present_mode == self.config.present_mode {
+            log::warn!("No other present mode is supported");
+        } else {
+            self.set_present_mode(present_mode);
+        }
+    }
+
+    fn configure_surface(&self) {
+        if let Some(surface) = &self.surface {
+            let config = surface_config(&self.config, self.surface_format);
+            surface.configure(&self.device, &config);
+        }
+    }
+
+    // Android destroys the native window while the app is in the background and browsers can
+    // detach the canvas, the surface has to go with it
+    pub fn suspend(&mut self) {
+        self.surface = None;
+    }
+
+    // Recreates the surface for the new native window, the pipelines keep the old format
+    pub fn resume(&mut self, window: &Window) {
+        if self.surface.is_some() {
+            return;
         }
+        self.surface = Some(unsafe { self.instance.create_surface(window) });
+        // At the old size in case the new window has none yet
+        self.configure_surface();
+        self.resize(window.inner_size());
+        self.request_redraw();
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -344,18 +1316,232 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.camera
-                .projection
-                .resize(new_size.width, new_size.height);
-            self.surface.configure(&self.device, &self.config);
-            self.tex_view = create_multisampled_framebuffer(&self.device, &self.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.layout_views();
+            self.configure_surface();
+            self.publish(events::WindowResized {
+                width: new_size.width,
+                height: new_size.height,
+            });
+        }
+    }
+
+    // The first handler of WindowResized, so later ones see the new attachments
+    fn resize_attachments(&mut self, _resized: &events::WindowResized) {
+        self.tex_view = create_multisampled_framebuffer(&self.device, &self.config);
+        self.depth_texture =
+            texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.depth_resolve = DepthResolve::new(&self.device, &self.config, &self.depth_texture);
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.resize(&self.device, &self.config, &self.depth_resolve.texture);
+        }
+        if let Some(color_filter) = &mut self.color_filter {
+            color_filter.resize(&self.device, &self.config);
+        }
+        if let Some(fxaa) = &mut self.fxaa {
+            fxaa.resize(&self.device, &self.config);
+        }
+        if let Some(present) = &mut self.present {
+            present.resize(&self.device, &self.config);
+        }
+        self.outline.resize(&self.device, &self.config);
+        if let Some(volumetric) = &mut self.volumetric {
+            volumetric.resize(&self.device, &self.config, &self.depth_resolve.texture);
+        }
+        if let Some(lens_flare) = &mut self.lens_flare {
+            lens_flare.resize(&self.device, &self.depth_resolve.texture);
+        }
+    }
+
+    // Touch screens drive the main camera, see TouchInput
+    pub fn touch(&mut self, point: TouchPoint) -> bool {
+        let size = [self.config.width as f32, self.config.height as f32];
+        match self.touch_input.touch(point, size) {
+            Some(TouchGesture::Look { dx, dy }) => {
+                self.camera_controller.process_mouse(dx.into(), dy.into())
+            }
+            Some(TouchGesture::Pinch(spread)) => self.camera_controller.process_pinch(spread),
+            Some(TouchGesture::Joystick { right, forward }) => {
+                self.camera_controller.process_joystick(right, forward)
+            }
+            None => return false,
+        }
+        true
+    }
+
+    // For the window's resize events, applied by settle_resize once the size stops changing
+    pub fn request_resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.resize_debouncer
+            .request(new_size, instant::Instant::now());
+    }
+
+    // Resizes if the requested size has settled, else returns when to check again
+    pub fn settle_resize(&mut self, now: instant::Instant) -> Option<instant::Instant> {
+        if let Some(size) = self.resize_debouncer.take_ready(now) {
+            self.resize(size);
+            self.request_redraw();
+        }
+        self.resize_debouncer.deadline()
+    }
+
+    // Replaces what moves light `light` every update, see LightAnimator
+    pub fn set_light_animators(&mut self, light: usize, animators: Vec<LightAnimator>) {
+        self.light_render_group.set_animators(light, animators);
+        self.request_redraw();
+    }
+
+    // Projects `image` with spot light `light` inside its cone, None goes back to the plain cone
+    pub fn set_gobo(&mut self, light: usize, image: Option<&image::RgbaImage>) {
+        self.shadow_pass.set_gobo(&self.queue, light, image);
+        if let Some(uniform) = self.light_render_group.light_uniforms.get_mut(light) {
+            uniform.gobo = if image.is_some() { 1. } else { 0. };
+        }
+        self.request_redraw();
+    }
+
+    pub fn set_shadow_filter(&mut self, filter: ShadowFilter) {
+        self.shadow_pass
+            .set_filter(&self.device, &self.queue, filter);
+        log::warn!("Shadow filter: {:?}", filter);
+        self.request_redraw();
+    }
+
+    // With `correct` the deficiency is compensated instead of simulated
+    pub fn set_color_filter(&mut self, filter: ColorFilter, correct: bool) {
+        if filter == ColorFilter::None {
+            self.color_filter = None;
+        } else if let Some(color_filter) = &self.color_filter {
+            color_filter.set_filter(&self.queue, filter, correct);
+        } else {
+            self.color_filter = Some(ColorFilterPass::new(
+                &self.device,
+                &self.config,
+                filter,
+                correct,
+            ));
+        }
+        log::warn!("Color filter: {:?}, correction {}", filter, correct);
+        self.request_redraw();
+    }
+
+    // Smooths the edges of the final image, meant for when msaa is 1
+    pub fn set_fxaa(&mut self, enabled: bool) {
+        if !enabled {
+            self.fxaa = None;
+        } else if self.fxaa.is_none() {
+            self.fxaa = Some(FxaaPass::new(&self.device, &self.config));
+        }
+        log::warn!("FXAA: {}", enabled);
+        self.request_redraw();
+    }
+
+    pub fn set_lens_flares(&mut self, enabled: bool) {
+        if !enabled {
+            self.lens_flare = None;
+        } else if self.lens_flare.is_none() {
+            self.lens_flare = Some(LensFlarePass::new(
+                &self.device,
+                &self.config,
+                &self.depth_resolve.texture,
+                &self.camera,
+                &self.light_render_group,
+            ));
+        }
+        self.request_redraw();
+    }
+
+    // Scattering per world unit for the light shafts, 0 turns them off
+    pub fn set_volumetric_density(&mut self, density: f32) {
+        if density <= 0. {
+            self.volumetric = None;
+        } else if let Some(volumetric) = &self.volumetric {
+            volumetric.set_density(&self.queue, density);
+        } else {
+            self.volumetric = Some(VolumetricPass::new(
+                &self.device,
+                &self.queue,
+                &self.config,
+                &self.depth_resolve.texture,
+                &self.camera,
+                &self.light_render_group,
+                &self.shadow_pass,
+                density,
+            ));
         }
+        self.request_redraw();
     }
 
-    fn input(&mut self, event: &WindowEvent, window: &Window) -> bool {
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // True if the event was consumed
+    pub fn input(&mut self, event: &WindowEvent, window: &Window) -> bool {
         match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } if matches!(
+                key,
+                VirtualKeyCode::F8
+                    | VirtualKeyCode::F9
+                    | VirtualKeyCode::F10
+                    | VirtualKeyCode::F12
+                    | VirtualKeyCode::Back
+                    | VirtualKeyCode::Delete
+                    | VirtualKeyCode::PageUp
+                    | VirtualKeyCode::PageDown
+                    | VirtualKeyCode::Home
+                    | VirtualKeyCode::End
+                    | VirtualKeyCode::T
+                    | VirtualKeyCode::L
+                    | VirtualKeyCode::I
+                    | VirtualKeyCode::F
+                    | VirtualKeyCode::G
+                    | VirtualKeyCode::M
+                    | VirtualKeyCode::F6
+                    | VirtualKeyCode::F7
+                    | VirtualKeyCode::F11
+            ) || (self.modifiers.ctrl()
+                && matches!(key, VirtualKeyCode::Z | VirtualKeyCode::Y))
+                || (self.modifiers.alt() && *key == VirtualKeyCode::Return) =>
+            {
+                if *state == ElementState::Pressed {
+                    match key {
+                        VirtualKeyCode::F11 | VirtualKeyCode::Return => {
+                            self.set_window_mode(window, self.window_mode.next())
+                        }
+                        _ => self.process_edit_key(*key),
+                    }
+                }
+                true
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+            // Split screen: the arrow keys move the first split view, WASD the main camera
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode:
+                            Some(
+                                key @ (VirtualKeyCode::Up
+                                | VirtualKeyCode::Down
+                                | VirtualKeyCode::Left
+                                | VirtualKeyCode::Right),
+                            ),
+                        state,
+                        ..
+                    },
+                ..
+            } if !self.split_views.is_empty() => self.split_views[0]
+                .controller
+                .process_keyboard(*key, *state),
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
@@ -365,18 +1551,48 @@ impl State {
                     },
                 ..
             } => self.camera_controller.process_keyboard(*key, *state),
+            WindowEvent::Touch(touch) => self.touch(TouchPoint {
+                id: touch.id,
+                phase: touch.phase,
+                position: [touch.location.x as f32, touch.location.y as f32],
+            }),
             WindowEvent::MouseWheel { delta, .. } => {
                 self.camera_controller.process_scroll(delta);
                 true
             }
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::DroppedFile(path) => {
+                self.open_model_file(path);
+                false
+            }
             WindowEvent::MouseInput {
                 button: MouseButton::Left,
                 state: ElementState::Pressed,
                 ..
             } => {
-                self.mouse_pressed = true;
-                window.set_cursor_grab(true).ok();
-                window.set_cursor_visible(false);
+                self.cursor.capture(window);
+                true
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Middle,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                // Pick whatever is under the center of the screen
+                let (width, height) = self.camera.projection.size();
+                if let Some(ray) = self
+                    .camera
+                    .screen_ray([width as f32 / 2., height as f32 / 2.])
+                {
+                    self.picked = None;
+                    if let Some((group, hit)) = self.pick(&ray) {
+                        log::warn!("Picked group {}: {:?}", group, hit);
+                        self.picked = Some((group, hit.instance));
+                        if self.render_groups.id(group) == Some(self.canvas_group) {
+                            self.paint(hit.uv, 12.0, self.palette.brush);
+                        }
+                    }
+                }
                 true
             }
             WindowEvent::MouseInput {
@@ -384,107 +1600,871 @@ impl State {
                 state,
                 ..
             } => {
-                self.light_render_group.borrow_mut().light_uniforms[1].color =
-                    if *state == ElementState::Pressed {
-                        [1., 1., 1., 0.]
-                    } else {
-                        [0.; 4]
-                    };
+                let color = if *state == ElementState::Pressed {
+                    [1., 1., 1., 0.]
+                } else {
+                    [0.; 4]
+                };
+                self.light_render_group.light_uniforms[1].color = color;
                 true
             }
             _ => false,
         }
     }
 
-    fn update(&mut self, dt: std::time::Duration) {
+    // Ctrl+Z undoes, Ctrl+Y or Ctrl+Shift+Z redoes. The other keys edit the picked entity:
+    // PageUp/PageDown move it, Home/End stretch it vertically, T toggles a red material,
+    // Delete removes it and L dims the sun. With the physics feature I kicks it up.
+    // F frames the picked entity, or the whole scene when nothing is picked. G switches between
+    // flying and walking, M shows or hides the minimap, F6 the debug lines and F7 splits the
+    // screen with a second camera. F12 writes the frame graph to frame_graph.dot, Shift+F12
+    // shows it over the frame.
+    fn process_edit_key(&mut self, key: VirtualKeyCode) {
+        match key {
+            VirtualKeyCode::F6 => self.set_debug_view(if self.debug_view == DebugView::default() {
+                DebugView::all()
+            } else {
+                DebugView::default()
+            }),
+            VirtualKeyCode::F7 if self.split_views.is_empty() => {
+                self.add_split_view(self.camera.view.clone());
+            }
+            VirtualKeyCode::F7 => self.clear_split_views(),
+            VirtualKeyCode::M => self.set_minimap(!self.minimap.visible),
+            VirtualKeyCode::F => {
+                let picked = self.picked.and_then(|(group, instance)| {
+                    let group = self.render_groups.id(group)?;
+                    self.world.mesh_entity(group, instance)
+                });
+                match picked {
+                    Some(entity) => self.frame_entity(entity),
+                    None => self.frame_scene(),
+                };
+            }
+            VirtualKeyCode::G => self.set_camera_mode(match self.camera_controller.mode() {
+                CameraMode::Fly => CameraMode::Walk,
+                CameraMode::Walk => CameraMode::Fly,
+            }),
+            VirtualKeyCode::F8 => self.set_redraw_mode(match self.redraw_mode {
+                RedrawMode::Continuous => RedrawMode::Reactive,
+                RedrawMode::Reactive => RedrawMode::Continuous,
+            }),
+            VirtualKeyCode::F9 => self.cycle_present_mode(),
+            VirtualKeyCode::F10 => self.frame_limiter.cycle(),
+            VirtualKeyCode::F12 if self.modifiers.shift() => {
+                self.set_frame_graph_overlay(!self.frame_graph_overlay.visible);
+            }
+            VirtualKeyCode::F12 => self.export_frame_graph(),
+            VirtualKeyCode::Z if self.modifiers.shift() => {
+                self.redo();
+            }
+            VirtualKeyCode::Z | VirtualKeyCode::Back => {
+                self.undo();
+            }
+            VirtualKeyCode::Y => {
+                self.redo();
+            }
+            VirtualKeyCode::L => {
+                let mut light = self.light_render_group.light_uniforms[0];
+                light.diffuse_strength *= 0.5;
+                self.set_light(0, light);
+            }
+            _ => self.edit_picked(key),
+        }
+    }
+
+    fn edit_picked(&mut self, key: VirtualKeyCode) {
+        // Undoing an add or remove can leave the pick dangling
+        let (group, instance) = match self.picked {
+            Some(picked) if picked.0 < self.render_groups.len() => picked,
+            _ => return,
+        };
+        match key {
+            VirtualKeyCode::Delete => {
+                self.remove_render_group(group);
+                self.picked = None;
+            }
+            VirtualKeyCode::T => {
+                let red = self.palette.highlight;
+                let color = self
+                    .render_groups
+                    .get_mut(group)
+                    .and_then(|group| group.tint_mut())
+                    .map(|tint| tint.color());
+                if let Some(color) = color {
+                    self.set_material_color(group, if color == red { [1.; 3] } else { red });
+                }
+            }
+            VirtualKeyCode::PageUp | VirtualKeyCode::PageDown => {
+                let offset = if key == VirtualKeyCode::PageUp {
+                    1.
+                } else {
+                    -1.
+                };
+                let transform = self
+                    .render_groups
+                    .get(group)
+                    .and_then(|group| group.transform(instance));
+                if let Some(mut transform) = transform {
+                    transform.position.y += offset;
+                    self.set_instance_transform(group, instance, transform);
+                }
+            }
+            VirtualKeyCode::Home | VirtualKeyCode::End => {
+                let factor = if key == VirtualKeyCode::Home {
+                    1.25
+                } else {
+                    0.8
+                };
+                let transform = self
+                    .render_groups
+                    .get(group)
+                    .and_then(|group| group.transform(instance));
+                if let Some(mut transform) = transform {
+                    transform.scale.y *= factor;
+                    self.set_instance_transform(group, instance, transform);
+                }
+            }
+            #[cfg(feature = "physics")]
+            VirtualKeyCode::I => self.kick_picked(group, instance),
+            _ => {}
+        }
+    }
+
+    pub fn update(&mut self, dt: std::time::Duration) {
+        profile_scope!("update");
+        self.stats.begin_frame(dt);
+        self.apply_scene_commands();
+        let reactive = self.redraw_mode == RedrawMode::Reactive;
+        // After idling the first frame would otherwise catch up on seconds of motion
+        let dt = if reactive {
+            dt.min(Duration::from_millis(100))
+        } else {
+            dt
+        };
+        // The built-in scene animations only run in continuous mode
+        let animation_dt = if reactive { Duration::ZERO } else { dt };
+        let camera_start = self.camera.view.position;
         self.camera_controller
             .update_camera(&mut self.camera.view, dt);
+        if self.camera_controller.mode() == CameraMode::Walk {
+            self.walk_camera(camera_start);
+        } else if self.camera_collision {
+            self.collide_camera(camera_start);
+        }
+        self.run_scripts(dt);
         self.camera.update_camera(&self.queue);
-        self.light_render_group.borrow_mut().update_light(dt, self);
-        self.total_duration += dt;
-        let count = (3 + self.total_duration.as_secs() % 15) as usize;
-        self.render_group_sphere.borrow_mut().entity.obj =
-            create_sphere(10.0, count, count - 1, &self.device);
+        if let Some(depth_pre_pass) = &self.depth_pre_pass {
+            depth_pre_pass.update(&self.queue, &self.camera);
+        }
+        for target in &mut self.render_targets {
+            target.update(&self.queue);
+        }
+        if let Some(scene_color) = &mut self.scene_color {
+            scene_color.follow(&self.camera);
+            scene_color.update(&self.queue);
+        }
+        self.probe_capture.update(&self.queue, &self.scene.probes);
+        self.minimap.update(&self.queue, &self.camera.view);
+        self.update_split_views(dt);
+        self.light_render_group
+            .update_light(animation_dt, &self.config, &self.camera, &self.queue);
+        self.shadow_pass
+            .update(&self.device, &self.queue, &self.light_render_group);
+        let light_position = self.light_render_group.light_uniforms[0].position;
+        if let Some(trail) = self
+            .render_groups
+            .typed_mut::<TrailRenderGroup>(self.light_trail)
+        {
+            trail.record(light_position.into());
+        }
+        self.scene.update(dt, &self.queue);
+        // Simulated instead with the physics feature
+        #[cfg(feature = "physics")]
+        self.step_physics(animation_dt);
+        #[cfg(not(feature = "physics"))]
+        if let Some(dynamic) = self
+            .render_groups
+            .typed_mut::<DynamicRenderGroup>(self.render_group_dynamic)
+        {
+            let spin = Quaternion::from_axis_angle(
+                Vector3::unit_y(),
+                cgmath::Deg(90.0 * animation_dt.as_secs_f32()),
+            );
+            for object in &mut dynamic.objects {
+                object.transform.rotation = spin * object.transform.rotation;
+            }
+        }
+        self.run_world_systems();
+        self.publish_spawned();
+        #[cfg(feature = "audio")]
+        self.update_audio();
+        self.draw_debug_view();
+        let eye = self.camera.view.position;
+        for group in self.render_groups.iter_mut() {
+            group.update(dt, &self.queue);
+            group.select_lod(eye);
+        }
+        self.total_duration += animation_dt;
+        // The demo sphere steps from the coarsest tessellation up to full detail and over
+        let levels = (SPHERE_DETAIL_MAX - SPHERE_DETAIL_MIN + 1) as u64;
+        let step = (self.total_duration.as_secs() % levels) as usize;
+        if let Some(sphere) = self
+            .render_groups
+            .typed_mut::<GeoRenderGroup>(self.render_group_sphere)
+        {
+            sphere.set_lod(SPHERE_DETAIL_MAX - SPHERE_DETAIL_MIN - step);
+        }
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.frame_count += 1;
-        // println!("frame count: {}", self.frame_count);
-
-        let output = self.surface.get_current_texture()?;
-        let view = output
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        profile_scope!("render");
+        let output = {
+            profile_scope!("acquire");
+            match &self.surface {
+                Some(surface) => surface.get_current_texture()?,
+                None => return Ok(()),
+            }
+        };
+        let surface_view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        let view = match &self.present {
+            Some(present) => &present.texture.view,
+            None => &surface_view,
+        };
+        let scene_view = scene_view(&self.fxaa, &self.color_filter, view);
+        // scene_view in the frame graph, `view` is the surface
+        let scene_target = match (&self.fxaa, &self.color_filter) {
+            (Some(_), _) => "fxaa_input",
+            (None, Some(_)) => "color_filter_input",
+            (None, None) => "surface",
+        };
 
+        // The shadow scope is opened here and closed at the start of the main encoder,
+        // around the shadow buffers which are submitted in between
+        let mut shadow_timer_encoder =
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Shadow Timer Encoder"),
+                });
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
-        let refs: Vec<_> = self.render_groups.iter().map(|x| x.borrow()).collect();
-        self.shadow_pass.render_pass(
-            &mut encoder,
-            &refs,
-            &self.light_render_group.borrow().light_render_triplets,
+        let single_view = self.split_views.is_empty();
+        let culled = match &mut self.occlusion {
+            Some(occlusion) => occlusion.prepare(&self.queue, &self.camera, single_view),
+            None => false,
+        };
+        self.gpu_timer.begin_frame();
+        let light_render_group = &self.light_render_group;
+        let lights: &dyn RenderGroup = light_render_group;
+        let groups: Vec<&dyn RenderGroup> = iter::once(lights)
+            .chain(self.render_groups.iter().map(|(_, group)| group))
+            .collect();
+        let mut graph = FrameGraph::default();
+        // Submitted before the shadow maps, which draw the meshes it blends
+        if self.capabilities.compute {
+            profile_scope!("compute");
+            self.gpu_timer.begin(&mut shadow_timer_encoder, "compute");
+            {
+                let mut pass =
+                    shadow_timer_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("Compute Pass"),
+                    });
+                for group in &groups {
+                    group.dispatch(&mut pass);
+                }
+            }
+            self.gpu_timer.end(&mut shadow_timer_encoder);
+            graph.pass("Compute", &[], &["storage_buffers"]);
+        }
+        self.gpu_timer.begin(&mut shadow_timer_encoder, "shadow");
+        let shadow_pass = &self.shadow_pass;
+        let device = &self.device;
+        let groups = &groups;
+        // The shadow maps encoded on the rayon pool below, after the compute pass
+        let compute: &[&str] = if self.capabilities.compute {
+            &["storage_buffers"]
+        } else {
+            &[]
+        };
+        let light_count = light_render_group.light_render_pairs.len();
+        let shadow_layers: Vec<_> = (0..light_count)
+            .map(|i| format!("shadow_map[{}]", i))
+            .collect();
+        for (i, layer) in shadow_layers.iter().enumerate() {
+            graph.pass(&format!("Shadow light {}", i), compute, &[layer.as_str()]);
+        }
+        let shadow_layers: Vec<_> = shadow_layers.iter().map(String::as_str).collect();
+        // Shadow maps are recorded on the rayon pool while this thread records the rest
+        let (shadows, main_draws) = join_in_place(
+            || {
+                profile_scope!("shadow");
+                shadow_pass.encode(device, groups, light_render_group)
+            },
+            || {
+                let mut draws = DrawStats::default();
+                // Textures the shaders of the main pass sample
+                let mut sampled: Vec<_> = shadow_layers
+                    .iter()
+                    .map(|layer| layer.to_string())
+                    .collect();
+                self.gpu_timer.end(&mut encoder);
+                {
+                    profile_scope!("render targets");
+                    self.gpu_timer.begin(&mut encoder, "render targets");
+                    let targets = self.render_targets.iter().chain(&self.scene_color);
+                    let labels = (0..self.render_targets.len())
+                        .map(|i| format!("render_target_{}", i))
+                        .chain(self.scene_color.iter().map(|_| "scene_color".to_string()))
+                        .chain(self.minimap.target().map(|_| "minimap".to_string()));
+                    for (target, label) in targets.chain(self.minimap.target()).zip(labels) {
+                        let groups: Vec<&dyn RenderGroup> = iter::once(lights)
+                            .chain(
+                                self.render_groups
+                                    .iter()
+                                    .filter(|(id, _)| !target.is_hidden(*id))
+                                    .map(|(_, group)| group),
+                            )
+                            .collect();
+                        draws += target.render(&mut encoder, shadow_pass, &groups);
+                        let color = format!("{}_color", label);
+                        let depth = format!("{}_depth", label);
+                        describe_scene(&mut graph, &label, &color, &depth, &shadow_layers);
+                        // The minimap is composited over the frame instead
+                        if label != "minimap" {
+                            sampled.push(color);
+                        }
+                    }
+                    self.gpu_timer.end(&mut encoder);
+                }
+                if self.probe_capture.current().is_some() {
+                    profile_scope!("probe capture");
+                    self.gpu_timer.begin(&mut encoder, "probe capture");
+                    let groups: Vec<&dyn RenderGroup> = iter::once(lights)
+                        .chain(
+                            self.render_groups
+                                .iter()
+                                .filter(|(id, _)| *id != self.debug_lines)
+                                .map(|(_, group)| group),
+                        )
+                        .collect();
+                    draws += self.probe_capture.render(
+                        &mut encoder,
+                        shadow_pass,
+                        &groups,
+                        &self.scene.probes,
+                    );
+                    self.gpu_timer.end(&mut encoder);
+                    for face in 0..6 {
+                        let label = format!("probe_face_{}", face);
+                        let color = format!("{}_color", label);
+                        let depth = format!("{}_depth", label);
+                        describe_scene(&mut graph, &label, &color, &depth, &shadow_layers);
+                        let copy = format!("Probe copy {}", face);
+                        graph.pass(&copy, &[color.as_str()], &["reflection_probes"]);
+                    }
+                    sampled.push("reflection_probes".to_string());
+                }
+                if self.capabilities.compute {
+                    sampled.push("storage_buffers".to_string());
+                }
+                if let Some(occlusion) = self.occlusion.as_ref().filter(|_| culled) {
+                    profile_scope!("occlusion cull");
+                    self.gpu_timer.begin(&mut encoder, "occlusion cull");
+                    occlusion.cull(&mut encoder, &self.queue, groups);
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Occlusion cull", &["hiz_pyramid"], &["visible_instances"]);
+                    sampled.push("visible_instances".to_string());
+                }
+                {
+                    profile_scope!("main");
+                    self.gpu_timer.begin(&mut encoder, "main");
+                    let rects = self.view_rects();
+                    let viewport = |i: usize| rects.as_ref().map(|rects| rects[i]);
+                    let main_camera = SceneCamera {
+                        camera: &self.camera,
+                        depth_pre_pass: self.depth_pre_pass.as_ref(),
+                        viewport: viewport(0),
+                        culled,
+                    };
+                    let split_cameras =
+                        self.split_views
+                            .iter()
+                            .enumerate()
+                            .map(|(i, view)| SceneCamera {
+                                camera: &view.camera,
+                                depth_pre_pass: view.depth_pre_pass.as_ref(),
+                                viewport: viewport(i + 1),
+                                culled: false,
+                            });
+                    let cameras: Vec<_> = iter::once(main_camera).chain(split_cameras).collect();
+                    draws += render_scene(
+                        &mut encoder,
+                        scene_view,
+                        &self.tex_view,
+                        &self.depth_texture.view,
+                        &cameras,
+                        shadow_pass,
+                        groups,
+                    );
+                    self.gpu_timer.end(&mut encoder);
+                    let sampled: Vec<_> = sampled.iter().map(String::as_str).collect();
+                    describe_scene(&mut graph, "main", scene_target, "depth_texture", &sampled);
+                }
+                {
+                    profile_scope!("depth resolve");
+                    self.gpu_timer.begin(&mut encoder, "depth resolve");
+                    self.depth_resolve.resolve(&mut encoder);
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Depth resolve", &["depth_texture"], &["resolved_depth"]);
+                }
+                if let Some(occlusion) = &self.occlusion {
+                    profile_scope!("hi-z");
+                    self.gpu_timer.begin(&mut encoder, "hi-z");
+                    occlusion.build(&mut encoder);
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Hi-Z pyramid", &["resolved_depth"], &["hiz_pyramid"]);
+                }
+                if let Some(volumetric) = &self.volumetric {
+                    profile_scope!("volumetric");
+                    self.gpu_timer.begin(&mut encoder, "volumetric");
+                    volumetric.render(
+                        &mut encoder,
+                        scene_view,
+                        &self.camera,
+                        light_render_group,
+                        shadow_pass,
+                    );
+                    self.gpu_timer.end(&mut encoder);
+                    let mut reads = vec!["resolved_depth"];
+                    reads.extend(&shadow_layers);
+                    graph.pass("Volumetric", &reads, &["volumetric_scatter"]);
+                    graph.pass(
+                        "Volumetric composite",
+                        &["volumetric_scatter"],
+                        &[scene_target],
+                    );
+                }
+                if let Some(lens_flare) = &self.lens_flare {
+                    profile_scope!("lens flare");
+                    self.gpu_timer.begin(&mut encoder, "lens flare");
+                    lens_flare.render(&mut encoder, scene_view, &self.camera, light_render_group);
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Lens flare", &["resolved_depth"], &[scene_target]);
+                }
+                let picked = self
+                    .picked
+                    .and_then(|(group, instance)| Some((self.render_groups.get(group)?, instance)));
+                if let Some(picked) = picked {
+                    profile_scope!("outline");
+                    self.gpu_timer.begin(&mut encoder, "outline");
+                    self.outline.render(
+                        &mut encoder,
+                        &self.queue,
+                        scene_view,
+                        &self.camera,
+                        picked,
+                        self.palette.outline,
+                    );
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Outline mask", &[], &["outline_mask"]);
+                    graph.pass("Outline", &["outline_mask"], &[scene_target]);
+                }
+                if let Some(fxaa) = &self.fxaa {
+                    profile_scope!("fxaa");
+                    self.gpu_timer.begin(&mut encoder, "fxaa");
+                    let target = match &self.color_filter {
+                        Some(color_filter) => &color_filter.texture.view,
+                        None => view,
+                    };
+                    fxaa.apply(&mut encoder, target);
+                    self.gpu_timer.end(&mut encoder);
+                    let target = if self.color_filter.is_some() {
+                        "color_filter_input"
+                    } else {
+                        "surface"
+                    };
+                    graph.pass("FXAA", &["fxaa_input"], &[target]);
+                }
+                if let Some(color_filter) = &self.color_filter {
+                    profile_scope!("color filter");
+                    self.gpu_timer.begin(&mut encoder, "color filter");
+                    color_filter.apply(&mut encoder, view);
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Color filter", &["color_filter_input"], &["surface"]);
+                }
+                if self.minimap.visible {
+                    profile_scope!("minimap");
+                    self.gpu_timer.begin(&mut encoder, "minimap");
+                    let (width, height) = (self.config.width, self.config.height);
+                    self.minimap.composite(&mut encoder, view, width, height);
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Minimap", &["minimap_color"], &["surface"]);
+                }
+                if let Some(present) = &self.present {
+                    profile_scope!("present");
+                    self.gpu_timer.begin(&mut encoder, "present");
+                    present.apply(&mut encoder, &surface_view);
+                    self.gpu_timer.end(&mut encoder);
+                    graph.pass("Present", &["surface"], &["swapchain"]);
+                }
+                self.gpu_timer.resolve(&mut encoder);
+                draws
+            },
         );
+        let (shadow_buffers, shadow_draws) = shadows;
+        self.stats.draws += shadow_draws;
+        self.stats.draws += main_draws;
+        if self.frame_graph_overlay.visible {
+            profile_scope!("frame graph overlay");
+            let output = if self.present.is_some() {
+                "swapchain"
+            } else {
+                "surface"
+            };
+            graph.pass("Frame graph overlay", &[], &[output]);
+            let (width, height) = (self.config.width, self.config.height);
+            let overlay = &mut self.frame_graph_overlay;
+            overlay.update(&self.device, &self.queue, &graph, width, height);
+            overlay.render(&mut encoder, &surface_view);
+        }
+        self.frame_graph = graph;
+
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: if SAMPLE_COUNT == 1 {
-                        &view
-                    } else {
-                        &self.tex_view
-                    },
-                    resolve_target: Some(&view).filter(|_| SAMPLE_COUNT != 1),
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
+            profile_scope!("submit");
+            self.queue.submit(
+                iter::once(shadow_timer_encoder.finish())
+                    .chain(shadow_buffers)
+                    .chain(iter::once(encoder.finish())),
+            );
+            output.present();
+        }
+        // Objects light with the new probe from the next frame on
+        if self.probe_capture.current().is_some() {
+            self.scene.probes.generate_mips(&self.device, &self.queue);
+        }
+        self.gpu_timer.end_frame(&self.device);
+        self.stats.end_frame();
+        Ok(())
+    }
 
-            render_pass.set_bind_group(0, &self.camera.camera_bind_group, &[]);
-            render_pass.set_bind_group(3, &self.shadow_pass.shadow_map_bind_group, &[]);
-            refs.iter().for_each(|x| {
-                x.render(&mut render_pass, false);
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    // Single sampled copy of this frame's scene depth for post effects
+    pub fn resolved_depth(&self) -> &Texture {
+        &self.depth_resolve.texture
+    }
+
+    // Milliseconds per pass of a recent frame, empty without timestamp query support
+    pub fn gpu_timings(&self) -> &[(String, f32)] {
+        self.gpu_timer.timings()
+    }
+
+    // For creating the meshes, textures and instances of new groups
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    // For loading more textures and models from the same places as the built-in scene
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    pub fn capabilities(&self) -> &RendererCapabilities {
+        &self.capabilities
+    }
+
+    // A `size` by `size` noise texture that repeats, with mipmaps. Single channel, see noise.rs.
+    pub fn create_noise_texture(
+        &self,
+        size: u32,
+        settings: &NoiseSettings,
+        label: &str,
+    ) -> Texture {
+        noise::generate_noise(&self.device, &self.queue, size, settings, label)
+    }
+
+    pub fn camera_view(&self) -> &CameraView {
+        &self.camera.view
+    }
+
+    // Levels of detail for a group from add_mesh_group, see GeoRenderGroup::set_lods.
+    // False if the group is gone.
+    pub fn set_mesh_lods(&mut self, id: GroupId, lods: Vec<GeoObj>, distance: f32) -> bool {
+        match self.render_groups.typed_mut::<GeoRenderGroup>(id) {
+            Some(group) => {
+                group.set_lods(lods, distance);
+                self.request_redraw();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_minimap(&mut self, visible: bool) {
+        self.minimap.visible = visible;
+        self.request_redraw();
+    }
+
+    // The passes and attachments of each frame drawn over it, see frame_graph_overlay.rs
+    pub fn set_frame_graph_overlay(&mut self, visible: bool) {
+        self.frame_graph_overlay.visible = visible;
+        self.request_redraw();
+    }
+
+    pub fn set_camera_view(&mut self, view: CameraView) {
+        self.camera.view = view;
+        self.request_redraw();
+    }
+
+    // Draws `entity` at every instance with the lit pipeline and spawns an entity per instance.
+    // Undoable like any added group.
+    pub fn add_mesh_group(&mut self, entity: Entity, instances: Instances) -> GroupId {
+        let group = GeoRenderGroup::new(
+            &self.device,
+            &self.camera,
+            entity,
+            instances,
+            &self.config,
+            &self.light_render_group,
+            &self.shadow_pass,
+        );
+        let id = self.add_render_group(Box::new(group));
+        self.world.spawn_group(&self.render_groups, id);
+        id
+    }
+
+    // Like add_mesh_group for a model from resources::build_model
+    pub(crate) fn add_model_group(&mut self, model: model::Model, instances: Instances) -> GroupId {
+        let group = ModelRenderGroup::new(
+            model,
+            instances,
+            &self.device,
+            &self.camera,
+            &self.config,
+            &self.light_render_group,
+            &self.shadow_pass,
+        );
+        let id = self.add_render_group(Box::new(group));
+        self.world.spawn_group(&self.render_groups, id);
+        id
+    }
+
+    // Weights of the morph targets of mesh `mesh` of a model group, in place of its animation.
+    // False if the group is no model or the mesh has no morph targets.
+    pub fn set_morph_weights(&mut self, id: GroupId, mesh: usize, weights: &[f32]) -> bool {
+        let group = match self.render_groups.typed_mut::<ModelRenderGroup>(id) {
+            Some(group) => group,
+            None => return false,
+        };
+        let set = group.set_morph_weights(mesh, weights);
+        self.request_redraw();
+        set
+    }
+
+    // Closest hit over all render groups, with the index of the group that was hit
+    fn pick(&self, ray: &Ray) -> Option<(usize, RayHit)> {
+        self.render_groups
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, group))| Some((i, group.raycast(ray)?)))
+            .min_by(|(_, a), (_, b)| {
+                a.distance
+                    .partial_cmp(&b.distance)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    }
+
+    fn export_frame_graph(&self) {
+        let dot = self.frame_graph.to_dot();
+        #[cfg(not(target_arch = "wasm32"))]
+        match std::fs::write("frame_graph.dot", &dot) {
+            Ok(_) => log::warn!("Frame graph written to frame_graph.dot"),
+            Err(e) => log::error!("Failed to write frame graph: {}", e),
+        }
+        #[cfg(target_arch = "wasm32")]
+        log::warn!("{}", dot);
+    }
+}
+
+// `config` with the format the surface is presented in
+fn surface_config(
+    config: &wgpu::SurfaceConfiguration,
+    format: wgpu::TextureFormat,
+) -> wgpu::SurfaceConfiguration {
+    wgpu::SurfaceConfiguration {
+        format,
+        ..config.clone()
+    }
+}
+
+// Where the scene is drawn: the first post pass that takes a texture, else the surface
+fn scene_view<'a>(
+    fxaa: &'a Option<FxaaPass>,
+    color_filter: &'a Option<ColorFilterPass>,
+    surface: &'a wgpu::TextureView,
+) -> &'a wgpu::TextureView {
+    match (fxaa, color_filter) {
+        (Some(fxaa), _) => &fxaa.texture.view,
+        (None, Some(color_filter)) => &color_filter.texture.view,
+        (None, None) => surface,
+    }
+}
+
+// Runs `background` on the rayon pool while `foreground` runs on this thread, so only the
+// former has to be Send. Both run here one after the other on wasm.
+fn join_in_place<A: Send, B>(
+    background: impl FnOnce() -> A + Send,
+    foreground: impl FnOnce() -> B,
+) -> (A, B) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            (background(), foreground())
+        } else {
+            let mut result = None;
+            let b = rayon::in_place_scope(|scope| {
+                scope.spawn(|_| result = Some(background()));
+                foreground()
             });
+            (result.unwrap(), b)
         }
+    }
+}
 
-        self.queue.submit(iter::once(encoder.finish()));
-        output.present();
-        Ok(())
+// Adds the passes of render_scene, `sampled` are the textures read by its shaders
+fn describe_scene(graph: &mut FrameGraph, label: &str, color: &str, depth: &str, sampled: &[&str]) {
+    let mut reads = sampled.to_vec();
+    if DEPTH_PRE_PASS {
+        graph.pass(&format!("Depth pre-pass {}", label), &[], &[depth]);
+        reads.push(depth);
     }
+    let color_attachments = if sample_count() == 1 {
+        vec![color.to_string()]
+    } else {
+        vec![format!("{}_msaa", label), color.to_string()]
+    };
+    let mut writes: Vec<_> = color_attachments.iter().map(String::as_str).collect();
+    writes.push(depth);
+    graph.pass(&format!("Render {}", label), &reads, &writes);
+}
+
+// One of the cameras of a scene pass, drawn into `viewport` (x, y, width and height in pixels)
+// or the whole attachment
+pub(crate) struct SceneCamera<'a> {
+    pub camera: &'a Camera,
+    pub depth_pre_pass: Option<&'a DepthPrePass>,
+    pub viewport: Option<[f32; 4]>,
+    // Draws what the occlusion culling of this frame kept, see RenderGroup::render_culled
+    pub culled: bool,
+}
+
+// Draws every group into the given attachments once per camera, all of them sharing the shadow
+// maps; `msaa_view` is only used when sample_count() != 1
+fn render_scene(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    msaa_view: &wgpu::TextureView,
+    depth_view: &wgpu::TextureView,
+    cameras: &[SceneCamera],
+    shadow_pass: &ShadowPass,
+    groups: &[&dyn RenderGroup],
+) -> DrawStats {
+    let mut stats = DrawStats::default();
+    let mut pre_pass = false;
+    for camera in cameras {
+        if let Some(depth_pre_pass) = camera.depth_pre_pass {
+            stats += depth_pre_pass.render_pass(
+                encoder,
+                depth_view,
+                !pre_pass,
+                camera.viewport,
+                camera.culled,
+                groups,
+            );
+            pre_pass = true;
+        }
+    }
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Render Pass"),
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view: if sample_count() == 1 { view } else { msaa_view },
+            resolve_target: Some(view).filter(|_| sample_count() != 1),
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.0,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 1.0,
+                }),
+                store: true,
+            },
+        }],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view: depth_view,
+            depth_ops: Some(wgpu::Operations {
+                load: if pre_pass {
+                    wgpu::LoadOp::Load
+                } else {
+                    wgpu::LoadOp::Clear(1.0)
+                },
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    });
+
+    render_pass.set_bind_group(3, &shadow_pass.shadow_map_bind_group, &[]);
+    for camera in cameras {
+        if let Some([x, y, width, height]) = camera.viewport {
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        }
+        render_pass.set_bind_group(0, &camera.camera.camera_bind_group, &[]);
+        groups.iter().for_each(|x| {
+            if camera.culled {
+                x.render_culled(&mut render_pass, false);
+            } else {
+                x.render(&mut render_pass, false);
+            }
+            stats += x.draw_stats(false);
+        });
+    }
+    stats
 }
 
 fn create_multisampled_framebuffer(
     device: &wgpu::Device,
     config: &wgpu::SurfaceConfiguration,
+) -> wgpu::TextureView {
+    create_multisampled_view(device, config.width, config.height, config.format)
+}
+
+fn create_multisampled_view(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
 ) -> wgpu::TextureView {
     let multisampled_texture_extent = wgpu::Extent3d {
-        width: config.width,
-        height: config.height,
+        width,
+        height,
         depth_or_array_layers: 1,
     };
     let multisampled_frame_descriptor = &wgpu::TextureDescriptor {
         size: multisampled_texture_extent,
         mip_level_count: 1,
-        sample_count: SAMPLE_COUNT,
+        sample_count: sample_count(),
         dimension: wgpu::TextureDimension::D2,
-        format: config.format,
+        format,
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
         label: None,
     };
@@ -494,6 +2474,27 @@ fn create_multisampled_framebuffer(
         .create_view(&wgpu::TextureViewDescriptor::default())
 }
 
+// In place of a panic: logged, in the title of the window and on the web written into the page
+fn show_init_error(window: Option<&Window>, error: &InitError) {
+    log::error!("Renderer failed to start: {:?}", error);
+    if let Some(window) = window {
+        window.set_title(&format!("{} - {}", window_mode::TITLE, error));
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let document = web_sys::window().and_then(|win| win.document());
+        if let Some(document) = document {
+            if let (Ok(message), Some(body)) = (document.create_element("p"), document.body()) {
+                message.set_text_content(Some(&error.to_string()));
+                message
+                    .set_attribute("style", "color: #f66; font: 16px sans-serif; margin: 2em;")
+                    .ok();
+                body.append_child(&message).ok();
+            }
+        }
+    }
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
     cfg_if::cfg_if! {
@@ -504,29 +2505,30 @@ pub async fn run() {
             env_logger::init();
         }
     }
+    profiling::init();
 
     let event_loop = EventLoop::new();
     // let mut window = WindowBuilder::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let window = match WindowBuilder::new()
+        .with_title(window_mode::TITLE)
+        .build(&event_loop)
+    {
+        Ok(window) => window,
+        Err(e) => {
+            show_init_error(None, &InitError::Window(e));
+            return;
+        }
+    };
     let window = Rc::new(window);
+    // Only ever set on the web
+    let page_hidden = Rc::new(Cell::new(false));
+    let canvas_resized = Rc::new(Cell::new(None));
+    // Only ever filled on the web, winit reports touches elsewhere
+    let touches = Rc::new(std::cell::RefCell::new(Vec::new()));
     #[cfg(target_arch = "wasm32")]
     {
         use wasm_bindgen::JsCast;
         use winit::platform::web::WindowExtWebSys;
-        use winit::dpi::LogicalSize;
-        // Retrieve current width and height dimensions of browser client window
-        let get_window_size = || {
-            let client_window = web_sys::window().unwrap();
-            LogicalSize::new(
-                client_window.inner_width().unwrap().as_f64().unwrap(),
-                client_window.inner_height().unwrap().as_f64().unwrap(),
-            )
-        };
-
-        let window = Rc::clone(&window);
-
-        // Initialize winit window with current dimensions of browser client
-        window.set_inner_size(get_window_size());
 
         let client_window = web_sys::window().unwrap();
 
@@ -540,39 +2542,105 @@ pub async fn run() {
             })
             .expect("couldn't append canvas to document body");
 
-        // Listen for resize event on browser client. Adjust winit window dimensions
-        // on event trigger
+        // Follows the page layout instead of the browser window, so embedding pages can size it
+        resize::fit_canvas_to_parent(Rc::clone(&window), Rc::clone(&canvas_resized));
+        touch::listen_for_touches(&window.canvas(), Rc::clone(&touches));
+
+        // Throttle rendering while the tab is in the background
+        let hidden = Rc::clone(&page_hidden);
         let closure = wasm_bindgen::closure::Closure::wrap(Box::new(move |_e: web_sys::Event| {
-            let size = get_window_size();
-            window.set_inner_size(size)
+            let document = web_sys::window().and_then(|win| win.document());
+            hidden.set(document.map(|doc| doc.hidden()).unwrap_or(false));
         }) as Box<dyn FnMut(_)>);
         client_window
-            .add_event_listener_with_callback("resize", closure.as_ref().unchecked_ref())
+            .document()
+            .unwrap()
+            .add_event_listener_with_callback("visibilitychange", closure.as_ref().unchecked_ref())
             .unwrap();
         closure.forget();
     }
 
     // let window = window.build(&event_loop).unwrap();
     // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(&window).await;
+    let renderer_config = RendererConfig::load();
+    let mut state = match State::new(&window, &renderer_config).await {
+        Ok(state) => state,
+        Err(e) => {
+            show_init_error(Some(&window), &e);
+            // Keeps the window with the message open until it is closed
+            event_loop.run(move |event, _, control_flow| {
+                *control_flow = ControlFlow::Wait;
+                if let Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } = event
+                {
+                    *control_flow = ControlFlow::Exit;
+                }
+            });
+        }
+    };
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::WindowExtWebSys;
+        web_api::connect(state.scene_commands());
+        viewer::listen_for_drops(&window.canvas(), state.scene_commands());
+    }
 
     let mut last_render_time = instant::Instant::now();
+    // The title is refreshed once a second
+    let mut title_time = last_render_time;
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
-            Event::MainEventsCleared => window.request_redraw(),
+            Event::MainEventsCleared => {
+                state.frame_limiter.set_hidden(page_hidden.get());
+                if let Some(size) = canvas_resized.take() {
+                    state.request_resize(size);
+                }
+                for point in touches.take() {
+                    if state.touch(point) {
+                        state.request_redraw();
+                    }
+                }
+                if let Some(deadline) = state.settle_resize(instant::Instant::now()) {
+                    *control_flow = ControlFlow::WaitUntil(deadline);
+                    return;
+                }
+                if !state.needs_redraw() {
+                    *control_flow = ControlFlow::Wait;
+                    return;
+                }
+                // Browsers pace visible pages with requestAnimationFrame,
+                // there the background rate is the only cap that applies
+                let limited =
+                    cfg!(not(target_arch = "wasm32")) || state.frame_limiter.is_throttled();
+                match state.frame_limiter.next_frame() {
+                    Some(next) if limited && next > instant::Instant::now() => {
+                        *control_flow = ControlFlow::WaitUntil(next)
+                    }
+                    _ => window.request_redraw(),
+                }
+            }
             // NEW!
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion{ delta, },
                 .. // We're not using device_id currently
-            } => if state.mouse_pressed {
-                state.camera_controller.process_mouse(delta.0, delta.1)
+            } => if state.cursor.is_captured() {
+                state.camera_controller.process_mouse(delta.0, delta.1);
+                state.cursor.moved(&window);
+                state.request_redraw();
             }
             // UPDATED!
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == window.id() && !state.input(event, &window) => {
+            } if window_id == window.id() => {
+                if state.input(event, &window) {
+                    state.request_redraw();
+                    return;
+                }
                 match event {
                     #[cfg(not(target_arch="wasm32"))]
                     WindowEvent::CloseRequested
@@ -586,27 +2654,50 @@ pub async fn run() {
                         },
                         ..
                     } => {
-                        window.set_cursor_grab(false).ok();
-                        window.set_cursor_visible(true);
-                        state.mouse_pressed = false;
+                        state.cursor.release(&window);
+                    }
+                    WindowEvent::Focused(focused) => {
+                        if !focused {
+                            state.cursor.release(&window);
+                        }
+                        state.frame_limiter.set_focused(*focused);
                     }
                     WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
+                        state.request_resize(*physical_size);
                     }
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
+                        state.request_resize(**new_inner_size);
                     }
                     _ => {}
                 }
             }
+            Event::Suspended => state.suspend(),
+            Event::Resumed => state.resume(&window),
             // UPDATED!
-            Event::RedrawRequested(window_id) if window_id == window.id() => {
+            // Not until the attachments match the window again
+            Event::RedrawRequested(window_id)
+                if window_id == window.id() && !state.resize_debouncer.is_pending() =>
+            {
                 let now = instant::Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
+                state.frame_limiter.frame_started(now);
+                profiling::new_frame();
+                #[cfg(target_arch = "wasm32")]
+                web_api::report_fps(state.frame_stats().fps());
+                if (now - title_time).as_secs_f32() >= 1.0 {
+                    let stats = state.frame_stats();
+                    let mut status =
+                        format!("{:.0} fps | {} draws", stats.fps(), stats.draws.draw_calls);
+                    for (pass, ms) in state.gpu_timings() {
+                        status += &format!(" | {} {:.2} ms", pass, ms);
+                    }
+                    window_mode::set_title_status(&window, &status);
+                    title_time = now;
+                }
                 state.update(dt);
                 match state.render() {
-                    Ok(_) => {}
+                    Ok(_) => state.redraw_requested = false,
                     // Reconfigure the surface if lost
                     Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
                     // The system is out of memory, we should probably quit
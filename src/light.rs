@@ -1,11 +1,17 @@
-use crate::geo_gen::GeoObj;
-use crate::{geo_gen, texture, Camera, Projection, RenderGroup, State, MULTI_SAMPLE, PRIMITIVE};
-use cgmath::{Angle, Deg, Matrix4, Point3, Rotation3, SquareMatrix, Vector3};
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::geo_gen::{Entity, GeoObj, GeoRenderGroup};
+use crate::light_animation::{default_animators, LightAnimator, LightFrame};
+use crate::ltc::LtcTables;
+use crate::probes::ReflectionProbes;
+use crate::reflect::uniform_fields;
+use crate::scene::Scene;
+use crate::shadow::ShadowPass;
+use crate::stats::DrawStats;
+use crate::world_space::{InstanceTransform, Instances};
+use crate::{stats, texture, Camera, Projection, RenderGroup};
+use cgmath::{Angle, Deg, Matrix4, One, Point3, Quaternion, SquareMatrix, Vector3};
 use std::time::Duration;
 use wgpu::util::DeviceExt;
-use wgpu::{BindGroup, Buffer, Device, RenderPass, SurfaceConfiguration};
+use wgpu::{BindGroup, Buffer, Device, Queue, RenderPass, SurfaceConfiguration};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -26,6 +32,17 @@ pub struct LightUniform {
     // cutoff_inner_outer_eps[4] == 0? no_cutoff: cutoff
     pub cutoff_inner_outer_eps: [f32; 4],
     pub view_proj: [[f32; 4]; 4],
+    // Texels across the shadow map of the light, 0 is RendererConfig::shadow_resolution
+    pub shadow_resolution: f32,
+    // cast_shadows == 0? lit everywhere, skipped by the shadow pass: shadowed
+    pub cast_shadows: f32,
+    // gobo == 0? plain cone: the image of State::set_gobo is projected
+    pub gobo: f32,
+    pub _padding_4: f32,
+    // area_size[0] == 0? point, spot or directional: rectangle of this width and height facing
+    // along -direction, lit by ltc.rs
+    pub area_size: [f32; 2],
+    pub _padding_5: [f32; 2],
 }
 
 impl Default for LightUniform {
@@ -43,10 +60,40 @@ impl Default for LightUniform {
             point_clq: [1., 0.025, 0.0035, 1.],
             cutoff_inner_outer_eps: [0.; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            shadow_resolution: 0.,
+            cast_shadows: 1.,
+            gobo: 0.,
+            _padding_4: 0.,
+            area_size: [0.; 2],
+            _padding_5: [0.; 2],
         }
     }
 }
 
+uniform_fields!(LightUniform {
+    position: Vec3 => "Position",
+    direction: Vec3 => "Direction",
+    color: Color(0., 1.) => "Color",
+    diffuse_strength: Float(0., 10.) => "Diffuse",
+    ambient_strength: Float(0., 1.) => "Ambient",
+    specular_strength: Float(0., 10.) => "Specular",
+    point_clq: Vec4 => "Attenuation",
+    cutoff_inner_outer_eps: Vec4 => "Spot cutoff",
+    view_proj: Mat4 => "View projection",
+    shadow_resolution: Float(0., 8192.) => "Shadow resolution",
+    cast_shadows: Float(0., 1.) => "Cast shadows",
+    gobo: Float(0., 1.) => "Gobo",
+    area_size: Vec2 => "Area size",
+});
+
+pub const LIGHT_FOVY: f32 = 45.0;
+// Depth range of the shadow projection
+pub const LIGHT_NEAR: f32 = 1.0;
+pub const LIGHT_FAR: f32 = 300.0;
+// Bounds of RendererConfig::shadow_resolution and the per-light resolutions
+pub const MIN_SHADOW_RESOLUTION: u32 = 256;
+pub const MAX_SHADOW_RESOLUTION: u32 = 8192;
+
 impl LightUniform {
     pub fn calc_view_matrix(&self) -> Matrix4<f32> {
         Matrix4::look_to_rh(
@@ -56,11 +103,29 @@ impl LightUniform {
         )
     }
     fn calc_view_proj(&mut self, config: &SurfaceConfiguration) {
-        self.view_proj =
-            (Projection::new(config.width, config.height, cgmath::Deg(45.0), 1.0, 300.0)
-                .calc_matrix()
-                * self.calc_view_matrix())
-            .into();
+        self.view_proj = (Projection::new(
+            config.width,
+            config.height,
+            cgmath::Deg(LIGHT_FOVY),
+            LIGHT_NEAR,
+            LIGHT_FAR,
+        )
+        .calc_matrix()
+            * self.calc_view_matrix())
+        .into();
+    }
+
+    pub fn casts_shadows(&self) -> bool {
+        self.cast_shadows > 0.
+    }
+
+    // The shadow map size of the light when the renderer default is `default`
+    pub fn shadow_map_size(&self, default: u32) -> u32 {
+        if self.shadow_resolution > 0. {
+            (self.shadow_resolution as u32).clamp(MIN_SHADOW_RESOLUTION, MAX_SHADOW_RESOLUTION)
+        } else {
+            default
+        }
     }
 
     pub fn build_light(mut light: Self, config: &SurfaceConfiguration) -> Self {
@@ -76,167 +141,266 @@ pub fn cal_cutoff(inner: f32, outer: f32) -> [f32; 4] {
     [inner, outer, inner - outer, 1.]
 }
 
+// Spot light falloff for the cosine of the angle from its direction, mirrors cutoff in geo.wgsl
+pub fn cutoff_intensity(cutoff: [f32; 4], cos_angle: f32) -> f32 {
+    let [_, outer, epsilon, enabled] = cutoff;
+    if enabled == 0. {
+        return 1.;
+    }
+    ((cos_angle - outer) / epsilon).clamp(0., 1.)
+}
+
+// Size of the marker meshes relative to the objects they were made from
+const MARKER_SCALE: f32 = 0.25;
+
 pub struct LightRenderGroup {
     pub light_uniforms: Vec<LightUniform>,
     buffer: wgpu::Buffer,
     pub light_bind_group_layout: wgpu::BindGroupLayout,
-    light_bind_group: wgpu::BindGroup,
-    light_render_pipeline: wgpu::RenderPipeline,
-    pub light_render_triplets: Vec<(Buffer, BindGroup, GeoObj)>,
+    pub(crate) light_bind_group: wgpu::BindGroup,
+    // The uniform of a single light, bound by its shadow pass
+    pub light_render_pairs: Vec<(Buffer, BindGroup)>,
+    // Meshes showing where the lights are, moved to the lights every update
+    markers: Vec<GeoRenderGroup>,
+    marker_objs: Vec<GeoObj>,
+    // Grows and shrinks with the scene, see State::view_model
+    pub(crate) marker_scale: f32,
+    // Shared by the bind groups, only the area lights sample them
+    pub(crate) ltc: LtcTables,
+    // Run in order on their light every update
+    animators: Vec<Vec<LightAnimator>>,
+    // Animated seconds, for the animators that follow a clock
+    time: f32,
 }
 
 impl LightRenderGroup {
     pub fn new(
         device: &Device,
+        queue: &Queue,
         light_uniforms_and_objs: Vec<(LightUniform, GeoObj)>,
-        camera: &Camera,
         config: &SurfaceConfiguration,
-    ) -> Rc<RefCell<Self>> {
+        scene: &Scene,
+    ) -> Self {
         let (light_uniforms, objs): (Vec<LightUniform>, Vec<GeoObj>) =
             light_uniforms_and_objs.into_iter().unzip();
         let light_uniforms: Vec<_> = light_uniforms
             .into_iter()
             .map(|x| LightUniform::build_light(x, config))
             .collect();
-        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-            label: Some("Light Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
-        });
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light VB"),
             contents: bytemuck::cast_slice(&light_uniforms),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let [probe_texture, probe_sampler, probe_uniform, sky] = ReflectionProbes::layout_entries();
+        let [ltc_matrices, ltc_magnitudes, ltc_sampler] = LtcTables::layout_entries();
         let light_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Scene-wide lighting parameters such as the ambient model
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    probe_texture,
+                    probe_sampler,
+                    probe_uniform,
+                    ltc_matrices,
+                    ltc_magnitudes,
+                    ltc_sampler,
+                    sky,
+                ],
                 label: Some("Light Storage BindGroupLayout"),
             });
-        let light_render_triplets: Vec<_> = light_uniforms
+        let ltc = LtcTables::new(device, queue);
+        let light_render_pairs: Vec<_> = light_uniforms
             .iter()
-            .zip(objs)
-            .map(|(light_uniform, obj)| {
+            .map(|light_uniform| {
                 let buffer_per_light =
                     device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                         label: Some("Light VB"),
                         contents: bytemuck::cast_slice(&[*light_uniform]),
                         usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                     });
+                let [probe_texture, probe_sampler, probe_uniform, sky] =
+                    scene.probes.bind_group_entries();
+                let [ltc_matrices, ltc_magnitudes, ltc_sampler] = ltc.bind_group_entries();
                 let bind_group_per_light = device.create_bind_group(&wgpu::BindGroupDescriptor {
                     layout: &light_bind_group_layout,
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: buffer_per_light.as_entire_binding(),
-                    }],
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer_per_light.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: scene.buffer.as_entire_binding(),
+                        },
+                        probe_texture,
+                        probe_sampler,
+                        probe_uniform,
+                        ltc_matrices,
+                        ltc_magnitudes,
+                        ltc_sampler,
+                        sky,
+                    ],
                     label: None,
                 });
-                (buffer_per_light, bind_group_per_light, obj)
+                (buffer_per_light, bind_group_per_light)
             })
             .collect();
+        let [probe_texture, probe_sampler, probe_uniform, sky] = scene.probes.bind_group_entries();
+        let [ltc_matrices, ltc_magnitudes, ltc_sampler] = ltc.bind_group_entries();
         let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: scene.buffer.as_entire_binding(),
+                },
+                probe_texture,
+                probe_sampler,
+                probe_uniform,
+                ltc_matrices,
+                ltc_magnitudes,
+                ltc_sampler,
+                sky,
+            ],
             label: None,
         });
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[&camera.camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-        let light_render_pipeline =
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Light Render Pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[geo_gen::Vertex::desc()],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: Some(wgpu::BlendState {
-                            alpha: wgpu::BlendComponent::REPLACE,
-                            color: wgpu::BlendComponent::REPLACE,
-                        }),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    }],
-                }),
-                primitive: PRIMITIVE,
-                depth_stencil: texture::Texture::create_depth_state(),
-                multisample: MULTI_SAMPLE,
-                multiview: None,
-            });
-        Rc::new(RefCell::new(Self {
+        Self {
             light_uniforms,
             buffer,
             light_bind_group_layout,
             light_bind_group,
-            light_render_pipeline,
-            light_render_triplets,
-        }))
+            light_render_pairs,
+            markers: vec![],
+            marker_objs: objs,
+            marker_scale: MARKER_SCALE,
+            ltc,
+            animators: default_animators(light_uniforms.len()),
+            time: 0.,
+        }
     }
 
-    pub fn update_light(&mut self, dt: Duration, state: &State) {
-        let rotation: cgmath::Matrix3<f32> = cgmath::Quaternion::from_axis_angle(
-            cgmath::Vector3::unit_y(),
-            cgmath::Deg(-100. * dt.as_secs_f32()),
-        )
-        .into();
-        for (i, uniform) in self.light_uniforms.iter_mut().enumerate() {
-            *uniform = LightUniform::build_light(*uniform, &state.config);
-            if i == 0 {
-                let pos = cgmath::Vector3::from(uniform.position);
-                uniform.position = (rotation * pos).into();
-                uniform.direction = uniform.position;
-            } else if i == 1 {
-                let dir = state.camera.view.get_dir();
-                uniform.position = (state.camera.view.position + dir * 10.0).into();
-                uniform.direction = (-dir).into();
+    pub fn update_light(
+        &mut self,
+        dt: Duration,
+        config: &SurfaceConfiguration,
+        camera: &Camera,
+        queue: &Queue,
+    ) {
+        self.time += dt.as_secs_f32();
+        let frame = LightFrame {
+            dt: dt.as_secs_f32(),
+            time: self.time,
+            camera: &camera.view,
+        };
+        for (uniform, animators) in self.light_uniforms.iter_mut().zip(&mut self.animators) {
+            for animator in animators {
+                animator.apply(uniform, &frame);
             }
+            *uniform = LightUniform::build_light(*uniform, config);
         }
-        for ((buffer, _, _), uniform) in self.light_render_triplets.iter().zip(&self.light_uniforms)
-        {
-            state
-                .queue
-                .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.light_uniforms));
-            state
-                .queue
-                .write_buffer(buffer, 0, bytemuck::cast_slice(&[*uniform]));
+        for ((buffer, _), uniform) in self.light_render_pairs.iter().zip(&self.light_uniforms) {
+            stats::write_buffer(
+                queue,
+                &self.buffer,
+                0,
+                bytemuck::cast_slice(&self.light_uniforms),
+            );
+            stats::write_buffer(queue, buffer, 0, bytemuck::cast_slice(&[*uniform]));
         }
+        let scale = self.marker_scale;
+        for (marker, uniform) in self.markers.iter_mut().zip(&self.light_uniforms) {
+            let transform = InstanceTransform::new(uniform.position.into(), Quaternion::one())
+                .with_scale(Vector3::new(scale, scale, scale));
+            marker.set_transform(0, transform, queue);
+            let [r, g, b, _] = uniform.color;
+            if marker.entity.tint.emissive() != [r, g, b] {
+                marker.entity.tint.set_emissive([r, g, b]);
+            }
+            marker.update(dt, queue);
+        }
+    }
+
+    // Replaces the behaviors of light `index`, an empty list leaves it where it is
+    pub fn set_animators(&mut self, index: usize, animators: Vec<LightAnimator>) {
+        if let Some(slot) = self.animators.get_mut(index) {
+            *slot = animators;
+        }
+    }
+
+    // The markers are ordinary entities, black so the lights don't show on them, glowing in
+    // the color of their light. They need the layouts of this group, so they come after it.
+    pub(crate) fn add_markers(
+        &mut self,
+        device: &Device,
+        camera: &Camera,
+        config: &SurfaceConfiguration,
+        shadow_pass: &ShadowPass,
+    ) {
+        let black = texture::Texture::black(device, "Light marker");
+        let objs = std::mem::take(&mut self.marker_objs);
+        let markers = objs
+            .into_iter()
+            .map(|obj| {
+                let entity = Entity::with_texture(device, obj, &black);
+                let instances = Instances::new(vec![InstanceTransform::default()], device);
+                GeoRenderGroup::new(device, camera, entity, instances, config, self, shadow_pass)
+            })
+            .collect();
+        self.markers = markers;
+    }
+
+    fn visible_markers(&self) -> impl Iterator<Item = &GeoRenderGroup> {
+        self.markers
+            .iter()
+            .zip(&self.light_uniforms)
+            .filter(|(_, uniform)| uniform.color[3] != 0.)
+            .map(|(marker, _)| marker)
     }
 }
 
 impl RenderGroup for LightRenderGroup {
+    // Drawn first, binds the lights for every group after it
     fn render<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, shadow_pass: bool) {
-        if shadow_pass {
-            return;
+        if !shadow_pass {
+            render_pass.set_bind_group(1, &self.light_bind_group, &[]);
         }
-        render_pass.set_pipeline(&self.light_render_pipeline);
-        for (i, (_, bind_group_per_light, obj)) in self.light_render_triplets.iter().enumerate() {
-            if self.light_uniforms[i].color[3] == 0. {
-                continue;
-            }
-            render_pass.set_bind_group(1, bind_group_per_light, &[]);
-            render_pass.set_vertex_buffer(0, obj.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(obj.index_buffer.slice(..), GeoObj::INDEX_FORMAT);
-            render_pass.draw_indexed(obj.get_index_range(), 0, 0..1);
-        }
-        render_pass.set_bind_group(1, &self.light_bind_group, &[]);
+        self.visible_markers()
+            .for_each(|marker| marker.render(render_pass, shadow_pass));
+    }
+
+    // A marker sits on its light and would hide the whole scene from it
+    fn render_shadow<'a, 'b: 'a>(&'b self, _render_pass: &mut RenderPass<'a>, _lod: usize) {}
+
+    fn draw_stats(&self, shadow_pass: bool) -> DrawStats {
+        self.visible_markers()
+            .map(|marker| marker.draw_stats(shadow_pass))
+            .fold(DrawStats::default(), |mut stats, next| {
+                stats += next;
+                stats
+            })
     }
 }
@@ -0,0 +1,173 @@
+// Per-light behaviors run by LightRenderGroup::update_light, in order, every frame. Each one
+// edits the uniform in place, relative to what it was, so animators stack and edits made by
+// the inspector or undo history between frames stay.
+use crate::camera::CameraView;
+use crate::light::LightUniform;
+use cgmath::{Deg, Point3, Rotation3, Vector3};
+
+// What an animator gets to see besides its light
+pub struct LightFrame<'a> {
+    // Seconds since the last update, 0 while the scene is paused
+    pub dt: f32,
+    // Animated seconds since startup
+    pub time: f32,
+    pub camera: &'a CameraView,
+}
+
+type LightClosure = Box<dyn FnMut(&mut LightUniform, &LightFrame) + Send + Sync>;
+
+enum Behavior {
+    Orbit {
+        degrees_per_second: f32,
+    },
+    FollowCamera {
+        distance: f32,
+    },
+    // `factor` is the color scale applied by the last frame, divided out before the next
+    Flicker {
+        amount: f32,
+        speed: f32,
+        factor: f32,
+    },
+    ColorCycle {
+        period: f32,
+    },
+    Path {
+        points: Vec<[f32; 3]>,
+        seconds_per_point: f32,
+    },
+    Custom(LightClosure),
+}
+
+pub struct LightAnimator(Behavior);
+
+impl LightAnimator {
+    // Circles the y axis through the origin, pointing away from it
+    pub fn orbit(degrees_per_second: f32) -> Self {
+        Self(Behavior::Orbit { degrees_per_second })
+    }
+
+    // Sits `distance` in front of the camera looking back at it, a flashlight
+    pub fn follow_camera(distance: f32) -> Self {
+        Self(Behavior::FollowCamera { distance })
+    }
+
+    // Dims the color by up to `amount`, `speed` times faster than a calm candle
+    pub fn flicker(amount: f32, speed: f32) -> Self {
+        Self(Behavior::Flicker {
+            amount: amount.clamp(0., 1.),
+            speed,
+            factor: 1.,
+        })
+    }
+
+    // Turns the hue of the color once every `period` seconds
+    pub fn color_cycle(period: f32) -> Self {
+        Self(Behavior::ColorCycle { period })
+    }
+
+    // Moves along the closed polyline through `points`, a segment every `seconds_per_point`
+    pub fn path(points: Vec<[f32; 3]>, seconds_per_point: f32) -> Self {
+        Self(Behavior::Path {
+            points,
+            seconds_per_point,
+        })
+    }
+
+    pub fn custom(
+        closure: impl FnMut(&mut LightUniform, &LightFrame) + Send + Sync + 'static,
+    ) -> Self {
+        Self(Behavior::Custom(Box::new(closure)))
+    }
+
+    pub(crate) fn apply(&mut self, light: &mut LightUniform, frame: &LightFrame) {
+        match &mut self.0 {
+            Behavior::Orbit { degrees_per_second } => {
+                let rotation = cgmath::Quaternion::from_axis_angle(
+                    Vector3::unit_y(),
+                    Deg(*degrees_per_second * frame.dt),
+                );
+                let position = rotation * Vector3::from(light.position);
+                light.position = position.into();
+                light.direction = light.position;
+            }
+            Behavior::FollowCamera { distance } => {
+                let dir = frame.camera.get_dir();
+                light.position = (frame.camera.position + dir * *distance).into();
+                light.direction = (-dir).into();
+            }
+            Behavior::Flicker {
+                amount,
+                speed,
+                factor,
+            } => {
+                let new_factor = 1. - *amount * flicker_noise(frame.time * *speed);
+                // A light switched off keeps its factor, it has nothing to scale back
+                if *factor > 0. {
+                    for channel in &mut light.color[..3] {
+                        *channel *= new_factor / *factor;
+                    }
+                }
+                *factor = new_factor;
+            }
+            Behavior::ColorCycle { period } => {
+                if *period != 0. {
+                    let angle = std::f32::consts::TAU * frame.dt / *period;
+                    let [r, g, b, a] = light.color;
+                    let [r, g, b] = rotate_hue([r, g, b], angle);
+                    light.color = [r, g, b, a];
+                }
+            }
+            Behavior::Path {
+                points,
+                seconds_per_point,
+            } => {
+                if let Some(position) = path_position(points, frame.time / *seconds_per_point) {
+                    light.position = position.into();
+                }
+            }
+            Behavior::Custom(closure) => closure(light, frame),
+        }
+    }
+}
+
+// What light 0 and 1 always did: the sun circles the scene, the flashlight follows the camera
+pub(crate) fn default_animators(light_count: usize) -> Vec<Vec<LightAnimator>> {
+    (0..light_count)
+        .map(|i| match i {
+            0 => vec![LightAnimator::orbit(-100.)],
+            1 => vec![LightAnimator::follow_camera(10.)],
+            _ => vec![],
+        })
+        .collect()
+}
+
+// Smooth and never quite repeating, in 0..1
+pub fn flicker_noise(t: f32) -> f32 {
+    let n = t.sin() + 0.5 * (2.3 * t + 1.7).sin() + 0.25 * (5.1 * t + 0.3).sin();
+    0.5 + 0.5 * n / 1.75
+}
+
+// Rotation around the gray axis, keeps the brightness of grays
+pub fn rotate_hue([r, g, b]: [f32; 3], angle: f32) -> [f32; 3] {
+    let (s, c) = angle.sin_cos();
+    let k = (1. - c) / 3.;
+    let q = s / 3f32.sqrt();
+    [
+        (c + k) * r + (k - q) * g + (k + q) * b,
+        (k + q) * r + (c + k) * g + (k - q) * b,
+        (k - q) * r + (k + q) * g + (c + k) * b,
+    ]
+}
+
+// Linear between the points, wrapping from the last back to the first. None without points.
+pub fn path_position(points: &[[f32; 3]], t: f32) -> Option<Point3<f32>> {
+    if points.is_empty() || !t.is_finite() {
+        return points.first().map(|&p| p.into());
+    }
+    let t = t.rem_euclid(points.len() as f32);
+    let i = (t as usize).min(points.len() - 1);
+    let from = Point3::from(points[i]);
+    let to = Point3::from(points[(i + 1) % points.len()]);
+    Some(from + (to - from) * (t - i as f32))
+}
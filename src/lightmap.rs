@@ -0,0 +1,403 @@
+// Ambient light baked once for static surfaces. Every texel of an entity's lightmap gathers the
+// ambient light of the scene over its hemisphere with rays cast against the other groups, so
+// what is nearby shadows it and bounces some light back, plus the direct light of BakedLights.
+// geo.wgsl reads it in place of the ambient light of the scene, the scene lights stay dynamic.
+use crate::geo_gen::{GeoRenderGroup, Vertex};
+use crate::profiling::profile_scope;
+use crate::raycast::{Ray, RayHit};
+use crate::render_groups::{GroupId, ThreadSafe};
+use crate::scene::SceneUniform;
+use crate::world_space::InstanceTransform;
+use crate::{present, stats, texture, RenderGroup, State, TEXTURE_SAMPLE_COUNT};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector2, Vector3};
+use std::f32::consts::PI;
+use std::num::NonZeroU32;
+use wgpu::{Device, Queue};
+
+// Brightest value a lightmap holds, texels are stored divided by it
+pub const LIGHTMAP_RANGE: f32 = 4.;
+// Rays start this far above the surface so they don't hit it
+const BAKE_BIAS: f32 = 0.05;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BakedLight {
+    // Arriving from infinitely far along `direction`
+    Directional {
+        direction: Vector3<f32>,
+        color: [f32; 3],
+    },
+    // Fading out to nothing at `range`
+    Point {
+        position: Point3<f32>,
+        color: [f32; 3],
+        range: f32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BakeSettings {
+    // Rays over the hemisphere of each texel
+    pub samples: u32,
+    // Share of the light surfaces hit by the rays pass on, a gray albedo
+    pub bounce: f32,
+    // Occluders further away let the sky through
+    pub max_distance: f32,
+    // Lights that only exist in the lightmaps, the scene lights are not baked
+    pub lights: Vec<BakedLight>,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            samples: 32,
+            bounce: 0.4,
+            max_distance: 100.,
+            lights: vec![],
+        }
+    }
+}
+
+// Where a texel lies on the surface, in world space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LightmapTexel {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+// A square texture baked by State::bake_lightmaps, black until then. `uv` is the offset in xy and
+// scale in zw from the second uv set of the mesh to the lightmap, which the triangles may
+// not overlap in. Where they fall outside of it the surface keeps the ambient light of the scene.
+// The first instance of the group is baked and all of them show it.
+pub struct Lightmap {
+    pub(crate) texture: texture::Texture,
+    pub size: u32,
+    pub uv: [f32; 4],
+}
+
+impl Lightmap {
+    pub fn new(device: &Device, size: u32, uv: [f32; 4]) -> Self {
+        let size = size.max(1);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("lightmap"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: TEXTURE_SAMPLE_COUNT,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Self {
+            texture: texture::Texture {
+                texture,
+                view,
+                sampler,
+            },
+            size,
+            uv,
+        }
+    }
+
+    // Row major texels from encode_texel
+    pub(crate) fn write(&self, queue: &Queue, texels: &[[u8; 4]]) {
+        let bytes: &[u8] = bytemuck::cast_slice(texels);
+        stats::count_upload(bytes.len());
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * self.size),
+                rows_per_image: NonZeroU32::new(self.size),
+            },
+            wgpu::Extent3d {
+                width: self.size,
+                height: self.size,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+// The surface under the center of each texel, row major with row 0 at v = 1 like the textures.
+// None where no triangle covers the texel.
+pub fn lightmap_texels(
+    vertices: &[Vertex],
+    indices: &[u32],
+    uv: [f32; 4],
+    transform: &InstanceTransform,
+    size: u32,
+) -> Vec<Option<LightmapTexel>> {
+    let size = size.max(1) as usize;
+    let mut texels = vec![None; size * size];
+    let normal_matrix = transform.normal_matrix();
+    let to_texels = |v: &Vertex| {
+        let [u, v] = v.tex_coords2;
+        Vector2::new(
+            (u * uv[2] + uv[0]) * size as f32,
+            (1. - (v * uv[3] + uv[1])) * size as f32,
+        )
+    };
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| &vertices[triangle[i] as usize]);
+        let [pa, pb, pc] = [a, b, c].map(to_texels);
+        let area = cross(pb - pa, pc - pa);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+        let min = pa.zip(pb, f32::min).zip(pc, f32::min);
+        let max = pa.zip(pb, f32::max).zip(pc, f32::max);
+        let x_range = (min.x.floor().max(0.) as usize)..(max.x.ceil().max(0.) as usize).min(size);
+        let y_range = (min.y.floor().max(0.) as usize)..(max.y.ceil().max(0.) as usize).min(size);
+        for y in y_range {
+            for x in x_range.clone() {
+                let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let wa = cross(pb - p, pc - p) / area;
+                let wb = cross(pc - p, pa - p) / area;
+                let wc = 1. - wa - wb;
+                if wa < -1e-4 || wb < -1e-4 || wc < -1e-4 {
+                    continue;
+                }
+                let local = Vector3::from(a.position) * wa
+                    + Vector3::from(b.position) * wb
+                    + Vector3::from(c.position) * wc;
+                let normal = Vector3::from(a.normal) * wa
+                    + Vector3::from(b.normal) * wb
+                    + Vector3::from(c.normal) * wc;
+                texels[y * size + x] = Some(LightmapTexel {
+                    position: Point3::from_vec(transform.transform_point(local)),
+                    normal: (normal_matrix * normal).normalize(),
+                });
+            }
+        }
+    }
+    texels
+}
+
+fn cross(a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+// Cosine weighted directions around +z, stratified so few samples cover the hemisphere evenly
+pub fn hemisphere_samples(count: u32) -> Vec<Vector3<f32>> {
+    let count = count.max(1);
+    (0..count)
+        .map(|i| {
+            let u = (i as f32 + 0.5) / count as f32;
+            // Van der Corput sequence, the second coordinate of a Hammersley point set
+            let v = i.reverse_bits() as f32 / 2f32.powi(32);
+            let r = u.sqrt();
+            let phi = 2. * PI * v;
+            Vector3::new(r * phi.cos(), r * phi.sin(), (1. - u).sqrt())
+        })
+        .collect()
+}
+
+// Light arriving straight from the baked lights, shadowed by whatever `cast` hits first
+pub fn direct_light<F: Fn(&Ray) -> Option<RayHit>>(
+    position: Point3<f32>,
+    normal: Vector3<f32>,
+    lights: &[BakedLight],
+    cast: &F,
+) -> Vector3<f32> {
+    let origin = position + normal * BAKE_BIAS;
+    let mut light = Vector3::new(0., 0., 0.);
+    for baked in lights {
+        let (dir, distance, color) = match *baked {
+            BakedLight::Directional { direction, color } => {
+                (-direction.normalize(), f32::INFINITY, Vector3::from(color))
+            }
+            BakedLight::Point {
+                position: light_position,
+                color,
+                range,
+            } => {
+                let offset = light_position - position;
+                let distance = offset.magnitude();
+                let falloff = (1. - distance / range.max(f32::EPSILON)).max(0.).powi(2);
+                (offset / distance, distance, Vector3::from(color) * falloff)
+            }
+        };
+        let cos = normal.dot(dir);
+        if cos <= 0. || !cos.is_finite() || color == Vector3::new(0., 0., 0.) {
+            continue;
+        }
+        let shadowed = cast(&Ray::new(origin, dir)).map_or(false, |hit| hit.distance < distance);
+        if !shadowed {
+            light += color * cos;
+        }
+    }
+    light
+}
+
+// Light reaching the texel, what geo.wgsl multiplies the albedo with in place of the ambient
+// term. Rays leaving the scene see its ambient light, rays hitting something see that surface
+// lit by the ambient light and the baked lights, dimmed by the bounce.
+pub fn bake_texel<F: Fn(&Ray) -> Option<RayHit>>(
+    texel: &LightmapTexel,
+    samples: &[Vector3<f32>],
+    settings: &BakeSettings,
+    scene: &SceneUniform,
+    cast: &F,
+) -> [f32; 3] {
+    let normal = texel.normal;
+    let up = if normal.y.abs() < 0.99 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    let origin = texel.position + normal * BAKE_BIAS;
+    let mut gathered = Vector3::new(0., 0., 0.);
+    for sample in samples {
+        let dir = tangent * sample.x + bitangent * sample.y + normal * sample.z;
+        let hit = cast(&Ray::new(origin, dir)).filter(|hit| hit.distance <= settings.max_distance);
+        gathered += match hit {
+            Some(hit) => {
+                let lit = Vector3::from(scene.ambient(hit.normal))
+                    + direct_light(hit.position, hit.normal, &settings.lights, cast);
+                lit * settings.bounce
+            }
+            None => Vector3::from(scene.ambient(dir)),
+        };
+    }
+    let indirect = gathered / samples.len().max(1) as f32;
+    let direct = direct_light(texel.position, normal, &settings.lights, cast);
+    (indirect + direct).into()
+}
+
+// sRGB encoded like the other color textures, which keeps the precision in the dark
+pub fn encode_texel(color: [f32; 3]) -> [u8; 4] {
+    let [r, g, b] = color.map(|c| {
+        let c = (c / LIGHTMAP_RANGE).clamp(0., 1.);
+        (present::encode_srgb(c) * 255.).round() as u8
+    });
+    [r, g, b, 255]
+}
+
+// Copies the nearest covered neighbour into the empty texels bordering covered ones, so linear
+// filtering along the edges of the triangles doesn't pull in black. One ring per pass.
+pub fn dilate<T: Copy>(texels: &mut [Option<T>], size: u32, passes: u32) {
+    let size = size as usize;
+    for _ in 0..passes {
+        let source = texels.to_vec();
+        for y in 0..size {
+            for x in 0..size {
+                if source[y * size + x].is_some() {
+                    continue;
+                }
+                let neighbours = [
+                    (-1, 0),
+                    (1, 0),
+                    (0, -1),
+                    (0, 1),
+                    (-1, -1),
+                    (1, -1),
+                    (-1, 1),
+                    (1, 1),
+                ];
+                texels[y * size + x] = neighbours.iter().find_map(|&(dx, dy)| {
+                    let nx = x as isize + dx;
+                    let ny = y as isize + dy;
+                    let inside =
+                        (0..size as isize).contains(&nx) && (0..size as isize).contains(&ny);
+                    inside
+                        .then(|| source[ny as usize * size + nx as usize])
+                        .flatten()
+                });
+            }
+        }
+    }
+}
+
+// Bakes every texel, on the rayon pool except on wasm
+fn bake<F: Fn(&Ray) -> Option<RayHit> + ThreadSafe>(
+    texels: &[Option<LightmapTexel>],
+    size: u32,
+    settings: &BakeSettings,
+    scene: &SceneUniform,
+    cast: &F,
+) -> Vec<[u8; 4]> {
+    let samples = hemisphere_samples(settings.samples);
+    let bake_one = |texel: &Option<LightmapTexel>| {
+        texel
+            .as_ref()
+            .map(|texel| bake_texel(texel, &samples, settings, scene, cast))
+    };
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let mut light: Vec<_> = texels.iter().map(bake_one).collect();
+        } else {
+            use rayon::prelude::*;
+            let mut light: Vec<_> = texels.par_iter().map(bake_one).collect();
+        }
+    }
+    dilate(&mut light, size, 2);
+    light
+        .into_iter()
+        .map(|color| encode_texel(color.unwrap_or([0.; 3])))
+        .collect()
+}
+
+impl State {
+    // Bakes the lightmap of every mesh group created with one against the groups that don't
+    // animate, with the ambient light the scene has now. Bake again after moving what they
+    // see or changing the ambient light. The number of lightmaps baked.
+    pub fn bake_lightmaps(&mut self, settings: &BakeSettings) -> usize {
+        profile_scope!("bake lightmaps");
+        let baked: Vec<(GroupId, Vec<[u8; 4]>)> = {
+            let occluders: Vec<&dyn RenderGroup> = self
+                .render_groups
+                .iter()
+                .filter(|(id, group)| *id != self.debug_lines && !group.is_animating())
+                .map(|(_, group)| group)
+                .collect();
+            let cast = |ray: &Ray| {
+                occluders
+                    .iter()
+                    .filter_map(|group| group.raycast(ray))
+                    .min_by(|a, b| {
+                        a.distance
+                            .partial_cmp(&b.distance)
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+            };
+            let scene = &self.scene.uniform;
+            self.render_groups
+                .iter()
+                .filter_map(|(id, _)| {
+                    let group = self.render_groups.typed::<GeoRenderGroup>(id)?;
+                    let lightmap = group.entity.lightmap.as_ref()?;
+                    let transform = group.transform(0)?;
+                    let mesh = group.entity.obj.mesh_data();
+                    let texels = lightmap_texels(
+                        &mesh.vertex_data,
+                        &mesh.index_data,
+                        lightmap.uv,
+                        &transform,
+                        lightmap.size,
+                    );
+                    Some((id, bake(&texels, lightmap.size, settings, scene, &cast)))
+                })
+                .collect()
+        };
+        for (id, texels) in &baked {
+            if let Some(group) = self.render_groups.typed_mut::<GeoRenderGroup>(*id) {
+                group.write_lightmap(&self.queue, texels);
+            }
+        }
+        self.request_redraw();
+        baked.len()
+    }
+}
@@ -0,0 +1,101 @@
+// Lookup tables of the rectangle area lights. A linearly transformed cosine is a clamped cosine
+// stretched by a 3x3 matrix, close enough to the GGX lobe that the light of a polygon is the
+// integral of a cosine over the transformed polygon, which has a closed form. The matrices
+// and lobe magnitudes are fitted offline by src/bin/fit_ltc.rs and sampled by roughness and
+// view angle.
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupEntry, BindGroupLayoutEntry, Device, Queue, Sampler, TextureView};
+
+const LTC_SIZE: u32 = 64;
+const LTC_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Two tables of four f16 per entry, the layout is described in fit_ltc.rs
+static LTC_TABLES: &[u8] = include_bytes!("ltc.bin");
+
+pub(crate) struct LtcTables {
+    // Non-zero entries of the inverse matrix
+    matrix_view: TextureView,
+    // Magnitude and Fresnel weight of the lobe
+    magnitude_view: TextureView,
+    sampler: Sampler,
+}
+
+impl LtcTables {
+    pub(crate) fn new(device: &Device, queue: &Queue) -> Self {
+        let (matrices, magnitudes) = LTC_TABLES.split_at(LTC_TABLES.len() / 2);
+        let table = |label, data: &[u8]| {
+            device
+                .create_texture_with_data(
+                    queue,
+                    &wgpu::TextureDescriptor {
+                        label: Some(label),
+                        size: wgpu::Extent3d {
+                            width: LTC_SIZE,
+                            height: LTC_SIZE,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: LTC_FORMAT,
+                        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    },
+                    data,
+                )
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("LTC"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            matrix_view: table("LTC matrices", matrices),
+            magnitude_view: table("LTC magnitudes", magnitudes),
+            sampler,
+        }
+    }
+
+    // Bindings 5 to 7 of the light group
+    pub(crate) fn layout_entries() -> [BindGroupLayoutEntry; 3] {
+        let table = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        [
+            table(5),
+            table(6),
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    pub(crate) fn bind_group_entries(&self) -> [BindGroupEntry; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&self.matrix_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&self.magnitude_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+}
@@ -0,0 +1,136 @@
+use crate::camera::{Camera, CameraView, Projection};
+use crate::light::LightRenderGroup;
+use crate::render_target::RenderTarget;
+use crate::scene::Scene;
+use crate::GroupId;
+use cgmath::{Rad, Vector3};
+use std::borrow::Cow;
+use wgpu::{
+    BindGroup, CommandEncoder, Device, Queue, RenderPipeline, SurfaceConfiguration, TextureView,
+};
+
+// Texture size and distance from the window corner, in pixels
+const SIZE: u32 = 256;
+const MARGIN: u32 = 16;
+// World units from the center of the map to its edge
+const RANGE: f32 = 120.0;
+// The map camera floats this high above the main camera and sees as far below it
+const HEIGHT: f32 = 400.0;
+
+// Top-down orthographic view following the main camera, drawn into the top right corner of
+// the window. The map turns with the camera so forward is always up.
+pub struct Minimap {
+    target: RenderTarget,
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    pub(crate) visible: bool,
+}
+
+impl Minimap {
+    pub fn new(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        light_render_group: &LightRenderGroup,
+        scene: &Scene,
+        visible: bool,
+    ) -> Self {
+        let camera = Camera::new(
+            CameraView::top_down((0.0, HEIGHT, 0.0), Rad(0.0)),
+            Projection::orthographic(SIZE, SIZE, RANGE, 1.0, HEIGHT * 2.0),
+            device,
+        );
+        let target = RenderTarget::with_camera(device, config, light_render_group, scene, camera);
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Minimap Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("minimap.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Minimap Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[config.format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let color = &target.color_texture;
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&color.sampler),
+                },
+            ],
+            label: Some("Minimap Bind Group"),
+        });
+        Self {
+            target,
+            pipeline,
+            bind_group,
+            visible,
+        }
+    }
+
+    // Left out of the map, e.g. the skybox which has no meaning without a perspective
+    pub fn hide(&mut self, group: GroupId) {
+        self.target.hide(group);
+    }
+
+    pub fn update(&mut self, queue: &Queue, view: &CameraView) {
+        if !self.visible {
+            return;
+        }
+        let position = view.position + Vector3::unit_y() * HEIGHT;
+        self.target.camera.view = CameraView::top_down(position, view.yaw());
+        self.target.update(queue);
+    }
+
+    // Rendered with the other render targets
+    pub(crate) fn target(&self) -> Option<&RenderTarget> {
+        Some(&self.target).filter(|_| self.visible)
+    }
+
+    // Call after the scene was drawn onto `view`, skipped while the window is too small
+    pub fn composite(
+        &self,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        if !self.visible || width < SIZE + 2 * MARGIN || height < SIZE + 2 * MARGIN {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Minimap Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        let x = (width - SIZE - MARGIN) as f32;
+        render_pass.set_viewport(x, MARGIN as f32, SIZE as f32, SIZE as f32, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,42 @@
+// Draws the minimap texture as a disc with a rim and a marker for the camera in the middle,
+// into the viewport set in minimap.rs
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    var v_out: VertexOutput;
+    v_out.position = vec4<f32>(x, y, 0.0, 1.0);
+    v_out.tex_coords = vec2<f32>((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+    return v_out;
+}
+
+@group(0) @binding(0)
+var t_map: texture_2d<f32>;
+@group(0) @binding(1)
+var s_map: sampler;
+
+@fragment
+fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_map, s_map, f_in.tex_coords);
+    // -1 to 1 across the viewport, y down
+    let p = f_in.tex_coords * 2.0 - 1.0;
+    let radius = length(p);
+    if (radius > 1.0) {
+        discard;
+    }
+    if (radius > 0.96) {
+        return vec4<f32>(0.9, 0.9, 0.9, 1.0);
+    }
+    // Triangle pointing up, the way the camera looks
+    let along = (p.y + 0.08) / 0.13;
+    if (along >= 0.0 && along <= 1.0 && abs(p.x) <= along * 0.05) {
+        return vec4<f32>(1.0, 0.85, 0.1, 1.0);
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
@@ -0,0 +1,158 @@
+// Fills the lower mips of a texture, each level downsampled from the one above it by the blit
+// shader. Pipelines are created once per format and shared by every texture. Sampling an sRGB
+// view and rendering into one goes through linear, so sRGB textures filter correctly too.
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use wgpu::{CommandEncoder, Device, Queue, RenderPipeline, Texture, TextureFormat};
+
+pub struct MipmapGenerator {
+    shader: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    pipelines: HashMap<TextureFormat, RenderPipeline>,
+}
+
+thread_local! {
+    // Textures are uploaded on the render thread, which owns the one device
+    static GENERATOR: RefCell<Option<MipmapGenerator>> = RefCell::new(None);
+}
+
+impl MipmapGenerator {
+    pub fn new(device: &Device) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Mipmap"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("blit.wgsl"))),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("mip"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            shader,
+            sampler,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    // Every array layer on its own, so the faces of a cubemap don't bleed into each other.
+    // The texture needs the RENDER_ATTACHMENT usage.
+    pub fn generate(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture: &Texture,
+        format: TextureFormat,
+        mip_count: u32,
+        layers: u32,
+    ) {
+        let shader = &self.shader;
+        let pipeline = self
+            .pipelines
+            .entry(format)
+            .or_insert_with(|| create_pipeline(device, shader, format));
+        let bind_group_layout = pipeline.get_bind_group_layout(0);
+        for layer in 0..layers {
+            let views = (0..mip_count)
+                .map(|mip| {
+                    texture.create_view(&wgpu::TextureViewDescriptor {
+                        label: Some("mip"),
+                        format: None,
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        aspect: wgpu::TextureAspect::All,
+                        base_mip_level: mip,
+                        mip_level_count: NonZeroU32::new(1),
+                        base_array_layer: layer,
+                        array_layer_count: NonZeroU32::new(1),
+                    })
+                })
+                .collect::<Vec<_>>();
+            for (source, target) in views.iter().zip(&views[1..]) {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                    ],
+                    label: None,
+                });
+                let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mipmap Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: None,
+                });
+                rpass.set_pipeline(pipeline);
+                rpass.set_bind_group(0, &bind_group, &[]);
+                rpass.draw(0..3, 0..1);
+            }
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    shader: &wgpu::ShaderModule,
+    format: TextureFormat,
+) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("blit"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Submits right away, with the generator of the render thread created on first use
+pub(crate) fn generate_mipmaps(
+    device: &Device,
+    queue: &Queue,
+    texture: &Texture,
+    format: TextureFormat,
+    mip_count: u32,
+    layers: u32,
+) {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mipmap encoder"),
+    });
+    GENERATOR.with(|generator| {
+        generator
+            .borrow_mut()
+            .get_or_insert_with(|| MipmapGenerator::new(device))
+            .generate(device, &mut encoder, texture, format, mip_count, layers);
+    });
+    queue.submit(Some(encoder.finish()));
+}
@@ -1,25 +1,163 @@
-use std::cell::RefCell;
+use cgmath::{EuclideanSpace, Point3};
 use std::default::Default;
+use std::num::NonZeroU32;
 use std::ops::Range;
-use std::rc::Rc;
+use std::time::Duration;
 use wgpu::util::DeviceExt;
 use wgpu::{
-    BindGroup, BindGroupLayout, Buffer, Device, RenderPass, RenderPipeline, SurfaceConfiguration,
+    BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, Buffer, Device, Queue,
+    RenderPass, RenderPipeline, SurfaceConfiguration,
 };
 
-use crate::geo_gen::Vertex;
+use crate::bounds::BoundingSphere;
+use crate::deform::{self, MeshDeform};
+use crate::geo_gen::{MeshData, Vertex};
+use crate::imposter::Imposter;
+use crate::morph::MeshMorph;
+use crate::scene::Scene;
+use crate::skinning::{Influence, MeshSkin, ModelSkeleton, Skeleton};
+use crate::stats::DrawStats;
+use crate::tint::Tint;
+use crate::world_space::InstanceTransform;
 use crate::{
-    texture, uniform_desc, world_space, Camera, LightRenderGroup, RenderGroup, ShadowPass,
-    MULTI_SAMPLE, PRIMITIVE,
+    multi_sample, primitive, probes, texture, uniform_desc, world_space, Camera, LightRenderGroup,
+    RenderGroup, ShadowPass, TEXTURE_SAMPLE_COUNT,
 };
 
+// Other sampled textures of the fragment shader, the emissive one, the shadow maps and the
+// reflection probes, which the binding array has to leave room for
+const RESERVED_TEXTURES: u32 = 4;
+
+// Lets a model bind all its diffuse textures at once and pick the one of each mesh with a push
+// constant, instead of switching bind groups between meshes
+pub(crate) fn bindless_features() -> wgpu::Features {
+    wgpu::Features::TEXTURE_BINDING_ARRAY | wgpu::Features::PUSH_CONSTANTS
+}
+
+// Without `bindless_count` every material binds its own textures and uniform, with it they are
+// arrays of that many indexed by the push constant. `deform_group` is the group of the deform
+// bindings for the meshes moved in the vertex shader.
+fn model_shader_source(bindless_count: Option<usize>, deform_group: Option<u32>) -> String {
+    let source = probes::shader_source(include_str!("shader.wgsl"));
+    let source = deform::shader_source(&source, deform_group);
+    match bindless_count {
+        None => source
+            .replace("MATERIAL_TEXTURE", "texture_2d<f32>")
+            .replace("MATERIAL_UNIFORMS", "MaterialUniform")
+            .replace("MATERIAL_INDEX", ""),
+        Some(count) => source
+            .replace(
+                "// MATERIAL_INDEX_DECLARATION",
+                "struct MaterialIndex { index: u32 };\n\
+                 var<push_constant> material_index: MaterialIndex;",
+            )
+            .replace(
+                "MATERIAL_TEXTURE",
+                &format!("binding_array<texture_2d<f32>, {}>", count),
+            )
+            .replace(
+                "MATERIAL_UNIFORMS",
+                &format!("array<MaterialUniform, {}>", count),
+            )
+            .replace("MATERIAL_INDEX", "[material_index.index]"),
+    }
+}
+
+// Texture::desc followed by the specular, shininess and dissolve maps and the MaterialUniform.
+// `count` turns the textures of the material into binding arrays, the emissive one is shared.
+pub(crate) fn material_layout_entries(count: Option<NonZeroU32>) -> Vec<BindGroupLayoutEntry> {
+    let mut entries = texture::Texture::desc().entries.to_vec();
+    entries[0].count = count;
+    let map = |binding| BindGroupLayoutEntry {
+        binding,
+        count,
+        ..entries[0]
+    };
+    let maps = [4, 5, 6].map(map);
+    entries.extend(maps);
+    entries.push(BindGroupLayoutEntry {
+        binding: 7,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    });
+    entries
+}
+
+// The entries of material_layout_entries(None): the diffuse, specular, shininess and dissolve
+// maps, the first with its sampler, and the uniform of one material
+pub(crate) fn material_entries<'a>(
+    [diffuse, specular, shininess, dissolve]: [&'a texture::Texture; 4],
+    uniform: &'a Buffer,
+    tint: &'a Tint,
+    emissive: &'a texture::Texture,
+) -> Vec<BindGroupEntry<'a>> {
+    vec![
+        BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&diffuse.view),
+        },
+        BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(&diffuse.sampler),
+        },
+        BindGroupEntry {
+            binding: 2,
+            resource: tint.buffer.as_entire_binding(),
+        },
+        BindGroupEntry {
+            binding: 3,
+            resource: wgpu::BindingResource::TextureView(&emissive.view),
+        },
+        BindGroupEntry {
+            binding: 4,
+            resource: wgpu::BindingResource::TextureView(&specular.view),
+        },
+        BindGroupEntry {
+            binding: 5,
+            resource: wgpu::BindingResource::TextureView(&shininess.view),
+        },
+        BindGroupEntry {
+            binding: 6,
+            resource: wgpu::BindingResource::TextureView(&dissolve.view),
+        },
+        BindGroupEntry {
+            binding: 7,
+            resource: uniform.as_entire_binding(),
+        },
+    ]
+}
+
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
+    // map_Ks, map_Ns and map_d, Model::white_texture stands in for the missing ones
+    pub specular_texture: Option<texture::Texture>,
+    pub shininess_texture: Option<texture::Texture>,
+    pub dissolve_texture: Option<texture::Texture>,
+    // Drawn without back-face culling, set before the model is added to the scene
+    pub double_sided: bool,
     pub bind_group: wgpu::BindGroup,
     pub uniform_bind_group: MaterialGroup,
 }
 
+impl Material {
+    // For material_entries, `white` in place of the missing maps
+    fn maps<'a>(&'a self, white: &'a texture::Texture) -> [&'a texture::Texture; 4] {
+        [
+            Some(&self.diffuse_texture),
+            self.specular_texture.as_ref(),
+            self.shininess_texture.as_ref(),
+            self.dissolve_texture.as_ref(),
+        ]
+        .map(|map| map.unwrap_or(white))
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct MaterialUniform {
@@ -29,8 +167,12 @@ pub struct MaterialUniform {
     _padding1: f32,
     pub specular: [f32; 3],
     _padding2: f32,
+    pub emissive: [f32; 3],
+    _padding4: f32,
     pub shininess: f32,
-    _padding3: [f32; 3],
+    // How much of the environment the surface mirrors, the tint reflectivity raises it
+    pub reflectivity: f32,
+    _padding3: [f32; 2],
 }
 
 impl Default for MaterialUniform {
@@ -39,11 +181,14 @@ impl Default for MaterialUniform {
             ambient: [1.0; 3],
             diffuse: [1.0; 3],
             specular: [1.0; 3],
+            emissive: [0.0; 3],
             shininess: 32.0,
+            reflectivity: 0.0,
             _padding0: 0.,
             _padding1: 0.,
             _padding2: 0.,
-            _padding3: [0.; 3],
+            _padding3: [0.; 2],
+            _padding4: 0.,
         }
     }
 }
@@ -58,6 +203,20 @@ impl MaterialUniform {
             ..Default::default()
         }
     }
+
+    // Ke of the mtl file
+    pub fn with_emissive(self, emissive: [f32; 3]) -> Self {
+        Self { emissive, ..self }
+    }
+
+    // Pm of the mtl file
+    pub fn with_reflectivity(self, reflectivity: f32) -> Self {
+        Self {
+            reflectivity,
+            ..self
+        }
+    }
+
     pub fn create_buffer_and_bindgroup(self, device: &Device) -> MaterialGroup {
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light VB"),
@@ -85,7 +244,7 @@ impl MaterialUniform {
 
 pub struct MaterialGroup {
     uniform: MaterialUniform,
-    buffer: Buffer,
+    pub(crate) buffer: Buffer,
     bind_group_layout: BindGroupLayout,
     bind_group: BindGroup,
 }
@@ -96,118 +255,641 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    pub bounds: BoundingSphere,
+    // Simplified copies, index i is lod i + 1
+    pub lods: Vec<MeshLod>,
+    // Blend shapes, such meshes have no lods
+    pub(crate) morph: Option<MeshMorph>,
+    // Joint influences, skinned after the morph targets. Such meshes have no lods either.
+    pub(crate) skin: Option<MeshSkin>,
+    // The morph targets and skin in the vertex shader, None where they are moved on the CPU
+    pub(crate) deform: Option<MeshDeform>,
+}
+
+impl Mesh {
+    // Buffers and index count drawn at `lod`, past the coarsest level stays on it
+    fn buffers(&self, lod: usize) -> (&Buffer, &Buffer, u32) {
+        let level = match lod.min(self.lods.len()).checked_sub(1) {
+            Some(i) => &self.lods[i],
+            None => {
+                let skinned = self
+                    .skin
+                    .as_ref()
+                    .and_then(|skin| skin.vertex_buffer.as_ref());
+                let morphed = self
+                    .morph
+                    .as_ref()
+                    .and_then(|morph| morph.vertex_buffer.as_ref());
+                let vertex_buffer = skinned.or(morphed).unwrap_or(&self.vertex_buffer);
+                return (vertex_buffer, &self.index_buffer, self.num_elements);
+            }
+        };
+        (
+            &level.vertex_buffer,
+            &level.index_buffer,
+            level.num_elements,
+        )
+    }
+}
+
+pub struct MeshLod {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_elements: u32,
+}
+
+impl MeshLod {
+    pub fn new(device: &Device, label: &str, data: &MeshData) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", label)),
+            contents: bytemuck::cast_slice(&data.vertex_data),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", label)),
+            contents: bytemuck::cast_slice(&data.index_data),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Self {
+            vertex_buffer,
+            index_buffer,
+            num_elements: data.index_data.len() as u32,
+        }
+    }
 }
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
     pub texture_bind_group_layout: BindGroupLayout,
+    // Black, bound in the emissive slot of every material
+    pub emissive_texture: texture::Texture,
+    // Bound for the maps a material doesn't have
+    pub white_texture: texture::Texture,
+    // Shared by the bind groups of every material
+    pub tint: Tint,
+    // Poses the skinned meshes, taken by ModelRenderGroup::new
+    pub(crate) skeleton: Option<Skeleton>,
 }
 
 pub(crate) struct ModelRenderGroup {
     model: Model,
     instances: world_space::Instances,
     render_pipeline: RenderPipeline,
+    // For the meshes of double-sided materials, only created when there are any
+    double_sided_pipeline: Option<RenderPipeline>,
+    // Single and double-sided, for the meshes with a MeshDeform
+    deformed_pipelines: Option<(RenderPipeline, Option<RenderPipeline>)>,
+    // Every material texture and uniform with the shared tint, None binds the groups of the
+    // materials
+    bindless: Option<BindGroup>,
+    lod: usize,
+    // Camera distance per level of detail, 0 keeps full detail
+    lod_distance: f32,
+    imposter: Option<Imposter>,
+    // Beyond it the imposter is drawn instead of the meshes
+    imposter_distance: f32,
+    imposter_visible: bool,
+    skeleton: Option<ModelSkeleton>,
 }
 
 impl ModelRenderGroup {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        model: Model,
+        mut model: Model,
         instances: world_space::Instances,
         device: &Device,
         camera: &Camera,
         config: &SurfaceConfiguration,
         light_render_group: &LightRenderGroup,
         shadow_pass: &ShadowPass,
-    ) -> Rc<RefCell<Self>> {
+    ) -> Self {
+        let vertex_deform = deform::vertex_deform();
+        let skeleton = model
+            .skeleton
+            .take()
+            .map(|skeleton| ModelSkeleton::new(device, skeleton, vertex_deform));
+        let deformed = vertex_deform
+            && model
+                .meshes
+                .iter()
+                .any(|mesh| mesh.morph.is_some() || mesh.skin.is_some());
+        // The deform bindings share group 2 with the material, all 4 groups are taken
+        let deform_layouts = deformed.then(|| {
+            let mut entries = material_layout_entries(None);
+            entries.extend(deform::layout_entries());
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &entries,
+                label: Some("deformed_material_bind_group_layout"),
+            });
+            (layout, deform::bind_group_layout(device))
+        });
+        let Model {
+            meshes,
+            materials,
+            white_texture,
+            emissive_texture,
+            tint,
+            ..
+        } = &mut model;
+        for mesh in meshes.iter_mut() {
+            if let Some((layout, depth_layout)) = &deform_layouts {
+                let material = &materials[mesh.material];
+                let entries = material_entries(
+                    material.maps(white_texture),
+                    &material.uniform_bind_group.buffer,
+                    tint,
+                    emissive_texture,
+                );
+                let vertex_count = match (&mesh.morph, &mesh.skin) {
+                    (Some(morph), _) => morph.vertex_count(),
+                    (None, Some(skin)) => skin.vertex_count(),
+                    (None, None) => continue,
+                };
+                let skin = mesh.skin.as_mut().zip(skeleton.as_ref());
+                mesh.deform = Some(MeshDeform::new(
+                    device,
+                    &mesh.name,
+                    [layout, depth_layout],
+                    entries,
+                    vertex_count,
+                    mesh.morph.as_mut(),
+                    skin,
+                ));
+                continue;
+            }
+            if let Some(morph) = &mut mesh.morph {
+                morph.upload(device, &mesh.name);
+            }
+            if let (Some(skin), Some(skeleton)) = (&mut mesh.skin, &skeleton) {
+                skin.upload(device, &mesh.name, skeleton);
+            }
+        }
+        // Bindless meshes would need the deform bindings in the bindless group as well
+        let bindless = if deformed {
+            None
+        } else {
+            model.bindless_bind_group(device)
+        };
+        let source = model_shader_source(bindless.as_ref().map(|_| model.materials.len()), None);
         let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
             label: Some("Model Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
         });
-        let render_pipeline_layout =
+        let texture_bind_group_layout = bindless
+            .as_ref()
+            .map_or(&model.texture_bind_group_layout, |(layout, _)| layout);
+        let push_constant_ranges: &[wgpu::PushConstantRange] = if bindless.is_some() {
+            &[wgpu::PushConstantRange {
+                stages: wgpu::ShaderStages::FRAGMENT,
+                range: 0..4,
+            }]
+        } else {
+            &[]
+        };
+        let create_layout = |material_layout: &BindGroupLayout| {
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 bind_group_layouts: &[
                     &camera.camera_bind_group_layout,
                     &light_render_group.light_bind_group_layout,
-                    &model.texture_bind_group_layout,
+                    material_layout,
                     &shadow_pass.shadow_map_bind_group_layout, // &model.materials[0].uniform_bind_group.bind_group_layout,
                 ],
-                push_constant_ranges: &[],
+                push_constant_ranges,
+            })
+        };
+        let render_pipeline_layout = create_layout(texture_bind_group_layout);
+        // Cut out models test and write their own depth, they are left out of the pre-pass
+        let depth_stencil = if model.has_cutout() {
+            texture::Texture::create_depth_state()
+        } else {
+            texture::Texture::create_opaque_depth_state()
+        };
+        let create_pipeline = |layout: &wgpu::PipelineLayout,
+                               shader: &wgpu::ShaderModule,
+                               buffers: &[wgpu::VertexBufferLayout],
+                               double_sided| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Model Render Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[wgpu::ColorTargetState {
+                        format: config.format,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent::REPLACE,
+                            alpha: wgpu::BlendComponent::REPLACE,
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                primitive: primitive(double_sided),
+                depth_stencil: depth_stencil.clone(),
+                multisample: multi_sample(),
+                // If the pipeline will be used with a multiview render pass, this
+                // indicates how many array layers the attachments will have.
+                multiview: None,
+            })
+        };
+        let double_sided = model.materials.iter().any(|material| material.double_sided);
+        let buffers = [world_space::desc(), Vertex::desc()];
+        let render_pipeline = create_pipeline(&render_pipeline_layout, &shader, &buffers, false);
+        let double_sided_pipeline =
+            double_sided.then(|| create_pipeline(&render_pipeline_layout, &shader, &buffers, true));
+        let deformed_pipelines = deform_layouts.map(|(layout, _)| {
+            let layout = create_layout(&layout);
+            let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Deformed Model Shader"),
+                source: wgpu::ShaderSource::Wgsl(model_shader_source(None, Some(2)).into()),
             });
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Model Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[world_space::desc(), Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                }],
-            }),
-            primitive: PRIMITIVE,
-            depth_stencil: texture::Texture::create_depth_state(),
-            multisample: MULTI_SAMPLE,
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
+            let buffers = [world_space::desc(), Vertex::desc(), Influence::desc()];
+            (
+                create_pipeline(&layout, &shader, &buffers, false),
+                double_sided.then(|| create_pipeline(&layout, &shader, &buffers, true)),
+            )
         });
-        Rc::new(RefCell::new(Self {
+        Self {
             model,
             instances,
             render_pipeline,
-        }))
+            double_sided_pipeline,
+            deformed_pipelines,
+            bindless: bindless.map(|(_, bind_group)| bind_group),
+            lod: 0,
+            lod_distance: 0.,
+            imposter: None,
+            imposter_distance: 0.,
+            imposter_visible: false,
+            skeleton,
+        }
+    }
+
+    // Weights of the morph targets of mesh `mesh`, in place of its animation. False if it has
+    // no morph targets.
+    pub fn set_morph_weights(&mut self, mesh: usize, weights: &[f32]) -> bool {
+        match self
+            .model
+            .meshes
+            .get_mut(mesh)
+            .and_then(|mesh| mesh.morph.as_mut())
+        {
+            Some(morph) => {
+                morph.set_weights(weights);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Bakes the model around its first instance into billboards, drawn for every instance
+    // instead of the meshes while the closest one is more than `distance` from the camera.
+    // Shadows keep coming from the meshes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn bake_imposter(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        config: &SurfaceConfiguration,
+        camera: &Camera,
+        light_render_group: &LightRenderGroup,
+        shadow_pass: &ShadowPass,
+        scene: &Scene,
+        distance: f32,
+    ) {
+        let (bounds, transform) = match (
+            self.model.bounding_sphere(),
+            self.instances.instance_transforms.first(),
+        ) {
+            (Some(bounds), Some(transform)) => (bounds, *transform),
+            _ => return,
+        };
+        let imposter = Imposter::bake(
+            device,
+            queue,
+            config,
+            camera,
+            light_render_group,
+            shadow_pass,
+            scene,
+            self,
+            bounds,
+            &transform,
+        );
+        self.imposter = Some(imposter);
+        self.imposter_distance = distance;
+    }
+
+    // The meshes go one level coarser every `distance` between the camera and the closest
+    // instance, as far as their lods reach
+    pub fn set_lod_distance(&mut self, distance: f32) {
+        self.lod_distance = distance.max(0.);
+        if self.lod_distance == 0. {
+            self.lod = 0;
+        }
     }
 
     fn draw_mesh_instanced<'a, 'b: 'a>(
+        &self,
         mesh: &'b Mesh,
-        material: &'b Material,
+        lod: usize,
+        material: Option<&'b Material>,
         instances: Range<u32>,
         render_pass: &mut RenderPass<'a>,
     ) {
-        render_pass.set_vertex_buffer(1, mesh.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        render_pass.set_bind_group(2, &material.bind_group, &[]);
-        // render_pass.set_bind_group(3, &material.uniform_bind_group.bind_group, &[]);
-        render_pass.draw_indexed(0..mesh.num_elements, 0, instances);
+        let (vertex_buffer, index_buffer, num_elements) = mesh.buffers(lod);
+        render_pass.set_vertex_buffer(1, vertex_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        match (material, &mesh.deform, &self.bindless) {
+            (Some(_), Some(deform), _) => deform.bind(render_pass),
+            (Some(_), None, Some(_)) => {
+                let index = mesh.material as u32;
+                render_pass.set_push_constants(
+                    wgpu::ShaderStages::FRAGMENT,
+                    0,
+                    bytemuck::bytes_of(&index),
+                );
+            }
+            (Some(material), None, None) => {
+                render_pass.set_bind_group(2, &material.bind_group, &[]);
+                // render_pass.set_bind_group(3, &material.uniform_bind_group.bind_group, &[]);
+            }
+            _ => {}
+        }
+        render_pass.draw_indexed(0..num_elements, 0, instances);
+    }
+
+    fn draw<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, lod: usize, shadow_pass: bool) {
+        render_pass.set_vertex_buffer(0, self.instances.instance_buffer.slice(..));
+        if let (false, Some(bind_group)) = (shadow_pass, &self.bindless) {
+            render_pass.set_bind_group(2, bind_group, &[]);
+        }
+        for mesh in &self.model.meshes {
+            // The depth-only passes draw them with render_deformed
+            if shadow_pass && mesh.deform.is_some() {
+                continue;
+            }
+            let material = (!shadow_pass).then(|| &self.model.materials[mesh.material]);
+            if let Some(material) = material {
+                let (single, double) = match (&mesh.deform, &self.deformed_pipelines) {
+                    (Some(_), Some((single, double))) => (single, double),
+                    _ => (&self.render_pipeline, &self.double_sided_pipeline),
+                };
+                let pipeline = match double {
+                    Some(pipeline) if material.double_sided => pipeline,
+                    _ => single,
+                };
+                render_pass.set_pipeline(pipeline);
+            }
+            self.draw_mesh_instanced(
+                mesh,
+                lod,
+                material,
+                self.instances.get_instance_range(),
+                render_pass,
+            );
+        }
+    }
+}
+
+impl Model {
+    fn morphs(&self) -> impl Iterator<Item = &MeshMorph> {
+        self.meshes.iter().filter_map(|mesh| mesh.morph.as_ref())
+    }
+
+    // The dissolve map of a material discards fragments, which the depth pre-pass can't see
+    fn has_cutout(&self) -> bool {
+        self.materials
+            .iter()
+            .any(|material| material.dissolve_texture.is_some())
+    }
+
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.meshes
+            .iter()
+            .map(|m| m.bounds)
+            .reduce(|acc, next| acc.union(&next))
+    }
+
+    // The maps of every material in binding arrays and their uniforms in one buffer, with the
+    // sampler of the first, which is the sampler of every material. None without the features,
+    // when the textures don't fit or with a single material, where there is nothing to save.
+    fn bindless_bind_group(&self, device: &Device) -> Option<(BindGroupLayout, BindGroup)> {
+        let count = self.materials.len() as u32;
+        let max_textures = device.limits().max_sampled_textures_per_shader_stage;
+        if !device.features().contains(bindless_features())
+            || count < 2
+            || 4 * count + RESERVED_TEXTURES > max_textures
+        {
+            return None;
+        }
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &material_layout_entries(NonZeroU32::new(count)),
+            label: Some("bindless_texture_bind_group_layout"),
+        });
+        let views = |map: fn(&Material) -> Option<&texture::Texture>| -> Vec<_> {
+            self.materials
+                .iter()
+                .map(|material| &map(material).unwrap_or(&self.white_texture).view)
+                .collect()
+        };
+        let diffuse = views(|material| Some(&material.diffuse_texture));
+        let specular = views(|material| material.specular_texture.as_ref());
+        let shininess = views(|material| material.shininess_texture.as_ref());
+        let dissolve = views(|material| material.dissolve_texture.as_ref());
+        let uniforms: Vec<_> = self
+            .materials
+            .iter()
+            .map(|material| material.uniform_bind_group.uniform)
+            .collect();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bindless Material Buffer"),
+            contents: bytemuck::cast_slice(&uniforms),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureViewArray(&diffuse),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(
+                        &self.materials[0].diffuse_texture.sampler,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.tint.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.emissive_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureViewArray(&specular),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureViewArray(&shininess),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureViewArray(&dissolve),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bindless_diffuse_bind_group"),
+        });
+        Some((layout, bind_group))
     }
 }
 
 impl RenderGroup for ModelRenderGroup {
-    fn render<'a, 'b: 'a>(&'b self, render_pass: &mut wgpu::RenderPass<'a>, shadow_pass: bool) {
-        if !shadow_pass {
-            render_pass.set_pipeline(&self.render_pipeline);
+    fn update(&mut self, dt: Duration, queue: &Queue) {
+        self.model.tint.update(dt, queue);
+        let moved = self
+            .skeleton
+            .as_mut()
+            .map_or(false, |skeleton| skeleton.update(dt, queue));
+        for mesh in &mut self.model.meshes {
+            let morphed = mesh
+                .morph
+                .as_mut()
+                .map_or(false, |morph| morph.update(dt, queue));
+            if let (true, Some(morph), Some(deform)) = (morphed, &mesh.morph, &mesh.deform) {
+                deform.set_weights(queue, morph.weights());
+            }
+            if let (Some(skin), Some(skeleton)) = (&mesh.skin, &self.skeleton) {
+                if moved || morphed {
+                    skin.update(queue, skeleton, mesh.morph.as_ref());
+                }
+            }
+        }
+    }
+
+    fn transform(&self, instance: usize) -> Option<InstanceTransform> {
+        self.instances.instance_transforms.get(instance).copied()
+    }
+
+    fn set_transform(&mut self, instance: usize, transform: InstanceTransform, queue: &Queue) {
+        if let Some(old) = self.instances.instance_transforms.get_mut(instance) {
+            *old = transform;
+            self.instances.update_buffer(queue);
+        }
+    }
+
+    fn set_hidden(&mut self, instance: usize, hidden: bool, queue: &Queue) {
+        self.instances.set_hidden(instance, hidden, queue);
+    }
+
+    fn tint_mut(&mut self) -> Option<&mut Tint> {
+        Some(&mut self.model.tint)
+    }
+
+    fn select_lod(&mut self, eye: Point3<f32>) {
+        let closest = self.instances.closest_distance(eye.to_vec());
+        self.imposter_visible = self.imposter.is_some()
+            && self.imposter_distance > 0.
+            && closest.map_or(false, |closest| closest > self.imposter_distance);
+        if self.lod_distance <= 0. {
+            return;
+        }
+        if let Some(closest) = closest {
+            self.lod = (closest / self.lod_distance) as usize;
+        }
+    }
+
+    fn is_animating(&self) -> bool {
+        self.model.tint.is_animating()
+            || self.model.morphs().any(MeshMorph::is_animating)
+            || self
+                .skeleton
+                .as_ref()
+                .map_or(false, ModelSkeleton::is_animating)
+    }
+
+    // The pre-pass can't switch per mesh, the back faces of closed single-sided meshes are
+    // behind their front faces anyway
+    fn double_sided(&self) -> bool {
+        self.double_sided_pipeline.is_some()
+    }
+
+    fn cutout(&self) -> bool {
+        self.model.has_cutout()
+    }
+
+    fn deformed(&self) -> bool {
+        self.deformed_pipelines.is_some()
+    }
+
+    // Full detail, deformed meshes have no lods
+    fn render_deformed<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, shadow_map: bool) {
+        // The billboards write their own depth in the main pass
+        if self.imposter_visible && !shadow_map {
+            return;
         }
         render_pass.set_vertex_buffer(0, self.instances.instance_buffer.slice(..));
         for mesh in &self.model.meshes {
-            if shadow_pass {
-                render_pass.set_vertex_buffer(1, mesh.vertex_buffer.slice(..));
-                render_pass
-                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                render_pass.draw_indexed(
-                    0..mesh.num_elements,
-                    0,
-                    self.instances.get_instance_range(),
-                );
-            } else {
-                let material = &self.model.materials[mesh.material];
-                Self::draw_mesh_instanced(
-                    mesh,
-                    material,
-                    self.instances.get_instance_range(),
+            if let Some(deform) = &mesh.deform {
+                deform.render_depth(
                     render_pass,
+                    &mesh.vertex_buffer,
+                    &mesh.index_buffer,
+                    mesh.num_elements,
+                    self.instances.get_instance_range(),
                 );
             }
         }
     }
+
+    fn draw_stats(&self, shadow_pass: bool) -> DrawStats {
+        let instances = self.instances.instance_transforms.len() as u32;
+        if self.imposter_visible && !shadow_pass {
+            return DrawStats::new(1, instances);
+        }
+        let meshes = self.model.meshes.len() as u32;
+        DrawStats::new(meshes, meshes * instances)
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.instances
+            .bounding_sphere(&self.model.bounding_sphere()?)
+    }
+
+    fn instance_bounding_sphere(&self, instance: usize) -> Option<BoundingSphere> {
+        self.instances
+            .instance_bounding_sphere(instance, &self.model.bounding_sphere()?)
+    }
+
+    fn render<'a, 'b: 'a>(&'b self, render_pass: &mut wgpu::RenderPass<'a>, shadow_pass: bool) {
+        match &self.imposter {
+            // Only the depth pre-pass gets here with shadow_pass, the billboards write their
+            // own depth
+            Some(_) if self.imposter_visible && shadow_pass => {}
+            Some(imposter) if self.imposter_visible => imposter.render(
+                render_pass,
+                &self.instances.instance_buffer,
+                self.instances.instance_transforms.len() as u32,
+            ),
+            _ => self.draw(render_pass, self.lod, shadow_pass),
+        }
+    }
+
+    // Never finer than what the camera sees
+    fn render_shadow<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, lod: usize) {
+        self.draw(render_pass, self.lod.max(lod), true);
+    }
 }
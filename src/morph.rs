@@ -0,0 +1,224 @@
+// Blend shapes of model meshes, loaded from the morph targets of glTF files. The offsets of every
+// target stay on the GPU in a storage buffer and deform of deform.wgsl adds them up with the
+// current weights in the vertex shaders. Every pass calls it, so the shadow maps and the depth
+// pre-pass see the same shape as the main pass. Without storage buffers in vertex shaders
+// blend_morph_targets does the same on the CPU and the vertices are uploaded whenever the
+// weights change.
+use crate::geo_gen::Vertex;
+use crate::stats;
+use cgmath::{InnerSpace, Vector3};
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+use wgpu::{Buffer, Device, Queue};
+
+// Offsets from the rest pose, one per vertex of the mesh
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphTarget {
+    pub positions: Vec<[f32; 3]>,
+    // Empty when the target leaves the normals alone
+    pub normals: Vec<[f32; 3]>,
+}
+
+// Weights of every target at each key time, played in a loop
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphAnimation {
+    pub times: Vec<f32>,
+    pub weights: Vec<Vec<f32>>,
+}
+
+impl MorphAnimation {
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.)
+    }
+
+    // Linear between the keys around `time`, held before the first one
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let duration = self.duration();
+        let time = if duration > 0. {
+            time.rem_euclid(duration)
+        } else {
+            0.
+        };
+        let next = self.times.iter().position(|&key| key > time);
+        match next {
+            Some(0) => self.weights[0].clone(),
+            Some(next) => {
+                let (start, end) = (self.times[next - 1], self.times[next]);
+                let t = (time - start) / (end - start);
+                let (a, b) = (&self.weights[next - 1], &self.weights[next]);
+                a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect()
+            }
+            None => self.weights.last().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+// The targets of a mesh with the weights it starts at
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphTargets {
+    pub targets: Vec<MorphTarget>,
+    pub weights: Vec<f32>,
+    // Drives the weights unless they are set by hand
+    pub animation: Option<MorphAnimation>,
+}
+
+impl MorphTargets {
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    // Farthest any vertex moves with every weight at 1, added to the bounds of the mesh
+    pub fn reach(&self) -> f32 {
+        self.targets
+            .iter()
+            .map(|target| {
+                target
+                    .positions
+                    .iter()
+                    .map(|&delta| Vector3::from(delta).magnitude())
+                    .fold(0., f32::max)
+            })
+            .sum()
+    }
+}
+
+// The rest pose plus every target scaled by its weight, normals renormalized. Mirrors deform
+// in deform.wgsl, missing weights count as 0.
+pub fn blend_morph_targets(
+    rest: &[Vertex],
+    targets: &[MorphTarget],
+    weights: &[f32],
+) -> Vec<Vertex> {
+    let mut vertices = rest.to_vec();
+    for (target, &weight) in targets.iter().zip(weights) {
+        if weight == 0. {
+            continue;
+        }
+        for (vertex, delta) in vertices.iter_mut().zip(&target.positions) {
+            let position = Vector3::from(vertex.position) + Vector3::from(*delta) * weight;
+            vertex.position = position.into();
+        }
+        for (vertex, delta) in vertices.iter_mut().zip(&target.normals) {
+            let normal = Vector3::from(vertex.normal) + Vector3::from(*delta) * weight;
+            vertex.normal = normal.into();
+        }
+    }
+    for vertex in &mut vertices {
+        let normal = Vector3::from(vertex.normal);
+        if normal.magnitude2() > 0. {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+    vertices
+}
+
+// The morph targets of one mesh of a model. Drawn at rest until upload, which
+// ModelRenderGroup::new calls on the CPU path. The GPU path hands the targets to MeshDeform.
+pub(crate) struct MeshMorph {
+    // Only kept on the CPU path
+    rest: Vec<Vertex>,
+    targets: MorphTargets,
+    time: f32,
+    // The weights changed since they were last blended or uploaded
+    changed: bool,
+    // The blended vertices, drawn instead of the vertex buffer of the mesh
+    pub vertex_buffer: Option<Buffer>,
+}
+
+impl MeshMorph {
+    pub fn new(rest: Vec<Vertex>, mut targets: MorphTargets) -> Self {
+        targets.weights.resize(targets.targets.len(), 0.);
+        Self {
+            rest,
+            targets,
+            time: 0.,
+            changed: false,
+            vertex_buffer: None,
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.rest.len()
+    }
+
+    // Position then normal offset of every vertex of the first target, then of the next one, as
+    // deform.wgsl reads them
+    pub fn take_deltas(&mut self) -> Vec<[f32; 4]> {
+        let vertex_count = self.rest.len();
+        let deltas = self
+            .targets
+            .targets
+            .iter()
+            .flat_map(|target| {
+                (0..vertex_count).flat_map(|i| {
+                    let [x, y, z] = target.positions.get(i).copied().unwrap_or_default();
+                    let [nx, ny, nz] = target.normals.get(i).copied().unwrap_or_default();
+                    [[x, y, z, 0.], [nx, ny, nz, 0.]]
+                })
+            })
+            .collect();
+        self.rest = vec![];
+        self.targets.targets = vec![];
+        deltas
+    }
+
+    pub fn upload(&mut self, device: &Device, label: &str) {
+        let (rest, targets) = (&self.rest, &self.targets);
+        let blended = blend_morph_targets(rest, &targets.targets, &targets.weights);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Morphed Vertex Buffer", label)),
+            contents: bytemuck::cast_slice(&blended),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        self.vertex_buffer = Some(vertex_buffer);
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.targets.weights
+    }
+
+    // Stops the animation, weights past the targets are ignored
+    pub fn set_weights(&mut self, weights: &[f32]) {
+        self.targets.animation = None;
+        for (weight, new) in self.targets.weights.iter_mut().zip(weights) {
+            *weight = *new;
+        }
+        self.changed = true;
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.targets.animation.is_some()
+    }
+
+    // The blended vertices on the CPU path
+    pub fn blended(&self) -> Vec<Vertex> {
+        let targets = &self.targets;
+        blend_morph_targets(&self.rest, &targets.targets, &targets.weights)
+    }
+
+    // Advances the animation and uploads the blended vertices on the CPU path, true when the
+    // weights changed and MeshDeform has to get them on the GPU path
+    pub fn update(&mut self, dt: Duration, queue: &Queue) -> bool {
+        if let Some(animation) = &self.targets.animation {
+            self.time += dt.as_secs_f32();
+            let mut weights = animation.sample(self.time);
+            weights.resize(self.targets.weights.len(), 0.);
+            if weights != self.targets.weights {
+                self.targets.weights = weights;
+                self.changed = true;
+            }
+        }
+        if !std::mem::take(&mut self.changed) {
+            return false;
+        }
+        if let Some(vertex_buffer) = &self.vertex_buffer {
+            stats::write_buffer(
+                queue,
+                vertex_buffer,
+                0,
+                bytemuck::cast_slice(&self.blended()),
+            );
+        }
+        true
+    }
+}
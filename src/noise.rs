@@ -0,0 +1,248 @@
+// Tileable noise textures rendered on the GPU at startup, so fog, water and terrain get their
+// variation without more images in the binary. A fullscreen pass rather than a compute one, so
+// WebGL gets the same textures. `noise` is the same function on the CPU, noise.wgsl mirrors it.
+use crate::particles::{hash, unit};
+use crate::{mipmap, texture};
+use std::borrow::Cow;
+use std::f32::consts::{FRAC_1_SQRT_2, TAU};
+use wgpu::util::DeviceExt;
+use wgpu::{Device, Queue};
+
+// Single channel, read the red one
+pub const NOISE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// Fbm stops adding layers here, they would be finer than the texels anyway
+pub const MAX_OCTAVES: u32 = 12;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    // Smooth gradient noise, 0.5 on average
+    Perlin,
+    // Distance to the nearest of one random point per cell, 0 on the points
+    Worley,
+    // Octaves of Perlin noise summed, clouds and fog
+    Fbm,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NoiseSettings {
+    pub kind: NoiseKind,
+    // Cells across the texture, whole ones so the edges line up when it repeats
+    pub period: u32,
+    // Layers summed by Fbm, each with twice the cells and half the weight of the one before
+    pub octaves: u32,
+    pub seed: u32,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::Fbm,
+            period: 4,
+            octaves: 5,
+            seed: 0,
+        }
+    }
+}
+
+// Cells past the period wrap around, which is what makes the noise tile
+fn lattice_hash(x: i32, y: i32, period: u32, seed: u32) -> u32 {
+    let period = period.max(1) as i32;
+    hash(x.rem_euclid(period) as u32 ^ hash(y.rem_euclid(period) as u32 ^ hash(seed)))
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// In [-1/sqrt(2), 1/sqrt(2)], `x` and `y` in cells
+fn perlin(x: f32, y: f32, period: u32, seed: u32) -> f32 {
+    let (cell_x, cell_y) = (x.floor(), y.floor());
+    let (fx, fy) = (x - cell_x, y - cell_y);
+    let (ix, iy) = (cell_x as i32, cell_y as i32);
+    let corner = |dx: i32, dy: i32| {
+        let angle = unit(lattice_hash(ix + dx, iy + dy, period, seed)) * TAU;
+        angle.cos() * (fx - dx as f32) + angle.sin() * (fy - dy as f32)
+    };
+    let (u, v) = (fade(fx), fade(fy));
+    lerp(
+        lerp(corner(0, 0), corner(1, 0), u),
+        lerp(corner(0, 1), corner(1, 1), u),
+        v,
+    )
+}
+
+// Distance in cells, capped at 1
+fn worley(x: f32, y: f32, period: u32, seed: u32) -> f32 {
+    let (cell_x, cell_y) = (x.floor() as i32, y.floor() as i32);
+    let mut nearest = 1f32;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let h = lattice_hash(cell_x + dx, cell_y + dy, period, seed);
+            let feature_x = (cell_x + dx) as f32 + unit(h);
+            let feature_y = (cell_y + dy) as f32 + unit(hash(h));
+            nearest = nearest.min((feature_x - x).hypot(feature_y - y));
+        }
+    }
+    nearest
+}
+
+// In [0, 1] at texture coordinates `u` and `v`, repeating every 1 on both
+pub fn noise(settings: &NoiseSettings, u: f32, v: f32) -> f32 {
+    let period = settings.period.max(1);
+    let (x, y) = (u * period as f32, v * period as f32);
+    let value = match settings.kind {
+        NoiseKind::Perlin => 0.5 + perlin(x, y, period, settings.seed) * FRAC_1_SQRT_2,
+        NoiseKind::Worley => worley(x, y, period, settings.seed),
+        NoiseKind::Fbm => {
+            let (mut sum, mut weight, mut total) = (0., 1., 0.);
+            for octave in 0..settings.octaves.clamp(1, MAX_OCTAVES) {
+                let period = period << octave;
+                let seed = settings.seed.wrapping_add(octave);
+                sum += perlin(u * period as f32, v * period as f32, period, seed) * weight;
+                total += weight;
+                weight *= 0.5;
+            }
+            0.5 + sum / total * FRAC_1_SQRT_2
+        }
+    };
+    value.clamp(0., 1.)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct NoiseUniform {
+    // Kind, period, octaves and seed
+    settings: [u32; 4],
+    // Width of the texture, yzw unused
+    size: [u32; 4],
+}
+
+impl NoiseUniform {
+    fn new(settings: &NoiseSettings, size: u32) -> Self {
+        let kind = match settings.kind {
+            NoiseKind::Perlin => 0,
+            NoiseKind::Worley => 1,
+            NoiseKind::Fbm => 2,
+        };
+        Self {
+            settings: [
+                kind,
+                settings.period.max(1),
+                settings.octaves.clamp(1, MAX_OCTAVES),
+                settings.seed,
+            ],
+            size: [size, 0, 0, 0],
+        }
+    }
+}
+
+// A `size` by `size` texture with a full mip chain and a repeating sampler. Submits right away,
+// meant for startup where a pass or two more doesn't matter.
+pub(crate) fn generate_noise(
+    device: &Device,
+    queue: &Queue,
+    size: u32,
+    settings: &NoiseSettings,
+    label: &str,
+) -> texture::Texture {
+    let size = size.max(1);
+    let mip_level_count = u32::BITS - size.leading_zeros();
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: NOISE_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let target = texture.create_view(&wgpu::TextureViewDescriptor {
+        base_mip_level: 0,
+        mip_level_count: std::num::NonZeroU32::new(1),
+        ..Default::default()
+    });
+
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Noise Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("noise.wgsl"))),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Noise Pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[NOISE_FORMAT.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Noise Buffer"),
+        contents: bytemuck::cast_slice(&[NoiseUniform::new(settings, size)]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+        label: Some("Noise Bind Group"),
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Noise Encoder"),
+    });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Noise Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    queue.submit(Some(encoder.finish()));
+    mipmap::generate_mipmaps(device, queue, &texture, NOISE_FORMAT, mip_level_count, 1);
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some(label),
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    texture::Texture {
+        texture,
+        view,
+        sampler,
+    }
+}
@@ -0,0 +1,96 @@
+// Tileable noise of noise.rs, every function mirrors the one there
+struct Noise {
+    // Kind, period, octaves and seed
+    settings: vec4<u32>,
+    // Width of the texture, yzw unused
+    size: vec4<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> noise: Noise;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+// PCG hash, as in particles.rs
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn unit(x: u32) -> f32 {
+    return f32(x) / 4294967295.0;
+}
+
+fn wrap(x: i32, period: i32) -> u32 {
+    return u32(((x % period) + period) % period);
+}
+
+fn lattice_hash(cell: vec2<i32>, period: u32, seed: u32) -> u32 {
+    let p = i32(max(period, 1u));
+    return hash(wrap(cell.x, p) ^ hash(wrap(cell.y, p) ^ hash(seed)));
+}
+
+fn fade(t: vec2<f32>) -> vec2<f32> {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn corner(cell: vec2<i32>, offset: vec2<i32>, f: vec2<f32>, period: u32, seed: u32) -> f32 {
+    let angle = unit(lattice_hash(cell + offset, period, seed)) * 6.283185307;
+    return dot(vec2<f32>(cos(angle), sin(angle)), f - vec2<f32>(offset));
+}
+
+fn perlin(p: vec2<f32>, period: u32, seed: u32) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    let t = fade(f);
+    return mix(
+        mix(corner(cell, vec2<i32>(0, 0), f, period, seed), corner(cell, vec2<i32>(1, 0), f, period, seed), t.x),
+        mix(corner(cell, vec2<i32>(0, 1), f, period, seed), corner(cell, vec2<i32>(1, 1), f, period, seed), t.x),
+        t.y
+    );
+}
+
+fn worley(p: vec2<f32>, period: u32, seed: u32) -> f32 {
+    let cell = vec2<i32>(floor(p));
+    var nearest = 1.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour = cell + vec2<i32>(x, y);
+            let h = lattice_hash(neighbour, period, seed);
+            let feature = vec2<f32>(neighbour) + vec2<f32>(unit(h), unit(hash(h)));
+            nearest = min(nearest, distance(feature, p));
+        }
+    }
+    return nearest;
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = position.xy / f32(noise.size.x);
+    let period = noise.settings.y;
+    let seed = noise.settings.w;
+    var value = 0.0;
+    if noise.settings.x == 0u {
+        value = 0.5 + perlin(uv * f32(period), period, seed) * 0.70710678;
+    } else if noise.settings.x == 1u {
+        value = worley(uv * f32(period), period, seed);
+    } else {
+        var sum = 0.0;
+        var weight = 1.0;
+        var total = 0.0;
+        for (var octave = 0u; octave < noise.settings.z; octave++) {
+            let octave_period = period << octave;
+            sum += perlin(uv * f32(octave_period), octave_period, seed + octave) * weight;
+            total += weight;
+            weight *= 0.5;
+        }
+        value = 0.5 + sum / total * 0.70710678;
+    }
+    return vec4<f32>(vec3<f32>(clamp(value, 0.0, 1.0)), 1.0);
+}
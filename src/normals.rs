@@ -0,0 +1,84 @@
+use crate::geo_gen::{MeshData, Vertex};
+use cgmath::{InnerSpace, Vector3, Zero};
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NormalMode {
+    // Faces weighted by their area, large faces dominate
+    Smooth,
+    // Faces weighted by their corner angle, independent of how a face is triangulated
+    AngleWeighted,
+    // One normal per triangle, every triangle gets its own vertices
+    Flat,
+}
+
+// Vertices at the same position share a smooth normal, so texture seams don't show as creases.
+// Zero area triangles are skipped, a vertex without any keeps its old normal.
+pub fn recompute_normals(mesh: &mut MeshData, mode: NormalMode) {
+    if mode == NormalMode::Flat {
+        flatten(mesh);
+        return;
+    }
+    let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+    let slots: Vec<usize> = mesh
+        .vertex_data
+        .iter()
+        .map(|v| {
+            let count = welded.len();
+            *welded.entry(position_key(v)).or_insert(count)
+        })
+        .collect();
+    let mut sums = vec![Vector3::zero(); welded.len()];
+    for t in mesh.index_data.chunks_exact(3) {
+        let corners = [t[0], t[1], t[2]].map(|i| i as usize);
+        let [a, b, c] = corners.map(|i| Vector3::from(mesh.vertex_data[i].position));
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() == 0. {
+            continue;
+        }
+        let angles = [
+            angle(b - a, c - a),
+            angle(c - b, a - b),
+            angle(a - c, b - c),
+        ];
+        for (corner, angle) in corners.into_iter().zip(angles) {
+            sums[slots[corner]] += match mode {
+                NormalMode::AngleWeighted => normal.normalize() * angle,
+                _ => normal,
+            };
+        }
+    }
+    for (vertex, slot) in mesh.vertex_data.iter_mut().zip(slots) {
+        let sum = sums[slot];
+        if sum.magnitude2() > 0. {
+            vertex.normal = sum.normalize().into();
+        }
+    }
+}
+
+// -0 and 0 weld together
+fn position_key(vertex: &Vertex) -> [u32; 3] {
+    vertex.position.map(|x| (x + 0.).to_bits())
+}
+
+fn angle(u: Vector3<f32>, v: Vector3<f32>) -> f32 {
+    u.angle(v).0
+}
+
+fn flatten(mesh: &mut MeshData) {
+    let mut vertex_data = Vec::with_capacity(mesh.index_data.len());
+    for t in mesh.index_data.chunks_exact(3) {
+        let mut corners = [t[0], t[1], t[2]].map(|i| mesh.vertex_data[i as usize]);
+        let [a, b, c] = corners.map(|v| Vector3::from(v.position));
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() > 0. {
+            let normal = normal.normalize().into();
+            for corner in &mut corners {
+                corner.normal = normal;
+            }
+        }
+        vertex_data.extend(corners);
+    }
+    mesh.index_data = (0..vertex_data.len() as u32).collect();
+    mesh.vertex_data = vertex_data;
+}
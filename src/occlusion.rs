@@ -0,0 +1,450 @@
+use crate::bounds::BoundingSphere;
+use crate::camera::Camera;
+use crate::world_space::{Instances, INSTANCE_SIZE};
+use crate::{compute, stats, texture, RenderGroup};
+use bytemuck::Zeroable;
+use std::borrow::Cow;
+use std::ops::Range;
+use wgpu::util::DeviceExt;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, CommandEncoder, ComputePass, ComputePipeline, Device,
+    Queue, RenderPass, RenderPipeline, SurfaceConfiguration, TextureView,
+};
+
+const PYRAMID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+// Invocations per workgroup of cs_main in occlusion.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+// Levels of the Hi-Z pyramid over a `width` x `height` depth buffer. Level 0 is already half
+// the resolution, the last is a single texel.
+pub fn hiz_levels(width: u32, height: u32) -> u32 {
+    let base = (width / 2).max(height / 2).max(1);
+    u32::BITS - base.leading_zeros()
+}
+
+// Pyramid level where bounds `extent` pixels across cover at most 2x2 texels, the texels of
+// level i being 2^(i + 1) pixels wide. Mirrors the lookup in occlusion.wgsl.
+pub fn hiz_level(extent: f32, levels: u32) -> u32 {
+    let level = extent.max(1.).log2().ceil() as i32 - 1;
+    level.clamp(0, levels.max(1) as i32 - 1) as u32
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    // Camera the pyramid was built from
+    relative_view_proj: [[f32; 4]; 4],
+    render_origin: [f32; 4],
+    // Width and height of the depth buffer, pyramid levels in z, w unused
+    size_levels: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GroupUniform {
+    // Bounds of the mesh in its own space, radius in w
+    center_radius: [f32; 4],
+    // Instance count in x, yzw unused
+    count: [u32; 4],
+}
+
+// Layout of wgpu's indexed indirect draw, instance_count is counted up by cs_main
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawIndexedArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+// Levels of the pyramid and the passes that build them, recreated with the depth buffer
+struct Pyramid {
+    width: u32,
+    height: u32,
+    levels: u32,
+    // All levels, read by the culling
+    view: TextureView,
+    // One view per level as the render target, bind group i reads what level i reduces
+    level_views: Vec<TextureView>,
+    level_bind_groups: Vec<BindGroup>,
+}
+
+// Hi-Z occlusion culling of instanced groups drawn by the main camera. After the depth resolve
+// the scene depth is reduced into a mip pyramid keeping the farthest depth of every block.
+// At the start of the next frame a compute shader projects the bounds of every instance with
+// the camera of that depth, compares the nearest point against the level where the bounds
+// cover 2x2 texels and appends the survivors to a buffer drawn indirectly, see CulledInstances.
+// Instances coming out from behind an occluder show up one frame late. Needs compute shaders,
+// without them everything is drawn, see compute.rs.
+pub(crate) struct OcclusionCulling {
+    first_pipeline: RenderPipeline,
+    reduce_pipeline: RenderPipeline,
+    cull_pipeline: ComputePipeline,
+    cull_layout: BindGroupLayout,
+    // Group 1 of cs_main, one bind group per culled group
+    pub(crate) group_layout: BindGroupLayout,
+    uniform_buffer: Buffer,
+    cull_bind_group: BindGroup,
+    pyramid: Pyramid,
+    // Camera of the frame the pyramid is being built from, None until there is one to test
+    previous: Option<CullUniform>,
+}
+
+impl OcclusionCulling {
+    pub fn new(device: &Device, config: &SurfaceConfiguration, depth: &texture::Texture) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Hi-Z Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("hiz.wgsl"))),
+        });
+        let create_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Hi-Z Pipeline"),
+                layout: None,
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[wgpu::ColorTargetState {
+                        format: PYRAMID_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let first_pipeline = create_pipeline("fs_first");
+        let reduce_pipeline = create_pipeline("fs_reduce");
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Uniform Buffer"),
+            size: std::mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cull_layout = compute::bind_group_layout(
+            device,
+            "Occlusion Bind Group Layout",
+            &[compute::uniform_entry(0), compute::texture_entry(1)],
+        );
+        let group_layout = compute::bind_group_layout(
+            device,
+            "Occlusion Group Bind Group Layout",
+            &[
+                compute::uniform_entry(0),
+                compute::storage_entry(1, true),
+                compute::storage_entry(2, false),
+                compute::storage_entry(3, false),
+            ],
+        );
+        let cull_pipeline = compute::create_pipeline(
+            device,
+            "Occlusion Pipeline",
+            include_str!("occlusion.wgsl"),
+            &[&cull_layout, &group_layout],
+        );
+        let pyramid =
+            Self::create_pyramid(device, config, depth, &first_pipeline, &reduce_pipeline);
+        let cull_bind_group =
+            Self::create_cull_bind_group(device, &cull_layout, &uniform_buffer, &pyramid);
+        Self {
+            first_pipeline,
+            reduce_pipeline,
+            cull_pipeline,
+            cull_layout,
+            group_layout,
+            uniform_buffer,
+            cull_bind_group,
+            pyramid,
+            previous: None,
+        }
+    }
+
+    // The pyramid of the old size can't be tested against, culling resumes a frame later
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        config: &SurfaceConfiguration,
+        depth: &texture::Texture,
+    ) {
+        self.pyramid = Self::create_pyramid(
+            device,
+            config,
+            depth,
+            &self.first_pipeline,
+            &self.reduce_pipeline,
+        );
+        self.cull_bind_group = Self::create_cull_bind_group(
+            device,
+            &self.cull_layout,
+            &self.uniform_buffer,
+            &self.pyramid,
+        );
+        self.previous = None;
+    }
+
+    fn create_pyramid(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        depth: &texture::Texture,
+        first_pipeline: &RenderPipeline,
+        reduce_pipeline: &RenderPipeline,
+    ) -> Pyramid {
+        let levels = hiz_levels(config.width, config.height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("hiz_pyramid"),
+            size: wgpu::Extent3d {
+                width: (config.width / 2).max(1),
+                height: (config.height / 2).max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PYRAMID_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let level_views: Vec<_> = (0..levels)
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("hiz_level"),
+                    base_mip_level: level,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let level_bind_groups = (0..levels as usize)
+            .map(|level| match level.checked_sub(1) {
+                None => device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &first_pipeline.get_bind_group_layout(0),
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&depth.view),
+                    }],
+                    label: Some("Hi-Z Bind Group"),
+                }),
+                Some(previous) => device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &reduce_pipeline.get_bind_group_layout(0),
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&level_views[previous]),
+                    }],
+                    label: Some("Hi-Z Bind Group"),
+                }),
+            })
+            .collect();
+        Pyramid {
+            width: config.width,
+            height: config.height,
+            levels,
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            level_views,
+            level_bind_groups,
+        }
+    }
+
+    fn create_cull_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        pyramid: &Pyramid,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&pyramid.view),
+                },
+            ],
+            label: Some("Occlusion Bind Group"),
+        })
+    }
+
+    // Call once a frame before cull with the camera being drawn, true if the pyramid of the
+    // last frame can be tested against. Split views share the depth texture, the pyramid only
+    // fits the main camera drawn alone.
+    pub fn prepare(&mut self, queue: &Queue, camera: &Camera, single_view: bool) -> bool {
+        let current = single_view.then(|| CullUniform {
+            relative_view_proj: camera.camera_uniform.relative_view_proj,
+            render_origin: camera.camera_uniform.render_origin,
+            size_levels: [
+                self.pyramid.width as f32,
+                self.pyramid.height as f32,
+                self.pyramid.levels as f32,
+                0.,
+            ],
+        });
+        match std::mem::replace(&mut self.previous, current) {
+            Some(previous) => {
+                stats::write_buffer(
+                    queue,
+                    &self.uniform_buffer,
+                    0,
+                    bytemuck::cast_slice(&[previous]),
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Tests the instances of every group that draws culled, before the depth pre-pass
+    pub fn cull(&self, encoder: &mut CommandEncoder, queue: &Queue, groups: &[&dyn RenderGroup]) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Occlusion Cull Pass"),
+        });
+        pass.set_pipeline(&self.cull_pipeline);
+        pass.set_bind_group(0, &self.cull_bind_group, &[]);
+        for group in groups {
+            group.cull(queue, &mut pass);
+        }
+    }
+
+    // Call after the depth resolve, the pyramid is tested against in the next frame
+    pub fn build(&self, encoder: &mut CommandEncoder) {
+        let pyramid = &self.pyramid;
+        for (level, (view, bind_group)) in pyramid
+            .level_views
+            .iter()
+            .zip(&pyramid.level_bind_groups)
+            .enumerate()
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Hi-Z Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(if level == 0 {
+                &self.first_pipeline
+            } else {
+                &self.reduce_pipeline
+            });
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+// The instances of a group that passed the last cull and the indirect draw of them
+pub(crate) struct CulledInstances {
+    count: u32,
+    uniform_buffer: Buffer,
+    // Compacted copies of the instances, laid out as world_space::desc
+    visible_buffer: Buffer,
+    args_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl CulledInstances {
+    // `instances` must have enable_storage called
+    pub fn new(device: &Device, occlusion: &OcclusionCulling, instances: &Instances) -> Self {
+        let count = instances.instance_transforms.len() as u32;
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culled Instances Uniform Buffer"),
+            size: std::mem::size_of::<GroupUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let visible_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: (count.max(1) as usize * INSTANCE_SIZE) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let args_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Culled Draw Buffer"),
+            contents: bytemuck::cast_slice(&[DrawIndexedArgs::zeroed()]),
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &occlusion.group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instances.instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: visible_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: args_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Culled Instances Bind Group"),
+        });
+        Self {
+            count,
+            uniform_buffer,
+            visible_buffer,
+            args_buffer,
+            bind_group,
+        }
+    }
+
+    // Tests every instance of a mesh with bounds `local`, the draw covers `indices` of it.
+    // Written at cull time so a level of detail chosen this frame draws its own index count.
+    pub fn cull<'a, 'b: 'a>(
+        &'b self,
+        queue: &Queue,
+        pass: &mut ComputePass<'a>,
+        local: &BoundingSphere,
+        indices: Range<u32>,
+    ) {
+        let uniform = GroupUniform {
+            center_radius: [local.center.x, local.center.y, local.center.z, local.radius],
+            count: [self.count, 0, 0, 0],
+        };
+        stats::write_buffer(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform]),
+        );
+        let args = DrawIndexedArgs {
+            index_count: indices.end - indices.start,
+            instance_count: 0,
+            first_index: indices.start,
+            base_vertex: 0,
+            first_instance: 0,
+        };
+        stats::write_buffer(queue, &self.args_buffer, 0, bytemuck::cast_slice(&[args]));
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.dispatch(compute::workgroup_count(self.count, WORKGROUP_SIZE), 1, 1);
+    }
+
+    // The mesh buffers must already be bound at slot 1 and as the index buffer
+    pub fn draw<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.visible_buffer.slice(..));
+        render_pass.draw_indexed_indirect(&self.args_buffer, 0);
+    }
+}
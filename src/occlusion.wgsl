@@ -0,0 +1,114 @@
+// Tests instance bounds against the Hi-Z pyramid, see occlusion.rs
+struct Cull {
+    // Camera the pyramid was built from
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+    // Width and height of the depth buffer, pyramid levels in z
+    size_levels: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var t_pyramid: texture_2d<f32>;
+
+struct Group {
+    // Bounds of the mesh in its own space, radius in w
+    center_radius: vec4<f32>,
+    count: vec4<u32>,
+};
+
+// Mirrors DrawIndexedArgs
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> group: Group;
+// world_space::InstanceRaw as floats, 16 of the model matrix first
+@group(1) @binding(1)
+var<storage, read> instances: array<f32>;
+@group(1) @binding(2)
+var<storage, read_write> visible: array<f32>;
+@group(1) @binding(3)
+var<storage, read_write> args: DrawArgs;
+
+fn model_column(first: u32) -> vec4<f32> {
+    return vec4<f32>(instances[first], instances[first + 1u], instances[first + 2u], instances[first + 3u]);
+}
+
+// Conservative, anything not provably behind the depth of the last frame is kept
+fn is_visible(first: u32) -> bool {
+    let model = mat4x4<f32>(
+        model_column(first),
+        model_column(first + 4u),
+        model_column(first + 8u),
+        model_column(first + 12u)
+    );
+    let center = (model * vec4<f32>(group.center_radius.xyz, 1.0)).xyz - cull.render_origin.xyz;
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = group.center_radius.w * scale;
+
+    // Screen rect and nearest depth of the box around the sphere
+    var lo = vec3<f32>(1e9);
+    var hi = vec3<f32>(-1e9);
+    for (var i = 0u; i < 8u; i++) {
+        let corner = center + vec3<f32>(
+            select(-radius, radius, (i & 1u) != 0u),
+            select(-radius, radius, (i & 2u) != 0u),
+            select(-radius, radius, (i & 4u) != 0u)
+        );
+        let clip = cull.relative_view_proj * vec4<f32>(corner, 1.0);
+        // Reaches behind the near plane, too close to tell
+        if clip.w <= 0.0001 {
+            return true;
+        }
+        let ndc = clip.xyz / clip.w;
+        let screen = vec3<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5, ndc.z);
+        lo = min(lo, screen);
+        hi = max(hi, screen);
+    }
+    // Off the last frame's screen, there is no depth to test against
+    if hi.x < 0.0 || hi.y < 0.0 || lo.x > 1.0 || lo.y > 1.0 {
+        return true;
+    }
+
+    // Mirrors occlusion::hiz_level, texels of level i are 2^(i + 1) pixels wide
+    let size = cull.size_levels.xy;
+    let min_pixel = clamp(lo.xy, vec2<f32>(0.0), vec2<f32>(1.0)) * size;
+    let max_pixel = clamp(hi.xy, vec2<f32>(0.0), vec2<f32>(1.0)) * size;
+    let extent = max(max_pixel.x - min_pixel.x, max_pixel.y - min_pixel.y);
+    let levels = i32(cull.size_levels.z);
+    let level = clamp(i32(ceil(log2(max(extent, 1.0)))) - 1, 0, levels - 1);
+    let texel = exp2(f32(level + 1));
+    let base = max(vec2<i32>(size) / 2, vec2<i32>(1));
+    let last = max(base >> vec2<u32>(u32(level)), vec2<i32>(1)) - 1;
+    let a = clamp(vec2<i32>(min_pixel / texel), vec2<i32>(0), last);
+    let b = clamp(vec2<i32>(max_pixel / texel), vec2<i32>(0), last);
+    let farthest = max(
+        max(textureLoad(t_pyramid, a, level).r, textureLoad(t_pyramid, vec2<i32>(b.x, a.y), level).r),
+        max(textureLoad(t_pyramid, vec2<i32>(a.x, b.y), level).r, textureLoad(t_pyramid, b, level).r)
+    );
+    return lo.z <= farthest;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance = id.x;
+    if instance >= group.count.x {
+        return;
+    }
+    // 33 floats per instance, mirrors world_space::INSTANCE_SIZE
+    let first = instance * 33u;
+    if !is_visible(first) {
+        return;
+    }
+    let slot = atomicAdd(&args.instance_count, 1u);
+    for (var i = 0u; i < 33u; i++) {
+        visible[slot * 33u + i] = instances[first + i];
+    }
+}
@@ -0,0 +1,247 @@
+use crate::camera::Camera;
+use crate::{geo_gen, stats, texture, world_space, RenderGroup};
+use bytemuck::Zeroable;
+use std::borrow::Cow;
+use wgpu::util::DeviceExt;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, Queue, RenderPipeline,
+    SurfaceConfiguration, TextureView,
+};
+
+const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// In pixels
+const OUTLINE_WIDTH: f32 = 3.;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    instance: u32,
+    width: f32,
+    _padding: [u32; 2],
+}
+
+// Silhouette of the picked instance. Its group is drawn again through render_shadow into a
+// mask, the other instances collapsed, then a ring around the mask is blended onto the scene.
+// The ring shows through whatever covers the object.
+pub struct OutlinePass {
+    mask_pipeline: RenderPipeline,
+    outline_pipeline: RenderPipeline,
+    buffer: Buffer,
+    mask_bind_group: BindGroup,
+    outline_layout: BindGroupLayout,
+    outline_bind_group: BindGroup,
+    mask: texture::Texture,
+}
+
+impl OutlinePass {
+    pub fn new(device: &Device, config: &SurfaceConfiguration, camera: &Camera) -> Self {
+        let uniform_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let mask_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry(wgpu::ShaderStages::VERTEX)],
+            label: Some("Outline Mask Bind Group Layout"),
+        });
+        let outline_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    ..uniform_entry(wgpu::ShaderStages::FRAGMENT)
+                },
+            ],
+            label: Some("Outline Bind Group Layout"),
+        });
+        let mask_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Mask Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("outline_mask.wgsl"))),
+        });
+        let mask_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Mask Pipeline Layout"),
+            bind_group_layouts: &[&camera.camera_bind_group_layout, &mask_layout],
+            push_constant_ranges: &[],
+        });
+        let mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Mask Pipeline"),
+            layout: Some(&mask_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &mask_shader,
+                entry_point: "vs_main",
+                buffers: &[world_space::desc(), geo_gen::Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &mask_shader,
+                entry_point: "fs_main",
+                targets: &[MASK_FORMAT.into()],
+            }),
+            // Back faces fill the same silhouette
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let outline_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("outline.wgsl"))),
+        });
+        let outline_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Outline Pipeline Layout"),
+                bind_group_layouts: &[&outline_layout],
+                push_constant_ranges: &[],
+            });
+        let outline_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Pipeline"),
+            layout: Some(&outline_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &outline_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &outline_shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Buffer"),
+            contents: bytemuck::cast_slice(&[OutlineUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let mask_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &mask_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("Outline Mask Bind Group"),
+        });
+        let mask = create_mask(device, config);
+        let outline_bind_group = create_outline_bind_group(device, &outline_layout, &mask, &buffer);
+        Self {
+            mask_pipeline,
+            outline_pipeline,
+            buffer,
+            mask_bind_group,
+            outline_layout,
+            outline_bind_group,
+            mask,
+        }
+    }
+
+    pub fn resize(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        self.mask = create_mask(device, config);
+        self.outline_bind_group =
+            create_outline_bind_group(device, &self.outline_layout, &self.mask, &self.buffer);
+    }
+
+    // Outlines `instance` of `group` in `color` onto `target`
+    pub fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        queue: &Queue,
+        target: &TextureView,
+        camera: &Camera,
+        (group, instance): (&dyn RenderGroup, usize),
+        color: [f32; 4],
+    ) {
+        let uniform = OutlineUniform {
+            color,
+            instance: instance as u32,
+            width: OUTLINE_WIDTH,
+            _padding: [0; 2],
+        };
+        stats::write_buffer(queue, &self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Outline Mask Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &self.mask.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.mask_pipeline);
+            render_pass.set_bind_group(0, &camera.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.mask_bind_group, &[]);
+            group.render_shadow(&mut render_pass, 0);
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.outline_pipeline);
+        render_pass.set_bind_group(0, &self.outline_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_mask(device: &Device, config: &SurfaceConfiguration) -> texture::Texture {
+    texture::Texture::create_render_target(
+        device,
+        config.width,
+        config.height,
+        MASK_FORMAT,
+        "outline_mask",
+    )
+}
+
+fn create_outline_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    mask: &texture::Texture,
+    buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&mask.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("Outline Bind Group"),
+    })
+}
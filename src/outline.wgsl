@@ -0,0 +1,52 @@
+// A ring of `outline.width` pixels around the mask of the picked instance, see outline.rs
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    var v_out: VertexOutput;
+    v_out.position = vec4<f32>(x, y, 0.0, 1.0);
+    v_out.tex_coords = vec2<f32>((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+    return v_out;
+}
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    instance: u32,
+    width: f32,
+};
+
+@group(0) @binding(0)
+var t_mask: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> outline: OutlineUniform;
+
+@fragment
+fn fs_main(v_in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(t_mask));
+    let texel = vec2<i32>(v_in.position.xy);
+    // The object itself keeps its shading
+    if textureLoad(t_mask, texel, 0).r > 0.5 {
+        discard;
+    }
+    let radius = i32(ceil(outline.width));
+    var coverage = 0.0;
+    for (var x = -radius; x <= radius; x++) {
+        for (var y = -radius; y <= radius; y++) {
+            let offset = length(vec2<f32>(f32(x), f32(y)));
+            let neighbor = clamp(texel + vec2<i32>(x, y), vec2<i32>(0), size - vec2<i32>(1));
+            let mask = textureLoad(t_mask, neighbor, 0).r;
+            // Fades over the last pixel for a smooth outer edge
+            coverage = max(coverage, mask * clamp(outline.width + 1.0 - offset, 0.0, 1.0));
+        }
+    }
+    if coverage <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(outline.color.rgb, outline.color.a * coverage);
+}
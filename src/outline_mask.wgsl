@@ -0,0 +1,61 @@
+// Coverage of the picked instance, see outline.rs
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    proj_inv: mat4x4<f32>,
+    view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+fn world_to_clip(world_position: vec3<f32>) -> vec4<f32> {
+    return camera.relative_view_proj * vec4<f32>(world_position - camera.render_origin.xyz, 1.0);
+}
+
+struct OutlineUniform {
+    color: vec4<f32>,
+    instance: u32,
+    width: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+};
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32
+) -> @builtin(position) vec4<f32> {
+    // The group draws all of its instances, the others collapse outside the clip volume
+    if instance_index != outline.instance {
+        return vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    }
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3
+    );
+    return world_to_clip((model_matrix * vec4<f32>(model.position, 1.0)).xyz);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
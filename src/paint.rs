@@ -0,0 +1,143 @@
+use crate::{stats, texture};
+use anyhow::Result;
+use std::num::NonZeroU32;
+use wgpu::{Device, Queue};
+
+// Older strokes are forgotten past this
+const MAX_UNDO: usize = 64;
+
+// Pixels of the texture before a brush touched them
+struct Patch {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+// A texture with a CPU copy that brushes are painted into, uploading only the touched patch.
+// Expects mip_level_count 1, painting doesn't update lower mips.
+pub struct PaintableTexture {
+    pub texture: texture::Texture,
+    pixels: image::RgbaImage,
+    undo: Vec<Patch>,
+}
+
+impl PaintableTexture {
+    pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: &str) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        let texture = texture::Texture::from_image(device, queue, &img, Some(label), 1)?;
+        Ok(Self {
+            texture,
+            pixels: img.to_rgba8(),
+            undo: vec![],
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.pixels.dimensions()
+    }
+
+    // Stamp a round brush centered on a RayHit uv, `radius` is in pixels and the color's
+    // alpha is the brush opacity. One call is one undo step, false if no pixel was touched.
+    pub fn paint(&mut self, queue: &Queue, uv: [f32; 2], radius: f32, color: [u8; 4]) -> bool {
+        let (width, height) = self.size();
+        // Same flip and wrapping as the shaders' texture lookup
+        let cx = uv[0].rem_euclid(1.) * width as f32;
+        let cy = (1. - uv[1].rem_euclid(1.)) * height as f32;
+        let x0 = ((cx - radius).floor().max(0.)) as u32;
+        let y0 = ((cy - radius).floor().max(0.)) as u32;
+        let x1 = ((cx + radius).ceil() as u32).min(width);
+        let y1 = ((cy + radius).ceil() as u32).min(height);
+        if x0 >= x1 || y0 >= y1 {
+            return false;
+        }
+        let patch = self.read_patch(x0, y0, x1 - x0, y1 - y0);
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(patch);
+
+        let opacity = color[3] as f32 / 255.;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let distance =
+                    ((x as f32 + 0.5 - cx).powi(2) + (y as f32 + 0.5 - cy).powi(2)).sqrt();
+                // Soft edge over the last pixel of the radius
+                let coverage = (radius - distance).clamp(0., 1.) * opacity;
+                if coverage <= 0. {
+                    continue;
+                }
+                let pixel = self.pixels.get_pixel_mut(x, y);
+                for (channel, brush) in pixel.0.iter_mut().zip(color).take(3) {
+                    *channel = (*channel as f32 + (brush as f32 - *channel as f32) * coverage)
+                        .round() as u8;
+                }
+            }
+        }
+        let touched = self.read_patch(x0, y0, x1 - x0, y1 - y0);
+        self.upload(queue, &touched);
+        true
+    }
+
+    // Restore the pixels of the last paint call, false if there was nothing to undo
+    pub fn undo(&mut self, queue: &Queue) -> bool {
+        let patch = match self.undo.pop() {
+            Some(patch) => patch,
+            None => return false,
+        };
+        let row = patch.width as usize * 4;
+        for (dy, pixels) in patch.pixels.chunks_exact(row).enumerate() {
+            for (dx, pixel) in pixels.chunks_exact(4).enumerate() {
+                let (x, y) = (patch.x + dx as u32, patch.y + dy as u32);
+                self.pixels
+                    .put_pixel(x, y, image::Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            }
+        }
+        self.upload(queue, &patch);
+        true
+    }
+
+    fn read_patch(&self, x: u32, y: u32, width: u32, height: u32) -> Patch {
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for row in y..y + height {
+            for column in x..x + width {
+                pixels.extend_from_slice(&self.pixels.get_pixel(column, row).0);
+            }
+        }
+        Patch {
+            x,
+            y,
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn upload(&self, queue: &Queue, patch: &Patch) {
+        stats::count_upload(patch.pixels.len());
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: patch.x,
+                    y: patch.y,
+                    z: 0,
+                },
+            },
+            &patch.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * patch.width),
+                rows_per_image: NonZeroU32::new(patch.height),
+            },
+            wgpu::Extent3d {
+                width: patch.width,
+                height: patch.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
@@ -0,0 +1,40 @@
+// Colors of what the engine draws as feedback, also meant for the host's HUD.
+// The high contrast set avoids telling things apart by red and green alone.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Palette {
+    // Material color of the picked object toggled with T
+    pub highlight: [f32; 3],
+    // Silhouette around the picked object
+    pub outline: [f32; 4],
+    // Middle click paint on the poster
+    pub brush: [u8; 4],
+    // The light trail, alpha is its opacity
+    pub trail: [f32; 4],
+    // For HUD text and its backing
+    pub text: [f32; 4],
+    pub background: [f32; 4],
+}
+
+impl Palette {
+    pub fn standard() -> Self {
+        Self {
+            highlight: [1., 0.3, 0.3],
+            outline: [1., 0.6, 0.1, 1.],
+            brush: [255, 40, 80, 200],
+            trail: [1.0, 0.8, 0.4, 0.8],
+            text: [1.; 4],
+            background: [0., 0., 0., 0.5],
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        Self {
+            highlight: [1., 0.85, 0.],
+            outline: [0., 0.9, 1., 1.],
+            brush: [0, 90, 255, 255],
+            trail: [1., 1., 1., 1.],
+            text: [1., 1., 0., 1.],
+            background: [0., 0., 0., 1.],
+        }
+    }
+}
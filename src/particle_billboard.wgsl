@@ -0,0 +1,66 @@
+// Camera facing sparks, see particles.rs
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    proj_inv: mat4x4<f32>,
+    view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+fn world_to_clip(world_position: vec3<f32>) -> vec4<f32> {
+    return camera.relative_view_proj * vec4<f32>(world_position - camera.render_origin.xyz, 1.0);
+}
+
+struct ParticleInput {
+    @location(0) position: vec3<f32>,
+    @location(1) age: f32,
+    @location(2) velocity: vec3<f32>,
+    @location(3) lifetime: f32,
+};
+
+var<private> CORNERS: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, particle: ParticleInput) -> VertexOutput {
+    var v_out: VertexOutput;
+    if particle.age < 0.0 || particle.age >= particle.lifetime {
+        // Degenerate, clipped away
+        v_out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return v_out;
+    }
+    let life = particle.age / particle.lifetime;
+    // Rows of the view matrix are the camera axes in world space
+    let right = vec3<f32>(camera.view[0].x, camera.view[1].x, camera.view[2].x);
+    let up = vec3<f32>(camera.view[0].y, camera.view[1].y, camera.view[2].y);
+    let corner = CORNERS[vertex_index];
+    let size = 0.12 * (1.0 - life);
+    let world_position = particle.position + (right * corner.x + up * corner.y) * size;
+    v_out.clip_position = world_to_clip(world_position);
+    v_out.corner = corner;
+    // White hot when emitted, cooling through yellow to a dim red
+    v_out.color = mix(vec3<f32>(4.0, 3.2, 1.5), vec3<f32>(0.6, 0.08, 0.0), sqrt(life));
+    return v_out;
+}
+
+@fragment
+fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    let falloff = max(1.0 - dot(f_in.corner, f_in.corner), 0.0);
+    return vec4<f32>(f_in.color * falloff * falloff, 1.0);
+}
@@ -0,0 +1,356 @@
+use crate::bounds::BoundingSphere;
+use crate::camera::Camera;
+use crate::stats::DrawStats;
+use crate::{compute, multi_sample, stats, texture, RenderGroup, PRIMITIVE};
+use cgmath::Point3;
+use std::f32::consts::TAU;
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+use wgpu::{
+    BindGroup, Buffer, ComputePass, ComputePipeline, Device, Queue, RenderPass, RenderPipeline,
+    SurfaceConfiguration,
+};
+
+// Invocations per workgroup of cs_main in particles.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Particle {
+    pub position: [f32; 3],
+    // Seconds since it was emitted, not born yet while negative
+    pub age: f32,
+    pub velocity: [f32; 3],
+    pub lifetime: f32,
+}
+
+impl Particle {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32,
+        2 => Float32x3,
+        3 => Float32,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// Where and how particles are thrown out
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Emitter {
+    pub position: [f32; 3],
+    // Launch speed, each particle between 0.75 and 1.25 of it
+    pub speed: f32,
+    // Largest angle from straight up in radians
+    pub spread: f32,
+    // Downward acceleration
+    pub gravity: f32,
+    // Longest life in seconds, each particle lives between half of it and all of it
+    pub lifetime: f32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            position: [0.; 3],
+            speed: 12.,
+            spread: 0.35,
+            gravity: 9.8,
+            lifetime: 3.,
+        }
+    }
+}
+
+// PCG hash, mirrored in particles.wgsl so both paths emit the same particles
+pub(crate) fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+// In [0, 1]
+pub(crate) fn unit(x: u32) -> f32 {
+    x as f32 / u32::MAX as f32
+}
+
+// Particle `index` as it is emitted in frame `frame`
+pub fn spawn_particle(emitter: &Emitter, index: u32, frame: u32) -> Particle {
+    let seed = hash(index ^ hash(frame));
+    let angle = unit(seed) * TAU;
+    let tilt = unit(hash(seed)) * emitter.spread;
+    let speed = emitter.speed * (0.75 + 0.5 * unit(hash(seed ^ 1)));
+    Particle {
+        position: emitter.position,
+        age: 0.,
+        velocity: [
+            tilt.sin() * angle.cos() * speed,
+            tilt.cos() * speed,
+            tilt.sin() * angle.sin() * speed,
+        ],
+        lifetime: emitter.lifetime * (0.5 + 0.5 * unit(hash(seed ^ 2))),
+    }
+}
+
+// Advances one particle by `dt` seconds, emitting it again once its life is over.
+// Mirrors cs_main in particles.wgsl, the CPU path runs this where compute is unavailable.
+pub fn step_particle(particle: &mut Particle, emitter: &Emitter, dt: f32, index: u32, frame: u32) {
+    particle.age += dt;
+    if particle.age < 0. {
+        return;
+    }
+    if particle.age >= particle.lifetime {
+        *particle = spawn_particle(emitter, index, frame);
+        return;
+    }
+    particle.velocity[1] -= emitter.gravity * dt;
+    for axis in 0..3 {
+        particle.position[axis] += particle.velocity[axis] * dt;
+    }
+}
+
+// Emitted at staggered times over one lifetime, so the stream is steady from the start
+fn initial_particles(emitter: &Emitter, count: u32) -> Vec<Particle> {
+    (0..count)
+        .map(|index| Particle {
+            age: -unit(hash(index ^ 3)) * emitter.lifetime,
+            ..spawn_particle(emitter, index, 0)
+        })
+        .collect()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleUniform {
+    // Emitter position, speed in w
+    position_speed: [f32; 4],
+    // Spread, gravity, lifetime and the step in seconds
+    params: [f32; 4],
+    // Frame and particle count, zw unused
+    frame_count: [u32; 4],
+}
+
+// The simulation on the GPU, None on backends without compute shaders
+struct ParticleCompute {
+    pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+// A fountain of glowing sparks, the first user of compute.rs. Where compute shaders are
+// supported the particles never leave the GPU: cs_main steps them in place in the buffer the
+// billboards are drawn from. Elsewhere step_particle runs on the CPU and the whole buffer is
+// uploaded every update, the same simulation at a higher cost.
+pub struct ParticleRenderGroup {
+    pub emitter: Emitter,
+    count: u32,
+    frame: u32,
+    // Only kept on the CPU path
+    particles: Vec<Particle>,
+    particle_buffer: Buffer,
+    compute: Option<ParticleCompute>,
+    render_pipeline: RenderPipeline,
+}
+
+impl ParticleRenderGroup {
+    pub fn new(
+        device: &Device,
+        camera: &Camera,
+        config: &SurfaceConfiguration,
+        emitter: Emitter,
+        count: u32,
+        compute_supported: bool,
+    ) -> Self {
+        let particles = initial_particles(&emitter, count);
+        let particle_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Buffer"),
+            contents: bytemuck::cast_slice(&particles),
+            usage: wgpu::BufferUsages::VERTEX
+                | if compute_supported {
+                    wgpu::BufferUsages::STORAGE
+                } else {
+                    wgpu::BufferUsages::COPY_DST
+                },
+        });
+        let compute = compute_supported.then(|| {
+            let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Particle Uniform Buffer"),
+                size: std::mem::size_of::<ParticleUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let layout = compute::bind_group_layout(
+                device,
+                "Particle Bind Group Layout",
+                &[compute::uniform_entry(0), compute::storage_entry(1, false)],
+            );
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: particle_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("Particle Bind Group"),
+            });
+            ParticleCompute {
+                pipeline: compute::create_pipeline(
+                    device,
+                    "Particle Pipeline",
+                    include_str!("particles.wgsl"),
+                    &[&layout],
+                ),
+                uniform_buffer,
+                bind_group,
+            }
+        });
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("particle_billboard.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[&camera.camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Particle::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    // Added onto the scene, the order of the sparks doesn't matter
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..PRIMITIVE
+            },
+            depth_stencil: texture::Texture::create_depth_state().map(|state| {
+                wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    ..state
+                }
+            }),
+            multisample: multi_sample(),
+            multiview: None,
+        });
+        Self {
+            emitter,
+            count,
+            frame: 0,
+            particles: if compute_supported { vec![] } else { particles },
+            particle_buffer,
+            compute,
+            render_pipeline,
+        }
+    }
+}
+
+impl RenderGroup for ParticleRenderGroup {
+    // Keeps simulating without asking for redraws, reactive mode can still idle
+    fn update(&mut self, dt: Duration, queue: &Queue) {
+        self.frame = self.frame.wrapping_add(1);
+        let dt = dt.as_secs_f32();
+        match &self.compute {
+            Some(compute) => {
+                let [x, y, z] = self.emitter.position;
+                let uniform = ParticleUniform {
+                    position_speed: [x, y, z, self.emitter.speed],
+                    params: [
+                        self.emitter.spread,
+                        self.emitter.gravity,
+                        self.emitter.lifetime,
+                        dt,
+                    ],
+                    frame_count: [self.frame, self.count, 0, 0],
+                };
+                stats::write_buffer(
+                    queue,
+                    &compute.uniform_buffer,
+                    0,
+                    bytemuck::cast_slice(&[uniform]),
+                );
+            }
+            None => {
+                for (index, particle) in self.particles.iter_mut().enumerate() {
+                    step_particle(particle, &self.emitter, dt, index as u32, self.frame);
+                }
+                stats::write_buffer(
+                    queue,
+                    &self.particle_buffer,
+                    0,
+                    bytemuck::cast_slice(&self.particles),
+                );
+            }
+        }
+    }
+
+    fn dispatch<'a, 'b: 'a>(&'b self, pass: &mut ComputePass<'a>) {
+        if let Some(compute) = &self.compute {
+            pass.set_pipeline(&compute.pipeline);
+            pass.set_bind_group(0, &compute.bind_group, &[]);
+            pass.dispatch(compute::workgroup_count(self.count, WORKGROUP_SIZE), 1, 1);
+        }
+    }
+
+    // Highest a particle can fly at full speed straight up, and as far to the sides
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let emitter = &self.emitter;
+        let speed = emitter.speed * 1.25;
+        let reach = (speed * speed / (2. * emitter.gravity.max(0.1))).min(speed * emitter.lifetime);
+        let [x, y, z] = emitter.position;
+        Some(BoundingSphere {
+            center: Point3::new(x, y, z),
+            radius: reach,
+        })
+    }
+
+    fn draw_stats(&self, shadow_pass: bool) -> DrawStats {
+        if shadow_pass || self.count == 0 {
+            return DrawStats::default();
+        }
+        DrawStats::new(1, self.count)
+    }
+
+    fn render<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, shadow_pass: bool) {
+        // Glowing, they neither cast shadows nor write depth
+        if shadow_pass || self.count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(0, self.particle_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.count);
+    }
+}
@@ -0,0 +1,65 @@
+// Steps the particles in place, mirrors particles::step_particle
+struct Particle {
+    position: vec3<f32>,
+    // Not born yet while negative
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+};
+
+struct Simulation {
+    // Emitter position, speed in w
+    position_speed: vec4<f32>,
+    // Spread, gravity, lifetime and the step in seconds
+    params: vec4<f32>,
+    // Frame and particle count
+    frame_count: vec4<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> simulation: Simulation;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+// Mirrors particles::hash
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn unit(x: u32) -> f32 {
+    return f32(x) / 4294967295.0;
+}
+
+// Mirrors particles::spawn_particle
+fn spawn(index: u32) -> Particle {
+    let seed = hash(index ^ hash(simulation.frame_count.x));
+    let angle = unit(seed) * 6.2831853;
+    let tilt = unit(hash(seed)) * simulation.params.x;
+    let speed = simulation.position_speed.w * (0.75 + 0.5 * unit(hash(seed ^ 1u)));
+    var particle: Particle;
+    particle.position = simulation.position_speed.xyz;
+    particle.age = 0.0;
+    particle.velocity = vec3<f32>(sin(tilt) * cos(angle), cos(tilt), sin(tilt) * sin(angle)) * speed;
+    particle.lifetime = simulation.params.z * (0.5 + 0.5 * unit(hash(seed ^ 2u)));
+    return particle;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= simulation.frame_count.y {
+        return;
+    }
+    let dt = simulation.params.w;
+    var particle = particles[index];
+    particle.age += dt;
+    if particle.age >= particle.lifetime {
+        particle = spawn(index);
+    } else if particle.age >= 0.0 {
+        particle.velocity.y -= simulation.params.y * dt;
+        particle.position += particle.velocity * dt;
+    }
+    particles[index] = particle;
+}
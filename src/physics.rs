@@ -0,0 +1,271 @@
+// Rigid body simulation with rapier, enabled by the `physics` feature. Bodies mirror instances
+// of render groups: State::update steps the world at a fixed rate and writes the poses of the
+// moving bodies back into the instances.
+use crate::dynamic_uniform::DynamicRenderGroup;
+use crate::world_space::InstanceTransform;
+use crate::{GroupId, State};
+use cgmath::{Quaternion, Vector3};
+use rapier3d::na;
+use rapier3d::prelude::*;
+use std::time::Duration;
+
+const STEP: f32 = 1. / 60.;
+// A long frame slows the simulation down instead of stalling on catch up steps
+const MAX_STEPS: u32 = 4;
+
+#[derive(Debug, Copy, Clone)]
+pub enum BodyShape {
+    // Half extents
+    Cuboid(Vector3<f32>),
+    Ball(f32),
+    // Everything behind the plane through the instance origin with this local normal
+    HalfSpace(Vector3<f32>),
+}
+
+struct Link {
+    body: RigidBodyHandle,
+    group: GroupId,
+    instance: usize,
+    // Pose last written to the instance, an edit that changes it teleports the body
+    written: InstanceTransform,
+}
+
+pub struct Physics {
+    pipeline: PhysicsPipeline,
+    gravity: Vector<Real>,
+    integration_parameters: IntegrationParameters,
+    islands: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd: CCDSolver,
+    accumulator: f32,
+    links: Vec<Link>,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self {
+            pipeline: PhysicsPipeline::new(),
+            gravity: vector![0., -9.81, 0.],
+            integration_parameters: IntegrationParameters {
+                dt: STEP,
+                ..Default::default()
+            },
+            islands: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies: RigidBodySet::new(),
+            colliders: ColliderSet::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd: CCDSolver::new(),
+            accumulator: 0.,
+            links: vec![],
+        }
+    }
+}
+
+impl Physics {
+    pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
+        self.gravity = vector![gravity.x, gravity.y, gravity.z];
+    }
+
+    // Static bodies never move, dynamic ones fall and collide. The shape is in world units,
+    // the instance scale is not applied.
+    pub fn add_body(
+        &mut self,
+        group: GroupId,
+        instance: usize,
+        transform: InstanceTransform,
+        shape: BodyShape,
+        dynamic: bool,
+    ) -> RigidBodyHandle {
+        let builder = if dynamic {
+            RigidBodyBuilder::new_dynamic()
+        } else {
+            RigidBodyBuilder::new_static()
+        };
+        let body = self
+            .bodies
+            .insert(builder.position(isometry(&transform)).build());
+        let collider = match shape {
+            BodyShape::Cuboid(half) => ColliderBuilder::cuboid(half.x, half.y, half.z),
+            BodyShape::Ball(radius) => ColliderBuilder::ball(radius),
+            BodyShape::HalfSpace(normal) => {
+                let normal = vector![normal.x, normal.y, normal.z];
+                ColliderBuilder::halfspace(na::Unit::new_normalize(normal))
+            }
+        };
+        self.colliders.insert_with_parent(
+            collider.restitution(0.3).build(),
+            body,
+            &mut self.bodies,
+        );
+        self.links.push(Link {
+            body,
+            group,
+            instance,
+            written: transform,
+        });
+        body
+    }
+
+    // Wakes the body up, for throwing and kicking things around regardless of their mass
+    pub fn add_velocity(&mut self, body: RigidBodyHandle, velocity: Vector3<f32>) {
+        if let Some(body) = self.bodies.get_mut(body) {
+            let velocity = body.linvel() + vector![velocity.x, velocity.y, velocity.z];
+            body.set_linvel(velocity, true);
+        }
+    }
+
+    // The body mirroring an instance
+    pub fn body_of(&self, group: GroupId, instance: usize) -> Option<RigidBodyHandle> {
+        self.links
+            .iter()
+            .find(|link| link.group == group && link.instance == instance)
+            .map(|link| link.body)
+    }
+
+    // Number of fixed steps taken
+    pub fn step(&mut self, dt: Duration) -> u32 {
+        self.accumulator = (self.accumulator + dt.as_secs_f32()).min(STEP * MAX_STEPS as f32);
+        let mut steps = 0;
+        while self.accumulator >= STEP {
+            self.pipeline.step(
+                &self.gravity,
+                &self.integration_parameters,
+                &mut self.islands,
+                &mut self.broad_phase,
+                &mut self.narrow_phase,
+                &mut self.bodies,
+                &mut self.colliders,
+                &mut self.impulse_joints,
+                &mut self.multibody_joints,
+                &mut self.ccd,
+                &(),
+                &(),
+            );
+            self.accumulator -= STEP;
+            steps += 1;
+        }
+        steps
+    }
+
+    // Moves the bodies whose instances were changed from outside, and drops the links of
+    // instances that are gone
+    fn sync(&mut self, mut current: impl FnMut(GroupId, usize) -> Option<InstanceTransform>) {
+        let bodies = &mut self.bodies;
+        self.links
+            .retain_mut(|link| match current(link.group, link.instance) {
+                Some(transform) => {
+                    let edited = transform.position != link.written.position
+                        || transform.rotation != link.written.rotation;
+                    if let (true, Some(body)) = (edited, bodies.get_mut(link.body)) {
+                        body.set_position(isometry(&transform), true);
+                        body.set_linvel(Vector::zeros(), true);
+                        body.set_angvel(Vector::zeros(), true);
+                    }
+                    link.written = transform;
+                    true
+                }
+                None => false,
+            });
+    }
+
+    // Poses of the awake dynamic bodies, with the scale their instances had
+    fn moved(&mut self) -> Vec<(GroupId, usize, InstanceTransform)> {
+        let bodies = &self.bodies;
+        self.links
+            .iter_mut()
+            .filter_map(|link| {
+                let body = bodies.get(link.body)?;
+                if !body.is_dynamic() || body.is_sleeping() {
+                    return None;
+                }
+                let position = body.position();
+                let translation = position.translation.vector;
+                let rotation = position.rotation;
+                link.written = InstanceTransform::new(
+                    Vector3::new(translation.x, translation.y, translation.z),
+                    Quaternion::new(rotation.w, rotation.i, rotation.j, rotation.k),
+                )
+                .with_scale(link.written.scale);
+                Some((link.group, link.instance, link.written))
+            })
+            .collect()
+    }
+}
+
+fn isometry(transform: &InstanceTransform) -> Isometry<Real> {
+    let (p, q) = (transform.position, transform.rotation);
+    Isometry::from_parts(
+        na::Translation3::new(p.x, p.y, p.z),
+        na::UnitQuaternion::new_normalize(na::Quaternion::new(q.s, q.v.x, q.v.y, q.v.z)),
+    )
+}
+
+impl State {
+    pub fn physics_mut(&mut self) -> &mut Physics {
+        &mut self.physics
+    }
+
+    // A body starting at the current transform of the instance, None if there is no such instance
+    pub fn add_rigid_body(
+        &mut self,
+        group: GroupId,
+        instance: usize,
+        shape: BodyShape,
+        dynamic: bool,
+    ) -> Option<RigidBodyHandle> {
+        let index = self.render_groups.index_of(group)?;
+        let transform = self.render_groups.get(index)?.transform(instance)?;
+        Some(
+            self.physics
+                .add_body(group, instance, transform, shape, dynamic),
+        )
+    }
+
+    // Bodies of edited instances are moved first, so undo and the edit keys still work
+    pub(crate) fn step_physics(&mut self, dt: Duration) {
+        let groups = &self.render_groups;
+        self.physics
+            .sync(|id, instance| groups.get(groups.index_of(id)?)?.transform(instance));
+        if self.physics.step(dt) == 0 {
+            return;
+        }
+        for (id, instance, transform) in self.physics.moved() {
+            let group = self.render_groups.index_of(id);
+            if let Some(group) = group.and_then(|index| self.render_groups.get_mut(index)) {
+                group.set_transform(instance, transform, &self.queue);
+            }
+        }
+    }
+
+    // The floor holds the demo sphere and the ring of cubes, I kicks the picked one
+    pub(crate) fn add_demo_bodies(&mut self, floor: GroupId) {
+        self.add_rigid_body(floor, 0, BodyShape::HalfSpace(Vector3::unit_z()), false);
+        self.add_rigid_body(self.render_group_sphere, 0, BodyShape::Ball(10.), true);
+        let cubes = self
+            .render_groups
+            .typed::<DynamicRenderGroup>(self.render_group_dynamic)
+            .map_or(0, |group| group.objects.len());
+        for instance in 0..cubes {
+            let shape = BodyShape::Cuboid(Vector3::new(1.5, 1.5, 1.5));
+            self.add_rigid_body(self.render_group_dynamic, instance, shape, true);
+        }
+    }
+
+    pub(crate) fn kick_picked(&mut self, group: usize, instance: usize) {
+        let body = self
+            .render_groups
+            .id(group)
+            .and_then(|id| self.physics.body_of(id, instance));
+        if let Some(body) = body {
+            self.physics.add_velocity(body, Vector3::new(0., 15., 0.));
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// Everything needed to build a scene from another crate, `use learn_graphics::prelude::*`.
+// Meshes, textures and instances are created with `State::device` and `State::queue`, then
+// added with `State::add_mesh_group`. Names here only change with a breaking release.
+
+pub use crate::atlas::{AtlasLayout, TextureAtlas};
+#[cfg(feature = "audio")]
+pub use crate::audio::{Audio, SoundSettings};
+pub use crate::camera::{
+    CameraController, CameraMode, CameraView, Projection, ScreenPoint, Visibility,
+};
+pub use crate::capabilities::RendererCapabilities;
+pub use crate::color_filter::ColorFilter;
+pub use crate::config::{Backend, PowerPreference, RedrawMode, RendererConfig};
+pub use crate::cursor::GrabMode;
+pub use crate::debug_lines::{frustum_corners, DebugView, LineList, LineVertex};
+pub use crate::ecs::{
+    CameraHandle, Components, EntityId, LightHandle, MaterialHandle, MeshHandle, World,
+};
+pub use crate::error::InitError;
+pub use crate::events::{
+    AssetLoaded, EntitySpawned, EventBus, LightChanged, Subscription, WindowResized,
+};
+pub use crate::geo_gen::{
+    capsule_mesh, cone_mesh, create_capsule, create_cone, create_cube, create_cylinder,
+    create_floor, create_icosphere, create_parametric, create_parametric_with_normals,
+    create_plane, create_rounded_cube, create_sphere, create_square, create_torus, cube_mesh,
+    cylinder_mesh, floor_mesh, icosphere_mesh, parametric_mesh, parametric_with_normals_mesh,
+    plane_mesh, rounded_cube_mesh, sphere_mesh, square_mesh, torus_mesh, Entity, GeoObj, MeshData,
+    Vertex,
+};
+pub use crate::glass::Glass;
+pub use crate::hud::HudAnchor;
+pub use crate::light::LightUniform;
+pub use crate::light_animation::{LightAnimator, LightFrame};
+pub use crate::lightmap::{BakeSettings, BakedLight, Lightmap};
+pub use crate::mipmap::MipmapGenerator;
+pub use crate::morph::{MorphAnimation, MorphTarget, MorphTargets};
+pub use crate::noise::{NoiseKind, NoiseSettings};
+pub use crate::normals::{recompute_normals, NormalMode};
+pub use crate::palette::Palette;
+pub use crate::particles::{spawn_particle, step_particle, Emitter, Particle};
+#[cfg(feature = "physics")]
+pub use crate::physics::{BodyShape, Physics};
+pub use crate::render_groups::{GroupId, RenderGroups};
+pub use crate::resources::Assets;
+#[cfg(feature = "scripting")]
+pub use crate::rhai_script::RhaiScript;
+pub use crate::scene::{AmbientMode, SceneUniform};
+pub use crate::scene_commands::{SceneCommand, SceneCommands};
+pub use crate::script::{SceneApi, Script, Spin};
+pub use crate::shadow::ShadowFilter;
+pub use crate::simplify::{lod_chain, simplify};
+pub use crate::skinning::{Skeleton, SkeletonAnimation, SkeletonNode, Skin};
+pub use crate::split_screen::{split_rects, SplitView};
+pub use crate::startup::Startup;
+pub use crate::texture::Texture;
+pub use crate::tint::Tint;
+pub use crate::unwrap::{box_unwrap, planar_unwrap};
+pub use crate::vegetation::{blade_fade, scatter_blades, GrassBlade};
+pub use crate::world_space::{
+    grid, jitter_rotations, jitter_scales, random_colors, scatter, InstanceAttributes,
+    InstanceTransform, Instances,
+};
+pub use crate::{run, RenderGroup, State};
@@ -0,0 +1,126 @@
+use crate::texture;
+use std::borrow::Cow;
+use wgpu::{
+    BindGroup, CommandEncoder, Device, RenderPipeline, SurfaceConfiguration, TextureFormat,
+    TextureView,
+};
+
+// Output of the shaders is linear and relies on an sRGB target to encode it. Some surfaces,
+// browser WebGPU canvases among them, only offer plain Unorm formats that would show it washed
+// out. On those the frame is rendered into `texture` in the sRGB counterpart of the surface
+// format, then copied onto the surface gamma encoded by the shader.
+pub struct PresentPass {
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+    pub texture: texture::Texture,
+}
+
+// What the scene is rendered in for a surface in `surface`: the sRGB variant of 8 bit Unorm
+// formats, the surface format itself otherwise. Float surfaces take linear values as they are.
+pub fn scene_format(surface: TextureFormat) -> TextureFormat {
+    match surface {
+        TextureFormat::Bgra8Unorm => TextureFormat::Bgra8UnormSrgb,
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgb10a2Unorm => TextureFormat::Rgba8UnormSrgb,
+        format => format,
+    }
+}
+
+// The sRGB transfer function present.wgsl applies, `linear` in [0, 1]
+pub fn encode_srgb(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    }
+}
+
+impl PresentPass {
+    // None when the surface is sRGB or float and the scene can be drawn onto it directly
+    pub fn new(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        surface_format: TextureFormat,
+    ) -> Option<Self> {
+        if config.format == surface_format {
+            return None;
+        }
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Present Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("present.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Present Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[surface_format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let texture = create_frame_texture(device, config);
+        let bind_group = create_bind_group(device, &pipeline, &texture);
+        Some(Self {
+            pipeline,
+            bind_group,
+            texture,
+        })
+    }
+
+    pub fn resize(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        self.texture = create_frame_texture(device, config);
+        self.bind_group = create_bind_group(device, &self.pipeline, &self.texture);
+    }
+
+    // Call last, after everything was drawn into `texture`
+    pub fn apply(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Present Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_frame_texture(device: &Device, config: &SurfaceConfiguration) -> texture::Texture {
+    texture::Texture::create_render_target(
+        device,
+        config.width,
+        config.height,
+        config.format,
+        "present_frame",
+    )
+}
+
+fn create_bind_group(
+    device: &Device,
+    pipeline: &RenderPipeline,
+    texture: &texture::Texture,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(&texture.view),
+        }],
+        label: Some("Present Bind Group"),
+    })
+}
@@ -0,0 +1,187 @@
+// Fills reflection probes with the scene around them instead of the sky. The six faces of a probe
+// are rendered like any render target, then mirrored into its layers of the probe texture since
+// cube faces are left-handed. One probe is captured per frame, after the shadow maps of the frame.
+use crate::camera::{Camera, CameraView, Projection};
+use crate::light::LightRenderGroup;
+use crate::probes::{ReflectionProbes, PROBE_SIZE};
+use crate::render_target::RenderTarget;
+use crate::scene::Scene;
+use crate::shadow::ShadowPass;
+use crate::stats::DrawStats;
+use crate::{texture, RenderGroup, State};
+use cgmath::{Deg, EuclideanSpace, Point3, Vector3};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use wgpu::{BindGroup, CommandEncoder, Device, Queue, RenderPipeline, SurfaceConfiguration};
+
+pub(crate) struct ProbeCapture {
+    faces: Vec<RenderTarget>,
+    pipeline: RenderPipeline,
+    // Sampling the color texture of each face
+    bind_groups: Vec<BindGroup>,
+    pending: VecDeque<usize>,
+    // Drawn in the coming frame
+    current: Option<usize>,
+}
+
+impl ProbeCapture {
+    pub fn new(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        light_render_group: &LightRenderGroup,
+        scene: &Scene,
+    ) -> Self {
+        let faces: Vec<_> = (0..6)
+            .map(|face| {
+                let view = CameraView::cube_face(Point3::origin(), face);
+                let projection = Projection::new(PROBE_SIZE, PROBE_SIZE, Deg(90.), 0.1, 800.);
+                let camera = Camera::new(view, projection, device);
+                RenderTarget::with_camera(device, config, light_render_group, scene, camera)
+            })
+            .collect();
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Probe Capture Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("blit.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Probe Capture Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_mirror",
+                targets: &[texture::TEXTURE_FORMAT.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let layout = pipeline.get_bind_group_layout(0);
+        let bind_groups = faces
+            .iter()
+            .map(|face| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&face.color_texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&face.color_texture.sampler),
+                        },
+                    ],
+                    label: Some("Probe Capture bind group"),
+                })
+            })
+            .collect();
+        Self {
+            faces,
+            pipeline,
+            bind_groups,
+            pending: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    // Captured in a later frame, in the order requested
+    pub fn request(&mut self, probe: usize) {
+        if !self.pending.contains(&probe) {
+            self.pending.push_back(probe);
+        }
+    }
+
+    // The probe drawn in the coming frame
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    // Points the faces at the next probe waiting, before the frame is drawn
+    pub fn update(&mut self, queue: &Queue, probes: &ReflectionProbes) {
+        self.current = None;
+        while let Some(probe) = self.pending.pop_front() {
+            if let Some(position) = probes.position(probe) {
+                for (face, target) in self.faces.iter_mut().enumerate() {
+                    target.camera.view = CameraView::cube_face(Point3::from_vec(position), face);
+                    target.update(queue);
+                }
+                self.current = Some(probe);
+                return;
+            }
+        }
+    }
+
+    // Draws the faces of the current probe into its layers of the probe texture, mip 0 only
+    pub fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        shadow_pass: &ShadowPass,
+        groups: &[&dyn RenderGroup],
+        probes: &ReflectionProbes,
+    ) -> DrawStats {
+        let mut draws = DrawStats::default();
+        let probe = match self.current {
+            Some(probe) => probe,
+            None => return draws,
+        };
+        for (face, (target, bind_group)) in self.faces.iter().zip(&self.bind_groups).enumerate() {
+            draws += target.render(encoder, shadow_pass, groups);
+            let layer = probes
+                .texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("probe face"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: 0,
+                    mip_level_count: NonZeroU32::new(1),
+                    base_array_layer: (probe * 6 + face) as u32,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                });
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Probe Capture Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &layer,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        draws
+    }
+}
+
+impl State {
+    // Renders the probe again in a coming frame, after the scene around it changed.
+    // False if there is no such probe.
+    pub fn capture_probe(&mut self, probe: usize) -> bool {
+        if probe >= self.scene.probes.len() {
+            return false;
+        }
+        self.probe_capture.request(probe);
+        self.request_redraw();
+        true
+    }
+
+    // Places a probe captured in the coming frame, None when all slots are taken.
+    // Objects already nearer to it than to the others see it black until then.
+    pub fn add_probe(&mut self, position: Vector3<f32>) -> Option<usize> {
+        let probe = self.scene.probes.add(&self.queue, position)?;
+        self.capture_probe(probe);
+        Some(probe)
+    }
+}
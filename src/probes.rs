@@ -0,0 +1,256 @@
+use crate::{mipmap, stats, texture};
+use bytemuck::Zeroable;
+use cgmath::{InnerSpace, Vector3};
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, Ordering};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupEntry, BindGroupLayoutEntry, Buffer, Device, Queue};
+
+// Texels per probe face
+pub const PROBE_SIZE: u32 = 128;
+// Down to 1x1, the shaders read ambient light from mip 5 with 4x4 texels a face
+pub const PROBE_MIPS: u32 = 8;
+// Slots in the uniform, the shaders declare the same length
+const MAX_PROBES: usize = 8;
+
+// Whether the adapter has cube array textures, set at startup from RendererCapabilities
+pub(crate) static CUBE_ARRAYS: AtomicBool = AtomicBool::new(true);
+
+fn cube_arrays() -> bool {
+    CUBE_ARRAYS.load(Ordering::Relaxed)
+}
+
+// Cubes in the texture, only one can be bound without cube arrays
+fn probe_capacity() -> usize {
+    if cube_arrays() {
+        MAX_PROBES
+    } else {
+        1
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProbeUniform {
+    // xyz world position, w unused
+    positions: [[f32; 4]; MAX_PROBES],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+// Localized reflections, every probe is one cube of a cube array bound with the lights in group 1.
+// Each object reflects the probe nearest to its origin, weighted by its reflectivity, or the sky
+// cube while there are no probes. With the spherical harmonics ambient model the nearest probe
+// also lights it, see probe_capture.rs for filling them from the scene.
+pub struct ReflectionProbes {
+    pub texture: texture::Texture,
+    // A copy of the skybox at probe size, black until written
+    pub sky: texture::Texture,
+    positions: Vec<Vector3<f32>>,
+    buffer: Buffer,
+}
+
+impl ReflectionProbes {
+    pub fn new(device: &Device) -> Self {
+        let texture = texture::Texture::create_cube_array(
+            device,
+            PROBE_SIZE,
+            probe_capacity() as u32,
+            PROBE_MIPS,
+            "reflection probes",
+        );
+        let sky = texture::Texture::create_cube(device, PROBE_SIZE, "sky reflection");
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Reflection Probe Buffer"),
+            contents: bytemuck::cast_slice(&[ProbeUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            texture,
+            sky,
+            positions: vec![],
+            buffer,
+        }
+    }
+
+    // The index of the new probe, None when all slots are taken.
+    // Its faces stay black until written.
+    pub fn add(&mut self, queue: &Queue, position: Vector3<f32>) -> Option<usize> {
+        if self.positions.len() == probe_capacity() {
+            return None;
+        }
+        self.positions.push(position);
+        self.write_uniform(queue);
+        Some(self.positions.len() - 1)
+    }
+
+    pub fn position(&self, probe: usize) -> Option<Vector3<f32>> {
+        self.positions.get(probe).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    // Nearest probe to a world position, mirrors nearest_probe in geo.wgsl
+    pub fn nearest(&self, position: Vector3<f32>) -> Option<usize> {
+        self.positions
+            .iter()
+            .map(|probe| (probe - position).magnitude2())
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(i, _)| i)
+    }
+
+    // PROBE_SIZE square faces in posx, negx, posy, negy, posz, negz order
+    pub fn write_faces(&self, queue: &Queue, probe: usize, faces: &[image::RgbaImage]) {
+        write_cube(queue, &self.texture.texture, probe, faces);
+    }
+
+    // Averages the faces down for the ambient light, after the first mip of a probe changed
+    pub fn generate_mips(&self, device: &Device, queue: &Queue) {
+        let layers = (self.positions.len() * 6) as u32;
+        if layers > 0 {
+            mipmap::generate_mipmaps(
+                device,
+                queue,
+                &self.texture.texture,
+                texture::TEXTURE_FORMAT,
+                PROBE_MIPS,
+                layers,
+            );
+        }
+    }
+
+    // Faces like write_faces, reflected by objects while there are no probes
+    pub fn write_sky(&self, queue: &Queue, faces: &[image::RgbaImage]) {
+        write_cube(queue, &self.sky.texture, 0, faces);
+    }
+
+    fn write_uniform(&self, queue: &Queue) {
+        let mut uniform = ProbeUniform::zeroed();
+        for (slot, position) in uniform.positions.iter_mut().zip(&self.positions) {
+            *slot = [position.x, position.y, position.z, 1.];
+        }
+        uniform.count = self.positions.len() as u32;
+        stats::write_buffer(queue, &self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Bindings 2 to 4 and the sky at 8 of the light bind group
+    pub(crate) fn layout_entries() -> [BindGroupLayoutEntry; 4] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    multisampled: false,
+                    view_dimension: if cube_arrays() {
+                        wgpu::TextureViewDimension::CubeArray
+                    } else {
+                        wgpu::TextureViewDimension::Cube
+                    },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    pub(crate) fn bind_group_entries(&self) -> [BindGroupEntry; 4] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&self.texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: self.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(&self.sky.view),
+            },
+        ]
+    }
+}
+
+// Layers `probe * 6` to `probe * 6 + 5` of `texture`
+fn write_cube(queue: &Queue, texture: &wgpu::Texture, probe: usize, faces: &[image::RgbaImage]) {
+    for (face, image) in faces.iter().enumerate().take(6) {
+        if image.dimensions() != (PROBE_SIZE, PROBE_SIZE) {
+            log::error!("Probe faces must be {0}x{0}", PROBE_SIZE);
+            return;
+        }
+        stats::count_upload(image.len());
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: (probe * 6 + face) as u32,
+                },
+            },
+            image,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * PROBE_SIZE),
+                rows_per_image: NonZeroU32::new(PROBE_SIZE),
+            },
+            wgpu::Extent3d {
+                width: PROBE_SIZE,
+                height: PROBE_SIZE,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+// Fills in the probe texture type of the shaders including the probe bindings
+pub(crate) fn shader_source(source: &str) -> String {
+    if cube_arrays() {
+        source
+            .replace("PROBE_TEXTURE", "texture_cube_array<f32>")
+            .replace("PROBE_INDEX", "probe,")
+    } else {
+        source
+            .replace("PROBE_TEXTURE", "texture_cube<f32>")
+            .replace("PROBE_INDEX", "")
+    }
+}
@@ -0,0 +1,41 @@
+// CPU scope profiling with puffin, enabled by the native only `profiling` feature.
+// Connect puffin_viewer to the address below while the app runs.
+// Without the feature every call compiles to nothing.
+
+#[cfg(all(feature = "profiling", not(target_arch = "wasm32")))]
+const SERVER_ADDRESS: &str = "127.0.0.1:8585";
+
+#[cfg(all(feature = "profiling", not(target_arch = "wasm32")))]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        puffin::profile_scope!($name);
+    };
+}
+
+#[cfg(not(all(feature = "profiling", not(target_arch = "wasm32"))))]
+macro_rules! profile_scope {
+    ($name:expr) => {};
+}
+
+pub(crate) use profile_scope;
+
+// Starts the server, it lives for the rest of the process
+pub(crate) fn init() {
+    #[cfg(all(feature = "profiling", not(target_arch = "wasm32")))]
+    {
+        puffin::set_scopes_on(true);
+        match puffin_http::Server::new(SERVER_ADDRESS) {
+            Ok(server) => {
+                log::warn!("Puffin server listening on {}", SERVER_ADDRESS);
+                std::mem::forget(server);
+            }
+            Err(e) => log::error!("Failed to start the puffin server: {}", e),
+        }
+    }
+}
+
+// Closes the scopes of the previous frame, call once per frame
+pub(crate) fn new_frame() {
+    #[cfg(all(feature = "profiling", not(target_arch = "wasm32")))]
+    puffin::GlobalProfiler::lock().new_frame();
+}
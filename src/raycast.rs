@@ -0,0 +1,159 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::geo_gen::Vertex;
+use cgmath::{ElementWise, EuclideanSpace, InnerSpace, Point3, Quaternion, Rotation, Vector3};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    // Always normalized so hit distances are in world units
+    pub direction: Vector3<f32>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RayHit {
+    pub distance: f32,
+    pub position: Point3<f32>,
+    // Interpolated vertex normal, flipped to face the ray
+    pub normal: Vector3<f32>,
+    pub uv: [f32; 2],
+    // Index into the mesh's index buffer divided by 3
+    pub triangle: usize,
+    pub instance: usize,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // The same ray seen from the local space of an instance. Distances along it are in local
+    // units when the instance is scaled.
+    pub fn to_local(
+        &self,
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Self {
+        let inverse = rotation.invert();
+        let origin = inverse.rotate_vector(self.origin.to_vec() - position);
+        let direction = inverse.rotate_vector(self.direction);
+        Self::new(
+            Point3::from_vec(origin.div_element_wise(scale)),
+            direction.div_element_wise(scale),
+        )
+    }
+
+    // Distance to the first intersection, 0 if the origin is inside
+    pub fn intersect_sphere(&self, sphere: &BoundingSphere) -> Option<f32> {
+        let to_center = sphere.center - self.origin;
+        let along = to_center.dot(self.direction);
+        let distance2 = to_center.magnitude2() - along * along;
+        let radius2 = sphere.radius * sphere.radius;
+        if distance2 > radius2 {
+            return None;
+        }
+        let half_chord = (radius2 - distance2).sqrt();
+        if along + half_chord < 0. {
+            return None;
+        }
+        Some((along - half_chord).max(0.))
+    }
+
+    // Slab test, the distance to where the ray enters, 0 if the origin is inside
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut near = 0f32;
+        let mut far = f32::INFINITY;
+        for axis in 0..3 {
+            let inverse = 1. / self.direction[axis];
+            let a = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let b = (aabb.max[axis] - self.origin[axis]) * inverse;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+
+    // Möller–Trumbore, both faces count since entities are drawn without culling.
+    // Returns the distance and the barycentric weights of the second and third vertex.
+    pub fn intersect_triangle(&self, [a, b, c]: [Point3<f32>; 3]) -> Option<(f32, f32, f32)> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let inv_det = 1. / det;
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = edge2.dot(q) * inv_det;
+        if t < 0. {
+            return None;
+        }
+        Some((t, u, v))
+    }
+}
+
+// Closest hit against every triangle of a mesh, in the mesh's local space
+pub fn raycast_mesh(ray: &Ray, vertices: &[Vertex], indices: &[u32]) -> Option<RayHit> {
+    (0..indices.len() / 3)
+        .map(|triangle| raycast_triangle(ray, vertices, indices, triangle))
+        .fold(None, closer)
+}
+
+// Hit against one triangle of a mesh with its normal and uv interpolated
+pub(crate) fn raycast_triangle(
+    ray: &Ray,
+    vertices: &[Vertex],
+    indices: &[u32],
+    triangle: usize,
+) -> Option<RayHit> {
+    let face = &indices[triangle * 3..triangle * 3 + 3];
+    let [a, b, c] = [face[0], face[1], face[2]].map(|i| &vertices[i as usize]);
+    let positions = [a, b, c].map(|v| Point3::from(v.position));
+    let (distance, u, v) = ray.intersect_triangle(positions)?;
+    let w = 1. - u - v;
+    let normal =
+        Vector3::from(a.normal) * w + Vector3::from(b.normal) * u + Vector3::from(c.normal) * v;
+    let normal = if normal.dot(ray.direction) > 0. {
+        -normal.normalize()
+    } else {
+        normal.normalize()
+    };
+    let uv = [0, 1].map(|i| a.tex_coords[i] * w + b.tex_coords[i] * u + c.tex_coords[i] * v);
+    Some(RayHit {
+        distance,
+        position: ray.at(distance),
+        normal,
+        uv,
+        triangle,
+        instance: 0,
+    })
+}
+
+// Keeps whichever hit is nearer
+pub fn closer(a: Option<RayHit>, b: Option<RayHit>) -> Option<RayHit> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.distance < a.distance { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{bail, Result};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FieldKind {
+    Float,
+    Uint,
+    Vec2,
+    Vec3,
+    Vec4,
+    // A vec4 edited as rgb, alpha is left to the renderer
+    Color,
+    Mat4,
+}
+
+impl FieldKind {
+    pub fn components(self) -> usize {
+        match self {
+            FieldKind::Float | FieldKind::Uint => 1,
+            FieldKind::Vec2 => 2,
+            FieldKind::Vec3 => 3,
+            FieldKind::Vec4 | FieldKind::Color => 4,
+            FieldKind::Mat4 => 16,
+        }
+    }
+}
+
+// Describes one field of a uniform struct for the inspector, serializer and scripts
+#[derive(Debug, Copy, Clone)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub display_name: &'static str,
+    pub kind: FieldKind,
+    // Inclusive bounds applied to every component when writing
+    pub range: Option<(f32, f32)>,
+    // Byte offset into the uniform
+    pub offset: usize,
+}
+
+// Implemented with uniform_fields!, which fails to compile when a listed field is renamed
+// and panics on first use when one changes size, so the metadata can't drift from the struct.
+pub trait UniformFields: bytemuck::Pod {
+    fn fields() -> Vec<FieldInfo>;
+
+    fn field(name: &str) -> Option<FieldInfo> {
+        Self::fields().into_iter().find(|field| field.name == name)
+    }
+
+    // Components of a field as floats, uints are converted
+    fn read_field(&self, name: &str) -> Option<Vec<f32>> {
+        let field = Self::field(name)?;
+        let bytes = &bytemuck::bytes_of(self)[field.offset..];
+        let values = (0..field.kind.components()).map(|i| {
+            let word: [u8; 4] = bytes[i * 4..i * 4 + 4].try_into().unwrap();
+            match field.kind {
+                FieldKind::Uint => u32::from_ne_bytes(word) as f32,
+                _ => f32::from_ne_bytes(word),
+            }
+        });
+        Some(values.collect())
+    }
+
+    // Colors accept 3 components, everything else needs all of them
+    fn write_field(&mut self, name: &str, values: &[f32]) -> Result<()> {
+        let field = match Self::field(name) {
+            Some(field) => field,
+            None => bail!("Unknown field {}", name),
+        };
+        let expected = field.kind.components();
+        if values.len() != expected && !(field.kind == FieldKind::Color && values.len() == 3) {
+            bail!("{} takes {} values, got {}", name, expected, values.len());
+        }
+        let bytes = &mut bytemuck::bytes_of_mut(self)[field.offset..];
+        for (i, value) in values.iter().enumerate() {
+            let value = match field.range {
+                Some((min, max)) => value.clamp(min, max),
+                None => *value,
+            };
+            let word = match field.kind {
+                FieldKind::Uint => (value.max(0.) as u32).to_ne_bytes(),
+                _ => value.to_ne_bytes(),
+            };
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&word);
+        }
+        Ok(())
+    }
+}
+
+// uniform_fields!(Type {
+//     field: Kind => "Display name",
+//     ranged: Kind(min, max) => "Display name",
+// });
+macro_rules! uniform_fields {
+    (@range) => {
+        None
+    };
+    (@range $min:expr, $max:expr) => {
+        Some(($min, $max))
+    };
+    ($ty:ty { $($field:ident: $kind:ident $(($min:expr, $max:expr))? => $display:expr),* $(,)? }) => {
+        impl $crate::reflect::UniformFields for $ty {
+            fn fields() -> Vec<$crate::reflect::FieldInfo> {
+                let base = <$ty as bytemuck::Zeroable>::zeroed();
+                let start = &base as *const $ty as usize;
+                vec![$({
+                    let kind = $crate::reflect::FieldKind::$kind;
+                    assert_eq!(
+                        std::mem::size_of_val(&base.$field),
+                        kind.components() * 4,
+                        "{} is not a {:?}",
+                        stringify!($field),
+                        kind,
+                    );
+                    $crate::reflect::FieldInfo {
+                        name: stringify!($field),
+                        display_name: $display,
+                        kind,
+                        range: $crate::reflect::uniform_fields!(@range $($min, $max)?),
+                        offset: &base.$field as *const _ as usize - start,
+                    }
+                }),*]
+            }
+        }
+    };
+}
+
+pub(crate) use uniform_fields;
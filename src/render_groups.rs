@@ -0,0 +1,113 @@
+use crate::RenderGroup;
+use std::any::Any;
+
+// Render groups are Send + Sync on native so the scene can move to other threads and be
+// recorded from several at once. wgpu resources are neither on the web, where there are no
+// threads anyway.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait ThreadSafe: Send + Sync {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send + Sync> ThreadSafe for T {}
+
+#[cfg(target_arch = "wasm32")]
+pub trait ThreadSafe {}
+#[cfg(target_arch = "wasm32")]
+impl<T> ThreadSafe for T {}
+
+// Lets RenderGroups hand out a group as its concrete type
+pub trait AsAny: Any {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Stays valid while the group moves around the list, unlike its index
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GroupId(u64);
+
+// Owns every render group in draw order. Indices are what picking and editing use,
+// ids are for code that keeps a handle to one group.
+#[derive(Default)]
+pub struct RenderGroups {
+    groups: Vec<(GroupId, Box<dyn RenderGroup>)>,
+    next_id: u64,
+}
+
+impl RenderGroups {
+    pub fn push(&mut self, group: impl RenderGroup + 'static) -> GroupId {
+        let id = self.new_id();
+        self.groups.push((id, Box::new(group)));
+        id
+    }
+
+    // For a group that is inserted later
+    pub fn new_id(&mut self) -> GroupId {
+        let id = GroupId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    // Puts back a group taken out with `remove`, or a new one with an id from `new_id`
+    pub fn insert(&mut self, index: usize, group: (GroupId, Box<dyn RenderGroup>)) {
+        let index = index.min(self.groups.len());
+        self.groups.insert(index, group);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<(GroupId, Box<dyn RenderGroup>)> {
+        (index < self.groups.len()).then(|| self.groups.remove(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&dyn RenderGroup> {
+        self.groups.get(index).map(|(_, group)| &**group)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut dyn RenderGroup> {
+        match self.groups.get_mut(index) {
+            Some((_, group)) => Some(&mut **group),
+            None => None,
+        }
+    }
+
+    pub fn id(&self, index: usize) -> Option<GroupId> {
+        self.groups.get(index).map(|(id, _)| *id)
+    }
+
+    pub fn index_of(&self, id: GroupId) -> Option<usize> {
+        self.groups.iter().position(|(x, _)| *x == id)
+    }
+
+    // None once the group was removed or if it is not a T
+    pub fn typed<T: RenderGroup + 'static>(&self, id: GroupId) -> Option<&T> {
+        let group = self.groups.iter().find(|(x, _)| *x == id)?;
+        (*group.1).as_any().downcast_ref()
+    }
+
+    pub fn typed_mut<T: RenderGroup + 'static>(&mut self, id: GroupId) -> Option<&mut T> {
+        let group = self.groups.iter_mut().find(|(x, _)| *x == id)?;
+        (*group.1).as_any_mut().downcast_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GroupId, &dyn RenderGroup)> {
+        self.groups.iter().map(|(id, group)| (*id, &**group))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn RenderGroup>> {
+        self.groups.iter_mut().map(|(_, group)| group)
+    }
+}
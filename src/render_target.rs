@@ -0,0 +1,134 @@
+use crate::camera::{Camera, CameraView, Projection};
+use crate::depth_prepass::DepthPrePass;
+use crate::geo_gen::{Entity, GeoObj};
+use crate::light::LightRenderGroup;
+use crate::scene::Scene;
+use crate::shadow::ShadowPass;
+use crate::stats::DrawStats;
+use crate::{
+    create_multisampled_view, render_scene, texture, GroupId, RenderGroup, SceneCamera,
+    DEPTH_PRE_PASS,
+};
+use wgpu::{CommandEncoder, Device, Queue, SurfaceConfiguration, TextureView};
+
+// Renders the scene from a secondary camera into a texture that entities can sample,
+// e.g. a security camera monitor or a mirror.
+pub struct RenderTarget {
+    pub(crate) camera: Camera,
+    pub(crate) color_texture: texture::Texture,
+    msaa_view: TextureView,
+    depth_texture: texture::Texture,
+    depth_pre_pass: Option<DepthPrePass>,
+    // Groups sampling this target can't be drawn into it in the same frame
+    hidden: Vec<GroupId>,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        light_render_group: &LightRenderGroup,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+        view: CameraView,
+    ) -> Self {
+        let camera = Camera::new(
+            view,
+            Projection::new(width, height, cgmath::Deg(45.0), 1., 800.0),
+            device,
+        );
+        Self::with_camera(device, config, light_render_group, scene, camera)
+    }
+
+    // The texture has the size of the camera's projection
+    pub fn with_camera(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        light_render_group: &LightRenderGroup,
+        scene: &Scene,
+        camera: Camera,
+    ) -> Self {
+        let (width, height) = camera.projection.size();
+        // Same format and sample count as the surface so every scene pipeline can draw into it
+        let color_texture = texture::Texture::create_render_target(
+            device,
+            width,
+            height,
+            config.format,
+            "render_target_color",
+        );
+        let msaa_view = create_multisampled_view(device, width, height, config.format);
+        let depth_texture = texture::Texture::create_depth_texture_sized(
+            device,
+            width,
+            height,
+            "render_target_depth",
+        );
+        // Each view needs its own pre-pass uniform, they are all written before the frame is submitted
+        let depth_pre_pass = if DEPTH_PRE_PASS {
+            Some(DepthPrePass::new(device, light_render_group, scene))
+        } else {
+            None
+        };
+        Self {
+            camera,
+            color_texture,
+            msaa_view,
+            depth_texture,
+            depth_pre_pass,
+            hidden: vec![],
+        }
+    }
+
+    pub fn create_entity(&self, device: &Device, obj: GeoObj) -> Entity {
+        Entity::with_texture(device, obj, &self.color_texture)
+    }
+
+    pub fn hide(&mut self, group: GroupId) {
+        self.hidden.push(group);
+    }
+
+    pub(crate) fn is_hidden(&self, group: GroupId) -> bool {
+        self.hidden.contains(&group)
+    }
+
+    // Sees what `camera` sees, stretched over the texture, for surfaces sampling the scene
+    // behind them in screen space
+    pub fn follow(&mut self, camera: &Camera) {
+        let (width, height) = self.camera.projection.size();
+        self.camera.view = camera.view.clone();
+        self.camera.projection = camera.projection.resized(width, height);
+        self.camera.set_relative(camera.is_relative());
+    }
+
+    pub fn update(&mut self, queue: &Queue) {
+        self.camera.update_camera(queue);
+        if let Some(depth_pre_pass) = &self.depth_pre_pass {
+            depth_pre_pass.update(queue, &self.camera);
+        }
+    }
+
+    pub(crate) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        shadow_pass: &ShadowPass,
+        groups: &[&dyn RenderGroup],
+    ) -> DrawStats {
+        let camera = SceneCamera {
+            camera: &self.camera,
+            depth_pre_pass: self.depth_pre_pass.as_ref(),
+            viewport: None,
+            culled: false,
+        };
+        render_scene(
+            encoder,
+            &self.color_texture.view,
+            &self.msaa_view,
+            &self.depth_texture.view,
+            &[camera],
+            shadow_pass,
+            groups,
+        )
+    }
+}
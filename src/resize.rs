@@ -0,0 +1,92 @@
+use instant::{Duration, Instant};
+use winit::dpi::PhysicalSize;
+#[cfg(target_arch = "wasm32")]
+use {
+    std::{cell::Cell, rc::Rc},
+    wasm_bindgen::prelude::*,
+    winit::window::Window,
+};
+
+// Every resize recreates the multisampled, depth and post-processing attachments, doing it for
+// each event of a window drag stutters. Sizes are applied once they stop changing for `delay`,
+// frames are skipped meanwhile since the surface would not match them.
+pub struct ResizeDebouncer {
+    pub delay: Duration,
+    pending: Option<(PhysicalSize<u32>, Instant)>,
+}
+
+impl ResizeDebouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending: None,
+        }
+    }
+
+    // The latest size wins and restarts the delay. Minimized windows report 0, those keep the
+    // attachments as they are.
+    pub fn request(&mut self, size: PhysicalSize<u32>, now: Instant) {
+        if size.width > 0 && size.height > 0 {
+            self.pending = Some((size, now));
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // When the pending size is applied if no other comes in
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, requested)| requested + self.delay)
+    }
+
+    // The pending size once the deadline has passed
+    pub fn take_ready(&mut self, now: Instant) -> Option<PhysicalSize<u32>> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => self.pending.take().map(|(size, _)| size),
+            _ => None,
+        }
+    }
+}
+
+// Not part of the stable web-sys API yet
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    type ResizeObserver;
+    #[wasm_bindgen(constructor)]
+    fn new(callback: &JsValue) -> ResizeObserver;
+    #[wasm_bindgen(method)]
+    fn observe(this: &ResizeObserver, target: &web_sys::Element);
+}
+
+// Keeps the canvas the size of the element the page put it in. New sizes are left in `resized`
+// for the event loop, like the page visibility.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn fit_canvas_to_parent(
+    window: Rc<Window>,
+    resized: Rc<Cell<Option<PhysicalSize<u32>>>>,
+) {
+    use winit::dpi::LogicalSize;
+    use winit::platform::web::WindowExtWebSys;
+    let parent = match window.canvas().parent_element() {
+        Some(parent) => parent,
+        None => return,
+    };
+    let fit = {
+        let parent = parent.clone();
+        move || {
+            let width = parent.client_width().max(1);
+            let height = parent.client_height().max(1);
+            window.set_inner_size(LogicalSize::new(width, height));
+            resized.set(Some(window.inner_size()));
+        }
+    };
+    // The observer reports asynchronously, the state is created at the right size
+    fit();
+    let closure = Closure::wrap(Box::new(fit) as Box<dyn FnMut()>);
+    let observer = ResizeObserver::new(closure.as_ref());
+    observer.observe(&parent);
+    closure.forget();
+    std::mem::forget(observer);
+}
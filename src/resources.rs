@@ -1,82 +1,253 @@
+use std::collections::HashMap;
 use std::io::{BufReader, Cursor};
+use std::sync::Arc;
 
-use cfg_if::cfg_if;
+use anyhow::{bail, Context};
+use cgmath::{InnerSpace, Matrix, Matrix4, Point3, Quaternion, SquareMatrix, Transform, Vector3};
+use serde::Deserialize;
 use wgpu::util::DeviceExt;
 
-use crate::geo_gen::Vertex;
+use crate::bounds::BoundingSphere;
+use crate::config::RendererConfig;
+use crate::geo_gen::{MeshData, Vertex};
 use crate::model::MaterialUniform;
-use crate::{model, texture};
+use crate::morph::{MeshMorph, MorphAnimation, MorphTarget, MorphTargets};
+use crate::normals::{recompute_normals, NormalMode};
+use crate::profiling::profile_scope;
+use crate::simplify::lod_chain;
+use crate::skinning::{
+    ChannelKeys, Influence, MeshSkin, NodeChannel, Skeleton, SkeletonAnimation, SkeletonNode, Skin,
+    SkinBinding,
+};
+use crate::tint::Tint;
+use crate::{model, texture, texture_decode};
 use rayon::prelude::*;
 
-#[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let base = reqwest::Url::parse(&format!(
-        "{}/{}/",
-        location.origin().unwrap(),
-        option_env!("RES_PATH").unwrap_or("obj"),
-    ))
-    .unwrap();
-    base.join(file_name).unwrap()
-}
-
-pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let txt = reqwest::get(url)
-                .await?
-                .text()
-                .await?;
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("obj")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
+const MANIFEST_FILE: &str = "assets.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    // Names used by the code and the model files, mapped to paths under the root
+    #[serde(default)]
+    files: HashMap<String, String>,
+}
+
+// Where textures and models are read from. A name is first looked up in the manifest of the
+// root, then the path is tried in every override directory before the root, so single files can
+// be replaced without copying the rest. Native builds read the file system, the web fetches the
+// same paths relative to the page. Shaders stay compiled in.
+#[derive(Debug, Clone)]
+pub struct Assets {
+    root: String,
+    overrides: Vec<String>,
+    files: HashMap<String, String>,
+    // Handed over by the user instead of read, like files dropped onto the page. Looked up by
+    // file name before any path is tried.
+    dropped: Arc<HashMap<String, Vec<u8>>>,
+}
+
+impl Assets {
+    // An empty root is the obj directory copied into the build, or served next to the page
+    pub fn new(root: &str, overrides: &[String]) -> Self {
+        Self {
+            root: if root.is_empty() {
+                default_root()
+            } else {
+                root.to_string()
+            },
+            overrides: overrides.to_vec(),
+            files: HashMap::new(),
+            dropped: Arc::default(),
+        }
+    }
+
+    // Keyed by file name, the directories of the paths in model files are ignored for these
+    pub fn with_dropped_files(mut self, files: HashMap<String, Vec<u8>>) -> Self {
+        self.dropped = Arc::new(files);
+        self
+    }
+
+    // With the manifest of the root if there is one, an invalid one is ignored with an error
+    pub async fn from_config(config: &RendererConfig) -> Self {
+        let mut assets = Self::new(&config.asset_root, &config.asset_overrides);
+        let manifest = match read(&join(&assets.root, MANIFEST_FILE)).await {
+            Ok(Some(bytes)) => String::from_utf8(bytes)
+                .map_err(anyhow::Error::from)
+                .and_then(|source| assets.set_manifest(&source)),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = manifest {
+            log::error!("Invalid asset manifest, ignoring it: {:?}", e);
         }
+        assets
+    }
+
+    pub fn set_manifest(&mut self, source: &str) -> anyhow::Result<()> {
+        let manifest: Manifest = toml::from_str(source)?;
+        self.files = manifest.files;
+        Ok(())
+    }
+
+    // Paths tried for `name`, in order
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        let path = self.files.get(name).map_or(name, String::as_str);
+        self.overrides
+            .iter()
+            .chain(Some(&self.root))
+            .map(|dir| join(dir, path))
+            .collect()
+    }
+
+    pub async fn load_string(&self, name: &str) -> anyhow::Result<String> {
+        profile_scope!("load_string");
+        Ok(String::from_utf8(self.load_binary(name).await?)?)
     }
 
-    Ok(txt)
-}
-
-pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let data = reqwest::get(url)
-                .await?
-                .bytes()
-                .await?
-                .to_vec();
-        } else {
-            println!("texture file_name: {}", file_name);
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("obj")
-                .join(file_name);
-            let data = std::fs::read(path)?;
+    pub async fn load_binary(&self, name: &str) -> anyhow::Result<Vec<u8>> {
+        profile_scope!("load_binary");
+        if let Some(data) = self.dropped.get(split_asset_path(name).1) {
+            return Ok(data.clone());
         }
+        let candidates = self.candidates(name);
+        for path in &candidates {
+            if let Some(data) = read(path).await? {
+                return Ok(data);
+            }
+        }
+        if let Some(data) = embedded(name) {
+            return Ok(data.to_vec());
+        }
+        bail!("Asset {} not found, tried {:?}", name, candidates)
     }
+}
 
-    Ok(data)
+fn join(dir: &str, path: &str) -> String {
+    if dir.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), path)
+    }
 }
 
-pub async fn load_texture(
-    file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name, 1)
+#[cfg(target_arch = "wasm32")]
+fn default_root() -> String {
+    option_env!("RES_PATH").unwrap_or("obj").to_string()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_root() -> String {
+    format!("{}/obj", env!("OUT_DIR"))
+}
+
+// None if there is no such file, other failures are errors
+#[cfg(target_arch = "wasm32")]
+async fn read(path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let origin = web_sys::window().unwrap().location().origin().unwrap();
+    // Absolute URLs replace the origin
+    let url = reqwest::Url::parse(&format!("{}/", origin))?.join(path)?;
+    let response = reqwest::get(url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.bytes().await?.to_vec()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn read(path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    log::debug!("Reading {}", path);
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The textures of the built-in scene compiled in, for binaries shipped without the obj directory
+#[cfg(feature = "embed-assets")]
+fn embedded(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "asuka.png" => Some(include_bytes!("../obj/asuka.png")),
+        "albedo.png" => Some(include_bytes!("../obj/albedo.png")),
+        "texture_test.png" => Some(include_bytes!("../obj/texture_test.png")),
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "embed-assets"))]
+fn embedded(_name: &str) -> Option<&'static [u8]> {
+    None
+}
+
+// Splits a path or URL into the directory to make an Assets root of and the file name
+pub fn split_asset_path(path: &str) -> (&str, &str) {
+    match path.rsplit_once(|c| c == '/' || c == '\\') {
+        Some((dir, file)) => (if dir.is_empty() { "/" } else { dir }, file),
+        // An empty root would be the build's obj directory
+        None => (".", path),
+    }
+}
+
+// OBJ, glTF or GLB, by the extension
+pub fn is_model_file(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".obj") || is_gltf(&path)
+}
+
+fn is_gltf(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".gltf") || path.ends_with(".glb")
 }
 
 pub async fn load_model(
+    assets: &Assets,
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     scale: f32,
+    // Simplified copies generated per mesh, see ModelRenderGroup::set_lod_distance
+    lod_levels: usize,
+    // Replaces the normals of the file
+    normals: Option<NormalMode>,
 ) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_name).await?;
+    profile_scope!("load_model");
+    let source = fetch_model(assets, file_name).await?;
+    build_model(source, device, queue, scale, lod_levels, normals)
+}
+
+// An OBJ or glTF file with its materials read and its textures decoded, all that is left is
+// uploading.
+// Plain data without the device, so it can be loaded while the event loop keeps running and
+// sent with a SceneCommand.
+pub struct ModelSource {
+    file_name: String,
+    models: Vec<tobj::Model>,
+    // The second uv set of each model, laid out like its texcoords. OBJ files have none and glTF
+    // primitives without TEXCOORD_1 are left empty, their texcoords are used for both.
+    tex_coords2: Vec<Vec<f32>>,
+    // Of each model, empty for OBJ files
+    morph_targets: Vec<MorphTargets>,
+    skins: Vec<Option<SkinBinding>>,
+    // The nodes the skins are posed by, None without skins
+    skeleton: Option<Skeleton>,
+    materials: Vec<tobj::Material>,
+    // Of every material, the diffuse texture and the maps it sets in material_maps order
+    textures: Vec<texture_decode::DecodedImage>,
+}
+
+impl ModelSource {
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+}
+
+pub async fn fetch_model(assets: &Assets, file_name: &str) -> anyhow::Result<ModelSource> {
+    profile_scope!("fetch_model");
+    if is_gltf(file_name) {
+        return fetch_gltf(assets, file_name).await;
+    }
+    let obj_text = assets.load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
@@ -88,85 +259,576 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            match assets.load_string(&p).await {
+                Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                // tobj only has its own errors, the cause is logged
+                Err(e) => {
+                    log::error!("{:?}", e);
+                    Err(tobj::LoadError::OpenFileFailed)
+                }
+            }
         },
     )
     .await?;
 
+    let obj_materials = obj_materials?;
+    // The diffuse texture and whichever of map_Ks, map_Ns and map_d are set, in that order
+    let mut files = Vec::new();
+    for m in &obj_materials {
+        let [diffuse, maps @ ..] = material_maps(m);
+        files.push((diffuse.to_string(), assets.load_binary(diffuse).await?));
+        for path in maps.into_iter().filter(|path| !path.is_empty()) {
+            files.push((path.to_string(), assets.load_binary(path).await?));
+        }
+    }
+    let textures = texture_decode::decode_all(files)
+        .into_iter()
+        .collect::<anyhow::Result<_>>()?;
+    Ok(ModelSource {
+        file_name: file_name.to_string(),
+        models,
+        tex_coords2: vec![],
+        morph_targets: vec![],
+        skins: vec![],
+        skeleton: None,
+        materials: obj_materials,
+        textures,
+    })
+}
+
+// The default scene with the node transforms baked into the vertices, in the types of an OBJ
+// file. Skinned meshes stay in their bind pose, their joints place them. The base color becomes the diffuse texture, roughness the specular highlight and metallic
+// the reflectivity. Buffers and images are read from a GLB or the files next to the glTF, data
+// URIs are not supported.
+async fn fetch_gltf(assets: &Assets, file_name: &str) -> anyhow::Result<ModelSource> {
+    profile_scope!("fetch_gltf");
+    let gltf = gltf::Gltf::from_slice(&assets.load_binary(file_name).await?)?;
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        buffers.push(match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().context("GLB without a binary chunk")?,
+            gltf::buffer::Source::Uri(uri) => load_gltf_uri(assets, uri).await?,
+        });
+    }
+
+    let mut materials = Vec::new();
+    // Of every material the label, the base color factor and whether a texture was read for it
+    let mut diffuse = Vec::new();
+    let mut files = Vec::new();
+    for material in gltf.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        let label = format!("{} {}", file_name, material.name().unwrap_or("material"));
+        let textured = match pbr.base_color_texture() {
+            Some(info) => {
+                let bytes = match info.texture().source().source() {
+                    gltf::image::Source::View { view, .. } => {
+                        let start = view.offset();
+                        buffers[view.buffer().index()][start..start + view.length()].to_vec()
+                    }
+                    gltf::image::Source::Uri { uri, .. } => load_gltf_uri(assets, uri).await?,
+                };
+                files.push((label.clone(), bytes));
+                true
+            }
+            None => false,
+        };
+        // Blinn-Phong exponent of about the same lobe
+        let alpha = pbr.roughness_factor().powi(2).max(0.01);
+        let mut obj_material = tobj::Material {
+            name: label.clone(),
+            specular: [1. - pbr.roughness_factor(); 3],
+            shininess: (2. / alpha.powi(2) - 2.).min(512.),
+            diffuse_texture: label.clone(),
+            ..Default::default()
+        };
+        let [r, g, b] = material.emissive_factor();
+        obj_material
+            .unknown_param
+            .insert("Ke".to_string(), format!("{} {} {}", r, g, b));
+        obj_material
+            .unknown_param
+            .insert("Pm".to_string(), pbr.metallic_factor().to_string());
+        materials.push(obj_material);
+        diffuse.push((label, pbr.base_color_factor(), textured));
+    }
+    // For primitives without a material
+    let default_material = materials.len();
+    let label = format!("{} default material", file_name);
+    materials.push(tobj::Material {
+        name: label.clone(),
+        diffuse_texture: label.clone(),
+        ..Default::default()
+    });
+    diffuse.push((label, [1.; 4], false));
+
+    let mut decoded = texture_decode::decode_all(files).into_iter();
+    let textures = diffuse
+        .into_iter()
+        .map(|(label, factor, textured)| {
+            let mut image = if textured {
+                decoded.next().unwrap()?.image
+            } else {
+                image::RgbaImage::from_pixel(1, 1, [255; 4].into())
+            };
+            for pixel in image.pixels_mut() {
+                for (channel, factor) in pixel.0.iter_mut().zip(factor) {
+                    *channel = (*channel as f32 * factor).round() as u8;
+                }
+            }
+            Ok(texture_decode::DecodedImage { label, image })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .context("glTF file without a scene")?;
+    let animations = gltf_morph_animations(&gltf, &buffers);
+    let mut models = Vec::new();
+    let mut tex_coords2 = Vec::new();
+    let mut morph_targets = Vec::new();
+    let mut skins = Vec::new();
+    let mut nodes: Vec<_> = scene
+        .nodes()
+        .map(|node| (node, Matrix4::identity()))
+        .collect();
+    while let Some((node, parent)) = nodes.pop() {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        let normal_transform = transform.invert().unwrap_or(transform).transpose();
+        nodes.extend(node.children().map(|child| (child, transform)));
+        let (transform, normal_transform) = match node.skin() {
+            Some(_) => (Matrix4::identity(), Matrix4::identity()),
+            None => (transform, normal_transform),
+        };
+        let mesh = match node.mesh() {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let positions: Vec<_> = match reader.read_positions() {
+                Some(positions) => positions
+                    .flat_map(|p| <[f32; 3]>::from(transform.transform_point(Point3::from(p))))
+                    .collect(),
+                None => continue,
+            };
+            let normals = reader.read_normals().map_or(vec![], |normals| {
+                normals
+                    .flat_map(|n| {
+                        let n = normal_transform.transform_vector(n.into()).normalize();
+                        <[f32; 3]>::from(n)
+                    })
+                    .collect()
+            });
+            // The shaders flip v for OBJ files, which start at the bottom
+            let texcoords = reader.read_tex_coords(0).map_or(vec![], |uvs| {
+                uvs.into_f32().flat_map(|[u, v]| [u, 1. - v]).collect()
+            });
+            tex_coords2.push(reader.read_tex_coords(1).map_or(vec![], |uvs| {
+                uvs.into_f32().flat_map(|[u, v]| [u, 1. - v]).collect()
+            }));
+            let targets = reader
+                .read_morph_targets()
+                .map(|(positions, normals, _)| MorphTarget {
+                    positions: positions.map_or(vec![], |deltas| {
+                        deltas
+                            .map(|d| transform.transform_vector(d.into()).into())
+                            .collect()
+                    }),
+                    normals: normals.map_or(vec![], |deltas| {
+                        deltas
+                            .map(|d| normal_transform.transform_vector(d.into()).into())
+                            .collect()
+                    }),
+                })
+                .collect();
+            morph_targets.push(MorphTargets {
+                targets,
+                weights: node
+                    .weights()
+                    .or_else(|| mesh.weights())
+                    .map_or(vec![], <[f32]>::to_vec),
+                animation: animations.get(&node.index()).cloned(),
+            });
+            skins.push(node.skin().and_then(|skin| {
+                let joints = reader.read_joints(0)?.into_u16();
+                let weights = reader.read_weights(0)?.into_f32();
+                let influences = joints
+                    .zip(weights)
+                    .map(|(joints, weights)| Influence {
+                        joints: joints.map(u32::from),
+                        weights,
+                    })
+                    .collect();
+                Some(SkinBinding {
+                    skin: skin.index(),
+                    influences,
+                })
+            }));
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32 / 3).collect(),
+            };
+            let mesh_name = mesh.name().unwrap_or(file_name).to_string();
+            models.push(tobj::Model::new(
+                tobj::Mesh {
+                    positions,
+                    normals,
+                    texcoords,
+                    indices,
+                    material_id: Some(primitive.material().index().unwrap_or(default_material)),
+                    ..Default::default()
+                },
+                mesh_name,
+            ));
+        }
+    }
+    Ok(ModelSource {
+        file_name: file_name.to_string(),
+        models,
+        tex_coords2,
+        morph_targets,
+        skins,
+        skeleton: gltf_skeleton(&gltf, &buffers),
+        materials,
+        textures,
+    })
+}
+
+// Weight keys of the first animation channel moving the morph targets of each node, by node
+// index. Cubic spline keys are played linearly through their values.
+fn gltf_morph_animations(gltf: &gltf::Gltf, buffers: &[Vec<u8>]) -> HashMap<usize, MorphAnimation> {
+    let mut animations = HashMap::new();
+    for channel in gltf.animations().flat_map(|animation| animation.channels()) {
+        let target = channel.target();
+        if target.property() != gltf::animation::Property::MorphTargetWeights {
+            continue;
+        }
+        let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let (times, values): (Vec<_>, Vec<_>) = match (reader.read_inputs(), reader.read_outputs())
+        {
+            (Some(times), Some(gltf::animation::util::ReadOutputs::MorphTargetWeights(values))) => {
+                (times.collect(), values.into_f32().collect())
+            }
+            _ => continue,
+        };
+        let per_key = values.len() / times.len().max(1);
+        if per_key == 0 {
+            continue;
+        }
+        let cubic =
+            channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline;
+        // In-tangents, values and out-tangents
+        let count = if cubic { per_key / 3 } else { per_key };
+        let offset = if cubic { count } else { 0 };
+        let weights = values
+            .chunks_exact(per_key)
+            .map(|key| key[offset..offset + count].to_vec())
+            .collect();
+        animations
+            .entry(target.node().index())
+            .or_insert(MorphAnimation { times, weights });
+    }
+    animations
+}
+
+// Every node of the file in its rest pose with the skins and the node channels of the first
+// animation that has any. None without skins. Cubic spline keys are played linearly through
+// their values.
+fn gltf_skeleton(gltf: &gltf::Gltf, buffers: &[Vec<u8>]) -> Option<Skeleton> {
+    gltf.skins().next()?;
+    let mut nodes: Vec<_> = gltf
+        .nodes()
+        .map(|node| {
+            let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+            SkeletonNode {
+                parent: None,
+                translation: translation.into(),
+                rotation: Quaternion::new(w, x, y, z),
+                scale: scale.into(),
+            }
+        })
+        .collect();
+    for node in gltf.nodes() {
+        for child in node.children() {
+            nodes[child.index()].parent = Some(node.index());
+        }
+    }
+    let skins = gltf
+        .skins()
+        .map(|skin| {
+            let joints: Vec<_> = skin.joints().map(|joint| joint.index()).collect();
+            let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+                Some(matrices) => matrices.map(Matrix4::from).collect(),
+                None => vec![Matrix4::identity(); joints.len()],
+            };
+            Skin {
+                joints,
+                inverse_bind_matrices,
+            }
+        })
+        .collect();
+    let animation = gltf
+        .animations()
+        .map(|animation| gltf_node_channels(&animation, buffers))
+        .find(|channels| !channels.is_empty())
+        .map(|channels| SkeletonAnimation { channels });
+    Some(Skeleton {
+        nodes,
+        skins,
+        animation,
+    })
+}
+
+// The translation, rotation and scale channels of `animation`
+fn gltf_node_channels(animation: &gltf::Animation, buffers: &[Vec<u8>]) -> Vec<NodeChannel> {
+    use gltf::animation::util::ReadOutputs;
+    animation
+        .channels()
+        .filter_map(|channel| {
+            let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let times: Vec<_> = reader.read_inputs()?.collect();
+            let cubic =
+                channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline;
+            let keys = match reader.read_outputs()? {
+                ReadOutputs::Translations(keys) => {
+                    let keys = keys.map(Vector3::from).collect();
+                    ChannelKeys::Translation(spline_values(keys, cubic))
+                }
+                ReadOutputs::Rotations(keys) => {
+                    let keys = keys
+                        .into_f32()
+                        .map(|[x, y, z, w]| Quaternion::new(w, x, y, z))
+                        .collect();
+                    ChannelKeys::Rotation(spline_values(keys, cubic))
+                }
+                ReadOutputs::Scales(keys) => {
+                    let keys = keys.map(Vector3::from).collect();
+                    ChannelKeys::Scale(spline_values(keys, cubic))
+                }
+                ReadOutputs::MorphTargetWeights(_) => return None,
+            };
+            Some(NodeChannel {
+                node: channel.target().node().index(),
+                times,
+                keys,
+            })
+        })
+        .collect()
+}
+
+// Cubic spline keys come as in-tangent, value and out-tangent
+fn spline_values<T: Copy>(keys: Vec<T>, cubic: bool) -> Vec<T> {
+    if cubic {
+        keys.chunks_exact(3).map(|key| key[1]).collect()
+    } else {
+        keys
+    }
+}
+
+async fn load_gltf_uri(assets: &Assets, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        bail!("Data URIs in glTF files are not supported, convert it to GLB");
+    }
+    assets.load_binary(uri).await
+}
+
+pub fn build_model(
+    source: ModelSource,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    scale: f32,
+    lod_levels: usize,
+    normals: Option<NormalMode>,
+) -> anyhow::Result<model::Model> {
+    profile_scope!("build_model");
+    let ModelSource {
+        file_name,
+        models,
+        mut tex_coords2,
+        mut morph_targets,
+        mut skins,
+        skeleton,
+        materials: obj_materials,
+        textures,
+    } = source;
+    tex_coords2.resize(models.len(), vec![]);
+    morph_targets.resize(models.len(), MorphTargets::default());
+    skins.resize(models.len(), None);
+    let skeleton = skeleton.map(|skeleton| skeleton.scaled(scale));
+    let file_name = file_name.as_str();
+    let mut decoded = textures.into_iter();
     let mut materials = Vec::new();
 
-    let texture_bind_group_layout = device.create_bind_group_layout(&texture::Texture::desc());
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
+    let texture_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &model::material_layout_entries(None),
+            label: Some("material_bind_group_layout"),
+        });
+    let tint = Tint::new(device);
+    let emissive_texture = texture::Texture::black(device, "No emission");
+    let white_texture = texture::Texture::white(device, queue, "No map")?;
+    for m in obj_materials {
+        let mut upload = |format| -> anyhow::Result<_> {
+            let decoded = decoded.next().unwrap();
+            texture::Texture::from_rgba_format(
+                device,
+                queue,
+                &decoded.image,
+                Some(&decoded.label),
+                1,
+                format,
+            )
+        };
+        let diffuse_texture = upload(texture::TEXTURE_FORMAT)?;
+        let [_, specular, shininess, dissolve] = material_maps(&m);
+        let mut map = |path: &str| {
+            if path.is_empty() {
+                Ok(None)
+            } else {
+                upload(texture::DATA_TEXTURE_FORMAT).map(Some)
+            }
+        };
+        let specular_texture = map(specular)?;
+        let shininess_texture = map(shininess)?;
+        let dissolve_texture = map(dissolve)?;
+        let uniform_bind_group =
+            MaterialUniform::new(m.ambient, m.diffuse, m.specular, m.shininess)
+                .with_emissive(emissive_color(&m))
+                .with_reflectivity(reflectivity(&m))
+                .create_buffer_and_bindgroup(device);
+        let maps = [
+            Some(&diffuse_texture),
+            specular_texture.as_ref(),
+            shininess_texture.as_ref(),
+            dissolve_texture.as_ref(),
+        ]
+        .map(|map| map.unwrap_or(&white_texture));
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
+            entries: &model::material_entries(
+                maps,
+                &uniform_bind_group.buffer,
+                &tint,
+                &emissive_texture,
+            ),
             label: None,
         });
-        let material_uniform_group =
-            MaterialUniform::new(m.ambient, m.diffuse, m.specular, m.shininess);
         materials.push(model::Material {
             name: m.name,
             diffuse_texture,
+            specular_texture,
+            shininess_texture,
+            dissolve_texture,
+            double_sided: false,
             bind_group,
-            uniform_bind_group: material_uniform_group.create_buffer_and_bindgroup(device),
+            uniform_bind_group,
         })
     }
 
     let iter = {
         cfg_if::cfg_if! {
             if #[cfg(target_arch = "wasm32")] {
-                models.into_iter()
+                models.into_iter().zip(tex_coords2).zip(morph_targets).zip(skins)
             } else {
-                models.into_par_iter()
+                models.into_par_iter().zip(tex_coords2).zip(morph_targets).zip(skins)
             }
         }
     };
     let meshes = iter
-        .map(|m| {
+        .map(|(((m, tex_coords2), morph_targets), skin)| {
+            let has_normals = !m.mesh.normals.is_empty();
+            let has_tex_coords = !m.mesh.texcoords.is_empty();
+            let has_tex_coords2 = !tex_coords2.is_empty();
             let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| Vertex {
-                    position: [
-                        scale * m.mesh.positions[i * 3],
-                        scale * m.mesh.positions[i * 3 + 1],
-                        scale * m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+                .map(|i| {
+                    let vertex = Vertex::new(
+                        [
+                            scale * m.mesh.positions[i * 3],
+                            scale * m.mesh.positions[i * 3 + 1],
+                            scale * m.mesh.positions[i * 3 + 2],
+                        ],
+                        if has_tex_coords {
+                            [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]]
+                        } else {
+                            [0.; 2]
+                        },
+                        if has_normals {
+                            [
+                                m.mesh.normals[i * 3],
+                                m.mesh.normals[i * 3 + 1],
+                                m.mesh.normals[i * 3 + 2],
+                            ]
+                        } else {
+                            [0., 1., 0.]
+                        },
+                    );
+                    if has_tex_coords2 {
+                        vertex.with_tex_coords2([tex_coords2[i * 2], tex_coords2[i * 2 + 1]])
+                    } else {
+                        vertex
+                    }
                 })
                 .collect::<Vec<_>>();
 
+            let mut data = MeshData::new(vertices, m.mesh.indices);
+            // Files without normals are shaded smooth
+            let fallback = if has_normals {
+                None
+            } else {
+                Some(NormalMode::Smooth)
+            };
+            if let Some(mode) = normals.or(fallback) {
+                recompute_normals(&mut data, mode);
+            }
+
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
+                contents: bytemuck::cast_slice(&data.vertex_data),
                 usage: wgpu::BufferUsages::VERTEX,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+                contents: bytemuck::cast_slice(&data.index_data),
                 usage: wgpu::BufferUsages::INDEX,
             });
 
+            let morph = mesh_morph(morph_targets, &data, scale);
+            let skin = mesh_skin(skin, &data, skeleton.as_ref());
+            let mut bounds = match &skin {
+                Some((_, bounds)) => *bounds,
+                None => BoundingSphere::from_points(data.vertex_data.iter().map(|v| v.position)),
+            };
+            if let Some((_, reach)) = &morph {
+                bounds.radius += reach;
+            }
+            // Simplifying would lose the vertices the targets and joints move
+            let deformed = morph.is_some() || skin.is_some();
+            let lod_levels = if deformed { 0 } else { lod_levels };
+            let lods = lod_chain(&data, lod_levels)
+                .iter()
+                .enumerate()
+                .map(|(i, lod)| {
+                    let label = format!("{:?} lod {}", file_name, i + 1);
+                    model::MeshLod::new(device, &label, lod)
+                })
+                .collect();
+
             model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
+                num_elements: data.index_data.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                bounds,
+                lods,
+                morph: morph.map(|(morph, _)| morph),
+                skin: skin.map(|(skin, _)| skin),
+                deform: None,
             }
         })
         .collect::<Vec<_>>();
@@ -175,5 +837,83 @@ pub async fn load_model(
         meshes,
         materials,
         texture_bind_group_layout,
+        emissive_texture,
+        white_texture,
+        tint,
+        skeleton,
     })
 }
+
+// The morph targets scaled like the vertices, with how far they reach. None without targets or
+// when recomputed normals split the vertices they were made for.
+fn mesh_morph(mut targets: MorphTargets, data: &MeshData, scale: f32) -> Option<(MeshMorph, f32)> {
+    if targets.is_empty() {
+        return None;
+    }
+    let count = data.vertex_data.len();
+    let fits = targets.targets.iter().all(|target| {
+        [&target.positions, &target.normals]
+            .iter()
+            .all(|deltas| deltas.is_empty() || deltas.len() == count)
+    });
+    if !fits {
+        log::warn!("Morph targets dropped, the vertices changed");
+        return None;
+    }
+    for target in &mut targets.targets {
+        for delta in &mut target.positions {
+            *delta = delta.map(|x| x * scale);
+        }
+    }
+    let reach = targets.reach();
+    Some((MeshMorph::new(data.vertex_data.clone(), targets), reach))
+}
+
+// The skin of a mesh with its bounds over the animation. None without a skin, when the skeleton
+// doesn't have it or when recomputed normals split the vertices.
+fn mesh_skin(
+    binding: Option<SkinBinding>,
+    data: &MeshData,
+    skeleton: Option<&Skeleton>,
+) -> Option<(MeshSkin, BoundingSphere)> {
+    let (binding, skeleton) = (binding?, skeleton?);
+    if binding.skin >= skeleton.skins.len() {
+        return None;
+    }
+    if binding.influences.len() != data.vertex_data.len() {
+        log::warn!("Skin dropped, the vertices changed");
+        return None;
+    }
+    let bounds = skeleton.skinned_bounds(&binding, &data.vertex_data);
+    Some((MeshSkin::new(data.vertex_data.clone(), binding), bounds))
+}
+
+// The diffuse texture is required, the others are empty when the mtl file doesn't set them
+fn material_maps(material: &tobj::Material) -> [&str; 4] {
+    [
+        &material.diffuse_texture,
+        &material.specular_texture,
+        &material.shininess_texture,
+        &material.dissolve_texture,
+    ]
+}
+
+// `Ke r g b` of the material, tobj keeps it with the keys it doesn't know
+fn emissive_color(material: &tobj::Material) -> [f32; 3] {
+    let mut color = [0.; 3];
+    if let Some(value) = material.unknown_param.get("Ke") {
+        for (channel, value) in color.iter_mut().zip(value.split_whitespace()) {
+            *channel = value.parse().unwrap_or(0.);
+        }
+    }
+    color
+}
+
+// `Pm` of the material, the metalness of the PBR extension to mtl files
+fn reflectivity(material: &tobj::Material) -> f32 {
+    material
+        .unknown_param
+        .get("Pm")
+        .and_then(|value| value.trim().parse::<f32>().ok())
+        .map_or(0., |value| value.clamp(0., 1.))
+}
@@ -0,0 +1,371 @@
+// Scene behaviors written in Rhai, enabled by the `scripting` feature. A script file runs from
+// top to bottom every update with `dt` and `time` in seconds, and `state`, an object map kept
+// between updates and reloads. The functions registered here read and change the world, the
+// lights and the main camera of the SceneApi, which are moved into the engine for the run and
+// back after.
+use crate::camera::CameraView;
+use crate::ecs::{EntityId, World};
+use crate::events::AssetLoaded;
+use crate::light::LightUniform;
+use crate::script::{SceneApi, Script};
+use crate::State;
+use anyhow::anyhow;
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector3};
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+// What the registered functions work on during a run
+struct Frame {
+    world: World,
+    camera: CameraView,
+    lights: Vec<LightUniform>,
+    entity: Option<EntityId>,
+    finished: bool,
+}
+
+type SharedFrame = Rc<RefCell<Frame>>;
+
+fn vector(v: [f32; 3]) -> Array {
+    v.iter().map(|&x| Dynamic::from(x)).collect()
+}
+
+fn light_index(index: INT) -> usize {
+    usize::try_from(index).unwrap_or(usize::MAX)
+}
+
+// Entities are `Entity` values, lights are numbered like State::set_light. Angles are degrees,
+// transforms are relative to the parent of the entity. Dead entities and missing lights read
+// as zero and ignore changes.
+fn register_scene(engine: &mut Engine, frame: &SharedFrame) {
+    engine.register_type_with_name::<EntityId>("Entity");
+    engine.on_print(|text| log::info!("{}", text));
+
+    let shared = frame.clone();
+    engine.register_fn("entity", move || {
+        shared.borrow().entity.map_or(Dynamic::UNIT, Dynamic::from)
+    });
+    let shared = frame.clone();
+    engine.register_fn("meshes", move || -> Array {
+        let frame = shared.borrow();
+        let meshes = frame.world.meshes().iter();
+        meshes.map(|(entity, _)| Dynamic::from(entity)).collect()
+    });
+    let shared = frame.clone();
+    engine.register_fn("is_alive", move |entity: EntityId| {
+        shared.borrow().world.is_alive(entity)
+    });
+    let shared = frame.clone();
+    engine.register_fn("despawn", move |entity: EntityId| {
+        shared.borrow_mut().world.despawn(entity)
+    });
+    let shared = frame.clone();
+    engine.register_fn("position", move |entity: EntityId| {
+        let transform = shared.borrow().world.transform(entity);
+        vector(transform.map_or([0.; 3], |t| t.position().into()))
+    });
+    let shared = frame.clone();
+    engine.register_fn(
+        "set_position",
+        move |entity: EntityId, x: FLOAT, y: FLOAT, z: FLOAT| {
+            let world = &mut shared.borrow_mut().world;
+            if let Some(mut transform) = world.transform(entity) {
+                transform.position = Vector3::new(x, y, z);
+                world.set_transform(entity, transform);
+            }
+        },
+    );
+    let shared = frame.clone();
+    engine.register_fn(
+        "turn",
+        move |entity: EntityId, x: FLOAT, y: FLOAT, z: FLOAT, degrees: FLOAT| {
+            let axis = Vector3::new(x, y, z);
+            if axis.magnitude2() == 0. {
+                return;
+            }
+            let world = &mut shared.borrow_mut().world;
+            if let Some(mut transform) = world.transform(entity) {
+                let turn = Quaternion::from_axis_angle(axis.normalize(), Deg(degrees));
+                transform.rotation = turn * transform.rotation;
+                world.set_transform(entity, transform);
+            }
+        },
+    );
+    let shared = frame.clone();
+    engine.register_fn("set_scale", move |entity: EntityId, scale: FLOAT| {
+        let world = &mut shared.borrow_mut().world;
+        if let Some(mut transform) = world.transform(entity) {
+            transform.scale = Vector3::new(scale, scale, scale);
+            world.set_transform(entity, transform);
+        }
+    });
+
+    let shared = frame.clone();
+    engine.register_fn("camera_position", move || {
+        vector(shared.borrow().camera.position.into())
+    });
+    let shared = frame.clone();
+    engine.register_fn(
+        "set_camera_position",
+        move |x: FLOAT, y: FLOAT, z: FLOAT| {
+            shared.borrow_mut().camera.position = (x, y, z).into();
+        },
+    );
+    let shared = frame.clone();
+    engine.register_fn("camera_yaw", move || {
+        Deg::from(shared.borrow().camera.yaw()).0
+    });
+    let shared = frame.clone();
+    engine.register_fn("camera_pitch", move || {
+        Deg::from(shared.borrow().camera.pitch()).0
+    });
+    let shared = frame.clone();
+    engine.register_fn("set_camera_angles", move |yaw: FLOAT, pitch: FLOAT| {
+        shared
+            .borrow_mut()
+            .camera
+            .set_yaw_pitch(Deg(yaw), Deg(pitch));
+    });
+
+    let shared = frame.clone();
+    engine.register_fn("light_count", move || shared.borrow().lights.len() as INT);
+    let shared = frame.clone();
+    engine.register_fn("light_position", move |index: INT| {
+        let frame = shared.borrow();
+        let light = frame.lights.get(light_index(index));
+        vector(light.map_or([0.; 3], |light| light.position))
+    });
+    let shared = frame.clone();
+    engine.register_fn(
+        "set_light_position",
+        move |index: INT, x: FLOAT, y: FLOAT, z: FLOAT| {
+            if let Some(light) = shared.borrow_mut().lights.get_mut(light_index(index)) {
+                light.position = [x, y, z];
+            }
+        },
+    );
+    let shared = frame.clone();
+    engine.register_fn("light_color", move |index: INT| {
+        let frame = shared.borrow();
+        let light = frame.lights.get(light_index(index));
+        let [r, g, b, _] = light.map_or([0.; 4], |light| light.color);
+        vector([r, g, b])
+    });
+    let shared = frame.clone();
+    engine.register_fn(
+        "set_light_color",
+        move |index: INT, r: FLOAT, g: FLOAT, b: FLOAT| {
+            if let Some(light) = shared.borrow_mut().lights.get_mut(light_index(index)) {
+                light.color = [r, g, b, light.color[3]];
+            }
+        },
+    );
+
+    let shared = frame.clone();
+    engine.register_fn("finish", move || shared.borrow_mut().finished = true);
+}
+
+pub struct RhaiScript {
+    name: String,
+    engine: Engine,
+    frame: SharedFrame,
+    ast: AST,
+    // Holds `state` between runs
+    scope: Scope<'static>,
+    // By an error or finish(), until the source is reloaded
+    stopped: bool,
+}
+
+impl RhaiScript {
+    // `name` is only used in log messages
+    pub fn new(name: &str, source: &str) -> anyhow::Result<Self> {
+        let frame = Rc::new(RefCell::new(Frame {
+            world: World::default(),
+            camera: CameraView::new((0., 0., 0.), Deg(0.), Deg(0.)),
+            lights: vec![],
+            entity: None,
+            finished: false,
+        }));
+        let mut engine = Engine::new();
+        register_scene(&mut engine, &frame);
+        let ast = compile(&engine, name, source)?;
+        let mut scope = Scope::new();
+        scope.push("state", Map::new());
+        Ok(Self {
+            name: name.to_string(),
+            engine,
+            frame,
+            ast,
+            scope,
+            stopped: false,
+        })
+    }
+
+    // Keeps `state`. On a compile error the old source keeps running.
+    pub fn reload(&mut self, source: &str) -> anyhow::Result<()> {
+        self.ast = compile(&self.engine, &self.name, source)?;
+        self.stopped = false;
+        Ok(())
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+}
+
+fn compile(engine: &Engine, name: &str, source: &str) -> anyhow::Result<AST> {
+    engine
+        .compile(source)
+        .map_err(|e| anyhow!("Script {} does not compile: {}", name, e))
+}
+
+impl Script for RhaiScript {
+    fn on_update(&mut self, dt: Duration, scene: &mut SceneApi) {
+        if self.stopped {
+            return;
+        }
+        {
+            let mut frame = self.frame.borrow_mut();
+            frame.world = std::mem::take(scene.world_mut());
+            frame.camera = scene.camera().clone();
+            frame.lights = scene.lights().to_vec();
+            frame.entity = scene.entity();
+            frame.finished = false;
+        }
+        let len = self.scope.len();
+        self.scope.push_constant("dt", dt.as_secs_f32());
+        self.scope
+            .push_constant("time", scene.elapsed().as_secs_f32());
+        let result = self.engine.run_ast_with_scope(&mut self.scope, &self.ast);
+        self.scope.rewind(len);
+
+        let mut frame = self.frame.borrow_mut();
+        *scene.world_mut() = std::mem::take(&mut frame.world);
+        *scene.camera_mut() = frame.camera.clone();
+        scene.lights_mut().copy_from_slice(&frame.lights);
+        if let Err(e) = result {
+            log::error!("Script {} stopped: {}", self.name, e);
+            self.stopped = true;
+        }
+        if frame.finished {
+            self.stopped = true;
+            scene.finish();
+        }
+    }
+}
+
+// Every .rhai file of a directory as one global script. The directory is read again twice a
+// second: new files start, removed ones stop and changed ones are reloaded, so scripts can be
+// edited while the scene runs. Native only, the web can't list directories.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ScriptFolder {
+    dir: std::path::PathBuf,
+    // With the source they were loaded from, by path
+    scripts: std::collections::BTreeMap<std::path::PathBuf, (String, RhaiScript)>,
+    // Until the directory is read again
+    poll_in: Duration,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ScriptFolder {
+    // Read on the first update, a missing directory has no scripts
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            scripts: Default::default(),
+            poll_in: Duration::ZERO,
+        }
+    }
+
+    // Compiled scripts, including stopped ones
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    // Files that fail to compile are logged, and keep their old version if they had one
+    pub fn poll(&mut self) {
+        let mut paths: Vec<_> = std::fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        paths.retain(|path| path.extension().map_or(false, |ext| ext == "rhai"));
+        self.scripts.retain(|path, _| paths.contains(path));
+        for path in paths {
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::error!("Script {} not read: {:?}", path.display(), e);
+                    continue;
+                }
+            };
+            let loaded = match self.scripts.get_mut(&path) {
+                Some((old, _)) if *old == source => continue,
+                Some((old, script)) => {
+                    *old = source.clone();
+                    script.reload(&source)
+                }
+                None => RhaiScript::new(&path.display().to_string(), &source).map(|script| {
+                    self.scripts.insert(path.clone(), (source, script));
+                }),
+            };
+            match loaded {
+                Ok(()) => log::info!("Script {} loaded", path.display()),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Script for ScriptFolder {
+    fn on_update(&mut self, dt: Duration, scene: &mut SceneApi) {
+        self.poll_in = self.poll_in.saturating_sub(dt);
+        if self.poll_in.is_zero() {
+            self.poll();
+            self.poll_in = POLL_INTERVAL;
+        }
+        for (_, script) in self.scripts.values_mut() {
+            script.on_update(dt, scene);
+            // Only that file stops
+            scene.take_finished();
+        }
+    }
+
+    // New files are picked up whenever something else redraws
+    fn is_animating(&self) -> bool {
+        self.scripts
+            .values()
+            .any(|(_, script)| !script.is_stopped())
+    }
+}
+
+impl State {
+    // A Rhai script of the assets, for the whole scene or attached to `entity`. Runs on the web
+    // too, without reloading.
+    pub async fn add_rhai_script(
+        &mut self,
+        file: &str,
+        entity: Option<EntityId>,
+    ) -> anyhow::Result<()> {
+        let source = self.assets.load_string(file).await?;
+        let script = RhaiScript::new(file, &source)?;
+        match entity {
+            Some(entity) => self.add_entity_script(entity, script),
+            None => self.add_script(script),
+        }
+        let name = file.to_string();
+        self.publish(AssetLoaded { name, group: None });
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use crate::probes::ReflectionProbes;
+use crate::reflect::uniform_fields;
+use crate::stats;
+use crate::State;
+use cgmath::Vector3;
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+use wgpu::{Buffer, Device, Queue};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AmbientMode {
+    // Only the per-light ambient terms contribute
+    None = 0,
+    Flat = 1,
+    // Blend between sky and ground color along the world up axis
+    Hemisphere = 2,
+    // Irradiance from the skybox projected onto L2 spherical harmonics
+    SphericalHarmonics = 3,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SceneUniform {
+    // Flat mode only uses the sky color
+    pub ambient_sky: [f32; 4],
+    pub ambient_ground: [f32; 4],
+    pub ambient_mode: u32,
+    pub ambient_intensity: f32,
+    // Seconds since startup, for animated effects in shaders
+    pub time: f32,
+    pub _padding: f32,
+    pub ambient_sh: [[f32; 4]; 9],
+}
+
+impl Default for SceneUniform {
+    fn default() -> Self {
+        Self {
+            ambient_sky: [0.05, 0.06, 0.08, 0.],
+            ambient_ground: [0.03, 0.025, 0.02, 0.],
+            ambient_mode: AmbientMode::Hemisphere as u32,
+            ambient_intensity: 1.0,
+            time: 0.,
+            _padding: 0.,
+            ambient_sh: [[0.; 4]; 9],
+        }
+    }
+}
+
+impl SceneUniform {
+    // What scene_ambient of geo.wgsl gives a surface facing `normal`, lightmaps are baked with it
+    pub fn ambient(&self, normal: Vector3<f32>) -> [f32; 3] {
+        let rgb = |c: [f32; 4]| Vector3::new(c[0], c[1], c[2]);
+        let n = normal;
+        let ambient = match self.ambient_mode {
+            1 => rgb(self.ambient_sky),
+            2 => {
+                let t = n.y * 0.5 + 0.5;
+                rgb(self.ambient_ground) + (rgb(self.ambient_sky) - rgb(self.ambient_ground)) * t
+            }
+            3 => {
+                let sh = self.ambient_sh.map(rgb);
+                let sum = sh[0] * 0.282095
+                    + sh[1] * (0.488603 * n.y)
+                    + sh[2] * (0.488603 * n.z)
+                    + sh[3] * (0.488603 * n.x)
+                    + sh[4] * (1.092548 * n.x * n.y)
+                    + sh[5] * (1.092548 * n.y * n.z)
+                    + sh[6] * (0.315392 * (3. * n.z * n.z - 1.))
+                    + sh[7] * (1.092548 * n.x * n.z)
+                    + sh[8] * (0.546274 * (n.x * n.x - n.y * n.y));
+                sum.map(|c| c.max(0.))
+            }
+            _ => Vector3::new(0., 0., 0.),
+        };
+        (ambient * self.ambient_intensity).into()
+    }
+
+    pub fn set_ambient_mode(&mut self, mode: AmbientMode) {
+        self.ambient_mode = mode as u32;
+    }
+
+    pub fn set_flat_ambient(&mut self, color: [f32; 3]) {
+        self.set_ambient_mode(AmbientMode::Flat);
+        self.ambient_sky = extend(color);
+    }
+
+    pub fn set_hemisphere_ambient(&mut self, sky: [f32; 3], ground: [f32; 3]) {
+        self.set_ambient_mode(AmbientMode::Hemisphere);
+        self.ambient_sky = extend(sky);
+        self.ambient_ground = extend(ground);
+    }
+
+    pub fn set_ambient_intensity(&mut self, intensity: f32) {
+        self.ambient_intensity = intensity;
+    }
+}
+
+uniform_fields!(SceneUniform {
+    ambient_sky: Color(0., 10.) => "Ambient sky",
+    ambient_ground: Color(0., 10.) => "Ambient ground",
+    ambient_mode: Uint(0., 3.) => "Ambient mode",
+    ambient_intensity: Float(0., 10.) => "Ambient intensity",
+    time: Float => "Time",
+});
+
+struct AmbientFade {
+    from: ([f32; 4], [f32; 4], f32),
+    to: ([f32; 4], [f32; 4], f32),
+    elapsed: Duration,
+    duration: Duration,
+}
+
+pub struct Scene {
+    pub uniform: SceneUniform,
+    pub(crate) buffer: Buffer,
+    pub probes: ReflectionProbes,
+    fade: Option<AmbientFade>,
+}
+
+impl Scene {
+    pub fn new(device: &Device) -> Self {
+        let uniform = SceneUniform::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Scene Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            uniform,
+            buffer,
+            probes: ReflectionProbes::new(device),
+            fade: None,
+        }
+    }
+
+    pub fn set_ambient_sh(&mut self, sh: [[f32; 4]; 9]) {
+        self.uniform.ambient_sh = sh;
+    }
+
+    // Animate the ambient colors and intensity towards the targets, the mode is left untouched
+    pub fn fade_ambient(
+        &mut self,
+        sky: [f32; 3],
+        ground: [f32; 3],
+        intensity: f32,
+        duration: Duration,
+    ) {
+        let uniform = &self.uniform;
+        self.fade = Some(AmbientFade {
+            from: (
+                uniform.ambient_sky,
+                uniform.ambient_ground,
+                uniform.ambient_intensity,
+            ),
+            to: (extend(sky), extend(ground), intensity),
+            elapsed: Duration::ZERO,
+            duration,
+        });
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    pub fn update(&mut self, dt: Duration, queue: &Queue) {
+        self.uniform.time += dt.as_secs_f32();
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed += dt;
+            let t = if fade.duration.is_zero() {
+                1.0
+            } else {
+                (fade.elapsed.as_secs_f32() / fade.duration.as_secs_f32()).min(1.0)
+            };
+            let (sky, ground, intensity) = fade.from;
+            let (sky_to, ground_to, intensity_to) = fade.to;
+            self.uniform.ambient_sky = lerp4(sky, sky_to, t);
+            self.uniform.ambient_ground = lerp4(ground, ground_to, t);
+            self.uniform.ambient_intensity = intensity + (intensity_to - intensity) * t;
+            if t >= 1.0 {
+                self.fade = None;
+            }
+        }
+        stats::write_buffer(
+            queue,
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+}
+
+// The setters are undoable edits of the uniform, the fade is not
+impl State {
+    pub fn set_ambient_mode(&mut self, mode: AmbientMode) {
+        self.edit_ambient(|uniform| uniform.set_ambient_mode(mode));
+    }
+
+    pub fn set_flat_ambient(&mut self, color: [f32; 3]) {
+        self.edit_ambient(|uniform| uniform.set_flat_ambient(color));
+    }
+
+    pub fn set_hemisphere_ambient(&mut self, sky: [f32; 3], ground: [f32; 3]) {
+        self.edit_ambient(|uniform| uniform.set_hemisphere_ambient(sky, ground));
+    }
+
+    pub fn set_ambient_intensity(&mut self, intensity: f32) {
+        self.edit_ambient(|uniform| uniform.set_ambient_intensity(intensity));
+    }
+
+    pub fn fade_ambient(
+        &mut self,
+        sky: [f32; 3],
+        ground: [f32; 3],
+        intensity: f32,
+        duration: Duration,
+    ) {
+        self.scene.fade_ambient(sky, ground, intensity, duration);
+    }
+
+    pub fn scene_uniform(&self) -> &SceneUniform {
+        &self.scene.uniform
+    }
+
+    fn edit_ambient(&mut self, edit: impl FnOnce(&mut SceneUniform)) {
+        let mut uniform = self.scene.uniform;
+        edit(&mut uniform);
+        self.set_ambient(uniform);
+    }
+}
+
+fn extend(color: [f32; 3]) -> [f32; 4] {
+    [color[0], color[1], color[2], 0.]
+}
+
+fn lerp4(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut res = a;
+    res.iter_mut().zip(b).for_each(|(x, y)| *x += (y - *x) * t);
+    res
+}
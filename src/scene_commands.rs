@@ -0,0 +1,182 @@
+use crate::camera::CameraView;
+use crate::events::AssetLoaded;
+use crate::geo_gen::{self, Entity, Vertex};
+use crate::light::LightUniform;
+use crate::resources::{self, ModelSource};
+use crate::scene::SceneUniform;
+use crate::texture::Texture;
+use crate::world_space::{InstanceAttributes, InstanceTransform, Instances};
+use crate::State;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+// A scene mutation made of plain data, so it can be built on any thread
+pub enum SceneCommand {
+    SetTransform {
+        group: usize,
+        instance: usize,
+        transform: InstanceTransform,
+    },
+    SetLight {
+        index: usize,
+        uniform: LightUniform,
+    },
+    SetMaterialColor {
+        group: usize,
+        color: [f32; 3],
+    },
+    SetAmbient(SceneUniform),
+    SetCamera(CameraView),
+    // Off keeps the color for switching the light back on
+    SetLightEnabled {
+        index: usize,
+        enabled: bool,
+    },
+    // GPU resources are created on the render thread, the image is expected to be decoded
+    // already. Without an image the mesh is drawn white. Attributes pair up with the
+    // transforms, missing ones default.
+    AddMesh {
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        transforms: Vec<InstanceTransform>,
+        attributes: Vec<InstanceAttributes>,
+        image: Option<image::RgbaImage>,
+    },
+    RemoveGroup(usize),
+    // From resources::fetch_model, uploaded here and drawn at `transform`
+    AddModel {
+        source: ModelSource,
+        scale: f32,
+        transform: InstanceTransform,
+    },
+    // Replaces the scene, see State::view_model
+    ViewModel(ModelSource),
+}
+
+// Send + Clone handle for worker threads and async loaders. Commands are applied in the
+// order they were pushed at the start of the next State::update, through the undoable edits.
+#[derive(Clone)]
+pub struct SceneCommands {
+    sender: Sender<SceneCommand>,
+}
+
+impl SceneCommands {
+    // False once the State is gone
+    pub fn push(&self, command: SceneCommand) -> bool {
+        self.sender.send(command).is_ok()
+    }
+}
+
+pub(crate) fn channel() -> (SceneCommands, Receiver<SceneCommand>) {
+    let (sender, receiver) = mpsc::channel();
+    (SceneCommands { sender }, receiver)
+}
+
+impl State {
+    pub fn scene_commands(&self) -> SceneCommands {
+        self.scene_commands.clone()
+    }
+
+    // The sync point, commands pushed while this runs wait for the next frame
+    pub(crate) fn apply_scene_commands(&mut self) {
+        let commands: Vec<_> = self.scene_command_receiver.try_iter().collect();
+        if !commands.is_empty() {
+            self.request_redraw();
+        }
+        for command in commands {
+            match command {
+                SceneCommand::SetTransform {
+                    group,
+                    instance,
+                    transform,
+                } => self.set_instance_transform(group, instance, transform),
+                SceneCommand::SetLight { index, uniform } => self.set_light(index, uniform),
+                SceneCommand::SetMaterialColor { group, color } => {
+                    self.set_material_color(group, color)
+                }
+                SceneCommand::SetAmbient(uniform) => self.set_ambient(uniform),
+                SceneCommand::SetCamera(view) => self.set_camera_view(view),
+                SceneCommand::SetLightEnabled { index, enabled } => {
+                    self.set_light_enabled(index, enabled)
+                }
+                SceneCommand::AddMesh {
+                    vertices,
+                    indices,
+                    transforms,
+                    attributes,
+                    image,
+                } => self.add_mesh(vertices, indices, transforms, attributes, image),
+                SceneCommand::RemoveGroup(index) => self.remove_render_group(index),
+                SceneCommand::AddModel {
+                    source,
+                    scale,
+                    transform,
+                } => self.add_model(source, scale, transform),
+                SceneCommand::ViewModel(source) => self.view_model(source),
+            }
+        }
+    }
+
+    pub fn set_light_enabled(&mut self, index: usize, enabled: bool) {
+        let mut uniform = match self.light_render_group.light_uniforms.get(index) {
+            Some(uniform) => *uniform,
+            None => return,
+        };
+        if enabled {
+            match self.switched_off_lights.remove(&index) {
+                Some(color) => uniform.color = color,
+                None => return,
+            }
+        } else if !self.switched_off_lights.contains_key(&index) {
+            self.switched_off_lights.insert(index, uniform.color);
+            uniform.color = [0.; 4];
+        } else {
+            return;
+        }
+        self.set_light(index, uniform);
+    }
+
+    fn add_model(&mut self, source: ModelSource, scale: f32, transform: InstanceTransform) {
+        let name = source.file_name().to_string();
+        let model = resources::build_model(source, &self.device, &self.queue, scale, 0, None);
+        match model {
+            Ok(model) => {
+                let instances = Instances::new(vec![transform], &self.device);
+                let group = Some(self.add_model_group(model, instances));
+                self.publish(AssetLoaded { name, group });
+            }
+            Err(e) => log::error!("Failed to upload model: {:?}", e),
+        }
+    }
+
+    fn add_mesh(
+        &mut self,
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        transforms: Vec<InstanceTransform>,
+        attributes: Vec<InstanceAttributes>,
+        image: Option<image::RgbaImage>,
+    ) {
+        if vertices.is_empty() || transforms.is_empty() {
+            log::error!("Ignoring a mesh without vertices or instances");
+            return;
+        }
+        let image = image.unwrap_or_else(|| image::RgbaImage::from_pixel(1, 1, [255; 4].into()));
+        let texture = match Texture::from_rgba(
+            &self.device,
+            &self.queue,
+            &image,
+            Some("scene command texture"),
+            1,
+        ) {
+            Ok(texture) => texture,
+            Err(e) => {
+                log::error!("Failed to upload mesh texture: {}", e);
+                return;
+            }
+        };
+        let obj = geo_gen::GeoObj::new(vertices, indices, &self.device);
+        let entity = Entity::with_texture(&self.device, obj, &texture);
+        let instances = Instances::with_attributes(transforms, attributes, &self.device);
+        self.add_mesh_group(entity, instances);
+    }
+}
@@ -0,0 +1,210 @@
+// Gameplay behaviors written outside of State::update. Scripts run every update after the camera
+// moved and before the world is synced to the render groups, so the transforms they set are
+// drawn the same frame. Global scripts run first, then those of each entity in the order they
+// were added.
+use crate::camera::CameraView;
+use crate::ecs::{EntityId, World};
+use crate::light::LightUniform;
+use crate::world_space::InstanceTransform;
+use crate::State;
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector3};
+use std::time::Duration;
+
+pub trait Script {
+    fn on_update(&mut self, dt: Duration, scene: &mut SceneApi);
+
+    // Keeps redrawing in reactive mode, scripts that only react to the camera or other input
+    // can return false
+    fn is_animating(&self) -> bool {
+        true
+    }
+}
+
+impl<F: FnMut(Duration, &mut SceneApi)> Script for F {
+    fn on_update(&mut self, dt: Duration, scene: &mut SceneApi) {
+        self(dt, scene)
+    }
+}
+
+// What a script gets to see and change during its update
+pub struct SceneApi<'a> {
+    world: &'a mut World,
+    camera: &'a mut CameraView,
+    lights: &'a mut [LightUniform],
+    entity: Option<EntityId>,
+    elapsed: Duration,
+    finished: bool,
+}
+
+impl SceneApi<'_> {
+    // The entity the script is attached to, None for global scripts
+    pub fn entity(&self) -> Option<EntityId> {
+        self.entity
+    }
+
+    pub fn world(&self) -> &World {
+        self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        self.world
+    }
+
+    pub fn camera(&self) -> &CameraView {
+        self.camera
+    }
+
+    // Moves the main camera, the controller keeps steering it from there
+    pub fn camera_mut(&mut self) -> &mut CameraView {
+        self.camera
+    }
+
+    // Animated lights are moved again after the scripts, see State::set_light_animators
+    pub fn lights(&self) -> &[LightUniform] {
+        self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut [LightUniform] {
+        self.lights
+    }
+
+    // Since the first update with scripts, including this one
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    // Of the entity of the script, relative to its parent
+    pub fn transform(&self) -> Option<InstanceTransform> {
+        self.world.transform(self.entity?)
+    }
+
+    pub fn set_transform(&mut self, transform: InstanceTransform) {
+        if let Some(entity) = self.entity {
+            self.world.set_transform(entity, transform);
+        }
+    }
+
+    // Drops the script after this update, for one-off triggers
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    // For scripts running others, which are finished on their own
+    pub(crate) fn take_finished(&mut self) -> bool {
+        std::mem::take(&mut self.finished)
+    }
+}
+
+// Turns its entity around `axis`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Spin {
+    pub axis: Vector3<f32>,
+    pub degrees_per_second: f32,
+}
+
+impl Script for Spin {
+    fn on_update(&mut self, dt: Duration, scene: &mut SceneApi) {
+        if let Some(mut transform) = scene.transform() {
+            let angle = Deg(self.degrees_per_second * dt.as_secs_f32());
+            let turn = Quaternion::from_axis_angle(self.axis.normalize(), angle);
+            transform.rotation = turn * transform.rotation;
+            scene.set_transform(transform);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Scripts {
+    global: Vec<Box<dyn Script>>,
+    entities: Vec<(EntityId, Box<dyn Script>)>,
+    elapsed: Duration,
+}
+
+impl Scripts {
+    pub fn add(&mut self, script: impl Script + 'static) {
+        self.global.push(Box::new(script));
+    }
+
+    // Dropped with the entity
+    pub fn add_to(&mut self, entity: EntityId, script: impl Script + 'static) {
+        self.entities.push((entity, Box::new(script)));
+    }
+
+    // Drops the scripts of `entity`, returns how many it had
+    pub fn remove_from(&mut self, entity: EntityId) -> usize {
+        let count = self.entities.len();
+        self.entities.retain(|(owner, _)| *owner != entity);
+        count - self.entities.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.global.len() + self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_animating(&self) -> bool {
+        let global = self.global.iter();
+        let attached = self.entities.iter().map(|(_, script)| script);
+        global.chain(attached).any(|script| script.is_animating())
+    }
+
+    // Scripts of despawned entities are dropped without running
+    pub fn update(
+        &mut self,
+        dt: Duration,
+        world: &mut World,
+        camera: &mut CameraView,
+        lights: &mut [LightUniform],
+    ) {
+        if self.is_empty() {
+            return;
+        }
+        self.elapsed += dt;
+        let elapsed = self.elapsed;
+        let mut run = |entity: Option<EntityId>, script: &mut dyn Script| {
+            // Checked for every script, an earlier one may have despawned it
+            if entity.map_or(false, |entity| !world.is_alive(entity)) {
+                return false;
+            }
+            let mut scene = SceneApi {
+                world: &mut *world,
+                camera: &mut *camera,
+                lights: &mut *lights,
+                entity,
+                elapsed,
+                finished: false,
+            };
+            script.on_update(dt, &mut scene);
+            !scene.finished
+        };
+        self.global.retain_mut(|script| run(None, script.as_mut()));
+        self.entities
+            .retain_mut(|(entity, script)| run(Some(*entity), script.as_mut()));
+    }
+}
+
+impl State {
+    pub fn add_script(&mut self, script: impl Script + 'static) {
+        self.scripts.add(script);
+        self.request_redraw();
+    }
+
+    // Runs while the entity is alive
+    pub fn add_entity_script(&mut self, entity: EntityId, script: impl Script + 'static) {
+        self.scripts.add_to(entity, script);
+        self.request_redraw();
+    }
+
+    pub fn scripts_mut(&mut self) -> &mut Scripts {
+        &mut self.scripts
+    }
+
+    pub(crate) fn run_scripts(&mut self, dt: Duration) {
+        let lights = &mut self.light_render_group.light_uniforms;
+        self.scripts
+            .update(dt, &mut self.world, &mut self.camera.view, lights);
+    }
+}
@@ -5,11 +5,18 @@ struct CameraUniform {
     proj_inv: mat4x4<f32>,
             // from world to camera
     view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
 };
 
 @group(0) @binding(0) // 1.
 var<uniform> camera: CameraUniform;
 
+// Camera relative when enabled, the subtraction keeps precision a translation in the matrix loses
+fn world_to_clip(world_position: vec3<f32>) -> vec4<f32> {
+    return camera.relative_view_proj * vec4<f32>(world_position - camera.render_origin.xyz, 1.0);
+}
+
 struct Light {
     position: vec3<f32>,
     direction: vec3<f32>,
@@ -23,6 +30,13 @@ struct Light {
     // cutoff_inner_outer_eps[4] == 0? no_cutoff: cutoff
     cutoff_inner_outer_eps: vec4<f32>,
     view_proj: mat4x4<f32>,
+    // 0 is ShadowSettings.resolution
+    shadow_resolution: f32,
+    cast_shadows: f32,
+    // 1 projects its layer of t_gobo
+    gobo: f32,
+    // area_size.x > 0? rectangle light: point, spot or directional
+    area_size: vec2<f32>,
 }
 
 struct Lights {
@@ -32,11 +46,108 @@ struct Lights {
 @group(1) @binding(0)
 var<uniform> lights: Lights;
 
+struct SceneUniform {
+    ambient_sky: vec4<f32>,
+    ambient_ground: vec4<f32>,
+    // 0: none, 1: flat, 2: hemisphere, 3: spherical harmonics
+    ambient_mode: u32,
+    ambient_intensity: f32,
+    time: f32,
+    ambient_sh: array<vec4<f32>, 9>,
+}
+
+@group(1) @binding(1)
+var<uniform> scene: SceneUniform;
+
+fn scene_ambient(normal: vec3<f32>) -> vec3<f32> {
+    var ambient = vec3<f32>(0.0);
+    if scene.ambient_mode == 1u {
+        ambient = scene.ambient_sky.rgb;
+    } else if scene.ambient_mode == 2u {
+        ambient = mix(scene.ambient_ground.rgb, scene.ambient_sky.rgb, normal.y * 0.5 + 0.5);
+    } else if scene.ambient_mode == 3u {
+        let sh = scene.ambient_sh;
+        ambient = sh[0].rgb * 0.282095
+            + sh[1].rgb * 0.488603 * normal.y
+            + sh[2].rgb * 0.488603 * normal.z
+            + sh[3].rgb * 0.488603 * normal.x
+            + sh[4].rgb * 1.092548 * normal.x * normal.y
+            + sh[5].rgb * 1.092548 * normal.y * normal.z
+            + sh[6].rgb * 0.315392 * (3.0 * normal.z * normal.z - 1.0)
+            + sh[7].rgb * 1.092548 * normal.x * normal.z
+            + sh[8].rgb * 0.546274 * (normal.x * normal.x - normal.y * normal.y);
+        ambient = max(ambient, vec3<f32>(0.0));
+    }
+    return ambient * scene.ambient_intensity;
+}
+
+struct ReflectionProbes {
+    // xyz world position
+    positions: array<vec4<f32>, 8>,
+    count: u32,
+}
+
+// A cube array on native, a single cube on WebGL, see probes.rs
+@group(1) @binding(2)
+var t_probes: PROBE_TEXTURE;
+@group(1) @binding(3)
+var s_probes: sampler;
+@group(1) @binding(4)
+var<uniform> probes: ReflectionProbes;
+// Tables of the rectangle lights, see ltc.rs
+@group(1) @binding(5)
+var t_ltc_matrix: texture_2d<f32>;
+@group(1) @binding(6)
+var t_ltc_magnitude: texture_2d<f32>;
+@group(1) @binding(7)
+var s_ltc: sampler;
+// The skybox at probe size, reflected while there are no probes
+@group(1) @binding(8)
+var t_sky: texture_cube<f32>;
+
+// Index of the probe closest to position, -1 without probes
+fn nearest_probe(position: vec3<f32>) -> i32 {
+    var nearest = -1;
+    var nearest_distance = 0.0;
+    for (var i = 0u; i < probes.count; i++) {
+        let offset = probes.positions[i].xyz - position;
+        let distance = dot(offset, offset);
+        if nearest < 0 || distance < nearest_distance {
+            nearest = i32(i);
+            nearest_distance = distance;
+        }
+    }
+    return nearest;
+}
+
+fn sample_probe(dir: vec3<f32>, probe: i32) -> vec3<f32> {
+    return textureSampleLevel(t_probes, s_probes, dir, PROBE_INDEX 0.0).rgb;
+}
+
+// Light arriving around `normal` from the scene near the probe, which a coarse mip of its cube
+// averages. Stands in for the spherical harmonics of the sky, see probes.rs.
+fn probe_ambient(normal: vec3<f32>, probe: i32) -> vec3<f32> {
+    if scene.ambient_mode != 3u || probe < 0 {
+        return scene_ambient(normal);
+    }
+    return textureSampleLevel(t_probes, s_probes, normal, PROBE_INDEX 5.0).rgb * scene.ambient_intensity;
+}
+
+// What a mirror facing `dir` shows, the sky when there is no probe
+fn sample_environment(dir: vec3<f32>, probe: i32) -> vec3<f32> {
+    if probe < 0 {
+        return textureSampleLevel(t_sky, s_probes, dir, 0.0).rgb;
+    }
+    return sample_probe(dir, probe);
+}
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    // DEFORM_INPUT
 };
+// DEFORM_FUNCTION
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
@@ -45,6 +156,9 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) color: vec4<f32>,
+    // offset in xy, scale in zw
+    @location(13) uv: vec4<f32>,
 };
 
 struct VertexOutput {
@@ -52,6 +166,10 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    // Nearest reflection probe to the instance origin
+    @location(3) @interpolate(flat) probe: i32,
+    // Multiplied with the diffuse texture
+    @location(4) instance_color: vec4<f32>,
 };
 
 
@@ -73,42 +191,102 @@ fn vs_main(
             instance.normal_matrix_2
         );
     var v_out: VertexOutput;
-    v_out.tex_coords = model.tex_coords;
-    v_out.world_normal = normal_matrix * model.normal;
-    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    v_out.tex_coords = model.tex_coords * instance.uv.zw + instance.uv.xy;
+    v_out.instance_color = instance.color;
+    let deformed = deform(model);
+    v_out.world_normal = normalize(normal_matrix * deformed.normal);
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(deformed.position, 1.0);
     v_out.world_position = world_position.xyz;
-    v_out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    v_out.clip_position = world_to_clip(world_position.xyz);
+    v_out.probe = nearest_probe(instance.model_matrix_3.xyz);
     return v_out;
 }
 
 // Fragment shader
 
+// The material textures and uniform become arrays indexed by a push constant on the bindless
+// path, see model_shader_source in model.rs
+// MATERIAL_INDEX_DECLARATION
 @group(2) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_diffuse: MATERIAL_TEXTURE;
 @group(2) @binding(1)
 var s_diffuse: sampler;
 
-struct MaterialUniform {
-    ambient: vec3<f32>,
-    diffuse: vec3<f32>,
-    specular: vec3<f32>,
-    shininess: f32
+struct TintUniform {
+    tint: vec4<f32>,
+    // alpha is the mix factor
+    flash: vec4<f32>,
+    // Mix factor of the environment, the nearest reflection probe or the sky
+    reflectivity: f32,
+    // How much light passes through thin surfaces lit from behind
+    translucency: f32,
+    uv_scroll: vec2<f32>,
+    // columns, rows, frames per second
+    flipbook: vec4<f32>,
+    // alpha is the weight of the emissive texture
+    emissive: vec4<f32>,
 };
-// @group(3) @binding(0)
-// var<uniform> material_uniform: MaterialUniform;
+@group(2) @binding(2)
+var<uniform> tint: TintUniform;
+@group(2) @binding(3)
+var t_emissive: texture_2d<f32>;
+
+// Added after lighting and tint, unlit surfaces still glow and bright ones go past 1
+fn emission(v_tex: vec2<f32>) -> vec3<f32> {
+    let texel = textureSample(t_emissive, s_diffuse, v_tex).rgb;
+    return tint.emissive.rgb * mix(vec3<f32>(1.0), texel, tint.emissive.a);
+}
 
-fn multisample_tex(tex_coords: vec2<f32>, sample_count: f32) -> vec4<f32> {
-    let tex_c = vec2<f32>(tex_coords.x % 1.0, 1.0 - tex_coords.y % 1.0);
-    let normal_tex_cord = vec2<i32>(vec2<f32>(textureDimensions(t_diffuse)) * tex_c);
-    var obj_color = vec4<f32>(0.0);
-    let sample_count: f32 = 1.0;
-    for (var i: i32 = 0; i < i32(sample_count); i = i + 1) {
-      obj_color = obj_color + textureLoad(t_diffuse, normal_tex_cord, i);
+// Texture space coordinates of the diffuse texture with scrolling and flipbook applied
+fn diffuse_uv(tex_coords: vec2<f32>) -> vec2<f32> {
+    let cells = max(tint.flipbook.xy, vec2<f32>(1.0));
+    let frames = cells.x * cells.y;
+    let scrolling = any(tint.uv_scroll != vec2<f32>(0.0));
+    if !scrolling && (frames <= 1.0 || tint.flipbook.z <= 0.0) {
+        return vec2<f32>(tex_coords.x, 1.0 - tex_coords.y);
     }
-    obj_color = obj_color / sample_count;
-    return obj_color;
+    let uv = fract(tex_coords + tint.uv_scroll * scene.time);
+    let frame = floor(scene.time * tint.flipbook.z) % frames;
+    let cell = vec2<f32>(frame % cells.x, floor(frame / cells.x));
+    return (vec2<f32>(uv.x, 1.0 - uv.y) + cell) / cells;
+}
+
+fn apply_tint(color: vec3<f32>) -> vec3<f32> {
+    return mix(color * tint.tint.rgb, tint.flash.rgb, tint.flash.a);
 }
 
+// The tint raises the reflectivity of the material, it never lowers it
+fn apply_reflection(f_in: VertexOutput, color: vec3<f32>, material: f32) -> vec3<f32> {
+    let reflectivity = max(tint.reflectivity, material);
+    if reflectivity <= 0.0 {
+        return color;
+    }
+    let view_dir = normalize(f_in.world_position - camera.view_pos.xyz);
+    let dir = reflect(view_dir, normalize(f_in.world_normal));
+    return mix(color, sample_environment(dir, f_in.probe), reflectivity);
+}
+
+// The colors of the mtl file, alpha is unused
+struct MaterialUniform {
+    ambient: vec4<f32>,
+    diffuse: vec4<f32>,
+    specular: vec4<f32>,
+    emissive: vec4<f32>,
+    shininess: f32,
+    // Pm of the mtl file, how much of the environment the surface mirrors
+    reflectivity: f32,
+};
+
+// map_Ks multiplies Ks, map_Ns multiplies Ns and the alpha of map_d cuts the surface out
+@group(2) @binding(4)
+var t_specular: MATERIAL_TEXTURE;
+@group(2) @binding(5)
+var t_shininess: MATERIAL_TEXTURE;
+@group(2) @binding(6)
+var t_dissolve: MATERIAL_TEXTURE;
+@group(2) @binding(7)
+var<uniform> materials: MATERIAL_UNIFORMS;
+
 fn attenuation(light: Light, world_position: vec3<f32>) -> vec3<f32> {
     var light_color = light.color.rgb;
     if light.point_clq[3] != 0.0 {
@@ -135,31 +313,279 @@ var t_shadow: texture_depth_2d_array;
 @binding(1)
 var sampler_shadow: sampler_comparison;
 
+// See ShadowFilter in shadow.rs
+struct ShadowSettings {
+    filter: u32,
+    texel_size: f32,
+    light_size: f32,
+    near: f32,
+    far: f32,
+    // Of the lights without their own
+    resolution: f32,
+};
+@group(3)
+@binding(2)
+var<uniform> shadow_settings: ShadowSettings;
+// Blurred depth moments of ShadowFilter::Variance, see variance_shadow.rs
+@group(3)
+@binding(3)
+var t_moments: texture_2d_array<f32>;
+@group(3)
+@binding(4)
+var sampler_moments: sampler;
+// Images projected by the spot lights, see gobo.rs
+@group(3)
+@binding(5)
+var t_gobo: texture_2d_array<f32>;
+
+var<private> POISSON_DISK: array<vec2<f32>, 16> = array<vec2<f32>, 16>(
+    vec2<f32>(-0.94201624, -0.39906216),
+    vec2<f32>(0.94558609, -0.76890725),
+    vec2<f32>(-0.09418410, -0.92938870),
+    vec2<f32>(0.34495938, 0.29387760),
+    vec2<f32>(-0.91588581, 0.45771432),
+    vec2<f32>(-0.81544232, -0.87912464),
+    vec2<f32>(-0.38277543, 0.27676845),
+    vec2<f32>(0.97484398, 0.75648379),
+    vec2<f32>(0.44323325, -0.97511554),
+    vec2<f32>(0.53742981, -0.47373420),
+    vec2<f32>(-0.26496911, -0.41893023),
+    vec2<f32>(0.79197514, 0.19090188),
+    vec2<f32>(-0.24188840, 0.99706507),
+    vec2<f32>(-0.81409955, 0.91437590),
+    vec2<f32>(0.19984126, 0.78641367),
+    vec2<f32>(0.14383161, -0.14100790)
+);
+
+// Distance from the light of a shadow map depth
+fn linear_shadow_depth(depth: f32) -> f32 {
+    let near = shadow_settings.near;
+    let far = shadow_settings.far;
+    return near * far / (far - depth * (far - near));
+}
+
+// (2 * radius + 1)^2 taps one texel apart
+fn pcf_grid(uv: vec2<f32>, light_id: i32, depth: f32, radius: i32) -> f32 {
+    var lit = 0.0;
+    for (var x = -radius; x <= radius; x++) {
+        for (var y = -radius; y <= radius; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow_settings.texel_size;
+            lit += textureSampleCompareLevel(t_shadow, sampler_shadow, uv + offset, light_id, depth);
+        }
+    }
+    let width = f32(2 * radius + 1);
+    return lit / (width * width);
+}
+
+fn pcf_poisson(uv: vec2<f32>, light_id: i32, depth: f32, radius: f32) -> f32 {
+    var lit = 0.0;
+    for (var i = 0; i < 16; i++) {
+        let offset = POISSON_DISK[i] * radius;
+        lit += textureSampleCompareLevel(t_shadow, sampler_shadow, uv + offset, light_id, depth);
+    }
+    return lit / 16.0;
+}
+
+// Penumbra radius in shadow map coordinates, 0 without casters in front of the receiver
+// `scale` is the part of the array the map of the light covers
+fn pcss_radius(uv: vec2<f32>, light_id: i32, depth: f32, scale: f32) -> f32 {
+    let receiver = linear_shadow_depth(depth);
+    let near = shadow_settings.near;
+    // The part of the map the light sees the receiver through
+    let search = shadow_settings.light_size * (receiver - near) / receiver * scale;
+    let size = vec2<f32>(textureDimensions(t_shadow));
+    var blockers = 0.0;
+    var count = 0.0;
+    for (var i = 0; i < 16; i++) {
+        let texel = clamp((uv + POISSON_DISK[i] * search) * size, vec2<f32>(0.0), size - vec2<f32>(1.0));
+        let blocker = textureLoad(t_shadow, vec2<i32>(texel), light_id, 0);
+        if blocker < depth {
+            blockers += linear_shadow_depth(blocker);
+            count += 1.0;
+        }
+    }
+    if count == 0.0 {
+        return 0.0;
+    }
+    let blocker = blockers / count;
+    let penumbra = (receiver - blocker) / blocker * shadow_settings.light_size;
+    return penumbra * near / receiver * scale;
+}
+
+// Upper bound of the lit fraction from the mean and variance of the casters' depth
+fn variance_shadow(uv: vec2<f32>, light_id: i32, depth: f32) -> f32 {
+    let moments = textureSampleLevel(t_moments, sampler_moments, uv, light_id, 0.0).xy;
+    let receiver = linear_shadow_depth(depth) / shadow_settings.far;
+    if receiver <= moments.x {
+        return 1.0;
+    }
+    let variance = max(moments.y - moments.x * moments.x, 0.0001);
+    let distance = receiver - moments.x;
+    let lit = variance / (variance + distance * distance);
+    // Cuts off the tail of the bound, which lights up overlapping shadows
+    return clamp((lit - 0.3) / 0.7, 0.0, 1.0);
+}
 
 fn fetch_shadow(light_id: i32, homogeneous_coords: vec4<f32>) -> f32 {
-    if (homogeneous_coords.w <= 0.0) {
+    let light = lights.lights[light_id];
+    if (homogeneous_coords.w <= 0.0 || light.cast_shadows <= 0.0) {
         return 1.0;
     }
+    // Clamped to MIN_SHADOW_RESOLUTION and MAX_SHADOW_RESOLUTION of light.rs like the shadow pass does
+    let resolution = select(clamp(light.shadow_resolution, 256.0, 8192.0), shadow_settings.resolution, light.shadow_resolution <= 0.0);
+    let scale = min(resolution * shadow_settings.texel_size, 1.0);
     // compensate for the Y-flip difference between the NDC and texture coordinates
     let flip_correction = vec2<f32>(0.5, -0.5);
     // compute texture coordinates for shadow lookup
     let proj_correction = 1.0 / homogeneous_coords.w;
-    let light_local = homogeneous_coords.xy * flip_correction * proj_correction + vec2<f32>(0.5, 0.5);
+    // Smaller maps fill the top left corner of their layer
+    let light_local = (homogeneous_coords.xy * flip_correction * proj_correction + vec2<f32>(0.5, 0.5)) * scale;
+    let depth = homogeneous_coords.z * proj_correction;
+    let filter = shadow_settings.filter;
+    if filter == 1u {
+        return pcf_grid(light_local, light_id, depth, 1);
+    }
+    if filter == 2u {
+        return pcf_grid(light_local, light_id, depth, 2);
+    }
+    if filter == 3u {
+        return pcf_poisson(light_local, light_id, depth, 2.5 * shadow_settings.texel_size);
+    }
+    if filter == 4u {
+        // Never sharper than a texel
+        let radius = max(pcss_radius(light_local, light_id, depth, scale), shadow_settings.texel_size);
+        return pcf_poisson(light_local, light_id, depth, radius);
+    }
+    if filter == 5u {
+        return variance_shadow(light_local, light_id, depth);
+    }
     // do the lookup, using HW PCF and comparison
-    return textureSampleCompareLevel(t_shadow, sampler_shadow, light_local, light_id, homogeneous_coords.z * proj_correction);
+    return textureSampleCompareLevel(t_shadow, sampler_shadow, light_local, light_id, depth);
+}
+
+// Integral of a cosine over the edge between the direction v1 and v2, as a vector
+fn ltc_edge(v1: vec3<f32>, v2: vec3<f32>) -> vec3<f32> {
+    let x = dot(v1, v2);
+    let y = abs(x);
+    // Fitted to theta / sin(theta), without the acos
+    let a = 0.8543985 + (0.4965155 + 0.0145206 * y) * y;
+    let b = 3.4175940 + (4.1616724 + y) * y;
+    let v = a / b;
+    let theta_sin_theta = select(0.5 * inverseSqrt(max(1.0 - x * x, 1e-7)) - v, v, x > 0.0);
+    return cross(v1, v2) * theta_sin_theta;
+}
+
+// The cosine under m_inv integrated over the rectangle p0 p1 p2 p3 seen from `position`,
+// clipped to the horizon through the sphere approximation of the form factor
+fn ltc_rect(normal: vec3<f32>, view_dir: vec3<f32>, position: vec3<f32>, m_inv: mat3x3<f32>, p0: vec3<f32>, p1: vec3<f32>, p2: vec3<f32>, p3: vec3<f32>) -> f32 {
+    var tangent = view_dir - normal * dot(view_dir, normal);
+    if (dot(tangent, tangent) < 1e-8) {
+        // Looking straight down the normal, any tangent will do
+        tangent = cross(normal, select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(normal.x) > 0.9));
+    }
+    let t1 = normalize(tangent);
+    let t2 = -cross(normal, t1);
+    let m = m_inv * transpose(mat3x3<f32>(t1, t2, normal));
+    let v0 = normalize(m * (p0 - position));
+    let v1 = normalize(m * (p1 - position));
+    let v2 = normalize(m * (p2 - position));
+    let v3 = normalize(m * (p3 - position));
+    let form_factor = ltc_edge(v0, v1) + ltc_edge(v1, v2) + ltc_edge(v2, v3) + ltc_edge(v3, v0);
+    let len = length(form_factor);
+    return max((len * len + form_factor.z) / (len + 1.0), 0.0);
+}
+
+// Rectangle of LightUniform::area_size centered on the light, lit from the side the light
+// faces. The Blinn-Phong exponent is mapped to the GGX roughness of the tables.
+fn area_light(light: Light, position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, albedo: vec3<f32>, shininess: f32, specular_tint: vec3<f32>) -> vec3<f32> {
+    let facing = normalize(light.direction);
+    if (dot(facing, position - light.position) > 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let up = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(facing.y) > 0.99);
+    let width_dir = normalize(cross(up, facing));
+    let half_width = width_dir * 0.5 * light.area_size.x;
+    let half_height = cross(facing, width_dir) * 0.5 * light.area_size.y;
+    let p0 = light.position + half_width - half_height;
+    let p1 = light.position - half_width - half_height;
+    let p2 = light.position - half_width + half_height;
+    let p3 = light.position + half_width + half_height;
+
+    let roughness = sqrt(sqrt(2.0 / (shininess + 2.0)));
+    let n_dot_v = clamp(dot(normal, view_dir), 0.0, 1.0);
+    // Texel centers of the 64x64 tables
+    let uv = vec2<f32>(roughness, sqrt(1.0 - n_dot_v)) * (63.0 / 64.0) + vec2<f32>(0.5 / 64.0);
+    let t1 = textureSampleLevel(t_ltc_matrix, s_ltc, uv, 0.0);
+    let t2 = textureSampleLevel(t_ltc_magnitude, s_ltc, uv, 0.0);
+    let m_inv = mat3x3<f32>(
+        vec3<f32>(t1.x, 0.0, t1.y),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(t1.z, 0.0, t1.w),
+    );
+    let identity = mat3x3<f32>(
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+    );
+    let diffuse = ltc_rect(normal, view_dir, position, identity, p0, p1, p2, p3);
+    let specular = ltc_rect(normal, view_dir, position, m_inv, p0, p1, p2, p3);
+    // Schlick with the specular color as F0
+    let fresnel = specular_tint * t2.x + (vec3<f32>(1.0) - specular_tint) * t2.y;
+    return light.color.rgb * (light.diffuse_strength * diffuse * albedo + light.specular_strength * specular * fresnel);
+}
+
+// Color of the gobo of the light at the point, white for lights without one
+fn gobo(light_id: i32, homogeneous_coords: vec4<f32>) -> vec3<f32> {
+    if (lights.lights[light_id].gobo <= 0.0) {
+        return vec3<f32>(1.0);
+    }
+    if (homogeneous_coords.w <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let uv = homogeneous_coords.xy / homogeneous_coords.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+        return vec3<f32>(0.0);
+    }
+    return textureSampleLevel(t_gobo, sampler_moments, uv, light_id, 0.0).rgb;
+}
+
+// Back light glowing through thin geometry, the surface is assumed to be uniformly thin
+fn translucency(normal: vec3<f32>, light_dir: vec3<f32>, view_dir: vec3<f32>) -> f32 {
+    if tint.translucency <= 0.0 {
+        return 0.0;
+    }
+    let through = max(dot(-normal, light_dir), 0.0);
+    let behind = pow(max(dot(view_dir, -light_dir), 0.0), 4.0);
+    return tint.translucency * 0.5 * (through + behind);
 }
 
 @fragment
-fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+fn fs_main(vertex: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+     // Back faces only reach here on double-sided surfaces, they are lit from their own side
+     var f_in = vertex;
+     f_in.world_normal = select(-vertex.world_normal, vertex.world_normal, front_facing);
      let light_count = 2;
      var res = vec3<f32>(0.);
      // let light_count = i32(arrayLength(&lights.lights));
-     let v_tex = vec2<f32>(f_in.tex_coords.x, 1.0 - f_in.tex_coords.y);
-     let obj_color = textureSample(t_diffuse, s_diffuse, v_tex);
+     let v_tex = diffuse_uv(f_in.tex_coords);
+     let obj_color = textureSample(t_diffuse MATERIAL_INDEX, s_diffuse, v_tex) * f_in.instance_color;
+     let material = materials MATERIAL_INDEX;
+     let specular_map = textureSample(t_specular MATERIAL_INDEX, s_diffuse, v_tex).rgb;
+     let shininess_map = textureSample(t_shininess MATERIAL_INDEX, s_diffuse, v_tex).r;
+     let dissolve = textureSample(t_dissolve MATERIAL_INDEX, s_diffuse, v_tex).a;
+     // Sampled before discard, derivatives need uniform control flow
+     let glow = emission(v_tex);
+     if dissolve < 0.5 {
+        discard;
+     }
+     // Ns 0 is common in exported files, a flat lobe instead of specular everywhere
+     let shininess = max(material.shininess * shininess_map, 1.0);
+     let specular_tint = material.specular.rgb * specular_map;
 
      for(var i: i32 = 0; i < light_count; i++) {
      let light = lights.lights[i];
-     let shadow = fetch_shadow(i, light.view_proj * vec4<f32>(f_in.world_position, 1.0));
+     let light_space = light.view_proj * vec4<f32>(f_in.world_position, 1.0);
+     let shadow = fetch_shadow(i, light_space);
      let dis = length(light.position - f_in.world_position);
      let light_color = attenuation(light, f_in.world_position);
      let ambient_strength = light.ambient_strength;
@@ -167,15 +593,21 @@ fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
      let light_dir = normalize(light.position - f_in.world_position);
      let view_dir = normalize(camera.view_pos.xyz - f_in.world_position);
      let half_dir = normalize(view_dir + light_dir);
+     if light.area_size.x > 0.0 {
+         res += shadow * (ambient_color * obj_color.rgb + area_light(light, f_in.world_position, f_in.world_normal, view_dir, obj_color.rgb, shininess, specular_tint));
+         continue;
+     }
 
-     let cut_off_intensity = cutoff(light, -light_dir);
+     let cut_off_intensity = cutoff(light, -light_dir) * gobo(i, light_space);
 
      let diffuse_strength = max(dot(f_in.world_normal, light_dir), 0.0);
      let diffuse_color = light_color * diffuse_strength  * cut_off_intensity; // * material_uniform.diffuse
 
-     let specular_strength = pow(max(dot(f_in.world_normal, half_dir), 0.0), 32.0);
-     let specular_color = light.specular_strength * specular_strength * light_color * cut_off_intensity; // * material_uniform.specular
-        res += shadow * (ambient_color + diffuse_color + specular_color) * obj_color.rgb;
+     let specular_strength = pow(max(dot(f_in.world_normal, half_dir), 0.0), shininess);
+     let specular_color = light.specular_strength * specular_strength * light_color * cut_off_intensity * specular_tint;
+     let translucent_color = translucency(f_in.world_normal, light_dir, view_dir) * light_color * cut_off_intensity;
+        res += shadow * ((ambient_color + diffuse_color + translucent_color) * obj_color.rgb + specular_color);
      }
-    return vec4<f32>(res, obj_color.a);
+    res += probe_ambient(normalize(f_in.world_normal), f_in.probe) * obj_color.rgb;
+    return vec4<f32>(apply_tint(apply_reflection(f_in, res, material.reflectivity)) + glow, obj_color.a);
 }
\ No newline at end of file
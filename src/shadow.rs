@@ -1,39 +1,164 @@
-use crate::geo_gen::GeoObj;
+use crate::bounds::BoundingSphere;
+use crate::deform;
+use crate::gobo::Gobos;
+use crate::light::{LightUniform, LIGHT_FAR, LIGHT_FOVY, LIGHT_NEAR, MIN_SHADOW_RESOLUTION};
+use crate::profiling::profile_scope;
+use crate::skinning::Influence;
+use crate::stats;
+use crate::stats::DrawStats;
+use crate::variance_shadow::{empty_moments_view, VarianceShadowMaps};
 use crate::{geo_gen, world_space, LightRenderGroup, RenderGroup};
-use std::cell::Ref;
+use cgmath::Transform;
+use image::RgbaImage;
+use serde::Deserialize;
 use std::num::NonZeroU32;
+use wgpu::util::DeviceExt;
 use wgpu::{
-    BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, RenderPipeline, Sampler, Texture,
-    TextureView,
+    BindGroup, BindGroupLayout, Buffer, CommandBuffer, CommandEncoder, Device, PipelineLayout,
+    Queue, RenderPipeline, Sampler, TextureView,
 };
 
+// How the lit shaders sample the shadow maps, every tap is a bilinear comparison
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShadowFilter {
+    // A single tap, hard and aliased edges
+    Hard = 0,
+    // Square kernels of 3x3 and 5x5 taps
+    Pcf3 = 1,
+    Pcf5 = 2,
+    // 16 taps on a Poisson disk, smoother than the grids at the same radius
+    Poisson = 3,
+    // The Poisson kernel scaled by the distance to the casters found in a blocker search, sharp
+    // where the shadow touches its caster and softer further away
+    Pcss = 4,
+    // Blurred depth moments instead of depth comparisons, see variance_shadow.rs
+    Variance = 5,
+}
+
+// Matches ShadowSettings in shader.wgsl and geo.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    filter: u32,
+    texel_size: f32,
+    light_size: f32,
+    near: f32,
+    far: f32,
+    // For the lights that leave their resolution at 0
+    resolution: f32,
+    _padding: [f32; 2],
+}
+
 pub struct ShadowPass {
+    // RendererConfig::shadow_resolution
+    default_resolution: u32,
+    // Of the array, every light renders into a corner of its resolution
+    size: u32,
     pipeline: RenderPipeline,
-    shadow_texture: Texture,
+    pipeline_layout: PipelineLayout,
+    // For the meshes moved in the vertex shader, None where they are moved on the CPU
+    deformed_pipeline: Option<RenderPipeline>,
+    deformed_pipeline_layout: Option<PipelineLayout>,
     shadow_view: TextureView,
     shadow_target_views: Vec<TextureView>,
     shadow_sampler: Sampler,
+    filter: ShadowFilter,
+    uniform_buffer: Buffer,
+    // Created the first time ShadowFilter::Variance is selected
+    variance: Option<VarianceShadowMaps>,
+    moments_sampler: Sampler,
+    empty_moments_view: TextureView,
+    // Sampled with the moments sampler
+    gobos: Gobos,
     pub shadow_map_bind_group_layout: BindGroupLayout,
     pub(crate) shadow_map_bind_group: BindGroup,
 }
 const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
-impl ShadowPass {
-    pub fn new(device: &Device, light_render_group: &LightRenderGroup) -> Self {
-        let light_count = light_render_group.light_render_triplets.len();
-        let size = wgpu::Extent3d {
-            width: 2048,
-            height: 2048,
+// Casters smaller than this many shadow map texels across are skipped
+const SHADOW_CULL_TEXELS: f32 = 2.0;
+// Casters smaller than this draw their first shadow lod
+const SHADOW_LOD_TEXELS: f32 = 96.0;
+// Width of the light for PCSS, in shadow map coordinates at the near plane of the light
+const PCSS_LIGHT_SIZE: f32 = 0.01;
+
+// Large enough for the largest map of the lights that cast shadows
+fn array_size(light_render_group: &LightRenderGroup, default_resolution: u32) -> u32 {
+    light_render_group
+        .light_uniforms
+        .iter()
+        .filter(|light| light.casts_shadows())
+        .map(|light| light.shadow_map_size(default_resolution))
+        .max()
+        .unwrap_or(MIN_SHADOW_RESOLUTION)
+}
+
+// The depth array and a view of each layer to render into
+fn create_maps(device: &Device, size: u32, light_count: usize) -> (TextureView, Vec<TextureView>) {
+    let shadow_texture = device.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
             depth_or_array_layers: light_count as u32,
-        };
-        let shadow_texture = device.create_texture(&wgpu::TextureDescriptor {
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: SHADOW_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: None,
-        });
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: SHADOW_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        label: None,
+    });
+    let shadow_view = shadow_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let shadow_target_views = (0..light_count)
+        .map(|i| {
+            shadow_texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("shadow"),
+                format: None,
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level: 0,
+                mip_level_count: None,
+                base_array_layer: i as u32,
+                array_layer_count: NonZeroU32::new(1),
+            })
+        })
+        .collect();
+    (shadow_view, shadow_target_views)
+}
+
+// None if the caster is too small in the light's view to leave a visible shadow
+fn shadow_lod(light: &LightUniform, bounds: &BoundingSphere, shadow_size: u32) -> Option<usize> {
+    let center = light.calc_view_matrix().transform_point(bounds.center);
+    let distance = -center.z;
+    if distance < -bounds.radius {
+        // Entirely behind the light
+        return None;
+    }
+    if distance <= bounds.radius {
+        // The light is inside the bounds
+        return Some(0);
+    }
+    let half_fov = cgmath::Rad::from(cgmath::Deg(LIGHT_FOVY / 2.0)).0;
+    let diameter = bounds.radius / (distance * half_fov.tan()) * shadow_size as f32;
+    if diameter < SHADOW_CULL_TEXELS {
+        None
+    } else if diameter < SHADOW_LOD_TEXELS {
+        Some(1)
+    } else {
+        Some(0)
+    }
+}
+impl ShadowPass {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        light_render_group: &LightRenderGroup,
+        default_resolution: u32,
+        filter: ShadowFilter,
+    ) -> Self {
+        let light_count = light_render_group.light_render_pairs.len();
+        let size = array_size(light_render_group, default_resolution);
+        let (shadow_view, shadow_target_views) = create_maps(device, size, light_count);
         let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("shadow"),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -45,62 +170,25 @@ impl ShadowPass {
             compare: Some(wgpu::CompareFunction::LessEqual),
             ..Default::default()
         });
-        let shadow_view = shadow_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let mut shadow_target_views: Vec<_> = (0..light_count)
-            .map(|i| {
-                shadow_texture.create_view(&wgpu::TextureViewDescriptor {
-                    label: Some("shadow"),
-                    format: None,
-                    dimension: Some(wgpu::TextureViewDimension::D2),
-                    aspect: wgpu::TextureAspect::All,
-                    base_mip_level: 0,
-                    mip_level_count: None,
-                    base_array_layer: i as u32,
-                    array_layer_count: NonZeroU32::new(1),
-                })
-            })
-            .collect();
-        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-            label: Some("Shadow Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
-        });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("shadow"),
             bind_group_layouts: &[&light_render_group.light_bind_group_layout],
             push_constant_ranges: &[],
         });
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("shadow"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_bake",
-                buffers: &[world_space::desc(), geo_gen::Vertex::desc()],
-            },
-            fragment: None,
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: device
-                    .features()
-                    .contains(wgpu::Features::DEPTH_CLIP_CONTROL),
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: SHADOW_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState {
-                    constant: 2, // corresponds to bilinear filtering
-                    slope_scale: 2.0,
-                    clamp: 0.0,
-                },
-            }),
-            multisample: Default::default(),
-            multiview: None,
+        let pipeline = create_pipeline(device, &pipeline_layout, false);
+        let deformed_pipeline_layout = deform::vertex_deform().then(|| {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("deformed shadow"),
+                bind_group_layouts: &[
+                    &light_render_group.light_bind_group_layout,
+                    &deform::bind_group_layout(device),
+                ],
+                push_constant_ranges: &[],
+            })
         });
+        let deformed_pipeline = deformed_pipeline_layout
+            .as_ref()
+            .map(|layout| create_pipeline(device, layout, true));
         let shadow_map_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -120,59 +208,418 @@ impl ShadowPass {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("shadow map layout"),
             });
-        let shadow_map_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Shadow map bind group"),
-            layout: &shadow_map_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&shadow_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&shadow_sampler),
-                },
-            ],
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow settings"),
+            contents: bytemuck::cast_slice(&[Self::uniform(filter, size, default_resolution)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let moments_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow moments"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
         });
+        let variance = (filter == ShadowFilter::Variance).then(|| {
+            VarianceShadowMaps::new(
+                device,
+                &pipeline_layout,
+                deformed_pipeline_layout.as_ref(),
+                SHADOW_FORMAT,
+                size,
+                light_count as u32,
+            )
+        });
+        let empty_moments_view = empty_moments_view(device);
+        let gobos = Gobos::new(device, queue, light_count);
+        let shadow_map_bind_group = create_bind_group(
+            device,
+            &shadow_map_bind_group_layout,
+            [
+                &shadow_view,
+                variance
+                    .as_ref()
+                    .map_or(&empty_moments_view, |variance| &variance.view),
+                &gobos.view,
+            ],
+            [&shadow_sampler, &moments_sampler],
+            &uniform_buffer,
+        );
         Self {
+            default_resolution,
+            size,
             pipeline,
-            shadow_texture,
+            pipeline_layout,
+            deformed_pipeline,
+            deformed_pipeline_layout,
             shadow_view,
             shadow_target_views,
             shadow_sampler,
+            filter,
+            uniform_buffer,
+            variance,
+            moments_sampler,
+            empty_moments_view,
+            gobos,
             shadow_map_bind_group_layout,
             shadow_map_bind_group,
         }
     }
-    pub fn render_pass(
+
+    // Binds the moments once they exist
+    fn update_bind_group(&mut self, device: &Device) {
+        let moments_view = self
+            .variance
+            .as_ref()
+            .map_or(&self.empty_moments_view, |variance| &variance.view);
+        self.shadow_map_bind_group = create_bind_group(
+            device,
+            &self.shadow_map_bind_group_layout,
+            [&self.shadow_view, moments_view, &self.gobos.view],
+            [&self.shadow_sampler, &self.moments_sampler],
+            &self.uniform_buffer,
+        );
+    }
+
+    fn uniform(filter: ShadowFilter, size: u32, default_resolution: u32) -> ShadowUniform {
+        ShadowUniform {
+            filter: filter as u32,
+            texel_size: 1.0 / size as f32,
+            light_size: PCSS_LIGHT_SIZE,
+            near: LIGHT_NEAR,
+            far: LIGHT_FAR,
+            resolution: default_resolution as f32,
+            _padding: [0.; 2],
+        }
+    }
+
+    fn write_uniform(&self, queue: &Queue) {
+        let uniform = Self::uniform(self.filter, self.size, self.default_resolution);
+        stats::write_buffer(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform]),
+        );
+    }
+
+    // Reallocates the maps when the lights need a different size or there are more of them
+    pub(crate) fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        light_render_group: &LightRenderGroup,
+    ) {
+        let light_count = light_render_group.light_render_pairs.len();
+        let size = array_size(light_render_group, self.default_resolution);
+        if size == self.size && light_count == self.shadow_target_views.len() {
+            return;
+        }
+        self.size = size;
+        (self.shadow_view, self.shadow_target_views) = create_maps(device, size, light_count);
+        if light_count != self.gobos.light_count() {
+            // The images of the old lights are dropped with their layers
+            self.gobos = Gobos::new(device, queue, light_count);
+        }
+        if self.variance.is_some() {
+            self.variance = Some(VarianceShadowMaps::new(
+                device,
+                &self.pipeline_layout,
+                self.deformed_pipeline_layout.as_ref(),
+                SHADOW_FORMAT,
+                size,
+                light_count as u32,
+            ));
+        }
+        self.update_bind_group(device);
+        self.write_uniform(queue);
+    }
+
+    // Light `light` projects `image` where LightUniform::gobo is set, None clears it
+    pub fn set_gobo(&self, queue: &Queue, light: usize, image: Option<&RgbaImage>) {
+        if light < self.gobos.light_count() {
+            self.gobos.set(queue, light, image);
+        }
+    }
+
+    pub fn filter(&self) -> ShadowFilter {
+        self.filter
+    }
+
+    pub fn set_filter(&mut self, device: &Device, queue: &Queue, filter: ShadowFilter) {
+        self.filter = filter;
+        if filter == ShadowFilter::Variance && self.variance.is_none() {
+            self.variance = Some(VarianceShadowMaps::new(
+                device,
+                &self.pipeline_layout,
+                self.deformed_pipeline_layout.as_ref(),
+                SHADOW_FORMAT,
+                self.size,
+                self.shadow_target_views.len() as u32,
+            ));
+            self.update_bind_group(device);
+        }
+        self.write_uniform(queue);
+    }
+    // One command buffer per light, recorded in parallel on native. On wasm all lights share one.
+    pub fn encode(
+        &self,
+        device: &Device,
+        groups: &[&dyn RenderGroup],
+        light_render_group: &LightRenderGroup,
+    ) -> (Vec<CommandBuffer>, DrawStats) {
+        let light_count = light_render_group.light_render_pairs.len();
+        let new_encoder = || {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Shadow Encoder"),
+            })
+        };
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let mut encoder = new_encoder();
+                let mut stats = DrawStats::default();
+                for i in 0..light_count {
+                    stats += self.render_light(&mut encoder, groups, light_render_group, i);
+                }
+                (vec![encoder.finish()], stats)
+            } else {
+                use rayon::prelude::*;
+                let encoded: Vec<_> = (0..light_count)
+                    .into_par_iter()
+                    .map(|i| {
+                        profile_scope!("shadow light");
+                        let mut encoder = new_encoder();
+                        let stats = self.render_light(&mut encoder, groups, light_render_group, i);
+                        (encoder.finish(), stats)
+                    })
+                    .collect();
+                let mut stats = DrawStats::default();
+                let buffers = encoded
+                    .into_iter()
+                    .map(|(buffer, light_stats)| {
+                        stats += light_stats;
+                        buffer
+                    })
+                    .collect();
+                (buffers, stats)
+            }
+        }
+    }
+
+    fn render_light(
         &self,
         encoder: &mut CommandEncoder,
-        refs: &Vec<Ref<dyn RenderGroup>>,
-        lights: &Vec<(Buffer, BindGroup, GeoObj)>,
-    ) {
-        for (i, light) in lights.iter().enumerate() {
-            {
-                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("ShadowPass"),
-                    color_attachments: &[],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &self.shadow_target_views[i],
-                        depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
-                            store: true,
-                        }),
-                        stencil_ops: None,
-                    }),
-                });
-                pass.set_pipeline(&self.pipeline);
-                pass.set_bind_group(0, &light.1, &[]);
-                refs.iter().for_each(|x| {
-                    x.render(&mut pass, true);
-                });
+        groups: &[&dyn RenderGroup],
+        light_render_group: &LightRenderGroup,
+        i: usize,
+    ) -> DrawStats {
+        let mut stats = DrawStats::default();
+        let light = &light_render_group.light_render_pairs[i];
+        let uniform = &light_render_group.light_uniforms[i];
+        if !uniform.casts_shadows() {
+            return stats;
+        }
+        let resolution = uniform.shadow_map_size(self.default_resolution);
+        let variance = self
+            .variance
+            .as_ref()
+            .filter(|_| self.filter == ShadowFilter::Variance);
+        let moments_attachments: Vec<_> = variance
+            .iter()
+            .map(|variance| wgpu::RenderPassColorAttachment {
+                view: &variance.layer_views[i],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // Fully lit past the far plane
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })
+            .collect();
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ShadowPass"),
+            color_attachments: &moments_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.shadow_target_views[i],
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        // The lit shaders only sample this corner
+        pass.set_viewport(0., 0., resolution as f32, resolution as f32, 0., 1.);
+        pass.set_pipeline(variance.map_or(&self.pipeline, |variance| &variance.pipeline));
+        pass.set_bind_group(0, &light.1, &[]);
+        let mut deformed = vec![];
+        groups.iter().for_each(|x| {
+            let lod = match x.bounding_sphere() {
+                Some(bounds) => shadow_lod(uniform, &bounds, resolution),
+                None => Some(0),
+            };
+            match lod {
+                Some(lod) => {
+                    x.render_shadow(&mut pass, lod);
+                    stats += x.draw_stats(true);
+                    if x.deformed() {
+                        deformed.push(x);
+                    }
+                }
+                None => stats.culled += 1,
+            }
+        });
+        let deformed_pipeline = match variance {
+            Some(variance) => variance.deformed_pipeline.as_ref(),
+            None => self.deformed_pipeline.as_ref(),
+        };
+        if let (Some(pipeline), false) = (deformed_pipeline, deformed.is_empty()) {
+            pass.set_pipeline(pipeline);
+            for x in deformed {
+                x.render_deformed(&mut pass, true);
             }
         }
+        drop(pass);
+        if let Some(variance) = variance {
+            variance.blur(encoder, i);
+        }
+        stats
     }
 }
+
+// The depth maps and their comparison sampler, the filter settings, the moments with their
+// filtering sampler and the gobos sampled with it too
+fn create_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    [depth_view, moments_view, gobo_view]: [&TextureView; 3],
+    [depth_sampler, moments_sampler]: [&Sampler; 2],
+    uniform_buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Shadow map bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(depth_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(moments_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(moments_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(gobo_view),
+            },
+        ],
+    })
+}
+
+// shadow.wgsl with the far plane the moments are normalized by. The `deformed` shader reads the
+// buffers of the meshes moved in the vertex shader from deform::DEPTH_GROUP.
+pub(crate) fn shader_source(deformed: bool) -> String {
+    let source = include_str!("shadow.wgsl").replace("LIGHT_FAR", &format!("{:?}", LIGHT_FAR));
+    deform::shader_source(&source, deformed.then(|| deform::DEPTH_GROUP))
+}
+
+// The vertex buffers of the depth-only passes, the joint influences after the vertices for the
+// `deformed` pipelines
+pub(crate) fn vertex_buffers<'a>(deformed: bool) -> Vec<wgpu::VertexBufferLayout<'a>> {
+    let mut buffers = vec![world_space::desc(), geo_gen::Vertex::desc()];
+    if deformed {
+        buffers.push(Influence::desc());
+    }
+    buffers
+}
+
+fn create_pipeline(device: &Device, layout: &PipelineLayout, deformed: bool) -> RenderPipeline {
+    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some("Shadow Shader"),
+        source: wgpu::ShaderSource::Wgsl(shader_source(deformed).into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("shadow"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_bake",
+            buffers: &vertex_buffers(deformed),
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: device
+                .features()
+                .contains(wgpu::Features::DEPTH_CLIP_CONTROL),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: SHADOW_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState {
+                constant: 2, // corresponds to bilinear filtering
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        }),
+        multisample: Default::default(),
+        multiview: None,
+    })
+}
@@ -12,6 +12,13 @@ struct Light {
     // cutoff_inner_outer_eps[4] == 0? no_cutoff: cutoff
     cutoff_inner_outer_eps: vec4<f32>,
     view_proj: mat4x4<f32>,
+    // 0 is ShadowSettings.resolution
+    shadow_resolution: f32,
+    cast_shadows: f32,
+    // 1 projects its layer of t_gobo
+    gobo: f32,
+    // area_size.x > 0? rectangle light: point, spot or directional
+    area_size: vec2<f32>,
 }
 
 
@@ -24,7 +31,9 @@ struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    // DEFORM_INPUT
 };
+// DEFORM_FUNCTION
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
@@ -43,5 +52,41 @@ fn vs_bake(model: VertexInput, instance: InstanceInput) -> @builtin(position) ve
         instance.model_matrix_2,
         instance.model_matrix_3
     );
-    return light.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
-}
\ No newline at end of file
+    return light.view_proj * model_matrix * vec4<f32>(deform(model).position, 1.0);
+}
+// The depth pre-pass, where `light` is the camera: view_proj is its relative_view_proj and
+// position its render_origin. Must match world_to_clip in geo.wgsl for the Equal depth test.
+@vertex
+fn vs_depth(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3
+    );
+    let world_position = model_matrix * vec4<f32>(deform(model).position, 1.0);
+    return light.view_proj * vec4<f32>(world_position.xyz - light.position, 1.0);
+}
+
+struct MomentsOutput {
+    @builtin(position) position: vec4<f32>,
+    // Distance along the light direction over the far plane, linear unlike the depth buffer
+    @location(0) depth: f32,
+};
+
+@vertex
+fn vs_moments(model: VertexInput, instance: InstanceInput) -> MomentsOutput {
+    var out: MomentsOutput;
+    out.position = vs_bake(model, instance);
+    out.depth = out.position.w / LIGHT_FAR;
+    return out;
+}
+
+// The first two moments of the depth for variance shadow maps, filtered like colors later
+@fragment
+fn fs_moments(vertex: MomentsOutput) -> @location(0) vec2<f32> {
+    // The slope of the depth across the pixel adds to the variance, less acne on steep surfaces
+    let dx = dpdx(vertex.depth);
+    let dy = dpdy(vertex.depth);
+    return vec2<f32>(vertex.depth, vertex.depth * vertex.depth + 0.25 * (dx * dx + dy * dy));
+}
@@ -0,0 +1,331 @@
+use crate::geo_gen::MeshData;
+use cgmath::{InnerSpace, Vector3};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// Quadric error metric simplification (Garland and Heckbert 1997) through half edge collapses.
+// A collapse moves one vertex onto a neighbour, so the vertices that survive keep their exact
+// position, normal and texture coordinates. Open borders, which include the texture seams of
+// meshes loaded with split vertices, are held in place by steep planes along them.
+const BORDER_WEIGHT: f64 = 1000.;
+
+// Upper triangle of the symmetric 4x4 matrix summing the squared distances to planes
+#[derive(Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // Plane n . p + d = 0 with a unit normal
+    fn plane(n: Vector3<f64>, d: f64, weight: f64) -> Self {
+        let [a, b, c] = [n.x, n.y, n.z];
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+        .scaled(weight)
+    }
+
+    fn scaled(self, weight: f64) -> Self {
+        Self(self.0.map(|x| x * weight))
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        aa * x * x
+            + bb * y * y
+            + cc * z * z
+            + 2. * (ab * x * y + ac * x * z + bc * y * z)
+            + 2. * (ad * x + bd * y + cd * z)
+            + dd
+    }
+}
+
+// `from` moved onto `to`, valid while neither vertex changed since it was queued
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    stamps: [u32; 2],
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed so the heap pops the cheapest collapse
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+struct Simplifier {
+    positions: Vec<Vector3<f64>>,
+    triangles: Vec<[usize; 3]>,
+    alive: Vec<bool>,
+    // Triangles around each vertex, dead ones are skipped
+    adjacent: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    removed: Vec<bool>,
+    stamps: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl Simplifier {
+    fn new(mesh: &MeshData) -> Self {
+        let positions: Vec<Vector3<f64>> = mesh
+            .vertex_data
+            .iter()
+            .map(|v| Vector3::from(v.position.map(f64::from)))
+            .collect();
+        let triangles: Vec<[usize; 3]> = mesh
+            .index_data
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]].map(|i| i as usize))
+            .collect();
+        let count = positions.len();
+        let mut simplifier = Self {
+            alive: vec![true; triangles.len()],
+            adjacent: vec![vec![]; count],
+            quadrics: vec![Quadric::default(); count],
+            removed: vec![false; count],
+            stamps: vec![0; count],
+            heap: BinaryHeap::new(),
+            positions,
+            triangles,
+        };
+        let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+        for (i, &triangle) in simplifier.triangles.iter().enumerate() {
+            let [a, b, c] = triangle;
+            for v in triangle {
+                simplifier.adjacent[v].push(i);
+            }
+            for (x, y) in [(a, b), (b, c), (c, a)] {
+                *edges.entry(edge_key(x, y)).or_insert(0) += 1;
+            }
+            let normal = simplifier.normal(triangle);
+            let area = normal.magnitude() / 2.;
+            if area > 0. {
+                let n = normal.normalize();
+                let p = simplifier.positions[a];
+                let quadric = Quadric::plane(n, -n.dot(p), area);
+                for v in triangle {
+                    simplifier.quadrics[v].add(&quadric);
+                }
+            }
+        }
+        for &[a, b, c] in &simplifier.triangles {
+            let normal = simplifier.normal([a, b, c]);
+            if normal.magnitude2() == 0. {
+                continue;
+            }
+            for (x, y) in [(a, b), (b, c), (c, a)] {
+                if edges[&edge_key(x, y)] != 1 {
+                    continue;
+                }
+                let edge = simplifier.positions[y] - simplifier.positions[x];
+                let side = edge.cross(normal);
+                if side.magnitude2() == 0. {
+                    continue;
+                }
+                let n = side.normalize();
+                let p = simplifier.positions[x];
+                let quadric = Quadric::plane(n, -n.dot(p), BORDER_WEIGHT * edge.magnitude2());
+                simplifier.quadrics[x].add(&quadric);
+                simplifier.quadrics[y].add(&quadric);
+            }
+        }
+        // Sorted so equal costs collapse in the same order every run
+        let mut keys: Vec<_> = edges.into_keys().collect();
+        keys.sort_unstable();
+        for (a, b) in keys {
+            simplifier.queue(a, b);
+        }
+        simplifier
+    }
+
+    fn normal(&self, [a, b, c]: [usize; 3]) -> Vector3<f64> {
+        let [a, b, c] = [a, b, c].map(|v| self.positions[v]);
+        (b - a).cross(c - a)
+    }
+
+    fn queue(&mut self, a: usize, b: usize) {
+        let mut quadric = self.quadrics[a];
+        quadric.add(&self.quadrics[b]);
+        let onto_b = quadric.error(self.positions[b]);
+        let onto_a = quadric.error(self.positions[a]);
+        let (from, to, cost) = if onto_b <= onto_a {
+            (a, b, onto_b)
+        } else {
+            (b, a, onto_a)
+        };
+        self.heap.push(Collapse {
+            cost,
+            from,
+            to,
+            stamps: [self.stamps[from], self.stamps[to]],
+        });
+    }
+
+    fn neighbours(&self, v: usize) -> HashSet<usize> {
+        self.adjacent[v]
+            .iter()
+            .filter(|&&t| self.alive[t])
+            .flat_map(|&t| self.triangles[t])
+            .filter(|&w| w != v)
+            .collect()
+    }
+
+    // Keeps the surface manifold and every remaining triangle facing the same way
+    fn can_collapse(&self, from: usize, to: usize) -> bool {
+        let shared = self.adjacent[from]
+            .iter()
+            .filter(|&&t| self.alive[t] && self.triangles[t].contains(&to))
+            .count();
+        let common = self
+            .neighbours(from)
+            .intersection(&self.neighbours(to))
+            .count();
+        if common != shared {
+            return false;
+        }
+        let kept = || {
+            self.adjacent[from]
+                .iter()
+                .filter(|&&t| self.alive[t] && !self.triangles[t].contains(&to))
+        };
+        let around = kept().fold(Vector3::new(0., 0., 0.), |sum, &t| {
+            sum + self.normal(self.triangles[t])
+        });
+        kept().all(|&t| {
+            let before = self.normal(self.triangles[t]);
+            let moved = self.triangles[t].map(|v| if v == from { to } else { v });
+            let moved = self.normal(moved);
+            // Degenerate triangles, like at the poles of a uv sphere, follow their surroundings
+            if before.magnitude2() == 0. {
+                moved.magnitude2() == 0. || moved.dot(around) > 0.
+            } else {
+                moved.dot(before) > 0.
+            }
+        })
+    }
+
+    fn collapse(&mut self, from: usize, to: usize) -> usize {
+        let mut removed_triangles = 0;
+        self.removed[from] = true;
+        let quadric = self.quadrics[from];
+        self.quadrics[to].add(&quadric);
+        for t in std::mem::take(&mut self.adjacent[from]) {
+            if !self.alive[t] {
+                continue;
+            }
+            if self.triangles[t].contains(&to) {
+                self.alive[t] = false;
+                removed_triangles += 1;
+                continue;
+            }
+            for v in &mut self.triangles[t] {
+                if *v == from {
+                    *v = to;
+                }
+            }
+            self.adjacent[to].push(t);
+        }
+        let alive = &self.alive;
+        self.adjacent[to].retain(|&t| alive[t]);
+        self.stamps[to] += 1;
+        let mut neighbours: Vec<_> = self.neighbours(to).into_iter().collect();
+        neighbours.sort_unstable();
+        for w in neighbours {
+            self.queue(to, w);
+        }
+        removed_triangles
+    }
+
+    fn run(&mut self, target_triangles: usize) {
+        let mut triangles = self.alive.iter().filter(|&&alive| alive).count();
+        while triangles > target_triangles {
+            let Collapse {
+                from, to, stamps, ..
+            } = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let stale = self.removed[from]
+                || self.removed[to]
+                || stamps != [self.stamps[from], self.stamps[to]];
+            if stale || !self.can_collapse(from, to) {
+                continue;
+            }
+            triangles -= self.collapse(from, to);
+        }
+    }
+}
+
+// Down to about `target_triangles`, fewer collapses happen when borders or flips block them.
+// Only vertices that are still referenced are kept.
+pub fn simplify(mesh: &MeshData, target_triangles: usize) -> MeshData {
+    let mut simplifier = Simplifier::new(mesh);
+    simplifier.run(target_triangles);
+    let mut remap = vec![None; mesh.vertex_data.len()];
+    let mut vertex_data = Vec::new();
+    let mut index_data = Vec::new();
+    let alive = simplifier.alive.iter();
+    for (triangle, _) in simplifier.triangles.iter().zip(alive).filter(|(_, a)| **a) {
+        for &v in triangle {
+            let index = *remap[v].get_or_insert_with(|| {
+                vertex_data.push(mesh.vertex_data[v]);
+                vertex_data.len() as u32 - 1
+            });
+            index_data.push(index);
+        }
+    }
+    MeshData::new(vertex_data, index_data)
+}
+
+// Up to `levels` meshes after `mesh`, each with about half the triangles of the one before.
+// Ends early once a level barely shrinks.
+pub fn lod_chain(mesh: &MeshData, levels: usize) -> Vec<MeshData> {
+    let mut chain: Vec<MeshData> = Vec::with_capacity(levels);
+    for _ in 0..levels {
+        let previous = chain.last().unwrap_or(mesh);
+        let triangles = previous.index_data.len() / 3;
+        let next = simplify(previous, triangles / 2);
+        if next.index_data.len() / 3 > triangles * 9 / 10 {
+            break;
+        }
+        chain.push(next);
+    }
+    chain
+}
@@ -0,0 +1,446 @@
+// Skeletal animation of model meshes, loaded from the skins of glTF files. The animation poses
+// the nodes of the file and the joint matrices of every skin, the global transform of a joint
+// times its inverse bind matrix, go to a storage buffer. deform of deform.wgsl blends them by
+// the joint indices and weights of each vertex in the vertex shaders, after the morph targets,
+// so every pass draws the skinned positions. Without storage buffers in vertex shaders
+// skin_vertices does the same on the CPU and the vertices are uploaded whenever the joints move.
+use crate::bounds::BoundingSphere;
+use crate::geo_gen::Vertex;
+use crate::morph::MeshMorph;
+use crate::stats;
+use cgmath::{InnerSpace, Matrix4, One, Quaternion, SquareMatrix, Vector3, VectorSpace, Zero};
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+use wgpu::{Buffer, Device, Queue};
+
+// Times the animation is sampled at for the bounds of a skinned mesh
+const BOUNDS_SAMPLES: usize = 8;
+
+// A node of the scene graph of the file, joints are nodes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkeletonNode {
+    pub parent: Option<usize>,
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for SkeletonNode {
+    fn default() -> Self {
+        Self {
+            parent: None,
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1., 1., 1.),
+        }
+    }
+}
+
+impl SkeletonNode {
+    pub fn local_transform(&self) -> Matrix4<f32> {
+        let scale = self.scale;
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
+    }
+}
+
+// The joint indices of the vertices index into `joints`, which are node indices
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Skin {
+    pub joints: Vec<usize>,
+    // From the bind pose of the mesh to the space of each joint, identity when missing
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelKeys {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+// Keys of one property of one node, one per time
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeChannel {
+    pub node: usize,
+    pub times: Vec<f32>,
+    pub keys: ChannelKeys,
+}
+
+impl NodeChannel {
+    // The keys around `time` and how far it is between them, held outside the keys
+    fn around(&self, time: f32) -> (usize, usize, f32) {
+        match self.times.iter().position(|&key| key > time) {
+            Some(0) => (0, 0, 0.),
+            Some(next) => {
+                let (start, end) = (self.times[next - 1], self.times[next]);
+                (next - 1, next, (time - start) / (end - start))
+            }
+            None => {
+                let last = self.times.len().saturating_sub(1);
+                (last, last, 0.)
+            }
+        }
+    }
+
+    // Sets the property of `node` to its value at `time`, linear between the keys and spherical
+    // for rotations
+    pub fn apply(&self, node: &mut SkeletonNode, time: f32) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, t) = self.around(time);
+        match &self.keys {
+            ChannelKeys::Translation(keys) => {
+                if let (Some(&a), Some(&b)) = (keys.get(a), keys.get(b)) {
+                    node.translation = a.lerp(b, t);
+                }
+            }
+            ChannelKeys::Rotation(keys) => {
+                if let (Some(&a), Some(&b)) = (keys.get(a), keys.get(b)) {
+                    node.rotation = a.slerp(b, t).normalize();
+                }
+            }
+            ChannelKeys::Scale(keys) => {
+                if let (Some(&a), Some(&b)) = (keys.get(a), keys.get(b)) {
+                    node.scale = a.lerp(b, t);
+                }
+            }
+        }
+    }
+}
+
+// Played in a loop, nodes without channels keep their rest pose
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SkeletonAnimation {
+    pub channels: Vec<NodeChannel>,
+}
+
+impl SkeletonAnimation {
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0., f32::max)
+    }
+
+    pub fn pose(&self, nodes: &[SkeletonNode], time: f32) -> Vec<SkeletonNode> {
+        let duration = self.duration();
+        let time = if duration > 0. {
+            time.rem_euclid(duration)
+        } else {
+            0.
+        };
+        let mut nodes = nodes.to_vec();
+        for channel in &self.channels {
+            if let Some(node) = nodes.get_mut(channel.node) {
+                channel.apply(node, time);
+            }
+        }
+        nodes
+    }
+}
+
+// The nodes of a file in their rest pose with its skins, shared by the meshes of a model
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Skeleton {
+    pub nodes: Vec<SkeletonNode>,
+    pub skins: Vec<Skin>,
+    pub animation: Option<SkeletonAnimation>,
+}
+
+impl Skeleton {
+    // For vertices multiplied by `scale`: the translations are scaled, which is the same as
+    // scaling the skinned positions
+    pub fn scaled(mut self, scale: f32) -> Self {
+        for node in &mut self.nodes {
+            node.translation *= scale;
+        }
+        for skin in &mut self.skins {
+            for matrix in &mut skin.inverse_bind_matrices {
+                matrix.w.x *= scale;
+                matrix.w.y *= scale;
+                matrix.w.z *= scale;
+            }
+        }
+        let channels = self.animation.iter_mut().flat_map(|a| &mut a.channels);
+        for channel in channels {
+            if let ChannelKeys::Translation(keys) = &mut channel.keys {
+                for key in keys {
+                    *key *= scale;
+                }
+            }
+        }
+        self
+    }
+
+    // The joint matrices of every skin with the nodes posed at `time`
+    pub fn joint_matrices(&self, time: f32) -> Vec<Vec<Matrix4<f32>>> {
+        let nodes = match &self.animation {
+            Some(animation) => animation.pose(&self.nodes, time),
+            None => self.nodes.clone(),
+        };
+        let globals = global_transforms(&nodes);
+        self.skins
+            .iter()
+            .map(|skin| {
+                skin.joints
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &joint)| {
+                        let global = globals.get(joint).copied();
+                        let inverse_bind = skin.inverse_bind_matrices.get(i).copied();
+                        global.unwrap_or_else(Matrix4::identity)
+                            * inverse_bind.unwrap_or_else(Matrix4::identity)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Around the mesh at evenly spaced times of the animation, as culling can't follow the joints
+    pub fn skinned_bounds(&self, binding: &SkinBinding, rest: &[Vertex]) -> BoundingSphere {
+        let (samples, duration) = match &self.animation {
+            Some(animation) => (BOUNDS_SAMPLES, animation.duration()),
+            None => (1, 0.),
+        };
+        (0..samples)
+            .map(|i| {
+                let matrices = self.joint_matrices(duration * i as f32 / samples as f32);
+                let matrices = matrices.get(binding.skin).map_or(&[][..], Vec::as_slice);
+                let skinned = skin_vertices(rest, &binding.influences, matrices);
+                BoundingSphere::from_points(skinned.iter().map(|v| v.position))
+            })
+            .reduce(|acc, next| acc.union(&next))
+            .unwrap_or_else(|| BoundingSphere::from_points(rest.iter().map(|v| v.position)))
+    }
+}
+
+// Model space transform of every node, parents may come after their children
+pub fn global_transforms(nodes: &[SkeletonNode]) -> Vec<Matrix4<f32>> {
+    let mut globals: Vec<Option<Matrix4<f32>>> = vec![None; nodes.len()];
+    for start in 0..nodes.len() {
+        // Up to the first ancestor already done, a cycle ends at the root
+        let mut chain = vec![];
+        let mut node = Some(start);
+        while let Some(i) = node {
+            if globals[i].is_some() || chain.len() > nodes.len() {
+                break;
+            }
+            chain.push(i);
+            node = nodes[i].parent.filter(|&parent| parent < nodes.len());
+        }
+        let mut global = node
+            .and_then(|i| globals[i])
+            .unwrap_or_else(Matrix4::identity);
+        for &i in chain.iter().rev() {
+            global = global * nodes[i].local_transform();
+            globals[i] = Some(global);
+        }
+    }
+    globals
+        .into_iter()
+        .map(|global| global.unwrap_or_else(Matrix4::identity))
+        .collect()
+}
+
+// Up to 4 joints of the skin moving a vertex, with their weights
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Influence {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl Influence {
+    // The third vertex buffer of the meshes deformed in the vertex shaders
+    pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        static ATTRIBUTES: &[wgpu::VertexAttribute; 2] =
+            &wgpu::vertex_attr_array![14 => Uint32x4, 15 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Influence>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: ATTRIBUTES,
+        }
+    }
+}
+
+// The skin of a mesh with the influence of every vertex
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SkinBinding {
+    pub skin: usize,
+    pub influences: Vec<Influence>,
+}
+
+// Every vertex moved by the weighted sum of its joint matrices, normals renormalized. Mirrors
+// deform in deform.wgsl, vertices without weight, or whose joints are past `matrices`, stay
+// where they are.
+pub fn skin_vertices(
+    source: &[Vertex],
+    influences: &[Influence],
+    matrices: &[Matrix4<f32>],
+) -> Vec<Vertex> {
+    let mut vertices = source.to_vec();
+    for (vertex, influence) in vertices.iter_mut().zip(influences) {
+        let mut skin = Matrix4::zero();
+        let mut total = 0.;
+        for (&joint, &weight) in influence.joints.iter().zip(&influence.weights) {
+            if let Some(matrix) = matrices.get(joint as usize).filter(|_| weight != 0.) {
+                skin += matrix * weight;
+                total += weight;
+            }
+        }
+        if total == 0. {
+            continue;
+        }
+        let position = skin * Vector3::from(vertex.position).extend(1.);
+        vertex.position = position.truncate().into();
+        let normal = (skin * Vector3::from(vertex.normal).extend(0.)).truncate();
+        if normal.magnitude2() > 0. {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+    vertices
+}
+
+// Column major, a storage buffer can't be empty so a skin without joints gets the identity
+pub(crate) fn matrix_data(matrices: &[Matrix4<f32>]) -> Vec<[[f32; 4]; 4]> {
+    if matrices.is_empty() {
+        return vec![Matrix4::identity().into()];
+    }
+    matrices.iter().map(|&matrix| matrix.into()).collect()
+}
+
+// The skeleton of a model in its current pose
+pub(crate) struct ModelSkeleton {
+    skeleton: Skeleton,
+    time: f32,
+    // Of every skin
+    matrices: Vec<Vec<Matrix4<f32>>>,
+    // The joints moved since the skinned meshes were last updated
+    moved: bool,
+    // The joint matrices of every skin on the GPU, None where the vertices are skinned on the
+    // CPU
+    joint_buffers: Option<Vec<Buffer>>,
+}
+
+impl ModelSkeleton {
+    pub fn new(device: &Device, skeleton: Skeleton, vertex_deform: bool) -> Self {
+        let matrices = skeleton.joint_matrices(0.);
+        let joint_buffers = vertex_deform.then(|| {
+            matrices
+                .iter()
+                .enumerate()
+                .map(|(i, matrices)| {
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("Skin {} Joint Matrix Buffer", i)),
+                        contents: bytemuck::cast_slice(&matrix_data(matrices)),
+                        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    })
+                })
+                .collect()
+        });
+        Self {
+            skeleton,
+            time: 0.,
+            matrices,
+            moved: true,
+            joint_buffers,
+        }
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.skeleton.animation.is_some()
+    }
+
+    // Of skin `skin` in the current pose
+    fn matrices(&self, skin: usize) -> &[Matrix4<f32>] {
+        self.matrices.get(skin).map_or(&[], Vec::as_slice)
+    }
+
+    // The joint matrices of skin `skin` with how many there are, None on the CPU path
+    pub fn joint_buffer(&self, skin: usize) -> Option<(&Buffer, u32)> {
+        let buffer = self.joint_buffers.as_ref()?.get(skin)?;
+        Some((buffer, self.matrices(skin).len() as u32))
+    }
+
+    // Advances the animation and uploads the joint matrices on the GPU path. True when the
+    // joints moved, or on the first call, for the meshes skinned on the CPU.
+    pub fn update(&mut self, dt: Duration, queue: &Queue) -> bool {
+        if self.skeleton.animation.is_some() {
+            self.time += dt.as_secs_f32();
+            let matrices = self.skeleton.joint_matrices(self.time);
+            if matrices != self.matrices {
+                self.matrices = matrices;
+                self.moved = true;
+                for (buffer, matrices) in self.joint_buffers.iter().flatten().zip(&self.matrices) {
+                    let data = matrix_data(matrices);
+                    stats::write_buffer(queue, buffer, 0, bytemuck::cast_slice(&data));
+                }
+            }
+        }
+        std::mem::take(&mut self.moved)
+    }
+}
+
+// The skin of one mesh of a model. Drawn in the bind pose until upload, which
+// ModelRenderGroup::new calls on the CPU path. The GPU path hands the influences to MeshDeform.
+pub(crate) struct MeshSkin {
+    skin: usize,
+    // Both only kept on the CPU path
+    rest: Vec<Vertex>,
+    influences: Vec<Influence>,
+    // The skinned vertices, drawn instead of the vertex buffer of the mesh
+    pub vertex_buffer: Option<Buffer>,
+}
+
+impl MeshSkin {
+    pub fn new(rest: Vec<Vertex>, binding: SkinBinding) -> Self {
+        Self {
+            skin: binding.skin,
+            rest,
+            influences: binding.influences,
+            vertex_buffer: None,
+        }
+    }
+
+    pub fn skin(&self) -> usize {
+        self.skin
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.rest.len()
+    }
+
+    // For the vertex shaders, which skin the vertex buffer of the mesh
+    pub fn take_influences(&mut self) -> Vec<Influence> {
+        self.rest = vec![];
+        std::mem::take(&mut self.influences)
+    }
+
+    pub fn upload(&mut self, device: &Device, label: &str, skeleton: &ModelSkeleton) {
+        let matrices = skeleton.matrices(self.skin);
+        let skinned = skin_vertices(&self.rest, &self.influences, matrices);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Skinned Vertex Buffer", label)),
+            contents: bytemuck::cast_slice(&skinned),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        self.vertex_buffer = Some(vertex_buffer);
+    }
+
+    // Skins and uploads the vertices on the CPU path, the blended morph targets when the mesh
+    // has them
+    pub fn update(&self, queue: &Queue, skeleton: &ModelSkeleton, morph: Option<&MeshMorph>) {
+        let vertex_buffer = match &self.vertex_buffer {
+            Some(vertex_buffer) => vertex_buffer,
+            None => return,
+        };
+        let matrices = skeleton.matrices(self.skin);
+        let morphed = morph.map(MeshMorph::blended);
+        let source = morphed.as_deref().unwrap_or(&self.rest);
+        let skinned = skin_vertices(source, &self.influences, matrices);
+        stats::write_buffer(queue, vertex_buffer, 0, bytemuck::cast_slice(&skinned));
+    }
+}
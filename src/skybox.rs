@@ -1,8 +1,10 @@
-use crate::{resources, texture, Camera, RenderGroup, MULTI_SAMPLE};
-use image::{DynamicImage, GenericImageView};
-use std::cell::RefCell;
+use crate::profiling::profile_scope;
+use crate::resources::Assets;
+use crate::stats::DrawStats;
+use crate::{mipmap, multi_sample, probes, stats, texture, texture_decode, Camera, RenderGroup};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, RgbaImage};
 use std::num::NonZeroU32;
-use std::rc::Rc;
 use wgpu::{
     BindGroup, Device, Queue, RenderPass, RenderPipeline, SurfaceConfiguration, Texture,
     TextureDimension,
@@ -11,6 +13,10 @@ use wgpu::{
 pub struct SkyboxRenderGroup {
     sky_pipeline: RenderPipeline,
     bind_group: BindGroup,
+    // Diffuse irradiance of the sky as L2 spherical harmonics, see Scene::set_ambient_sh
+    pub(crate) sh: [[f32; 4]; 9],
+    // The sky at probe resolution, fills reflection probes until the scene itself is captured
+    pub(crate) probe_faces: Vec<RgbaImage>,
 }
 
 impl RenderGroup for SkyboxRenderGroup {
@@ -22,6 +28,13 @@ impl RenderGroup for SkyboxRenderGroup {
         render_pass.set_pipeline(&self.sky_pipeline);
         render_pass.draw(0..3, 0..1);
     }
+
+    fn draw_stats(&self, shadow_pass: bool) -> DrawStats {
+        if shadow_pass {
+            return DrawStats::default();
+        }
+        DrawStats::new(1, 1)
+    }
 }
 
 pub async fn create(
@@ -29,7 +42,8 @@ pub async fn create(
     config: &SurfaceConfiguration,
     queue: &Queue,
     camera: &Camera,
-) -> Rc<RefCell<SkyboxRenderGroup>> {
+    assets: &Assets,
+) -> anyhow::Result<SkyboxRenderGroup> {
     let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
         label: None,
         source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
@@ -55,7 +69,7 @@ pub async fn create(
             },
         ],
     });
-    let tex = create_cubemap(device, queue).await;
+    let (tex, sh, probe_faces) = create_cubemap(device, queue, assets).await?;
     let texture_view = tex.create_view(&wgpu::TextureViewDescriptor {
         label: Some("cubemap view"),
         dimension: Some(wgpu::TextureViewDimension::Cube),
@@ -108,32 +122,115 @@ pub async fn create(
             ..Default::default()
         },
         depth_stencil: texture::Texture::create_depth_state(),
-        multisample: MULTI_SAMPLE,
+        multisample: multi_sample(),
         multiview: None,
     });
-    Rc::new(RefCell::new(SkyboxRenderGroup {
+    Ok(SkyboxRenderGroup {
         sky_pipeline,
         bind_group,
-    }))
+        sh,
+        probe_faces,
+    })
 }
 
-async fn load_cubemap(dir: &str, ext: &str) -> Vec<DynamicImage> {
+async fn load_cubemap(assets: &Assets, dir: &str, ext: &str) -> anyhow::Result<Vec<DynamicImage>> {
+    profile_scope!("load_cubemap");
     let faces = ["posx", "negx", "posy", "negy", "posz", "negz"];
-    let mut vec = vec![];
+    let mut files = vec![];
     for face in faces {
         let filename = face.to_owned() + ext;
-        let path = std::path::Path::new(dir).join(filename);
-        let bytes = resources::load_binary(path.to_str().unwrap())
-            .await
-            .unwrap();
-        vec.push(image::load_from_memory(&bytes).unwrap());
+        let bytes = assets.load_binary(&format!("{}/{}", dir, filename)).await?;
+        files.push((face.to_owned(), bytes));
     }
-    vec
+    texture_decode::decode_all(files)
+        .into_iter()
+        .map(|decoded| Ok(DynamicImage::ImageRgba8(decoded?.image)))
+        .collect()
 }
 
-async fn create_cubemap(device: &Device, queue: &Queue) -> Texture {
-    // let images = load_cubemap("Yokohama", ".jpg").await;
-    let images = load_cubemap("skype", ".png").await;
+// Cube face directions for texel coordinates in [-1, 1], in posx, negx, posy, negy, posz, negz order
+fn cube_dir(face: usize, u: f32, v: f32) -> [f32; 3] {
+    match face {
+        0 => [1., -v, -u],
+        1 => [-1., -v, u],
+        2 => [u, 1., v],
+        3 => [u, -1., -v],
+        4 => [u, -v, 1.],
+        _ => [-u, -v, -1.],
+    }
+}
+
+// Project the cubemap onto the first 9 SH basis functions and convolve with the cosine lobe,
+// so the shader only has to evaluate the basis at the surface normal.
+fn compute_sh(images: &[DynamicImage]) -> [[f32; 4]; 9] {
+    // The sky is sampled sparsely, irradiance is low frequency anyway
+    const SAMPLES: u32 = 32;
+    let mut sh = [[0f32; 4]; 9];
+    let mut total_weight = 0.;
+    for (face, image) in images.iter().enumerate() {
+        let (width, height) = image.dimensions();
+        for y in 0..SAMPLES {
+            for x in 0..SAMPLES {
+                let u = 2. * (x as f32 + 0.5) / SAMPLES as f32 - 1.;
+                let v = 2. * (y as f32 + 0.5) / SAMPLES as f32 - 1.;
+                let [dx, dy, dz] = cube_dir(face, u, v);
+                let len_sq = dx * dx + dy * dy + dz * dz;
+                let len = len_sq.sqrt();
+                let (dx, dy, dz) = (dx / len, dy / len, dz / len);
+                // Solid angle of the texel relative to the face center
+                let weight = 1. / (len_sq * len);
+                total_weight += weight;
+
+                let pixel = image.get_pixel(x * width / SAMPLES, y * height / SAMPLES);
+                let color = [pixel[0], pixel[1], pixel[2]].map(|c| (c as f32 / 255.).powf(2.2));
+                let basis = [
+                    0.282095,
+                    0.488603 * dy,
+                    0.488603 * dz,
+                    0.488603 * dx,
+                    1.092548 * dx * dy,
+                    1.092548 * dy * dz,
+                    0.315392 * (3. * dz * dz - 1.),
+                    1.092548 * dx * dz,
+                    0.546274 * (dx * dx - dy * dy),
+                ];
+                for (coefficient, y_lm) in sh.iter_mut().zip(basis) {
+                    coefficient
+                        .iter_mut()
+                        .zip(color)
+                        .for_each(|(c, color)| *c += color * y_lm * weight);
+                }
+            }
+        }
+    }
+    // Monte Carlo normalization over the sphere and the cosine lobe convolution divided by PI
+    let band_factor = [1., 2. / 3., 2. / 3., 2. / 3., 0.25, 0.25, 0.25, 0.25, 0.25];
+    let norm = 4. * std::f32::consts::PI / total_weight;
+    for (coefficient, factor) in sh.iter_mut().zip(band_factor) {
+        coefficient.iter_mut().for_each(|c| *c *= norm * factor);
+    }
+    sh
+}
+
+async fn create_cubemap(
+    device: &Device,
+    queue: &Queue,
+    assets: &Assets,
+) -> anyhow::Result<(Texture, [[f32; 4]; 9], Vec<RgbaImage>)> {
+    // let images = load_cubemap(assets, "Yokohama", ".jpg").await?;
+    let images = load_cubemap(assets, "skype", ".png").await?;
+    let sh = compute_sh(&images);
+    let probe_faces = images
+        .iter()
+        .map(|image| {
+            image::imageops::resize(
+                image,
+                probes::PROBE_SIZE,
+                probes::PROBE_SIZE,
+                FilterType::Triangle,
+            )
+        })
+        .collect();
     let (width, height) = images[0].dimensions();
     let total =
         images
@@ -160,8 +257,11 @@ async fn create_cubemap(device: &Device, queue: &Queue) -> Texture {
         sample_count: 1,
         dimension: TextureDimension::D2,
         format: texture::TEXTURE_FORMAT,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::COPY_DST,
     });
+    stats::count_upload(total.len());
     queue.write_texture(
         wgpu::ImageCopyTexture {
             aspect: wgpu::TextureAspect::All,
@@ -177,5 +277,6 @@ async fn create_cubemap(device: &Device, queue: &Queue) -> Texture {
         },
         size,
     );
-    tex
+    mipmap::generate_mipmaps(device, queue, &tex, texture::TEXTURE_FORMAT, max_mips, 6);
+    Ok((tex, sh, probe_faces))
 }
@@ -0,0 +1,386 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::camera::CameraMode;
+use crate::geo_gen::Vertex;
+use crate::raycast::{closer, raycast_triangle, Ray, RayHit};
+use crate::world_space::InstanceTransform;
+use crate::{GroupId, State};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
+use std::cmp::Ordering;
+
+// Triangles per leaf
+const LEAF_SIZE: usize = 4;
+// Distance the camera keeps from surfaces
+const CAMERA_RADIUS: f32 = 0.5;
+// Push outs per frame, enough to settle in a corner between a few surfaces
+const CAMERA_PUSHES: usize = 4;
+// Walk mode capsule, from the feet to the eye. Ledges up to the step height are climbed onto,
+// the capsule's spheres start above them.
+const EYE_HEIGHT: f32 = 8.0;
+const WALK_RADIUS: f32 = 1.5;
+const STEP_HEIGHT: f32 = 2.0;
+
+struct Node {
+    bounds: Aabb,
+    // A leaf holds `count` triangles from `first` in the triangle order. An inner node has a
+    // count of 0, its first child right after it and the second at `first`.
+    first: u32,
+    count: u32,
+}
+
+// Bounding volume hierarchy over the triangles of one mesh, in the mesh's local space.
+// Queries borrow the vertices and indices it was built from instead of keeping a copy.
+#[derive(Default)]
+pub struct MeshBvh {
+    nodes: Vec<Node>,
+    triangles: Vec<u32>,
+}
+
+impl MeshBvh {
+    // Triangles with an index past the vertices are left out
+    pub fn new(vertices: &[Vertex], indices: &[u32]) -> Self {
+        let valid = |i: u32| (i as usize) < vertices.len();
+        let bounds: Vec<Aabb> = indices
+            .chunks_exact(3)
+            .map(|t| {
+                let corners = t.iter().filter(|&&i| valid(i));
+                Aabb::from_points(corners.map(|&i| Point3::from(vertices[i as usize].position)))
+                    .unwrap_or_else(|| Aabb::around(Point3::origin(), Vector3::zero()))
+            })
+            .collect();
+        let mut bvh = Self {
+            nodes: vec![],
+            triangles: (0..bounds.len() as u32)
+                .zip(indices.chunks_exact(3))
+                .filter(|(_, t)| t.iter().all(|&i| valid(i)))
+                .map(|(triangle, _)| triangle)
+                .collect(),
+        };
+        if !bvh.triangles.is_empty() {
+            bvh.build(&bounds, 0, bvh.triangles.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize) {
+        let triangles = &mut self.triangles[start..end];
+        let node_bounds = triangles
+            .iter()
+            .map(|&t| bounds[t as usize])
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            first: start as u32,
+            count: (end - start) as u32,
+        });
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+        // Median split along the axis the triangle centers spread the most
+        let centers = triangles.iter().map(|&t| bounds[t as usize].center());
+        let spread = Aabb::from_points(centers).unwrap();
+        let size = spread.max - spread.min;
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        let middle = (start + end) / 2;
+        triangles.select_nth_unstable_by(middle - start, |&a, &b| {
+            let a = bounds[a as usize].center()[axis];
+            let b = bounds[b as usize].center()[axis];
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        });
+        self.build(bounds, start, middle);
+        let second = self.nodes.len() as u32;
+        self.build(bounds, middle, end);
+        self.nodes[index].first = second;
+        self.nodes[index].count = 0;
+    }
+
+    // The closest hit like raycast_mesh, skipping the boxes the ray misses or enters too late
+    pub fn raycast(&self, ray: &Ray, vertices: &[Vertex], indices: &[u32]) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = match self.nodes.get(index) {
+                Some(node) => node,
+                None => break,
+            };
+            let entry = match ray.intersect_aabb(&node.bounds) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if matches!(&closest, Some(hit) if hit.distance < entry) {
+                continue;
+            }
+            match self.leaf(node) {
+                Some(triangles) => {
+                    for &triangle in triangles {
+                        let hit = raycast_triangle(ray, vertices, indices, triangle as usize);
+                        closest = closer(closest, hit);
+                    }
+                }
+                None => stack.extend([node.first as usize, index + 1]),
+            }
+        }
+        closest
+    }
+
+    // Triangles of every leaf whose box overlaps `aabb`, candidates for an exact test
+    pub fn query(&self, aabb: &Aabb, mut f: impl FnMut(usize)) {
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = match self.nodes.get(index) {
+                Some(node) => node,
+                None => break,
+            };
+            if !node.bounds.overlaps(aabb) {
+                continue;
+            }
+            match self.leaf(node) {
+                Some(triangles) => triangles.iter().for_each(|&t| f(t as usize)),
+                None => stack.extend([node.first as usize, index + 1]),
+            }
+        }
+    }
+
+    fn leaf(&self, node: &Node) -> Option<&[u32]> {
+        let first = node.first as usize;
+        (node.count > 0).then(|| &self.triangles[first..first + node.count as usize])
+    }
+}
+
+// Where a sphere touches an instance of a render group
+#[derive(Debug, Copy, Clone)]
+pub struct Contact {
+    pub instance: usize,
+    // Closest point of the surface, in world space
+    pub point: Point3<f32>,
+    pub distance: f32,
+}
+
+// Local space box around a world space box seen from an instance
+pub(crate) fn local_bounds(aabb: &Aabb, transform: &InstanceTransform) -> Aabb {
+    let corners = aabb
+        .corners()
+        .map(|corner| Point3::from_vec(transform.inverse_transform_point(corner.to_vec())));
+    Aabb::from_points(corners.into_iter()).unwrap()
+}
+
+// Ericson, Real-Time Collision Detection 5.1.5
+pub fn closest_point_on_triangle(p: Point3<f32>, [a, b, c]: [Point3<f32>; 3]) -> Point3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0. && d2 <= 0. {
+        return a;
+    }
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0. && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0. && d1 >= 0. && d3 <= 0. {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0. && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0. && d2 >= 0. && d6 <= 0. {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0. && d4 - d3 >= 0. && d5 - d6 >= 0. {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let sum = va + vb + vc;
+    if sum <= 0. {
+        // Degenerate, the triangle is a segment
+        return [(a, b), (b, c), (c, a)]
+            .map(|(from, to)| closest_point_on_segment(p, from, to))
+            .into_iter()
+            .min_by(|x, y| {
+                let (x, y) = ((x - p).magnitude2(), (y - p).magnitude2());
+                x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+            })
+            .unwrap();
+    }
+    a + ab * (vb / sum) + ac * (vc / sum)
+}
+
+fn closest_point_on_segment(p: Point3<f32>, a: Point3<f32>, b: Point3<f32>) -> Point3<f32> {
+    let ab = b - a;
+    let length2 = ab.magnitude2();
+    if length2 == 0. {
+        return a;
+    }
+    a + ab * ((p - a).dot(ab) / length2).clamp(0., 1.)
+}
+
+// Separating axis test (Akenine-Möller) on the box faces, the triangle plane and the nine
+// edge cross products
+pub fn triangle_overlaps_aabb(triangle: [Point3<f32>; 3], aabb: &Aabb) -> bool {
+    let center = aabb.center();
+    let half = (aabb.max - aabb.min) / 2.;
+    let [a, b, c] = triangle.map(|p| p - center);
+    let edges = [b - a, c - b, a - c];
+    let box_axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+    let separates = |axis: Vector3<f32>| {
+        let [pa, pb, pc] = [a.dot(axis), b.dot(axis), c.dot(axis)];
+        let radius = half.x * axis.x.abs() + half.y * axis.y.abs() + half.z * axis.z.abs();
+        pa.min(pb).min(pc) > radius || pa.max(pb).max(pc) < -radius
+    };
+    let edge_axes = edges
+        .iter()
+        .flat_map(|&edge| box_axes.map(|axis| axis.cross(edge)));
+    !box_axes
+        .into_iter()
+        .chain([edges[0].cross(edges[1])])
+        .chain(edge_axes)
+        .any(separates)
+}
+
+impl State {
+    // Closest hit over every render group that keeps its meshes on the CPU
+    pub fn raycast(&self, ray: &Ray) -> Option<(GroupId, RayHit)> {
+        let (index, hit) = self.pick(ray)?;
+        Some((self.render_groups.id(index)?, hit))
+    }
+
+    pub fn sphere_contacts(&self, sphere: &BoundingSphere) -> Vec<(GroupId, Contact)> {
+        self.render_groups
+            .iter()
+            .flat_map(|(id, group)| {
+                let contacts = group.contacts(sphere).into_iter();
+                contacts.map(move |contact| (id, contact))
+            })
+            .collect()
+    }
+
+    // Group and instance of everything with a triangle inside the box
+    pub fn aabb_overlaps(&self, aabb: &Aabb) -> Vec<(GroupId, usize)> {
+        self.render_groups
+            .iter()
+            .flat_map(|(id, group)| {
+                let instances = group.overlapping(aabb).into_iter();
+                instances.map(move |instance| (id, instance))
+            })
+            .collect()
+    }
+
+    pub fn set_camera_collision(&mut self, enabled: bool) {
+        self.camera_collision = enabled;
+    }
+
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        self.camera_controller.set_mode(mode, &mut self.camera.view);
+    }
+
+    // Stops the camera where its path from `from` went through a surface, then pushes it out
+    // of the surfaces closer than its radius
+    pub(crate) fn collide_camera(&mut self, from: Point3<f32>) {
+        let mut position = self.camera.view.position;
+        let travel = position - from;
+        let distance = travel.magnitude();
+        if distance > 0. {
+            let ray = Ray::new(from, travel);
+            if let Some((_, hit)) = self.pick(&ray) {
+                if hit.distance < distance + CAMERA_RADIUS {
+                    position = ray.at((hit.distance - CAMERA_RADIUS).max(0.));
+                }
+            }
+        }
+        self.camera.view.position = self.push_out(position, CAMERA_RADIUS);
+    }
+
+    // Walk mode: walls stop the capsule, ceilings end a jump and the camera lands on whatever
+    // is below the feet. Without anything below it keeps falling.
+    pub(crate) fn walk_camera(&mut self, from: Point3<f32>) {
+        let mut eye = self.camera.view.position;
+        let travel = Vector3::new(eye.x - from.x, 0., eye.z - from.z);
+        let distance = travel.magnitude();
+        if distance > 0. {
+            let knee = Point3::new(from.x, eye.y - EYE_HEIGHT + STEP_HEIGHT, from.z);
+            let ray = Ray::new(knee, travel);
+            if let Some((_, hit)) = self.pick(&ray) {
+                if hit.distance < distance + WALK_RADIUS {
+                    let stop = ray.at((hit.distance - WALK_RADIUS).max(0.));
+                    eye = Point3::new(stop.x, eye.y, stop.z);
+                }
+            }
+        }
+        let lowest = EYE_HEIGHT - STEP_HEIGHT - WALK_RADIUS;
+        for drop in [0., lowest / 2., lowest] {
+            let center = eye - Vector3::unit_y() * drop;
+            let push = self.push_out(center, WALK_RADIUS) - center;
+            if push.y < 0. {
+                self.camera.view.velocity.y = self.camera.view.velocity.y.min(0.);
+            }
+            eye += push;
+        }
+
+        // From the higher of the two eye positions so a fast fall can't pass through the ground
+        let top = eye.y.max(from.y);
+        let ray = Ray::new(Point3::new(eye.x, top, eye.z), -Vector3::unit_y());
+        // Sticks to the ground when walking down slopes and steps
+        let snap = if self.camera_controller.grounded {
+            STEP_HEIGHT
+        } else {
+            0.
+        };
+        let ground = self
+            .pick(&ray)
+            .map(|(_, hit)| hit.position.y)
+            .filter(|&y| y >= eye.y - EYE_HEIGHT - snap);
+        let falling = self.camera.view.velocity.y <= 0.;
+        self.camera_controller.grounded = match ground {
+            Some(y) if falling => {
+                eye.y = y + EYE_HEIGHT;
+                self.camera.view.velocity.y = 0.;
+                true
+            }
+            _ => false,
+        };
+        self.camera.view.position = eye;
+    }
+
+    // A sphere moved out of the surfaces it overlaps, deepest first
+    fn push_out(&self, mut position: Point3<f32>, radius: f32) -> Point3<f32> {
+        for _ in 0..CAMERA_PUSHES {
+            let sphere = BoundingSphere {
+                center: position,
+                radius,
+            };
+            let deepest = self
+                .sphere_contacts(&sphere)
+                .into_iter()
+                .map(|(_, contact)| contact)
+                .filter(|contact| contact.distance > 0.)
+                .min_by(|a, b| {
+                    a.distance
+                        .partial_cmp(&b.distance)
+                        .unwrap_or(Ordering::Equal)
+                });
+            match deepest {
+                Some(contact) => {
+                    let away = (position - contact.point) / contact.distance;
+                    position = contact.point + away * radius;
+                }
+                None => break,
+            }
+        }
+        position
+    }
+}
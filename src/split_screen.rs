@@ -0,0 +1,115 @@
+// Extra cameras sharing the window with the main one, e.g. for a second player. Every camera
+// draws the scene into its own rectangle of the main pass, the shadow maps and the rest of the
+// frame are shared.
+use crate::camera::{Camera, CameraController, CameraView};
+use crate::depth_prepass::DepthPrePass;
+use crate::{State, DEPTH_PRE_PASS};
+
+pub struct SplitView {
+    pub(crate) camera: Camera,
+    // Not fed by the window events, except for the arrow keys driving the first split view
+    pub controller: CameraController,
+    pub(crate) depth_pre_pass: Option<DepthPrePass>,
+}
+
+impl SplitView {
+    pub fn camera_view(&self) -> &CameraView {
+        &self.camera.view
+    }
+}
+
+// Pixel rectangles (x, y, width, height) of `count` views side by side, in rows once there are
+// more than two. The last row is left short when the views don't fill it.
+pub fn split_rects(count: usize, width: u32, height: u32) -> Vec<[f32; 4]> {
+    let columns = if count <= 2 {
+        count.max(1)
+    } else {
+        (1..count)
+            .find(|columns| columns * columns >= count)
+            .unwrap()
+    };
+    let rows = (count.max(1) - 1) / columns + 1;
+    let cell = [
+        (width / columns as u32) as f32,
+        (height / rows as u32) as f32,
+    ];
+    (0..count)
+        .map(|i| {
+            let (column, row) = ((i % columns) as f32, (i / columns) as f32);
+            [column * cell[0], row * cell[1], cell[0], cell[1]]
+        })
+        .collect()
+}
+
+impl State {
+    // Index of the new view, starting at 0. The main camera keeps the first rectangle.
+    pub fn add_split_view(&mut self, view: CameraView) -> usize {
+        let mut camera = Camera::new(view, self.camera.projection.clone(), &self.device);
+        camera.set_relative(self.camera.is_relative());
+        let depth_pre_pass = if DEPTH_PRE_PASS {
+            Some(DepthPrePass::new(
+                &self.device,
+                &self.light_render_group,
+                &self.scene,
+            ))
+        } else {
+            None
+        };
+        self.split_views.push(SplitView {
+            camera,
+            controller: CameraController::new(
+                self.camera_controller.speed(),
+                self.camera_controller.sensitivity(),
+            ),
+            depth_pre_pass,
+        });
+        self.layout_views();
+        self.split_views.len() - 1
+    }
+
+    // Back to the main camera alone
+    pub fn clear_split_views(&mut self) {
+        self.split_views.clear();
+        self.layout_views();
+    }
+
+    // For driving a split view from a gamepad or the network
+    pub fn split_view_mut(&mut self, index: usize) -> Option<&mut SplitView> {
+        self.split_views.get_mut(index)
+    }
+
+    // Only with split views, a single camera draws into the whole attachment
+    pub(crate) fn view_rects(&self) -> Option<Vec<[f32; 4]>> {
+        if self.split_views.is_empty() {
+            return None;
+        }
+        let count = self.split_views.len() + 1;
+        Some(split_rects(count, self.config.width, self.config.height))
+    }
+
+    // Every projection matches the aspect of its rectangle
+    pub(crate) fn layout_views(&mut self) {
+        let (width, height) = (self.config.width, self.config.height);
+        let rects = self
+            .view_rects()
+            .unwrap_or_else(|| vec![[0., 0., width as f32, height as f32]]);
+        let cameras = std::iter::once(&mut self.camera)
+            .chain(self.split_views.iter_mut().map(|view| &mut view.camera));
+        for (camera, [_, _, width, height]) in cameras.zip(rects) {
+            camera
+                .projection
+                .resize((width as u32).max(1), (height as u32).max(1));
+        }
+        self.request_redraw();
+    }
+
+    pub(crate) fn update_split_views(&mut self, dt: std::time::Duration) {
+        for view in &mut self.split_views {
+            view.controller.update_camera(&mut view.camera.view, dt);
+            view.camera.update_camera(&self.queue);
+            if let Some(depth_pre_pass) = &view.depth_pre_pass {
+                depth_pre_pass.update(&self.queue, &view.camera);
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{InitError, RendererConfig, State};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use winit::window::Window;
+
+impl State {
+    // For hosts without an async runtime, blocks until the adapter, device and assets are ready
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new_blocking(
+        window: &Window,
+        renderer_config: &RendererConfig,
+    ) -> Result<Self, InitError> {
+        pollster::block_on(Self::new(window, renderer_config))
+    }
+}
+
+// Renderer construction driven by the host's event loop instead of an executor, the only
+// way to wait on the browser without blocking it. Poll once per loop iteration.
+pub enum Startup {
+    // Waiting on the adapter, the device or the assets
+    Loading(Pin<Box<dyn Future<Output = Result<State, InitError>>>>),
+    Ready(Box<State>),
+    // Polling again does nothing, show the error instead
+    Failed(InitError),
+}
+
+impl Startup {
+    pub fn new(window: Rc<Window>, renderer_config: RendererConfig) -> Self {
+        Startup::Loading(Box::pin(async move {
+            State::new(&window, &renderer_config).await
+        }))
+    }
+
+    // Advances loading without blocking, the state once it is ready
+    pub fn poll(&mut self) -> Option<&mut State> {
+        if let Startup::Loading(future) = self {
+            let waker = noop_waker();
+            let mut context = Context::from_waker(&waker);
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(Ok(state)) => *self = Startup::Ready(Box::new(state)),
+                Poll::Ready(Err(e)) => *self = Startup::Failed(e),
+                Poll::Pending => {}
+            }
+        }
+        match self {
+            Startup::Ready(state) => Some(state),
+            Startup::Loading(_) | Startup::Failed(_) => None,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Startup::Ready(_))
+    }
+
+    pub fn error(&self) -> Option<&InitError> {
+        match self {
+            Startup::Failed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// Polling every iteration makes wake-ups unnecessary
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
@@ -0,0 +1,87 @@
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use wgpu::{Buffer, BufferAddress, Queue};
+
+// Weight of the newest frame in the frame time average
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+
+// Bytes handed to the queue since startup, all buffer and texture uploads go through here
+static UPLOADED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn count_upload(bytes: usize) {
+    UPLOADED_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn write_buffer(queue: &Queue, buffer: &Buffer, offset: BufferAddress, data: &[u8]) {
+    count_upload(data.len());
+    queue.write_buffer(buffer, offset, data);
+}
+
+// What one pass issued
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draw_calls: u32,
+    pub instances: u32,
+    // Groups skipped by culling
+    pub culled: u32,
+}
+
+impl DrawStats {
+    pub fn new(draw_calls: u32, instances: u32) -> Self {
+        Self {
+            draw_calls,
+            instances,
+            culled: 0,
+        }
+    }
+}
+
+impl AddAssign for DrawStats {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_calls += other.draw_calls;
+        self.instances += other.instances;
+        self.culled += other.culled;
+    }
+}
+
+// CPU side numbers of the last frame, gathered in State::update and State::render
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FrameStats {
+    pub frame: usize,
+    pub frame_time: Duration,
+    // Exponential moving average in seconds
+    pub frame_time_average: f32,
+    pub draws: DrawStats,
+    pub uploaded_bytes: u64,
+    uploaded_total: u64,
+}
+
+impl FrameStats {
+    pub fn fps(&self) -> f32 {
+        if self.frame_time_average > 0. {
+            1. / self.frame_time_average
+        } else {
+            0.
+        }
+    }
+
+    pub(crate) fn begin_frame(&mut self, dt: Duration) {
+        self.frame += 1;
+        self.frame_time = dt;
+        let dt = dt.as_secs_f32();
+        self.frame_time_average = if self.frame == 1 {
+            dt
+        } else {
+            self.frame_time_average + (dt - self.frame_time_average) * FRAME_TIME_SMOOTHING
+        };
+        self.draws = DrawStats::default();
+    }
+
+    // Uploads since the previous call, made by update and render
+    pub(crate) fn end_frame(&mut self) {
+        let total = UPLOADED_BYTES.load(Ordering::Relaxed);
+        self.uploaded_bytes = total - self.uploaded_total;
+        self.uploaded_total = total;
+    }
+}
@@ -1,9 +1,10 @@
-use std::borrow::Cow;
 use std::num::{NonZeroU32, NonZeroU8};
+use std::sync::atomic::{AtomicU8, Ordering};
 
-use crate::{SAMPLE_COUNT, TEXTURE_SAMPLE_COUNT};
+use crate::mipmap::generate_mipmaps;
+use crate::profiling::profile_scope;
+use crate::{sample_count, stats, DEPTH_PRE_PASS, TEXTURE_SAMPLE_COUNT};
 use anyhow::*;
-use image::GenericImageView;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -12,6 +13,31 @@ pub struct Texture {
 }
 
 pub const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+// Specular, shininess and dissolve maps are sampled as stored
+pub const DATA_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+// Tried in order when the surface has no preferred format
+pub const FALLBACK_SURFACE_FORMATS: [wgpu::TextureFormat; 4] = [
+    wgpu::TextureFormat::Bgra8UnormSrgb,
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+    wgpu::TextureFormat::Bgra8Unorm,
+    wgpu::TextureFormat::Rgba8Unorm,
+];
+
+// The preferred format of the surface, else the first fallback the adapter can render to
+pub fn surface_format(
+    preferred: Option<wgpu::TextureFormat>,
+    renderable: impl Fn(wgpu::TextureFormat) -> bool,
+) -> Option<wgpu::TextureFormat> {
+    preferred.or_else(|| {
+        FALLBACK_SURFACE_FORMATS
+            .into_iter()
+            .find(|format| renderable(*format))
+    })
+}
+
+// Set from RendererConfig::anisotropy at startup
+pub(crate) static ANISOTROPY: AtomicU8 = AtomicU8::new(16);
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
@@ -26,21 +52,44 @@ impl Texture {
         })
     }
 
+    // Opaque geometry only shades the fragments that survived the depth pre-pass
+    pub fn create_opaque_depth_state() -> Option<wgpu::DepthStencilState> {
+        if !DEPTH_PRE_PASS {
+            return Self::create_depth_state();
+        }
+        Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Equal,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        })
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
+    ) -> Self {
+        Self::create_depth_texture_sized(device, config.width, config.height, label)
+    }
+
+    pub fn create_depth_texture_sized(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: SAMPLE_COUNT,
+            sample_count: sample_count(),
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -67,6 +116,135 @@ impl Texture {
         }
     }
 
+    // A single-sampled color texture that can be both rendered into and sampled from
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    // 1x1 and cleared to zero, for the emissive slot of materials that don't glow
+    pub fn black(device: &wgpu::Device, label: &str) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: TEXTURE_SAMPLE_COUNT,
+            dimension: wgpu::TextureDimension::D2,
+            format: TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    // `count` cubes of six square layers each, face f of cube i is layer i * 6 + f.
+    // Without cube arrays (WebGL2, some GL drivers) the view is a single cube and `count` must be 1.
+    // The mips below the first are left to the caller.
+    pub fn create_cube_array(
+        device: &wgpu::Device,
+        size: u32,
+        count: u32,
+        mip_level_count: u32,
+        label: &str,
+    ) -> Self {
+        let dimension = if crate::probes::CUBE_ARRAYS.load(Ordering::Relaxed) {
+            wgpu::TextureViewDimension::CubeArray
+        } else {
+            wgpu::TextureViewDimension::Cube
+        };
+        Self::create_cubes(device, size, count, mip_level_count, dimension, label)
+    }
+
+    // One cube of six square layers, viewed as a cube everywhere
+    pub fn create_cube(device: &wgpu::Device, size: u32, label: &str) -> Self {
+        Self::create_cubes(device, size, 1, 1, wgpu::TextureViewDimension::Cube, label)
+    }
+
+    fn create_cubes(
+        device: &wgpu::Device,
+        size: u32,
+        count: u32,
+        mip_level_count: u32,
+        dimension: wgpu::TextureViewDimension,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: count * 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(dimension),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -74,7 +252,10 @@ impl Texture {
         label: &str,
         mip_level_count: u32,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
+        let img = {
+            profile_scope!("decode texture");
+            image::load_from_memory(bytes)?
+        };
         Self::from_image(device, queue, &img, Some(label), mip_level_count)
     }
 
@@ -85,11 +266,40 @@ impl Texture {
         label: Option<&str>,
         mip_level_count: u32,
     ) -> Result<Self> {
+        Self::from_rgba(device, queue, &img.to_rgba8(), label, mip_level_count)
+    }
+
+    // 1x1 and opaque white, for the maps of materials that don't have them
+    pub fn white(device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> Result<Self> {
+        let white = image::RgbaImage::from_pixel(1, 1, image::Rgba([u8::MAX; 4]));
+        Self::from_rgba_format(device, queue, &white, Some(label), 1, DATA_TEXTURE_FORMAT)
+    }
+
+    // Uploads pixels decoded elsewhere, see texture_decode
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &image::RgbaImage,
+        label: Option<&str>,
+        mip_level_count: u32,
+    ) -> Result<Self> {
+        Self::from_rgba_format(device, queue, rgba, label, mip_level_count, TEXTURE_FORMAT)
+    }
+
+    // DATA_TEXTURE_FORMAT for maps that aren't colors
+    pub fn from_rgba_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &image::RgbaImage,
+        label: Option<&str>,
+        mip_level_count: u32,
+        format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        profile_scope!("upload texture");
         #[cfg(target_arch = "wasm32")]
         let mip_level_count = 1;
 
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        let dimensions = rgba.dimensions();
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -102,7 +312,7 @@ impl Texture {
             mip_level_count,
             sample_count: TEXTURE_SAMPLE_COUNT,
             dimension: wgpu::TextureDimension::D2,
-            format: TEXTURE_FORMAT,
+            format,
             usage: if mip_level_count == 1 {
                 wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST
             } else {
@@ -112,6 +322,7 @@ impl Texture {
             },
         });
 
+        stats::count_upload(rgba.len());
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
@@ -119,7 +330,7 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: NonZeroU32::new(4 * dimensions.0),
@@ -136,11 +347,12 @@ impl Texture {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Linear,
-            anisotropy_clamp: NonZeroU8::new(16).filter(|_| mip_level_count != 1),
+            anisotropy_clamp: NonZeroU8::new(ANISOTROPY.load(Ordering::Relaxed))
+                .filter(|a| a.get() > 1 && mip_level_count != 1),
             ..Default::default()
         });
         if mip_level_count != 1 {
-            generate_mipmaps(device, queue, &texture, mip_level_count);
+            generate_mipmaps(device, queue, &texture, format, mip_level_count, 1);
         }
         Ok(Self {
             texture,
@@ -168,106 +380,30 @@ impl Texture {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
-            ],
-            label: Some("texture_bind_group_layout"),
-        }
-    }
-}
-
-fn generate_mipmaps(
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    texture: &wgpu::Texture,
-    mip_count: u32,
-) {
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("Mipmap encoder"),
-    });
-    let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: Some("Mipmap"),
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("blit.wgsl"))),
-    });
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("blit"),
-        layout: None,
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: &[],
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[TEXTURE_FORMAT.into()],
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            ..Default::default()
-        },
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        multiview: None,
-    });
-
-    let bind_group_layout = pipeline.get_bind_group_layout(0);
-
-    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-        label: Some("mip"),
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Linear,
-        min_filter: wgpu::FilterMode::Linear,
-        mipmap_filter: wgpu::FilterMode::Nearest,
-        ..Default::default()
-    });
-
-    let views = (0..mip_count)
-        .map(|mip| {
-            texture.create_view(&wgpu::TextureViewDescriptor {
-                label: Some("mip"),
-                format: None,
-                dimension: None,
-                aspect: wgpu::TextureAspect::All,
-                base_mip_level: mip,
-                mip_level_count: NonZeroU32::new(1),
-                base_array_layer: 0,
-                array_layer_count: None,
-            })
-        })
-        .collect::<Vec<_>>();
-
-    for target_mip in 1..mip_count as usize {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&views[target_mip - 1]),
+                // tint::TintUniform of the entity or model
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                // Emissive texture, read with the sampler of the diffuse one
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: TEXTURE_SAMPLE_COUNT != 1,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
                 },
             ],
-            label: None,
-        });
-
-        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &views[target_mip],
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
-                    store: true,
-                },
-            }],
-            depth_stencil_attachment: None,
-        });
-        rpass.set_pipeline(&pipeline);
-        rpass.set_bind_group(0, &bind_group, &[]);
-        rpass.draw(0..3, 0..1);
+            label: Some("texture_bind_group_layout"),
+        }
     }
-    queue.submit(Some(encoder.finish()));
 }
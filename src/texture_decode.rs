@@ -0,0 +1,81 @@
+use crate::profiling::profile_scope;
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+// Pixels ready for Texture::from_rgba, decoding and conversion already happened off the render thread
+pub struct DecodedImage {
+    pub label: String,
+    pub image: RgbaImage,
+}
+
+fn decode(label: String, bytes: &[u8]) -> Result<DecodedImage> {
+    profile_scope!("decode texture");
+    let image = image::load_from_memory(bytes)
+        .with_context(|| format!("Failed to decode {}", label))?
+        .into_rgba8();
+    Ok(DecodedImage { label, image })
+}
+
+// Decodes every file at once on the rayon pool, in input order. Serial on wasm.
+pub fn decode_all(files: Vec<(String, Vec<u8>)>) -> Vec<Result<DecodedImage>> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            files
+                .into_iter()
+                .map(|(label, bytes)| decode(label, &bytes))
+                .collect()
+        } else {
+            use rayon::prelude::*;
+            files
+                .into_par_iter()
+                .map(|(label, bytes)| decode(label, &bytes))
+                .collect()
+        }
+    }
+}
+
+// Streams decodes through the rayon pool while the render thread keeps drawing.
+// Finished images are picked up with `finished`, in completion order.
+pub struct TextureDecoder {
+    sender: Sender<Result<DecodedImage>>,
+    receiver: Receiver<Result<DecodedImage>>,
+    pending: usize,
+}
+
+impl Default for TextureDecoder {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            pending: 0,
+        }
+    }
+}
+
+impl TextureDecoder {
+    // Decodes immediately on wasm, there are no worker threads
+    pub fn decode(&mut self, label: String, bytes: Vec<u8>) {
+        self.pending += 1;
+        let sender = self.sender.clone();
+        let job = move || {
+            sender.send(decode(label, &bytes)).ok();
+        };
+        #[cfg(target_arch = "wasm32")]
+        job();
+        #[cfg(not(target_arch = "wasm32"))]
+        rayon::spawn(job);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    // Never blocks
+    pub fn finished(&mut self) -> Vec<Result<DecodedImage>> {
+        let finished: Vec<_> = self.receiver.try_iter().collect();
+        self.pending -= finished.len();
+        finished
+    }
+}
@@ -0,0 +1,292 @@
+use crate::reflect::uniform_fields;
+use crate::stats;
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+use wgpu::{Buffer, Device, Queue};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1. - (1. - t) * (1. - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2. * t * t
+                } else {
+                    1. - (-2. * t + 2.).powi(2) / 2.
+                }
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TintUniform {
+    // Multiplied with the shaded color, alpha unused
+    pub tint: [f32; 4],
+    // Mixed over the result, alpha is the mix factor
+    pub flash: [f32; 4],
+    // How much of the nearest reflection probe or the sky shows, see probes.rs. Models mirror at
+    // least the reflectivity of their material.
+    pub reflectivity: f32,
+    // Back light passing through thin surfaces like leaves and cloth
+    pub translucency: f32,
+    // Texture coordinates per second of scene time, wrapping around
+    pub uv_scroll: [f32; 2],
+    // Atlas columns, rows and frames per second, frames run row by row from the top left.
+    // Fewer than two frames shows the whole texture.
+    pub flipbook: [f32; 4],
+    // Light given off regardless of the scene lights, alpha weighs in the emissive texture
+    pub emissive: [f32; 4],
+    // Offset in xy and scale in zw from the texture coordinates to the lightmap of the entity,
+    // a zero scale lights it without one. See lightmap.rs.
+    pub lightmap_uv: [f32; 4],
+}
+
+impl Default for TintUniform {
+    fn default() -> Self {
+        Self {
+            tint: [1.; 4],
+            flash: [0.; 4],
+            reflectivity: 0.,
+            translucency: 0.,
+            uv_scroll: [0.; 2],
+            flipbook: [1., 1., 0., 0.],
+            emissive: [0.; 4],
+            lightmap_uv: [0.; 4],
+        }
+    }
+}
+
+uniform_fields!(TintUniform {
+    tint: Color(0., 10.) => "Tint",
+    flash: Vec4(0., 1.) => "Flash",
+    reflectivity: Float(0., 1.) => "Reflectivity",
+    translucency: Float(0., 1.) => "Translucency",
+    flipbook: Vec4(0., 120.) => "Flipbook",
+    emissive: Color(0., 10.) => "Emissive",
+});
+
+// Fades from full strength back to nothing over `duration`
+struct Effect {
+    color: [f32; 3],
+    elapsed: Duration,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl Effect {
+    fn strength(&self) -> f32 {
+        if self.duration.is_zero() {
+            return 0.;
+        }
+        let t = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        1. - self.easing.apply(t)
+    }
+
+    fn is_done(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+// Per-entity color animation, the uniform is rewritten in place so bind groups stay untouched
+pub struct Tint {
+    uniform: TintUniform,
+    // Persistent multiplier the tint effect fades back to, edited as a material color
+    color: [f32; 3],
+    reflectivity: f32,
+    translucency: f32,
+    uv_scroll: [f32; 2],
+    flipbook: [f32; 4],
+    emissive: [f32; 3],
+    // Multiplies the emissive color by the emissive texture, otherwise the whole surface glows
+    emissive_texture: bool,
+    lightmap_uv: [f32; 4],
+    pub(crate) buffer: Buffer,
+    flash: Option<Effect>,
+    tint: Option<Effect>,
+    dirty: bool,
+}
+
+impl Tint {
+    pub fn new(device: &Device) -> Self {
+        let uniform = TintUniform::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tint Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            uniform,
+            color: [1.; 3],
+            reflectivity: 0.,
+            translucency: 0.,
+            uv_scroll: uniform.uv_scroll,
+            flipbook: uniform.flipbook,
+            emissive: [0.; 3],
+            emissive_texture: false,
+            lightmap_uv: [0.; 4],
+            buffer,
+            flash: None,
+            tint: None,
+            dirty: false,
+        }
+    }
+
+    // Blend the entity towards `color`, e.g. white or red on damage
+    pub fn flash(&mut self, color: [f32; 3], duration: Duration, easing: Easing) {
+        self.flash = Some(Effect {
+            color,
+            elapsed: Duration::ZERO,
+            duration,
+            easing,
+        });
+    }
+
+    // Multiply the entity by `color`, fading back to its own colors
+    pub fn tint(&mut self, color: [f32; 3], duration: Duration, easing: Easing) {
+        self.tint = Some(Effect {
+            color,
+            elapsed: Duration::ZERO,
+            duration,
+            easing,
+        });
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        self.color
+    }
+
+    pub fn set_color(&mut self, color: [f32; 3]) {
+        self.color = color;
+        self.dirty = true;
+    }
+
+    pub fn reflectivity(&self) -> f32 {
+        self.reflectivity
+    }
+
+    pub fn set_reflectivity(&mut self, reflectivity: f32) {
+        self.reflectivity = reflectivity.clamp(0., 1.);
+        self.dirty = true;
+    }
+
+    pub fn translucency(&self) -> f32 {
+        self.translucency
+    }
+
+    pub fn set_translucency(&mut self, translucency: f32) {
+        self.translucency = translucency.clamp(0., 1.);
+        self.dirty = true;
+    }
+
+    pub fn uv_scroll(&self) -> [f32; 2] {
+        self.uv_scroll
+    }
+
+    // Conveyor belts and the like, the sampler clamps so the coordinates are wrapped in the shader
+    pub fn set_uv_scroll(&mut self, speed: [f32; 2]) {
+        self.uv_scroll = speed;
+        self.dirty = true;
+    }
+
+    // columns, rows and frames per second of the atlas
+    pub fn flipbook(&self) -> (u32, u32, f32) {
+        let [columns, rows, fps, _] = self.flipbook;
+        (columns as u32, rows as u32, fps)
+    }
+
+    // Plays a texture atlas of columns x rows frames, e.g. fire or a screen
+    pub fn set_flipbook(&mut self, columns: u32, rows: u32, fps: f32) {
+        self.flipbook = [columns.max(1) as f32, rows.max(1) as f32, fps.max(0.), 0.];
+        self.dirty = true;
+    }
+
+    pub fn emissive(&self) -> [f32; 3] {
+        self.emissive
+    }
+
+    // Not clamped, values above 1 are what a bright pass picks up
+    pub fn set_emissive(&mut self, color: [f32; 3]) {
+        self.emissive = color;
+        self.dirty = true;
+    }
+
+    pub(crate) fn set_emissive_texture(&mut self, enabled: bool) {
+        self.emissive_texture = enabled;
+        self.dirty = true;
+    }
+
+    // Set once the lightmap of the entity is baked
+    pub(crate) fn set_lightmap_uv(&mut self, uv: [f32; 4]) {
+        self.lightmap_uv = uv;
+        self.dirty = true;
+    }
+
+    fn has_uv_animation(&self) -> bool {
+        let [columns, rows, fps, _] = self.flipbook;
+        self.uv_scroll != [0.; 2] || (columns * rows > 1. && fps > 0.)
+    }
+
+    fn has_effects(&self) -> bool {
+        self.flash.is_some() || self.tint.is_some()
+    }
+
+    // UV animation runs on the scene time in the shader, it only needs frames
+    pub fn is_animating(&self) -> bool {
+        self.has_effects() || self.has_uv_animation()
+    }
+
+    pub fn update(&mut self, dt: Duration, queue: &Queue) {
+        if !self.has_effects() && !self.dirty {
+            return;
+        }
+        self.uniform = TintUniform::default();
+        let [r, g, b] = self.color;
+        self.uniform.tint = [r, g, b, 1.];
+        self.uniform.reflectivity = self.reflectivity;
+        self.uniform.translucency = self.translucency;
+        self.uniform.uv_scroll = self.uv_scroll;
+        self.uniform.flipbook = self.flipbook;
+        let [r, g, b] = self.emissive;
+        self.uniform.emissive = [r, g, b, if self.emissive_texture { 1. } else { 0. }];
+        self.uniform.lightmap_uv = self.lightmap_uv;
+        if let Some(flash) = &mut self.flash {
+            flash.elapsed += dt;
+            let [r, g, b] = flash.color;
+            self.uniform.flash = [r, g, b, flash.strength()];
+        }
+        if let Some(tint) = &mut self.tint {
+            tint.elapsed += dt;
+            let strength = tint.strength();
+            for (channel, color) in self.uniform.tint.iter_mut().zip(tint.color) {
+                *channel *= 1. + (color - 1.) * strength;
+            }
+        }
+        stats::write_buffer(
+            queue,
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+        // Effects that just finished still need one write to reset the uniform
+        self.dirty = self.has_effects();
+        if matches!(&self.flash, Some(flash) if flash.is_done()) {
+            self.flash = None;
+        }
+        if matches!(&self.tint, Some(tint) if tint.is_done()) {
+            self.tint = None;
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use winit::event::TouchPhase;
+#[cfg(target_arch = "wasm32")]
+use {
+    std::{cell::RefCell, rc::Rc},
+    wasm_bindgen::{prelude::*, JsCast},
+};
+
+// A finger touching down in the lower left, as a fraction of the window, steers like a joystick
+const JOYSTICK_ZONE: [f32; 2] = [0.4, 0.5];
+// Distance from where the joystick finger went down to full speed, as a fraction of the
+// shorter side of the window
+const JOYSTICK_RADIUS: f32 = 0.12;
+
+// One finger in physical pixels from the top left of the window
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TouchPoint {
+    pub id: u64,
+    pub phase: TouchPhase,
+    pub position: [f32; 2],
+}
+
+// What the fingers on the screen ask the camera to do
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TouchGesture {
+    // One finger dragged outside the joystick, in pixels like mouse motion
+    Look { dx: f32, dy: f32 },
+    // Change in pixels of the distance between two fingers, positive when they spread
+    Pinch(f32),
+    // Each in [-1, 1], 0 when the finger is lifted
+    Joystick { right: f32, forward: f32 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Role {
+    Joystick { origin: [f32; 2] },
+    Look,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Finger {
+    id: u64,
+    role: Role,
+    last: [f32; 2],
+}
+
+// Turns touches into camera input for phones and tablets: one finger looks around, two pinch to
+// move along the view and a finger starting in the lower left is a virtual joystick. The engine
+// has no UI of its own, `joystick` is for whatever draws one.
+#[derive(Debug, Default)]
+pub struct TouchInput {
+    fingers: Vec<Finger>,
+}
+
+impl TouchInput {
+    // `size` of the window in pixels
+    pub fn touch(&mut self, point: TouchPoint, size: [f32; 2]) -> Option<TouchGesture> {
+        match point.phase {
+            TouchPhase::Started => {
+                let [x, y] = point.position;
+                let in_zone = x < size[0] * JOYSTICK_ZONE[0] && y > size[1] * JOYSTICK_ZONE[1];
+                let role = if in_zone && self.joystick().is_none() {
+                    Role::Joystick {
+                        origin: point.position,
+                    }
+                } else {
+                    Role::Look
+                };
+                self.fingers.push(Finger {
+                    id: point.id,
+                    role,
+                    last: point.position,
+                });
+                None
+            }
+            TouchPhase::Moved => {
+                let index = self.fingers.iter().position(|f| f.id == point.id)?;
+                let finger = self.fingers[index];
+                let gesture = match finger.role {
+                    Role::Joystick { origin } => {
+                        let radius = size[0].min(size[1]) * JOYSTICK_RADIUS;
+                        let [right, down] = joystick_axes(origin, point.position, radius);
+                        Some(TouchGesture::Joystick {
+                            right,
+                            forward: -down,
+                        })
+                    }
+                    Role::Look => {
+                        let looking: Vec<_> = self
+                            .fingers
+                            .iter()
+                            .filter(|f| f.role == Role::Look)
+                            .collect();
+                        match looking[..] {
+                            [_] => Some(TouchGesture::Look {
+                                dx: point.position[0] - finger.last[0],
+                                dy: point.position[1] - finger.last[1],
+                            }),
+                            [a, b] => {
+                                let other = if a.id == point.id { b } else { a };
+                                let before = distance(finger.last, other.last);
+                                let after = distance(point.position, other.last);
+                                Some(TouchGesture::Pinch(after - before))
+                            }
+                            _ => None,
+                        }
+                    }
+                };
+                self.fingers[index].last = point.position;
+                gesture
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                let index = self.fingers.iter().position(|f| f.id == point.id)?;
+                let finger = self.fingers.remove(index);
+                matches!(finger.role, Role::Joystick { .. }).then(|| TouchGesture::Joystick {
+                    right: 0.,
+                    forward: 0.,
+                })
+            }
+        }
+    }
+
+    // Where the joystick finger went down and where it is now
+    pub fn joystick(&self) -> Option<([f32; 2], [f32; 2])> {
+        self.fingers.iter().find_map(|finger| match finger.role {
+            Role::Joystick { origin } => Some((origin, finger.last)),
+            Role::Look => None,
+        })
+    }
+}
+
+fn distance(a: [f32; 2], b: [f32; 2]) -> f32 {
+    (a[0] - b[0]).hypot(a[1] - b[1])
+}
+
+// Offset from `origin` in radii, at most 1 long
+fn joystick_axes(origin: [f32; 2], position: [f32; 2], radius: f32) -> [f32; 2] {
+    let offset = [
+        (position[0] - origin[0]) / radius,
+        (position[1] - origin[1]) / radius,
+    ];
+    let length = offset[0].hypot(offset[1]);
+    if length > 1. {
+        offset.map(|axis| axis / length)
+    } else {
+        offset
+    }
+}
+
+// winit doesn't report touches on the web, they are read from the canvas' touch events and
+// queued for the event loop. Also keeps the browser from scrolling or zooming the page.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn listen_for_touches(
+    canvas: &web_sys::HtmlCanvasElement,
+    queue: Rc<RefCell<Vec<TouchPoint>>>,
+) {
+    canvas.style().set_property("touch-action", "none").ok();
+    let events = [
+        ("touchstart", TouchPhase::Started),
+        ("touchmove", TouchPhase::Moved),
+        ("touchend", TouchPhase::Ended),
+        ("touchcancel", TouchPhase::Cancelled),
+    ];
+    for (event, phase) in events {
+        let queue = Rc::clone(&queue);
+        let target = canvas.clone();
+        let closure = Closure::wrap(Box::new(move |e: web_sys::TouchEvent| {
+            let rect = target.get_bounding_client_rect();
+            let scale = web_sys::window().map_or(1., |win| win.device_pixel_ratio());
+            let touches = e.changed_touches();
+            for i in 0..touches.length() {
+                if let Some(touch) = touches.get(i) {
+                    let x = (touch.client_x() as f64 - rect.left()) * scale;
+                    let y = (touch.client_y() as f64 - rect.top()) * scale;
+                    queue.borrow_mut().push(TouchPoint {
+                        id: touch.identifier() as u64,
+                        phase,
+                        position: [x as f32, y as f32],
+                    });
+                }
+            }
+        }) as Box<dyn FnMut(_)>);
+        canvas
+            .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+            .ok();
+        closure.forget();
+    }
+}
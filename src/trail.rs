@@ -0,0 +1,200 @@
+use crate::camera::Camera;
+use crate::stats::DrawStats;
+use crate::{multi_sample, stats, texture, RenderGroup, PRIMITIVE};
+use cgmath::{InnerSpace, Vector3};
+use std::collections::VecDeque;
+use std::time::Duration;
+use wgpu::{Buffer, Device, Queue, RenderPass, RenderPipeline, SurfaceConfiguration};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TrailVertex {
+    position: [f32; 3],
+    direction: [f32; 3],
+    color: [f32; 4],
+    offset: f32,
+}
+
+impl TrailVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 4] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x4, 3 => Float32];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TrailVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+struct TrailPoint {
+    position: Vector3<f32>,
+    age: Duration,
+}
+
+// Records the recent positions of something moving and draws them as a camera facing ribbon
+// that narrows and fades out towards its tail.
+pub struct TrailRenderGroup {
+    points: VecDeque<TrailPoint>,
+    capacity: usize,
+    pub color: [f32; 4],
+    pub width: f32,
+    // Points older than this are dropped
+    pub lifetime: Duration,
+    // A new point is only recorded after moving this far
+    pub min_distance: f32,
+    vertex_buffer: Buffer,
+    vertex_count: u32,
+    render_pipeline: RenderPipeline,
+}
+
+impl TrailRenderGroup {
+    pub fn new(
+        device: &Device,
+        camera: &Camera,
+        config: &SurfaceConfiguration,
+        capacity: usize,
+        color: [f32; 4],
+        width: f32,
+        lifetime: Duration,
+    ) -> Self {
+        let capacity = capacity.max(2);
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail Vertex Buffer"),
+            size: (std::mem::size_of::<TrailVertex>() * capacity * 2) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Trail Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("trail.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail Pipeline Layout"),
+            bind_group_layouts: &[&camera.camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Trail Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[TrailVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                // The ribbon faces the camera from either side
+                cull_mode: None,
+                ..PRIMITIVE
+            },
+            // Tested against the scene but never occludes it
+            depth_stencil: texture::Texture::create_depth_state().map(|state| {
+                wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    ..state
+                }
+            }),
+            multisample: multi_sample(),
+            multiview: None,
+        });
+        Self {
+            points: VecDeque::with_capacity(capacity),
+            capacity,
+            color,
+            width,
+            lifetime,
+            min_distance: 0.5,
+            vertex_buffer,
+            vertex_count: 0,
+            render_pipeline,
+        }
+    }
+
+    pub fn record(&mut self, position: Vector3<f32>) {
+        if let Some(last) = self.points.back() {
+            if (position - last.position).magnitude() < self.min_distance {
+                return;
+            }
+        }
+        if self.points.len() == self.capacity {
+            self.points.pop_front();
+        }
+        self.points.push_back(TrailPoint {
+            position,
+            age: Duration::ZERO,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+}
+
+impl RenderGroup for TrailRenderGroup {
+    fn update(&mut self, dt: Duration, queue: &Queue) {
+        for point in &mut self.points {
+            point.age += dt;
+        }
+        let lifetime = self.lifetime;
+        self.points.retain(|point| point.age < lifetime);
+
+        let points = &self.points;
+        let mut vertices = Vec::with_capacity(points.len() * 2);
+        for (i, point) in points.iter().enumerate() {
+            let prev = points[i.saturating_sub(1)].position;
+            let next = points[(i + 1).min(points.len() - 1)].position;
+            let fade = 1. - point.age.as_secs_f32() / lifetime.as_secs_f32().max(f32::EPSILON);
+            let [r, g, b, a] = self.color;
+            for side in [-1., 1.] {
+                vertices.push(TrailVertex {
+                    position: point.position.into(),
+                    direction: (next - prev).into(),
+                    color: [r, g, b, a * fade],
+                    offset: side * self.width * 0.5 * fade,
+                });
+            }
+        }
+        if !vertices.is_empty() {
+            stats::write_buffer(
+                queue,
+                &self.vertex_buffer,
+                0,
+                bytemuck::cast_slice(&vertices),
+            );
+        }
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    // Keeps fading out until the last point expires
+    fn is_animating(&self) -> bool {
+        !self.points.is_empty()
+    }
+
+    fn draw_stats(&self, shadow_pass: bool) -> DrawStats {
+        if shadow_pass || self.vertex_count < 4 {
+            return DrawStats::default();
+        }
+        DrawStats::new(1, 1)
+    }
+
+    fn render<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, shadow_pass: bool) {
+        // Trails are transparent, they neither cast shadows nor write depth
+        if shadow_pass || self.vertex_count < 4 {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
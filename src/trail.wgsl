@@ -0,0 +1,51 @@
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    proj_inv: mat4x4<f32>,
+            // from world to camera
+    view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Camera relative when enabled, the subtraction keeps precision a translation in the matrix loses
+fn world_to_clip(world_position: vec3<f32>) -> vec4<f32> {
+    return camera.relative_view_proj * vec4<f32>(world_position - camera.render_origin.xyz, 1.0);
+}
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    // Towards the newer end of the trail
+    @location(1) direction: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    // Signed distance from the center line, already scaled by the fade
+    @location(3) offset: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(v_in: VertexInput) -> VertexOutput {
+    // Expand sideways so the ribbon always faces the camera
+    let to_camera = camera.view_pos.xyz - v_in.position;
+    var side = cross(v_in.direction, to_camera);
+    if length(side) < 0.0001 {
+        side = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    let position = v_in.position + normalize(side) * v_in.offset;
+    var v_out: VertexOutput;
+    v_out.clip_position = world_to_clip(position);
+    v_out.color = v_in.color;
+    return v_out;
+}
+
+@fragment
+fn fs_main(f_in: VertexOutput) -> @location(0) vec4<f32> {
+    return f_in.color;
+}
@@ -0,0 +1,85 @@
+// Simple layouts of the second uv set, for lightmaps and detail textures on meshes whose texture
+// coordinates repeat or overlap. Only tex_coords2 is written, the texture coordinates stay.
+use crate::bounds::Aabb;
+use crate::geo_gen::{MeshData, CUBE_FACES};
+use cgmath::{InnerSpace, Point3, Vector3};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// Projects the mesh along `normal` into the unit square, keeping its proportions. Triangles
+// behind each other overlap, fine for floors, walls and terrain.
+pub fn planar_unwrap(mesh: &mut MeshData, normal: Vector3<f32>) {
+    let normal = normal.normalize();
+    let reference = if normal.y.abs() < 0.99 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_z()
+    };
+    let right = reference.cross(normal).normalize();
+    let up = normal.cross(right);
+    let projected: Vec<[f32; 2]> = mesh
+        .vertex_data
+        .iter()
+        .map(|v| {
+            let p = Vector3::from(v.position);
+            [p.dot(right), p.dot(up)]
+        })
+        .collect();
+    let (mut min, mut max) = ([f32::MAX; 2], [f32::MIN; 2]);
+    for st in &projected {
+        for i in 0..2 {
+            min[i] = min[i].min(st[i]);
+            max[i] = max[i].max(st[i]);
+        }
+    }
+    let scale = 1. / (max[0] - min[0]).max(max[1] - min[1]).max(f32::EPSILON);
+    for (vertex, [s, t]) in mesh.vertex_data.iter_mut().zip(projected) {
+        vertex.tex_coords2 = [(s - min[0]) * scale, (t - min[1]) * scale];
+    }
+}
+
+// Every triangle is projected along the side of the bounding box its face points at, like the
+// faces of cube_mesh, into one of six cells of a 3 x 2 grid. `padding` is the share of a cell
+// left empty around it so lightmap texels don't bleed between sides. Vertices on an edge
+// between two sides are split, triangles behind each other on the same side overlap.
+pub fn box_unwrap(mesh: &mut MeshData, padding: f32) {
+    let bounds = match Aabb::from_points(mesh.vertex_data.iter().map(|v| v.position.into())) {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    let size = bounds.max - bounds.min;
+    // One scale for every side so texels are the same size on all of them
+    let extent = size.x.max(size.y).max(size.z).max(f32::EPSILON);
+    let center = bounds.center();
+    let padding = padding.clamp(0., 0.5);
+    let mut vertex_data = Vec::with_capacity(mesh.vertex_data.len());
+    let mut split: HashMap<(u32, usize), u32> = HashMap::new();
+    let mut index_data = Vec::with_capacity(mesh.index_data.len());
+    for t in mesh.index_data.chunks_exact(3) {
+        let [a, b, c] =
+            [t[0], t[1], t[2]].map(|i| Vector3::from(mesh.vertex_data[i as usize].position));
+        let face_normal = (b - a).cross(c - a);
+        let along = CUBE_FACES.map(|[normal, ..]| face_normal.dot(normal.into()));
+        let side = (0..6)
+            .max_by(|&x, &y| along[x].partial_cmp(&along[y]).unwrap_or(Ordering::Equal))
+            .unwrap_or(0);
+        let [_, right, up] = CUBE_FACES[side].map(Vector3::from);
+        let cell = [(side % 3) as f32, (side / 3) as f32];
+        for &i in t {
+            let index = *split.entry((i, side)).or_insert_with(|| {
+                let mut vertex = mesh.vertex_data[i as usize];
+                let offset = Point3::from(vertex.position) - center;
+                let st = [offset.dot(right), offset.dot(up)].map(|x| x / extent + 0.5);
+                vertex.tex_coords2 = [0, 1].map(|k| {
+                    let inside = padding + st[k] * (1. - 2. * padding);
+                    (cell[k] + inside) / [3., 2.][k]
+                });
+                vertex_data.push(vertex);
+                vertex_data.len() as u32 - 1
+            });
+            index_data.push(index);
+        }
+    }
+    mesh.vertex_data = vertex_data;
+    mesh.index_data = index_data;
+}
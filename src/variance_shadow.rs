@@ -0,0 +1,230 @@
+// Variance shadow maps for ShadowFilter::Variance. The shadow pass also writes the first two
+// moments of the linear light depth into a color target, which is blurred in two passes and
+// then sampled with linear filtering. The lit shaders bound the lit fraction with Chebyshev's
+// inequality, soft shadows for one filtered tap instead of a PCF kernel.
+use crate::shadow;
+use std::num::NonZeroU32;
+use wgpu::{BindGroup, CommandEncoder, Device, RenderPipeline, TextureView};
+
+const MOMENTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+pub(crate) struct VarianceShadowMaps {
+    pub(crate) pipeline: RenderPipeline,
+    // With the deformed layout of the shadow pass, for the meshes moved in the vertex shader
+    pub(crate) deformed_pipeline: Option<RenderPipeline>,
+    // One layer per light like the depth maps, sampled as an array
+    pub(crate) view: TextureView,
+    pub(crate) layer_views: Vec<TextureView>,
+    // Holds the horizontal pass of one layer at a time
+    scratch_view: TextureView,
+    horizontal_pipeline: RenderPipeline,
+    vertical_pipeline: RenderPipeline,
+    // Per light, reading its layer
+    horizontal_bind_groups: Vec<BindGroup>,
+    vertical_bind_group: BindGroup,
+}
+
+impl VarianceShadowMaps {
+    // Renders with the layouts of the shadow pipelines into its depth maps of `depth_format`
+    pub(crate) fn new(
+        device: &Device,
+        pipeline_layout: &wgpu::PipelineLayout,
+        deformed_layout: Option<&wgpu::PipelineLayout>,
+        depth_format: wgpu::TextureFormat,
+        size: u32,
+        light_count: u32,
+    ) -> Self {
+        let moments_texture = |label, layers| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: MOMENTS_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            })
+        };
+        let texture = moments_texture("Shadow moments", light_count);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow moments"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layer_views: Vec<_> = (0..light_count)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow moments layer"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let scratch_view = moments_texture("Shadow moments blur", 1)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let create_pipeline = |layout: &wgpu::PipelineLayout, deformed| {
+            let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Shadow moments"),
+                source: wgpu::ShaderSource::Wgsl(shadow::shader_source(deformed).into()),
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("shadow moments"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_moments",
+                    buffers: &shadow::vertex_buffers(deformed),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_moments",
+                    targets: &[MOMENTS_FORMAT.into()],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                // No bias, only the moments are compared
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: Default::default(),
+                multiview: None,
+            })
+        };
+        let pipeline = create_pipeline(pipeline_layout, false);
+        let deformed_pipeline = deformed_layout.map(|layout| create_pipeline(layout, true));
+
+        let blur_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow moments blur"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("vsm_blur.wgsl").into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow moments blur"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+        });
+        let blur_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow moments blur"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let blur_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Shadow moments blur"),
+                layout: Some(&blur_layout),
+                vertex: wgpu::VertexState {
+                    module: &blur_shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &blur_shader,
+                    entry_point,
+                    targets: &[MOMENTS_FORMAT.into()],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+            })
+        };
+        let source_bind_group = |view: &TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Shadow moments blur"),
+                layout: &bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }],
+            })
+        };
+        Self {
+            pipeline,
+            deformed_pipeline,
+            view,
+            horizontal_bind_groups: layer_views.iter().map(source_bind_group).collect(),
+            vertical_bind_group: source_bind_group(&scratch_view),
+            layer_views,
+            scratch_view,
+            horizontal_pipeline: blur_pipeline("fs_horizontal"),
+            vertical_pipeline: blur_pipeline("fs_vertical"),
+        }
+    }
+
+    // After the moments of the light are rendered, in the same encoder
+    pub(crate) fn blur(&self, encoder: &mut CommandEncoder, layer: usize) {
+        let passes = [
+            (
+                &self.horizontal_pipeline,
+                &self.horizontal_bind_groups[layer],
+                &self.scratch_view,
+            ),
+            (
+                &self.vertical_pipeline,
+                &self.vertical_bind_group,
+                &self.layer_views[layer],
+            ),
+        ];
+        for (pipeline, bind_group, target) in passes {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow moments blur"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+// Bound in place of the moments until ShadowFilter::Variance is first selected
+pub(crate) fn empty_moments_view(device: &Device) -> TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("No shadow moments"),
+            size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: MOMENTS_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        })
+        .create_view(&wgpu::TextureViewDescriptor {
+            label: Some("No shadow moments"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+}
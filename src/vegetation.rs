@@ -0,0 +1,248 @@
+use crate::bounds::BoundingSphere;
+use crate::camera::Camera;
+use crate::light::LightRenderGroup;
+use crate::stats::DrawStats;
+use crate::world_space::Rng;
+use crate::{multi_sample, stats, texture, RenderGroup, PRIMITIVE};
+use cgmath::Vector3;
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, Buffer, Device, Queue, RenderPass, RenderPipeline, SurfaceConfiguration};
+
+// Two vertices per segment row and one at the tip, see vs_main in vegetation.wgsl
+const BLADE_VERTICES: u32 = 7;
+
+// One grass blade, its shape is built in the vertex shader from these
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GrassBlade {
+    // Root of the blade
+    pub position: [f32; 3],
+    // Turn about y in radians, the blade leans along its facing
+    pub yaw: f32,
+    pub height: f32,
+    pub width: f32,
+    // In [0, TAU), offsets the sway and picks when the blade thins out with distance
+    pub phase: f32,
+    // Brightness variation multiplied into the color
+    pub shade: f32,
+}
+
+impl GrassBlade {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32,
+        2 => Float32,
+        3 => Float32,
+        4 => Float32,
+        5 => Float32,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GrassBlade>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// Random blades on the floor plane at `height`, within `half_extent` of `center` on x and z.
+// The same seed gives the same field.
+pub fn scatter_blades(
+    count: usize,
+    center: Vector3<f32>,
+    half_extent: f32,
+    height: f32,
+    seed: u64,
+) -> Vec<GrassBlade> {
+    let mut rng = Rng::new(seed);
+    (0..count)
+        .map(|_| GrassBlade {
+            position: [
+                center.x + rng.signed() * half_extent,
+                height,
+                center.z + rng.signed() * half_extent,
+            ],
+            yaw: rng.unit() * std::f32::consts::TAU,
+            height: 1.5 + rng.unit() * 2.5,
+            width: 0.2 + rng.unit() * 0.15,
+            phase: rng.unit() * std::f32::consts::TAU,
+            shade: 0.7 + rng.unit() * 0.6,
+        })
+        .collect()
+}
+
+// How much of the field is left `distance` from the camera on the floor plane: 1 up to
+// `start`, falling to 0 at `end`. Mirrors blade_fade in vegetation.wgsl.
+pub fn blade_fade(distance: f32, start: f32, end: f32) -> f32 {
+    ((end - distance) / (end - start).max(f32::EPSILON)).clamp(0., 1.)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct VegetationUniform {
+    // Direction on xz, strength and frequency of the gusts
+    wind: [f32; 4],
+    // Start and end of the distance fade, seconds since startup in z, w unused
+    fade_time: [f32; 4],
+}
+
+// A field of grass blades in one instanced draw, the flagship instancing stress test.
+// Blades sway in the wind in the vertex shader and thin out with distance from the camera,
+// the survivors shrinking into the ground instead of popping. Blades are lit by the same
+// lights as everything else but neither cast shadows nor take part in the depth pre-pass.
+pub struct VegetationRenderGroup {
+    blade_count: u32,
+    bounds: BoundingSphere,
+    // On the xz plane, need not be normalized
+    pub wind_direction: [f32; 2],
+    // How far a gust bends a blade tip, in blade heights
+    pub wind_strength: f32,
+    // Radians per second of the sway
+    pub wind_frequency: f32,
+    // Blades start thinning out at the first distance and are gone at the second
+    pub fade: [f32; 2],
+    time: Duration,
+    instance_buffer: Buffer,
+    uniform_buffer: Buffer,
+    bind_group: BindGroup,
+    render_pipeline: RenderPipeline,
+}
+
+impl VegetationRenderGroup {
+    pub fn new(
+        device: &Device,
+        camera: &Camera,
+        config: &SurfaceConfiguration,
+        light_render_group: &LightRenderGroup,
+        blades: &[GrassBlade],
+    ) -> Self {
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vegetation Instance Buffer"),
+            contents: bytemuck::cast_slice(blades),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Vegetation Uniform Buffer"),
+            size: std::mem::size_of::<VegetationUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout =
+            device.create_bind_group_layout(&crate::uniform_desc("Vegetation Bind Group Layout"));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("Vegetation Bind Group"),
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Vegetation Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("vegetation.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Vegetation Pipeline Layout"),
+            bind_group_layouts: &[
+                &camera.camera_bind_group_layout,
+                &light_render_group.light_bind_group_layout,
+                &layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Vegetation Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[GrassBlade::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                // Blades are flat, seen from either side
+                cull_mode: None,
+                ..PRIMITIVE
+            },
+            // Not in the depth pre-pass, so depth is tested and written here
+            depth_stencil: texture::Texture::create_depth_state(),
+            multisample: multi_sample(),
+            multiview: None,
+        });
+        let max_height = blades.iter().map(|blade| blade.height).fold(0., f32::max);
+        let mut bounds = BoundingSphere::from_points(blades.iter().map(|blade| blade.position));
+        bounds.radius += max_height;
+        Self {
+            blade_count: blades.len() as u32,
+            bounds,
+            wind_direction: [1., 0.3],
+            wind_strength: 0.35,
+            wind_frequency: 1.7,
+            fade: [120., 220.],
+            time: Duration::ZERO,
+            instance_buffer,
+            uniform_buffer,
+            bind_group,
+            render_pipeline,
+        }
+    }
+}
+
+impl RenderGroup for VegetationRenderGroup {
+    // The wind follows wall time but doesn't ask for redraws, reactive mode can still idle
+    fn update(&mut self, dt: Duration, queue: &Queue) {
+        self.time += dt;
+        let [x, z] = self.wind_direction;
+        let length = (x * x + z * z).sqrt().max(f32::EPSILON);
+        let [start, end] = self.fade;
+        let uniform = VegetationUniform {
+            wind: [
+                x / length,
+                z / length,
+                self.wind_strength,
+                self.wind_frequency,
+            ],
+            // Wrapped so the sway keeps its precision in long sessions
+            fade_time: [start, end, (self.time.as_secs_f64() % 3600.) as f32, 0.],
+        };
+        stats::write_buffer(
+            queue,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniform]),
+        );
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        Some(self.bounds)
+    }
+
+    fn draw_stats(&self, shadow_pass: bool) -> DrawStats {
+        if shadow_pass || self.blade_count == 0 {
+            return DrawStats::default();
+        }
+        DrawStats::new(1, self.blade_count)
+    }
+
+    fn render<'a, 'b: 'a>(&'b self, render_pass: &mut RenderPass<'a>, shadow_pass: bool) {
+        // Too thin to shadow anything, and the pre-pass would need the wind as well
+        if shadow_pass || self.blade_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..BLADE_VERTICES, 0..self.blade_count);
+    }
+}
@@ -0,0 +1,152 @@
+// Instanced grass blades with wind and a distance fade, see vegetation.rs
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    proj_inv: mat4x4<f32>,
+    view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+fn world_to_clip(world_position: vec3<f32>) -> vec4<f32> {
+    return camera.relative_view_proj * vec4<f32>(world_position - camera.render_origin.xyz, 1.0);
+}
+
+// Mirrors Light in shader.wgsl
+struct Light {
+    position: vec3<f32>,
+    direction: vec3<f32>,
+    color: vec4<f32>,
+    diffuse_strength: f32,
+    ambient_strength: f32,
+    specular_strength: f32,
+    point_clq: vec4<f32>,
+    cutoff_inner_outer_eps: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    shadow_resolution: f32,
+    cast_shadows: f32,
+    gobo: f32,
+    area_size: vec2<f32>,
+}
+
+struct Lights {
+    lights: array<Light, 2>
+}
+
+@group(1) @binding(0)
+var<uniform> lights: Lights;
+
+struct Vegetation {
+    // Direction on xz, strength in blade heights, frequency in radians per second
+    wind: vec4<f32>,
+    // Fade start and end, time in seconds
+    fade_time: vec4<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> vegetation: Vegetation;
+
+struct BladeInput {
+    @location(0) position: vec3<f32>,
+    @location(1) yaw: f32,
+    @location(2) height: f32,
+    @location(3) width: f32,
+    @location(4) phase: f32,
+    @location(5) shade: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    // 0 at the root, 1 at the tip
+    @location(2) along: f32,
+    @location(3) shade: f32,
+};
+
+// Mirrors vegetation::blade_fade
+fn blade_fade(distance: f32) -> f32 {
+    let start = vegetation.fade_time.x;
+    let end = vegetation.fade_time.y;
+    return clamp((end - distance) / max(end - start, 0.0001), 0.0, 1.0);
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, blade: BladeInput) -> VertexOutput {
+    var v_out: VertexOutput;
+    v_out.shade = blade.shade;
+    // Thinned out with distance, each blade disappears once the fade drops below its phase.
+    // Survivors grow out of the ground over the next stretch instead of popping in.
+    let fade = blade_fade(length(camera.view_pos.xz - blade.position.xz));
+    let grow = smoothstep(0.0, 0.15, fade - blade.phase / 6.2831853);
+    if grow <= 0.0 {
+        // Degenerate, clipped away
+        v_out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return v_out;
+    }
+    let height = blade.height * grow;
+
+    // Three segments, rows of two vertices up the blade and the last vertex alone at the tip
+    let along = min(f32(vertex_index / 2u) / 3.0, 1.0);
+    var side = f32(vertex_index % 2u) * 2.0 - 1.0;
+    if vertex_index == 6u {
+        side = 0.0;
+    }
+    let facing = vec3<f32>(sin(blade.yaw), 0.0, cos(blade.yaw));
+    let right = vec3<f32>(cos(blade.yaw), 0.0, -sin(blade.yaw));
+
+    // Gusts travel across the field along the wind, each blade a little out of step
+    let wind_direction = vec3<f32>(vegetation.wind.x, 0.0, vegetation.wind.y);
+    let travel = dot(blade.position.xz, vegetation.wind.xy) * 0.05;
+    let gust = sin(vegetation.fade_time.z * vegetation.wind.w - travel + blade.phase * 0.3);
+    let sway = vegetation.wind.z * (0.6 + 0.4 * gust);
+    // Rest lean plus sway, both growing with the square of the height so the root stays put
+    let bend = along * along * height;
+    let offset = facing * 0.25 * bend + wind_direction * sway * bend;
+    let world_position = blade.position
+        + right * side * blade.width * 0.5 * (1.0 - along)
+        + vec3<f32>(0.0, along * height, 0.0)
+        + offset;
+    // Derivative of the bend along the blade
+    let tangent = vec3<f32>(0.0, height, 0.0) + (facing * 0.25 + wind_direction * sway) * 2.0 * along * height;
+
+    v_out.world_position = world_position;
+    v_out.world_normal = normalize(cross(right, tangent));
+    v_out.along = along;
+    v_out.clip_position = world_to_clip(world_position);
+    return v_out;
+}
+
+fn cutoff(light: Light, dir: vec3<f32>) -> f32 {
+    if light.cutoff_inner_outer_eps[3] == 0.0 {
+        return 1.0;
+    }
+    let theta = dot(-light.direction, dir);
+    return clamp((theta - light.cutoff_inner_outer_eps[1]) / light.cutoff_inner_outer_eps[2], 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(f_in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let normal = select(-f_in.world_normal, f_in.world_normal, front_facing);
+    // Dark at the root where the blades shade each other, yellowing towards the tip
+    let root = vec3<f32>(0.03, 0.12, 0.02);
+    let tip = vec3<f32>(0.35, 0.6, 0.12);
+    let albedo = mix(root, tip, f_in.along) * f_in.shade;
+    var res = vec3<f32>(0.0);
+    for (var i: i32 = 0; i < 2; i++) {
+        let light = lights.lights[i];
+        if light.color.a == 0.0 || light.area_size.x > 0.0 {
+            continue;
+        }
+        let light_dir = normalize(light.position - f_in.world_position);
+        let facing_light = dot(normal, light_dir);
+        // Light through the back of a thin blade, dimmer than on its face
+        let diffuse = max(facing_light, 0.0) + max(-facing_light, 0.0) * 0.4;
+        let strength = light.ambient_strength + diffuse * light.diffuse_strength * cutoff(light, -light_dir);
+        res += light.color.rgb * strength * albedo;
+    }
+    return vec4<f32>(res, 1.0);
+}
@@ -0,0 +1,194 @@
+// Dropping a model file onto the window turns the demo into a previewer for it: the scene is
+// replaced by the model, the camera backs off until it fits and the lights move out or in with
+// its size.
+use crate::bounds::BoundingSphere;
+use crate::camera::{fit_distance, CameraView};
+use crate::events::AssetLoaded;
+use crate::history::History;
+use crate::light::LightUniform;
+use crate::resources::{self, Assets, ModelSource};
+use crate::scene_commands::SceneCommand;
+use crate::world_space::{InstanceTransform, Instances};
+use crate::State;
+use cgmath::{EuclideanSpace, Point3, Rad};
+#[cfg(target_arch = "wasm32")]
+use {
+    crate::scene_commands::SceneCommands,
+    std::collections::HashMap,
+    wasm_bindgen::{prelude::*, JsCast},
+};
+
+// Radius of a model the lights, clip planes and camera speed of the built-in scene suit
+const SCENE_RADIUS: f32 = 20.0;
+
+// Moves the light `factor` times as far from the origin and stretches its falloff to match, so
+// a scene `factor` times the size is lit the same
+pub fn scale_light(light: &LightUniform, factor: f32) -> LightUniform {
+    let [constant, linear, quadratic, attenuated] = light.point_clq;
+    LightUniform {
+        position: light.position.map(|axis| axis * factor),
+        point_clq: [
+            constant,
+            linear / factor,
+            quadratic / (factor * factor),
+            attenuated,
+        ],
+        ..*light
+    }
+}
+
+impl State {
+    // Replaces the scene with the model centered on the origin, the camera keeps its heading
+    pub fn view_model(&mut self, source: ModelSource) {
+        let name = source.file_name().to_string();
+        let model = resources::build_model(source, &self.device, &self.queue, 1., 0, None);
+        let model = match model {
+            Ok(model) => model,
+            Err(e) => {
+                log::error!("Failed to upload model: {:?}", e);
+                return;
+            }
+        };
+        let bounds = model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+            .filter(|bounds| bounds.radius > 0.)
+            .unwrap_or(BoundingSphere {
+                center: Point3::origin(),
+                radius: 1.,
+            });
+        self.clear_scene();
+        let transform = InstanceTransform {
+            position: -bounds.center.to_vec(),
+            ..Default::default()
+        };
+        let instances = Instances::new(vec![transform], &self.device);
+        let group = Some(self.add_model_group(model, instances));
+        self.set_scene_scale(bounds.radius / SCENE_RADIUS);
+
+        let projection = &self.camera.projection;
+        let distance = fit_distance(bounds.radius, projection.fovy(), projection.aspect());
+        // Level with the center, flying sinks the camera to the ground plane through it
+        let yaw = self.camera.view.yaw();
+        let position = Point3::new(-yaw.0.cos(), 0., -yaw.0.sin()) * distance;
+        self.set_camera_view(CameraView::new(position, yaw, Rad(0.)));
+        self.publish(AssetLoaded { name, group });
+    }
+
+    // Everything but the debug lines, for good: undo history goes too, its edits address the
+    // groups by index
+    fn clear_scene(&mut self) {
+        for index in (0..self.render_groups.len()).rev() {
+            if self.render_groups.id(index) != Some(self.debug_lines) {
+                self.render_groups.remove(index);
+            }
+        }
+        let meshes: Vec<_> = self
+            .world
+            .meshes()
+            .iter()
+            .filter(|(_, mesh)| mesh.group != self.debug_lines)
+            .map(|(entity, _)| entity)
+            .collect();
+        for entity in meshes {
+            self.world.despawn(entity);
+        }
+        self.history = History::default();
+        self.picked = None;
+    }
+
+    // Relative to the built-in scene, which is 1
+    fn set_scene_scale(&mut self, scale: f32) {
+        let factor = scale / self.scene_scale;
+        self.scene_scale = scale;
+        for light in &mut self.light_render_group.light_uniforms {
+            *light = scale_light(light, factor);
+        }
+        self.light_render_group.marker_scale *= factor;
+        let (znear, zfar) = self.camera.projection.depth_range();
+        self.camera
+            .projection
+            .set_depth_range(znear * factor, zfar * factor);
+        let speed = self.camera_controller.speed();
+        self.camera_controller.set_speed(speed * factor);
+    }
+
+    // Reads the file on a thread and views it once it is decoded, the materials and textures
+    // are looked up next to it
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open_model_file(&self, path: &std::path::Path) {
+        let path = path.to_string_lossy().into_owned();
+        if !resources::is_model_file(&path) {
+            log::warn!("{} is not an OBJ, glTF or GLB file", path);
+            return;
+        }
+        let commands = self.scene_commands();
+        std::thread::spawn(move || {
+            let (dir, file_name) = resources::split_asset_path(&path);
+            let assets = Assets::new(dir, &[]);
+            match pollster::block_on(resources::fetch_model(&assets, file_name)) {
+                Ok(source) => {
+                    commands.push(SceneCommand::ViewModel(source));
+                }
+                Err(e) => log::error!("Failed to load {}: {:?}", path, e),
+            }
+        });
+    }
+}
+
+// The model among the files with everything else it refers to, which the browser only hands
+// over when the files are picked or dropped together
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn read_files(files: web_sys::FileList) -> anyhow::Result<ModelSource> {
+    use anyhow::Context;
+    let mut dropped = HashMap::new();
+    let mut model = None;
+    for file in (0..files.length()).filter_map(|i| files.get(i)) {
+        let name = file.name();
+        let buffer = wasm_bindgen_futures::JsFuture::from(file.array_buffer())
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {:?}", name, e))?;
+        if model.is_none() && resources::is_model_file(&name) {
+            model = Some(name.clone());
+        }
+        dropped.insert(name, js_sys::Uint8Array::new(&buffer).to_vec());
+    }
+    let model = model.context("None of the files is an OBJ, glTF or GLB file")?;
+    let assets = Assets::new(".", &[]).with_dropped_files(dropped);
+    resources::fetch_model(&assets, &model).await
+}
+
+// winit doesn't report dropped files on the web, the canvas takes them itself
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn listen_for_drops(canvas: &web_sys::HtmlCanvasElement, commands: SceneCommands) {
+    // Without it the browser opens the file instead
+    let on_drag_over = Closure::wrap(Box::new(|e: web_sys::DragEvent| {
+        e.prevent_default();
+    }) as Box<dyn FnMut(_)>);
+    canvas
+        .add_event_listener_with_callback("dragover", on_drag_over.as_ref().unchecked_ref())
+        .ok();
+    on_drag_over.forget();
+    let on_drop = Closure::wrap(Box::new(move |e: web_sys::DragEvent| {
+        e.prevent_default();
+        let files = match e.data_transfer().and_then(|data| data.files()) {
+            Some(files) => files,
+            None => return,
+        };
+        let commands = commands.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match read_files(files).await {
+                Ok(source) => {
+                    commands.push(SceneCommand::ViewModel(source));
+                }
+                Err(e) => log::error!("Failed to load the dropped files: {:?}", e),
+            }
+        });
+    }) as Box<dyn FnMut(_)>);
+    canvas
+        .add_event_listener_with_callback("drop", on_drop.as_ref().unchecked_ref())
+        .ok();
+    on_drop.forget();
+}
@@ -0,0 +1,332 @@
+use crate::camera::Camera;
+use crate::light::LightRenderGroup;
+use crate::noise::{self, NoiseKind, NoiseSettings};
+use crate::shadow::ShadowPass;
+use crate::{stats, texture};
+use std::borrow::Cow;
+use wgpu::util::DeviceExt;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, Queue, RenderPipeline,
+    SurfaceConfiguration, TextureView,
+};
+
+const SCATTER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const STEPS: u32 = 32;
+const ANISOTROPY: f32 = 0.4;
+// Air further away than this stays clear, also where rays into the sky end
+const MAX_DISTANCE: f32 = 100.;
+// Texels across the fog density noise, a tile covers 48 world units in volumetric.wgsl
+const NOISE_SIZE: u32 = 128;
+const FOG_NOISE: NoiseSettings = NoiseSettings {
+    kind: NoiseKind::Fbm,
+    period: 4,
+    octaves: 4,
+    seed: 7,
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VolumetricUniform {
+    density: f32,
+    anisotropy: f32,
+    max_distance: f32,
+    steps: u32,
+}
+
+impl VolumetricUniform {
+    fn new(density: f32) -> Self {
+        Self {
+            density,
+            anisotropy: ANISOTROPY,
+            max_distance: MAX_DISTANCE,
+            steps: STEPS,
+        }
+    }
+}
+
+// Light shafts of the shadow casting spot and directional lights. Rays from the camera to the
+// resolved scene depth are marched through the shadow maps at half resolution, then the
+// scattered light is upsampled and added onto the scene. The air thickens and thins with a
+// tiling noise texture, so the shafts break up into patches.
+pub struct VolumetricPass {
+    march_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    buffer: Buffer,
+    march_bind_group: BindGroup,
+    composite_bind_group: BindGroup,
+    texture: texture::Texture,
+    noise: texture::Texture,
+}
+
+impl VolumetricPass {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        config: &SurfaceConfiguration,
+        depth: &texture::Texture,
+        camera: &Camera,
+        light_render_group: &LightRenderGroup,
+        shadow_pass: &ShadowPass,
+        density: f32,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Volumetric Bind Group Layout"),
+        });
+        let march_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Volumetric Pipeline Layout"),
+            bind_group_layouts: &[
+                &camera.camera_bind_group_layout,
+                &light_render_group.light_bind_group_layout,
+                &layout,
+                &shadow_pass.shadow_map_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let march_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Volumetric Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("volumetric.wgsl"))),
+        });
+        let march_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Volumetric Pipeline"),
+            layout: Some(&march_layout),
+            vertex: wgpu::VertexState {
+                module: &march_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &march_shader,
+                entry_point: "fs_main",
+                targets: &[SCATTER_FORMAT.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        // The mipmap blit, added onto the scene
+        let composite_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Volumetric Composite Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("blit.wgsl"))),
+        });
+        let composite_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Volumetric Composite Pipeline"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &composite_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &composite_shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Volumetric Buffer"),
+            contents: bytemuck::cast_slice(&[VolumetricUniform::new(density)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let texture = create_scatter_texture(device, config);
+        let noise = noise::generate_noise(device, queue, NOISE_SIZE, &FOG_NOISE, "fog_noise");
+        let march_bind_group = create_march_bind_group(device, &layout, depth, &buffer, &noise);
+        let composite_bind_group =
+            create_composite_bind_group(device, &composite_pipeline, &texture);
+        Self {
+            march_pipeline,
+            composite_pipeline,
+            layout,
+            buffer,
+            march_bind_group,
+            composite_bind_group,
+            texture,
+            noise,
+        }
+    }
+
+    pub fn set_density(&self, queue: &Queue, density: f32) {
+        let uniform = VolumetricUniform::new(density);
+        stats::write_buffer(queue, &self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // `depth` is the recreated resolved depth
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        config: &SurfaceConfiguration,
+        depth: &texture::Texture,
+    ) {
+        self.texture = create_scatter_texture(device, config);
+        self.march_bind_group =
+            create_march_bind_group(device, &self.layout, depth, &self.buffer, &self.noise);
+        self.composite_bind_group =
+            create_composite_bind_group(device, &self.composite_pipeline, &self.texture);
+    }
+
+    // Call after the depth resolve, adds the shafts onto `target`
+    pub fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        target: &TextureView,
+        camera: &Camera,
+        light_render_group: &LightRenderGroup,
+        shadow_pass: &ShadowPass,
+    ) {
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Volumetric Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &self.texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.march_pipeline);
+            render_pass.set_bind_group(0, &camera.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &light_render_group.light_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.march_bind_group, &[]);
+            render_pass.set_bind_group(3, &shadow_pass.shadow_map_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Volumetric Composite Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_scatter_texture(device: &Device, config: &SurfaceConfiguration) -> texture::Texture {
+    texture::Texture::create_render_target(
+        device,
+        (config.width / 2).max(1),
+        (config.height / 2).max(1),
+        SCATTER_FORMAT,
+        "volumetric_scatter",
+    )
+}
+
+fn create_march_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    depth: &texture::Texture,
+    buffer: &Buffer,
+    noise: &texture::Texture,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&noise.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&noise.sampler),
+            },
+        ],
+        label: Some("Volumetric Bind Group"),
+    })
+}
+
+fn create_composite_bind_group(
+    device: &Device,
+    pipeline: &RenderPipeline,
+    texture: &texture::Texture,
+) -> BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("Volumetric Composite Bind Group"),
+    })
+}
@@ -0,0 +1,183 @@
+// Light scattered towards the camera by the air in front of the scene, see volumetric.rs
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    proj_inv: mat4x4<f32>,
+    view: mat4x4<f32>,
+    relative_view_proj: mat4x4<f32>,
+    render_origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Mirrors Light in shader.wgsl
+struct Light {
+    position: vec3<f32>,
+    direction: vec3<f32>,
+    color: vec4<f32>,
+    diffuse_strength: f32,
+    ambient_strength: f32,
+    specular_strength: f32,
+    point_clq: vec4<f32>,
+    cutoff_inner_outer_eps: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    shadow_resolution: f32,
+    cast_shadows: f32,
+    gobo: f32,
+    area_size: vec2<f32>,
+}
+
+struct Lights {
+    lights: array<Light, 2>
+}
+
+@group(1) @binding(0)
+var<uniform> lights: Lights;
+
+struct VolumetricUniform {
+    density: f32,
+    // Henyey-Greenstein g, towards 1 the shafts glow when looking into the light
+    anisotropy: f32,
+    max_distance: f32,
+    steps: u32,
+};
+
+@group(2) @binding(0)
+var t_depth: texture_depth_2d;
+@group(2) @binding(1)
+var<uniform> volumetric: VolumetricUniform;
+// Fbm from noise.rs, scales the density so the air is patchy
+@group(2) @binding(2)
+var t_noise: texture_2d<f32>;
+@group(2) @binding(3)
+var s_noise: sampler;
+
+@group(3) @binding(0)
+var t_shadow: texture_depth_2d_array;
+@group(3) @binding(1)
+var sampler_shadow: sampler_comparison;
+
+struct ShadowSettings {
+    filter: u32,
+    texel_size: f32,
+    light_size: f32,
+    near: f32,
+    far: f32,
+    resolution: f32,
+};
+@group(3) @binding(2)
+var<uniform> shadow_settings: ShadowSettings;
+@group(3) @binding(4)
+var sampler_moments: sampler;
+@group(3) @binding(5)
+var t_gobo: texture_2d_array<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    var v_out: VertexOutput;
+    v_out.position = vec4<f32>(x, y, 0.0, 1.0);
+    v_out.tex_coords = vec2<f32>((x + 1.0) / 2.0, (1.0 - y) / 2.0);
+    return v_out;
+}
+
+fn attenuation(light: Light, world_position: vec3<f32>) -> vec3<f32> {
+    var light_color = light.color.rgb;
+    if light.point_clq[3] != 0.0 {
+        let dis = length(light.position - world_position);
+        light_color = light_color / (light.point_clq[0] + light.point_clq[1] * dis + light.point_clq[2] * dis * dis);
+    }
+    return light_color;
+}
+
+fn cutoff(light: Light, dir: vec3<f32>) -> f32 {
+    if light.cutoff_inner_outer_eps[3] == 0.0 {
+        return 1.0;
+    }
+    let theta = dot(-light.direction, dir);
+    return clamp((theta - light.cutoff_inner_outer_eps[1]) / light.cutoff_inner_outer_eps[2], 0.0, 1.0);
+}
+
+// One hard comparison per step, the march averages the edges out. Outside the frustum of the
+// light is dark.
+fn visibility(light_id: i32, world_position: vec3<f32>) -> vec3<f32> {
+    let light = lights.lights[light_id];
+    let coords = light.view_proj * vec4<f32>(world_position, 1.0);
+    if coords.w <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let uv = coords.xy / coords.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        return vec3<f32>(0.0);
+    }
+    // Same corner of the array as fetch_shadow in shader.wgsl
+    let resolution = select(clamp(light.shadow_resolution, 256.0, 8192.0), shadow_settings.resolution, light.shadow_resolution <= 0.0);
+    let scale = min(resolution * shadow_settings.texel_size, 1.0);
+    let lit = textureSampleCompareLevel(t_shadow, sampler_shadow, uv * scale, light_id, coords.z / coords.w);
+    var gobo = vec3<f32>(1.0);
+    if light.gobo > 0.0 {
+        gobo = textureSampleLevel(t_gobo, sampler_moments, uv, light_id, 0.0).rgb;
+    }
+    return lit * gobo;
+}
+
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * 3.14159265 * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+// Per pixel offset of the first step, turns banding into noise the upsampling blurs
+fn dither(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+@fragment
+fn fs_main(v_in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let texel = clamp(vec2<i32>(v_in.tex_coords * size), vec2<i32>(0), vec2<i32>(size) - vec2<i32>(1));
+    let depth = textureLoad(t_depth, texel, 0);
+    let ndc = vec4<f32>(v_in.tex_coords.x * 2.0 - 1.0, 1.0 - v_in.tex_coords.y * 2.0, depth, 1.0);
+    let view_space = camera.proj_inv * ndc;
+    // The view matrix is a rotation and translation, the transpose of its rotation inverts it
+    let rotation = mat3x3<f32>(camera.view[0].xyz, camera.view[1].xyz, camera.view[2].xyz);
+    let offset = transpose(rotation) * (view_space.xyz / view_space.w);
+    let march_length = min(length(offset), volumetric.max_distance);
+    let ray_dir = normalize(offset);
+    let origin = camera.view_pos.xyz;
+
+    let steps = max(volumetric.steps, 1u);
+    let step_length = march_length / f32(steps);
+    let start = dither(v_in.position.xy);
+    var scattered = vec3<f32>(0.0);
+    var optical_depth = 0.0;
+    for (var i = 0u; i < steps; i++) {
+        let t = (f32(i) + start) * step_length;
+        let position = origin + ray_dir * t;
+        // A tile of the noise covers 48 world units, drifting sideways with height. Between a
+        // quarter and one and three quarters of the set density, about the same on average.
+        let uv = (position.xz + vec2<f32>(position.y * 0.5)) / 48.0;
+        let density = volumetric.density * (0.25 + 1.5 * textureSampleLevel(t_noise, s_noise, uv, 0.0).r);
+        // Light lost on the way back to the camera
+        let transmittance = exp(-optical_depth);
+        optical_depth += density * step_length;
+        for (var light_id = 0; light_id < 2; light_id++) {
+            let light = lights.lights[light_id];
+            // Without a shadow map there is nothing to carve the shafts out of
+            if light.cast_shadows <= 0.0 || light.area_size.x > 0.0 {
+                continue;
+            }
+            let light_dir = normalize(position - light.position);
+            let phase = henyey_greenstein(dot(light_dir, -ray_dir), volumetric.anisotropy);
+            let light_color = attenuation(light, position) * cutoff(light, light_dir);
+            scattered += light_color * visibility(light_id, position) * phase * transmittance * density;
+        }
+    }
+    return vec4<f32>(scattered * step_length, 1.0);
+}
@@ -0,0 +1,38 @@
+// Separable Gaussian blur of one layer of the variance shadow maps, fs_horizontal into the
+// scratch texture and fs_vertical back into the layer
+@group(0)
+@binding(0)
+var t_source: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+// Binomial weights of 7 taps
+var<private> WEIGHTS: array<f32, 7> = array<f32, 7>(
+    0.015625, 0.09375, 0.234375, 0.3125, 0.234375, 0.09375, 0.015625
+);
+
+fn blur(position: vec4<f32>, step: vec2<i32>) -> vec2<f32> {
+    let size = vec2<i32>(textureDimensions(t_source));
+    let center = vec2<i32>(position.xy);
+    var moments = vec2<f32>(0.0);
+    for (var i = 0; i < 7; i++) {
+        let texel = clamp(center + step * (i - 3), vec2<i32>(0), size - vec2<i32>(1));
+        moments += textureLoad(t_source, texel, 0).xy * WEIGHTS[i];
+    }
+    return moments;
+}
+
+@fragment
+fn fs_horizontal(@builtin(position) position: vec4<f32>) -> @location(0) vec2<f32> {
+    return blur(position, vec2<i32>(1, 0));
+}
+
+@fragment
+fn fs_vertical(@builtin(position) position: vec4<f32>) -> @location(0) vec2<f32> {
+    return blur(position, vec2<i32>(0, 1));
+}
@@ -0,0 +1,91 @@
+// Functions a page hosting the wasm build calls to drive the renderer, exported next to run().
+// The state lives in the event loop, so they push SceneCommands applied at the next update and
+// read what the loop last reported.
+use crate::camera::CameraView;
+use crate::resources::{self, Assets};
+use crate::scene_commands::{SceneCommand, SceneCommands};
+use crate::viewer;
+use crate::world_space::InstanceTransform;
+use cgmath::Deg;
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static COMMANDS: RefCell<Option<SceneCommands>> = RefCell::new(None);
+    static FPS: Cell<f32> = Cell::new(0.);
+}
+
+// Called by run() once the state exists, commands sent before are dropped
+pub(crate) fn connect(commands: SceneCommands) {
+    COMMANDS.with(|cell| *cell.borrow_mut() = Some(commands));
+}
+
+pub(crate) fn report_fps(fps: f32) {
+    FPS.with(|cell| cell.set(fps));
+}
+
+// False while the renderer is still starting
+fn push(command: SceneCommand) -> bool {
+    COMMANDS.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .map_or(false, |commands| commands.push(command))
+    })
+}
+
+// Moves the main camera to `x, y, z` looking along `yaw` and `pitch` in degrees
+#[wasm_bindgen]
+pub fn set_camera_pose(x: f32, y: f32, z: f32, yaw: f32, pitch: f32) -> bool {
+    push(SceneCommand::SetCamera(CameraView::new(
+        (x, y, z),
+        Deg(yaw),
+        Deg(pitch),
+    )))
+}
+
+// Light 0 is the sun, 1 the flashlight, the others are the lamps of the scene
+#[wasm_bindgen]
+pub fn set_light_enabled(index: usize, enabled: bool) -> bool {
+    push(SceneCommand::SetLightEnabled { index, enabled })
+}
+
+// Fetches an OBJ or glTF file with the files it refers to, which are looked up next to it, and
+// adds it at the origin. The promise rejects with the reason when loading fails.
+#[wasm_bindgen]
+pub async fn load_model(url: String, scale: f32) -> Result<(), JsValue> {
+    let (dir, file_name) = resources::split_asset_path(&url);
+    let assets = Assets::new(dir, &[]);
+    let source = resources::fetch_model(&assets, file_name)
+        .await
+        .map_err(|e| JsValue::from_str(&format!("{:#}", e)))?;
+    let command = SceneCommand::AddModel {
+        source,
+        scale,
+        transform: InstanceTransform::default(),
+    };
+    if push(command) {
+        Ok(())
+    } else {
+        Err(JsValue::from_str("The renderer has not started"))
+    }
+}
+
+// Replaces the scene with the model among the files, for a file input of the page:
+// `open_files(input.files)`. The other files are the materials and textures it refers to.
+#[wasm_bindgen]
+pub async fn open_files(files: web_sys::FileList) -> Result<(), JsValue> {
+    let source = viewer::read_files(files)
+        .await
+        .map_err(|e| JsValue::from_str(&format!("{:#}", e)))?;
+    if push(SceneCommand::ViewModel(source)) {
+        Ok(())
+    } else {
+        Err(JsValue::from_str("The renderer has not started"))
+    }
+}
+
+// Frames per second averaged over the last frames, 0 before the first
+#[wasm_bindgen]
+pub fn fps() -> f32 {
+    FPS.with(Cell::get)
+}
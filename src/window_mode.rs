@@ -0,0 +1,60 @@
+use winit::window::{Fullscreen, Window};
+
+pub const TITLE: &str = "learn-graphics";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    // Fullscreen window on the current monitor, keeps the desktop video mode
+    Borderless,
+    // Takes over the monitor with its largest video mode, native only
+    Exclusive,
+}
+
+impl WindowMode {
+    pub fn next(self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless if cfg!(not(target_arch = "wasm32")) => WindowMode::Exclusive,
+            _ => WindowMode::Windowed,
+        }
+    }
+
+    // Returns the mode that was actually applied, Exclusive falls back to Borderless
+    // without a monitor or video mode
+    pub fn apply(self, window: &Window) -> Self {
+        let (mode, fullscreen) = match self {
+            WindowMode::Windowed => (self, None),
+            WindowMode::Borderless => {
+                (self, Some(Fullscreen::Borderless(window.current_monitor())))
+            }
+            WindowMode::Exclusive => {
+                let video_mode = window.current_monitor().and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate())
+                    })
+                });
+                match video_mode {
+                    Some(video_mode) => (self, Some(Fullscreen::Exclusive(video_mode))),
+                    None => (
+                        WindowMode::Borderless,
+                        Some(Fullscreen::Borderless(window.current_monitor())),
+                    ),
+                }
+            }
+        };
+        window.set_fullscreen(fullscreen);
+        log::warn!("Window mode: {:?}", mode);
+        mode
+    }
+}
+
+// Appends a status such as the frame rate to the window title
+pub fn set_title_status(window: &Window, status: &str) {
+    if status.is_empty() {
+        window.set_title(TITLE);
+    } else {
+        window.set_title(&format!("{} - {}", TITLE, status));
+    }
+}
@@ -1,10 +1,42 @@
+use crate::bounds::BoundingSphere;
+use crate::stats;
+use cgmath::{ElementWise, InnerSpace, One, Quaternion, Rad, Rotation3, Vector3, Zero};
 use std::ops::Range;
 use wgpu::util::DeviceExt;
-use wgpu::{Buffer, Device, VertexAttribute};
+use wgpu::{Buffer, Device, Queue, VertexAttribute};
 
+#[derive(Copy, Clone)]
 pub struct InstanceTransform {
     pub(crate) position: cgmath::Vector3<f32>,
     pub(crate) rotation: cgmath::Quaternion<f32>,
+    // Per axis, applied before the rotation
+    pub(crate) scale: cgmath::Vector3<f32>,
+}
+
+impl Default for InstanceTransform {
+    fn default() -> Self {
+        Self::new(Vector3::zero(), Quaternion::one())
+    }
+}
+
+// Per-instance look, so varied copies of one mesh still draw in one call
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InstanceAttributes {
+    // Multiplied with the diffuse texture
+    pub color: [f32; 4],
+    // Texture coordinates are scaled, then offset
+    pub uv_offset: [f32; 2],
+    pub uv_scale: [f32; 2],
+}
+
+impl Default for InstanceAttributes {
+    fn default() -> Self {
+        Self {
+            color: [1.; 4],
+            uv_offset: [0.; 2],
+            uv_scale: [1.; 2],
+        }
+    }
 }
 
 #[repr(C)]
@@ -12,22 +44,100 @@ pub struct InstanceTransform {
 struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    color: [f32; 4],
+    // offset in xy, scale in zw
+    uv: [f32; 4],
 }
 
+// Bytes per instance in Instances::instance_buffer
+pub(crate) const INSTANCE_SIZE: usize = std::mem::size_of::<InstanceRaw>();
+
 impl InstanceTransform {
-    fn to_raw(&self) -> InstanceRaw {
+    pub fn new(position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+        Self {
+            position,
+            rotation,
+            scale: Vector3::new(1., 1., 1.),
+        }
+    }
+
+    pub fn with_scale(self, scale: Vector3<f32>) -> Self {
+        Self { scale, ..self }
+    }
+
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        self.rotation
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // Inverse transpose of the upper 3x3, the rotation with each axis divided by its scale.
+    // Not normalized, the shaders normalize the result.
+    pub fn normal_matrix(&self) -> cgmath::Matrix3<f32> {
+        let rotation = cgmath::Matrix3::from(self.rotation);
+        cgmath::Matrix3::from_cols(
+            rotation.x / self.scale.x,
+            rotation.y / self.scale.y,
+            rotation.z / self.scale.z,
+        )
+    }
+
+    pub fn transform_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+        self.position + self.rotation * self.scale.mul_element_wise(point)
+    }
+
+    pub fn inverse_transform_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+        (self.rotation.conjugate() * (point - self.position)).div_element_wise(self.scale)
+    }
+
+    // `local` placed relative to this transform, as a parent places its child.
+    // Scales multiply per axis, which ignores the shear of a rotated child under a
+    // non-uniformly scaled parent.
+    pub fn compose(&self, local: &InstanceTransform) -> InstanceTransform {
+        InstanceTransform {
+            position: self.transform_point(local.position),
+            rotation: self.rotation * local.rotation,
+            scale: self.scale.mul_element_wise(local.scale),
+        }
+    }
+
+    // The local transform that composes with this one to `world`
+    pub fn relative(&self, world: &InstanceTransform) -> InstanceTransform {
+        let inverse = self.rotation.conjugate();
+        InstanceTransform {
+            position: (inverse * (world.position - self.position)).div_element_wise(self.scale),
+            rotation: inverse * world.rotation,
+            scale: world.scale.div_element_wise(self.scale),
+        }
+    }
+
+    fn to_raw(&self, attributes: &InstanceAttributes) -> InstanceRaw {
+        let [offset_u, offset_v] = attributes.uv_offset;
+        let [scale_u, scale_v] = attributes.uv_scale;
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
+            model: self.model_matrix().into(),
+            normal: self.normal_matrix().into(),
+            color: attributes.color,
+            uv: [offset_u, offset_v, scale_u, scale_v],
         }
     }
 }
 
 pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
     use std::mem;
-    static ATTRIBUTES: &[VertexAttribute; 7] = &wgpu::vertex_attr_array![
+    static ATTRIBUTES: &[VertexAttribute; 9] = &wgpu::vertex_attr_array![
         5 => Float32x4,
         6 => Float32x4,
         7 => Float32x4,
@@ -35,6 +145,8 @@ pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         9 => Float32x3,
         10 => Float32x3,
         11 => Float32x3,
+        12 => Float32x4,
+        13 => Float32x4,
         ];
     wgpu::VertexBufferLayout {
         array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
@@ -48,32 +160,261 @@ pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
 
 pub struct Instances {
     pub instance_transforms: Vec<InstanceTransform>,
+    // Instances past the end use the defaults
+    pub instance_attributes: Vec<InstanceAttributes>,
     // instances_raw: Vec<InstanceRaw>,
     pub instance_buffer: wgpu::Buffer,
+    // Of despawned entities, instances past the end are shown
+    hidden: Vec<bool>,
 }
 
 impl Instances {
-    fn get_raw_and_buffer(instance_transforms: &Vec<InstanceTransform>, device: &Device) -> Buffer {
-        let instances_raw: Vec<InstanceRaw> = instance_transforms
+    // A hidden instance gets a zero model matrix, every vertex lands on one point and nothing
+    // is rasterized in any pass
+    fn get_raw(
+        instance_transforms: &[InstanceTransform],
+        instance_attributes: &[InstanceAttributes],
+        hidden: &[bool],
+    ) -> Vec<InstanceRaw> {
+        instance_transforms
             .iter()
-            .map(InstanceTransform::to_raw)
-            .collect();
+            .enumerate()
+            .map(|(i, transform)| match hidden.get(i) {
+                Some(true) => bytemuck::Zeroable::zeroed(),
+                _ => transform.to_raw(&instance_attributes.get(i).copied().unwrap_or_default()),
+            })
+            .collect()
+    }
+    fn get_raw_and_buffer(
+        instance_transforms: &[InstanceTransform],
+        instance_attributes: &[InstanceAttributes],
+        hidden: &[bool],
+        device: &Device,
+        usage: wgpu::BufferUsages,
+    ) -> Buffer {
+        let instances_raw = Self::get_raw(instance_transforms, instance_attributes, hidden);
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
             contents: bytemuck::cast_slice(&instances_raw),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | usage,
         });
         instance_buffer
     }
-    pub(crate) fn new(instance_transforms: Vec<InstanceTransform>, device: &Device) -> Self {
-        let instance_buffer = Self::get_raw_and_buffer(&instance_transforms, device);
+    pub fn new(instance_transforms: Vec<InstanceTransform>, device: &Device) -> Self {
+        Self::with_attributes(instance_transforms, vec![], device)
+    }
+
+    // Attributes pair up with the transforms
+    pub fn with_attributes(
+        instance_transforms: Vec<InstanceTransform>,
+        instance_attributes: Vec<InstanceAttributes>,
+        device: &Device,
+    ) -> Self {
+        let instance_buffer = Self::get_raw_and_buffer(
+            &instance_transforms,
+            &instance_attributes,
+            &[],
+            device,
+            wgpu::BufferUsages::empty(),
+        );
         Self {
             instance_transforms,
+            instance_attributes,
             instance_buffer,
+            hidden: vec![],
+        }
+    }
+
+    // Recreates the buffer so compute shaders can read it, WebGL can't bind storage buffers
+    pub(crate) fn enable_storage(&mut self, device: &Device) {
+        self.instance_buffer = Self::get_raw_and_buffer(
+            &self.instance_transforms,
+            &self.instance_attributes,
+            &self.hidden,
+            device,
+            wgpu::BufferUsages::STORAGE,
+        );
+    }
+
+    // Upload edited transforms and attributes, the instance count must not grow
+    pub(crate) fn update_buffer(&self, queue: &Queue) {
+        let instances_raw = Self::get_raw(
+            &self.instance_transforms,
+            &self.instance_attributes,
+            &self.hidden,
+        );
+        stats::write_buffer(
+            queue,
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&instances_raw),
+        );
+    }
+
+    pub fn is_hidden(&self, instance: usize) -> bool {
+        self.hidden.get(instance).copied().unwrap_or(false)
+    }
+
+    // Uploaded right away, the transform is kept for when it is shown again
+    pub(crate) fn set_hidden(&mut self, instance: usize, hidden: bool, queue: &Queue) {
+        if instance >= self.instance_transforms.len() || self.is_hidden(instance) == hidden {
+            return;
         }
+        if self.hidden.len() <= instance {
+            self.hidden.resize(instance + 1, false);
+        }
+        self.hidden[instance] = hidden;
+        self.update_buffer(queue);
+    }
+
+    // Transforms of the instances that are drawn
+    fn shown(&self) -> impl Iterator<Item = &InstanceTransform> {
+        let transforms = self.instance_transforms.iter().enumerate();
+        transforms
+            .filter(move |(i, _)| !self.is_hidden(*i))
+            .map(|(_, transform)| transform)
     }
 
     pub fn get_instance_range(&self) -> Range<u32> {
         0..self.instance_transforms.len() as u32
     }
+
+    // World space bounds of a mesh with bounds `local` drawn at every instance
+    pub fn bounding_sphere(&self, local: &BoundingSphere) -> Option<BoundingSphere> {
+        self.shown()
+            .map(|t| local.transformed(t.position, t.rotation, t.scale))
+            .reduce(|acc, next| acc.union(&next))
+    }
+
+    pub fn instance_bounding_sphere(
+        &self,
+        instance: usize,
+        local: &BoundingSphere,
+    ) -> Option<BoundingSphere> {
+        if self.is_hidden(instance) {
+            return None;
+        }
+        let t = self.instance_transforms.get(instance)?;
+        Some(local.transformed(t.position, t.rotation, t.scale))
+    }
+
+    // Distance from `eye` to the nearest instance origin, None without instances
+    pub fn closest_distance(&self, eye: Vector3<f32>) -> Option<f32> {
+        self.shown()
+            .map(|t| (t.position - eye).magnitude())
+            .reduce(f32::min)
+    }
+}
+
+// A grid of `counts` instances per axis around `center`, `spacing` apart
+pub fn grid(counts: [usize; 3], spacing: f32, center: Vector3<f32>) -> Vec<InstanceTransform> {
+    let [nx, ny, nz] = counts;
+    let offset = |n: usize| (n.max(1) - 1) as f32 * spacing / 2.;
+    let corner = center - Vector3::new(offset(nx), offset(ny), offset(nz));
+    let mut transforms = Vec::with_capacity(nx * ny * nz);
+    for y in 0..ny {
+        for z in 0..nz {
+            for x in 0..nx {
+                transforms.push(InstanceTransform::new(
+                    corner + Vector3::new(x as f32, y as f32, z as f32) * spacing,
+                    Quaternion::one(),
+                ));
+            }
+        }
+    }
+    transforms
+}
+
+// Random positions on the floor plane at `height`, within `half_extent` of `center` on x and z,
+// each turned about y. The same seed gives the same layout.
+pub fn scatter(
+    count: usize,
+    center: Vector3<f32>,
+    half_extent: f32,
+    height: f32,
+    seed: u64,
+) -> Vec<InstanceTransform> {
+    let mut rng = Rng::new(seed);
+    (0..count)
+        .map(|_| {
+            InstanceTransform::new(
+                Vector3::new(
+                    center.x + rng.signed() * half_extent,
+                    height,
+                    center.z + rng.signed() * half_extent,
+                ),
+                Quaternion::from_axis_angle(
+                    Vector3::unit_y(),
+                    Rad(rng.unit() * std::f32::consts::TAU),
+                ),
+            )
+        })
+        .collect()
+}
+
+// Turns every instance by up to `max_angle` about a random axis
+pub fn jitter_rotations(transforms: &mut [InstanceTransform], max_angle: Rad<f32>, seed: u64) {
+    let mut rng = Rng::new(seed);
+    for transform in transforms {
+        let axis = rng.direction();
+        let angle = max_angle * rng.signed();
+        transform.rotation = Quaternion::from_axis_angle(axis, angle) * transform.rotation;
+    }
+}
+
+// Multiplies every scale by a random factor in `min..max`, the same on all axes
+pub fn jitter_scales(transforms: &mut [InstanceTransform], min: f32, max: f32, seed: u64) {
+    let mut rng = Rng::new(seed);
+    for transform in transforms {
+        transform.scale *= min + (max - min) * rng.unit();
+    }
+}
+
+// Random colors for instanced copies, the alpha stays 1
+pub fn random_colors(count: usize, seed: u64) -> Vec<InstanceAttributes> {
+    let mut rng = Rng::new(seed);
+    (0..count)
+        .map(|_| InstanceAttributes {
+            color: [rng.unit(), rng.unit(), rng.unit(), 1.],
+            ..Default::default()
+        })
+        .collect()
+}
+
+// SplitMix64, enough for placing instances without pulling in a crate
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // In [0, 1)
+    pub(crate) fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // In [-1, 1)
+    pub(crate) fn signed(&mut self) -> f32 {
+        self.unit() * 2. - 1.
+    }
+
+    // Uniform on the unit sphere
+    fn direction(&mut self) -> Vector3<f32> {
+        loop {
+            let v = Vector3::new(self.signed(), self.signed(), self.signed());
+            let length2 = v.magnitude2();
+            if length2 > 1e-4 && length2 <= 1. {
+                return v / length2.sqrt();
+            }
+        }
+    }
 }
@@ -0,0 +1,58 @@
+// Where asset names are looked for, without touching the file system
+use learn_graphics::{split_asset_path, Assets};
+
+#[test]
+fn overrides_come_before_the_root() {
+    let assets = Assets::new("obj/", &["mods".to_string(), "patches/".to_string()]);
+    assert_eq!(
+        assets.candidates("girl.obj"),
+        ["mods/girl.obj", "patches/girl.obj", "obj/girl.obj"]
+    );
+}
+
+#[test]
+fn an_empty_root_is_the_build_directory() {
+    let assets = Assets::new("", &[]);
+    let candidates = assets.candidates("albedo.png");
+    assert_eq!(candidates.len(), 1);
+    assert!(candidates[0].ends_with("obj/albedo.png"));
+}
+
+#[test]
+fn the_manifest_renames_files() {
+    let mut assets = Assets::new("assets", &[]);
+    assets
+        .set_manifest(
+            r#"
+            [files]
+            "albedo.png" = "textures/floor.png"
+            "#,
+        )
+        .unwrap();
+    assert_eq!(
+        assets.candidates("albedo.png"),
+        ["assets/textures/floor.png"]
+    );
+    assert_eq!(assets.candidates("asuka.png"), ["assets/asuka.png"]);
+}
+
+#[test]
+fn invalid_manifests_are_errors() {
+    let mut assets = Assets::new("assets", &[]);
+    assert!(assets.set_manifest("[files]\nalbedo = 1").is_err());
+    assert!(assets.set_manifest("unknown = true").is_err());
+}
+
+#[test]
+fn urls_split_into_a_root_and_a_file() {
+    assert_eq!(
+        split_asset_path("models/duck/duck.obj"),
+        ("models/duck", "duck.obj")
+    );
+    assert_eq!(
+        split_asset_path("https://example.com/duck.obj"),
+        ("https://example.com", "duck.obj")
+    );
+    assert_eq!(split_asset_path("/duck.obj"), ("/", "duck.obj"));
+    assert_eq!(split_asset_path("duck.obj"), (".", "duck.obj"));
+}
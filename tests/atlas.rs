@@ -0,0 +1,44 @@
+use learn_graphics::prelude::*;
+
+fn overlap(a: [u32; 4], b: [u32; 4]) -> bool {
+    a[0] < b[0] + b[2] && b[0] < a[0] + a[2] && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
+}
+
+#[test]
+fn packed_images_fit_and_keep_apart() {
+    let sizes = [(64, 64), (32, 16), (128, 8), (16, 48), (64, 64), (8, 8)];
+    let layout = AtlasLayout::pack(&sizes);
+    assert_eq!(layout.rects.len(), sizes.len());
+    for (i, (rect, size)) in layout.rects.iter().zip(sizes).enumerate() {
+        assert_eq!((rect[2], rect[3]), size);
+        assert!(rect[0] + rect[2] <= layout.width && rect[1] + rect[3] <= layout.height);
+        for other in &layout.rects[i + 1..] {
+            assert!(!overlap(*rect, *other), "{:?} overlaps {:?}", rect, other);
+        }
+    }
+}
+
+#[test]
+fn attributes_map_the_unit_square_onto_the_image() {
+    let layout = AtlasLayout::pack(&[(32, 32), (16, 64)]);
+    let [x, y, width, height] = layout.rects[0];
+    let attributes = layout.attributes(0);
+    // The vertex shader scales then offsets, the diffuse lookup flips v
+    let sample = |u: f32, v: f32| {
+        let tc = [
+            u * attributes.uv_scale[0] + attributes.uv_offset[0],
+            v * attributes.uv_scale[1] + attributes.uv_offset[1],
+        ];
+        [
+            tc[0] * layout.width as f32,
+            (1. - tc[1]) * layout.height as f32,
+        ]
+    };
+    let close = |a: [f32; 2], b: [f32; 2]| (a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3;
+    assert!(close(sample(0., 1.), [x as f32, y as f32]));
+    assert!(close(
+        sample(1., 0.),
+        [(x + width) as f32, (y + height) as f32]
+    ));
+    assert_eq!(attributes.color, [1.; 4]);
+}
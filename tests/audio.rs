@@ -0,0 +1,43 @@
+// Distance falloff and panning of positional sounds
+#![cfg(feature = "audio")]
+mod common;
+use cgmath::{Deg, Point3};
+use common::close;
+use learn_graphics::prelude::*;
+use learn_graphics::{distance_gain, listener_direction};
+
+#[test]
+fn sounds_fade_out_between_the_distances() {
+    let settings = SoundSettings {
+        min_distance: 10.,
+        max_distance: 110.,
+        ..Default::default()
+    };
+    assert_eq!(distance_gain(0., &settings), 1.);
+    assert_eq!(distance_gain(10., &settings), 1.);
+    // Half the inverse distance gain, half faded
+    assert!(close(distance_gain(60., &settings), 10. / 60. * 0.5));
+    assert_eq!(distance_gain(110., &settings), 0.);
+    assert_eq!(distance_gain(500., &settings), 0.);
+    let mut last = 1.;
+    for distance in (10..110).step_by(5) {
+        let gain = distance_gain(distance as f32, &settings);
+        assert!(gain <= last);
+        last = gain;
+    }
+}
+
+#[test]
+fn sounds_are_panned_by_where_they_are_in_view() {
+    // Looking down +x
+    let view = CameraView::new((0., 0., 0.), Deg(0.), Deg(0.)).calc_matrix();
+    let [x, y, z] = listener_direction(&view, Point3::new(50., 0., 0.));
+    assert!(close(x, 0.) && close(y, 0.) && close(z, -1.));
+    // +z is to the right
+    let [x, _, z] = listener_direction(&view, Point3::new(0., 0., 3.));
+    assert!(close(x, 1.) && close(z, 0.));
+    let [_, y, _] = listener_direction(&view, Point3::new(0., 2., 0.));
+    assert!(close(y, 1.));
+    let at_the_camera = listener_direction(&view, Point3::new(0., 0., 0.));
+    assert_eq!(at_the_camera, [0., 0., -1.]);
+}
@@ -0,0 +1,45 @@
+// Backend fallback and what is picked from the adapter's capabilities
+use learn_graphics::backend_order;
+use learn_graphics::prelude::{Backend, RendererCapabilities};
+
+fn capabilities(sample_counts: Vec<u32>) -> RendererCapabilities {
+    RendererCapabilities {
+        backend: wgpu::Backend::Gl,
+        adapter_name: "test".to_string(),
+        max_texture_size: 2048,
+        features: wgpu::Features::empty(),
+        sample_counts,
+        compute: false,
+        vertex_storage: false,
+        cube_arrays: false,
+        anisotropic_filtering: false,
+        webgpu_compliant: false,
+    }
+}
+
+#[test]
+fn the_web_tries_webgpu_before_webgl() {
+    assert_eq!(
+        backend_order(Backend::All, true),
+        [wgpu::Backends::BROWSER_WEBGPU, wgpu::Backends::GL]
+    );
+    assert_eq!(backend_order(Backend::Gl, true), [wgpu::Backends::GL]);
+}
+
+#[test]
+fn native_backends_are_tried_once() {
+    assert_eq!(backend_order(Backend::All, false), [wgpu::Backends::all()]);
+    assert_eq!(
+        backend_order(Backend::Vulkan, false),
+        [wgpu::Backends::VULKAN]
+    );
+}
+
+#[test]
+fn msaa_is_lowered_to_what_the_adapter_supports() {
+    let full = capabilities(vec![1, 4]);
+    assert_eq!(full.sample_count(4), 4);
+    assert_eq!(full.sample_count(1), 1);
+    let downlevel = capabilities(vec![1]);
+    assert_eq!(downlevel.sample_count(4), 1);
+}
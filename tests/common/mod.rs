@@ -0,0 +1,37 @@
+// Helpers shared by the test files, each pulls them in with `mod common;`
+#![allow(dead_code)]
+use cgmath::{InnerSpace, Point3, Vector3};
+
+// Tolerance of close, checks that need another one call close_within
+pub const EPSILON: f32 = 1e-4;
+
+// Scalars, vectors and points compare by the distance between them
+pub trait Distance {
+    fn distance(self, other: Self) -> f32;
+}
+
+impl Distance for f32 {
+    fn distance(self, other: Self) -> f32 {
+        (self - other).abs()
+    }
+}
+
+impl Distance for Vector3<f32> {
+    fn distance(self, other: Self) -> f32 {
+        (self - other).magnitude()
+    }
+}
+
+impl Distance for Point3<f32> {
+    fn distance(self, other: Self) -> f32 {
+        (self - other).magnitude()
+    }
+}
+
+pub fn close<T: Distance>(a: T, b: T) -> bool {
+    close_within(a, b, EPSILON)
+}
+
+pub fn close_within<T: Distance>(a: T, b: T, epsilon: f32) -> bool {
+    a.distance(b) < epsilon
+}
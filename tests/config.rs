@@ -0,0 +1,128 @@
+use learn_graphics::prelude::*;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn empty_file_is_the_default() {
+    let config = RendererConfig::from_toml("").unwrap();
+    let default = RendererConfig::default();
+    assert_eq!(config.shadow_resolution, default.shadow_resolution);
+    assert_eq!(config.color_filter, ColorFilter::None);
+    assert_eq!(config.stress_test_cubes, 0);
+}
+
+#[test]
+fn file_settings_are_read_and_validated() {
+    let config = RendererConfig::from_toml(
+        r#"
+        backend = "vulkan"
+        redraw_mode = "reactive"
+        shadow_resolution = 100000
+        anisotropy = 0
+        color_filter = "deuteranopia"
+        camera_mode = "walk"
+        bindless = false
+        shadow_filter = "pcss"
+        volumetric_density = -0.5
+        imposter_distance = -100.0
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.backend, Backend::Vulkan);
+    assert_eq!(config.redraw_mode, RedrawMode::Reactive);
+    assert_eq!(config.shadow_resolution, 8192);
+    assert_eq!(config.anisotropy, 1);
+    assert_eq!(config.color_filter, ColorFilter::Deuteranopia);
+    assert_eq!(config.camera_mode, CameraMode::Walk);
+    assert!(!config.bindless);
+    assert_eq!(config.shadow_filter, ShadowFilter::Pcss);
+    assert_eq!(config.volumetric_density, 0.);
+    assert_eq!(config.imposter_distance, 0.);
+}
+
+#[test]
+fn unknown_file_settings_are_errors() {
+    assert!(RendererConfig::from_toml("no_such_setting = 1").is_err());
+}
+
+#[test]
+fn arguments_override_the_file() {
+    let config = RendererConfig::from_toml("max_fps = 30")
+        .unwrap()
+        .with_args(args(&[
+            "--max-fps",
+            "144",
+            "--color-filter=Protanopia",
+            "--stress-test-cubes",
+            "1000",
+            "--grass-blades=50000",
+            "--occlusion-culling=true",
+            "--particles",
+            "20000",
+            "--high-contrast=true",
+            "--camera-mode",
+            "Walk",
+            "--shadow-filter=Poisson",
+            "--volumetric-density",
+            "0.02",
+            "--lens-flares=false",
+            "--glass",
+            "false",
+            "--lightmaps=true",
+            "--fxaa",
+            "true",
+            "--asset-root=assets",
+            "--asset-overrides",
+            "mods, patches",
+            "--resize-delay-ms=250",
+            "--cursor-grab",
+            "Confined",
+            "--music=music.ogg",
+            "--volume",
+            "0.5",
+            "--scripts=my_scripts",
+        ]))
+        .unwrap();
+    assert_eq!(config.max_fps(), Some(144));
+    assert_eq!(config.color_filter, ColorFilter::Protanopia);
+    assert_eq!(config.stress_test_cubes, 1000);
+    assert_eq!(config.grass_blades, 50000);
+    assert!(config.occlusion_culling);
+    assert_eq!(config.particles, 20000);
+    assert!(config.high_contrast);
+    assert_eq!(config.camera_mode, CameraMode::Walk);
+    assert_eq!(config.shadow_filter, ShadowFilter::Poisson);
+    assert_eq!(config.volumetric_density, 0.02);
+    assert!(!config.lens_flares);
+    assert!(!config.glass);
+    assert!(config.lightmaps);
+    assert!(config.fxaa);
+    assert_eq!(config.asset_root, "assets");
+    assert_eq!(config.asset_overrides, ["mods", "patches"]);
+    assert_eq!(config.resize_delay_ms, 250);
+    assert_eq!(config.cursor_grab, GrabMode::Confined);
+    assert_eq!(config.music, "music.ogg");
+    assert_eq!(config.volume, 0.5);
+    assert_eq!(config.scripts, "my_scripts");
+}
+
+#[test]
+fn bad_arguments_are_errors() {
+    let default = RendererConfig::default;
+    assert!(default().with_args(args(&["max-fps", "60"])).is_err());
+    assert!(default().with_args(args(&["--max-fps"])).is_err());
+    assert!(default().with_args(args(&["--max-fps", "fast"])).is_err());
+    assert!(default()
+        .with_args(args(&["--no-such-setting", "1"]))
+        .is_err());
+}
+
+#[test]
+fn unlimited_fps_is_none() {
+    let config = RendererConfig::default()
+        .with_args(args(&["--max-fps=0"]))
+        .unwrap();
+    assert_eq!(config.max_fps(), None);
+}
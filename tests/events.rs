@@ -0,0 +1,90 @@
+// The event bus on a plain context, carried the way State carries its own
+use learn_graphics::prelude::*;
+
+#[derive(Default)]
+struct App {
+    events: EventBus<App>,
+    log: Vec<String>,
+}
+
+fn events_of(app: &mut App) -> &mut EventBus<App> {
+    &mut app.events
+}
+
+impl App {
+    fn publish<E: 'static>(&mut self, event: E) {
+        EventBus::publish(self, events_of, event);
+    }
+}
+
+fn resized(width: u32) -> WindowResized {
+    WindowResized { width, height: 1 }
+}
+
+#[test]
+fn handlers_get_their_event_type_in_order() {
+    let mut app = App::default();
+    app.events.subscribe(|app: &mut App, e: &WindowResized| {
+        app.log.push(format!("first {}", e.width));
+    });
+    app.events.subscribe(|app: &mut App, e: &LightChanged| {
+        app.log.push(format!("light {}", e.index));
+    });
+    app.events.subscribe(|app: &mut App, e: &WindowResized| {
+        app.log.push(format!("second {}", e.width));
+    });
+    assert_eq!(app.events.subscriber_count::<WindowResized>(), 2);
+    app.publish(resized(640));
+    app.publish(LightChanged { index: 1 });
+    assert_eq!(app.log, ["first 640", "second 640", "light 1"]);
+}
+
+#[test]
+fn unsubscribed_handlers_stop() {
+    let mut app = App::default();
+    let subscription = app.events.subscribe(|app: &mut App, _: &LightChanged| {
+        app.log.push("light".to_string());
+    });
+    app.publish(LightChanged { index: 0 });
+    assert!(app.events.unsubscribe(subscription));
+    assert!(!app.events.unsubscribe(subscription));
+    app.publish(LightChanged { index: 0 });
+    assert_eq!(app.log, ["light"]);
+}
+
+#[test]
+fn events_published_by_handlers_wait_for_the_current_one() {
+    let mut app = App::default();
+    app.events.subscribe(|app: &mut App, e: &WindowResized| {
+        app.log.push(format!("resized {}", e.width));
+        app.publish(LightChanged { index: 0 });
+    });
+    app.events.subscribe(|app: &mut App, _: &LightChanged| {
+        app.log.push("light".to_string());
+    });
+    app.events.subscribe(|app: &mut App, e: &WindowResized| {
+        app.log.push(format!("also resized {}", e.width));
+    });
+    app.publish(resized(10));
+    assert_eq!(app.log, ["resized 10", "also resized 10", "light"]);
+}
+
+#[test]
+fn handlers_can_subscribe_and_unsubscribe_while_running() {
+    let mut app = App::default();
+    let once = app.events.subscribe(|app: &mut App, e: &WindowResized| {
+        app.log.push(format!("once {}", e.width));
+    });
+    app.events
+        .subscribe(move |app: &mut App, _: &WindowResized| {
+            if app.events.unsubscribe(once) {
+                app.events.subscribe(|app: &mut App, e: &WindowResized| {
+                    app.log.push(format!("late {}", e.width));
+                });
+            }
+        });
+    app.publish(resized(1));
+    app.publish(resized(2));
+    assert_eq!(app.log, ["once 1", "late 2"]);
+    assert_eq!(app.events.subscriber_count::<WindowResized>(), 2);
+}
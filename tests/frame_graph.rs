@@ -0,0 +1,40 @@
+// Resource versions of the frame graph and the columns of the overlay
+use learn_graphics::FrameGraph;
+
+#[test]
+fn writes_create_new_versions() {
+    let mut graph = FrameGraph::default();
+    graph.pass("Render", &["shadow_map"], &["surface"]);
+    graph.pass("Outline", &["surface"], &["surface"]);
+    assert_eq!(graph.len(), 2);
+    let dot = graph.to_dot();
+    assert!(dot.contains("\"shadow_map#0\" -> pass0;"));
+    assert!(dot.contains("pass0 -> \"surface#1\";"));
+    assert!(dot.contains("\"surface#1\" -> pass1;"));
+    assert!(dot.contains("pass1 -> \"surface#2\";"));
+}
+
+#[test]
+fn layout_places_nodes_right_of_their_inputs() {
+    let mut graph = FrameGraph::default();
+    graph.pass("Shadow", &[], &["shadow_map"]);
+    graph.pass("Depth", &[], &["depth"]);
+    graph.pass("Render", &["shadow_map", "depth"], &["surface"]);
+    let layout = graph.layout();
+    let node = |label: &str| {
+        layout
+            .nodes
+            .iter()
+            .find(|node| node.label == label)
+            .unwrap()
+    };
+    assert_eq!((node("Shadow").column, node("Shadow").row), (0, 0));
+    assert_eq!((node("Depth").column, node("Depth").row), (0, 1));
+    assert_eq!(node("shadow_map v1").column, 1);
+    assert_eq!(node("Render").column, 2);
+    assert!(node("Render").pass && !node("surface v1").pass);
+    assert_eq!(node("surface v1").column, 3);
+    assert_eq!(layout.rows, vec![2, 2, 1, 1]);
+    // Two reads and three writes
+    assert_eq!(layout.edges.len(), 5);
+}
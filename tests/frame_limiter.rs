@@ -0,0 +1,14 @@
+// Present modes cycled with F9
+use learn_graphics::next_present_mode;
+use wgpu::PresentMode::{Fifo, Immediate, Mailbox};
+
+#[test]
+fn present_modes_skip_unsupported() {
+    let all = [Fifo, Mailbox, Immediate];
+    assert_eq!(next_present_mode(Fifo, &all), Mailbox);
+    assert_eq!(next_present_mode(Immediate, &all), Fifo);
+    assert_eq!(next_present_mode(Fifo, &[Fifo, Immediate]), Immediate);
+    assert_eq!(next_present_mode(Immediate, &[Fifo, Immediate]), Fifo);
+    // Nothing else to switch to
+    assert_eq!(next_present_mode(Fifo, &[Fifo]), Fifo);
+}
@@ -0,0 +1,34 @@
+// Camera placement that fits bounds into the view
+use cgmath::{Deg, InnerSpace, Point3, Vector3};
+use learn_graphics::{fit_distance, framing_position, Aabb, BoundingSphere};
+
+#[test]
+fn the_camera_backs_off_along_its_view() {
+    let bounds = Aabb::around(Point3::new(0., 5., 0.), Vector3::new(1., 1., 1.));
+    let fovy = Deg(60.).into();
+    let position = framing_position(&bounds, -Vector3::unit_z(), fovy, 1.5);
+    let distance = fit_distance(3f32.sqrt(), fovy, 1.5);
+    assert!((position - Point3::new(0., 5., distance)).magnitude() < 1e-4);
+}
+
+#[test]
+fn larger_bounds_are_framed_from_further() {
+    let fovy = Deg(60.).into();
+    let dir = Vector3::new(1., -1., 0.);
+    let near = Aabb::around(Point3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
+    let far = Aabb::around(Point3::new(0., 0., 0.), Vector3::new(10., 10., 10.));
+    let near = framing_position(&near, dir, fovy, 1.);
+    let far = framing_position(&far, dir, fovy, 1.);
+    assert!(far.y > near.y && far.x < near.x);
+}
+
+#[test]
+fn a_sphere_fits_in_its_box() {
+    let sphere = BoundingSphere {
+        center: Point3::new(1., 2., 3.),
+        radius: 2.,
+    };
+    let aabb = sphere.aabb();
+    assert_eq!(aabb.min, Point3::new(-1., 0., 1.));
+    assert_eq!(aabb.max, Point3::new(3., 4., 5.));
+}
@@ -0,0 +1,250 @@
+// Mesh generators checked on their plain data, no device needed
+use cgmath::{InnerSpace, Vector3};
+use learn_graphics::prelude::*;
+use learn_graphics::MeshData;
+
+fn position(mesh: &MeshData, i: u32) -> Vector3<f32> {
+    mesh.vertex_data[i as usize].position.into()
+}
+
+fn triangles(mesh: &MeshData) -> impl Iterator<Item = [Vector3<f32>; 3]> + '_ {
+    mesh.index_data.chunks_exact(3).map(|t| {
+        [
+            position(mesh, t[0]),
+            position(mesh, t[1]),
+            position(mesh, t[2]),
+        ]
+    })
+}
+
+fn face_normal([a, b, c]: [Vector3<f32>; 3]) -> Vector3<f32> {
+    (b - a).cross(c - a)
+}
+
+fn assert_well_formed(name: &str, mesh: &MeshData) {
+    assert!(!mesh.vertex_data.is_empty(), "{} has no vertices", name);
+    assert_eq!(
+        mesh.index_data.len() % 3,
+        0,
+        "{} is not a triangle list",
+        name
+    );
+    let count = mesh.vertex_data.len() as u32;
+    assert!(
+        mesh.index_data.iter().all(|&i| i < count),
+        "{} indexes past its vertices",
+        name
+    );
+    for vertex in &mesh.vertex_data {
+        let length = Vector3::from(vertex.normal).magnitude();
+        assert!(
+            (length - 1.).abs() < 1e-3,
+            "{} has a normal of length {}",
+            name,
+            length
+        );
+    }
+}
+
+// For convex meshes around the origin, counterclockwise triangles face away from it
+fn assert_winds_outward(name: &str, mesh: &MeshData) {
+    for triangle in triangles(mesh) {
+        let centroid = (triangle[0] + triangle[1] + triangle[2]) / 3.;
+        let facing = face_normal(triangle).dot(centroid);
+        assert!(facing >= -1e-5, "{} has a triangle facing inward", name);
+    }
+}
+
+// Vertex normals point to the same side as the faces they belong to
+fn assert_normals_match_faces(name: &str, mesh: &MeshData) {
+    for face in mesh.index_data.chunks_exact(3) {
+        let normal = face_normal([0, 1, 2].map(|i| position(mesh, face[i])));
+        if normal.magnitude2() < 1e-10 {
+            continue;
+        }
+        for &i in face {
+            let vertex_normal = Vector3::from(mesh.vertex_data[i as usize].normal);
+            assert!(
+                normal.normalize().dot(vertex_normal) > 0.,
+                "{} has a vertex normal against its face",
+                name
+            );
+        }
+    }
+}
+
+fn convex_meshes() -> Vec<(&'static str, MeshData)> {
+    vec![
+        ("cube", cube_mesh(2.)),
+        ("rounded cube", rounded_cube_mesh(2., 0.3, 4)),
+        ("sphere", sphere_mesh(1.5, 16, 12)),
+        ("icosphere", icosphere_mesh(1.5, 2)),
+        ("cylinder", cylinder_mesh(1., 3., 24)),
+        ("cone", cone_mesh(1., 2., 24)),
+        ("capsule", capsule_mesh(0.5, 2., 16, 6)),
+    ]
+}
+
+#[test]
+fn generators_are_well_formed() {
+    let mut meshes = convex_meshes();
+    meshes.extend([
+        ("square", square_mesh(2., 3.)),
+        ("floor", floor_mesh(2., 3.)),
+        ("torus", torus_mesh(2., 0.5, 24, 12)),
+        ("plane", plane_mesh(4., 2., 8)),
+    ]);
+    for (name, mesh) in &meshes {
+        assert_well_formed(name, mesh);
+    }
+}
+
+#[test]
+fn convex_generators_wind_outward() {
+    for (name, mesh) in &convex_meshes() {
+        assert_winds_outward(name, mesh);
+        assert_normals_match_faces(name, mesh);
+    }
+}
+
+#[test]
+fn cube_has_a_quad_per_face() {
+    let mesh = cube_mesh(2.);
+    assert_eq!(mesh.vertex_data.len(), 24);
+    assert_eq!(mesh.index_data.len(), 36);
+    for vertex in &mesh.vertex_data {
+        assert!(vertex.position.iter().all(|x| (x.abs() - 1.).abs() < 1e-5));
+    }
+}
+
+#[test]
+fn unrounded_cube_matches_cube() {
+    let cube = cube_mesh(2.);
+    let rounded = rounded_cube_mesh(2., 0., 3);
+    assert_eq!(cube.vertex_data.len(), rounded.vertex_data.len());
+    assert_eq!(cube.index_data, rounded.index_data);
+}
+
+#[test]
+fn rounded_cube_stays_inside_its_size() {
+    let mesh = rounded_cube_mesh(2., 0.5, 6);
+    for vertex in &mesh.vertex_data {
+        assert!(vertex.position.iter().all(|x| x.abs() <= 1. + 1e-5));
+    }
+    // The corners are cut off
+    let corner = Vector3::new(1., 1., 1.).magnitude();
+    let farthest = mesh
+        .vertex_data
+        .iter()
+        .map(|v| Vector3::from(v.position).magnitude())
+        .fold(0., f32::max);
+    assert!(farthest < corner - 0.1);
+}
+
+#[test]
+fn sphere_vertices_lie_on_the_radius() {
+    let (u, v) = (16, 12);
+    let mesh = sphere_mesh(1.5, u, v);
+    assert_eq!(mesh.vertex_data.len(), (u + 1) * (v + 1));
+    assert_eq!(mesh.index_data.len(), 6 * u * v);
+    for vertex in &mesh.vertex_data {
+        let p = Vector3::from(vertex.position);
+        assert!((p.magnitude() - 1.5).abs() < 1e-4);
+        assert!(p.normalize().dot(vertex.normal.into()) > 0.999);
+    }
+}
+
+#[test]
+fn dense_spheres_keep_every_index() {
+    // Past what u16 indices can address
+    let mesh = sphere_mesh(1., 400, 200);
+    assert!(mesh.vertex_data.len() > u16::MAX as usize);
+    assert_well_formed("dense sphere", &mesh);
+}
+
+#[test]
+fn icosphere_subdivides_every_triangle() {
+    for subdivisions in 0..4 {
+        let mesh = icosphere_mesh(2., subdivisions);
+        assert_eq!(
+            mesh.index_data.len(),
+            20 * 4usize.pow(subdivisions as u32) * 3
+        );
+        for vertex in &mesh.vertex_data {
+            assert!((Vector3::from(vertex.position).magnitude() - 2.).abs() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn torus_surface_is_minor_radius_from_the_ring() {
+    let (major, minor) = (2., 0.5);
+    let mesh = torus_mesh(major, minor, 24, 12);
+    for vertex in &mesh.vertex_data {
+        let [x, y, z] = vertex.position;
+        let from_axis = (x * x + z * z).sqrt();
+        let to_ring = ((from_axis - major).powi(2) + y * y).sqrt();
+        assert!((to_ring - minor).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn plane_faces_up() {
+    let subdivisions = 8;
+    let mesh = plane_mesh(4., 2., subdivisions);
+    assert_eq!(
+        mesh.vertex_data.len(),
+        (subdivisions + 1) * (subdivisions + 1)
+    );
+    for triangle in triangles(&mesh) {
+        assert!(face_normal(triangle).y > 0.);
+    }
+    for vertex in &mesh.vertex_data {
+        let [x, y, z] = vertex.position;
+        assert!(x.abs() <= 2. + 1e-5 && z.abs() <= 1. + 1e-5 && y == 0.);
+    }
+}
+
+fn unit_sphere(u: f32, v: f32) -> [f32; 3] {
+    let (theta, phi) = (v * std::f32::consts::PI, u * 2. * std::f32::consts::PI);
+    [
+        theta.sin() * phi.cos(),
+        -theta.cos(),
+        -theta.sin() * phi.sin(),
+    ]
+}
+
+#[test]
+fn parametric_normals_follow_the_surface() {
+    let mesh = parametric_mesh(unit_sphere, 24, 16);
+    assert_well_formed("parametric sphere", &mesh);
+    assert_eq!(mesh.vertex_data.len(), 25 * 17);
+    // Including the poles, where the u derivative vanishes
+    for vertex in &mesh.vertex_data {
+        let radial = Vector3::from(vertex.position).normalize();
+        assert!(radial.dot(vertex.normal.into()) > 0.99);
+    }
+    assert_winds_outward("parametric sphere", &mesh);
+}
+
+#[test]
+fn parametric_normals_can_be_given() {
+    let up = |_, _| [0., 1., 0.];
+    let mesh = parametric_with_normals_mesh(|u, v| [u, 0., -v], up, 4, 4);
+    assert!(mesh.vertex_data.iter().all(|v| v.normal == [0., 1., 0.]));
+    for triangle in triangles(&mesh) {
+        assert!(face_normal(triangle).y > 0.);
+    }
+    let uv = mesh.vertex_data.last().unwrap().tex_coords;
+    assert_eq!(uv, [1., 1.]);
+}
+
+#[test]
+fn index_format_fits_the_vertex_count() {
+    assert_eq!(GeoObj::index_format_for(24), wgpu::IndexFormat::Uint16);
+    assert_eq!(
+        GeoObj::index_format_for(u16::MAX as usize),
+        wgpu::IndexFormat::Uint32
+    );
+    assert_eq!(GeoObj::index_format_for(1 << 20), wgpu::IndexFormat::Uint32);
+}
@@ -0,0 +1,29 @@
+// Which baked views an imposter shows, without a device
+mod common;
+use cgmath::Rad;
+use common::close;
+use learn_graphics::{imposter_view_angle, imposter_views, IMPOSTER_VIEWS};
+use std::f32::consts::TAU;
+
+#[test]
+fn baked_angles_show_their_own_view() {
+    for view in 0..IMPOSTER_VIEWS {
+        let (first, second, blend) = imposter_views(imposter_view_angle(view) + Rad(1e-5));
+        assert_eq!(first, view);
+        assert_eq!(second, (view + 1) % IMPOSTER_VIEWS);
+        assert!(close(blend, 0.));
+    }
+}
+
+#[test]
+fn halfway_blends_evenly_and_wraps() {
+    let step = TAU / IMPOSTER_VIEWS as f32;
+    let (first, second, blend) = imposter_views(Rad(1.5 * step));
+    assert_eq!((first, second), (1, 2));
+    assert!(close(blend, 0.5));
+    // Just short of a full turn, between the last view and the first
+    let (first, second, blend) = imposter_views(Rad(-0.5 * step));
+    assert_eq!((first, second), (IMPOSTER_VIEWS - 1, 0));
+    assert!(close(blend, 0.5));
+    assert_eq!(imposter_views(Rad(TAU + 0.25 * step)).0, 0);
+}
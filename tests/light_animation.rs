@@ -0,0 +1,32 @@
+// The clock-driven light animators, without a device
+mod common;
+use common::close;
+use learn_graphics::{flicker_noise, path_position, rotate_hue};
+
+#[test]
+fn hue_rotation_keeps_grays_and_turns_back() {
+    let gray = rotate_hue([0.4, 0.4, 0.4], 1.3);
+    assert!(gray.iter().all(|&c| close(c, 0.4)));
+    let red = [1., 0., 0.];
+    let turned = rotate_hue(red, std::f32::consts::TAU / 3.);
+    assert!(close(turned[1], 1.) && close(turned[0], 0.) && close(turned[2], 0.));
+    let back = rotate_hue(turned, -std::f32::consts::TAU / 3.);
+    assert!(back.iter().zip(red).all(|(&a, b)| close(a, b)));
+}
+
+#[test]
+fn path_wraps_around() {
+    let points = [[0., 0., 0.], [2., 0., 0.], [2., 2., 0.]];
+    assert_eq!(path_position(&points, 0.5).unwrap(), [1., 0., 0.].into());
+    // The last segment leads back to the first point
+    assert_eq!(path_position(&points, 2.5).unwrap(), [1., 1., 0.].into());
+    assert_eq!(path_position(&points, 3.5), path_position(&points, 0.5));
+    assert_eq!(path_position(&[], 1.), None);
+}
+
+#[test]
+fn flicker_stays_in_range() {
+    assert!((0..1000)
+        .map(|i| flicker_noise(i as f32 * 0.37))
+        .all(|n| (0. ..=1.).contains(&n)));
+}
@@ -0,0 +1,206 @@
+// Lightmap texel placement and the light gathered for a texel, with stand-in ray casts
+mod common;
+use cgmath::{Deg, InnerSpace, Point3, Quaternion, Rotation3, Vector3};
+use common::close_within;
+use learn_graphics::internals::{
+    bake_texel, dilate, direct_light, encode_texel, hemisphere_samples, lightmap_texels,
+    LightmapTexel, LIGHTMAP_RANGE,
+};
+use learn_graphics::prelude::*;
+use learn_graphics::{Ray, RayHit};
+
+fn flat_ambient(color: [f32; 3]) -> SceneUniform {
+    let [r, g, b] = color;
+    SceneUniform {
+        ambient_mode: 1,
+        ambient_sky: [r, g, b, 0.],
+        ..Default::default()
+    }
+}
+
+// A surface facing down `distance` along every ray
+fn roof(distance: f32) -> impl Fn(&Ray) -> Option<RayHit> {
+    move |ray: &Ray| {
+        Some(RayHit {
+            distance,
+            position: ray.at(distance),
+            normal: -Vector3::unit_y(),
+            uv: [0.; 2],
+            triangle: 0,
+            instance: 0,
+        })
+    }
+}
+
+fn open_sky(_: &Ray) -> Option<RayHit> {
+    None
+}
+
+fn floor_texel() -> LightmapTexel {
+    LightmapTexel {
+        position: Point3::new(0., 0., 0.),
+        normal: Vector3::unit_y(),
+    }
+}
+
+#[test]
+fn floor_texels_cover_the_lightmap() {
+    // floor_mesh covers its second uv set once
+    let mesh = floor_mesh(10., 10.);
+    let uv = [0., 0., 1., 1.];
+    let texels = lightmap_texels(
+        &mesh.vertex_data,
+        &mesh.index_data,
+        uv,
+        &InstanceTransform::default(),
+        8,
+    );
+    assert_eq!(texels.len(), 64);
+    assert!(texels.iter().all(Option::is_some));
+    // Row 0 is the top of the texture, at v = 1
+    let first = texels[0].unwrap();
+    assert!(close_within(first.position.x, -5. + 10. / 16., 1e-3));
+    assert!(close_within(first.position.y, 5. - 10. / 16., 1e-3));
+    assert!(close_within(first.normal.z, 1., 1e-3));
+}
+
+#[test]
+fn texels_outside_the_mesh_stay_empty() {
+    let mesh = floor_mesh(10., 10.);
+    let texels = lightmap_texels(
+        &mesh.vertex_data,
+        &mesh.index_data,
+        [0., 0., 0.5, 0.5],
+        &InstanceTransform::default(),
+        8,
+    );
+    // The mesh covers the bottom left quarter
+    assert_eq!(texels.iter().filter(|texel| texel.is_some()).count(), 16);
+    assert!(texels[7 * 8].is_some());
+    assert!(texels[7].is_none());
+}
+
+#[test]
+fn texels_follow_the_instance() {
+    let mesh = floor_mesh(10., 10.);
+    // Laid flat like the floor of the demo scene
+    let rotation = Quaternion::from_axis_angle(Vector3::unit_x(), Deg(-90.));
+    let transform = InstanceTransform::new(Vector3::new(0., 3., 0.), rotation);
+    let texels = lightmap_texels(
+        &mesh.vertex_data,
+        &mesh.index_data,
+        [0., 0., 1., 1.],
+        &transform,
+        4,
+    );
+    for texel in texels.into_iter().flatten() {
+        assert!(close_within(texel.position.y, 3., 1e-3));
+        assert!(close_within(texel.normal.y, 1., 1e-3));
+    }
+}
+
+#[test]
+fn hemisphere_samples_are_cosine_weighted() {
+    let samples = hemisphere_samples(256);
+    assert_eq!(samples.len(), 256);
+    assert!(samples
+        .iter()
+        .all(|s| s.z > 0. && close_within(s.magnitude(), 1., 1e-3)));
+    // The mean cosine of a cosine weighted hemisphere is 2/3
+    let mean = samples.iter().map(|s| s.z).sum::<f32>() / 256.;
+    assert!((mean - 2. / 3.).abs() < 0.01);
+    let center = samples.iter().fold(Vector3::new(0., 0., 0.), |a, s| a + s) / 256.;
+    assert!(center.x.abs() < 0.02 && center.y.abs() < 0.02);
+}
+
+#[test]
+fn open_sky_bakes_the_ambient_light() {
+    let scene = flat_ambient([0.2, 0.3, 0.4]);
+    let samples = hemisphere_samples(16);
+    let light = bake_texel(
+        &floor_texel(),
+        &samples,
+        &BakeSettings::default(),
+        &scene,
+        &open_sky,
+    );
+    assert!(
+        close_within(light[0], 0.2, 1e-3)
+            && close_within(light[1], 0.3, 1e-3)
+            && close_within(light[2], 0.4, 1e-3)
+    );
+}
+
+#[test]
+fn a_roof_only_bounces_light() {
+    let scene = flat_ambient([1., 1., 1.]);
+    let samples = hemisphere_samples(16);
+    let settings = BakeSettings {
+        bounce: 0.25,
+        ..Default::default()
+    };
+    let light = bake_texel(&floor_texel(), &samples, &settings, &scene, &roof(2.));
+    assert!(close_within(light[0], 0.25, 1e-3));
+    // Too far away to shade the texel
+    let settings = BakeSettings {
+        max_distance: 1.,
+        ..settings
+    };
+    let light = bake_texel(&floor_texel(), &samples, &settings, &scene, &roof(2.));
+    assert!(close_within(light[0], 1., 1e-3));
+}
+
+#[test]
+fn baked_lights_fade_and_cast_shadows() {
+    let lights = [BakedLight::Point {
+        position: Point3::new(0., 10., 0.),
+        color: [1., 1., 1.],
+        range: 20.,
+    }];
+    let texel = floor_texel();
+    let lit = direct_light(texel.position, texel.normal, &lights, &open_sky);
+    assert!(close_within(lit.x, 0.25, 1e-3));
+    let shadowed = direct_light(texel.position, texel.normal, &lights, &roof(5.));
+    assert_eq!(shadowed, Vector3::new(0., 0., 0.));
+    // The roof is behind the light
+    let behind = direct_light(texel.position, texel.normal, &lights, &roof(15.));
+    assert!(close_within(behind.x, 0.25, 1e-3));
+    let sun = [BakedLight::Directional {
+        direction: Vector3::new(0., -1., -1.),
+        color: [1., 0., 0.],
+    }];
+    let lit = direct_light(texel.position, texel.normal, &sun, &open_sky);
+    assert!(close_within(lit.x, 0.5f32.sqrt(), 1e-3) && lit.y == 0.);
+}
+
+#[test]
+fn dilate_grows_covered_texels() {
+    let mut texels = vec![None; 25];
+    texels[12] = Some(1);
+    dilate(&mut texels, 5, 1);
+    assert_eq!(texels.iter().filter(|texel| texel.is_some()).count(), 9);
+    dilate(&mut texels, 5, 1);
+    assert!(texels.iter().all(|texel| *texel == Some(1)));
+}
+
+#[test]
+fn texels_are_encoded_up_to_the_range() {
+    assert_eq!(encode_texel([0.; 3]), [0, 0, 0, 255]);
+    assert_eq!(encode_texel([LIGHTMAP_RANGE; 3]), [255, 255, 255, 255]);
+    assert_eq!(encode_texel([LIGHTMAP_RANGE * 2.; 3]), [255, 255, 255, 255]);
+    // sRGB keeps more steps in the dark
+    assert!(encode_texel([LIGHTMAP_RANGE * 0.1; 3])[0] > 80);
+}
+
+#[test]
+fn hemisphere_ambient_blends_sky_and_ground() {
+    let scene = SceneUniform {
+        ambient_sky: [1., 1., 1., 0.],
+        ambient_ground: [0., 0., 0., 0.],
+        ambient_intensity: 2.,
+        ..Default::default()
+    };
+    assert_eq!(scene.ambient(Vector3::unit_y()), [2.; 3]);
+    assert_eq!(scene.ambient(-Vector3::unit_y()), [0.; 3]);
+    assert_eq!(scene.ambient(Vector3::unit_x()), [1.; 3]);
+}
@@ -0,0 +1,299 @@
+// Bounds, rays and instance transforms, everything picking and culling builds on
+mod common;
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Point3, Quaternion, Rad, Rotation3, Vector3};
+use common::close;
+use learn_graphics::prelude::*;
+use learn_graphics::{raycast_mesh, Aabb, BoundingSphere, Ray};
+
+#[test]
+fn aabb_of_no_points_is_none() {
+    assert!(Aabb::from_points(std::iter::empty()).is_none());
+}
+
+#[test]
+fn aabb_grows_to_fit() {
+    let points = [Point3::new(1., -2., 0.), Point3::new(-1., 3., 4.)];
+    let aabb = Aabb::from_points(points.into_iter()).unwrap();
+    assert_eq!(aabb.min, Point3::new(-1., -2., 0.));
+    assert_eq!(aabb.max, Point3::new(1., 3., 4.));
+    assert_eq!(aabb.center(), Point3::new(0., 0.5, 2.));
+    let other = Aabb::from_points([Point3::new(5., 0., 0.)].into_iter()).unwrap();
+    assert_eq!(aabb.union(&other).max, Point3::new(5., 3., 4.));
+}
+
+#[test]
+fn bounding_sphere_contains_its_points() {
+    let points = [[1., 0., 0.], [-3., 2., 0.], [0., 0., 5.], [2., -2., -2.]];
+    let sphere = BoundingSphere::from_points(points.iter().copied());
+    for p in points {
+        assert!((Point3::from(p) - sphere.center).magnitude() <= sphere.radius + 1e-5);
+    }
+    let empty = BoundingSphere::from_points(std::iter::empty());
+    assert_eq!(empty.radius, 0.);
+}
+
+#[test]
+fn bounding_sphere_union_contains_both() {
+    let a = BoundingSphere {
+        center: Point3::new(0., 0., 0.),
+        radius: 1.,
+    };
+    let b = BoundingSphere {
+        center: Point3::new(4., 0., 0.),
+        radius: 2.,
+    };
+    let union = a.union(&b);
+    assert!(close(union.radius, 3.5));
+    assert!(close(union.center.to_vec(), Vector3::new(2.5, 0., 0.)));
+    // A sphere already inside is absorbed
+    let inner = BoundingSphere {
+        center: Point3::new(4.5, 0., 0.),
+        radius: 0.5,
+    };
+    assert!(close(b.union(&inner).radius, 2.));
+}
+
+#[test]
+fn bounding_sphere_follows_the_transform() {
+    let local = BoundingSphere {
+        center: Point3::new(1., 0., 0.),
+        radius: 1.,
+    };
+    let rotation = Quaternion::from_angle_y(Deg(90.));
+    let moved = local.transformed(Vector3::new(0., 5., 0.), rotation, Vector3::new(2., 1., 3.));
+    assert!(close(moved.center.to_vec(), Vector3::new(0., 5., -2.)));
+    assert!(close(moved.radius, 3.));
+}
+
+#[test]
+fn ray_direction_is_normalized() {
+    let ray = Ray::new(Point3::origin(), Vector3::new(0., 3., 4.));
+    assert!(close(ray.direction.magnitude(), 1.));
+    assert!(close(ray.at(5.).to_vec(), Vector3::new(0., 3., 4.)));
+}
+
+#[test]
+fn ray_sphere_intersection() {
+    let sphere = BoundingSphere {
+        center: Point3::new(0., 0., -10.),
+        radius: 2.,
+    };
+    let forward = Vector3::new(0., 0., -1.);
+    let hit = Ray::new(Point3::origin(), forward).intersect_sphere(&sphere);
+    assert!(close(hit.unwrap(), 8.));
+    let behind = Ray::new(Point3::origin(), -forward).intersect_sphere(&sphere);
+    assert!(behind.is_none());
+    let beside = Ray::new(Point3::new(3., 0., 0.), forward).intersect_sphere(&sphere);
+    assert!(beside.is_none());
+    let inside = Ray::new(Point3::new(0., 0., -10.), forward).intersect_sphere(&sphere);
+    assert_eq!(inside, Some(0.));
+}
+
+#[test]
+fn ray_triangle_intersection_has_barycentrics() {
+    let triangle = [
+        Point3::new(0., 0., 0.),
+        Point3::new(1., 0., 0.),
+        Point3::new(0., 1., 0.),
+    ];
+    let ray = Ray::new(Point3::new(0.25, 0.5, 2.), Vector3::new(0., 0., -1.));
+    let (distance, u, v) = ray.intersect_triangle(triangle).unwrap();
+    assert!(close(distance, 2.) && close(u, 0.25) && close(v, 0.5));
+    // Back faces count too
+    let from_behind = Ray::new(Point3::new(0.25, 0.5, -2.), Vector3::new(0., 0., 1.));
+    assert!(from_behind.intersect_triangle(triangle).is_some());
+    let outside = Ray::new(Point3::new(1., 1., 2.), Vector3::new(0., 0., -1.));
+    assert!(outside.intersect_triangle(triangle).is_none());
+}
+
+#[test]
+fn raycast_finds_the_closest_cube_face() {
+    let cube = cube_mesh(2.);
+    let ray = Ray::new(Point3::new(0., 0., 5.), Vector3::new(0., 0., -1.));
+    let hit = raycast_mesh(&ray, &cube.vertex_data, &cube.index_data).unwrap();
+    assert!(close(hit.distance, 4.));
+    assert!(close(hit.normal, Vector3::unit_z()));
+    assert!(close(hit.uv[0], 0.5) && close(hit.uv[1], 0.5));
+    let miss = Ray::new(Point3::new(3., 0., 5.), Vector3::new(0., 0., -1.));
+    assert!(raycast_mesh(&miss, &cube.vertex_data, &cube.index_data).is_none());
+}
+
+#[test]
+fn raycast_in_instance_space() {
+    let transform = InstanceTransform::new(
+        Vector3::new(10., 0., 0.),
+        Quaternion::from_angle_y(Deg(45.)),
+    )
+    .with_scale(Vector3::new(2., 2., 2.));
+    let ray = Ray::new(Point3::new(10., 0., 20.), Vector3::new(0., 0., -1.));
+    let local = ray.to_local(
+        transform.position(),
+        transform.rotation(),
+        transform.scale(),
+    );
+    let sphere = sphere_mesh(1., 32, 16);
+    let hit = raycast_mesh(&local, &sphere.vertex_data, &sphere.index_data).unwrap();
+    let world = transform.transform_point(hit.position.to_vec());
+    // The scaled sphere has a radius of 2, faceting keeps the hit slightly inside
+    assert!(world.z > 1.9 && world.z <= 2. + 1e-4);
+}
+
+#[test]
+fn transform_matrices_agree() {
+    let transform = InstanceTransform::new(
+        Vector3::new(1., 2., 3.),
+        Quaternion::from_axis_angle(Vector3::new(1., 1., 0.).normalize(), Rad(0.7)),
+    )
+    .with_scale(Vector3::new(1., 2., 0.5));
+    let point = Vector3::new(0.3, -1., 2.);
+    let by_matrix = (transform.model_matrix() * point.extend(1.)).truncate();
+    assert!(close(by_matrix, transform.transform_point(point)));
+    // Normals stay perpendicular to surface tangents under non uniform scale
+    let tangent = Vector3::new(1., 1., 0.);
+    let normal = Vector3::new(1., -1., 0.);
+    let model = transform.model_matrix();
+    let world_tangent = (model * tangent.extend(0.)).truncate();
+    let world_normal = transform.normal_matrix() * normal;
+    assert!(close(world_tangent.dot(world_normal), 0.));
+}
+
+#[test]
+fn relative_undoes_compose() {
+    let parent = InstanceTransform::new(
+        Vector3::new(4., 0., -1.),
+        Quaternion::from_angle_z(Deg(30.)),
+    )
+    .with_scale(Vector3::new(2., 2., 2.));
+    let child =
+        InstanceTransform::new(Vector3::new(0., 1., 0.), Quaternion::from_angle_x(Deg(10.)));
+    let world = parent.compose(&child);
+    let local = parent.relative(&world);
+    assert!(close(local.position(), child.position()));
+    assert!(close(local.scale(), child.scale()));
+    let angle = local.rotation().dot(child.rotation()).abs();
+    assert!(close(angle, 1.));
+}
+
+#[test]
+fn top_down_orthographic_keeps_forward_up() {
+    let view = CameraView::top_down((0., 100., 0.), Deg(0.));
+    assert!(close(view.get_dir(), -Vector3::unit_y()));
+    let projection = Projection::orthographic(256, 256, 10., 1., 200.);
+    let clip = |p: Point3<f32>| {
+        let clip = projection.calc_matrix() * view.calc_matrix() * p.to_homogeneous();
+        clip.truncate() / clip.w
+    };
+    // Yaw 0 looks along +x, which is the top of the map, +z is to its right
+    let ahead = clip(Point3::new(10., 0., 0.));
+    assert!(close(ahead.x, 0.) && close(ahead.y, 1.));
+    let right = clip(Point3::new(0., 0., 10.));
+    assert!(close(right.x, 1.) && close(right.y, 0.));
+    // No perspective, height only changes the depth
+    let low = clip(Point3::new(10., -50., 0.));
+    assert!(close(low.x, ahead.x) && close(low.y, ahead.y));
+    assert!(low.z > ahead.z);
+}
+
+#[test]
+fn cube_faces_see_the_cube_map_directions_mirrored() {
+    // Directions of texel (s, t) in [-1, 1] of each face, t pointing down the image
+    let texel = |face: usize, s: f32, t: f32| match face {
+        0 => Vector3::new(1., -t, -s),
+        1 => Vector3::new(-1., -t, s),
+        2 => Vector3::new(s, 1., t),
+        3 => Vector3::new(s, -1., -t),
+        4 => Vector3::new(s, -t, 1.),
+        _ => Vector3::new(-s, -t, -1.),
+    };
+    let projection = Projection::new(128, 128, Deg(90.), 0.1, 100.);
+    for face in 0..6 {
+        let view = CameraView::cube_face((0., 0., 0.), face);
+        for (s, t) in [(0.5, -0.5), (-0.25, 0.75), (0., 0.)] {
+            let point = Point3::from_vec(texel(face, s, t) * 10.);
+            let clip = projection.calc_matrix() * view.calc_matrix() * point.to_homogeneous();
+            let ndc = clip.truncate() / clip.w;
+            // Mirrored horizontally, and rows run down the image
+            assert!(close(ndc.x, -s) && close(ndc.y, -t), "face {}", face);
+        }
+    }
+}
+
+#[test]
+fn frustum_corners_span_the_view_volume() {
+    let view = CameraView::top_down((0., 100., 0.), Deg(0.));
+    let projection = Projection::orthographic(256, 256, 10., 1., 200.);
+    let corners = frustum_corners(projection.calc_matrix() * view.calc_matrix()).unwrap();
+    let bounds = Aabb::from_points(corners.into_iter()).unwrap();
+    // Unprojecting loses some precision at this depth range
+    let near = |a: Point3<f32>, b: [f32; 3]| (a.to_vec() - Vector3::from(b)).magnitude() < 1e-2;
+    assert!(near(bounds.min, [-10., -100., -10.]));
+    assert!(near(bounds.max, [10., 99., 10.]));
+    // The near face comes first
+    assert!(corners[..4]
+        .iter()
+        .all(|corner| (corner.y - 99.).abs() < 1e-2));
+    let mut lines = LineList::default();
+    lines.frustum(projection.calc_matrix() * view.calc_matrix(), [1.; 4]);
+    assert_eq!(lines.len(), 12);
+    lines.aabb(&bounds, [1.; 4]);
+    lines.axes(&InstanceTransform::default(), 1.);
+    assert_eq!(lines.len(), 27);
+}
+
+#[test]
+fn sphere_lines_lie_on_the_sphere() {
+    let sphere = BoundingSphere {
+        center: Point3::new(1., 2., 3.),
+        radius: 4.,
+    };
+    let mut lines = LineList::default();
+    lines.sphere(&sphere, [1.; 4]);
+    assert_eq!(lines.len(), 96);
+    assert!(lines.vertices.iter().all(|vertex| {
+        let distance = (Point3::from(vertex.position) - sphere.center).magnitude();
+        close(distance, sphere.radius)
+    }));
+    lines.clear();
+    assert!(lines.is_empty());
+}
+
+#[test]
+fn grid_is_centered() {
+    let transforms = grid([3, 1, 2], 2., Vector3::new(0., 5., 0.));
+    assert_eq!(transforms.len(), 6);
+    let sum = transforms
+        .iter()
+        .fold(Vector3::new(0., 0., 0.), |sum, t| sum + t.position());
+    assert!(close(sum / 6., Vector3::new(0., 5., 0.)));
+}
+
+#[test]
+fn scatter_is_deterministic_and_bounded() {
+    let center = Vector3::new(10., 0., -10.);
+    let a = scatter(100, center, 5., 1., 3);
+    let b = scatter(100, center, 5., 1., 3);
+    let c = scatter(100, center, 5., 1., 4);
+    assert!(a.iter().zip(&b).all(|(a, b)| a.position() == b.position()));
+    assert!(a.iter().zip(&c).any(|(a, c)| a.position() != c.position()));
+    for t in &a {
+        let p = t.position();
+        assert!((p.x - center.x).abs() <= 5. && (p.z - center.z).abs() <= 5. && p.y == 1.);
+    }
+}
+
+#[test]
+fn jitter_stays_in_range() {
+    let mut transforms = grid([4, 4, 4], 1., Vector3::new(0., 0., 0.));
+    jitter_scales(&mut transforms, 0.5, 1.5, 1);
+    jitter_rotations(&mut transforms, Rad(0.5), 1);
+    for t in &transforms {
+        let scale = t.scale();
+        assert!(scale.x >= 0.5 && scale.x <= 1.5);
+        assert!(scale.x == scale.y && scale.y == scale.z);
+        assert!(close(t.rotation().magnitude(), 1.));
+    }
+    for attributes in random_colors(16, 9) {
+        assert!(attributes.color.iter().all(|c| (0. ..=1.).contains(c)));
+        assert_eq!(attributes.color[3], 1.);
+    }
+}
@@ -0,0 +1,75 @@
+// Morph target blending and weight keys, the CPU path of deform.wgsl
+mod common;
+use common::close_within;
+use learn_graphics::internals::blend_morph_targets;
+use learn_graphics::prelude::*;
+
+fn lift(height: f32, count: usize) -> MorphTarget {
+    MorphTarget {
+        positions: vec![[0., height, 0.]; count],
+        normals: vec![],
+    }
+}
+
+#[test]
+fn targets_add_up_by_weight() {
+    let rest = square_mesh(2., 2.).vertex_data;
+    let targets = [lift(1., rest.len()), lift(-4., rest.len())];
+    let blended = blend_morph_targets(&rest, &targets, &[0.5, 0.25]);
+    for (vertex, before) in blended.iter().zip(&rest) {
+        assert!(close_within(
+            vertex.position[1],
+            before.position[1] - 0.5,
+            1e-5
+        ));
+        assert_eq!(vertex.position[0], before.position[0]);
+        assert_eq!(vertex.tex_coords, before.tex_coords);
+    }
+    // Missing weights count as 0
+    let blended = blend_morph_targets(&rest, &targets, &[1.]);
+    assert!(close_within(
+        blended[0].position[1],
+        rest[0].position[1] + 1.,
+        1e-5
+    ));
+}
+
+#[test]
+fn blended_normals_are_unit_length() {
+    let rest = square_mesh(2., 2.).vertex_data;
+    let tilt = MorphTarget {
+        positions: vec![],
+        normals: vec![[1., 0., -1.]; rest.len()],
+    };
+    let blended = blend_morph_targets(&rest, &[tilt], &[1.]);
+    let [x, y, z] = blended[0].normal;
+    assert!(close_within(x, 1., 1e-5) && close_within(y, 0., 1e-5) && close_within(z, 0., 1e-5));
+    let blended = blend_morph_targets(&rest, &[lift(1., 4)], &[0.]);
+    assert_eq!(blended[0].normal, rest[0].normal);
+}
+
+#[test]
+fn weight_keys_are_interpolated_in_a_loop() {
+    let animation = MorphAnimation {
+        times: vec![0.5, 1.5, 2.],
+        weights: vec![vec![0., 1.], vec![1., 0.], vec![0., 0.]],
+    };
+    assert_eq!(animation.duration(), 2.);
+    // Held before the first key
+    assert_eq!(animation.sample(0.25), vec![0., 1.]);
+    let halfway = animation.sample(1.);
+    assert!(close_within(halfway[0], 0.5, 1e-5) && close_within(halfway[1], 0.5, 1e-5));
+    let looped = animation.sample(3.);
+    assert!(close_within(looped[0], 0.5, 1e-5) && close_within(looped[1], 0.5, 1e-5));
+    assert!(MorphAnimation::default().sample(1.).is_empty());
+}
+
+#[test]
+fn reach_covers_every_target_at_full_weight() {
+    let targets = MorphTargets {
+        targets: vec![lift(2., 3), lift(-1., 3)],
+        ..Default::default()
+    };
+    assert!(close_within(targets.reach(), 3., 1e-5));
+    assert!(MorphTargets::default().is_empty());
+}
@@ -0,0 +1,100 @@
+// The CPU mirror of noise.wgsl, without a device
+use learn_graphics::{noise, NoiseKind, NoiseSettings, MAX_OCTAVES};
+
+const KINDS: [NoiseKind; 3] = [NoiseKind::Perlin, NoiseKind::Worley, NoiseKind::Fbm];
+
+fn settings(kind: NoiseKind) -> NoiseSettings {
+    NoiseSettings {
+        kind,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn values_stay_in_range() {
+    for kind in KINDS {
+        let settings = settings(kind);
+        for i in 0..64 {
+            for j in 0..64 {
+                let value = noise(&settings, i as f32 / 64., j as f32 / 64.);
+                assert!((0. ..=1.).contains(&value), "{kind:?} {value}");
+            }
+        }
+    }
+}
+
+#[test]
+fn opposite_edges_match() {
+    for kind in KINDS {
+        let settings = settings(kind);
+        for i in 0..32 {
+            let t = i as f32 / 32.;
+            let at = noise(&settings, t, 0.3);
+            assert!(
+                (at - noise(&settings, t + 1., 0.3)).abs() < 1e-3,
+                "{kind:?}"
+            );
+            assert!(
+                (at - noise(&settings, t - 1., 0.3)).abs() < 1e-3,
+                "{kind:?}"
+            );
+            let at = noise(&settings, 0.7, t);
+            assert!(
+                (at - noise(&settings, 0.7, t + 1.)).abs() < 1e-3,
+                "{kind:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn varies_with_the_seed_and_position() {
+    for kind in KINDS {
+        let a = settings(kind);
+        let b = NoiseSettings { seed: 1, ..a };
+        let differs = |f: &dyn Fn(f32) -> bool| (0..16).any(|i| f(i as f32 / 16. + 0.03));
+        assert!(
+            differs(&|t| noise(&a, t, 0.4) != noise(&b, t, 0.4)),
+            "{kind:?}"
+        );
+        assert!(
+            differs(&|t| noise(&a, t, 0.4) != noise(&a, 0.4, t)),
+            "{kind:?}"
+        );
+    }
+}
+
+#[test]
+fn perlin_is_neutral_on_the_lattice() {
+    let settings = settings(NoiseKind::Perlin);
+    for i in 0..4 {
+        for j in 0..4 {
+            let value = noise(&settings, i as f32 / 4., j as f32 / 4.);
+            assert!((value - 0.5).abs() < 1e-5);
+        }
+    }
+}
+
+#[test]
+fn fbm_with_one_octave_is_perlin() {
+    let perlin = settings(NoiseKind::Perlin);
+    let fbm = NoiseSettings {
+        kind: NoiseKind::Fbm,
+        octaves: 1,
+        ..perlin
+    };
+    for i in 0..32 {
+        let t = i as f32 / 32. + 0.01;
+        assert_eq!(noise(&perlin, t, 1. - t), noise(&fbm, t, 1. - t));
+    }
+    // Octaves past the limit add nothing
+    let limit = NoiseSettings {
+        octaves: MAX_OCTAVES,
+        ..fbm
+    };
+    let beyond = NoiseSettings {
+        octaves: MAX_OCTAVES + 4,
+        ..fbm
+    };
+    assert_eq!(noise(&limit, 0.37, 0.61), noise(&beyond, 0.37, 0.61));
+}
@@ -0,0 +1,61 @@
+// Normal recomputation on generated meshes, no device needed
+mod common;
+use cgmath::{InnerSpace, Vector3};
+use common::close_within;
+use learn_graphics::prelude::*;
+
+#[test]
+fn smooth_sphere_normals_point_outward() {
+    let mut sphere = sphere_mesh(2., 24, 12);
+    recompute_normals(&mut sphere, NormalMode::Smooth);
+    for vertex in &sphere.vertex_data {
+        let outward = Vector3::from(vertex.position).normalize();
+        assert!(Vector3::from(vertex.normal).dot(outward) > 0.95);
+    }
+}
+
+#[test]
+fn smooth_normals_are_shared_across_seams() {
+    let mut sphere = sphere_mesh(1., 16, 8);
+    recompute_normals(&mut sphere, NormalMode::AngleWeighted);
+    for a in &sphere.vertex_data {
+        for b in &sphere.vertex_data {
+            if a.position == b.position {
+                assert_eq!(a.normal, b.normal);
+            }
+        }
+    }
+}
+
+#[test]
+fn angle_weighted_cube_corners_are_diagonal() {
+    let mut cube = cube_mesh(2.);
+    recompute_normals(&mut cube, NormalMode::AngleWeighted);
+    let mut corners = 0;
+    for vertex in &cube.vertex_data {
+        let p = Vector3::from(vertex.position);
+        if p.x.abs() == 1. && p.y.abs() == 1. && p.z.abs() == 1. {
+            assert!(close_within(vertex.normal.into(), p.normalize(), 1e-3));
+            corners += 1;
+        }
+    }
+    assert!(corners >= 8);
+}
+
+#[test]
+fn flat_normals_match_their_triangle() {
+    let mut sphere = sphere_mesh(1., 12, 6);
+    let triangles = sphere.index_data.len() / 3;
+    recompute_normals(&mut sphere, NormalMode::Flat);
+    assert_eq!(sphere.vertex_data.len(), triangles * 3);
+    for t in sphere.index_data.chunks_exact(3) {
+        let [a, b, c] = [t[0], t[1], t[2]].map(|i| sphere.vertex_data[i as usize]);
+        let [pa, pb, pc] = [a, b, c].map(|v| Vector3::from(v.position));
+        let normal = (pb - pa).cross(pc - pa);
+        if normal.magnitude2() > 0. {
+            for v in [a, b, c] {
+                assert!(close_within(v.normal.into(), normal.normalize(), 1e-3));
+            }
+        }
+    }
+}
@@ -0,0 +1,32 @@
+// Hi-Z pyramid sizes and the level bounds are tested at, without a device
+use learn_graphics::internals::{hiz_level, hiz_levels};
+
+#[test]
+fn pyramid_halves_down_to_one_texel() {
+    // 960x540 at level 0, then 480, 240, 120, 60, 30, 15, 7, 3 and 1 texels wide
+    assert_eq!(hiz_levels(1920, 1080), 10);
+    assert_eq!(hiz_levels(1024, 1024), 10);
+    assert_eq!(hiz_levels(1, 1), 1);
+    assert_eq!(hiz_levels(3, 1), 1);
+}
+
+#[test]
+fn bounds_cover_at_most_two_texels() {
+    let levels = hiz_levels(1920, 1080);
+    for extent in [0.5, 1., 2., 3., 17., 100., 900.] {
+        let level = hiz_level(extent, levels);
+        let texel = 2f32.powi(level as i32 + 1);
+        assert!(extent <= texel, "{} pixels at level {}", extent, level);
+        // One level finer would need more than 2x2 texels
+        if level > 0 {
+            assert!(extent > texel / 2.);
+        }
+    }
+}
+
+#[test]
+fn large_bounds_use_the_last_level() {
+    assert_eq!(hiz_level(10000., 10), 9);
+    assert_eq!(hiz_level(f32::INFINITY, 10), 9);
+    assert_eq!(hiz_level(1000., 1), 0);
+}
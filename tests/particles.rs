@@ -0,0 +1,65 @@
+// The CPU particle step the web build runs, without a device
+use learn_graphics::{spawn_particle, step_particle, workgroup_count, Emitter, Particle};
+
+#[test]
+fn spawns_are_deterministic_and_inside_the_cone() {
+    let emitter = Emitter {
+        position: [1., 2., 3.],
+        ..Default::default()
+    };
+    for index in 0..100 {
+        let particle = spawn_particle(&emitter, index, 5);
+        assert_eq!(particle, spawn_particle(&emitter, index, 5));
+        assert_eq!(particle.position, emitter.position);
+        assert_eq!(particle.age, 0.);
+        assert!(
+            particle.lifetime >= emitter.lifetime / 2. && particle.lifetime <= emitter.lifetime
+        );
+        let [x, y, z] = particle.velocity;
+        let speed = (x * x + y * y + z * z).sqrt();
+        assert!(speed >= emitter.speed * 0.75 - 1e-3 && speed <= emitter.speed * 1.25 + 1e-3);
+        assert!((y / speed).acos() <= emitter.spread + 1e-3);
+    }
+    assert_ne!(
+        spawn_particle(&emitter, 0, 5),
+        spawn_particle(&emitter, 1, 5)
+    );
+    assert_ne!(
+        spawn_particle(&emitter, 0, 5),
+        spawn_particle(&emitter, 0, 6)
+    );
+}
+
+#[test]
+fn particles_fall_and_are_emitted_again() {
+    let emitter = Emitter::default();
+    let mut particle = spawn_particle(&emitter, 0, 0);
+    let launch = particle.velocity[1];
+    step_particle(&mut particle, &emitter, 0.5, 0, 1);
+    assert!((particle.velocity[1] - (launch - emitter.gravity * 0.5)).abs() < 1e-4);
+    assert!(particle.position[1] > emitter.position[1]);
+    step_particle(&mut particle, &emitter, emitter.lifetime, 0, 2);
+    assert_eq!(particle, spawn_particle(&emitter, 0, 2));
+}
+
+#[test]
+fn unborn_particles_stay_put() {
+    let emitter = Emitter::default();
+    let mut particle = Particle {
+        age: -1.,
+        ..spawn_particle(&emitter, 3, 0)
+    };
+    let before = particle;
+    step_particle(&mut particle, &emitter, 0.25, 3, 1);
+    assert_eq!(particle.position, before.position);
+    assert_eq!(particle.velocity, before.velocity);
+    assert_eq!(particle.age, -0.75);
+}
+
+#[test]
+fn workgroups_cover_every_item() {
+    assert_eq!(workgroup_count(0, 64), 0);
+    assert_eq!(workgroup_count(1, 64), 1);
+    assert_eq!(workgroup_count(64, 64), 1);
+    assert_eq!(workgroup_count(65, 64), 2);
+}
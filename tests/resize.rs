@@ -0,0 +1,51 @@
+// Window resizes applied once the size settles
+use instant::{Duration, Instant};
+use learn_graphics::ResizeDebouncer;
+use winit::dpi::PhysicalSize;
+
+#[test]
+fn sizes_wait_for_the_delay() {
+    let start = Instant::now();
+    let mut debouncer = ResizeDebouncer::new(Duration::from_millis(100));
+    assert!(!debouncer.is_pending());
+    debouncer.request(PhysicalSize::new(800, 600), start);
+    assert_eq!(
+        debouncer.deadline(),
+        Some(start + Duration::from_millis(100))
+    );
+    assert_eq!(
+        debouncer.take_ready(start + Duration::from_millis(50)),
+        None
+    );
+    assert_eq!(
+        debouncer.take_ready(start + Duration::from_millis(100)),
+        Some(PhysicalSize::new(800, 600))
+    );
+    assert!(!debouncer.is_pending());
+}
+
+#[test]
+fn the_last_size_of_a_drag_wins() {
+    let start = Instant::now();
+    let mut debouncer = ResizeDebouncer::new(Duration::from_millis(100));
+    for i in 0..10 {
+        let at = start + Duration::from_millis(i * 20);
+        debouncer.request(PhysicalSize::new(800 + i as u32, 600), at);
+        assert_eq!(debouncer.take_ready(at), None);
+    }
+    let settled = start + Duration::from_millis(280);
+    assert_eq!(
+        debouncer.take_ready(settled),
+        Some(PhysicalSize::new(809, 600))
+    );
+}
+
+#[test]
+fn minimized_windows_keep_their_size() {
+    let now = Instant::now();
+    let mut debouncer = ResizeDebouncer::new(Duration::ZERO);
+    debouncer.request(PhysicalSize::new(0, 0), now);
+    assert!(!debouncer.is_pending());
+    debouncer.request(PhysicalSize::new(640, 480), now);
+    assert_eq!(debouncer.take_ready(now), Some(PhysicalSize::new(640, 480)));
+}
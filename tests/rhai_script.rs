@@ -0,0 +1,119 @@
+// Rhai scripts run against a world, lights and a camera without a window
+#![cfg(feature = "scripting")]
+use cgmath::{Deg, Point3, Vector3};
+use learn_graphics::prelude::*;
+use learn_graphics::{ScriptFolder, Scripts};
+use std::time::Duration;
+
+struct Scene {
+    world: World,
+    camera: CameraView,
+    lights: Vec<LightUniform>,
+    scripts: Scripts,
+}
+
+impl Scene {
+    fn new() -> Self {
+        Self {
+            world: World::default(),
+            camera: CameraView::new((0., 0., 0.), Deg(0.), Deg(0.)),
+            lights: vec![LightUniform::default()],
+            scripts: Scripts::default(),
+        }
+    }
+
+    fn update(&mut self) {
+        let dt = Duration::from_millis(500);
+        let lights = &mut self.lights;
+        self.scripts
+            .update(dt, &mut self.world, &mut self.camera, lights);
+    }
+}
+
+#[test]
+fn scripts_move_entities_lights_and_the_camera() {
+    let mut scene = Scene::new();
+    let entity = scene.world.spawn();
+    scene
+        .world
+        .set_transform(entity, InstanceTransform::default());
+    let source = r#"
+        let e = entity();
+        let p = position(e);
+        set_position(e, p[0] + dt, 2.0, 0.0);
+        set_camera_position(0.0, 5.0, time);
+        set_light_color(0, 1.0, 0.5, 0.0);
+    "#;
+    let script = RhaiScript::new("move", source).unwrap();
+    scene.scripts.add_to(entity, script);
+    scene.update();
+    scene.update();
+    let position = scene.world.transform(entity).unwrap().position();
+    assert_eq!(position, Vector3::new(1., 2., 0.));
+    assert_eq!(scene.camera.position, Point3::new(0., 5., 1.));
+    assert_eq!(&scene.lights[0].color[..3], &[1., 0.5, 0.]);
+}
+
+#[test]
+fn state_is_kept_between_updates_and_reloads() {
+    let mut scene = Scene::new();
+    let count = r#"
+        state.count = if "count" in state { state.count + 1 } else { 1 };
+        set_camera_position(state.count.to_float(), 0.0, 0.0);
+    "#;
+    let mut script = RhaiScript::new("count", count).unwrap();
+    // Compile errors keep the old source
+    assert!(script.reload("let = ;").is_err());
+    scene.scripts.add(script);
+    scene.update();
+    scene.update();
+    assert_eq!(scene.camera.position.x, 2.);
+}
+
+#[test]
+fn errors_stop_the_script_and_keep_the_world() {
+    let mut scene = Scene::new();
+    let entity = scene.world.spawn();
+    let script = RhaiScript::new("broken", "set_light_color(0, 0.0, 0.0, 0.0); missing();");
+    scene.scripts.add(script.unwrap());
+    scene.update();
+    assert!(scene.world.is_alive(entity));
+    assert_eq!(scene.lights[0].color[0], 0.);
+    // Stopped, but not dropped
+    scene.lights[0].color[0] = 1.;
+    scene.update();
+    assert_eq!(scene.lights[0].color[0], 1.);
+    assert_eq!(scene.scripts.len(), 1);
+}
+
+#[test]
+fn finished_scripts_are_dropped() {
+    let mut scene = Scene::new();
+    let script = RhaiScript::new("once", "set_camera_angles(90.0, 0.0); finish();").unwrap();
+    scene.scripts.add(script);
+    scene.update();
+    assert!(scene.scripts.is_empty());
+    assert!((Deg::from(scene.camera.yaw()).0 - 90.).abs() < 1e-4);
+}
+
+#[test]
+fn folders_reload_changed_files() {
+    let dir = std::env::temp_dir().join(format!("rhai_scripts_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("lift.rhai");
+    std::fs::write(&file, "set_camera_position(0.0, 1.0, 0.0);").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a script").unwrap();
+    let mut scene = Scene::new();
+    scene.scripts.add(ScriptFolder::new(&dir));
+    scene.update();
+    assert_eq!(scene.camera.position.y, 1.);
+    std::fs::write(&file, "set_camera_position(0.0, 2.0, 0.0);").unwrap();
+    // Polled every half second
+    scene.update();
+    assert_eq!(scene.camera.position.y, 2.);
+    std::fs::remove_file(&file).unwrap();
+    scene.camera.position.y = 0.;
+    scene.update();
+    assert_eq!(scene.camera.position.y, 0.);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,92 @@
+// Global and entity scripts run against a world and a camera without a window
+use cgmath::{Deg, InnerSpace, One, Point3, Quaternion, Rotation, Vector3};
+use learn_graphics::prelude::*;
+use learn_graphics::Scripts;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn camera() -> CameraView {
+    CameraView::new((0., 0., 0.), Deg(0.), Deg(0.))
+}
+
+#[test]
+fn spin_turns_its_entity() {
+    let mut world = World::default();
+    let entity = world.spawn();
+    world.set_transform(entity, InstanceTransform::default());
+    let mut scripts = Scripts::default();
+    scripts.add_to(
+        entity,
+        Spin {
+            axis: Vector3::unit_y(),
+            degrees_per_second: 90.,
+        },
+    );
+    let mut camera = camera();
+    scripts.update(Duration::from_secs(1), &mut world, &mut camera, &mut []);
+    let rotation = world.transform(entity).unwrap().rotation();
+    let x = rotation.rotate_vector(Vector3::unit_x());
+    assert!((x - -Vector3::unit_z()).magnitude() < 1e-5);
+}
+
+#[test]
+fn global_scripts_see_the_camera_and_time() {
+    let mut world = World::default();
+    let mut camera = camera();
+    let mut scripts = Scripts::default();
+    let seen = Rc::new(Cell::new(Duration::ZERO));
+    let seen_by_script = seen.clone();
+    scripts.add(move |_dt: Duration, scene: &mut SceneApi| {
+        assert_eq!(scene.entity(), None);
+        seen_by_script.set(scene.elapsed());
+        scene.camera_mut().position.y += 1.;
+    });
+    for _ in 0..3 {
+        scripts.update(Duration::from_millis(500), &mut world, &mut camera, &mut []);
+    }
+    assert_eq!(seen.get(), Duration::from_millis(1500));
+    assert_eq!(camera.position, Point3::new(0., 3., 0.));
+}
+
+#[test]
+fn finished_scripts_and_despawned_entities_are_dropped() {
+    let mut world = World::default();
+    let (door, other) = (world.spawn(), world.spawn());
+    let mut camera = camera();
+    let mut scripts = Scripts::default();
+    // Opens once the camera comes close
+    scripts.add_to(door, |_dt: Duration, scene: &mut SceneApi| {
+        if scene.camera().position.x > 5. {
+            let open = InstanceTransform::new(Vector3::new(0., 10., 0.), Quaternion::one());
+            scene.set_transform(open);
+            scene.finish();
+        }
+    });
+    scripts.add_to(other, |_dt: Duration, _scene: &mut SceneApi| {});
+    scripts.update(Duration::from_millis(16), &mut world, &mut camera, &mut []);
+    assert_eq!(scripts.len(), 2);
+    assert_eq!(world.transform(door), None);
+    camera.position.x = 10.;
+    world.despawn(other);
+    scripts.update(Duration::from_millis(16), &mut world, &mut camera, &mut []);
+    assert!(scripts.is_empty());
+    assert_eq!(world.transform(door).unwrap().position().y, 10.);
+}
+
+#[test]
+fn scripts_are_removed_by_entity() {
+    let mut world = World::default();
+    let entity = world.spawn();
+    let mut scripts = Scripts::default();
+    let spin = Spin {
+        axis: Vector3::unit_x(),
+        degrees_per_second: 1.,
+    };
+    scripts.add_to(entity, spin);
+    scripts.add_to(entity, spin);
+    scripts.add(spin);
+    assert!(scripts.is_animating());
+    assert_eq!(scripts.remove_from(entity), 2);
+    assert_eq!(scripts.len(), 1);
+}
@@ -0,0 +1,131 @@
+// CPU mirrors of shader math and the matrices uploaded to color passes
+mod common;
+use cgmath::Vector3;
+use common::close;
+use learn_graphics::prelude::{AmbientMode, LightUniform, SceneUniform};
+use learn_graphics::{cal_cutoff, cutoff_intensity, fresnel, ColorFilter};
+
+fn transform(matrix: [[f32; 3]; 3], color: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row.iter().zip(color).map(|(m, c)| m * c).sum())
+}
+
+#[test]
+fn cutoff_stores_cosines() {
+    let [inner, outer, epsilon, enabled] = cal_cutoff(10., 30.);
+    assert!(close(inner, 10f32.to_radians().cos()));
+    assert!(close(outer, 30f32.to_radians().cos()));
+    assert!(close(epsilon, inner - outer));
+    assert_eq!(enabled, 1.);
+}
+
+#[test]
+#[should_panic]
+fn cutoff_needs_the_inner_cone_inside() {
+    cal_cutoff(30., 10.);
+}
+
+#[test]
+fn cutoff_falls_off_between_the_cones() {
+    let cutoff = cal_cutoff(10., 30.);
+    let at = |degrees: f32| cutoff_intensity(cutoff, degrees.to_radians().cos());
+    assert_eq!(at(0.), 1.);
+    assert!(close(at(10.), 1.));
+    assert_eq!(at(45.), 0.);
+    let halfway = at(20.);
+    assert!(halfway > 0. && halfway < 1.);
+    assert!(at(15.) > at(25.));
+    // Lights without a cone are not cut off
+    assert_eq!(cutoff_intensity([0.; 4], -1.), 1.);
+}
+
+#[test]
+fn no_filter_is_identity() {
+    for correct in [false, true] {
+        let color = [0.2, 0.5, 0.9];
+        let filtered = transform(ColorFilter::None.matrix(correct), color);
+        assert!(filtered.iter().zip(color).all(|(a, b)| close(*a, b)));
+    }
+}
+
+#[test]
+fn filters_keep_grays() {
+    let filters = [
+        ColorFilter::Protanopia,
+        ColorFilter::Deuteranopia,
+        ColorFilter::Tritanopia,
+    ];
+    for filter in filters {
+        for correct in [false, true] {
+            for gray in [0., 0.5, 1.] {
+                let filtered = transform(filter.matrix(correct), [gray; 3]);
+                assert!(
+                    filtered.iter().all(|c| (c - gray).abs() < 1e-3),
+                    "{:?} changes gray {} to {:?}",
+                    filter,
+                    gray,
+                    filtered
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn protanopia_confuses_red_and_green() {
+    let matrix = ColorFilter::Protanopia.matrix(false);
+    let distance = |a: [f32; 3], b: [f32; 3]| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f32>()
+            .sqrt()
+    };
+    let (red, green) = ([1., 0., 0.], [0., 1., 0.]);
+    let seen = distance(transform(matrix, red), transform(matrix, green));
+    assert!(seen < distance(red, green));
+    // Correction moves the lost difference into channels the viewer still has
+    let corrected = ColorFilter::Protanopia.matrix(true);
+    let simulated_after = |c| transform(matrix, transform(corrected, c));
+    let recovered = distance(simulated_after(red), simulated_after(green));
+    assert!(recovered > seen);
+}
+
+#[test]
+fn shadow_resolution_falls_back_and_clamps() {
+    let light = |shadow_resolution| LightUniform {
+        shadow_resolution,
+        ..Default::default()
+    };
+    assert_eq!(light(0.).shadow_map_size(2048), 2048);
+    assert_eq!(light(1024.).shadow_map_size(2048), 1024);
+    assert_eq!(light(16.).shadow_map_size(2048), 256);
+    assert_eq!(light(1e6).shadow_map_size(2048), 8192);
+    assert!(LightUniform::default().casts_shadows());
+}
+
+#[test]
+fn glass_reflects_more_at_grazing_angles() {
+    // 4% of the light is reflected head on by window glass
+    assert!(close(fresnel(1., 1.5), 0.04));
+    assert!(close(fresnel(0., 1.5), 1.));
+    assert!(fresnel(0.5, 1.5) < fresnel(0.2, 1.5));
+    // Water reflects less than glass
+    assert!(fresnel(1., 1.33) < fresnel(1., 1.5));
+}
+
+#[test]
+fn ambient_setters_switch_the_mode() {
+    let mut scene = SceneUniform::default();
+    scene.set_flat_ambient([0.2, 0.3, 0.4]);
+    scene.set_ambient_intensity(0.5);
+    let down = scene.ambient(-Vector3::unit_y());
+    assert!(close(down[0], 0.1) && close(down[1], 0.15) && close(down[2], 0.2));
+
+    scene.set_hemisphere_ambient([1., 1., 1.], [0., 0., 0.]);
+    assert_eq!(scene.ambient_mode, AmbientMode::Hemisphere as u32);
+    assert!(close(scene.ambient(Vector3::unit_y())[0], 0.5));
+    assert!(close(scene.ambient(-Vector3::unit_y())[0], 0.));
+
+    scene.set_ambient_mode(AmbientMode::None);
+    assert_eq!(scene.ambient(Vector3::unit_y()), [0.; 3]);
+}
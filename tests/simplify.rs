@@ -0,0 +1,79 @@
+// Quadric simplification on generated meshes, no device needed
+use cgmath::{InnerSpace, Vector3};
+use learn_graphics::prelude::*;
+use learn_graphics::MeshData;
+
+fn triangle_count(mesh: &MeshData) -> usize {
+    mesh.index_data.len() / 3
+}
+
+fn assert_valid(mesh: &MeshData) {
+    let count = mesh.vertex_data.len() as u32;
+    assert_eq!(mesh.index_data.len() % 3, 0);
+    assert!(mesh.index_data.iter().all(|&i| i < count));
+}
+
+fn extent(mesh: &MeshData) -> ([f32; 3], [f32; 3]) {
+    mesh.vertex_data.iter().fold(
+        ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]),
+        |(min, max), v| {
+            let p = v.position;
+            (
+                [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])],
+                [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])],
+            )
+        },
+    )
+}
+
+#[test]
+fn simplify_reaches_the_target() {
+    let sphere = sphere_mesh(1., 32, 16);
+    let target = triangle_count(&sphere) / 4;
+    let simplified = simplify(&sphere, target);
+    assert_valid(&simplified);
+    assert!(triangle_count(&simplified) <= target);
+    assert!(simplified.vertex_data.len() < sphere.vertex_data.len());
+}
+
+#[test]
+fn surviving_vertices_stay_on_the_surface() {
+    let simplified = simplify(&icosphere_mesh(2., 3), 100);
+    for vertex in &simplified.vertex_data {
+        let distance = Vector3::from(vertex.position).magnitude();
+        assert!((distance - 2.).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn no_triangle_turns_inward() {
+    for lod in lod_chain(&sphere_mesh(1., 48, 24), 4) {
+        assert_valid(&lod);
+        for t in lod.index_data.chunks_exact(3) {
+            let [a, b, c] =
+                [t[0], t[1], t[2]].map(|i| Vector3::from(lod.vertex_data[i as usize].position));
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot(a + b + c) >= -1e-6);
+        }
+    }
+}
+
+#[test]
+fn borders_are_kept() {
+    let plane = plane_mesh(4., 2., 16);
+    let simplified = simplify(&plane, 8);
+    assert!(triangle_count(&simplified) < triangle_count(&plane));
+    assert_eq!(extent(&simplified), extent(&plane));
+}
+
+#[test]
+fn lod_chain_halves_each_level() {
+    let sphere = sphere_mesh(1., 32, 16);
+    let chain = lod_chain(&sphere, 3);
+    assert_eq!(chain.len(), 3);
+    let mut previous = triangle_count(&sphere);
+    for lod in &chain {
+        assert!(triangle_count(lod) <= previous / 2);
+        previous = triangle_count(lod);
+    }
+}
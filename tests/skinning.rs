@@ -0,0 +1,152 @@
+// Skeleton poses and skinning, the CPU path of deform.wgsl
+mod common;
+use cgmath::{Deg, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3};
+use common::close;
+use learn_graphics::internals::{global_transforms, skin_vertices};
+use learn_graphics::prelude::*;
+use learn_graphics::{ChannelKeys, Influence, NodeChannel, SkinBinding};
+
+fn vertex(position: [f32; 3]) -> Vertex {
+    Vertex::new(position, [0.; 2], [0., 1., 0.])
+}
+
+fn weighted(joints: [u32; 4], weights: [f32; 4]) -> Influence {
+    Influence { joints, weights }
+}
+
+fn node(parent: Option<usize>, translation: [f32; 3]) -> SkeletonNode {
+    SkeletonNode {
+        parent,
+        translation: translation.into(),
+        ..Default::default()
+    }
+}
+
+// A root at the origin with one joint 1 up, animated 1 along x over a second
+fn arm() -> Skeleton {
+    Skeleton {
+        nodes: vec![node(None, [0., 0., 0.]), node(Some(0), [0., 1., 0.])],
+        skins: vec![Skin {
+            joints: vec![0, 1],
+            inverse_bind_matrices: vec![
+                Matrix4::identity(),
+                Matrix4::from_translation(Vector3::new(0., -1., 0.)),
+            ],
+        }],
+        animation: Some(SkeletonAnimation {
+            channels: vec![NodeChannel {
+                node: 1,
+                times: vec![0., 1.],
+                keys: ChannelKeys::Translation(vec![
+                    Vector3::new(0., 1., 0.),
+                    Vector3::new(1., 1., 0.),
+                ]),
+            }],
+        }),
+    }
+}
+
+#[test]
+fn vertices_follow_their_weighted_joints() {
+    let matrices = [
+        Matrix4::identity(),
+        Matrix4::from_translation(Vector3::new(2., 0., 0.)),
+    ];
+    let source = [vertex([0., 1., 0.]); 4];
+    let influences = [
+        weighted([1, 0, 0, 0], [1., 0., 0., 0.]),
+        weighted([0, 1, 0, 0], [0.5, 0.5, 0., 0.]),
+        // No weight and a joint past the matrices stay in place
+        weighted([1, 0, 0, 0], [0.; 4]),
+        weighted([7, 0, 0, 0], [1., 0., 0., 0.]),
+    ];
+    let skinned = skin_vertices(&source, &influences, &matrices);
+    assert_eq!(skinned[0].position, [2., 1., 0.]);
+    assert_eq!(skinned[1].position, [1., 1., 0.]);
+    assert_eq!(skinned[2].position, [0., 1., 0.]);
+    assert_eq!(skinned[3].position, [0., 1., 0.]);
+}
+
+#[test]
+fn skinned_normals_rotate_and_stay_unit_length() {
+    let turn = Matrix4::from(Quaternion::from_angle_z(Deg(90.)));
+    let scaled = Matrix4::from_scale(3.) * turn;
+    let influences = [weighted([0, 0, 0, 0], [1., 0., 0., 0.])];
+    let skinned = skin_vertices(&[vertex([1., 0., 0.])], &influences, &[scaled]);
+    let [x, y, z] = skinned[0].normal;
+    assert!(close(x, -1.) && close(y, 0.) && close(z, 0.));
+    assert!(close(skinned[0].position[1], 3.));
+}
+
+#[test]
+fn children_are_placed_by_their_parents() {
+    // The parent comes after its child
+    let nodes = [node(Some(1), [0., 1., 0.]), node(None, [2., 0., 0.])];
+    let globals = global_transforms(&nodes);
+    assert_eq!(globals[0].w.truncate(), Vector3::new(2., 1., 0.));
+    assert_eq!(globals[1].w.truncate(), Vector3::new(2., 0., 0.));
+}
+
+#[test]
+fn the_bind_pose_leaves_vertices_in_place() {
+    let skeleton = Skeleton {
+        animation: None,
+        ..arm()
+    };
+    for matrix in &skeleton.joint_matrices(0.)[0] {
+        assert_eq!(*matrix, Matrix4::identity());
+    }
+}
+
+#[test]
+fn channels_are_interpolated_in_a_loop() {
+    let skeleton = arm();
+    let animation = skeleton.animation.as_ref().unwrap();
+    assert_eq!(animation.duration(), 1.);
+    let pose = animation.pose(&skeleton.nodes, 0.25);
+    assert!(close(pose[1].translation.x, 0.25));
+    let looped = animation.pose(&skeleton.nodes, 1.5);
+    assert!(close(looped[1].translation.x, 0.5));
+    // The root has no channel
+    assert_eq!(pose[0], skeleton.nodes[0]);
+    let rotation = NodeChannel {
+        node: 0,
+        times: vec![1., 2.],
+        keys: ChannelKeys::Rotation(vec![
+            Quaternion::from_angle_y(Deg(0.)),
+            Quaternion::from_angle_y(Deg(90.)),
+        ]),
+    };
+    let mut turned = SkeletonNode::default();
+    rotation.apply(&mut turned, 1.5);
+    let expected = Quaternion::from_angle_y(Deg(45.));
+    assert!(close(turned.rotation.s, expected.s) && close(turned.rotation.v.y, expected.v.y));
+    // Held before the first key
+    rotation.apply(&mut turned, 0.);
+    assert!(close(turned.rotation.s, 1.));
+}
+
+#[test]
+fn scaled_skeletons_skin_scaled_vertices() {
+    let influences = [weighted([1, 0, 0, 0], [1., 0., 0., 0.])];
+    let skeleton = arm();
+    let matrices = &skeleton.joint_matrices(0.5)[0];
+    let skinned = skin_vertices(&[vertex([0., 2., 0.])], &influences, matrices);
+    let scaled = skeleton.scaled(3.);
+    let matrices = &scaled.joint_matrices(0.5)[0];
+    let skinned_scaled = skin_vertices(&[vertex([0., 6., 0.])], &influences, matrices);
+    for (a, b) in skinned[0].position.iter().zip(skinned_scaled[0].position) {
+        assert!(close(a * 3., b));
+    }
+}
+
+#[test]
+fn bounds_cover_the_animation() {
+    let binding = SkinBinding {
+        skin: 0,
+        influences: vec![weighted([1, 0, 0, 0], [1., 0., 0., 0.])],
+    };
+    let bounds = arm().skinned_bounds(&binding, &[vertex([0., 1., 0.])]);
+    // From x = 0 to nearly 1 at the last sample
+    assert!(bounds.radius > 0.4 && bounds.center.x > 0.4);
+}
@@ -0,0 +1,117 @@
+// Mesh BVH queries and the primitive tests behind collision, no device needed
+mod common;
+use cgmath::{Point3, Vector3};
+use common::close;
+use learn_graphics::prelude::*;
+use learn_graphics::{
+    closest_point_on_triangle, raycast_mesh, triangle_overlaps_aabb, Aabb, MeshBvh, Ray,
+};
+
+#[test]
+fn bvh_raycast_matches_brute_force() {
+    let mesh = icosphere_mesh(3., 3);
+    let bvh = MeshBvh::new(&mesh.vertex_data, &mesh.index_data);
+    for i in 0..64 {
+        let angle = i as f32 * 0.7;
+        let origin = Point3::new(angle.cos() * 10., (i as f32 - 32.) * 0.2, angle.sin() * 10.);
+        let ray = Ray::new(origin, Point3::new(0.3, 0., -0.2) - origin);
+        let fast = bvh.raycast(&ray, &mesh.vertex_data, &mesh.index_data);
+        let slow = raycast_mesh(&ray, &mesh.vertex_data, &mesh.index_data);
+        match (fast, slow) {
+            (Some(fast), Some(slow)) => assert!((fast.distance - slow.distance).abs() < 1e-4),
+            (None, None) => {}
+            (fast, slow) => panic!("{:?} and {:?} disagree", fast, slow),
+        }
+    }
+}
+
+#[test]
+fn bvh_raycast_misses_an_empty_mesh() {
+    let bvh = MeshBvh::new(&[], &[]);
+    let ray = Ray::new(Point3::new(0., 0., 5.), -Vector3::unit_z());
+    assert!(bvh.raycast(&ray, &[], &[]).is_none());
+}
+
+#[test]
+fn bvh_query_finds_the_triangles_in_a_box() {
+    let mesh = plane_mesh(10., 10., 10);
+    let bvh = MeshBvh::new(&mesh.vertex_data, &mesh.index_data);
+    let corner = Aabb::around(Point3::new(-4.5, 0., -4.5), Vector3::new(0.1, 0.1, 0.1));
+    let mut found = vec![];
+    bvh.query(&corner, |triangle| found.push(triangle));
+    assert!(!found.is_empty());
+    assert!(found.len() < mesh.index_data.len() / 3);
+    let far = Aabb::around(Point3::new(0., 5., 0.), Vector3::new(1., 1., 1.));
+    bvh.query(&far, |_| panic!("nothing is above the plane"));
+}
+
+#[test]
+fn closest_point_regions() {
+    let triangle = [
+        Point3::new(0., 0., 0.),
+        Point3::new(2., 0., 0.),
+        Point3::new(0., 2., 0.),
+    ];
+    let inside = closest_point_on_triangle(Point3::new(0.5, 0.5, 3.), triangle);
+    assert!(close(inside, Point3::new(0.5, 0.5, 0.)));
+    let vertex = closest_point_on_triangle(Point3::new(-1., -1., 0.), triangle);
+    assert!(close(vertex, triangle[0]));
+    let edge = closest_point_on_triangle(Point3::new(1., -3., 1.), triangle);
+    assert!(close(edge, Point3::new(1., 0., 0.)));
+    let hypotenuse = closest_point_on_triangle(Point3::new(2., 2., 0.), triangle);
+    assert!(close(hypotenuse, Point3::new(1., 1., 0.)));
+}
+
+#[test]
+fn closest_point_on_a_degenerate_triangle() {
+    let segment = [
+        Point3::new(0., 0., 0.),
+        Point3::new(1., 0., 0.),
+        Point3::new(2., 0., 0.),
+    ];
+    let point = closest_point_on_triangle(Point3::new(1.5, 1., 0.), segment);
+    assert!(close(point, Point3::new(1.5, 0., 0.)));
+}
+
+#[test]
+fn triangle_box_overlap() {
+    let unit = Aabb::around(Point3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
+    let through = [
+        Point3::new(-5., 0., -5.),
+        Point3::new(5., 0., -5.),
+        Point3::new(0., 0., 5.),
+    ];
+    assert!(triangle_overlaps_aabb(through, &unit));
+    let above = through.map(|p| p + Vector3::new(0., 1.5, 0.));
+    assert!(!triangle_overlaps_aabb(above, &unit));
+    // Its bounds overlap the box but the diagonal keeps it outside
+    let beside = [
+        Point3::new(1.6, 0.6, 0.),
+        Point3::new(0.6, 1.6, 0.),
+        Point3::new(3., 3., 0.),
+    ];
+    assert!(!triangle_overlaps_aabb(beside, &unit));
+}
+
+#[test]
+fn aabb_overlap_and_corners() {
+    let a = Aabb::around(Point3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
+    let b = Aabb::around(Point3::new(1.5, 0., 0.), Vector3::new(1., 1., 1.));
+    let c = Aabb::around(Point3::new(3., 0., 0.), Vector3::new(0.5, 0.5, 0.5));
+    assert!(a.overlaps(&b));
+    assert!(!a.overlaps(&c));
+    let corners = a.corners();
+    assert!(corners.contains(&Point3::new(-1., -1., -1.)));
+    assert!(corners.contains(&Point3::new(1., 1., 1.)));
+}
+
+#[test]
+fn ray_enters_a_box() {
+    let aabb = Aabb::around(Point3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
+    let ray = Ray::new(Point3::new(0., 0., 5.), -Vector3::unit_z());
+    assert!((ray.intersect_aabb(&aabb).unwrap() - 4.).abs() < 1e-5);
+    let inside = Ray::new(Point3::new(0., 0., 0.), Vector3::unit_x());
+    assert_eq!(inside.intersect_aabb(&aabb), Some(0.));
+    let away = Ray::new(Point3::new(0., 0., 5.), Vector3::unit_z());
+    assert!(away.intersect_aabb(&aabb).is_none());
+}
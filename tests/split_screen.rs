@@ -0,0 +1,32 @@
+use learn_graphics::prelude::*;
+
+#[test]
+fn one_view_covers_the_window() {
+    assert_eq!(split_rects(1, 800, 600), vec![[0., 0., 800., 600.]]);
+}
+
+#[test]
+fn two_views_are_side_by_side() {
+    let rects = split_rects(2, 800, 600);
+    assert_eq!(rects, vec![[0., 0., 400., 600.], [400., 0., 400., 600.]]);
+}
+
+#[test]
+fn more_views_fill_a_grid() {
+    let rects = split_rects(3, 800, 600);
+    assert_eq!(
+        rects,
+        vec![
+            [0., 0., 400., 300.],
+            [400., 0., 400., 300.],
+            [0., 300., 400., 300.]
+        ]
+    );
+    let rects = split_rects(5, 900, 600);
+    assert_eq!(rects.len(), 5);
+    // Three columns and two rows, every rectangle inside the window
+    assert_eq!(rects[2], [600., 0., 300., 300.]);
+    assert!(rects
+        .iter()
+        .all(|[x, y, w, h]| x + w <= 900. && y + h <= 600.));
+}
@@ -0,0 +1,65 @@
+// Startup failures that are reported instead of panicking, and the surface format handling
+use learn_graphics::{
+    encode_srgb, scene_format, surface_format, InitError, FALLBACK_SURFACE_FORMATS,
+};
+use wgpu::TextureFormat;
+
+#[test]
+fn the_preferred_surface_format_wins() {
+    let format = surface_format(Some(TextureFormat::Rgba16Float), |_| false);
+    assert_eq!(format, Some(TextureFormat::Rgba16Float));
+}
+
+#[test]
+fn surfaces_without_a_preference_fall_back() {
+    assert_eq!(
+        surface_format(None, |_| true),
+        Some(FALLBACK_SURFACE_FORMATS[0])
+    );
+    let format = surface_format(None, |format| format == TextureFormat::Rgba8Unorm);
+    assert_eq!(format, Some(TextureFormat::Rgba8Unorm));
+    assert_eq!(surface_format(None, |_| false), None);
+}
+
+#[test]
+fn asset_errors_name_the_file_and_the_cause() {
+    let error = InitError::Asset {
+        name: "girl.obj".to_string(),
+        source: anyhow::anyhow!("not found"),
+    };
+    let message = error.to_string();
+    assert!(message.contains("girl.obj"));
+    assert!(message.contains("not found"));
+    assert!(std::error::Error::source(&error).is_some());
+    assert!(std::error::Error::source(&InitError::NoSurfaceFormat).is_none());
+}
+
+#[test]
+fn non_srgb_surfaces_get_an_srgb_scene() {
+    assert_eq!(
+        scene_format(TextureFormat::Bgra8Unorm),
+        TextureFormat::Bgra8UnormSrgb
+    );
+    assert_eq!(
+        scene_format(TextureFormat::Rgba8Unorm),
+        TextureFormat::Rgba8UnormSrgb
+    );
+    for format in [
+        TextureFormat::Bgra8UnormSrgb,
+        TextureFormat::Rgba8UnormSrgb,
+        TextureFormat::Rgba16Float,
+    ] {
+        assert_eq!(scene_format(format), format);
+    }
+}
+
+#[test]
+fn srgb_encoding_matches_the_transfer_function() {
+    assert_eq!(encode_srgb(0.), 0.);
+    assert!((encode_srgb(1.) - 1.).abs() < 1e-6);
+    // Mid grey in linear light is about 188 of 255
+    assert!((encode_srgb(0.5) * 255. - 187.5).abs() < 1.);
+    // Both pieces meet at the threshold
+    assert!((encode_srgb(0.0031308) - 0.0031308 * 12.92).abs() < 1e-6);
+    assert!((encode_srgb(0.0031309) - 0.0031308 * 12.92).abs() < 1e-5);
+}
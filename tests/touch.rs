@@ -0,0 +1,86 @@
+// Touch gestures of phones and tablets
+use learn_graphics::{TouchGesture, TouchInput, TouchPoint};
+use winit::event::TouchPhase;
+
+const SIZE: [f32; 2] = [1000., 800.];
+
+fn point(id: u64, phase: TouchPhase, x: f32, y: f32) -> TouchPoint {
+    TouchPoint {
+        id,
+        phase,
+        position: [x, y],
+    }
+}
+
+#[test]
+fn one_finger_looks_around() {
+    let mut input = TouchInput::default();
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Started, 600., 200.), SIZE),
+        None
+    );
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Moved, 610., 195.), SIZE),
+        Some(TouchGesture::Look { dx: 10., dy: -5. })
+    );
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Ended, 610., 195.), SIZE),
+        None
+    );
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Moved, 620., 195.), SIZE),
+        None
+    );
+}
+
+#[test]
+fn two_fingers_pinch() {
+    let mut input = TouchInput::default();
+    input.touch(point(1, TouchPhase::Started, 500., 200.), SIZE);
+    input.touch(point(2, TouchPhase::Started, 600., 200.), SIZE);
+    assert_eq!(
+        input.touch(point(2, TouchPhase::Moved, 650., 200.), SIZE),
+        Some(TouchGesture::Pinch(50.))
+    );
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Moved, 530., 200.), SIZE),
+        Some(TouchGesture::Pinch(-30.))
+    );
+}
+
+#[test]
+fn the_lower_left_is_a_joystick() {
+    let mut input = TouchInput::default();
+    input.touch(point(1, TouchPhase::Started, 100., 700.), SIZE);
+    assert_eq!(input.joystick(), Some(([100., 700.], [100., 700.])));
+    // The radius is 12% of the shorter side, 96 pixels
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Moved, 148., 700.), SIZE),
+        Some(TouchGesture::Joystick {
+            right: 0.5,
+            forward: 0.
+        })
+    );
+    // Clamped to full speed
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Moved, 100., 300.), SIZE),
+        Some(TouchGesture::Joystick {
+            right: 0.,
+            forward: 1.
+        })
+    );
+    // A second finger there looks instead
+    input.touch(point(2, TouchPhase::Started, 120., 650.), SIZE);
+    assert_eq!(
+        input.touch(point(2, TouchPhase::Moved, 125., 650.), SIZE),
+        Some(TouchGesture::Look { dx: 5., dy: 0. })
+    );
+    assert_eq!(
+        input.touch(point(1, TouchPhase::Cancelled, 100., 300.), SIZE),
+        Some(TouchGesture::Joystick {
+            right: 0.,
+            forward: 0.
+        })
+    );
+    assert_eq!(input.joystick(), None);
+}
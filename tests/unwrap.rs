@@ -0,0 +1,78 @@
+// Second uv sets laid out by the unwraps, no device needed
+use learn_graphics::prelude::*;
+
+fn in_unit_square(uv: [f32; 2]) -> bool {
+    uv.iter().all(|x| (-1e-5..=1. + 1e-5).contains(x))
+}
+
+#[test]
+fn the_second_set_copies_the_texture_coordinates() {
+    let vertex = Vertex::new([1., 2., 3.], [0.25, 0.75], [0., 1., 0.]);
+    assert_eq!(vertex.tex_coords2, [0.25, 0.75]);
+    let vertex = vertex.with_tex_coords2([0.5, 0.5]);
+    assert_eq!(vertex.tex_coords, [0.25, 0.75]);
+    assert_eq!(vertex.tex_coords2, [0.5, 0.5]);
+}
+
+#[test]
+fn planar_unwrap_fits_the_unit_square() {
+    // Twice as wide as high, facing +z
+    let mut mesh = square_mesh(2., 4.);
+    planar_unwrap(&mut mesh, cgmath::Vector3::unit_z());
+    let uvs: Vec<_> = mesh.vertex_data.iter().map(|v| v.tex_coords2).collect();
+    assert!(uvs.iter().all(|uv| in_unit_square(*uv)));
+    let max_u = uvs.iter().map(|uv| uv[0]).fold(0., f32::max);
+    let max_v = uvs.iter().map(|uv| uv[1]).fold(0., f32::max);
+    // Proportions are kept
+    assert!((max_u - 1.).abs() < 1e-5 && (max_v - 0.5).abs() < 1e-5);
+    // Texture coordinates are left alone
+    assert_eq!(mesh.vertex_data[0].tex_coords, [1., 1.]);
+}
+
+#[test]
+fn box_unwrap_gives_every_side_its_cell() {
+    let mut cube = cube_mesh(2.);
+    let triangles = cube.index_data.len();
+    box_unwrap(&mut cube, 0.1);
+    assert_eq!(cube.index_data.len(), triangles);
+    // The sides of cube_mesh have their own vertices already
+    assert_eq!(cube.vertex_data.len(), 24);
+    let mut cells = vec![];
+    for vertex in &cube.vertex_data {
+        let [u, v] = vertex.tex_coords2;
+        assert!(in_unit_square([u, v]));
+        let cell = ((u * 3.) as usize, (v * 2.) as usize);
+        // Padding keeps every side away from the edges of its cell
+        let inside = [u * 3. - cell.0 as f32, v * 2. - cell.1 as f32];
+        assert!(inside.iter().all(|x| (0.099..=0.901).contains(x)));
+        if !cells.contains(&cell) {
+            cells.push(cell);
+        }
+    }
+    assert_eq!(cells.len(), 6);
+}
+
+#[test]
+fn box_unwrap_splits_vertices_between_sides() {
+    let mut sphere = sphere_mesh(1., 16, 8);
+    let vertices = sphere.vertex_data.len();
+    box_unwrap(&mut sphere, 0.);
+    assert!(sphere.vertex_data.len() > vertices);
+    assert!(sphere
+        .vertex_data
+        .iter()
+        .all(|vertex| in_unit_square(vertex.tex_coords2)));
+    // The corners of a triangle stay in one cell
+    for t in sphere.index_data.chunks_exact(3) {
+        let uvs: Vec<_> = t
+            .iter()
+            .map(|&i| sphere.vertex_data[i as usize].tex_coords2)
+            .collect();
+        let center = [0, 1].map(|k| uvs.iter().map(|uv| uv[k]).sum::<f32>() / 3.);
+        let cell = [(center[0] * 3.).floor() / 3., (center[1] * 2.).floor() / 2.];
+        for [u, v] in uvs {
+            assert!(u >= cell[0] - 1e-5 && u <= cell[0] + 1. / 3. + 1e-5);
+            assert!(v >= cell[1] - 1e-5 && v <= cell[1] + 0.5 + 1e-5);
+        }
+    }
+}
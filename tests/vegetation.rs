@@ -0,0 +1,33 @@
+// Grass placement and the distance fade, without a device
+use learn_graphics::{blade_fade, scatter_blades};
+
+#[test]
+fn blades_stay_on_the_patch() {
+    let blades = scatter_blades(1000, (10., 0., -20.).into(), 50., -10., 3);
+    assert_eq!(blades.len(), 1000);
+    for blade in &blades {
+        let [x, y, z] = blade.position;
+        assert_eq!(y, -10.);
+        assert!((x - 10.).abs() <= 50. && (z + 20.).abs() <= 50.);
+        assert!(blade.height > 0. && blade.width > 0.);
+        assert!((0. ..std::f32::consts::TAU).contains(&blade.phase));
+    }
+}
+
+#[test]
+fn same_seed_same_field() {
+    let a = scatter_blades(100, (0., 0., 0.).into(), 10., 0., 5);
+    assert_eq!(a, scatter_blades(100, (0., 0., 0.).into(), 10., 0., 5));
+    assert_ne!(a, scatter_blades(100, (0., 0., 0.).into(), 10., 0., 6));
+}
+
+#[test]
+fn fade_falls_from_start_to_end() {
+    assert_eq!(blade_fade(0., 100., 200.), 1.);
+    assert_eq!(blade_fade(100., 100., 200.), 1.);
+    assert_eq!(blade_fade(150., 100., 200.), 0.5);
+    assert_eq!(blade_fade(250., 100., 200.), 0.);
+    // A cut-off instead of a fade when both distances match
+    assert_eq!(blade_fade(99., 100., 100.), 1.);
+    assert_eq!(blade_fade(101., 100., 100.), 0.);
+}
@@ -0,0 +1,35 @@
+// Framing and light scaling of the model viewer, and which dropped files it takes
+use cgmath::Deg;
+use learn_graphics::prelude::LightUniform;
+use learn_graphics::{fit_distance, is_model_file, scale_light};
+
+#[test]
+fn model_files_are_told_by_extension() {
+    assert!(is_model_file("duck.obj"));
+    assert!(is_model_file("models/Duck.GLB"));
+    assert!(is_model_file("scene.gltf"));
+    assert!(!is_model_file("duck.mtl"));
+    assert!(!is_model_file("scene.bin"));
+}
+
+#[test]
+fn a_sphere_fits_the_narrower_field_of_view() {
+    let wide = fit_distance(1., Deg(90.).into(), 2.);
+    assert!((wide - 2f32.sqrt()).abs() < 1e-5);
+    let tall = fit_distance(1., Deg(90.).into(), 0.5);
+    assert!(tall > wide);
+    assert!((fit_distance(3., Deg(90.).into(), 2.) - 3. * wide).abs() < 1e-4);
+}
+
+#[test]
+fn scaled_lights_reach_as_far_relative_to_the_scene() {
+    let light = LightUniform::default();
+    let scaled = scale_light(&light, 0.1);
+    let falloff = |light: &LightUniform, distance: f32| {
+        let [c, l, q, _] = light.point_clq;
+        1. / (c + l * distance + q * distance * distance)
+    };
+    assert!((falloff(&light, 50.) - falloff(&scaled, 5.)).abs() < 1e-5);
+    assert!((scaled.position[0] - light.position[0] * 0.1).abs() < 1e-5);
+    assert_eq!(scaled.color, light.color);
+}